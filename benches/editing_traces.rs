@@ -0,0 +1,58 @@
+//! Benchmarks replaying recorded editing traces.
+//!
+//! Replays every trace in `benches/traces`, or in the directory named by
+//! `CRDT_RGA_TRACES`, on a fresh replica, then renders the result. Download
+//! real traces such as `automerge-paper.json.gz` from
+//! <https://github.com/josephg/editing-traces> to measure realistic workloads.
+//!
+//! Run with: cargo bench --bench editing_traces --features traces
+
+use crdt_rga::RGA;
+use crdt_rga::testing::traces::{EditingTrace, trace_files};
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+
+fn trace_name(path: &std::path::Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.trim_end_matches(".gz")
+        .trim_end_matches(".json")
+        .to_string()
+}
+
+fn replay_traces(c: &mut Criterion) {
+    let traces: Vec<(String, EditingTrace)> = trace_files()
+        .expect("trace directory")
+        .into_iter()
+        .map(|path| {
+            let trace =
+                EditingTrace::load(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            (trace_name(&path), trace)
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("replay_trace");
+    group.sample_size(10);
+    for (name, trace) in &traces {
+        group.throughput(Throughput::Elements(trace.operation_count() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), trace, |b, trace| {
+            b.iter(|| {
+                let rga = RGA::new(1);
+                trace.replay(&rga).unwrap();
+                black_box(rga.visible_node_count())
+            });
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("render_trace");
+    for (name, trace) in &traces {
+        let rga = RGA::new(1);
+        trace.replay(&rga).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &rga, |b, rga| {
+            b.iter(|| black_box(rga.to_string()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, replay_traces);
+criterion_main!(benches);
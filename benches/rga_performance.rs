@@ -0,0 +1,247 @@
+//! Performance benchmarks for the RGA CRDT.
+//!
+//! Measures sequential vs concurrent insertion throughput, multi-replica
+//! synchronization, convergence time over a simulated WAN, the sequence
+//! backends against each other and the cost of rendering documents full of
+//! tombstones.
+//!
+//! Run with: cargo bench
+
+use crdt_rga::testing::{Cluster, Latency, LinkConfig, OpMix, Simulation, SimulationConfig};
+use crdt_rga::{ApplyOutcome, RGA, SequenceCrdt, VecSequence};
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+#[cfg(not(feature = "single-threaded"))]
+use std::sync::Arc;
+#[cfg(not(feature = "single-threaded"))]
+use std::thread;
+use std::time::Duration;
+
+fn sequential_inserts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequential_inserts");
+    for size in [100usize, 1_000, 10_000] {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                let rga = RGA::new(1);
+                let mut last_id = rga.sentinel_start_id();
+                for i in 0..size {
+                    let ch = char::from_u32(65 + (i % 26) as u32).unwrap();
+                    last_id = rga.insert_after(last_id, ch).unwrap();
+                }
+                black_box(rga.visible_node_count())
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Loading a document with `RgaBuilder` against typing it character by
+/// character, as imports used to.
+fn bulk_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bulk_build");
+    for size in [1_000usize, 100_000] {
+        let text: String = (0..size)
+            .map(|i| char::from_u32(97 + (i % 26) as u32).unwrap())
+            .collect();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("builder", size), &text, |b, text| {
+            b.iter(|| black_box(RGA::from_str(1, text).visible_node_count()));
+        });
+        group.bench_with_input(BenchmarkId::new("insert_after", size), &text, |b, text| {
+            b.iter(|| {
+                let rga = RGA::new(1);
+                let mut last_id = rga.sentinel_start_id();
+                for ch in text.chars() {
+                    last_id = rga.insert_after(last_id, ch).unwrap();
+                }
+                black_box(rga.visible_node_count())
+            });
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(feature = "single-threaded"))]
+fn concurrent_inserts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_inserts");
+    let per_thread = 1_000usize;
+    for threads in [2usize, 4, 8] {
+        group.throughput(Throughput::Elements((threads * per_thread) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let rga = Arc::new(RGA::new(1));
+                    let handles: Vec<_> = (0..threads)
+                        .map(|_| {
+                            let rga = Arc::clone(&rga);
+                            thread::spawn(move || {
+                                let start_id = rga.sentinel_start_id();
+                                for _ in 0..per_thread {
+                                    rga.insert_after(start_id, 'x').unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for handle in handles {
+                        handle.join().unwrap();
+                    }
+                    black_box(rga.total_node_count())
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn replica_sync(c: &mut Criterion) {
+    let mut group = c.benchmark_group("replica_sync");
+    let per_replica = 200usize;
+    for replicas in [2usize, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(replicas),
+            &replicas,
+            |b, &replicas| {
+                b.iter(|| {
+                    let mut cluster = Cluster::new(replicas);
+                    for rga in cluster.replicas() {
+                        let mut last_id = rga.sentinel_start_id();
+                        for i in 0..per_replica {
+                            let ch = char::from_u32(97 + (i % 26) as u32).unwrap();
+                            last_id = rga.insert_after(last_id, ch).unwrap();
+                        }
+                    }
+
+                    cluster.run_until_quiescent();
+                    black_box(cluster.replica(0).to_string())
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Reports simulated (not wall-clock) time until all replicas converge when
+/// every replica types concurrently over jittery WAN links.
+fn convergence_under_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wan_convergence_time");
+    let per_replica = 50usize;
+    let wan = LinkConfig {
+        latency: Latency::LongTail {
+            base: Duration::from_millis(40),
+            tail: Duration::from_millis(400),
+            probability: 0.05,
+        },
+        jitter: Duration::from_millis(30),
+    };
+    for replicas in [2usize, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(replicas),
+            &replicas,
+            |b, &replicas| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+                    for seed in 0..iters {
+                        let config = SimulationConfig {
+                            replicas,
+                            ops_per_replica: per_replica,
+                            mix: OpMix::inserts_only(),
+                            link: wan,
+                            seed,
+                            ..SimulationConfig::default()
+                        };
+                        total += Simulation::new(config).run().convergence_time;
+                    }
+                    total
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Types a document through the `SequenceCrdt` trait, appending most
+/// characters and deleting every tenth one, then merges a second replica's
+/// operations: the same workload on every backend.
+fn edit_sequence(local: &mut impl SequenceCrdt, remote: &mut impl SequenceCrdt, size: usize) {
+    let mut ops = Vec::with_capacity(size);
+    let mut last_id = remote.start_id();
+    for _ in 0..size {
+        let op = remote.insert(last_id, 'b').unwrap();
+        last_id = op.id();
+        ops.push(op);
+    }
+    let mut last_id = local.start_id();
+    for i in 0..size {
+        last_id = local.insert(last_id, 'a').unwrap().id();
+        if i % 10 == 9 {
+            local.remove(last_id).unwrap();
+        }
+    }
+    for op in ops {
+        assert_eq!(local.apply(op), ApplyOutcome::Applied);
+    }
+}
+
+/// The RGA against the vector backend, for picking one per workload.
+fn sequence_backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequence_backends");
+    for size in [1_000usize, 10_000] {
+        group.throughput(Throughput::Elements(2 * size as u64));
+        group.bench_with_input(BenchmarkId::new("rga", size), &size, |b, &size| {
+            b.iter(|| {
+                let (mut local, mut remote) = (RGA::new(1), RGA::new(2));
+                edit_sequence(&mut local, &mut remote, size);
+                black_box(local.visible_node_count())
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("vec", size), &size, |b, &size| {
+            b.iter(|| {
+                let (mut local, mut remote) = (VecSequence::new(1), VecSequence::new(2));
+                edit_sequence(&mut local, &mut remote, size);
+                black_box(local.len())
+            });
+        });
+    }
+    group.finish();
+}
+
+fn render_with_tombstones(c: &mut Criterion) {
+    let rga = RGA::new(1);
+    let mut last_id = rga.sentinel_start_id();
+    for i in 0..10_000usize {
+        last_id = rga.insert_after(last_id, 'a').unwrap();
+        if i % 2 == 0 {
+            rga.delete(last_id).unwrap();
+        }
+    }
+
+    c.bench_function("to_string_50pct_tombstones", |b| {
+        b.iter(|| black_box(rga.to_string()))
+    });
+}
+
+#[cfg(not(feature = "single-threaded"))]
+criterion_group!(
+    benches,
+    sequential_inserts,
+    bulk_build,
+    concurrent_inserts,
+    replica_sync,
+    convergence_under_latency,
+    sequence_backends,
+    render_with_tombstones
+);
+// Compare against the default build with: cargo bench --features single-threaded
+#[cfg(feature = "single-threaded")]
+criterion_group!(
+    benches,
+    sequential_inserts,
+    bulk_build,
+    replica_sync,
+    convergence_under_latency,
+    sequence_backends,
+    render_with_tombstones
+);
+criterion_main!(benches);
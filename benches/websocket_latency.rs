@@ -0,0 +1,106 @@
+//! Benchmarks end-to-end latency of edits through the collaboration server.
+//!
+//! Starts the Axum server on a loopback port with `M` sessions connected to
+//! the `main` document. Each iteration, one session sends an `insert` frame
+//! and the sample is the time until every session, the sender included, has
+//! received the resulting `update`. This covers parsing the frame, applying
+//! it to the document and fanning it out, so changes to per-document locking
+//! or the broadcast path show up here.
+//!
+//! Run with: cargo bench --bench websocket_latency
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crdt_rga::RGA;
+use crdt_rga::server::create_router;
+use crdt_rga::server::documents::{AppState, DocumentRegistry};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use futures_util::future::join_all;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async_with_config};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serves a fresh `main` document on a loopback port, returning its URL
+async fn start_server() -> String {
+    let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, create_router().with_state(state))
+            .await
+            .unwrap();
+    });
+    format!("ws://{addr}/ws")
+}
+
+/// Reads frames until one of the given type arrives
+async fn next_frame(socket: &mut Socket, kind: &str) -> Value {
+    loop {
+        let message = socket.next().await.expect("server closed the connection");
+        if let Message::Text(text) = message.unwrap() {
+            let frame: Value = serde_json::from_str(&text).unwrap();
+            if frame["type"] == kind {
+                return frame;
+            }
+        }
+    }
+}
+
+/// Connects a session and waits until it has caught up with the document
+async fn connect(url: &str) -> Socket {
+    // Without Nagle's algorithm, so small frames are not held back on the
+    // client side
+    let (mut socket, _) = connect_async_with_config(url, None, true).await.unwrap();
+    next_frame(&mut socket, "caught_up").await;
+    socket
+}
+
+/// Sends one insertion from the first session and waits for its update to
+/// reach every session
+async fn round_trip(sessions: &mut [Socket]) -> Duration {
+    let insert = json!({"type": "insert", "character": "x", "position": 0});
+    let start = Instant::now();
+    sessions[0]
+        .send(Message::Text(insert.to_string()))
+        .await
+        .unwrap();
+    join_all(
+        sessions
+            .iter_mut()
+            .map(|socket| next_frame(socket, "update")),
+    )
+    .await;
+    start.elapsed()
+}
+
+fn broadcast_latency(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("websocket_broadcast_latency");
+    for sessions in [1usize, 10, 50] {
+        let mut connected = runtime.block_on(async {
+            let url = start_server().await;
+            join_all((0..sessions).map(|_| connect(&url))).await
+        });
+        group.bench_with_input(BenchmarkId::from_parameter(sessions), &sessions, |b, _| {
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        total += round_trip(&mut connected).await;
+                    }
+                    total
+                })
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcast_latency);
+criterion_main!(benches);
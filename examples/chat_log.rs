@@ -0,0 +1,135 @@
+//! A replicated chat room built on the sequence CRDT.
+//!
+//! Three participants post, edit and delete messages in a `MessageLog` while
+//! their operations travel to each other with a delay, so some of them cross.
+//! Every participant ends up with the same history: concurrent messages in
+//! the same order, the latest of two concurrent edits, and no trace of the
+//! deleted message.
+//!
+//! Run with: cargo run --example chat_log
+
+use crdt_rga::{MessageLog, MessageOp, UniqueId};
+
+#[derive(Debug, Clone)]
+struct ChatMessage {
+    sender: &'static str,
+    text: String,
+}
+
+/// A participant's log and the operations it has not sent yet
+struct Participant {
+    name: &'static str,
+    log: MessageLog<ChatMessage>,
+    outbox: Vec<MessageOp<ChatMessage>>,
+}
+
+impl Participant {
+    fn new(replica_id: u64, name: &'static str) -> Self {
+        Self {
+            name,
+            log: MessageLog::new(replica_id),
+            outbox: Vec::new(),
+        }
+    }
+
+    fn say(&mut self, text: &str) -> UniqueId {
+        let op = self
+            .log
+            .post(ChatMessage {
+                sender: self.name,
+                text: text.to_string(),
+            })
+            .expect("the room is open");
+        let MessageOp::Post { node, .. } = &op else {
+            unreachable!("posting returns a post");
+        };
+        let id = node.id;
+        self.outbox.push(op);
+        id
+    }
+
+    fn correct(&mut self, id: UniqueId, text: &str) {
+        let message = ChatMessage {
+            text: text.to_string(),
+            ..self.log.get(id).expect("the message exists").clone()
+        };
+        let op = self.log.edit(id, message).expect("the message exists");
+        self.outbox.push(op);
+    }
+
+    fn retract(&mut self, id: UniqueId) {
+        let op = self.log.delete(id).expect("the message exists");
+        self.outbox.push(op);
+    }
+}
+
+/// Delivers every unsent operation to every other participant, in the order
+/// each sender made them
+fn sync(room: &mut [Participant]) {
+    let sent: Vec<(usize, Vec<MessageOp<ChatMessage>>)> = room
+        .iter_mut()
+        .enumerate()
+        .map(|(from, participant)| (from, std::mem::take(&mut participant.outbox)))
+        .collect();
+    for (from, ops) in sent {
+        for (to, participant) in room.iter_mut().enumerate() {
+            if to != from {
+                for op in &ops {
+                    participant.log.apply(op.clone());
+                }
+            }
+        }
+    }
+}
+
+fn print_history(participant: &Participant) {
+    println!("{}'s view:", participant.name);
+    for message in participant.log.messages() {
+        let edited = if message.edited { " (edited)" } else { "" };
+        println!(
+            "  [{}] {}: {}{}",
+            message.author, message.message.sender, message.message.text, edited
+        );
+    }
+}
+
+fn main() {
+    let mut room = [
+        Participant::new(1, "alice"),
+        Participant::new(2, "bob"),
+        Participant::new(3, "carol"),
+    ];
+
+    let agenda = room[0].say("Standup in 5 minutes?");
+    sync(&mut room);
+
+    // Bob and Carol answer at the same time, and Alice fixes her typo while
+    // Bob also rewords her message
+    room[1].say("Sure");
+    let carol = room[2].say("Can we make it 10?");
+    room[0].correct(agenda, "Standup in 5 minutes");
+    room[1].correct(agenda, "Standup in 5 minutes!");
+    sync(&mut room);
+
+    // Carol changes her mind and takes her message back
+    room[2].retract(carol);
+    room[2].say("Never mind, 5 works");
+    sync(&mut room);
+
+    for participant in &room {
+        print_history(participant);
+    }
+    let histories: Vec<Vec<String>> = room
+        .iter()
+        .map(|participant| {
+            participant
+                .log
+                .messages()
+                .iter()
+                .map(|message| message.message.text.clone())
+                .collect()
+        })
+        .collect();
+    assert!(histories.windows(2).all(|pair| pair[0] == pair[1]));
+    println!("All {} participants agree.", room.len());
+}
@@ -0,0 +1,42 @@
+//! Several users editing one document at once over a simulated WAN.
+//!
+//! Runs the seeded scenario from `crdt_rga::testing::simulation`, the same one
+//! the `wan_convergence_time` benchmark measures, and prints what the replicas
+//! converged on. The same arguments always print the same document.
+//!
+//! Run with: cargo run --example concurrent_editing -- [replicas] [ops] [seed]
+
+use crdt_rga::testing::{Latency, LinkConfig, Simulation, SimulationConfig};
+use std::time::Duration;
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse::<u64>().expect("arguments must be numbers"));
+    let defaults = SimulationConfig::default();
+    let config = SimulationConfig {
+        replicas: args.next().map_or(defaults.replicas, |n| n as usize),
+        ops_per_replica: args.next().map_or(defaults.ops_per_replica, |n| n as usize),
+        seed: args.next().unwrap_or(defaults.seed),
+        link: LinkConfig {
+            latency: Latency::LongTail {
+                base: Duration::from_millis(40),
+                tail: Duration::from_millis(400),
+                probability: 0.05,
+            },
+            jitter: Duration::from_millis(30),
+        },
+        ..defaults
+    };
+
+    println!(
+        "{} replicas making {} edits each, seed {}",
+        config.replicas, config.ops_per_replica, config.seed
+    );
+    let report = Simulation::new(config).run();
+    println!(
+        "{} insertions and {} deletions converged after {:?} of simulated time",
+        report.inserts, report.deletes, report.convergence_time
+    );
+    println!("Document: {:?}", report.content);
+}
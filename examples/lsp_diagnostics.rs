@@ -0,0 +1,124 @@
+//! Collaborative code editing with language server diagnostics.
+//!
+//! A language server checks a snapshot of a hosted document. While it works, a
+//! remote collaborator deletes a line above the code it is checking, so the
+//! positions it reports are stale by the time they arrive. The diagnostics are
+//! remapped onto the live text with the LSP helpers and shared with every
+//! connected session as decorations.
+//!
+//! Run with: cargo run --example lsp_diagnostics
+
+use crdt_rga::server::decorations::DecorationSpan;
+use crdt_rga::server::documents::DocumentRegistry;
+use crdt_rga::{LspPosition, LspRange, RGA};
+
+const SOURCE: &str = "// 🚧 scratch file\nfn main() {\n    let smile = \"😀\"; let unused_smile = smile;\n    let unused = 1;\n}\n";
+
+/// A stand-in language server: warns about bindings whose name starts with
+/// `unused`, reporting LSP ranges in UTF-16 code units
+fn check(text: &str) -> Vec<(LspRange, String)> {
+    let mut diagnostics = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let Some(binding) = content.find("let unused") else {
+            continue;
+        };
+        let name_start = binding + "let ".len();
+        let name_len = content[name_start..]
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(content.len() - name_start);
+        let name = &content[name_start..name_start + name_len];
+
+        let character = content[..name_start].encode_utf16().count() as u32;
+        let range = LspRange::new(
+            LspPosition::new(line as u32, character),
+            LspPosition::new(line as u32, character + name.encode_utf16().count() as u32),
+        );
+        diagnostics.push((range, format!("unused variable `{name}`")));
+    }
+    diagnostics
+}
+
+fn show(label: &str, rga: &RGA, diagnostics: &[(LspRange, String)]) {
+    println!("{label}:");
+    for (range, message) in diagnostics {
+        let start = rga.lsp_position_to_index(range.start).unwrap();
+        let end = rga.lsp_position_to_index(range.end).unwrap();
+        let text: String = rga
+            .to_string()
+            .chars()
+            .skip(start)
+            .take(end - start)
+            .collect();
+        println!(
+            "  {}:{}-{}:{} {:<34} covers {:?}",
+            range.start.line,
+            range.start.character,
+            range.end.line,
+            range.end.character,
+            message,
+            text
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let registry = DocumentRegistry::new(RGA::new(1));
+    let document = registry.main();
+    document
+        .rga
+        .insert_str_after(document.rga.sentinel_start_id(), SOURCE)
+        .unwrap();
+
+    // The language server is sent the current text
+    let snapshot = document.snapshot().await;
+    let diagnostics = check(&snapshot.to_string());
+    show("Reported against the snapshot", &snapshot, &diagnostics);
+
+    // Meanwhile a collaborator removes the comment on the first line
+    let remote = document.rga.fork(2);
+    remote.delete_line(0).unwrap();
+    for node in remote.missing_from(&document.rga) {
+        document.rga.apply_remote_op(node);
+    }
+    println!("\nLive text after the remote edit:\n{}", document.rga);
+
+    show(
+        "Stale positions in the live text",
+        &document.rga,
+        &diagnostics,
+    );
+
+    let ranges: Vec<LspRange> = diagnostics.iter().map(|(range, _)| *range).collect();
+    let remapped: Vec<(LspRange, String)> = document
+        .rga
+        .remap_lsp_ranges(&snapshot, &ranges)
+        .unwrap()
+        .into_iter()
+        .zip(diagnostics.into_iter().map(|(_, message)| message))
+        .collect();
+    show("\nRemapped onto the live text", &document.rga, &remapped);
+
+    // Sessions display the diagnostics as decorations until their text changes
+    let spans = remapped
+        .iter()
+        .map(|(range, message)| DecorationSpan {
+            start: document.rga.lsp_position_to_index(range.start).unwrap(),
+            end: document.rga.lsp_position_to_index(range.end).unwrap(),
+            kind: "warning".to_string(),
+            message: Some(message.clone()),
+            session_id: None,
+        })
+        .collect();
+    document.decorate("language-server", spans).unwrap();
+    println!("\nShared decorations:");
+    for decoration in document.decorations() {
+        println!(
+            "  {}..{} {} {}",
+            decoration.start,
+            decoration.end,
+            decoration.kind,
+            decoration.message.unwrap_or_default()
+        );
+    }
+}
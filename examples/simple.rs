@@ -10,25 +10,32 @@
 //! Run with: cargo run --example simple
 
 use crdt_rga::RGA;
-use std::sync::mpsc;
+use crdt_rga::testing::Cluster;
 use std::thread;
 use std::time::Duration;
 
 struct CollaborativeSession {
-    alice: RGA,
-    bob: RGA,
+    /// Alice is replica 1, Bob replica 2
+    replicas: Cluster,
     network_delay: Duration,
 }
 
 impl CollaborativeSession {
     fn new() -> Self {
         Self {
-            alice: RGA::new(1), // Alice = replica 1
-            bob: RGA::new(2),   // Bob = replica 2
+            replicas: Cluster::new(2),
             network_delay: Duration::from_millis(50),
         }
     }
 
+    fn alice(&self) -> &RGA {
+        self.replicas.replica(0)
+    }
+
+    fn bob(&self) -> &RGA {
+        self.replicas.replica(1)
+    }
+
     fn simulate_typing(&self, user: &str, text: &str, typing_speed: Duration) {
         print!("{} types: ", user);
         for ch in text.chars() {
@@ -43,26 +50,15 @@ impl CollaborativeSession {
         // Simulate network synchronization
         thread::sleep(self.network_delay);
 
-        // Alice's changes -> Bob
-        for node in self.alice.all_nodes() {
-            if !node.is_sentinel() {
-                self.bob.apply_remote_op(node);
-            }
-        }
-
-        // Bob's changes -> Alice
-        for node in self.bob.all_nodes() {
-            if !node.is_sentinel() {
-                self.alice.apply_remote_op(node);
-            }
-        }
+        // Alice's changes -> Bob, Bob's changes -> Alice
+        self.replicas.run_until_quiescent();
     }
 
     fn show_status(&self) {
-        println!("  Alice sees: '{}'", self.alice.to_string());
-        println!("  Bob sees:   '{}'", self.bob.to_string());
+        println!("  Alice sees: '{}'", self.alice());
+        println!("  Bob sees:   '{}'", self.bob());
 
-        if self.alice.to_string() == self.bob.to_string() {
+        if self.alice().to_string() == self.bob().to_string() {
             println!("  ✅ Synchronized!");
         } else {
             println!("  ⏳ Synchronizing...");
@@ -83,14 +79,14 @@ fn main() {
 
     // Alice starts the document
     println!("👩 Alice starts writing...");
-    let start_id = session.alice.sentinel_start_id();
+    let start_id = session.alice().sentinel_start_id();
     let mut last_id = start_id;
 
     let alice_text = "Hello";
     session.simulate_typing("Alice", alice_text, Duration::from_millis(200));
 
     for ch in alice_text.chars() {
-        last_id = session.alice.insert_after(last_id, ch).unwrap();
+        last_id = session.alice().insert_after(last_id, ch).unwrap();
     }
 
     session.show_status();
@@ -103,7 +99,7 @@ fn main() {
     // Bob continues where Alice left off
     println!("👨 Bob continues the sentence...");
     let alice_last_char = session
-        .bob
+        .bob()
         .all_nodes()
         .into_iter()
         .filter(|n| !n.is_sentinel() && !n.is_deleted)
@@ -116,7 +112,7 @@ fn main() {
     session.simulate_typing("Bob", bob_text, Duration::from_millis(180));
 
     for ch in bob_text.chars() {
-        bob_last_id = session.bob.insert_after(bob_last_id, ch).unwrap();
+        bob_last_id = session.bob().insert_after(bob_last_id, ch).unwrap();
     }
 
     session.show_status();
@@ -136,14 +132,14 @@ fn main() {
     println!("👩👨 Both users start typing at the same time...");
 
     // Simulate Alice typing "Fast" concurrently with Bob typing "Code"
-    let start_id = concurrent_session.alice.sentinel_start_id();
+    let start_id = concurrent_session.alice().sentinel_start_id();
 
     // Alice types "Fast" from beginning
     println!("🚀 Alice types from start:");
     let mut alice_last = start_id;
     for ch in "Fast".chars() {
         alice_last = concurrent_session
-            .alice
+            .alice()
             .insert_after(alice_last, ch)
             .unwrap();
         println!("  Alice typed: '{}'", ch);
@@ -154,24 +150,24 @@ fn main() {
     println!("🚀 Bob types from start (concurrent with Alice):");
     let mut bob_last = start_id;
     for ch in "Code".chars() {
-        bob_last = concurrent_session.bob.insert_after(bob_last, ch).unwrap();
+        bob_last = concurrent_session.bob().insert_after(bob_last, ch).unwrap();
         println!("  Bob typed: '{}'", ch);
         thread::sleep(Duration::from_millis(45));
     }
 
     println!("\nBefore synchronization:");
-    println!("  Alice sees: '{}'", concurrent_session.alice.to_string());
-    println!("  Bob sees:   '{}'", concurrent_session.bob.to_string());
+    println!("  Alice sees: '{}'", concurrent_session.alice());
+    println!("  Bob sees:   '{}'", concurrent_session.bob());
 
     // Apply all operations to both replicas (simulating full sync)
     println!("\n🌐 Network synchronization...");
     concurrent_session.sync_changes();
 
     println!("\n📊 Final Results:");
-    println!("  Alice's view: '{}'", concurrent_session.alice.to_string());
-    println!("  Bob's view:   '{}'", concurrent_session.bob.to_string());
+    println!("  Alice's view: '{}'", concurrent_session.alice());
+    println!("  Bob's view:   '{}'", concurrent_session.bob());
 
-    if concurrent_session.alice.to_string() == concurrent_session.bob.to_string() {
+    if concurrent_session.alice().to_string() == concurrent_session.bob().to_string() {
         println!("  ✅ Perfect convergence despite concurrent edits!");
         println!("  🎯 Notice how characters are interleaved based on Lamport timestamps!");
     }
@@ -180,7 +176,7 @@ fn main() {
     println!("\n🔍 Technical Analysis:");
     println!("The final order is determined by Lamport timestamps:");
     println!("(Lower counter wins, replica_id breaks ties)");
-    concurrent_session.alice.dump_nodes();
+    concurrent_session.alice().dump_nodes();
 
     println!("\n🎯 Real-World Applications:");
     println!("• Google Docs, Notion, Figma - collaborative editors");
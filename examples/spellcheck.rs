@@ -0,0 +1,119 @@
+//! Spell checking a hosted document as it is edited.
+//!
+//! A dictionary-based checker is attached to the main document as an
+//! analyzer. A bot then types two lines and fixes a typo; after each edit
+//! the checker re-checks the touched line and the unknown words are shared
+//! with every session as `spelling` decorations.
+//!
+//! Run with: cargo run --example spellcheck
+//!
+//! With `--serve`, the server keeps running on http://127.0.0.1:3000 after
+//! the demo, so WebSocket clients joining `/ws` see the decorations update as
+//! they type.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crdt_rga::RGA;
+use crdt_rga::server::analysis::{Analyzer, spawn_analyzer};
+use crdt_rga::server::decorations::DecorationSpan;
+use crdt_rga::server::documents::{AppState, DocumentRegistry, MAIN_DOCUMENT};
+use crdt_rga::server::{create_router, serve};
+
+const DICTIONARY: &str = "a an and are as at be by collaborative document documents edit edits \
+    every for in is it many merge of on one replica replicas same text that the this to with";
+
+/// Flags the words of a fixed dictionary's language that it does not know
+struct SpellChecker {
+    words: HashSet<&'static str>,
+}
+
+impl Analyzer for SpellChecker {
+    fn name(&self) -> &str {
+        "spellcheck"
+    }
+
+    async fn analyze(&self, text: &str, range: Range<usize>) -> Vec<DecorationSpan> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut spans = Vec::new();
+        let mut start = range.start;
+        while start < range.end {
+            if !chars[start].is_alphabetic() {
+                start += 1;
+                continue;
+            }
+            let end = (start..range.end)
+                .find(|&i| !chars[i].is_alphabetic())
+                .unwrap_or(range.end);
+            let word: String = chars[start..end].iter().collect();
+            if !self.words.contains(word.to_lowercase().as_str()) {
+                spans.push(DecorationSpan {
+                    start,
+                    end,
+                    kind: "spelling".to_string(),
+                    message: Some(format!("unknown word `{word}`")),
+                    session_id: None,
+                });
+            }
+            start = end;
+        }
+        spans
+    }
+}
+
+async fn show(state: &AppState, label: &str) {
+    // Give the analyzer a moment to catch up with the edit
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let main = state.main();
+    let text: Vec<char> = main.rga.to_string().chars().collect();
+    println!("{label}:");
+    for span in main.decorations() {
+        let word: String = text[span.start..span.end].iter().collect();
+        println!(
+            "  {:>3}..{:<3} {:<10} {}",
+            span.start,
+            span.end,
+            word,
+            span.message.unwrap_or_default()
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+    spawn_analyzer(
+        state.main(),
+        SpellChecker {
+            words: DICTIONARY.split_whitespace().collect(),
+        },
+    );
+
+    let mut bot = state.attach_bot(MAIN_DOCUMENT, "typist").unwrap();
+    bot.insert(0, "Every replica edits the same documnet")
+        .await
+        .unwrap();
+    show(&state, "After the first line").await;
+
+    // Retype the end of the misspelled word
+    let end = bot.text().chars().count();
+    bot.replace(end - 3, 3, "ent").await.unwrap();
+    show(&state, "\nAfter fixing the typo").await;
+
+    let end = bot.text().chars().count();
+    bot.insert(end, ".\nEdits are colaborative and merge.")
+        .await
+        .unwrap();
+    show(&state, "\nAfter the second line").await;
+
+    if std::env::args().any(|arg| arg == "--serve") {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+        println!("\nServing on http://{addr}, join /ws to edit with spell checking");
+        if let Err(e) = serve(addr, create_router().with_state(state)).await {
+            eprintln!("Server error: {e}");
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Compares two states of a document for support and debugging.
+//!
+//! ```text
+//! crdt-rga-diff OLD.snapshot NEW.snapshot
+//! crdt-rga-diff OLD.snapshot --log OPS.log
+//! ```
+//!
+//! The second form compares a snapshot with the state reached by replaying an
+//! operation log on top of it. Snapshots come from `RGA::to_snapshot` or
+//! `GET /docs/{id}/export?format=snapshot`; see `crdt::snapshot` for both
+//! formats.
+//!
+//! Prints a unified diff of the visible text, the operations that separate the
+//! two states, and what each replica contributed. Tombstones do not record who
+//! deleted a character, so deletions are attributed to the replica that wrote
+//! the deleted text. Exits with 0 if the states are identical, 1 if they
+//! differ and 2 on error, like `diff`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+use crdt_rga::{RGA, ReplicaId, UniqueId, parse_op_log};
+
+/// Unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+const USAGE: &str = "usage: crdt-rga-diff OLD.snapshot (NEW.snapshot | --log OPS.log)";
+
+/// How a node differs between the two states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Inserted,
+    /// Inserted and deleted again between the two states
+    InsertedDeleted,
+    Deleted,
+    Restored,
+    /// Known to the old state only; the new one is not a successor of it
+    Missing,
+}
+
+impl Change {
+    fn label(self) -> &'static str {
+        match self {
+            Change::Inserted => "inserted",
+            Change::InsertedDeleted => "inserted+deleted",
+            Change::Deleted => "deleted",
+            Change::Restored => "restored",
+            Change::Missing => "missing",
+        }
+    }
+}
+
+/// A node of either state, in document order
+struct Entry {
+    id: UniqueId,
+    character: char,
+    /// `Some(visible)` if the state has the node
+    old: Option<bool>,
+    new: Option<bool>,
+}
+
+impl Entry {
+    fn in_old(&self) -> bool {
+        self.old == Some(true)
+    }
+
+    fn in_new(&self) -> bool {
+        self.new == Some(true)
+    }
+
+    fn change(&self) -> Option<Change> {
+        match (self.old, self.new) {
+            (None, Some(true)) => Some(Change::Inserted),
+            (None, Some(false)) => Some(Change::InsertedDeleted),
+            (Some(true), Some(false)) => Some(Change::Deleted),
+            (Some(false), Some(true)) => Some(Change::Restored),
+            (Some(_), None) => Some(Change::Missing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Context,
+    Removed,
+    Added,
+}
+
+/// Aligns the nodes of both states by ID
+fn align(old: &RGA, new: &RGA) -> Vec<Entry> {
+    let union = new.fork(new.replica_id());
+    for node in old.missing_from(&union) {
+        union.apply_remote_op(node);
+    }
+    union
+        .all_nodes()
+        .into_iter()
+        .filter(|node| !node.is_sentinel())
+        .map(|node| Entry {
+            id: node.id,
+            character: node.character,
+            old: old.get_node(node.id).map(|node| !node.is_deleted),
+            new: new.get_node(node.id).map(|node| !node.is_deleted),
+        })
+        .collect()
+}
+
+/// Splits both texts into lines and pairs them up.
+///
+/// A line break visible in both states separates lines that can be compared
+/// one to one. Everything between two such breaks is either the same line in
+/// both, or a changed block shown as its old lines followed by its new ones.
+fn line_diff(entries: &[Entry]) -> Vec<(Tag, String)> {
+    let mut lines = Vec::new();
+    // Consecutive changed blocks are shown as one, removals first
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let mut old = String::new();
+    let mut new = String::new();
+    let mut flush = |old: &mut String, new: &mut String, last: bool| {
+        if old == new {
+            lines.extend(removed.drain(..).map(|line| (Tag::Removed, line)));
+            lines.extend(added.drain(..).map(|line| (Tag::Added, line)));
+            if !last || !old.is_empty() {
+                lines.push((Tag::Context, old.clone()));
+            }
+        } else {
+            // Text after the final break is only a line if it is not empty
+            let split = |text: &str| -> Vec<String> {
+                if last {
+                    text.lines().map(String::from).collect()
+                } else {
+                    text.split('\n').map(String::from).collect()
+                }
+            };
+            removed.extend(split(old));
+            added.extend(split(new));
+            if last {
+                lines.extend(removed.drain(..).map(|line| (Tag::Removed, line)));
+                lines.extend(added.drain(..).map(|line| (Tag::Added, line)));
+            }
+        }
+        old.clear();
+        new.clear();
+    };
+
+    for entry in entries {
+        if entry.character == '\n' && entry.in_old() && entry.in_new() {
+            flush(&mut old, &mut new, false);
+            continue;
+        }
+        if entry.in_old() {
+            old.push(entry.character);
+        }
+        if entry.in_new() {
+            new.push(entry.character);
+        }
+    }
+    flush(&mut old, &mut new, true);
+    lines
+}
+
+/// Formats the line diff as unified diff hunks
+fn unified(lines: &[(Tag, String)], old_name: &str, new_name: &str) -> String {
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].0 != Tag::Context)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Line numbers in each file before every diff line
+    let mut before = Vec::with_capacity(lines.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for (tag, _) in lines {
+        before.push((old_line, new_line));
+        if *tag != Tag::Added {
+            old_line += 1;
+        }
+        if *tag != Tag::Removed {
+            new_line += 1;
+        }
+    }
+    before.push((old_line, new_line));
+
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+    let mut next = 0;
+    while next < changed.len() {
+        let start = changed[next].saturating_sub(CONTEXT);
+        let mut last = changed[next];
+        next += 1;
+        while next < changed.len() && changed[next] - last <= 2 * CONTEXT {
+            last = changed[next];
+            next += 1;
+        }
+        let end = (last + CONTEXT + 1).min(lines.len());
+
+        let range = |from: usize, to: usize| {
+            let len = to - from;
+            if len == 0 {
+                format!("{from},0")
+            } else {
+                format!("{},{len}", from + 1)
+            }
+        };
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(before[start].0, before[end].0),
+            range(before[start].1, before[end].1)
+        );
+        for (tag, text) in &lines[start..end] {
+            let marker = match tag {
+                Tag::Context => ' ',
+                Tag::Removed => '-',
+                Tag::Added => '+',
+            };
+            let _ = writeln!(out, "{marker}{text}");
+        }
+    }
+    out
+}
+
+fn format_id(id: UniqueId) -> String {
+    format!("{}.{}.{}", id.counter(), id.replica_id(), id.sequence())
+}
+
+/// Lists changed nodes, merging neighbours with the same change and author
+fn op_diff(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    let mut run: Option<(Change, UniqueId, UniqueId, String)> = None;
+    let mut emit = |run: &mut Option<(Change, UniqueId, UniqueId, String)>| {
+        if let Some((change, first, last, text)) = run.take() {
+            let ids = if first == last {
+                format_id(first)
+            } else {
+                format!("{}..{}", format_id(first), format_id(last))
+            };
+            let _ = writeln!(
+                out,
+                "  {:<16} replica {:<4} {:<24} {:?}",
+                change.label(),
+                first.replica_id(),
+                ids,
+                text
+            );
+        }
+    };
+
+    for entry in entries {
+        let Some(change) = entry.change() else {
+            emit(&mut run);
+            continue;
+        };
+        match &mut run {
+            Some((kind, first, last, text))
+                if *kind == change && first.replica_id() == entry.id.replica_id() =>
+            {
+                *last = entry.id;
+                text.push(entry.character);
+            }
+            _ => {
+                emit(&mut run);
+                run = Some((change, entry.id, entry.id, entry.character.to_string()));
+            }
+        }
+    }
+    emit(&mut run);
+    out
+}
+
+#[derive(Default)]
+struct Contribution {
+    /// Nodes in the new state, tombstones included
+    nodes: usize,
+    /// Characters visible in the new state
+    visible: usize,
+    /// Nodes the old state did not have
+    inserted: usize,
+    /// Characters visible in the old state and deleted in the new one
+    deleted: usize,
+}
+
+fn contributions(entries: &[Entry]) -> String {
+    let mut replicas: BTreeMap<ReplicaId, Contribution> = BTreeMap::new();
+    for entry in entries {
+        let replica = replicas.entry(entry.id.replica_id()).or_default();
+        if entry.new.is_some() {
+            replica.nodes += 1;
+        }
+        if entry.in_new() {
+            replica.visible += 1;
+        }
+        if entry.old.is_none() {
+            replica.inserted += 1;
+        }
+        if entry.in_old() && entry.new == Some(false) {
+            replica.deleted += 1;
+        }
+    }
+
+    let mut out = format!(
+        "  {:>8} {:>8} {:>8} {:>8} {:>8}\n",
+        "replica", "nodes", "visible", "inserted", "deleted"
+    );
+    for (replica_id, c) in replicas {
+        let _ = writeln!(
+            out,
+            "  {:>8} {:>8} {:>8} {:>8} {:>8}",
+            replica_id, c.nodes, c.visible, c.inserted, c.deleted
+        );
+    }
+    out
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+}
+
+fn load(path: &str) -> Result<RGA, String> {
+    RGA::from_snapshot(0, &read(path)?).map_err(|e| format!("{path}: {e}"))
+}
+
+fn run(args: &[String]) -> Result<bool, String> {
+    let (old_name, new_name, old, new) = match args {
+        [old_path, flag, log_path] if flag == "--log" => {
+            let old = load(old_path)?;
+            let new = old.fork(0);
+            let entries = parse_op_log(&read(log_path)?).map_err(|e| format!("{log_path}: {e}"))?;
+            for (line, entry) in entries.iter().enumerate() {
+                new.apply_log_entry(entry)
+                    .map_err(|e| format!("{log_path}: entry {}: {e}", line + 1))?;
+            }
+            (old_path, log_path, old, new)
+        }
+        [old_path, new_path] => (old_path, new_path, load(old_path)?, load(new_path)?),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let entries = align(&old, &new);
+    let ops = op_diff(&entries);
+    if ops.is_empty() {
+        println!("No differences");
+        return Ok(false);
+    }
+
+    print!("{}", unified(&line_diff(&entries), old_name, new_name));
+    println!("\nOperations:\n{ops}");
+    println!("Replicas:\n{}", contributions(&entries));
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(false) => ExitCode::SUCCESS,
+        Ok(true) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("crdt-rga-diff: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
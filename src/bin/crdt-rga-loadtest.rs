@@ -0,0 +1,455 @@
+//! Drives a collaboration server with many simulated typists.
+//!
+//! ```text
+//! crdt-rga-loadtest [--url ws://127.0.0.1:3000/ws] [--clients 100]
+//!                   [--duration 30] [--wpm 40] [--seed 1]
+//! ```
+//!
+//! Every client connects to `--url` (a `/docs/{id}/ws` URL with a `token`
+//! query parameter works too), then types for `--duration` seconds at a caret
+//! of its own. Keystroke intervals are log-normal around the mean `--wpm`
+//! implies, with occasional pauses, bursts and backspaces, so load arrives in
+//! the uneven way people produce it.
+//!
+//! Clients negotiate batching in `hello`, so their own updates arrive as
+//! plain frames and their peers' in `batch` frames. The report gives:
+//!
+//! - op latency: from sending an edit to receiving its `update`
+//! - convergence lag: from the first client seeing a document version to the
+//!   last client seeing it
+//!
+//! Exits with 0 after a run, even a degraded one, and 2 on errors.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crdt_rga::testing::SplitMix64;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::net::TcpStream;
+use tokio::time::sleep_until;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+
+const USAGE: &str =
+    "usage: crdt-rga-loadtest [--url URL] [--clients N] [--duration SECS] [--wpm WPM] [--seed N]";
+
+/// How long clients keep reading after they stop typing, for the last
+/// updates to reach everyone
+const DRAIN: Duration = Duration::from_secs(5);
+/// Characters per word, by the usual typing-speed convention
+const CHARS_PER_WORD: f64 = 5.0;
+/// Spread of the log-normal keystroke intervals
+const INTERVAL_SIGMA: f64 = 0.6;
+/// Chance that a keystroke is preceded by a pause to think
+const PAUSE_PROBABILITY: f64 = 0.03;
+/// Chance that a keystroke is a backspace
+const BACKSPACE_PROBABILITY: f64 = 0.06;
+
+/// What the typists type, from a random offset each
+const TEXT: &str = "The quick brown fox jumps over the lazy dog while the \
+collaborative editor merges every keystroke into one shared document.\n\
+Replicas exchange operations, converge on the same text and never lose \
+an edit, however busy the document gets.\n";
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct Config {
+    url: String,
+    clients: usize,
+    duration: Duration,
+    wpm: f64,
+    seed: u64,
+}
+
+fn parse_args(args: &[String]) -> Result<Config, String> {
+    let mut config = Config {
+        url: "ws://127.0.0.1:3000/ws".to_string(),
+        clients: 100,
+        duration: Duration::from_secs(30),
+        wpm: 40.0,
+        seed: 1,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE)?;
+        let invalid = || format!("invalid value for {flag}: {value}");
+        match flag.as_str() {
+            "--url" => config.url = value.clone(),
+            "--clients" => config.clients = value.parse().map_err(|_| invalid())?,
+            "--duration" => {
+                let seconds: f64 = value.parse().map_err(|_| invalid())?;
+                config.duration = Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?;
+            }
+            "--wpm" => config.wpm = value.parse().map_err(|_| invalid())?,
+            "--seed" => config.seed = value.parse().map_err(|_| invalid())?,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if config.clients == 0 || config.wpm <= 0.0 {
+        return Err("--clients and --wpm must be positive".to_string());
+    }
+    Ok(config)
+}
+
+/// Standard normal sample, by the Box-Muller transform
+fn next_normal(rng: &mut SplitMix64) -> f64 {
+    let u = 1.0 - rng.next_f64();
+    let v = rng.next_f64();
+    (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
+
+/// A keystroke of a simulated typist
+enum Key {
+    Char(char),
+    Backspace,
+}
+
+/// Decides what a simulated person types, and when
+struct Typist {
+    rng: SplitMix64,
+    /// Log-mean of the interval between keystrokes, in seconds
+    mu: f64,
+    text: std::iter::Cycle<std::str::Chars<'static>>,
+}
+
+impl Typist {
+    fn new(seed: u64, wpm: f64) -> Self {
+        let mut rng = SplitMix64::new(seed);
+        let mean = 60.0 / (wpm * CHARS_PER_WORD);
+        let offset = (rng.next_u64() % TEXT.len() as u64) as usize;
+        let mut text = TEXT.chars().cycle();
+        for _ in 0..offset {
+            text.next();
+        }
+        Typist {
+            rng,
+            // The log-normal's mean is exp(mu + sigma²/2)
+            mu: mean.ln() - INTERVAL_SIGMA * INTERVAL_SIGMA / 2.0,
+            text,
+        }
+    }
+
+    /// Time until the next keystroke
+    fn next_delay(&mut self) -> Duration {
+        let interval = (self.mu + INTERVAL_SIGMA * next_normal(&mut self.rng)).exp();
+        let pause = if self.rng.next_f64() < PAUSE_PROBABILITY {
+            1.0 + 3.0 * self.rng.next_f64()
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64(interval + pause)
+    }
+
+    fn next_key(&mut self) -> Key {
+        if self.rng.next_f64() < BACKSPACE_PROBABILITY {
+            Key::Backspace
+        } else {
+            Key::Char(self.text.next().unwrap_or(' '))
+        }
+    }
+
+    /// A caret position in a document of `length` characters
+    fn caret(&mut self, length: usize) -> usize {
+        (self.rng.next_u64() % (length as u64 + 1)) as usize
+    }
+}
+
+/// Records when every document version reached every client
+struct Convergence {
+    clients: usize,
+    /// Versions up to this one predate the run
+    floor: u64,
+    /// Versions not seen by every client yet: when the first one saw it and
+    /// how many have
+    pending: BTreeMap<u64, (Instant, usize)>,
+    lags: Vec<Duration>,
+}
+
+impl Convergence {
+    /// Records that a client went from version `from` to version `to` at
+    /// `at`. Updates carry the whole content, so it saw every version between.
+    fn observe(&mut self, from: u64, to: u64, at: Instant) {
+        for version in from.max(self.floor) + 1..=to {
+            let (first, seen) = self.pending.entry(version).or_insert((at, 0));
+            *seen += 1;
+            if *seen == self.clients {
+                self.lags.push(at - *first);
+                self.pending.remove(&version);
+            }
+        }
+    }
+}
+
+/// A connected client that has caught up with the document
+struct Session {
+    socket: Socket,
+    version: u64,
+    length: usize,
+}
+
+/// What a client measured
+#[derive(Default)]
+struct ClientReport {
+    sent: usize,
+    errors: usize,
+    latencies: Vec<Duration>,
+}
+
+fn parse_frame(text: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("invalid frame {text}: {e}"))
+}
+
+fn frame_version(frame: &Value) -> Option<u64> {
+    frame["version"].as_u64()
+}
+
+fn frame_length(frame: &Value) -> Option<usize> {
+    frame["content"]
+        .as_str()
+        .map(|content| content.chars().count())
+}
+
+/// Connect, catch up with the document and negotiate batching
+async fn connect(url: &str) -> Result<Session, String> {
+    let (mut socket, _) = connect_async(url)
+        .await
+        .map_err(|e| format!("cannot connect to {url}: {e}"))?;
+    let hello = json!({"type": "hello", "compression": []});
+    socket
+        .send(Message::Text(hello.to_string()))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut session = Session {
+        socket,
+        version: 0,
+        length: 0,
+    };
+    // `init` and `caught_up` precede the answer to `hello`
+    loop {
+        let Some(message) = session.socket.next().await else {
+            return Err("connection closed while joining".to_string());
+        };
+        let Message::Text(text) = message.map_err(|e| e.to_string())? else {
+            continue;
+        };
+        let frame = parse_frame(&text)?;
+        match frame["type"].as_str() {
+            Some("init") => session.length = frame_length(&frame).unwrap_or(0),
+            Some("caught_up") => session.version = frame_version(&frame).unwrap_or(0),
+            Some("hello") => return Ok(session),
+            _ => {}
+        }
+    }
+}
+
+/// Type until `stop`, then read until `stop` + [`DRAIN`]
+async fn run_client(
+    mut session: Session,
+    mut typist: Typist,
+    stop: Instant,
+    convergence: Arc<Mutex<Convergence>>,
+) -> Result<ClientReport, String> {
+    let mut report = ClientReport::default();
+    let mut in_flight: VecDeque<Instant> = VecDeque::new();
+    let mut caret = typist.caret(session.length);
+    let mut next_key = Instant::now() + typist.next_delay();
+    let drained = stop + DRAIN;
+
+    let observe = |session: &mut Session, version: u64| {
+        if version > session.version {
+            let mut convergence = convergence.lock().unwrap();
+            convergence.observe(session.version, version, Instant::now());
+            session.version = version;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = sleep_until(next_key.into()), if next_key < stop => {
+                caret = caret.min(session.length);
+                let operation = match typist.next_key() {
+                    Key::Backspace if caret > 0 => {
+                        caret -= 1;
+                        json!({"type": "replace", "position": caret, "length": 1, "text": ""})
+                    }
+                    Key::Backspace => json!({"type": "insert", "character": " ", "position": 0}),
+                    Key::Char(character) => {
+                        caret += 1;
+                        json!({"type": "insert", "character": character, "position": caret - 1})
+                    }
+                };
+                session
+                    .socket
+                    .send(Message::Text(operation.to_string()))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                in_flight.push_back(Instant::now());
+                report.sent += 1;
+                next_key += typist.next_delay();
+            }
+            message = session.socket.next() => {
+                let Some(message) = message else {
+                    return Err("server closed the connection".to_string());
+                };
+                let Message::Text(text) = message.map_err(|e| e.to_string())? else {
+                    continue;
+                };
+                let frame = parse_frame(&text)?;
+                match frame["type"].as_str() {
+                    Some("update") => {
+                        if let Some(sent_at) = in_flight.pop_front() {
+                            report.latencies.push(sent_at.elapsed());
+                        }
+                        session.length = frame_length(&frame).unwrap_or(session.length);
+                        observe(&mut session, frame_version(&frame).unwrap_or(0));
+                    }
+                    Some("error") => {
+                        in_flight.pop_front();
+                        report.errors += 1;
+                    }
+                    Some("batch") => {
+                        let messages = frame["messages"].as_array().map_or(&[][..], Vec::as_slice);
+                        let latest = messages.iter().filter_map(|message| {
+                            Some((frame_version(message)?, frame_length(message)?))
+                        }).max();
+                        if let Some((version, length)) = latest {
+                            session.length = length;
+                            observe(&mut session, version);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ = sleep_until(drained.into()) => break,
+        }
+    }
+    let _ = session.socket.close(None).await;
+    Ok(report)
+}
+
+/// The nearest-rank percentile `p` of `sorted`
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    match sorted.len() {
+        0 => Duration::ZERO,
+        len => sorted[((p / 100.0 * len as f64).ceil() as usize).clamp(1, len) - 1],
+    }
+}
+
+fn summary(mut samples: Vec<Duration>) -> String {
+    samples.sort();
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    format!(
+        "p50 {:.1}  p90 {:.1}  p99 {:.1}  max {:.1} ms over {}",
+        ms(percentile(&samples, 50.0)),
+        ms(percentile(&samples, 90.0)),
+        ms(percentile(&samples, 99.0)),
+        ms(samples.last().copied().unwrap_or_default()),
+        samples.len()
+    )
+}
+
+async fn run(config: Config) -> Result<(), String> {
+    let connecting: Vec<_> = (0..config.clients)
+        .map(|_| {
+            let url = config.url.clone();
+            tokio::spawn(async move { connect(&url).await })
+        })
+        .collect();
+    let mut sessions = Vec::new();
+    let mut connect_errors = BTreeMap::new();
+    for handle in connecting {
+        match handle.await.map_err(|e| e.to_string())? {
+            Ok(session) => sessions.push(session),
+            Err(e) => *connect_errors.entry(e).or_insert(0) += 1,
+        }
+    }
+    for (error, count) in &connect_errors {
+        eprintln!("crdt-rga-loadtest: {count} clients failed: {error}");
+    }
+    if sessions.is_empty() {
+        return Err("no client could connect".to_string());
+    }
+    let connected = sessions.len();
+
+    let convergence = Arc::new(Mutex::new(Convergence {
+        clients: connected,
+        floor: sessions
+            .iter()
+            .map(|session| session.version)
+            .max()
+            .unwrap_or(0),
+        pending: BTreeMap::new(),
+        lags: Vec::new(),
+    }));
+    let started = Instant::now();
+    let stop = started + config.duration;
+    let running: Vec<_> = sessions
+        .into_iter()
+        .enumerate()
+        .map(|(index, session)| {
+            let typist = Typist::new(config.seed.wrapping_add(index as u64), config.wpm);
+            tokio::spawn(run_client(session, typist, stop, Arc::clone(&convergence)))
+        })
+        .collect();
+
+    let mut total = ClientReport::default();
+    let mut dropped = 0;
+    for handle in running {
+        match handle.await.map_err(|e| e.to_string())? {
+            Ok(report) => {
+                total.sent += report.sent;
+                total.errors += report.errors;
+                total.latencies.extend(report.latencies);
+            }
+            Err(e) => {
+                eprintln!("crdt-rga-loadtest: client dropped: {e}");
+                dropped += 1;
+            }
+        }
+    }
+
+    let mut convergence = convergence.lock().unwrap();
+    let seconds = config.duration.as_secs_f64();
+    println!(
+        "clients:          {connected} connected, {} failed, {dropped} dropped",
+        config.clients - connected
+    );
+    println!(
+        "operations:       {} sent, {} acknowledged, {} rejected ({:.1} ops/s)",
+        total.sent,
+        total.latencies.len(),
+        total.errors,
+        total.sent as f64 / seconds
+    );
+    println!("op latency:       {}", summary(total.latencies));
+    println!(
+        "convergence lag:  {}",
+        summary(std::mem::take(&mut convergence.lags))
+    );
+    if !convergence.pending.is_empty() {
+        println!(
+            "unconverged:      {} versions never reached every client",
+            convergence.pending.len()
+        );
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = parse_args(&args).and_then(|config| {
+        tokio::runtime::Runtime::new()
+            .map_err(|e| e.to_string())?
+            .block_on(run(config))
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("crdt-rga-loadtest: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Replays captured WebSocket sessions.
+//!
+//! ```text
+//! crdt-rga-replay [--from BASE.snapshot] [--out RESULT.snapshot] CAPTURE...
+//! crdt-rga-replay --server URL [--speed FACTOR] CAPTURE...
+//! ```
+//!
+//! Captures are written by a server started with `SESSION_CAPTURE_DIR`; see
+//! `server::capture` for the format. Frames of all captures are merged in the
+//! order they arrived.
+//!
+//! The first form applies them to a bare RGA with the server's replica ID,
+//! starting from `--from` or an empty document, and prints the resulting
+//! text, or writes its snapshot to `--out` for `crdt-rga-diff`. This is
+//! deterministic: the same captures always produce the same document.
+//!
+//! The second form re-drives a running server, one connection per captured
+//! session, sending each frame at its captured time divided by `--speed`.
+//!
+//! Exits with 0 on success and 2 on errors.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crdt_rga::RGA;
+use crdt_rga::server::capture::{Capture, Replayer, timeline};
+use futures_util::{SinkExt, StreamExt};
+use tokio::time::{Instant, sleep_until};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+const USAGE: &str = "usage: crdt-rga-replay [--from BASE.snapshot] [--out RESULT.snapshot] CAPTURE...\n       crdt-rga-replay --server URL [--speed FACTOR] CAPTURE...";
+
+/// How long connections stay open after their last frame, for the server to
+/// process it
+const LINGER: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Options {
+    from: Option<String>,
+    out: Option<String>,
+    server: Option<String>,
+    speed: Option<f64>,
+    captures: Vec<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| USAGE.to_string());
+        match arg.as_str() {
+            "--from" => options.from = Some(value()?),
+            "--out" => options.out = Some(value()?),
+            "--server" => options.server = Some(value()?),
+            "--speed" => {
+                let speed = value()?;
+                options.speed = match speed.parse() {
+                    Ok(speed) if speed > 0.0 => Some(speed),
+                    _ => return Err(format!("invalid speed: {speed}")),
+                };
+            }
+            flag if flag.starts_with("--") => return Err(USAGE.to_string()),
+            path => options.captures.push(path.to_string()),
+        }
+    }
+    let bare = options.from.is_some() || options.out.is_some();
+    let live = options.server.is_some() || options.speed.is_some();
+    if options.captures.is_empty() || (bare && live) {
+        return Err(USAGE.to_string());
+    }
+    Ok(options)
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Replay onto a bare RGA
+fn replay(captures: &[Capture], options: &Options) -> Result<(), String> {
+    let header = &captures[0].header;
+    if let Some(other) = captures
+        .iter()
+        .find(|capture| capture.header.document != header.document)
+    {
+        return Err(format!(
+            "sessions {} and {} edited different documents",
+            header.session_id, other.header.session_id
+        ));
+    }
+    let rga = match &options.from {
+        Some(path) => RGA::from_snapshot(header.replica_id, &read(path)?)
+            .map_err(|e| format!("{path}: {e}"))?,
+        None => RGA::new(header.replica_id),
+    };
+
+    let mut replayer = Replayer::new(rga);
+    let frames = timeline(captures);
+    for (capture, frame) in &frames {
+        if let Err(e) = replayer.apply(&capture.header, &frame.text) {
+            eprintln!(
+                "{} +{:.3}s: refused: {e}",
+                capture.header.session_id,
+                frame.offset.as_secs_f64()
+            );
+        }
+    }
+    eprintln!(
+        "Replayed {} frames of {} sessions on {}: {} edits, {} refused",
+        frames.len(),
+        captures.len(),
+        header.document,
+        replayer.edits,
+        replayer.rejected
+    );
+
+    match &options.out {
+        Some(path) => {
+            std::fs::write(path, replayer.rga().to_snapshot()).map_err(|e| format!("{path}: {e}"))
+        }
+        None => {
+            print!("{}", replayer.rga());
+            Ok(())
+        }
+    }
+}
+
+/// Re-drive a running server
+async fn drive(captures: Vec<Capture>, url: String, speed: f64) -> Result<(), String> {
+    let Some(first) = timeline(&captures)
+        .first()
+        .map(|(capture, frame)| capture.arrival(frame))
+    else {
+        return Ok(());
+    };
+    let start = Instant::now();
+
+    let sessions: Vec<_> = captures
+        .into_iter()
+        .map(|capture| {
+            let url = url.clone();
+            tokio::spawn(async move {
+                let (socket, _) = connect_async(url.as_str())
+                    .await
+                    .map_err(|e| format!("cannot connect to {url}: {e}"))?;
+                let (mut sink, mut stream) = socket.split();
+                // Responses are not checked, but must be read
+                let reader = tokio::spawn(async move { while stream.next().await.is_some() {} });
+                for frame in &capture.frames {
+                    let due = (capture.arrival(frame) - first) as f64 / speed;
+                    sleep_until(start + Duration::from_micros(due as u64)).await;
+                    sink.send(Message::Text(frame.text.clone()))
+                        .await
+                        .map_err(|e| format!("{}: {e}", capture.header.session_id))?;
+                }
+                tokio::time::sleep(LINGER).await;
+                let _ = sink.close().await;
+                reader.abort();
+                Ok::<_, String>(capture.frames.len())
+            })
+        })
+        .collect();
+
+    let mut sent = 0;
+    let count = sessions.len();
+    for session in sessions {
+        sent += session.await.map_err(|e| e.to_string())??;
+    }
+    eprintln!(
+        "Sent {sent} frames of {count} sessions in {:.1}s",
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    let captures = options
+        .captures
+        .iter()
+        .map(|path| Capture::parse(&read(path)?).map_err(|e| format!("{path}: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match &options.server {
+        Some(url) => tokio::runtime::Runtime::new()
+            .map_err(|e| e.to_string())?
+            .block_on(drive(captures, url.clone(), options.speed.unwrap_or(1.0))),
+        None => replay(&captures, &options),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("crdt-rga-replay: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
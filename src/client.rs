@@ -0,0 +1,506 @@
+//! Optimistic local echo for clients of a shared document.
+//!
+//! A [`Client`] owns the client's replica. Edits apply to it immediately, so
+//! the UI never waits for a round trip, and are tracked as [`PendingOp`]s
+//! until the server acknowledges them. [`Client::pending_ops`] is what a
+//! "saving…" indicator watches.
+//!
+//! The client does not own a connection. Operations travel as [`Node`]s, like
+//! the ops of the MQTT bridge: the application sends what
+//! [`Client::outgoing`] returns, feeds acknowledgements to [`Client::ack`] and
+//! peers' operations to [`Client::apply_remote`]. After a reconnect,
+//! [`Client::reconnect`] reconciles the pending operations with the server's
+//! state and returns those it never received.
+//!
+//! A client can also uphold session guarantees for its reads. With a
+//! [`SessionPolicy`] other than `Off`, [`Client::content`] checks that the
+//! replica reflects every edit made on this client (read-your-writes) and
+//! has not lost operations an earlier read showed (monotonic reads). Both
+//! hold as long as the replica is only merged into; they can break when
+//! [`Client::resync`] replaces it with a server's state that lags behind,
+//! such as a standby taking over after a failover.
+//!
+//! ```rust
+//! use crdt_rga::RGA;
+//! use crdt_rga::client::Client;
+//!
+//! let server = RGA::new(0);
+//! let mut client = Client::new(1);
+//! client.insert(0, "hi").unwrap();
+//! assert_eq!(client.replica().to_string(), "hi");
+//!
+//! for node in client.outgoing() {
+//!     server.apply_remote_op(node.clone());
+//!     client.ack(node.id);
+//! }
+//! assert!(client.pending_ops().is_empty());
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::{LamportTimestamp, Node, RGA, ReplicaId, UniqueId};
+
+/// What a pending operation does to its node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    /// The node was inserted
+    Insert,
+    /// The node was deleted
+    Delete,
+}
+
+/// A local operation the server has not acknowledged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingOp {
+    /// Whether the operation inserts or deletes its node
+    pub kind: OpKind,
+    /// The node as the operation left it, which is what is sent
+    pub node: Node,
+    /// Whether the operation was sent on the current connection
+    pub sent: bool,
+}
+
+impl PendingOp {
+    /// Whether `server` already holds the effect of this operation
+    fn applied_by(&self, server: &RGA) -> bool {
+        server
+            .get_node(self.node.id)
+            .is_some_and(|node| match self.kind {
+                OpKind::Insert => true,
+                OpKind::Delete => node.is_deleted,
+            })
+    }
+}
+
+/// How [`Client::content`] treats a read that breaks a session guarantee
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionPolicy {
+    /// Reads are not checked
+    #[default]
+    Off,
+    /// Stale reads return the content and are recorded, see
+    /// [`Client::take_warnings`]
+    Warn,
+    /// Stale reads fail until the replica catches up
+    Block,
+}
+
+/// A read that breaks a session guarantee
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StaleRead {
+    /// Edits made on this client that the replica does not reflect
+    MissingWrites(Vec<UniqueId>),
+    /// The replica holds fewer of `replica`'s operations than an earlier read
+    WentBack {
+        /// Replica whose operations were lost
+        replica: ReplicaId,
+        /// Newest counter of `replica` an earlier read showed
+        seen: u64,
+        /// Newest counter of `replica` the replica holds now
+        now: u64,
+    },
+}
+
+impl fmt::Display for StaleRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaleRead::MissingWrites(ids) => {
+                write!(f, "{} local edit(s) are not reflected yet", ids.len())
+            }
+            StaleRead::WentBack { replica, seen, now } => write!(
+                f,
+                "replica {replica} went back from {seen} to {now} since the last read"
+            ),
+        }
+    }
+}
+
+/// A replica that echoes local edits before the server confirms them.
+pub struct Client {
+    rga: RGA,
+    /// Unacknowledged operations, oldest first
+    pending: Vec<PendingOp>,
+    policy: SessionPolicy,
+    /// Every node this session inserted or deleted, with the last operation
+    written: BTreeMap<UniqueId, OpKind>,
+    /// Newest counter per replica shown by a read so far
+    seen: BTreeMap<ReplicaId, u64>,
+    /// Stale reads let through under [`SessionPolicy::Warn`]
+    warnings: Vec<StaleRead>,
+}
+
+impl Client {
+    /// Creates a client with an empty replica
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self::with_replica(RGA::new(replica_id))
+    }
+
+    /// Creates a client around an existing replica, such as one restored from
+    /// a snapshot
+    pub fn with_replica(rga: RGA) -> Self {
+        Client {
+            rga,
+            pending: Vec::new(),
+            policy: SessionPolicy::Off,
+            written: BTreeMap::new(),
+            seen: BTreeMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Sets how reads that break a session guarantee are treated.
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The policy for reads that break a session guarantee
+    pub fn session_policy(&self) -> SessionPolicy {
+        self.policy
+    }
+
+    /// The local replica, including unacknowledged edits
+    pub fn replica(&self) -> &RGA {
+        &self.rga
+    }
+
+    /// Inserts `text` at visible `position` and tracks the insertions.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - Visible index to insert at, clamped to the length
+    /// * `text` - The text to insert
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the inserted nodes
+    pub fn insert(&mut self, position: usize, text: &str) -> Result<Vec<UniqueId>, &'static str> {
+        let after_id = self.rga.read_txn().insertion_point(position);
+        let ids = self.rga.insert_str_after(after_id, text)?;
+        for &id in &ids {
+            self.track(OpKind::Insert, id);
+        }
+        Ok(ids)
+    }
+
+    /// Deletes the character at visible `position` and tracks the deletion.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - Visible index of the character
+    ///
+    /// # Returns
+    ///
+    /// The ID of the deleted node
+    pub fn delete(&mut self, position: usize) -> Result<UniqueId, &'static str> {
+        let id = self
+            .rga
+            .read_txn()
+            .visible_nodes()
+            .get(position)
+            .map(|node| node.id)
+            .ok_or("Position out of range")?;
+        self.rga.delete(id)?;
+        self.track(OpKind::Delete, id);
+        Ok(id)
+    }
+
+    fn track(&mut self, kind: OpKind, id: UniqueId) {
+        self.written.insert(id, kind);
+        if let Some(node) = self.rga.get_node(id) {
+            self.pending.push(PendingOp {
+                kind,
+                node,
+                sent: false,
+            });
+        }
+    }
+
+    /// Applies an operation received from the server or a peer
+    pub fn apply_remote(&self, node: Node) {
+        self.rga.apply_remote_op(node);
+    }
+
+    /// The operations not sent on the current connection yet, oldest first.
+    /// They are marked as sent and stay pending until acknowledged.
+    pub fn outgoing(&mut self) -> Vec<Node> {
+        self.pending
+            .iter_mut()
+            .filter(|op| !op.sent)
+            .map(|op| {
+                op.sent = true;
+                op.node.clone()
+            })
+            .collect()
+    }
+
+    /// Records the server's acknowledgement of the oldest pending operation
+    /// on node `id`.
+    ///
+    /// # Returns
+    ///
+    /// Whether an operation on `id` was pending
+    pub fn ack(&mut self, id: UniqueId) -> bool {
+        match self.pending.iter().position(|op| op.node.id == id) {
+            Some(index) => {
+                self.pending.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unacknowledged operations, oldest first
+    pub fn pending_ops(&self) -> &[PendingOp] {
+        &self.pending
+    }
+
+    /// Reconciles the client with the server after a new connection.
+    ///
+    /// Operations the server received before the old connection dropped are
+    /// acknowledged, whether or not their acknowledgement arrived, and the
+    /// server's nodes are merged into the replica.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server's state, as received on the new connection
+    ///
+    /// # Returns
+    ///
+    /// The operations to send again, oldest first
+    pub fn reconnect(&mut self, server: &RGA) -> Vec<Node> {
+        self.pending.retain(|op| !op.applied_by(server));
+        for op in &mut self.pending {
+            op.sent = false;
+        }
+        for node in server.all_nodes() {
+            if !node.is_sentinel() {
+                self.rga.apply_remote_op(node);
+            }
+        }
+        self.outgoing()
+    }
+
+    /// Replaces the replica with the server's state, for when the local one
+    /// cannot be merged with it, such as after a failover to another server.
+    ///
+    /// Pending operations the server lacks are returned to be sent, and
+    /// applied again on top of its state unless they build on text it lost.
+    /// Acknowledged edits the server lost are not resent: they, and the
+    /// pending operations left out, show up as [`StaleRead::MissingWrites`]
+    /// until they arrive.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server's state, as received on the new connection
+    ///
+    /// # Returns
+    ///
+    /// The operations to send again, oldest first
+    pub fn resync(&mut self, server: &RGA) -> Vec<Node> {
+        let rga = server.fork(self.rga.replica_id());
+        // Never reissue the IDs of edits the server lost
+        rga.update_clock(LamportTimestamp {
+            counter: self.rga.current_clock(),
+            replica_id: self.rga.replica_id(),
+            sequence: 0,
+        });
+        self.pending.retain(|op| !op.applied_by(server));
+        for op in &mut self.pending {
+            op.sent = false;
+            if op.node.origin.is_none_or(|origin| rga.holds(origin)) {
+                rga.apply_remote_op(op.node.clone());
+            }
+        }
+        self.rga = rga;
+        self.outgoing()
+    }
+
+    /// Reads the document's text, checking the session guarantees.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The visible text, stale or not unless the policy is
+    ///   [`SessionPolicy::Block`]
+    /// * `Err(StaleRead)` - The guarantee a blocked read broke; read again
+    ///   once more operations were applied
+    pub fn content(&mut self) -> Result<String, StaleRead> {
+        if self.policy != SessionPolicy::Off
+            && let Some(stale) = self.check_session()
+        {
+            if self.policy == SessionPolicy::Block {
+                return Err(stale);
+            }
+            self.warnings.push(stale);
+        }
+        Ok(self.rga.to_string())
+    }
+
+    /// The first guarantee a read now would break. A read that breaks none
+    /// becomes the floor for monotonic reads.
+    fn check_session(&mut self) -> Option<StaleRead> {
+        let missing: Vec<UniqueId> = self
+            .written
+            .iter()
+            .filter(|&(&id, &kind)| {
+                self.rga
+                    .get_node(id)
+                    .is_none_or(|node| kind == OpKind::Delete && !node.is_deleted)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        if !missing.is_empty() {
+            return Some(StaleRead::MissingWrites(missing));
+        }
+        let version = self.rga.version_vector();
+        for (&replica, &seen) in &self.seen {
+            let now = version.get(&replica).copied().unwrap_or_default();
+            if now < seen {
+                return Some(StaleRead::WentBack { replica, seen, now });
+            }
+        }
+        self.seen = version;
+        None
+    }
+
+    /// Takes the stale reads let through under [`SessionPolicy::Warn`],
+    /// oldest first
+    pub fn take_warnings(&mut self) -> Vec<StaleRead> {
+        core::mem::take(&mut self.warnings)
+    }
+
+    /// Starts a new session: edits made and versions read so far are no
+    /// longer checked
+    pub fn reset_session(&mut self) {
+        self.written.clear();
+        self.seen.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edits_stay_pending_until_acked() {
+        let server = RGA::new(0);
+        let mut client = Client::new(1);
+        let ids = client.insert(0, "ab").unwrap();
+        let deleted = client.delete(0).unwrap();
+        assert_eq!(deleted, ids[0]);
+        assert_eq!(client.replica().to_string(), "b");
+
+        let kinds: Vec<_> = client.pending_ops().iter().map(|op| op.kind).collect();
+        assert_eq!(kinds, [OpKind::Insert, OpKind::Insert, OpKind::Delete]);
+
+        let sent = client.outgoing();
+        assert_eq!(sent.len(), 3);
+        assert!(client.outgoing().is_empty());
+        for node in sent {
+            server.apply_remote_op(node);
+        }
+        assert_eq!(server.to_string(), "b");
+
+        assert!(client.ack(ids[0]));
+        assert_eq!(client.pending_ops()[1].kind, OpKind::Delete);
+        assert!(client.ack(ids[1]));
+        assert!(client.ack(ids[0]));
+        assert!(!client.ack(ids[0]));
+        assert!(client.pending_ops().is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_resends_what_the_server_missed() {
+        let server = RGA::new(0);
+        let mut client = Client::new(1);
+        client.insert(0, "a").unwrap();
+
+        // Received by the server, but the acknowledgement was lost
+        for node in client.outgoing() {
+            server.apply_remote_op(node);
+        }
+        // Sent on the dropped connection and lost with it
+        client.insert(1, "b").unwrap();
+        let lost = client.outgoing();
+        // Made while disconnected
+        client.insert(2, "c").unwrap();
+        // A peer's edit the client missed
+        server
+            .insert_str_after(server.sentinel_start_id(), "x")
+            .unwrap();
+
+        let resent = client.reconnect(&server);
+        assert_eq!(resent[0], lost[0]);
+        assert_eq!(resent.len(), 2);
+        assert_eq!(client.pending_ops().len(), 2);
+        assert!(client.pending_ops().iter().all(|op| op.sent));
+        for node in resent {
+            server.apply_remote_op(node);
+        }
+        assert_eq!(client.replica().to_string(), server.to_string());
+    }
+
+    #[test]
+    fn test_reads_wait_for_writes_a_failover_lost() {
+        let primary = RGA::new(0);
+        let standby = RGA::new(0);
+        let mut client = Client::new(1).with_session_policy(SessionPolicy::Block);
+        primary
+            .insert_str_after(primary.sentinel_start_id(), "x")
+            .unwrap();
+        client.reconnect(&primary);
+        assert_eq!(client.content().unwrap(), "x");
+
+        // The standby has not seen the text read already
+        assert!(client.resync(&standby).is_empty());
+        let stale = client.content().unwrap_err();
+        assert_eq!(
+            stale,
+            StaleRead::WentBack {
+                replica: 0,
+                seen: 1,
+                now: 0
+            }
+        );
+        assert!(stale.to_string().starts_with("replica 0 went back"));
+        client.reconnect(&primary);
+        assert_eq!(client.content().unwrap(), "x");
+
+        // Acknowledged by the primary, which fails before the standby sees it
+        let lost = client.insert(1, "a").unwrap();
+        for node in client.outgoing() {
+            client.ack(node.id);
+            primary.apply_remote_op(node);
+        }
+        // Still pending when the client fails over
+        client.insert(0, "b").unwrap();
+        assert_eq!(client.content().unwrap(), "bxa");
+
+        assert_eq!(client.resync(&standby).len(), 1);
+        assert_eq!(
+            client.content(),
+            Err(StaleRead::MissingWrites(lost.clone()))
+        );
+        // New edits do not reuse the lost IDs
+        let id = client.insert(0, "c").unwrap()[0];
+        assert!(id > lost[0]);
+
+        // Warnings let the read through and record it
+        let mut client = client.with_session_policy(SessionPolicy::Warn);
+        assert_eq!(client.content().unwrap(), "cb");
+        assert_eq!(
+            client.take_warnings(),
+            [StaleRead::MissingWrites(lost.clone())]
+        );
+        for node in primary.all_nodes() {
+            if !node.is_sentinel() {
+                client.apply_remote(node);
+            }
+        }
+        assert_eq!(client.content().unwrap(), "cbxa");
+        assert!(client.take_warnings().is_empty());
+    }
+}
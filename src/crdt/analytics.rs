@@ -0,0 +1,312 @@
+//! Concurrency and conflict analytics for the RGA CRDT.
+//!
+//! When several replicas insert after the same origin node without seeing each
+//! other's edits, their characters compete for the same spot and end up
+//! interleaved according to the tie-breaking order. This module reports where
+//! that happened so conflict hot-spots can be measured, and exposes the
+//! insertion tree the origins form for visualization and research tooling.
+//!
+//! For editors, [`RGA::merge_regions`] turns the conflicts into the spans of
+//! text they produced, with what each replica wrote there, so a frontend can
+//! mark merged text the way Git viewers mark merged hunks.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Range, RangeInclusive};
+
+use crate::crdt::anchor::Anchor;
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// A spot where insertions from different replicas competed for the same origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterleavingConflict {
+    /// The node every competing insertion was made after
+    pub origin: UniqueId,
+    /// The visible index right after the origin node
+    pub index: usize,
+    /// The distinct replicas that inserted at this origin, in ascending order
+    pub replicas: Vec<ReplicaId>,
+    /// The competing nodes, in ID order
+    pub nodes: Vec<UniqueId>,
+}
+
+/// What one replica wrote within a [`MergeRegion`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contribution {
+    /// The replica that inserted the text
+    pub replica: ReplicaId,
+    /// Its nodes in the region, in document order
+    pub nodes: Vec<UniqueId>,
+    /// The text as it wrote it, before being merged with the other
+    /// replicas' and including characters deleted since
+    pub text: String,
+}
+
+/// A span of text that concurrent insertions from several replicas were
+/// merged into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeRegion {
+    /// The node the merged insertions were made after
+    pub origin: UniqueId,
+    /// The visible characters the region covers
+    pub range: Range<usize>,
+    /// What each replica wrote in the region, in ascending replica order
+    pub contributions: Vec<Contribution>,
+}
+
+impl RGA {
+    /// Reports origins where insertions from more than one replica interleaved.
+    ///
+    /// Only nodes whose Lamport counter falls inside `window` are considered,
+    /// which restricts the report to a span of logical time. Deleted nodes are
+    /// included, since they took part in the conflict when they were inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - Inclusive range of Lamport counters to analyze
+    ///
+    /// # Returns
+    ///
+    /// The conflicts, ordered by their position in the document
+    pub fn interleaving_conflicts(&self, window: RangeInclusive<u64>) -> Vec<InterleavingConflict> {
+        let mut by_origin: BTreeMap<UniqueId, Vec<UniqueId>> = BTreeMap::new();
+        self.for_each_node(|node| {
+            if let Some(origin) = node.origin
+                && window.contains(&node.id.counter())
+            {
+                by_origin.entry(origin).or_default().push(node.id);
+            }
+        });
+
+        let mut conflicts: Vec<InterleavingConflict> = by_origin
+            .into_iter()
+            .filter_map(|(origin, nodes)| {
+                let mut replicas: Vec<ReplicaId> = nodes.iter().map(|id| id.replica_id()).collect();
+                replicas.sort_unstable();
+                replicas.dedup();
+                if replicas.len() < 2 {
+                    return None;
+                }
+
+                let index = self.resolve_anchor(&Anchor::after(origin)).ok()?;
+                Some(InterleavingConflict {
+                    origin,
+                    index,
+                    replicas,
+                    nodes,
+                })
+            })
+            .collect();
+
+        conflicts.sort_by_key(|conflict| conflict.index);
+        conflicts
+    }
+
+    /// The regions of text merged from several replicas' insertions.
+    ///
+    /// A region starts after the origin of an [`InterleavingConflict`] and
+    /// spans the text inserted after it since the start of `window`, directly
+    /// or after other text in the region. Text typed into the region after
+    /// the window is covered but not attributed, and a conflict nested inside
+    /// a region is part of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - Inclusive range of Lamport counters to analyze
+    ///
+    /// # Returns
+    ///
+    /// The regions, ordered by their position in the document
+    pub fn merge_regions(&self, window: RangeInclusive<u64>) -> Vec<MergeRegion> {
+        let conflicts = self.interleaving_conflicts(window.clone());
+        if conflicts.is_empty() {
+            return Vec::new();
+        }
+        let mut nodes: Vec<Node> = Vec::new();
+        self.for_each_node(|node| nodes.push(node.clone()));
+
+        let mut regions = Vec::new();
+        // Index in `nodes` past the previous region
+        let mut covered = 0;
+        for conflict in conflicts {
+            let Some(start) = nodes.iter().position(|node| node.id == conflict.origin) else {
+                continue;
+            };
+            if start < covered {
+                continue;
+            }
+            let mut inside = BTreeSet::from([conflict.origin]);
+            let mut end = start + 1;
+            while let Some(node) = nodes.get(end)
+                && !node.is_sentinel()
+                && node.id.counter() >= *window.start()
+                && node.origin.is_some_and(|origin| inside.contains(&origin))
+            {
+                inside.insert(node.id);
+                end += 1;
+            }
+            covered = end;
+
+            let visible = |nodes: &[Node]| nodes.iter().filter(|node| node.is_visible()).count();
+            let first = visible(&nodes[..=start]);
+            let mut contributions: BTreeMap<ReplicaId, Contribution> = BTreeMap::new();
+            for node in &nodes[start + 1..end] {
+                if !window.contains(&node.id.counter()) {
+                    continue;
+                }
+                let replica = node.id.replica_id();
+                let contribution = contributions.entry(replica).or_insert(Contribution {
+                    replica,
+                    nodes: Vec::new(),
+                    text: String::new(),
+                });
+                contribution.nodes.push(node.id);
+                contribution.text.push(node.character);
+            }
+            regions.push(MergeRegion {
+                origin: conflict.origin,
+                range: first..first + visible(&nodes[start + 1..end]),
+                contributions: contributions.into_values().collect(),
+            });
+        }
+        regions
+    }
+
+    /// The insertion tree: every node inserted after each origin.
+    ///
+    /// Each node with a known origin is a child of it, so the start sentinel
+    /// is the root of every tree built from local edits. Tombstones are
+    /// included.
+    ///
+    /// # Returns
+    ///
+    /// The children of every origin, in ID order
+    pub fn insertion_tree(&self) -> BTreeMap<UniqueId, Vec<UniqueId>> {
+        let mut tree: BTreeMap<UniqueId, Vec<UniqueId>> = BTreeMap::new();
+        self.for_each_node_by_id(|node| {
+            if let Some(origin) = node.origin {
+                tree.entry(origin).or_default().push(node.id);
+            }
+        });
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync(from: &RGA, to: &RGA) {
+        for node in from.all_nodes() {
+            if !node.is_sentinel() {
+                to.apply_remote_op(node);
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_inserts_at_same_origin_are_reported() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let start_id = rga1.sentinel_start_id();
+
+        rga1.insert_after(start_id, 'A').unwrap();
+        rga2.insert_after(start_id, 'B').unwrap();
+        sync(&rga1, &rga2);
+        sync(&rga2, &rga1);
+
+        let conflicts = rga1.interleaving_conflicts(0..=u64::MAX);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].origin, start_id);
+        assert_eq!(conflicts[0].index, 0);
+        assert_eq!(conflicts[0].replicas, vec![1, 2]);
+        assert_eq!(conflicts[0].nodes.len(), 2);
+        assert_eq!(conflicts, rga2.interleaving_conflicts(0..=u64::MAX));
+    }
+
+    #[test]
+    fn test_merge_regions_show_each_replicas_text() {
+        let rga1 = RGA::new(1);
+        let ids = rga1
+            .insert_str_after(rga1.sentinel_start_id(), "Hi.")
+            .unwrap();
+        let rga2 = rga1.fork(2);
+        let rga3 = rga1.fork(3);
+        rga2.insert_str_after(ids[1], " Ann").unwrap();
+        let bob = rga3.insert_str_after(ids[1], " Bobb").unwrap();
+        rga3.delete(bob[4]).unwrap();
+        sync(&rga2, &rga3);
+        sync(&rga3, &rga2);
+        // Typed into the middle of the second replica's text after the merge
+        let an = rga2.visible_nodes()[8].id;
+        rga2.insert_after(an, 'd').unwrap();
+        sync(&rga2, &rga1);
+        sync(&rga3, &rga1);
+        assert_eq!(rga1.to_string(), "Hi Bob Andn.");
+
+        let regions = rga1.merge_regions(2..=2);
+        assert_eq!(regions.len(), 1);
+        let region = &regions[0];
+        assert_eq!(region.origin, ids[1]);
+        assert_eq!(region.range, 2..11);
+        let texts: Vec<_> = region
+            .contributions
+            .iter()
+            .map(|c| (c.replica, c.text.as_str()))
+            .collect();
+        assert_eq!(texts, [(2, " Ann"), (3, " Bobb")]);
+        assert_eq!(region.contributions[1].nodes, bob);
+
+        // The later edit is attributed once the window reaches it
+        let regions = rga1.merge_regions(2..=u64::MAX);
+        assert_eq!(regions[0].range, 2..11);
+        assert_eq!(regions[0].contributions[0].text, " Andn");
+        assert!(rga1.merge_regions(0..=1).is_empty());
+    }
+
+    #[test]
+    fn test_insertion_tree_and_right_origins() {
+        let rga = RGA::new(1);
+        let start_id = rga.sentinel_start_id();
+        let ids = rga.insert_str_after(start_id, "ac").unwrap();
+        let b_id = rga.insert_after(ids[0], 'b').unwrap();
+
+        let tree = rga.insertion_tree();
+        assert_eq!(tree[&start_id], vec![ids[0]]);
+        assert_eq!(tree[&ids[0]], vec![ids[1], b_id]);
+
+        // The run was typed before the end sentinel, `b` between `a` and `c`
+        let end_id = rga.sentinel_end_id();
+        assert_eq!(rga.get_node(ids[1]).unwrap().right_origin(), Some(end_id));
+        assert_eq!(rga.get_node(b_id).unwrap().right_origin(), Some(ids[1]));
+    }
+
+    #[test]
+    fn test_single_replica_typing_is_not_a_conflict() {
+        let rga = RGA::new(1);
+        let mut last_id = rga.sentinel_start_id();
+        for ch in "hello".chars() {
+            last_id = rga.insert_after(last_id, ch).unwrap();
+        }
+
+        assert!(rga.interleaving_conflicts(0..=u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_window_limits_the_report() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let start_id = rga1.sentinel_start_id();
+
+        rga1.insert_after(start_id, 'A').unwrap();
+        rga2.insert_after(start_id, 'B').unwrap();
+        sync(&rga1, &rga2);
+        sync(&rga2, &rga1);
+
+        assert_eq!(rga1.interleaving_conflicts(1..=1).len(), 1);
+        assert!(rga1.interleaving_conflicts(2..=10).is_empty());
+    }
+}
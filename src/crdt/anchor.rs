@@ -0,0 +1,192 @@
+//! Position anchors with gravity for the RGA CRDT.
+//!
+//! An anchor pins a position in the document to a node rather than to an index,
+//! so it keeps pointing at the same logical spot while other replicas edit the
+//! text around it. The bias decides which side of the anchor receives text that
+//! is inserted exactly at the anchored spot.
+
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// Which side of its node an anchor sticks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bias {
+    /// The anchor sits immediately before its node and travels with it.
+    /// Text inserted at the anchored spot lands before the anchor.
+    Before,
+    /// The anchor sits immediately after its node.
+    /// Text inserted at the anchored spot lands after the anchor.
+    After,
+}
+
+/// A stable position in the document, expressed relative to a node.
+///
+/// Anchors survive concurrent edits: they always resolve to the index next to
+/// the node they reference, even when that node has since been deleted.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::{Anchor, Bias, RGA};
+///
+/// let rga = RGA::new(1);
+/// let a_id = rga.insert_after(rga.sentinel_start_id(), 'A').unwrap();
+///
+/// let caret = Anchor::new(a_id, Bias::After);
+/// assert_eq!(rga.resolve_anchor(&caret).unwrap(), 1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Anchor {
+    /// The node this anchor is attached to
+    pub id: UniqueId,
+    /// Which side of the node the anchor sticks to
+    pub bias: Bias,
+}
+
+impl Anchor {
+    /// Creates a new anchor attached to the given node.
+    pub fn new(id: UniqueId, bias: Bias) -> Self {
+        Anchor { id, bias }
+    }
+
+    /// Creates an anchor sitting immediately before the given node.
+    pub fn before(id: UniqueId) -> Self {
+        Anchor::new(id, Bias::Before)
+    }
+
+    /// Creates an anchor sitting immediately after the given node.
+    pub fn after(id: UniqueId) -> Self {
+        Anchor::new(id, Bias::After)
+    }
+}
+
+impl RGA {
+    /// Creates an anchor for the gap at the given visible index.
+    ///
+    /// With [`Bias::Before`] the anchor attaches to the character at `index`
+    /// (or the end sentinel when `index` equals the length); with
+    /// [`Bias::After`] it attaches to the character at `index - 1` (or the
+    /// start sentinel when `index` is 0).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Anchor)` - The anchor for the requested gap
+    /// * `Err(&str)` - Error message if `index` is past the end of the document
+    pub fn anchor_at(&self, index: usize, bias: Bias) -> Result<Anchor, &'static str> {
+        let visible = self.visible_nodes();
+        if index > visible.len() {
+            return Err("Anchor index out of range");
+        }
+
+        let id = match bias {
+            Bias::Before => visible
+                .get(index)
+                .map_or_else(|| self.sentinel_end_id(), |node| node.id),
+            Bias::After if index == 0 => self.sentinel_start_id(),
+            Bias::After => visible[index - 1].id,
+        };
+
+        Ok(Anchor::new(id, bias))
+    }
+
+    /// Resolves an anchor to its current visible index.
+    ///
+    /// Deleted nodes still resolve to the spot they used to occupy, so an anchor
+    /// whose node was removed collapses onto the surrounding text.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The index of the gap the anchor currently points at
+    /// * `Err(&str)` - Error message if the anchored node is unknown
+    pub fn resolve_anchor(&self, anchor: &Anchor) -> Result<usize, &'static str> {
+        let mut preceding = 0;
+        self.nodes
+            .find_map(|node| {
+                if node.id == anchor.id {
+                    let past_node = anchor.bias == Bias::After && node.is_visible();
+                    return Some(preceding + usize::from(past_node));
+                }
+                if node.is_visible() {
+                    preceding += 1;
+                }
+                None
+            })
+            .ok_or("Anchor node not found")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchor_resolution() {
+        let rga = RGA::new(1);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'A').unwrap();
+        let b_id = rga.insert_after(a_id, 'B').unwrap();
+
+        assert_eq!(rga.resolve_anchor(&Anchor::before(a_id)).unwrap(), 0);
+        assert_eq!(rga.resolve_anchor(&Anchor::after(a_id)).unwrap(), 1);
+        assert_eq!(rga.resolve_anchor(&Anchor::before(b_id)).unwrap(), 1);
+        assert_eq!(rga.resolve_anchor(&Anchor::after(b_id)).unwrap(), 2);
+
+        let start = Anchor::after(rga.sentinel_start_id());
+        let end = Anchor::before(rga.sentinel_end_id());
+        assert_eq!(rga.resolve_anchor(&start).unwrap(), 0);
+        assert_eq!(rga.resolve_anchor(&end).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_anchor_at_round_trip() {
+        let rga = RGA::new(1);
+        let mut last_id = rga.sentinel_start_id();
+        for ch in "abc".chars() {
+            last_id = rga.insert_after(last_id, ch).unwrap();
+        }
+
+        for index in 0..=3 {
+            for bias in [Bias::Before, Bias::After] {
+                let anchor = rga.anchor_at(index, bias).unwrap();
+                assert_eq!(rga.resolve_anchor(&anchor).unwrap(), index);
+            }
+        }
+        assert!(rga.anchor_at(4, Bias::Before).is_err());
+    }
+
+    #[test]
+    fn test_bias_decides_side_of_concurrent_insert() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let a_id = rga1.insert_after(rga1.sentinel_start_id(), 'A').unwrap();
+        let node_a = rga1.all_nodes().into_iter().find(|n| n.id == a_id).unwrap();
+        rga2.apply_remote_op(node_a);
+
+        // Both anchors point at the gap right after 'A'
+        let sticks_left = rga1.anchor_at(1, Bias::After).unwrap();
+        let sticks_right = rga1.anchor_at(1, Bias::Before).unwrap();
+
+        // Replica 2 concurrently types at that gap
+        let b_id = rga2.insert_after(a_id, 'B').unwrap();
+        let node_b = rga2.all_nodes().into_iter().find(|n| n.id == b_id).unwrap();
+        rga1.apply_remote_op(node_b);
+
+        assert_eq!(rga1.to_string(), "AB");
+        assert_eq!(rga1.resolve_anchor(&sticks_left).unwrap(), 1);
+        assert_eq!(rga1.resolve_anchor(&sticks_right).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_anchor_on_deleted_node() {
+        let rga = RGA::new(1);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'A').unwrap();
+        let b_id = rga.insert_after(a_id, 'B').unwrap();
+        rga.insert_after(b_id, 'C').unwrap();
+
+        rga.delete(b_id).unwrap();
+        assert_eq!(rga.resolve_anchor(&Anchor::before(b_id)).unwrap(), 1);
+        assert_eq!(rga.resolve_anchor(&Anchor::after(b_id)).unwrap(), 1);
+
+        let unknown = Anchor::after(UniqueId::new(999, 9));
+        assert!(rga.resolve_anchor(&unknown).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Detection of suspicious timestamps on remote operations.
+//!
+//! Replicas trust each other's timestamps. A peer with a buggy clock or a
+//! malicious one can stamp an operation far in the future, dragging every
+//! replica's Lamport clock along with it for good, or reuse an ID for a
+//! different character. A [`ClockMonitor`] inspects remote operations before
+//! they are applied and reports such [`ClockAnomaly`] events with the
+//! offending replica, so they can be logged and the peer investigated early.
+//!
+//! The monitor only reports: whether to apply, drop or disconnect is left to
+//! the caller.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// How far ahead of the local clock a remote counter may be before it is
+/// reported, unless configured otherwise
+pub const DEFAULT_MAX_CLOCK_SKEW: u64 = 1 << 20;
+
+/// A suspicious remote operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockAnomaly {
+    /// The operation's counter is further ahead of the local clock than the
+    /// configured skew allows
+    CounterAhead {
+        /// The offending operation
+        id: UniqueId,
+        /// The local clock when it arrived
+        local_counter: u64,
+    },
+    /// The operation is older than one already received from the same
+    /// replica. Replicas generate increasing IDs, so on a transport that
+    /// delivers each replica's operations in order this means the peer's
+    /// clock went backwards.
+    OutOfOrder {
+        /// The offending operation
+        id: UniqueId,
+        /// The newest ID previously received from the replica
+        latest: UniqueId,
+    },
+    /// The operation reuses the ID of a node holding a different character
+    ConflictingId {
+        /// The reused ID
+        id: UniqueId,
+        /// The character held locally
+        local: char,
+        /// The character in the operation
+        remote: char,
+    },
+}
+
+impl ClockAnomaly {
+    /// The replica that sent the operation
+    pub fn replica(&self) -> ReplicaId {
+        match self {
+            ClockAnomaly::CounterAhead { id, .. }
+            | ClockAnomaly::OutOfOrder { id, .. }
+            | ClockAnomaly::ConflictingId { id, .. } => id.replica_id(),
+        }
+    }
+}
+
+impl fmt::Display for ClockAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockAnomaly::CounterAhead { id, local_counter } => write!(
+                f,
+                "replica {} sent counter {}, {} ahead of the local clock",
+                id.replica_id(),
+                id.counter(),
+                id.counter() - local_counter
+            ),
+            ClockAnomaly::OutOfOrder { id, latest } => write!(
+                f,
+                "replica {} sent {:?} after {:?}",
+                id.replica_id(),
+                id,
+                latest
+            ),
+            ClockAnomaly::ConflictingId { id, local, remote } => write!(
+                f,
+                "replica {} reused {:?} for {:?}, which holds {:?}",
+                id.replica_id(),
+                id,
+                remote,
+                local
+            ),
+        }
+    }
+}
+
+/// Inspects remote operations for clock anomalies.
+#[derive(Debug)]
+pub struct ClockMonitor {
+    /// Largest distance a remote counter may be ahead of the local clock
+    max_skew: u64,
+    /// Whether each replica's operations are expected in the order they
+    /// were made
+    ordered_delivery: bool,
+    /// Newest ID received from each replica
+    latest: BTreeMap<ReplicaId, UniqueId>,
+    /// Anomalies reported per replica
+    reported: BTreeMap<ReplicaId, u64>,
+}
+
+impl Default for ClockMonitor {
+    fn default() -> Self {
+        ClockMonitor {
+            max_skew: DEFAULT_MAX_CLOCK_SKEW,
+            ordered_delivery: true,
+            latest: BTreeMap::new(),
+            reported: BTreeMap::new(),
+        }
+    }
+}
+
+impl ClockMonitor {
+    /// Creates a monitor allowing [`DEFAULT_MAX_CLOCK_SKEW`] that expects
+    /// ordered delivery
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how far ahead of the local clock a remote counter may be
+    pub fn set_max_skew(&mut self, max_skew: u64) {
+        self.max_skew = max_skew;
+    }
+
+    /// Declares whether the transport delivers each replica's operations in
+    /// the order they were made. Without that guarantee, out-of-order
+    /// operations are expected and not reported.
+    pub fn set_ordered_delivery(&mut self, ordered: bool) {
+        self.ordered_delivery = ordered;
+    }
+
+    /// Checks a remote operation before it is applied to `rga`.
+    ///
+    /// Copies of nodes `rga` already holds, such as deletions and
+    /// redeliveries, are only checked for a conflicting character, and only
+    /// insertions are checked for order.
+    ///
+    /// # Arguments
+    ///
+    /// * `rga` - The replica the operation is about to be applied to
+    /// * `node` - The remote operation
+    ///
+    /// # Returns
+    ///
+    /// The anomalies found, empty for a well-behaved operation
+    pub fn inspect(&mut self, rga: &RGA, node: &Node) -> Vec<ClockAnomaly> {
+        let mut anomalies = Vec::new();
+        let id = node.id;
+        if node.is_sentinel() {
+            return anomalies;
+        }
+
+        if let Some(local) = rga.get_node(id) {
+            if local.character != node.character {
+                anomalies.push(ClockAnomaly::ConflictingId {
+                    id,
+                    local: local.character,
+                    remote: node.character,
+                });
+            }
+        } else {
+            let local_counter = rga.current_clock();
+            if id.counter() > local_counter.saturating_add(self.max_skew) {
+                anomalies.push(ClockAnomaly::CounterAhead { id, local_counter });
+            }
+            // Deletions travel with the deleting replica's operations, so only
+            // insertions follow their author's order
+            match self.latest.get(&id.replica_id()) {
+                _ if node.is_deleted => {}
+                Some(&latest) if id < latest => {
+                    if self.ordered_delivery {
+                        anomalies.push(ClockAnomaly::OutOfOrder { id, latest });
+                    }
+                }
+                _ => {
+                    self.latest.insert(id.replica_id(), id);
+                }
+            }
+        }
+
+        if !anomalies.is_empty() {
+            *self.reported.entry(id.replica_id()).or_default() += anomalies.len() as u64;
+        }
+        anomalies
+    }
+
+    /// Number of anomalies reported for each replica so far
+    pub fn reported(&self) -> &BTreeMap<ReplicaId, u64> {
+        &self.reported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_well_behaved_peer_is_not_reported() {
+        let peer = RGA::new(2);
+        let ids = peer
+            .insert_str_after(peer.sentinel_start_id(), "abc")
+            .unwrap();
+        peer.delete(ids[1]).unwrap();
+
+        let rga = RGA::new(1);
+        let mut monitor = ClockMonitor::new();
+        for node in peer.all_nodes() {
+            assert!(monitor.inspect(&rga, &node).is_empty());
+            rga.apply_remote_op(node);
+        }
+        assert!(
+            monitor
+                .inspect(&rga, &peer.get_node(ids[1]).unwrap())
+                .is_empty()
+        );
+        assert!(monitor.reported().is_empty());
+    }
+
+    #[test]
+    fn test_anomalies_name_the_replica() {
+        let rga = RGA::new(1);
+        let mut monitor = ClockMonitor::new();
+        monitor.set_max_skew(100);
+
+        let ahead = Node::new(UniqueId::new(500, 3), 'x');
+        assert_eq!(
+            monitor.inspect(&rga, &ahead),
+            [ClockAnomaly::CounterAhead {
+                id: ahead.id,
+                local_counter: 0
+            }]
+        );
+        rga.apply_remote_op(ahead.clone());
+
+        let behind = Node::new(UniqueId::new(7, 3), 'y');
+        let anomalies = monitor.inspect(&rga, &behind);
+        assert_eq!(
+            anomalies,
+            [ClockAnomaly::OutOfOrder {
+                id: behind.id,
+                latest: ahead.id
+            }]
+        );
+        assert_eq!(anomalies[0].replica(), 3);
+
+        let forged = Node::new(ahead.id, 'z');
+        assert!(matches!(
+            monitor.inspect(&rga, &forged)[..],
+            [ClockAnomaly::ConflictingId {
+                local: 'x',
+                remote: 'z',
+                ..
+            }]
+        ));
+        assert_eq!(monitor.reported()[&3], 3);
+
+        monitor.set_ordered_delivery(false);
+        let behind = Node::new(UniqueId::new(8, 3), 'y');
+        assert!(monitor.inspect(&rga, &behind).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! A tamper-evident record of the batches appended to an operation log.
+//!
+//! With auditing enabled ([`SegmentedLog::enable_audit`]), every batch
+//! appended to the log also gets a line in `audit.log`, next to the segments:
+//! its position, its number of operations and a SHA-256 digest of the
+//! previous batch's digest, the position and the batch's operations in the
+//! operation log format. Each digest thus covers the whole history before it,
+//! and changing, dropping or reordering any logged operation changes every
+//! later digest.
+//!
+//! The audit file is append-only and never compacted. Batches whose
+//! operations are still in the log's segments are checked against them by
+//! [`SegmentedLog::verify_audit`]; batches compacted away are covered by the
+//! digests after them. Publishing the [`AuditChain::head`] digest elsewhere
+//! from time to time makes rewriting the whole chain detectable as well.
+//!
+//! The file starts with a header line followed by one line per batch:
+//!
+//! ```text
+//! crdt-rga audit 1
+//! 0 12 5f70bf18a086007016e948b04aed3b82...
+//! 12 3 a3c5...
+//! ```
+//!
+//! [`SegmentedLog::enable_audit`]: crate::crdt::oplog::SegmentedLog::enable_audit
+//! [`SegmentedLog::verify_audit`]: crate::crdt::oplog::SegmentedLog::verify_audit
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use sha2::{Digest as _, Sha256};
+
+use crate::crdt::snapshot::{LogEntry, write_log_entry};
+
+/// First line of an audit file
+pub const AUDIT_HEADER: &str = "crdt-rga audit 1";
+
+/// A SHA-256 digest, displayed in lowercase hexadecimal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Digest(pub [u8; 32]);
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Digest {
+    type Err = &'static str;
+
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("Digest is not 64 hexadecimal digits");
+        }
+        let mut digest = [0; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = core::str::from_utf8(pair).map_err(|_| "Invalid digest")?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| "Invalid digest")?;
+        }
+        Ok(Digest(digest))
+    }
+}
+
+/// One audited batch of operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Position of the batch's first operation in the log
+    pub first: u64,
+    /// Number of operations in the batch
+    pub count: u64,
+    /// Digest of the previous batch's digest and this batch
+    pub digest: Digest,
+}
+
+impl AuditRecord {
+    /// Position just past the batch's last operation
+    pub fn end(&self) -> u64 {
+        self.first + self.count
+    }
+}
+
+/// Digest chaining a batch of operations at `first` to `previous`
+pub fn chain_digest(previous: &Digest, first: u64, entries: &[LogEntry]) -> Digest {
+    let mut lines = String::new();
+    for entry in entries {
+        write_log_entry(&mut lines, entry);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(previous.0);
+    hasher.update(first.to_le_bytes());
+    hasher.update(lines.as_bytes());
+    Digest(hasher.finalize().into())
+}
+
+fn invalid(path: &Path, error: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {error}", path.display()),
+    )
+}
+
+/// Outcome of a successful [`SegmentedLog::verify_audit`](crate::crdt::oplog::SegmentedLog::verify_audit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Number of audited batches
+    pub batches: usize,
+    /// Batches checked against the operations still in the log; the others
+    /// were compacted away
+    pub verified: usize,
+    /// Digest of the newest batch
+    pub head: Digest,
+}
+
+/// An audit file and the records it holds
+#[derive(Debug)]
+pub struct AuditChain {
+    path: PathBuf,
+    records: Vec<AuditRecord>,
+    /// Appends to the file, opened on first use
+    file: Option<File>,
+}
+
+impl AuditChain {
+    /// Opens the audit file at `path`, creating it if needed.
+    ///
+    /// Fails if a line is malformed or a batch does not start where the
+    /// previous one ended. Digests are only checked against the operations
+    /// by [`SegmentedLog::verify_audit`](crate::crdt::oplog::SegmentedLog::verify_audit).
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut records: Vec<AuditRecord> = Vec::new();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let mut lines = text.lines();
+                if lines.next() != Some(AUDIT_HEADER) {
+                    return Err(invalid(&path, "missing audit header"));
+                }
+                for line in lines {
+                    let record = parse_record(line).map_err(|error| invalid(&path, error))?;
+                    if let Some(previous) = records.last()
+                        && previous.end() != record.first
+                    {
+                        return Err(invalid(&path, "audited batches are not contiguous"));
+                    }
+                    records.push(record);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut file = File::create(&path)?;
+                writeln!(file, "{AUDIT_HEADER}")?;
+                file.sync_data()?;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(AuditChain {
+            path,
+            records,
+            file: None,
+        })
+    }
+
+    /// The audited batches, oldest first
+    pub fn records(&self) -> &[AuditRecord] {
+        &self.records
+    }
+
+    /// Digest of the newest batch, all zeros before the first
+    pub fn head(&self) -> Digest {
+        self.records
+            .last()
+            .map_or_else(Digest::default, |record| record.digest)
+    }
+
+    /// Position just past the last audited operation, if any was audited
+    pub fn end(&self) -> Option<u64> {
+        self.records.last().map(AuditRecord::end)
+    }
+
+    /// Records a batch of operations logged at `first`.
+    ///
+    /// Returns the batch's digest, the new head of the chain.
+    pub fn append(&mut self, first: u64, entries: &[LogEntry]) -> io::Result<Digest> {
+        if self.end().is_some_and(|end| end != first) {
+            return Err(invalid(&self.path, "audited batches are not contiguous"));
+        }
+        let record = AuditRecord {
+            first,
+            count: entries.len() as u64,
+            digest: chain_digest(&self.head(), first, entries),
+        };
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self
+                .file
+                .insert(OpenOptions::new().append(true).open(&self.path)?),
+        };
+        writeln!(file, "{} {} {}", record.first, record.count, record.digest)?;
+        file.sync_data()?;
+        self.records.push(record);
+        Ok(record.digest)
+    }
+}
+
+impl AuditChain {
+    /// Recomputes the digests of the batches from `base` on, whose operations
+    /// were rewritten to `entries`, and rewrites the file.
+    ///
+    /// Earlier batches keep their digests and later ones chain to them, so
+    /// the chain still verifies against the log, under a new head.
+    pub fn rechain(&mut self, base: u64, entries: &[LogEntry]) -> io::Result<Digest> {
+        let mut previous = Digest::default();
+        for record in &mut self.records {
+            if record.first >= base {
+                let start = (record.first - base) as usize;
+                let batch = entries
+                    .get(start..start + record.count as usize)
+                    .ok_or_else(|| invalid(&self.path, "audited batch is not in the log"))?;
+                record.digest = chain_digest(&previous, record.first, batch);
+            }
+            previous = record.digest;
+        }
+
+        let mut text = format!("{AUDIT_HEADER}\n");
+        for record in &self.records {
+            text.push_str(&format!(
+                "{} {} {}\n",
+                record.first, record.count, record.digest
+            ));
+        }
+        let partial = self.path.with_extension("partial");
+        let mut file = File::create(&partial)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&partial, &self.path)?;
+        self.file = None;
+        Ok(self.head())
+    }
+}
+
+fn parse_record(line: &str) -> Result<AuditRecord, &'static str> {
+    let mut fields = line.split(' ');
+    let mut number = || {
+        fields
+            .next()
+            .and_then(|field| field.parse().ok())
+            .ok_or("Invalid audit record")
+    };
+    let first = number()?;
+    let count = number()?;
+    let digest = fields.next().ok_or("Invalid audit record")?.parse()?;
+    if fields.next().is_some() {
+        return Err("Invalid audit record");
+    }
+    Ok(AuditRecord {
+        first,
+        count,
+        digest,
+    })
+}
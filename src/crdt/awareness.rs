@@ -0,0 +1,354 @@
+//! Ephemeral state shared between replicas, outside the document.
+//!
+//! Presence, cursors, typing indicators and decorations change constantly and
+//! only matter while their replica is around. Sending them as document
+//! operations would grow the op log and snapshots with data nobody needs
+//! later, so they travel on a separate [`Awareness`] channel instead.
+//!
+//! Each replica owns one opaque state, typically JSON chosen by the
+//! application, and is the only one to change it. Every change carries a
+//! per-replica clock, and an [`AwarenessUpdate`] replaces what a peer holds
+//! only if its clock is greater, so updates can be delivered in any order and
+//! more than once. A state not refreshed within the time to live is considered
+//! gone: it is no longer reported and [`Awareness::expire`] drops it.
+//!
+//! Liveness comes from the updates themselves. A replica calls
+//! [`Awareness::heartbeat`] regularly, which re-sends its unchanged state with
+//! the next clock once half the time to live has passed, so peers keep it
+//! alive. When a replica crashes or loses its connection the heartbeats stop
+//! and every peer drops its state on its own, whatever transport carried it.
+//!
+//! Nothing here touches an [`crate::RGA`]; the time is passed in by the
+//! caller, in milliseconds, so the channel works without `std`.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crdt::types::ReplicaId;
+
+/// A change to the state of one replica
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwarenessUpdate {
+    /// The replica whose state changed
+    pub replica: ReplicaId,
+    /// The replica's awareness clock after the change
+    pub clock: u64,
+    /// The new state, or `None` once the replica withdrew it
+    pub state: Option<String>,
+}
+
+/// The latest state known for a replica
+#[derive(Debug, Clone)]
+struct Entry {
+    clock: u64,
+    state: Option<String>,
+    /// When the state last changed or was renewed, in milliseconds
+    seen_at: u64,
+}
+
+/// The ephemeral state of every replica, as seen by one of them.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::Awareness;
+///
+/// let mut alice = Awareness::new(1, 30_000);
+/// let mut bob = Awareness::new(2, 30_000);
+///
+/// let update = alice.set_local_state(r#"{"cursor":4}"#, 0);
+/// assert!(bob.apply(&update, 0));
+/// assert_eq!(bob.state(1, 1_000), Some(r#"{"cursor":4}"#));
+///
+/// // Alice's heartbeats keep her state alive
+/// let heartbeat = alice.heartbeat(20_000).unwrap();
+/// bob.apply(&heartbeat, 20_000);
+/// assert!(bob.state(1, 40_000).is_some());
+///
+/// // Then she went silent for longer than the time to live
+/// assert_eq!(bob.state(1, 60_000), None);
+/// assert_eq!(bob.expire(60_000)[0].replica, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Awareness {
+    replica: ReplicaId,
+    /// How long a remote state lasts without being refreshed, in milliseconds
+    ttl: u64,
+    states: BTreeMap<ReplicaId, Entry>,
+}
+
+impl Awareness {
+    /// Creates the awareness of `replica`, keeping remote states for `ttl`
+    /// milliseconds after they were last refreshed
+    pub fn new(replica: ReplicaId, ttl: u64) -> Self {
+        Awareness {
+            replica,
+            ttl,
+            states: BTreeMap::new(),
+        }
+    }
+
+    /// The replica owning the local state
+    pub fn replica(&self) -> ReplicaId {
+        self.replica
+    }
+
+    /// How long remote states last without being refreshed, in milliseconds
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    /// Changes how long remote states last without being refreshed.
+    ///
+    /// Every replica should use the same time to live, or peers with a
+    /// shorter one drop states between heartbeats.
+    pub fn set_ttl(&mut self, ttl: u64) {
+        self.ttl = ttl;
+    }
+
+    /// How often [`Awareness::heartbeat`] re-sends the local state: half the
+    /// time to live, leaving room for one late heartbeat
+    pub fn heartbeat_interval(&self) -> u64 {
+        self.ttl / 2
+    }
+
+    /// Replaces the local state.
+    ///
+    /// # Returns
+    ///
+    /// The update to send to peers
+    pub fn set_local_state(&mut self, state: impl Into<String>, now: u64) -> AwarenessUpdate {
+        self.change(self.replica, Some(state.into()), now)
+    }
+
+    /// Withdraws the local state, as when the replica leaves.
+    ///
+    /// # Returns
+    ///
+    /// The update to send to peers
+    pub fn clear_local_state(&mut self, now: u64) -> AwarenessUpdate {
+        self.change(self.replica, None, now)
+    }
+
+    /// The local state, if one is set
+    pub fn local_state(&self) -> Option<&str> {
+        self.states.get(&self.replica)?.state.as_deref()
+    }
+
+    /// Keeps the local state alive on peers.
+    ///
+    /// Meant to be called regularly, more often than the heartbeat interval.
+    ///
+    /// # Returns
+    ///
+    /// The local state with the next clock, to send to peers, if it was last
+    /// sent at least a heartbeat interval before `now`; `None` if it is too
+    /// early or no local state is set
+    pub fn heartbeat(&mut self, now: u64) -> Option<AwarenessUpdate> {
+        let entry = self.states.get(&self.replica)?;
+        if now.saturating_sub(entry.seen_at) < self.heartbeat_interval() {
+            return None;
+        }
+        let state = entry.state.clone()?;
+        Some(self.change(self.replica, Some(state), now))
+    }
+
+    /// Withdraws the state of another replica on its behalf, for example
+    /// when the connection that carried its updates closed.
+    ///
+    /// # Returns
+    ///
+    /// The update to send to peers, or `None` if no state is held for it
+    pub fn remove(&mut self, replica: ReplicaId, now: u64) -> Option<AwarenessUpdate> {
+        self.states.get(&replica)?.state.as_ref()?;
+        Some(self.change(replica, None, now))
+    }
+
+    /// Records a new state for `replica` with the next clock
+    fn change(&mut self, replica: ReplicaId, state: Option<String>, now: u64) -> AwarenessUpdate {
+        let clock = self.states.get(&replica).map_or(0, |entry| entry.clock) + 1;
+        self.states.insert(
+            replica,
+            Entry {
+                clock,
+                state: state.clone(),
+                seen_at: now,
+            },
+        );
+        AwarenessUpdate {
+            replica,
+            clock,
+            state,
+        }
+    }
+
+    /// Applies an update received from a peer at `now`.
+    ///
+    /// Updates whose clock is not greater than the one already held for the
+    /// replica are ignored, as are updates to the local state.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the update changed what is known about its replica
+    pub fn apply(&mut self, update: &AwarenessUpdate, now: u64) -> bool {
+        if update.replica == self.replica {
+            return false;
+        }
+        if let Some(entry) = self.states.get(&update.replica)
+            && entry.clock >= update.clock
+        {
+            return false;
+        }
+        self.states.insert(
+            update.replica,
+            Entry {
+                clock: update.clock,
+                state: update.state.clone(),
+                seen_at: now,
+            },
+        );
+        true
+    }
+
+    /// Whether the state of `replica` has outlived its time to live at `now`.
+    ///
+    /// The local state never expires.
+    fn is_expired(&self, replica: ReplicaId, entry: &Entry, now: u64) -> bool {
+        replica != self.replica && now.saturating_sub(entry.seen_at) > self.ttl
+    }
+
+    /// The state of `replica` at `now`, unless withdrawn or expired
+    pub fn state(&self, replica: ReplicaId, now: u64) -> Option<&str> {
+        let entry = self.states.get(&replica)?;
+        if self.is_expired(replica, entry, now) {
+            return None;
+        }
+        entry.state.as_deref()
+    }
+
+    /// The replicas with a state at `now` and their states, by replica
+    pub fn states(&self, now: u64) -> Vec<(ReplicaId, &str)> {
+        self.states
+            .iter()
+            .filter(|&(&replica, entry)| !self.is_expired(replica, entry, now))
+            .filter_map(|(&replica, entry)| Some((replica, entry.state.as_deref()?)))
+            .collect()
+    }
+
+    /// Every state held at `now`, as updates bringing a new peer up to date
+    pub fn updates(&self, now: u64) -> Vec<AwarenessUpdate> {
+        self.states
+            .iter()
+            .filter(|&(&replica, entry)| !self.is_expired(replica, entry, now))
+            .filter(|(_, entry)| entry.state.is_some())
+            .map(|(&replica, entry)| AwarenessUpdate {
+                replica,
+                clock: entry.clock,
+                state: entry.state.clone(),
+            })
+            .collect()
+    }
+
+    /// Drops the states that expired by `now` and those withdrawn before.
+    ///
+    /// Forgetting a state also forgets its clock, so a replica that comes
+    /// back is accepted whatever its clock.
+    ///
+    /// # Returns
+    ///
+    /// Withdrawals of the expired states, by replica, each with the last
+    /// clock seen, so peers holding that state still accept newer ones
+    pub fn expire(&mut self, now: u64) -> Vec<AwarenessUpdate> {
+        let mut expired = Vec::new();
+        let local = self.replica;
+        let ttl = self.ttl;
+        self.states.retain(|&replica, entry| {
+            if replica == local || now.saturating_sub(entry.seen_at) <= ttl {
+                return true;
+            }
+            if entry.state.is_some() {
+                expired.push(AwarenessUpdate {
+                    replica,
+                    clock: entry.clock,
+                    state: None,
+                });
+            }
+            false
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_writer_wins_per_replica() {
+        let mut alice = Awareness::new(1, 1_000);
+        let mut bob = Awareness::new(2, 1_000);
+        let mut carol = Awareness::new(3, 1_000);
+
+        let typing = alice.set_local_state("typing", 0);
+        let idle = alice.set_local_state("idle", 10);
+        let waving = bob.set_local_state("waving", 10);
+
+        // Out of order and duplicated delivery converge
+        assert!(carol.apply(&idle, 20));
+        assert!(!carol.apply(&typing, 20));
+        assert!(carol.apply(&waving, 20));
+        assert!(!carol.apply(&waving, 20));
+        assert_eq!(carol.states(30), [(1, "idle"), (2, "waving")]);
+        assert!(!alice.apply(&typing, 20));
+        assert_eq!(alice.local_state(), Some("idle"));
+
+        let left = bob.clear_local_state(40);
+        assert_eq!(left.clock, 2);
+        assert!(carol.apply(&left, 40));
+        assert_eq!(carol.state(2, 50), None);
+        assert_eq!(carol.updates(50), vec![idle.clone()]);
+
+        // Alice's state outlives its time to live, the local one never does
+        assert_eq!(carol.states(1_100), []);
+        carol.set_local_state("reading", 0);
+        assert_eq!(
+            carol.expire(1_100),
+            [AwarenessUpdate {
+                replica: 1,
+                clock: 2,
+                state: None,
+            }]
+        );
+        assert_eq!(carol.states(5_000), [(3, "reading")]);
+        assert!(carol.apply(&idle, 5_000));
+
+        assert_eq!(carol.remove(1, 5_000).unwrap().clock, 3);
+        assert_eq!(carol.remove(1, 5_000), None);
+    }
+
+    #[test]
+    fn test_heartbeats_keep_states_alive() {
+        let mut alice = Awareness::new(1, 1_000);
+        let mut bob = Awareness::new(2, 1_000);
+        assert_eq!(alice.heartbeat(0), None);
+        bob.apply(&alice.set_local_state("cursor", 0), 0);
+
+        // Nothing to send before half the time to live has passed
+        assert_eq!(alice.heartbeat(400), None);
+        for now in [500, 1_000, 1_500] {
+            let heartbeat = alice.heartbeat(now).unwrap();
+            assert_eq!(heartbeat.state.as_deref(), Some("cursor"));
+            assert!(bob.apply(&heartbeat, now));
+        }
+        assert_eq!(bob.expire(2_400), []);
+        assert_eq!(bob.state(1, 2_400), Some("cursor"));
+
+        // The heartbeats stopped
+        assert_eq!(bob.expire(2_600).len(), 1);
+        assert_eq!(bob.states(2_600), []);
+
+        alice.clear_local_state(3_000);
+        assert_eq!(alice.heartbeat(5_000), None);
+    }
+}
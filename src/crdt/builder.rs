@@ -0,0 +1,139 @@
+//! Building a document from existing text in bulk.
+//!
+//! Loading a large file character by character with [`RGA::insert_after`]
+//! looks up the reference node and its neighbour, ticks the clock and takes
+//! the store's write guard once per character. [`RgaBuilder`] collects the
+//! text first and then creates every node in one pass: the IDs are allocated
+//! as a single batch of sequential IDs sharing one Lamport counter, the nodes
+//! are chained to each other without any lookup, and they reach the store in
+//! one write. The document is the same as inserting the text with
+//! [`RGA::insert_str_after`] into an empty replica.
+
+use alloc::string::String;
+
+use crate::crdt::normalize::Normalization;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::ReplicaId;
+
+/// Collects text and builds a document holding it.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::RgaBuilder;
+///
+/// let mut builder = RgaBuilder::with_capacity(1, 11);
+/// builder.push_str("hello").push(' ').push_str("world");
+/// let rga = builder.build();
+/// assert_eq!(rga.to_string(), "hello world");
+/// ```
+#[derive(Debug, Clone)]
+pub struct RgaBuilder {
+    replica_id: ReplicaId,
+    normalization: Normalization,
+    text: String,
+}
+
+impl RgaBuilder {
+    /// Creates a builder for a document of replica `replica_id`.
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self::with_capacity(replica_id, 0)
+    }
+
+    /// Creates a builder with room for `capacity` bytes of text.
+    pub fn with_capacity(replica_id: ReplicaId, capacity: usize) -> Self {
+        RgaBuilder {
+            replica_id,
+            normalization: Normalization::default(),
+            text: String::with_capacity(capacity),
+        }
+    }
+
+    /// Sets the normalization applied to the text and kept by the document
+    /// for later inserts, see [`RGA::set_normalization`].
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Appends `text` to the document.
+    pub fn push_str(&mut self, text: &str) -> &mut Self {
+        self.text.push_str(text);
+        self
+    }
+
+    /// Appends a character to the document.
+    pub fn push(&mut self, character: char) -> &mut Self {
+        self.text.push(character);
+        self
+    }
+
+    /// Number of bytes of text collected so far.
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    /// Returns true if no text was collected.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Builds the document.
+    ///
+    /// # Returns
+    ///
+    /// A new RGA holding the collected text, normalized
+    pub fn build(self) -> RGA {
+        let mut rga = RGA::new(self.replica_id);
+        rga.set_normalization(self.normalization);
+        rga.insert_run(
+            rga.sentinel_start_id(),
+            &self.normalization.apply(&self.text),
+        );
+        rga
+    }
+}
+
+impl RGA {
+    /// Creates a document of replica `replica_id` holding `text`, built in
+    /// bulk by an [`RgaBuilder`].
+    pub fn from_str(replica_id: ReplicaId, text: &str) -> RGA {
+        let mut builder = RgaBuilder::with_capacity(replica_id, text.len());
+        builder.push_str(text);
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_builder_matches_inserting_the_text() {
+        let text = "Cafe\u{301} au lait\nand croissants";
+        let inserted = RGA::new(4);
+        inserted
+            .insert_str_after(inserted.sentinel_start_id(), text)
+            .unwrap();
+
+        let built = RGA::from_str(4, text);
+        assert_eq!(built.to_string(), "Café au lait\nand croissants");
+        assert_eq!(built.to_snapshot(), inserted.to_snapshot());
+        assert_eq!(built.current_clock(), inserted.current_clock());
+        assert!(built.validate().is_ok());
+
+        // The document keeps the builder's normalization
+        let mut raw = RgaBuilder::new(4).normalization(Normalization::Off);
+        raw.push_str(text);
+        let raw = raw.build();
+        assert_eq!(raw.to_string(), text);
+        assert_eq!(raw.normalization(), Normalization::Off);
+
+        // And can be edited on
+        let last = built.visible_nodes().last().unwrap().id;
+        built.insert_after(last, '!').unwrap();
+        assert!(built.to_string().ends_with("croissants!"));
+        assert_eq!(RgaBuilder::new(1).build().to_string(), "");
+    }
+}
@@ -0,0 +1,138 @@
+//! Multi-caret editing for the RGA CRDT.
+//!
+//! Editors with multi-cursor support apply the same keystroke at several carets
+//! at once. This module resolves every caret against a single snapshot of the
+//! document and stamps the resulting operations as one clock batch.
+
+use alloc::vec::Vec;
+
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+impl RGA {
+    /// Inserts the same character at every caret.
+    ///
+    /// Carets are visible indices into the document as it was before the edit,
+    /// so inserting at `[0, 2]` in `"ab"` places a character before `a` and one
+    /// after `b`. All new IDs share a single Lamport counter value.
+    ///
+    /// # Arguments
+    ///
+    /// * `carets` - Visible indices to insert at (0 ..= length)
+    /// * `character` - The character to insert at each caret
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, in caret order
+    /// * `Err(&str)` - Error message if any caret is out of range; nothing is inserted
+    pub fn insert_at_carets(
+        &self,
+        carets: &[usize],
+        character: char,
+    ) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        let visible = self.visible_nodes();
+        if carets.iter().any(|&caret| caret > visible.len()) {
+            return Err("Caret position out of range");
+        }
+
+        let after_ids: Vec<UniqueId> = carets
+            .iter()
+            .map(|&caret| match caret {
+                0 => self.sentinel_start_id(),
+                _ => visible[caret - 1].id,
+            })
+            .collect();
+
+        let new_ids: Vec<UniqueId> = self
+            .clock
+            .tick_batch(after_ids.len())
+            .into_iter()
+            .map(UniqueId::from)
+            .collect();
+
+        let right_origins: Vec<Option<UniqueId>> = after_ids
+            .iter()
+            .map(|&after_id| self.right_of(after_id))
+            .collect();
+        for ((&id, &after_id), &right_origin) in new_ids.iter().zip(&after_ids).zip(&right_origins)
+        {
+            self.insert_with_id(id, after_id, right_origin, character);
+            self.debug_validate(id);
+        }
+        Ok(new_ids)
+    }
+
+    /// Deletes the character at every caret.
+    ///
+    /// Carets are visible indices into the document as it was before the edit.
+    /// Several carets on the same character delete it only once.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document order
+    /// * `Err(&str)` - Error message if any caret is out of range; nothing is deleted
+    pub fn delete_at_carets(&self, carets: &[usize]) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        let visible = self.visible_nodes();
+        if carets.iter().any(|&caret| caret >= visible.len()) {
+            return Err("Caret position out of range");
+        }
+
+        let mut indices = carets.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let mut deleted = Vec::with_capacity(indices.len());
+        for index in indices {
+            let id = visible[index].id;
+            self.delete(id)?;
+            deleted.push(id);
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rga_with(text: &str) -> RGA {
+        let rga = RGA::new(1);
+        let mut last_id = rga.sentinel_start_id();
+        for ch in text.chars() {
+            last_id = rga.insert_after(last_id, ch).unwrap();
+        }
+        rga
+    }
+
+    #[test]
+    fn test_insert_at_carets_single_batch() {
+        let rga = rga_with("ab");
+        let ids = rga.insert_at_carets(&[1, 2], 'x').unwrap();
+
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0].counter(), ids[1].counter());
+        assert!(ids[0] < ids[1]);
+        assert_eq!(rga.visible_node_count(), 4);
+        assert_eq!(rga.to_string().matches('x').count(), 2);
+    }
+
+    #[test]
+    fn test_insert_at_carets_rejects_out_of_range() {
+        let rga = rga_with("ab");
+        assert!(rga.insert_at_carets(&[0, 3], 'x').is_err());
+        assert_eq!(rga.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_delete_at_carets() {
+        let rga = rga_with("abcd");
+        let deleted = rga.delete_at_carets(&[3, 0, 3]).unwrap();
+
+        assert_eq!(deleted.len(), 2);
+        assert_eq!(rga.to_string(), "bc");
+        assert!(rga.delete_at_carets(&[2]).is_err());
+        assert_eq!(rga.to_string(), "bc");
+    }
+}
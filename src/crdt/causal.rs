@@ -0,0 +1,387 @@
+//! Causal delivery of remote operations.
+//!
+//! Transports without ordering guarantees (gossip, MQTT topics per replica,
+//! multi-path networks) can hand a replica an insertion before the node it was
+//! inserted after, or a deletion before the insertion it removes. A
+//! [`CausalBuffer`] holds such operations back until their dependency has been
+//! applied, then applies them in a valid order.
+//!
+//! Once replicas garbage collect tombstones, a dependency may never arrive: a
+//! lagging replica can send an insertion whose origin everyone else has
+//! purged. Waiting for it would stall the operation forever, and guessing its
+//! position would misplace it. Instead the buffer is told the collection
+//! horizon, reports such operations as [`ResyncRequired`], and recovers when it
+//! is handed a snapshot with [`CausalBuffer::resync`].
+//!
+//! New nodes stamped by a replica the document does not let write (see
+//! [`RGA::restrict_writers`]) are rejected before they are applied or
+//! buffered, deleted or not.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::metrics::{ApplyOutcome, RejectReason};
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// An insertion whose origin may have been garbage collected.
+///
+/// The document has to be brought up to date from a snapshot before the
+/// insertion can be placed.
+#[derive(Debug, Clone)]
+pub struct ResyncRequired {
+    /// The insertion that could not be applied
+    pub node: Node,
+    /// Its origin, older than the collection horizon and not present
+    pub missing: UniqueId,
+}
+
+impl fmt::Display for ResyncRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "resync required: {:?} was inserted after {:?}, which may have been garbage collected",
+            self.node.id, self.missing
+        )
+    }
+}
+
+/// Buffers remote operations until the nodes they depend on are present.
+#[derive(Debug, Default)]
+pub struct CausalBuffer {
+    /// Operations waiting for a missing node, keyed by that node
+    pending: BTreeMap<UniqueId, Vec<Node>>,
+    /// Tombstones with lower IDs may have been garbage collected
+    horizon: Option<UniqueId>,
+    /// Insertions that cannot be applied until the next resync
+    resync: Vec<ResyncRequired>,
+}
+
+impl CausalBuffer {
+    /// Creates an empty buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of operations waiting for a dependency
+    pub fn len(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    /// Returns true if no operation is waiting
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Declares that tombstones with IDs below `horizon` may have been
+    /// garbage collected, by this replica or any other.
+    ///
+    /// Buffered insertions waiting for such a node will never see it arrive
+    /// and are moved to [`CausalBuffer::resync_required`].
+    pub fn set_horizon(&mut self, horizon: UniqueId) {
+        self.horizon = Some(horizon);
+        let collected: Vec<UniqueId> = self.pending.range(..horizon).map(|(&id, _)| id).collect();
+        for missing in collected {
+            for node in self.pending.remove(&missing).unwrap_or_default() {
+                // Deleting a collected node has nothing left to do
+                if !node.is_deleted {
+                    self.resync.push(ResyncRequired { node, missing });
+                }
+            }
+        }
+    }
+
+    /// Insertions that could not be applied because their origin may have
+    /// been garbage collected
+    pub fn resync_required(&self) -> &[ResyncRequired] {
+        &self.resync
+    }
+
+    /// Returns true if the document must be resynchronized from a snapshot
+    pub fn needs_resync(&self) -> bool {
+        !self.resync.is_empty()
+    }
+
+    /// Applies `node` to `rga` once its dependency is present.
+    ///
+    /// An insertion depends on its origin, a deletion on the node it deletes.
+    /// Applying a node releases every operation that was waiting for it. A
+    /// node already deleted locally is never resurrected by a stale copy.
+    ///
+    /// A missing dependency below the collection horizon will never arrive. A
+    /// deletion of it is dropped, and an insertion after it is recorded as
+    /// [`ResyncRequired`] instead of being buffered.
+    ///
+    /// # Returns
+    ///
+    /// The number of operations applied, including released ones; `0` if
+    /// `node` was buffered or needs a resync
+    pub fn deliver(&mut self, rga: &RGA, node: Node) -> usize {
+        self.deliver_reporting(rga, node).0
+    }
+
+    /// Applies `node` like [`CausalBuffer::deliver`], reporting what became
+    /// of it rather than how many operations were applied.
+    ///
+    /// # Returns
+    ///
+    /// * [`ApplyOutcome::Buffered`] - If `node` waits for the node given
+    /// * [`ApplyOutcome::Rejected`] - If its replica may not write, or the
+    ///   node it depends on may have been garbage collected
+    /// * Otherwise what [`RGA::apply_remote_op`] made of it
+    pub fn apply(&mut self, rga: &RGA, node: Node) -> ApplyOutcome {
+        self.deliver_reporting(rga, node).1
+    }
+
+    /// The number of operations applied and the outcome of `node` itself
+    fn deliver_reporting(&mut self, rga: &RGA, node: Node) -> (usize, ApplyOutcome) {
+        let mut applied = 0;
+        // Set by `node`, which is handled first
+        let mut outcome = None;
+        let mut ready = vec![node];
+        while let Some(node) = ready.pop() {
+            if let Some(reason) = rga.forbidden(&node) {
+                rga.record_rejected();
+                outcome.get_or_insert(ApplyOutcome::Rejected(reason));
+                continue;
+            }
+            let dependency = if node.is_deleted {
+                Some(node.id)
+            } else {
+                node.origin
+            };
+            if let Some(dependency) = dependency.filter(|&id| rga.get_node(id).is_none()) {
+                let held = if self.horizon.is_some_and(|horizon| dependency < horizon) {
+                    if !node.is_deleted {
+                        self.resync.push(ResyncRequired {
+                            node,
+                            missing: dependency,
+                        });
+                    }
+                    rga.record_rejected();
+                    ApplyOutcome::Rejected(RejectReason::Collected(dependency))
+                } else {
+                    self.pending.entry(dependency).or_default().push(node);
+                    rga.record_buffered();
+                    ApplyOutcome::Buffered(dependency)
+                };
+                outcome.get_or_insert(held);
+                continue;
+            }
+
+            // A node already deleted locally is deduplicated, not resurrected
+            let id = node.id;
+            outcome.get_or_insert(rga.apply_remote_op(node));
+            applied += 1;
+            if let Some(waiting) = self.pending.remove(&id) {
+                ready.extend(waiting);
+            }
+        }
+        (applied, outcome.unwrap_or(ApplyOutcome::Duplicate))
+    }
+
+    /// Brings `rga` up to date from a snapshot of another replica, then
+    /// retries the insertions that needed a resync.
+    ///
+    /// The snapshot's nodes are applied in the order given, which should be
+    /// its document order as [`RGA::all_nodes`] returns it, so each node
+    /// follows its origin as in [`RGA::merge`]. A node whose origin has not
+    /// arrived is held back like any delivered insertion, never placed
+    /// without it. Insertions the snapshot does not cover are delivered again
+    /// and may still need a newer snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `rga` - The local replica
+    /// * `snapshot` - Every node of the other replica, in document order
+    ///
+    /// # Returns
+    ///
+    /// The number of operations applied, including released ones
+    pub fn resync(&mut self, rga: &RGA, snapshot: impl IntoIterator<Item = Node>) -> usize {
+        let mut applied = 0;
+        let mut released = Vec::new();
+        let retry = core::mem::take(&mut self.resync);
+        for node in snapshot.into_iter().filter(|node| !node.is_sentinel()) {
+            let id = node.id;
+            if node.origin.is_some_and(|origin| !rga.holds(origin)) {
+                released.push(node);
+                continue;
+            }
+            if !rga.get_node(id).is_some_and(|local| local.is_deleted) {
+                rga.apply_remote_op(node);
+            }
+            applied += 1;
+            released.extend(self.pending.remove(&id).unwrap_or_default());
+        }
+
+        for node in released
+            .into_iter()
+            .chain(retry.into_iter().map(|resync| resync.node))
+        {
+            if rga.get_node(node.id).is_none() || node.is_deleted {
+                applied += self.deliver(rga, node);
+            }
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::provenance::ProvenanceFilter;
+
+    #[test]
+    fn test_insert_waits_for_origin() {
+        let source = RGA::new(1);
+        let a_id = source
+            .insert_after(source.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = source.insert_after(a_id, 'b').unwrap();
+
+        let target = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+        assert_eq!(buffer.deliver(&target, source.get_node(b_id).unwrap()), 0);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(target.to_string(), "");
+
+        assert_eq!(buffer.deliver(&target, source.get_node(a_id).unwrap()), 2);
+        assert!(buffer.is_empty());
+        assert_eq!(target.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_delete_waits_for_insert_and_is_not_undone() {
+        let source = RGA::new(1);
+        let a_id = source
+            .insert_after(source.sentinel_start_id(), 'a')
+            .unwrap();
+        let inserted = source.get_node(a_id).unwrap();
+        source.delete(a_id).unwrap();
+
+        let target = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+        assert_eq!(
+            buffer.apply(&target, source.get_node(a_id).unwrap()),
+            ApplyOutcome::Buffered(a_id)
+        );
+        assert_eq!(buffer.deliver(&target, inserted.clone()), 2);
+        assert!(target.get_node(a_id).unwrap().is_deleted);
+
+        // A redelivered insertion does not resurrect the node
+        assert_eq!(buffer.apply(&target, inserted), ApplyOutcome::Duplicate);
+        assert!(target.get_node(a_id).unwrap().is_deleted);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_insert_after_collected_origin_requires_resync() {
+        // `a` was deleted and then collected everywhere except on a lagging
+        // replica, which inserts `b` after it
+        let lagging = RGA::new(1);
+        let a_id = lagging
+            .insert_after(lagging.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = lagging.insert_after(a_id, 'b').unwrap();
+        let c_id = lagging.insert_after(b_id, 'c').unwrap();
+        let target = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+
+        // Buffered while `a` might still arrive, reported once it cannot
+        assert_eq!(buffer.deliver(&target, lagging.get_node(b_id).unwrap()), 0);
+        assert_eq!(buffer.deliver(&target, lagging.get_node(c_id).unwrap()), 0);
+        buffer.set_horizon(b_id);
+        assert!(buffer.needs_resync());
+        assert_eq!(buffer.resync_required()[0].missing, a_id);
+        assert_eq!(buffer.len(), 1);
+
+        // Deleting a collected node is dropped without a resync
+        let mut deleted = lagging.get_node(a_id).unwrap();
+        deleted.is_deleted = true;
+        assert_eq!(
+            buffer.apply(&target, deleted),
+            ApplyOutcome::Rejected(RejectReason::Collected(a_id))
+        );
+        assert_eq!(buffer.resync_required().len(), 1);
+
+        // A snapshot of a replica that collected `a` recovers
+        let upstream = RGA::new(3);
+        upstream.merge(&lagging);
+        upstream.delete(a_id).unwrap();
+        upstream.collect_garbage(b_id);
+        assert_eq!(buffer.resync(&target, upstream.all_nodes()), 2);
+        assert!(!buffer.needs_resync());
+        assert!(buffer.is_empty());
+        assert_eq!(target.to_string(), "bc");
+    }
+
+    #[test]
+    fn test_insertions_from_other_writers_are_rejected() {
+        let source = RGA::new(1);
+        let a_id = source
+            .insert_after(source.sentinel_start_id(), 'a')
+            .unwrap();
+        let target = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+        target.restrict_writers(ProvenanceFilter::only([2]));
+        assert_eq!(
+            buffer.apply(&target, source.get_node(a_id).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::Forbidden(1))
+        );
+        assert!(buffer.is_empty());
+        assert_eq!(target.sync_metrics().rejected, 1);
+
+        target.restrict_writers(ProvenanceFilter::All);
+        assert_eq!(
+            buffer.apply(&target, source.get_node(a_id).unwrap()),
+            ApplyOutcome::Applied
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_resync_places_nodes_whose_origins_were_rewritten() {
+        // "RQPX" with P purged: X's origin is rewritten, and Q is newer than X
+        let source = RGA::new(1);
+        let r = source
+            .insert_after(source.sentinel_start_id(), 'R')
+            .unwrap();
+        let p = source.insert_after(r, 'P').unwrap();
+        let x = source.insert_after(p, 'X').unwrap();
+        source.insert_after(r, 'Q').unwrap();
+        source.delete(p).unwrap();
+        let z = source.insert_after(x, 'Z').unwrap();
+        source.collect_garbage(z);
+        assert_eq!(source.to_string(), "RQXZ");
+
+        let resynced = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+        assert_eq!(buffer.resync(&resynced, source.all_nodes()), 4);
+        let merged = RGA::new(3);
+        merged.merge(&source);
+        let delivered = RGA::new(4);
+        let mut reversed = CausalBuffer::new();
+        for node in source.all_nodes().into_iter().rev() {
+            if !node.is_sentinel() {
+                reversed.deliver(&delivered, node);
+            }
+        }
+        for rga in [&resynced, &merged, &delivered] {
+            assert_eq!(rga.to_string(), "RQXZ");
+        }
+
+        // Out of document order, nodes wait for their origins
+        let shuffled = RGA::new(5);
+        let mut buffer = CausalBuffer::new();
+        assert_eq!(
+            buffer.resync(&shuffled, source.all_nodes().into_iter().rev()),
+            4
+        );
+        assert!(buffer.is_empty());
+        assert_eq!(shuffled.to_string(), "RQXZ");
+    }
+}
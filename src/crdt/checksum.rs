@@ -0,0 +1,340 @@
+//! Content checksums for detecting silently diverged replicas.
+//!
+//! Operations can be lost or corrupted on the way without any replica
+//! noticing: each keeps a valid document, just not the same one. Replicas
+//! therefore exchange [`Checksums`] from time to time and compare them with
+//! [`RGA::verify_checksums`].
+//!
+//! The nodes are split into ranges, one per replica that inserted them. A
+//! range is hashed up to the newest counter of its replica that the sender
+//! had seen, its entry in the sender's [`RGA::version_vector`]. The receiver
+//! checks every range it has caught up with, hashing its own nodes up to the
+//! same counter, so both sides hash the same operations even while newer
+//! ones are in flight; ranges it is behind on are left for a later exchange.
+//! A mismatch names the ranges that differ, and [`RGA::mismatched_nodes`]
+//! collects the nodes to send so the other side can repair them.
+//!
+//! Hashes cannot reveal operations lost after the last one the other replica
+//! received from their author: it simply looks behind. A [`ChecksumMonitor`]
+//! remembers the ranges each replica was behind on, and reports those it is
+//! still behind on at the same counter one exchange later as stalled.
+//!
+//! Deletions carry no timestamp of their own, so a node deleted while the
+//! checksums were in flight makes its range mismatch once. Reconciling it is
+//! harmless: applying nodes a replica already holds changes nothing.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// Checksum of the nodes one replica inserted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RangeChecksum {
+    /// Number of nodes in the range, tombstones included
+    pub nodes: u64,
+    /// FNV-1a hash of the nodes, in ID order
+    pub hash: u64,
+}
+
+/// A document's checksums, per replica range
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checksums {
+    /// How far each range reaches: the newest counter of each replica
+    pub version: BTreeMap<ReplicaId, u64>,
+    /// The checksum of each replica's range
+    pub ranges: BTreeMap<ReplicaId, RangeChecksum>,
+}
+
+/// A range whose checksums differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeMismatch {
+    /// Replica whose insertions the range holds
+    pub replica: ReplicaId,
+    /// Newest counter of the range
+    pub counter: u64,
+    /// This replica's checksum of the range
+    pub local: RangeChecksum,
+    /// The other replica's checksum of the range
+    pub remote: RangeChecksum,
+}
+
+/// Diagnostics of two replicas holding different content at a version both
+/// have reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The version the ranges were compared at
+    pub version: BTreeMap<ReplicaId, u64>,
+    /// The ranges that differ, by replica
+    pub ranges: Vec<RangeMismatch>,
+    /// Ranges the other replica has been behind on for two exchanges, with
+    /// the counter it is stuck at
+    pub stalled: BTreeMap<ReplicaId, u64>,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} range(s) differ", self.ranges.len())?;
+        for range in &self.ranges {
+            write!(
+                f,
+                "; replica {} up to {} has {} node(s) here and {} there",
+                range.replica, range.counter, range.local.nodes, range.remote.nodes
+            )?;
+        }
+        for (replica, counter) in &self.stalled {
+            write!(f, "; replica {replica} is stuck at {counter} there")?;
+        }
+        Ok(())
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl RangeChecksum {
+    fn add(&mut self, node: &Node) {
+        if self.nodes == 0 {
+            self.hash = FNV_OFFSET;
+        }
+        let mut write = |bytes: &[u8]| {
+            for &byte in bytes {
+                self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+            }
+        };
+        let mut write_id = |id: Option<UniqueId>| match id {
+            Some(id) => {
+                write(&id.counter().to_le_bytes());
+                write(&id.replica_id().to_le_bytes());
+                write(&id.sequence().to_le_bytes());
+            }
+            None => write(&[0xff]),
+        };
+        write_id(Some(node.id));
+        write_id(node.origin);
+        write_id(node.right_origin);
+        write(&u32::from(node.character).to_le_bytes());
+        write(&[u8::from(node.is_deleted)]);
+        self.nodes += 1;
+    }
+}
+
+impl RGA {
+    /// Checksums of the whole document, to send to other replicas.
+    pub fn checksums(&self) -> Checksums {
+        self.checksums_at(&self.version_vector())
+    }
+
+    /// Checksums of the replica ranges in `version`, each up to its counter.
+    pub fn checksums_at(&self, version: &BTreeMap<ReplicaId, u64>) -> Checksums {
+        let mut ranges: BTreeMap<ReplicaId, RangeChecksum> = version
+            .keys()
+            .map(|&replica| (replica, RangeChecksum::default()))
+            .collect();
+        self.for_each_node_by_id(|node| {
+            let replica = node.id.replica_id();
+            if version
+                .get(&replica)
+                .is_some_and(|&counter| node.id.counter() <= counter)
+                && let Some(range) = ranges.get_mut(&replica)
+            {
+                range.add(node);
+            }
+        });
+        Checksums {
+            version: version.clone(),
+            ranges,
+        }
+    }
+
+    /// Compares another replica's checksums with this document.
+    ///
+    /// Only ranges this document has caught up with are compared, at the
+    /// other replica's counters.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If every compared range matches
+    /// * `Err(ChecksumMismatch)` - The ranges that differ
+    pub fn verify_checksums(&self, remote: &Checksums) -> Result<(), ChecksumMismatch> {
+        let local_version = self.version_vector();
+        let version: BTreeMap<ReplicaId, u64> = remote
+            .version
+            .iter()
+            .filter(|&(replica, counter)| local_version.get(replica) >= Some(counter))
+            .map(|(&replica, &counter)| (replica, counter))
+            .collect();
+        let local = self.checksums_at(&version);
+        let ranges: Vec<RangeMismatch> = version
+            .iter()
+            .filter_map(|(&replica, &counter)| {
+                let local = local.ranges.get(&replica).copied().unwrap_or_default();
+                let remote = remote.ranges.get(&replica).copied().unwrap_or_default();
+                (local != remote).then_some(RangeMismatch {
+                    replica,
+                    counter,
+                    local,
+                    remote,
+                })
+            })
+            .collect();
+        if ranges.is_empty() {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch {
+                version,
+                ranges,
+                stalled: BTreeMap::new(),
+            })
+        }
+    }
+
+    /// The nodes to send the other replica of `mismatch`, in document order.
+    ///
+    /// A range that differs is sent whole, newer nodes than the compared
+    /// counter included, as the other replica may lack those too. Of a
+    /// stalled range, the nodes after the counter it is stuck at are sent.
+    pub fn mismatched_nodes(&self, mismatch: &ChecksumMismatch) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        self.for_each_node(|node| {
+            let replica = node.id.replica_id();
+            if !node.is_sentinel()
+                && (mismatch.ranges.iter().any(|range| range.replica == replica)
+                    || mismatch
+                        .stalled
+                        .get(&replica)
+                        .is_some_and(|&counter| node.id.counter() > counter))
+            {
+                nodes.push(node.clone());
+            }
+        });
+        nodes
+    }
+}
+
+/// Compares the checksums received from other replicas over successive
+/// exchanges.
+#[derive(Debug, Default)]
+pub struct ChecksumMonitor {
+    /// Per replica, the ranges it was behind on at its last exchange and its
+    /// counters there
+    behind: BTreeMap<ReplicaId, BTreeMap<ReplicaId, u64>>,
+    /// Mismatches reported per replica
+    reported: BTreeMap<ReplicaId, u64>,
+}
+
+impl ChecksumMonitor {
+    /// Creates a monitor with no exchanges seen
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compares `remote`, the checksums of `replica`, with `rga`, as
+    /// [`RGA::verify_checksums`] does, and reports the ranges `replica` has
+    /// been stuck behind on since its previous checksums.
+    pub fn inspect(
+        &mut self,
+        rga: &RGA,
+        replica: ReplicaId,
+        remote: &Checksums,
+    ) -> Result<(), ChecksumMismatch> {
+        let behind: BTreeMap<ReplicaId, u64> = rga
+            .version_vector()
+            .into_iter()
+            .filter_map(|(range, counter)| {
+                let remote_counter = remote.version.get(&range).copied().unwrap_or_default();
+                (remote_counter < counter).then_some((range, remote_counter))
+            })
+            .collect();
+        let previous = self
+            .behind
+            .insert(replica, behind.clone())
+            .unwrap_or_default();
+        let stalled: BTreeMap<ReplicaId, u64> = behind
+            .into_iter()
+            .filter(|(range, counter)| previous.get(range) == Some(counter))
+            .collect();
+
+        let result = match rga.verify_checksums(remote) {
+            Ok(()) if stalled.is_empty() => Ok(()),
+            Ok(()) => Err(ChecksumMismatch {
+                version: remote.version.clone(),
+                ranges: Vec::new(),
+                stalled,
+            }),
+            Err(mismatch) => Err(ChecksumMismatch {
+                stalled,
+                ..mismatch
+            }),
+        };
+        if result.is_err() {
+            *self.reported.entry(replica).or_default() += 1;
+        }
+        result
+    }
+
+    /// Number of mismatches reported for each replica so far
+    pub fn reported(&self) -> &BTreeMap<ReplicaId, u64> {
+        &self.reported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_checksums_detect_and_repair_divergence() {
+        let first = RGA::new(1);
+        let ids = first
+            .insert_str_after(first.sentinel_start_id(), "abc")
+            .unwrap();
+        let second = first.fork(2);
+        second.insert_after(ids[2], 'd').unwrap();
+        assert_eq!(second.version_vector(), BTreeMap::from([(1, 1), (2, 2)]));
+
+        // The second replica's newer insertion is not compared by the first
+        assert!(first.verify_checksums(&second.checksums()).is_ok());
+        assert!(second.verify_checksums(&first.checksums()).is_ok());
+
+        // A deletion that never arrived
+        first.delete(ids[1]).unwrap();
+        let mismatch = second.verify_checksums(&first.checksums()).unwrap_err();
+        assert_eq!(mismatch.ranges.len(), 1);
+        let range = mismatch.ranges[0];
+        assert_eq!((range.replica, range.counter), (1, 1));
+        assert_eq!(range.local.nodes, range.remote.nodes);
+        assert!(mismatch.to_string().contains("replica 1 up to 1"));
+
+        // Sending the range repairs it
+        let mismatch = first.verify_checksums(&second.checksums()).unwrap_err();
+        for node in first.mismatched_nodes(&mismatch) {
+            second.apply_remote_op(node);
+        }
+        assert!(second.verify_checksums(&first.checksums()).is_ok());
+        assert_eq!(second.to_string(), "acd");
+
+        // An insertion lost after everything else the first replica had
+        for node in second.all_nodes().into_iter().filter(|n| !n.is_sentinel()) {
+            first.apply_remote_op(node);
+        }
+        let mut monitor = ChecksumMonitor::new();
+        let e = second.insert_after(ids[2], 'e').unwrap();
+        assert!(monitor.inspect(&second, 1, &first.checksums()).is_ok());
+        let mismatch = monitor.inspect(&second, 1, &first.checksums()).unwrap_err();
+        assert!(mismatch.ranges.is_empty());
+        assert_eq!(mismatch.stalled, BTreeMap::from([(2, 2)]));
+        assert!(
+            mismatch
+                .to_string()
+                .ends_with("replica 2 is stuck at 2 there")
+        );
+        let nodes = second.mismatched_nodes(&mismatch);
+        assert_eq!(nodes.iter().map(|node| node.id).collect::<Vec<_>>(), [e]);
+        assert_eq!(monitor.reported(), &BTreeMap::from([(1, 1)]));
+    }
+}
@@ -0,0 +1,160 @@
+//! Forking documents and measuring how far a fork has drifted.
+//!
+//! A fork starts as a copy of its upstream under a different replica ID, so
+//! both can keep editing independently and later exchange operations like any
+//! two replicas. [`RGA::divergence_from`] reports which operations each side
+//! has that the other lacks.
+
+use alloc::vec::Vec;
+
+use crate::crdt::metrics::OpCounters;
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::ReplicaId;
+
+/// The operations a fork and its upstream do not have in common.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForkDivergence {
+    /// Operations the fork has that upstream lacks
+    pub ahead: Vec<Node>,
+    /// Operations upstream has that the fork lacks
+    pub behind: Vec<Node>,
+}
+
+impl ForkDivergence {
+    /// Returns true if neither side has operations the other lacks
+    pub fn is_even(&self) -> bool {
+        self.ahead.is_empty() && self.behind.is_empty()
+    }
+}
+
+impl RGA {
+    /// Creates a fork of this document owned by another replica.
+    ///
+    /// The fork holds every node of this document, tombstones included, and
+    /// its clock is at least this document's, so its edits never reuse IDs.
+    /// It keeps this document's normalization policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `replica_id` - The replica ID the fork edits as; must differ from
+    ///   every replica still editing this document
+    ///
+    /// # Returns
+    ///
+    /// * The forked document
+    pub fn fork(&self, replica_id: ReplicaId) -> RGA {
+        let mut fork = RGA::new(replica_id);
+        fork.set_normalization(self.normalization);
+        // Copied in document order, so the fork shows the same text
+        let mut previous = fork.sentinel_start_id();
+        self.for_each_node(|node| {
+            if !node.is_sentinel() {
+                fork.place_after(previous, node.clone());
+                previous = node.id;
+            }
+        });
+        // Copying the document is not sync traffic
+        fork.counters = OpCounters::new();
+        fork
+    }
+
+    /// Returns the operations this document has that `other` lacks.
+    ///
+    /// That is every node `other` never received, and every node deleted here
+    /// that is still visible in `other`. Applying them to `other` with
+    /// [`RGA::apply_remote_op`], in order, brings it up to date with this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The document to compare against
+    ///
+    /// # Returns
+    ///
+    /// * The missing operations in document order, every origin before the
+    ///   nodes inserted after it
+    pub fn missing_from(&self, other: &RGA) -> Vec<Node> {
+        let mut missing = Vec::new();
+        self.for_each_node(|node| {
+            if node.is_sentinel() {
+                return;
+            }
+            match other.get_node(node.id) {
+                None => missing.push(node.clone()),
+                Some(theirs) if node.is_deleted && !theirs.is_deleted => missing.push(node.clone()),
+                Some(_) => {}
+            }
+        });
+        missing
+    }
+
+    /// Compares this fork with its upstream document.
+    ///
+    /// # Arguments
+    ///
+    /// * `upstream` - The document this one was forked from
+    ///
+    /// # Returns
+    ///
+    /// * The operations each side has that the other lacks
+    pub fn divergence_from(&self, upstream: &RGA) -> ForkDivergence {
+        ForkDivergence {
+            ahead: self.missing_from(upstream),
+            behind: upstream.missing_from(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_converged;
+
+    #[test]
+    fn test_fork_shares_history() {
+        let upstream = RGA::new(1);
+        let a_id = upstream
+            .insert_after(upstream.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = upstream.insert_after(a_id, 'b').unwrap();
+        upstream.delete(a_id).unwrap();
+
+        let fork = upstream.fork(2);
+        assert_eq!(fork.replica_id(), 2);
+        assert_eq!(fork.to_string(), "b");
+        assert_eq!(fork.total_node_count(), upstream.total_node_count());
+        assert!(fork.current_clock() >= upstream.current_clock());
+        assert!(fork.divergence_from(&upstream).is_even());
+
+        // The fork's own edits get fresh IDs
+        let c_id = fork.insert_after(b_id, 'c').unwrap();
+        assert!(upstream.get_node(c_id).is_none());
+    }
+
+    #[test]
+    fn test_divergence_and_catch_up() {
+        let upstream = RGA::new(1);
+        let a_id = upstream
+            .insert_after(upstream.sentinel_start_id(), 'a')
+            .unwrap();
+        let fork = upstream.fork(2);
+
+        let b_id = fork.insert_after(a_id, 'b').unwrap();
+        upstream.delete(a_id).unwrap();
+        let c_id = upstream.insert_after(a_id, 'c').unwrap();
+
+        let divergence = fork.divergence_from(&upstream);
+        let ids = |nodes: &[Node]| nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+        assert_eq!(ids(&divergence.ahead), vec![b_id]);
+        assert_eq!(ids(&divergence.behind), vec![a_id, c_id]);
+
+        for node in divergence.behind {
+            fork.apply_remote_op(node);
+        }
+        for node in divergence.ahead {
+            upstream.apply_remote_op(node);
+        }
+        assert_converged([&fork, &upstream]);
+        assert!(fork.divergence_from(&upstream).is_even());
+    }
+}
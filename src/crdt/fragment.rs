@@ -0,0 +1,145 @@
+//! Copying and pasting text with its authorship.
+//!
+//! Pasting text normally makes the pasting replica its author: the new nodes
+//! carry its IDs, and [`crate::ProvenanceFilter`] attributes them to it.
+//! [`RGA::copy_range`] instead takes a [`Fragment`] remembering who wrote each
+//! character, and [`RGA::insert_fragment`] inserts it with fresh IDs, for
+//! ordering, while each node records its original author in
+//! [`Node::author`]. The author travels with the node to other replicas, in
+//! snapshots and in operation logs, so copied text stays attributed to whoever
+//! wrote it, wherever it is pasted.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crdt::node::Node;
+use crate::crdt::range::Range;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// Copied text, with the replica that wrote each character
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fragment {
+    chars: Vec<(char, ReplicaId)>,
+}
+
+impl Fragment {
+    /// The characters and their authors, in order
+    pub fn chars(&self) -> &[(char, ReplicaId)] {
+        &self.chars
+    }
+
+    /// The copied text
+    pub fn text(&self) -> String {
+        self.chars.iter().map(|&(character, _)| character).collect()
+    }
+
+    /// Number of copied characters
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    /// Returns true if nothing was copied
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+}
+
+impl RGA {
+    /// Copies the visible characters of a range with their authors.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Fragment)` - The copied characters, in document order
+    /// * `Err(&str)` - Error message if either end of the range is unknown
+    pub fn copy_range(&self, range: &Range) -> Result<Fragment, &'static str> {
+        Ok(Fragment {
+            chars: self
+                .range_nodes(range)?
+                .iter()
+                .filter(|node| node.is_visible())
+                .map(|node| (node.character, node.author()))
+                .collect(),
+        })
+    }
+
+    /// Pastes a fragment after the node identified by `after_id`.
+    ///
+    /// The fragment is inserted as one chained run, like
+    /// [`RGA::insert_str_after`], with fresh IDs of this replica. Characters
+    /// written by another replica record it as their author. The text is not
+    /// normalized again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, in text order
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    pub fn insert_fragment(
+        &self,
+        after_id: UniqueId,
+        fragment: &Fragment,
+    ) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        if !self.holds(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        if fragment.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids: Vec<UniqueId> = self
+            .clock
+            .tick_batch(fragment.len())
+            .into_iter()
+            .map(UniqueId::from)
+            .collect();
+        self.nodes.batch(|| {
+            let right_origin = self.right_of(after_id);
+            let mut after_id = after_id;
+            for (&id, &(character, author)) in ids.iter().zip(&fragment.chars) {
+                self.nodes.insert(Node {
+                    author: (author != self.replica_id()).then_some(author),
+                    ..Node::with_origins(id, character, after_id, right_origin)
+                });
+                self.debug_validate(id);
+                after_id = id;
+            }
+        });
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::provenance::ProvenanceFilter;
+    use crate::crdt::snapshot::{encode_op_log, parse_op_log};
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_pasted_text_keeps_its_author() {
+        let alice = RGA::new(1);
+        alice
+            .insert_str_after(alice.sentinel_start_id(), "quote")
+            .unwrap();
+        let bob = alice.fork(2);
+        let own = bob.insert_str_after(bob.sentinel_start_id(), "> ").unwrap();
+
+        let fragment = bob.copy_range(&bob.range_at(0..7).unwrap()).unwrap();
+        assert_eq!(fragment.text(), "> quote");
+        let pasted = bob.insert_fragment(own[1], &fragment).unwrap();
+        assert!(pasted.iter().all(|id| id.replica_id() == 2));
+        assert_eq!(bob.text_by(&ProvenanceFilter::only([1])), "quotequote");
+        assert_eq!(bob.text_by(&ProvenanceFilter::only([2])), "> > ");
+
+        // The author reaches other replicas through snapshots and logs
+        let restored = RGA::from_snapshot(3, &bob.to_snapshot()).unwrap();
+        assert_eq!(restored.text_by(&ProvenanceFilter::only([1])), "quotequote");
+        let replayed = alice.fork(4);
+        for entry in parse_op_log(&encode_op_log(&bob.log_entries_since(&alice))).unwrap() {
+            replayed.apply_log_entry(&entry).unwrap();
+        }
+        assert_eq!(replayed.to_string(), bob.to_string());
+        assert_eq!(replayed.text_by(&ProvenanceFilter::mute([2])), "quotequote");
+    }
+}
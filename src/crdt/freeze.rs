@@ -0,0 +1,193 @@
+//! Freezing a document against local edits.
+//!
+//! An administrator freezes a document to make it read-only on every replica,
+//! during an audit or before an export, and unfreezes it later. Whether the
+//! document is frozen is a last-writer-wins register: [`Rga::freeze`] and
+//! [`Rga::unfreeze`] stamp the new state with the replica's clock and return a
+//! [`Freeze`] operation, which the others integrate with
+//! [`Rga::apply_remote_freeze`]. The state with the greatest timestamp wins,
+//! so replicas agree on it whatever order the operations arrive in.
+//!
+//! While the document is frozen, local edits fail with [`FROZEN`] and change
+//! nothing. Remote operations are still applied: edits made concurrently with
+//! the freeze, before their replica learned of it, must merge for replicas to
+//! converge.
+//!
+//! Clones keep the frozen state; a fork is a new document and starts unfrozen.
+
+use crate::crdt::node::Element;
+use crate::crdt::rga::Rga;
+use crate::crdt::types::LamportTimestamp;
+
+/// Error returned by local edits of a frozen document
+pub const FROZEN: &str = "Document is frozen";
+
+/// A write to a document's frozen state, to send to other replicas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freeze {
+    /// Whether the document is frozen from now on
+    pub frozen: bool,
+    /// When the state was written; the greatest timestamp wins
+    pub timestamp: LamportTimestamp,
+}
+
+#[cfg(all(feature = "std", not(feature = "single-threaded")))]
+mod register {
+    use super::Freeze;
+    use parking_lot::Mutex;
+
+    /// The latest write to a document's frozen state, if any
+    #[derive(Default)]
+    pub(crate) struct FreezeRegister(Mutex<Option<Freeze>>);
+
+    impl FreezeRegister {
+        pub(crate) fn get(&self) -> Option<Freeze> {
+            *self.0.lock()
+        }
+
+        pub(crate) fn set(&self, freeze: Option<Freeze>) {
+            *self.0.lock() = freeze;
+        }
+    }
+}
+
+#[cfg(any(not(feature = "std"), feature = "single-threaded"))]
+mod register {
+    use super::Freeze;
+    use core::cell::Cell;
+
+    /// The latest write to a document's frozen state, if any
+    #[derive(Default)]
+    pub(crate) struct FreezeRegister(Cell<Option<Freeze>>);
+
+    impl FreezeRegister {
+        pub(crate) fn get(&self) -> Option<Freeze> {
+            self.0.get()
+        }
+
+        pub(crate) fn set(&self, freeze: Option<Freeze>) {
+            self.0.set(freeze);
+        }
+    }
+}
+
+pub(crate) use register::FreezeRegister;
+
+impl<T: Element> Rga<T> {
+    /// Freezes the document: local edits fail until it is unfrozen.
+    ///
+    /// # Returns
+    ///
+    /// The operation to send to other replicas
+    pub fn freeze(&self) -> Freeze {
+        self.write_freeze(true)
+    }
+
+    /// Unfreezes the document, allowing local edits again.
+    ///
+    /// # Returns
+    ///
+    /// The operation to send to other replicas
+    pub fn unfreeze(&self) -> Freeze {
+        self.write_freeze(false)
+    }
+
+    fn write_freeze(&self, frozen: bool) -> Freeze {
+        let freeze = Freeze {
+            frozen,
+            timestamp: self.clock.tick(),
+        };
+        self.freeze.set(Some(freeze));
+        freeze
+    }
+
+    /// Integrates a freeze or unfreeze made by another replica.
+    ///
+    /// # Returns
+    ///
+    /// True if it changed the register, false if a later write was already
+    /// integrated
+    pub fn apply_remote_freeze(&self, freeze: Freeze) -> bool {
+        self.clock.update(freeze.timestamp);
+        let newer = self
+            .freeze
+            .get()
+            .is_none_or(|current| current.timestamp < freeze.timestamp);
+        if newer {
+            self.freeze.set(Some(freeze));
+        }
+        newer
+    }
+
+    /// Returns true if local edits are refused
+    pub fn is_frozen(&self) -> bool {
+        self.freeze.get().is_some_and(|freeze| freeze.frozen)
+    }
+
+    /// The latest write to the frozen state, to hand to a replica joining
+    /// later, or `None` if the document was never frozen
+    pub fn freeze_state(&self) -> Option<Freeze> {
+        self.freeze.get()
+    }
+
+    /// Fails with [`FROZEN`] if the document is frozen
+    pub(crate) fn ensure_unfrozen(&self) -> Result<(), &'static str> {
+        if self.is_frozen() {
+            Err(FROZEN)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::rga::RGA;
+
+    #[test]
+    fn test_frozen_documents_refuse_local_edits() {
+        let rga = RGA::new(1);
+        let start = rga.sentinel_start_id();
+        let a = rga.insert_after(start, 'a').unwrap();
+        rga.freeze();
+
+        assert_eq!(rga.insert_after(a, 'b'), Err(FROZEN));
+        assert_eq!(rga.insert_str_after(a, "bc"), Err(FROZEN));
+        assert_eq!(rga.delete(a), Err(FROZEN));
+        assert!(rga.insert_at_carets(&[1], 'b').is_err());
+        assert!(rga.delete_at_carets(&[0]).is_err());
+        assert!(rga.transaction(|txn| txn.delete(a)).is_err());
+        assert_eq!(rga.to_string(), "a");
+
+        // Remote edits still merge
+        let other = rga.fork(2);
+        let b = other.insert_after(a, 'b').unwrap();
+        rga.apply_remote_op(other.get_node(b).unwrap());
+        assert_eq!(rga.to_string(), "ab");
+
+        rga.unfreeze();
+        rga.insert_after(b, 'c').unwrap();
+        assert_eq!(rga.to_string(), "abc");
+    }
+
+    #[test]
+    fn test_latest_freeze_wins() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let freeze = rga1.freeze();
+        // Concurrent with the freeze, and later by replica ID
+        let unfreeze = rga2.unfreeze();
+
+        assert!(rga1.apply_remote_freeze(unfreeze));
+        assert!(!rga2.apply_remote_freeze(freeze));
+        assert!(!rga1.is_frozen());
+        assert!(!rga2.is_frozen());
+        assert_eq!(rga1.freeze_state(), rga2.freeze_state());
+
+        // A freeze made after seeing the unfreeze wins everywhere
+        let refreeze = rga1.freeze();
+        assert!(rga2.apply_remote_freeze(refreeze));
+        assert!(rga2.is_frozen());
+    }
+}
@@ -0,0 +1,540 @@
+//! Garbage collection of tombstones, with remapping of the anchors into them.
+//!
+//! Once every replica has integrated the tombstones below some ID (the
+//! collection horizon, see [`crate::CausalBuffer::set_horizon`]), no operation
+//! will refer to them again and [`Rga::collect_garbage`] may purge them. Text
+//! outside the document can still point at them, though: carets and ranges
+//! hold [`Anchor`]s or [`Range`]s, formatting holds [`Mark`]s. Those holders register with
+//! the document through [`Rga::register_anchor_holder`], and every collection
+//! hands them a [`Remap`] from each purged node to its surviving neighbours
+//! before any reader sees the purged document.
+//!
+//! A purged node was invisible, so the gap it marked is the gap between its
+//! nearest surviving neighbours, and remapping never moves an anchor. A
+//! surviving node whose origin was purged gets the nearest older node before
+//! it as its origin instead, which keeps every origin older than its node and
+//! places the node where it was wherever the document is rebuilt from its
+//! nodes in ID order. Tombstones spilled to disk, and the nodes they refer to,
+//! are not collected.
+//!
+//! Applications that purge by the age of a deletion rather than by horizon
+//! call [`Rga::track_deletions`], collect what was deleted with
+//! [`Rga::take_deletions`] and later purge it with [`Rga::purge_tombstones`].
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::crdt::anchor::{Anchor, Bias};
+use crate::crdt::lsp::AnchoredRange;
+use crate::crdt::markup::Mark;
+use crate::crdt::node::Element;
+use crate::crdt::range::Range;
+use crate::crdt::rga::Rga;
+use crate::crdt::types::UniqueId;
+
+/// Something holding anchors into a document, updated when its nodes are
+/// garbage collected
+pub trait AnchorHolder: Send + Sync {
+    /// Replaces every anchor into a purged node
+    fn remap(&self, remap: &Remap);
+}
+
+/// Where the nodes purged by one garbage collection went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Remap {
+    /// Each purged node with its nearest surviving neighbours, before and after
+    purged: BTreeMap<UniqueId, (UniqueId, UniqueId)>,
+}
+
+impl Remap {
+    /// A remap moving each node to its new ID, for nodes renamed rather than
+    /// purged (see [`Rga::pseudonymize`])
+    pub(crate) fn renamed(renamed: impl IntoIterator<Item = (UniqueId, UniqueId)>) -> Self {
+        Remap {
+            purged: renamed
+                .into_iter()
+                .map(|(id, new_id)| (id, (new_id, new_id)))
+                .collect(),
+        }
+    }
+
+    /// Number of purged nodes
+    pub fn len(&self) -> usize {
+        self.purged.len()
+    }
+
+    /// Returns true if nothing was purged
+    pub fn is_empty(&self) -> bool {
+        self.purged.is_empty()
+    }
+
+    /// Returns true if `id` was purged
+    pub fn is_purged(&self, id: UniqueId) -> bool {
+        self.purged.contains_key(&id)
+    }
+
+    /// `id`, or the nearest node before it that survived
+    pub fn before(&self, id: UniqueId) -> UniqueId {
+        self.purged.get(&id).map_or(id, |&(before, _)| before)
+    }
+
+    /// `id`, or the nearest node after it that survived
+    pub fn after(&self, id: UniqueId) -> UniqueId {
+        self.purged.get(&id).map_or(id, |&(_, after)| after)
+    }
+
+    /// An anchor to the same gap that refers to a surviving node
+    pub fn anchor(&self, anchor: Anchor) -> Anchor {
+        match anchor.bias {
+            Bias::Before => Anchor::before(self.after(anchor.id)),
+            Bias::After => Anchor::after(self.before(anchor.id)),
+        }
+    }
+
+    /// A range over the same gaps that refers to surviving nodes
+    pub fn range(&self, range: AnchoredRange) -> AnchoredRange {
+        AnchoredRange {
+            start: self.anchor(range.start),
+            end: self.anchor(range.end),
+        }
+    }
+
+    /// A range over the same surviving characters, or `None` if it only
+    /// covered purged ones
+    pub fn span(&self, range: Range) -> Option<Range> {
+        // Both ends purged from the same gap: nothing between them survived
+        let emptied = match (self.purged.get(&range.start), self.purged.get(&range.end)) {
+            (Some(start), Some(end)) => start == end && start.0 != start.1,
+            _ => false,
+        };
+        (!emptied).then(|| Range::new(self.after(range.start), self.before(range.end)))
+    }
+
+    /// A mark over the same surviving characters, or `None` if it only
+    /// covered purged ones
+    pub fn mark(&self, mark: &Mark) -> Option<Mark> {
+        self.span(mark.range)
+            .map(|range| range.mark(mark.kind.clone()))
+    }
+}
+
+impl AnchorHolder for Mutex<Vec<Anchor>> {
+    fn remap(&self, remap: &Remap) {
+        for anchor in self.lock().iter_mut() {
+            *anchor = remap.anchor(*anchor);
+        }
+    }
+}
+
+impl AnchorHolder for Mutex<Vec<AnchoredRange>> {
+    fn remap(&self, remap: &Remap) {
+        for range in self.lock().iter_mut() {
+            *range = remap.range(*range);
+        }
+    }
+}
+
+impl AnchorHolder for Mutex<Vec<Range>> {
+    fn remap(&self, remap: &Remap) {
+        let mut ranges = self.lock();
+        *ranges = ranges
+            .iter()
+            .filter_map(|&range| remap.span(range))
+            .collect();
+    }
+}
+
+impl AnchorHolder for RwLock<Vec<Mark>> {
+    fn remap(&self, remap: &Remap) {
+        let mut marks = self.write();
+        *marks = marks.iter().filter_map(|mark| remap.mark(mark)).collect();
+    }
+}
+
+/// Anchor holders registered with a document
+#[derive(Default)]
+pub(crate) struct AnchorHolders(Mutex<Vec<Weak<dyn AnchorHolder>>>);
+
+/// IDs of the nodes deleted since they were last taken, if tracked
+#[derive(Default)]
+pub(crate) struct DeletionLog(Mutex<Option<Vec<UniqueId>>>);
+
+impl DeletionLog {
+    /// Moves the recorded IDs of renamed nodes to their new IDs
+    pub(crate) fn rename(&self, remap: &Remap) {
+        if let Some(deletions) = self.0.lock().as_mut() {
+            for id in deletions.iter_mut() {
+                *id = remap.after(*id);
+            }
+        }
+    }
+}
+
+/// The newest node garbage collected so far, if any
+#[derive(Default)]
+pub(crate) struct Collected(Mutex<Option<UniqueId>>);
+
+impl Clone for Collected {
+    fn clone(&self) -> Self {
+        Collected(Mutex::new(*self.0.lock()))
+    }
+}
+
+impl<T: Element> Rga<T> {
+    /// Registers `holder` to be remapped whenever this document is garbage
+    /// collected.
+    ///
+    /// The document keeps a weak reference, so dropping the holder
+    /// unregisters it.
+    pub fn register_anchor_holder(&self, holder: Arc<dyn AnchorHolder>) {
+        self.anchor_holders.0.lock().push(Arc::downgrade(&holder));
+    }
+
+    /// Purges the tombstones below the collection horizon.
+    ///
+    /// Surviving nodes whose origins were purged get the nearest older
+    /// surviving node before them as origins instead, and every registered
+    /// [`AnchorHolder`] is remapped before the collection becomes visible to
+    /// read transactions.
+    ///
+    /// # Arguments
+    ///
+    /// * `horizon` - Tombstones with lower IDs are purged. Every replica must
+    ///   have integrated them, or their later operations may refer to a purged
+    ///   node
+    ///
+    /// # Returns
+    ///
+    /// Where the purged nodes went
+    pub fn collect_garbage(&self, horizon: UniqueId) -> Remap {
+        self.collect(|id| id < horizon)
+    }
+
+    /// Purges the given tombstones, like [`Rga::collect_garbage`].
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - The tombstones to purge. Every replica must have integrated
+    ///   their deletion. IDs of visible or unknown nodes are ignored
+    ///
+    /// # Returns
+    ///
+    /// Where the purged nodes went
+    pub fn purge_tombstones(&self, ids: impl IntoIterator<Item = UniqueId>) -> Remap {
+        let ids: BTreeSet<UniqueId> = ids.into_iter().collect();
+        self.collect(|id| ids.contains(&id))
+    }
+
+    /// Starts recording the IDs of deleted nodes, local and remote, for
+    /// [`Rga::take_deletions`].
+    pub fn track_deletions(&self) {
+        self.deletion_log.0.lock().get_or_insert_with(Vec::new);
+    }
+
+    /// The IDs of the nodes deleted since the last call, oldest first.
+    ///
+    /// # Returns
+    ///
+    /// The deleted IDs; empty unless [`Rga::track_deletions`] was called
+    pub fn take_deletions(&self) -> Vec<UniqueId> {
+        self.deletion_log
+            .0
+            .lock()
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Starts recording the IDs of new nodes, local and remote, for
+    /// [`Rga::take_insertions`].
+    pub fn track_insertions(&self) {
+        self.nodes.track_insertions();
+    }
+
+    /// The IDs of the nodes inserted since the last call, in the order they
+    /// were inserted.
+    ///
+    /// # Returns
+    ///
+    /// The inserted IDs; empty unless [`Rga::track_insertions`] was called
+    pub fn take_insertions(&self) -> Vec<UniqueId> {
+        self.nodes.take_insertions()
+    }
+
+    /// Returns true if node `id`, which the document lacks, may have been
+    /// garbage collected: it is no newer than a node that was
+    pub(crate) fn may_be_collected(&self, id: UniqueId) -> bool {
+        self.collected.0.lock().is_some_and(|newest| id <= newest)
+    }
+
+    /// Records a deletion if deletions are tracked
+    pub(crate) fn log_deletion(&self, id: UniqueId) {
+        if let Some(deletions) = self.deletion_log.0.lock().as_mut() {
+            deletions.push(id);
+        }
+    }
+
+    /// Purges the in-memory tombstones whose IDs satisfy `purge`
+    fn collect(&self, purge: impl Fn(UniqueId) -> bool) -> Remap {
+        // Spilled tombstones cannot be rewritten, so their origins stay
+        let pinned: BTreeSet<UniqueId> = self
+            .spilled_nodes()
+            .iter()
+            .flat_map(|node| node.origin.into_iter().chain(node.right_origin))
+            .collect();
+
+        let mut nodes = Vec::new();
+        let mut purged = Vec::new();
+        let mut before = self.sentinel_start_id();
+        self.nodes.for_each(|node| {
+            if node.is_deleted && purge(node.id) && !pinned.contains(&node.id) {
+                purged.push((node.id, before));
+            } else {
+                before = node.id;
+                nodes.push((node.id, node.origin, node.right_origin));
+            }
+        });
+        if purged.is_empty() {
+            return Remap::default();
+        }
+
+        // Both lists are in document order: the survivor after a purged node
+        // is the first one after the survivor before it
+        let mut remap = Remap::default();
+        let mut survivors = nodes.iter().map(|&(id, _, _)| id).peekable();
+        let mut gap = None;
+        for &(id, before) in &purged {
+            let neighbours = match gap {
+                Some(neighbours @ (gap_before, _)) if gap_before == before => neighbours,
+                _ => {
+                    while survivors.next_if(|&survivor| survivor != before).is_some() {}
+                    survivors.next();
+                    let after = survivors.peek().copied();
+                    (before, after.unwrap_or(self.sentinel_end_id()))
+                }
+            };
+            gap = Some(neighbours);
+            remap.purged.insert(id, neighbours);
+        }
+
+        self.nodes.batch(|| {
+            for (id, _) in &purged {
+                self.nodes.remove(id);
+            }
+            if let Some(&newest) = purged.iter().map(|(id, _)| id).max() {
+                let mut collected = self.collected.0.lock();
+                *collected = (*collected).max(Some(newest));
+            }
+            // A node's origin is the nearest older node before it, so that is
+            // what replaces a purged one: the nearest surviving neighbour may
+            // be newer than the node, and integrating after it would misplace
+            // the node on replicas replaying by ID
+            let mut older: Vec<UniqueId> = Vec::new();
+            for &(id, origin, right_origin) in &nodes {
+                while older.last().is_some_and(|&previous| previous > id) {
+                    older.pop();
+                }
+                let dangling =
+                    |origin: Option<UniqueId>| origin.is_some_and(|o| remap.is_purged(o));
+                if dangling(origin) || dangling(right_origin) {
+                    let nearest = older.last().copied().unwrap_or(self.sentinel_start_id());
+                    self.nodes.update(&id, |node| {
+                        if dangling(node.origin) {
+                            node.origin = Some(nearest);
+                        }
+                        node.right_origin = node.right_origin.map(|origin| remap.after(origin));
+                    });
+                }
+                older.push(id);
+            }
+            self.remap_anchor_holders(&remap);
+        });
+        remap
+    }
+
+    /// Hands `remap` to every registered [`AnchorHolder`], forgetting the
+    /// dropped ones
+    pub(crate) fn remap_anchor_holders(&self, remap: &Remap) {
+        self.anchor_holders
+            .0
+            .lock()
+            .retain(|holder| match holder.upgrade() {
+                Some(holder) => {
+                    holder.remap(remap);
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::markup::MarkKind;
+    use crate::crdt::metrics::{ApplyOutcome, RejectReason};
+    use crate::crdt::op::Operation;
+    use crate::crdt::rga::RGA;
+
+    #[test]
+    fn test_remaps_anchors_into_collected_tombstones() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "abcd")
+            .unwrap();
+        let e = rga.insert_after(ids[3], 'e').unwrap();
+        rga.delete(ids[1]).unwrap();
+        rga.delete(ids[2]).unwrap();
+        assert_eq!(rga.to_string(), "ade");
+
+        let anchors = Arc::new(Mutex::new(vec![
+            Anchor::before(ids[1]),
+            Anchor::after(ids[2]),
+            Anchor::after(ids[3]),
+        ]));
+        let marks = Arc::new(RwLock::new(vec![
+            Range::new(ids[1], ids[2]).mark(MarkKind::Bold),
+            Range::new(ids[0], ids[2]).mark(MarkKind::Italic),
+        ]));
+        rga.register_anchor_holder(anchors.clone());
+        rga.register_anchor_holder(marks.clone());
+        rga.register_anchor_holder(Arc::new(Mutex::new(Vec::<Anchor>::new())));
+        let before: Vec<usize> = anchors
+            .lock()
+            .iter()
+            .map(|anchor| rga.resolve_anchor(anchor).unwrap())
+            .collect();
+
+        let remap = rga.collect_garbage(e);
+        assert_eq!(remap.len(), 2);
+        assert_eq!(rga.total_node_count(), 5);
+        assert_eq!(rga.to_string(), "ade");
+        assert!(rga.validate().is_ok());
+        assert_eq!(rga.get_node(ids[3]).unwrap().origin, Some(ids[0]));
+
+        let after: Vec<usize> = anchors
+            .lock()
+            .iter()
+            .map(|anchor| rga.resolve_anchor(anchor).unwrap())
+            .collect();
+        assert_eq!(after, before);
+        assert_eq!(
+            *marks.read(),
+            vec![Range::single(ids[0]).mark(MarkKind::Italic)]
+        );
+        assert_eq!(rga.anchor_holders.0.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_purges_tracked_deletions() {
+        let rga = RGA::new(1);
+        rga.track_deletions();
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "abc")
+            .unwrap();
+        rga.delete(ids[0]).unwrap();
+        rga.delete(ids[2]).unwrap();
+        assert_eq!(rga.take_deletions(), vec![ids[0], ids[2]]);
+        assert!(rga.take_deletions().is_empty());
+
+        let remap = rga.purge_tombstones([ids[0], ids[1]]);
+        assert_eq!(remap.len(), 1);
+        assert!(remap.is_purged(ids[0]));
+        assert_eq!(rga.total_node_count(), 4);
+        assert_eq!(rga.to_string(), "b");
+        assert!(rga.validate().is_ok());
+    }
+
+    #[test]
+    fn test_tracks_local_and_remote_insertions() {
+        let rga = RGA::new(1);
+        let a = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        assert!(rga.take_insertions().is_empty());
+
+        rga.track_insertions();
+        let b = rga.insert_after(a, 'b').unwrap();
+        let remote = RGA::new(2);
+        remote.merge(&rga);
+        let c = remote.insert_after(b, 'c').unwrap();
+        rga.merge(&remote);
+        assert_eq!(rga.take_insertions(), vec![b, c]);
+        assert!(rga.take_insertions().is_empty());
+    }
+
+    #[test]
+    fn test_operations_on_collected_nodes_are_rejected() {
+        let lagging = RGA::new(1);
+        let a = lagging
+            .insert_after(lagging.sentinel_start_id(), 'a')
+            .unwrap();
+        let b = lagging.insert_after(a, 'b').unwrap();
+        let rga = RGA::new(2);
+        rga.merge(&lagging);
+        rga.delete(a).unwrap();
+        rga.collect_garbage(b);
+
+        // `a` is gone for good, not merely late
+        let c = lagging.insert_after(a, 'c').unwrap();
+        assert_eq!(
+            rga.apply_remote_op(lagging.get_node(c).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::Collected(a))
+        );
+        assert_eq!(
+            rga.apply(Operation::Delete { id: a }),
+            ApplyOutcome::Rejected(RejectReason::Collected(a))
+        );
+
+        // Nodes newer than anything collected may still arrive
+        let d = lagging.insert_after(c, 'd').unwrap();
+        assert_eq!(
+            rga.apply_remote_op(lagging.get_node(d).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::MissingOrigin(c))
+        );
+        assert_eq!(
+            rga.apply(Operation::Delete { id: c }),
+            ApplyOutcome::Rejected(RejectReason::MissingNode(c))
+        );
+        assert_eq!(rga.sync_metrics().rejected, 4);
+        assert_eq!(rga.to_string(), "b");
+
+        // Copies remember what was collected
+        assert_eq!(
+            rga.clone().apply(Operation::Delete { id: a }),
+            ApplyOutcome::Rejected(RejectReason::Collected(a))
+        );
+    }
+
+    #[test]
+    fn test_collected_documents_rebuild_in_id_order() {
+        // "RQPX": Q is newer than P, so it lands between R and P
+        let rga = RGA::new(1);
+        let r = rga.insert_after(rga.sentinel_start_id(), 'R').unwrap();
+        let p = rga.insert_after(r, 'P').unwrap();
+        let x = rga.insert_after(p, 'X').unwrap();
+        let q = rga.insert_after(r, 'Q').unwrap();
+        assert_eq!(rga.to_string(), "RQPX");
+        rga.delete(p).unwrap();
+        let z = rga.insert_after(x, 'Z').unwrap();
+
+        let remap = rga.collect_garbage(z);
+        assert!(remap.is_purged(p));
+        // Q precedes X but is newer, so X is placed after R instead
+        assert_eq!(rga.get_node(x).unwrap().origin, Some(r));
+        assert!(q > x);
+        assert!(rga.validate().is_ok());
+
+        let mut nodes = rga.all_nodes();
+        nodes.sort();
+        let rebuilt = RGA::new(2);
+        for node in nodes.into_iter().filter(|node| !node.is_sentinel()) {
+            rebuilt.apply_remote_op(node);
+        }
+        assert_eq!(rebuilt.to_string(), "RQXZ");
+        // Collection keeps the index over the surviving nodes
+        assert_eq!(rga.delete_range_at(1, 2), Ok(vec![q, x]));
+        assert_eq!(rga.to_string(), "RZ");
+    }
+}
@@ -0,0 +1,387 @@
+//! Line-oriented access for code editors.
+//!
+//! Editors address text by row and column and convert between those and
+//! character indices on every cursor movement. The document answers both
+//! itself in O(log n): its index over the document order counts visible line
+//! breaks and is kept current across local and remote edits, so
+//! [`RGA::line_range`], [`RGA::position_to_index`] and the line operations
+//! never walk the text.
+//!
+//! A [`LineIndex`] is a copy of the line starts an editor can keep next to
+//! its own buffer, where both conversions are binary searches. Build one with
+//! [`RGA::line_index`], and keep it current with [`LineIndex::insert`] and
+//! [`LineIndex::delete`] instead of rebuilding it after every keystroke.
+//!
+//! Lines are separated by `'\n'`. Rows, columns and indices count characters,
+//! and a document always has at least one (possibly empty) line.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// Start indices of the lines of a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    /// Index of the first character of every line; the first is always 0
+    line_starts: Vec<usize>,
+    /// Number of characters in the text
+    len: usize,
+}
+
+impl LineIndex {
+    /// Indexes the lines of `text`
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        let mut len = 0;
+        for character in text.chars() {
+            len += 1;
+            if character == '\n' {
+                line_starts.push(len);
+            }
+        }
+        LineIndex { line_starts, len }
+    }
+
+    /// Number of lines
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Number of characters in the indexed text
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the indexed text is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Index range of a line's characters, without its line break
+    pub fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(row)?;
+        let end = match self.line_starts.get(row + 1) {
+            Some(&next) => next - 1,
+            None => self.len,
+        };
+        Some(start..end)
+    }
+
+    /// Converts a row and column to a character index.
+    ///
+    /// The column may point just past the line's last character.
+    pub fn to_index(&self, row: usize, column: usize) -> Option<usize> {
+        let range = self.line_range(row)?;
+        (column <= range.len()).then_some(range.start + column)
+    }
+
+    /// Converts a character index (0 ..= length) to a row and column
+    pub fn to_position(&self, index: usize) -> Option<(usize, usize)> {
+        if index > self.len {
+            return None;
+        }
+        // The last line starting at or before the index
+        let row = self.line_starts.partition_point(|&start| start <= index) - 1;
+        Some((row, index - self.line_starts[row]))
+    }
+
+    /// Updates the index for `text` inserted at character index `at`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the index was updated
+    /// * `Err(&str)` - Error message if `at` is past the end; nothing changed
+    pub fn insert(&mut self, at: usize, text: &str) -> Result<(), &'static str> {
+        if at > self.len {
+            return Err("Index out of range");
+        }
+        let inserted = text.chars().count();
+        let row = self.line_starts.partition_point(|&start| start <= at);
+        for start in &mut self.line_starts[row..] {
+            *start += inserted;
+        }
+        let new_starts = text
+            .chars()
+            .enumerate()
+            .filter(|&(_, character)| character == '\n')
+            .map(|(offset, _)| at + offset + 1);
+        self.line_starts.splice(row..row, new_starts);
+        self.len += inserted;
+        Ok(())
+    }
+
+    /// Updates the index for `deleted` removed from the characters in `range`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the index was updated
+    /// * `Err(&str)` - Error message if `range` reaches past the end, or
+    ///   `deleted` does not match its length or line breaks; nothing changed
+    pub fn delete(&mut self, range: Range<usize>, deleted: &str) -> Result<(), &'static str> {
+        if range.start > range.end || range.end > self.len {
+            return Err("Index out of range");
+        }
+        let removed = range.len();
+        // Lines starting inside the range lost their line break
+        let first = self
+            .line_starts
+            .partition_point(|&start| start <= range.start);
+        let last = self
+            .line_starts
+            .partition_point(|&start| start <= range.end);
+        let breaks = deleted.chars().filter(|&c| c == '\n').count();
+        if deleted.chars().count() != removed || last - first != breaks {
+            return Err("Deleted text does not match the index");
+        }
+        self.line_starts.drain(first..last);
+        for start in &mut self.line_starts[first..] {
+            *start -= removed;
+        }
+        self.len -= removed;
+        Ok(())
+    }
+}
+
+impl RGA {
+    /// The visible document as a string
+    fn visible_text(&self) -> String {
+        self.visible_nodes()
+            .iter()
+            .map(|node| node.character)
+            .collect()
+    }
+
+    /// Builds a line index of the visible document.
+    pub fn line_index(&self) -> LineIndex {
+        LineIndex::new(&self.visible_text())
+    }
+
+    /// Number of lines, one more than the visible line breaks
+    pub fn line_count(&self) -> usize {
+        self.nodes.line_count()
+    }
+
+    /// Index range of a line's characters, without its line break
+    pub fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        self.nodes.line_range(row)
+    }
+
+    /// Converts a row and column to a character index.
+    ///
+    /// The column may point just past the line's last character.
+    pub fn position_to_index(&self, row: usize, column: usize) -> Option<usize> {
+        let range = self.line_range(row)?;
+        (column <= range.len()).then_some(range.start + column)
+    }
+
+    /// Converts a character index (0 ..= length) to a row and column
+    pub fn index_to_position(&self, index: usize) -> Option<(usize, usize)> {
+        self.nodes.to_position(index)
+    }
+
+    /// Returns the text of a line, without its line break.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - The line number, starting at 0
+    ///
+    /// # Returns
+    ///
+    /// * `Some(String)` - The line's text
+    /// * `None` - If the document has fewer lines
+    pub fn line_at(&self, row: usize) -> Option<String> {
+        let ids = self.visible_ids(self.line_range(row)?)?;
+        ids.into_iter()
+            .map(|id| Some(self.get_node(id)?.character))
+            .collect()
+    }
+
+    /// Inserts a line so that it becomes line `row`.
+    ///
+    /// Inserting at the line count appends a line to the document.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Line number of the new line (0 ..= line count)
+    /// * `text` - The line's text, without a line break
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, line break included
+    /// * `Err(&str)` - Error message if `row` is out of range
+    pub fn insert_line(&self, row: usize, text: &str) -> Result<Vec<UniqueId>, &'static str> {
+        let line_count = self.line_count();
+        let (at, line) = if row < line_count {
+            let line = self.line_range(row).ok_or("Row out of range")?;
+            (line.start, String::from(text) + "\n")
+        } else if row == line_count {
+            (self.visible_node_count(), String::from("\n") + text)
+        } else {
+            return Err("Row out of range");
+        };
+        self.insert_str_after(self.insertion_point(at)?, &line)
+    }
+
+    /// Deletes a line together with its line break.
+    ///
+    /// The last line takes the line break before it instead, so no empty line
+    /// is left behind.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - The line number, starting at 0
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document order
+    /// * `Err(&str)` - Error message if `row` is out of range
+    pub fn delete_line(&self, row: usize) -> Result<Vec<UniqueId>, &'static str> {
+        let line = self.line_range(row).ok_or("Row out of range")?;
+        let range = if row + 1 < self.line_count() {
+            line.start..line.end + 1
+        } else {
+            line.start.saturating_sub(1)..line.end
+        };
+        self.delete_range_at(range.start, range.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn document(text: &str) -> RGA {
+        let rga = RGA::new(1);
+        rga.insert_str_after(rga.sentinel_start_id(), text).unwrap();
+        rga
+    }
+
+    #[test]
+    fn test_position_conversions() {
+        let index = LineIndex::new("ab\n\ncd");
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.line_range(0), Some(0..2));
+        assert_eq!(index.line_range(1), Some(3..3));
+        assert_eq!(index.line_range(2), Some(4..6));
+        assert_eq!(index.line_range(3), None);
+
+        assert_eq!(index.to_index(0, 2), Some(2));
+        assert_eq!(index.to_index(2, 1), Some(5));
+        assert_eq!(index.to_index(1, 1), None);
+        assert_eq!(index.to_position(0), Some((0, 0)));
+        assert_eq!(index.to_position(2), Some((0, 2)));
+        assert_eq!(index.to_position(3), Some((1, 0)));
+        assert_eq!(index.to_position(6), Some((2, 2)));
+        assert_eq!(index.to_position(7), None);
+
+        let empty = LineIndex::new("");
+        assert_eq!(empty.line_count(), 1);
+        assert_eq!(empty.to_position(0), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_incremental_updates_match_rebuilding() {
+        let mut text = String::from("fn main() {\n}\n");
+        let mut index = LineIndex::new(&text);
+
+        let edits: [(usize, &str); 3] = [(12, "    println!();\n"), (0, "\n\n"), (3, "x")];
+        for (at, inserted) in edits {
+            let byte = text.char_indices().nth(at).map_or(text.len(), |(b, _)| b);
+            text.insert_str(byte, inserted);
+            index.insert(at, inserted).unwrap();
+            assert_eq!(index, LineIndex::new(&text));
+        }
+
+        for range in [0..3, 10..20, 5..6] {
+            let deleted: String = text.chars().skip(range.start).take(range.len()).collect();
+            text = text
+                .chars()
+                .take(range.start)
+                .chain(text.chars().skip(range.end))
+                .collect();
+            index.delete(range, &deleted).unwrap();
+            assert_eq!(index, LineIndex::new(&text));
+        }
+    }
+
+    #[test]
+    fn test_updates_out_of_range_are_rejected() {
+        let mut index = LineIndex::new("ab\ncd");
+        let before = index.clone();
+        assert!(index.insert(5, "x").is_ok());
+        assert!(index.insert(7, "x").is_err());
+        assert!(index.delete(5..8, "cdx").is_err());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 3..1;
+        assert!(index.delete(reversed, "").is_err());
+        // The deleted text must be what the range held
+        assert!(index.delete(0..2, "a").is_err());
+        assert!(index.delete(0..2, "a\n").is_err());
+        assert!(index.delete(1..3, "b").is_err());
+        index.delete(5..6, "x").unwrap();
+        assert_eq!(index, before);
+    }
+
+    #[test]
+    fn test_line_operations() {
+        let rga = RGA::new(1);
+        assert_eq!(rga.line_at(0), Some(String::new()));
+        rga.insert_line(0, "one").unwrap();
+        rga.insert_line(2, "two").unwrap();
+        let ids = rga.insert_line(3, "three").unwrap();
+        assert_eq!(ids.len(), 6);
+        assert_eq!(rga.to_string(), "one\n\ntwo\nthree");
+        assert!(rga.insert_line(5, "x").is_err());
+
+        assert_eq!(rga.line_at(2), Some("two".to_string()));
+        assert_eq!(rga.line_at(4), None);
+
+        rga.delete_line(1).unwrap();
+        assert_eq!(rga.to_string(), "one\ntwo\nthree");
+        rga.delete_line(2).unwrap();
+        assert_eq!(rga.to_string(), "one\ntwo");
+        rga.delete_line(0).unwrap();
+        assert_eq!(rga.to_string(), "two");
+        assert!(rga.delete_line(1).is_err());
+
+        let single = document("only");
+        single.delete_line(0).unwrap();
+        assert_eq!(single.to_string(), "");
+        assert_eq!(single.line_index().line_count(), 1);
+    }
+
+    #[test]
+    fn test_document_keeps_its_lines_current() {
+        let rga = document("ab\n\ncd");
+        let remote = rga.fork(2);
+        assert_eq!(rga.line_count(), 3);
+        assert_eq!(rga.line_range(2), Some(4..6));
+        assert_eq!(rga.position_to_index(2, 1), Some(5));
+        assert_eq!(rga.position_to_index(1, 1), None);
+        assert_eq!(rga.index_to_position(3), Some((1, 0)));
+        assert_eq!(rga.index_to_position(7), None);
+
+        // A remote edit splits the first line
+        let ids = remote.insert_line(1, "x").unwrap();
+        for id in ids {
+            rga.apply_remote_op(remote.get_node(id).unwrap());
+        }
+        rga.delete_at(1).unwrap();
+        assert_eq!(rga.to_string(), "a\nx\n\ncd");
+        for row in 0..rga.line_count() {
+            assert_eq!(rga.line_range(row), rga.line_index().line_range(row));
+        }
+        for index in 0..=rga.visible_node_count() {
+            assert_eq!(
+                rga.index_to_position(index),
+                rga.line_index().to_position(index)
+            );
+        }
+        assert_eq!(rga.line_at(1), Some("x".to_string()));
+    }
+}
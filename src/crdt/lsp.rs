@@ -0,0 +1,284 @@
+//! Language Server Protocol position mapping.
+//!
+//! LSP addresses text by line and character, where the character offset
+//! counts UTF-16 code units rather than characters: an emoji outside the Basic
+//! Multilingual Plane counts twice. These helpers convert between LSP
+//! positions and visible indices, and pin LSP ranges to the document with
+//! [`Anchor`]s.
+//!
+//! A language server answers for the version of the text it was sent, while
+//! remote edits keep arriving. Anchoring its results against a copy of that
+//! version and resolving them against the live document moves diagnostics
+//! onto the text they were reported for; see [`RGA::remap_lsp_ranges`].
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crdt::anchor::{Anchor, Bias};
+use crate::crdt::lines::LineIndex;
+use crate::crdt::rga::RGA;
+
+/// A position as LSP expresses it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct LspPosition {
+    /// Zero-based line number
+    pub line: u32,
+    /// Offset within the line in UTF-16 code units
+    pub character: u32,
+}
+
+impl LspPosition {
+    pub fn new(line: u32, character: u32) -> Self {
+        LspPosition { line, character }
+    }
+}
+
+/// A range between two LSP positions, end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+impl LspRange {
+    pub fn new(start: LspPosition, end: LspPosition) -> Self {
+        LspRange { start, end }
+    }
+}
+
+/// A range pinned to the characters it covers.
+///
+/// Text typed directly before or after the range stays outside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnchoredRange {
+    pub start: Anchor,
+    pub end: Anchor,
+}
+
+/// The visible characters and their line index
+fn visible_lines(rga: &RGA) -> (Vec<char>, LineIndex) {
+    let chars: Vec<char> = rga
+        .visible_nodes()
+        .iter()
+        .map(|node| node.character)
+        .collect();
+    let text: String = chars.iter().collect();
+    let lines = LineIndex::new(&text);
+    (chars, lines)
+}
+
+fn to_index(
+    chars: &[char],
+    lines: &LineIndex,
+    position: LspPosition,
+) -> Result<usize, &'static str> {
+    let line = lines
+        .line_range(position.line as usize)
+        .ok_or("Line out of range")?;
+
+    // Offsets past the end of the line mean the end of the line, and an offset
+    // inside a surrogate pair means the character it belongs to
+    let mut units = 0;
+    for (index, character) in chars[line.clone()].iter().enumerate() {
+        units += character.len_utf16();
+        if units > position.character as usize {
+            return Ok(line.start + index);
+        }
+    }
+    Ok(line.end)
+}
+
+fn to_position(
+    chars: &[char],
+    lines: &LineIndex,
+    index: usize,
+) -> Result<LspPosition, &'static str> {
+    let (row, column) = lines.to_position(index).ok_or("Index out of range")?;
+    let line_start = index - column;
+    let units: usize = chars[line_start..index]
+        .iter()
+        .map(|character| character.len_utf16())
+        .sum();
+    Ok(LspPosition::new(row as u32, units as u32))
+}
+
+impl RGA {
+    /// Converts an LSP position to a visible index.
+    ///
+    /// As LSP specifies, an offset past the end of its line means the end of
+    /// the line.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - Line and UTF-16 offset
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The visible index of the position
+    /// * `Err(&str)` - Error message if the line does not exist
+    pub fn lsp_position_to_index(&self, position: LspPosition) -> Result<usize, &'static str> {
+        let (chars, lines) = visible_lines(self);
+        to_index(&chars, &lines, position)
+    }
+
+    /// Converts a visible index to an LSP position.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Visible index (0 ..= length)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(LspPosition)` - The line and UTF-16 offset of the index
+    /// * `Err(&str)` - Error message if `index` is past the end of the document
+    pub fn index_to_lsp_position(&self, index: usize) -> Result<LspPosition, &'static str> {
+        let (chars, lines) = visible_lines(self);
+        to_position(&chars, &lines, index)
+    }
+
+    /// Pins an LSP range to the characters it currently covers.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The range to anchor
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AnchoredRange)` - Anchors that follow the range through later edits
+    /// * `Err(&str)` - Error message if a line does not exist
+    pub fn anchor_lsp_range(&self, range: LspRange) -> Result<AnchoredRange, &'static str> {
+        let (chars, lines) = visible_lines(self);
+        let start = to_index(&chars, &lines, range.start)?;
+        let end = to_index(&chars, &lines, range.end)?.max(start);
+        Ok(AnchoredRange {
+            start: self.anchor_at(start, Bias::Before)?,
+            end: self.anchor_at(end, Bias::After)?,
+        })
+    }
+
+    /// Resolves an anchored range to LSP positions in the current text.
+    ///
+    /// A range whose text was deleted collapses to an empty range.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - A range anchored in this document or a copy of it
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(LspRange)` - Where the range is now
+    /// * `Err(&str)` - Error message if an anchored node is unknown
+    pub fn resolve_lsp_range(&self, range: &AnchoredRange) -> Result<LspRange, &'static str> {
+        let start = self.resolve_anchor(&range.start)?;
+        let end = self.resolve_anchor(&range.end)?.max(start);
+        let (chars, lines) = visible_lines(self);
+        Ok(LspRange::new(
+            to_position(&chars, &lines, start)?,
+            to_position(&chars, &lines, end)?,
+        ))
+    }
+
+    /// Maps ranges reported against `snapshot`, an earlier copy of this
+    /// document, onto the current text.
+    ///
+    /// Use it for diagnostics that a language server computed while remote
+    /// edits were being applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The copy of the document the ranges refer to
+    /// * `ranges` - Ranges in `snapshot`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<LspRange>)` - The ranges in this document, in the same order
+    /// * `Err(&str)` - Error message if a range does not fit `snapshot`, or
+    ///   `snapshot` has text this document has never seen
+    pub fn remap_lsp_ranges(
+        &self,
+        snapshot: &RGA,
+        ranges: &[LspRange],
+    ) -> Result<Vec<LspRange>, &'static str> {
+        ranges
+            .iter()
+            .map(|&range| self.resolve_lsp_range(&snapshot.anchor_lsp_range(range)?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::types::UniqueId;
+
+    fn document(text: &str) -> RGA {
+        let rga = RGA::new(1);
+        rga.insert_str_after(rga.sentinel_start_id(), text).unwrap();
+        rga
+    }
+
+    #[test]
+    fn test_utf16_positions() {
+        // The emoji is one character but two UTF-16 code units
+        let rga = document("a\u{1F600}b\nc");
+        assert_eq!(
+            rga.index_to_lsp_position(2).unwrap(),
+            LspPosition::new(0, 3)
+        );
+        assert_eq!(
+            rga.index_to_lsp_position(4).unwrap(),
+            LspPosition::new(1, 0)
+        );
+        assert_eq!(
+            rga.lsp_position_to_index(LspPosition::new(0, 3)).unwrap(),
+            2
+        );
+        assert_eq!(
+            rga.lsp_position_to_index(LspPosition::new(1, 1)).unwrap(),
+            5
+        );
+        assert!(rga.index_to_lsp_position(6).is_err());
+
+        // Inside the surrogate pair, and past the end of the line
+        assert_eq!(
+            rga.lsp_position_to_index(LspPosition::new(0, 2)).unwrap(),
+            1
+        );
+        assert_eq!(
+            rga.lsp_position_to_index(LspPosition::new(0, 99)).unwrap(),
+            3
+        );
+        assert!(rga.lsp_position_to_index(LspPosition::new(2, 0)).is_err());
+    }
+
+    #[test]
+    fn test_diagnostics_follow_remote_edits() {
+        let live = document("// todo\nlet \u{1F600} = unused;\n");
+        let snapshot = live.clone();
+        // `unused` as a language server would report it against the snapshot
+        let unused = LspRange::new(LspPosition::new(1, 9), LspPosition::new(1, 15));
+
+        // A remote replica deletes the first line before the diagnostic arrives
+        let ids: Vec<UniqueId> = live.visible_nodes().iter().map(|node| node.id).collect();
+        for &id in &ids[..8] {
+            live.delete(id).unwrap();
+        }
+
+        let remapped = live.remap_lsp_ranges(&snapshot, &[unused]).unwrap();
+        assert_eq!(
+            remapped,
+            [LspRange::new(
+                LspPosition::new(0, 9),
+                LspPosition::new(0, 15)
+            )]
+        );
+
+        // Deleting the diagnosed text collapses the range
+        let anchored = snapshot.anchor_lsp_range(unused).unwrap();
+        for &id in &ids[16..22] {
+            live.delete(id).unwrap();
+        }
+        let collapsed = live.resolve_lsp_range(&anchored).unwrap();
+        assert_eq!(collapsed.start, collapsed.end);
+    }
+}
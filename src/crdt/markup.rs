@@ -0,0 +1,467 @@
+//! Markdown and HTML export of formatted documents, and Markdown import.
+//!
+//! Inline formatting is kept beside the character sequence as [`Mark`]s,
+//! each spanning the characters from one node to another. Because marks name
+//! node IDs rather than indices, they stay attached to their text while other
+//! replicas edit around it, and characters inserted inside a span pick up its
+//! formatting.
+//!
+//! Block structure is read from the characters themselves: a line starting
+//! with `#` to `######` and a space is a heading and a line starting with
+//! `- ` is a list item, as in Markdown. Exporting to Markdown therefore
+//! leaves block syntax as typed and only writes inline marks, while HTML
+//! export turns both into elements.
+//!
+//! The importer understands the inline syntax the exporter writes: `**bold**`,
+//! `*italic*`, `` `code` ``, `[links](url)` and backslash escapes.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::ops::{self, RangeInclusive};
+
+use crate::crdt::range::Range;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// The kind of inline formatting a mark applies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkKind {
+    Bold,
+    Italic,
+    Code,
+    /// A hyperlink to the given URL
+    Link(String),
+}
+
+/// Inline formatting over a [`Range`] of characters.
+///
+/// A mark whose nodes are unknown to a document is ignored when exporting
+/// it. Deleted characters inside the range are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub kind: MarkKind,
+    /// The formatted characters
+    pub range: Range,
+}
+
+/// A visible character with the indices of the marks covering it
+type Formatted = (char, Vec<usize>);
+
+#[derive(Debug, Clone, Copy)]
+enum Syntax {
+    Markdown,
+    Html,
+}
+
+impl Syntax {
+    fn open(self, kind: &MarkKind, out: &mut String) {
+        match (self, kind) {
+            (Syntax::Markdown, MarkKind::Bold) => out.push_str("**"),
+            (Syntax::Markdown, MarkKind::Italic) => out.push('*'),
+            (Syntax::Markdown, MarkKind::Code) => out.push('`'),
+            (Syntax::Markdown, MarkKind::Link(_)) => out.push('['),
+            (Syntax::Html, MarkKind::Bold) => out.push_str("<strong>"),
+            (Syntax::Html, MarkKind::Italic) => out.push_str("<em>"),
+            (Syntax::Html, MarkKind::Code) => out.push_str("<code>"),
+            (Syntax::Html, MarkKind::Link(url)) => {
+                out.push_str("<a href=\"");
+                url.chars().for_each(|c| escape_html(c, out));
+                out.push_str("\">");
+            }
+        }
+    }
+
+    fn close(self, kind: &MarkKind, out: &mut String) {
+        match (self, kind) {
+            (Syntax::Markdown, MarkKind::Link(url)) => {
+                out.push_str("](");
+                out.push_str(url);
+                out.push(')');
+            }
+            (Syntax::Markdown, _) => self.open(kind, out),
+            (Syntax::Html, MarkKind::Bold) => out.push_str("</strong>"),
+            (Syntax::Html, MarkKind::Italic) => out.push_str("</em>"),
+            (Syntax::Html, MarkKind::Code) => out.push_str("</code>"),
+            (Syntax::Html, MarkKind::Link(_)) => out.push_str("</a>"),
+        }
+    }
+
+    fn text(self, character: char, in_code: bool, out: &mut String) {
+        match self {
+            Syntax::Markdown if !in_code && matches!(character, '\\' | '*' | '`' | '[' | ']') => {
+                out.push('\\');
+                out.push(character);
+            }
+            Syntax::Markdown => out.push(character),
+            Syntax::Html => escape_html(character, out),
+        }
+    }
+}
+
+fn escape_html(character: char, out: &mut String) {
+    match character {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        _ => out.push(character),
+    }
+}
+
+/// Writes characters with their marks, keeping the written spans nested
+fn render_inline(chars: &[Formatted], marks: &[Mark], syntax: Syntax, out: &mut String) {
+    let mut open: Vec<usize> = Vec::new();
+    for (character, active) in chars {
+        // Close back to the outermost mark that ends here, then open new ones
+        let keep = open
+            .iter()
+            .position(|mark| !active.contains(mark))
+            .unwrap_or(open.len());
+        for &mark in open[keep..].iter().rev() {
+            syntax.close(&marks[mark].kind, out);
+        }
+        open.truncate(keep);
+        for &mark in active {
+            if !open.contains(&mark) {
+                syntax.open(&marks[mark].kind, out);
+                open.push(mark);
+            }
+        }
+        let in_code = open.iter().any(|&mark| marks[mark].kind == MarkKind::Code);
+        syntax.text(*character, in_code, out);
+    }
+    for &mark in open.iter().rev() {
+        syntax.close(&marks[mark].kind, out);
+    }
+}
+
+/// Writes one line as an HTML block, returning whether it was a list item
+fn render_html_block(line: &[Formatted], marks: &[Mark], in_list: bool, out: &mut String) -> bool {
+    let prefix: String = line.iter().take(7).map(|(c, _)| *c).collect();
+    let level = prefix.chars().take_while(|&c| c == '#').count();
+    let is_item = prefix.starts_with("- ");
+
+    if in_list && !is_item {
+        out.push_str("</ul>\n");
+    }
+    if line.is_empty() {
+        return false;
+    }
+    if (1..=6).contains(&level) && prefix[level..].starts_with(' ') {
+        let tag = char::from(b'0' + level as u8);
+        out.push_str("<h");
+        out.push(tag);
+        out.push('>');
+        render_inline(&line[level + 1..], marks, Syntax::Html, out);
+        out.push_str("</h");
+        out.push(tag);
+        out.push_str(">\n");
+    } else if is_item {
+        if !in_list {
+            out.push_str("<ul>\n");
+        }
+        out.push_str("<li>");
+        render_inline(&line[2..], marks, Syntax::Html, out);
+        out.push_str("</li>\n");
+    } else {
+        out.push_str("<p>");
+        render_inline(line, marks, Syntax::Html, out);
+        out.push_str("</p>\n");
+    }
+    is_item
+}
+
+/// Index of the next unescaped `delimiter` at or after `from`
+fn find_unescaped(input: &[char], from: usize, delimiter: &[char]) -> Option<usize> {
+    let mut i = from;
+    while i + delimiter.len() <= input.len() {
+        if input[i] == '\\' {
+            i += 2;
+        } else if input[i..].starts_with(delimiter) {
+            return Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Splits inline Markdown into plain characters and the spans formatting them
+fn parse_inline(markdown: &str) -> (Vec<char>, Vec<(ops::Range<usize>, MarkKind)>) {
+    let input: Vec<char> = markdown.chars().collect();
+    let mut text = Vec::with_capacity(input.len());
+    let mut spans = Vec::new();
+    let mut bold: Option<usize> = None;
+    let mut italic: Option<usize> = None;
+    // Open links as (start in text, index of the closing `]`, URL, index past `)`)
+    let mut links: Vec<(usize, usize, String, usize)> = Vec::new();
+
+    let opens = |i: usize, width: usize| {
+        input.get(i + width).is_some_and(|c| !c.is_whitespace())
+            && find_unescaped(&input, i + width + 1, &input[i..i + width]).is_some()
+    };
+    let closes = |i: usize| i > 0 && !input[i - 1].is_whitespace();
+
+    let mut i = 0;
+    while i < input.len() {
+        if links.last().is_some_and(|link| link.1 == i) {
+            let (start, _, url, after) = links.pop().unwrap_or_default();
+            spans.push((start..text.len(), MarkKind::Link(url)));
+            i = after;
+            continue;
+        }
+        match input[i] {
+            '\\' if i + 1 < input.len() => {
+                text.push(input[i + 1]);
+                i += 2;
+            }
+            '`' => match find_unescaped(&input, i + 1, &['`']) {
+                Some(end) => {
+                    let start = text.len();
+                    text.extend_from_slice(&input[i + 1..end]);
+                    spans.push((start..text.len(), MarkKind::Code));
+                    i = end + 1;
+                }
+                None => {
+                    text.push('`');
+                    i += 1;
+                }
+            },
+            '*' if input.get(i + 1) == Some(&'*') => {
+                match bold {
+                    Some(start) if closes(i) => {
+                        spans.push((start..text.len(), MarkKind::Bold));
+                        bold = None;
+                    }
+                    None if opens(i, 2) => bold = Some(text.len()),
+                    _ => text.extend_from_slice(&['*', '*']),
+                }
+                i += 2;
+            }
+            '*' => {
+                match italic {
+                    Some(start) if closes(i) => {
+                        spans.push((start..text.len(), MarkKind::Italic));
+                        italic = None;
+                    }
+                    None if opens(i, 1) => italic = Some(text.len()),
+                    _ => text.push('*'),
+                }
+                i += 1;
+            }
+            '[' => {
+                let link = find_unescaped(&input, i + 1, &[']', '(']).and_then(|close| {
+                    let paren = find_unescaped(&input, close + 2, &[')'])?;
+                    let url = input[close + 2..paren].iter().collect();
+                    Some((text.len(), close, url, paren + 1))
+                });
+                match link {
+                    Some(link) => links.push(link),
+                    None => text.push('['),
+                }
+                i += 1;
+            }
+            character => {
+                text.push(character);
+                i += 1;
+            }
+        }
+    }
+
+    spans.retain(|(range, _)| !range.is_empty());
+    // Outer spans first, so exported delimiters nest the same way
+    spans.sort_by_key(|(range, _)| (range.start, Reverse(range.end)));
+    (text, spans)
+}
+
+impl RGA {
+    /// The visible characters with the marks covering each of them
+    fn formatted_chars(&self, marks: &[Mark]) -> Vec<Formatted> {
+        let nodes: Vec<_> = self
+            .all_nodes()
+            .into_iter()
+            .filter(|node| !node.is_sentinel())
+            .collect();
+        let positions: BTreeMap<UniqueId, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(position, node)| (node.id, position))
+            .collect();
+        let spans: Vec<Option<RangeInclusive<usize>>> = marks
+            .iter()
+            .map(|mark| Some(*positions.get(&mark.range.start)?..=*positions.get(&mark.range.end)?))
+            .collect();
+
+        nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.is_deleted)
+            .map(|(position, node)| {
+                let active = spans
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, span)| span.as_ref().is_some_and(|s| s.contains(&position)))
+                    .map(|(mark, _)| mark)
+                    .collect();
+                (node.character, active)
+            })
+            .collect()
+    }
+
+    /// Exports the visible document as Markdown.
+    ///
+    /// # Arguments
+    ///
+    /// * `marks` - Inline formatting to write
+    ///
+    /// # Returns
+    ///
+    /// * The Markdown text, with Markdown syntax in the content escaped
+    pub fn to_markdown(&self, marks: &[Mark]) -> String {
+        let mut out = String::new();
+        render_inline(
+            &self.formatted_chars(marks),
+            marks,
+            Syntax::Markdown,
+            &mut out,
+        );
+        out
+    }
+
+    /// Exports the visible document as an HTML fragment.
+    ///
+    /// Every line becomes a heading, a list item or a paragraph; empty lines
+    /// only separate blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `marks` - Inline formatting to write
+    ///
+    /// # Returns
+    ///
+    /// * The HTML fragment, with the content escaped
+    pub fn to_html(&self, marks: &[Mark]) -> String {
+        let chars = self.formatted_chars(marks);
+        let mut out = String::new();
+        let mut in_list = false;
+        for line in chars.split(|(character, _)| *character == '\n') {
+            in_list = render_html_block(line, marks, in_list, &mut out);
+        }
+        if in_list {
+            out.push_str("</ul>\n");
+        }
+        out
+    }
+
+    /// Inserts Markdown as text plus the marks describing its formatting.
+    ///
+    /// The text is normalized according to [`RGA::normalization`] and
+    /// inserted as a contiguous run. Inline syntax is turned into marks; block
+    /// syntax such as headings is kept as text.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_id` - The UniqueId of the node to insert after
+    /// * `markdown` - The Markdown to import
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Mark>)` - The marks of the imported text, outermost first
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    pub fn import_markdown(
+        &self,
+        after_id: UniqueId,
+        markdown: &str,
+    ) -> Result<Vec<Mark>, &'static str> {
+        // Normalize before parsing so every parsed character is inserted as is
+        let (text, spans) = parse_inline(&self.normalization.apply(markdown));
+
+        self.ensure_unfrozen()?;
+        if !self.holds(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        let text: String = text.into_iter().collect();
+        // Large imports are built in bulk, as a single run
+        let ids = self.insert_run(after_id, &text);
+
+        Ok(spans
+            .into_iter()
+            .map(|(range, kind)| Range::new(ids[range.start], ids[range.end - 1]).mark(kind))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const DOCUMENT: &str = "# Notes\n\nSome **bold *and* italic** text, `a*b` and [a link](https://example.com).\n- first\n- second";
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let rga = RGA::new(1);
+        let marks = rga
+            .import_markdown(rga.sentinel_start_id(), DOCUMENT)
+            .unwrap();
+        assert_eq!(marks.len(), 4);
+        assert_eq!(
+            rga.to_string(),
+            "# Notes\n\nSome bold and italic text, a*b and a link.\n- first\n- second"
+        );
+        assert_eq!(rga.to_markdown(&marks), DOCUMENT);
+    }
+
+    #[test]
+    fn test_html_export() {
+        let rga = RGA::new(1);
+        let marks = rga
+            .import_markdown(rga.sentinel_start_id(), DOCUMENT)
+            .unwrap();
+        assert_eq!(
+            rga.to_html(&marks),
+            "<h1>Notes</h1>\n\
+             <p>Some <strong>bold <em>and</em> italic</strong> text, <code>a*b</code> and \
+             <a href=\"https://example.com\">a link</a>.</p>\n\
+             <ul>\n<li>first</li>\n<li>second</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn test_plain_syntax_is_escaped() {
+        let rga = RGA::new(1);
+        let marks = rga
+            .import_markdown(rga.sentinel_start_id(), "2 * 3 = [6] \\*x\\* <b>")
+            .unwrap();
+        assert!(marks.is_empty());
+        assert_eq!(rga.to_string(), "2 * 3 = [6] *x* <b>");
+        assert_eq!(rga.to_markdown(&marks), "2 \\* 3 = \\[6\\] \\*x\\* <b>");
+        assert_eq!(rga.to_html(&marks), "<p>2 * 3 = [6] *x* &lt;b&gt;</p>\n");
+    }
+
+    #[test]
+    fn test_marks_follow_edits() {
+        let rga = RGA::new(1);
+        let marks = rga
+            .import_markdown(rga.sentinel_start_id(), "**bold**")
+            .unwrap();
+        let ids: Vec<UniqueId> = rga.visible_nodes().iter().map(|node| node.id).collect();
+
+        // Deleting the mark's first character leaves the rest formatted
+        rga.delete(ids[0]).unwrap();
+        assert_eq!(rga.to_markdown(&marks), "**old**");
+        rga.delete(ids[1]).unwrap();
+        rga.delete(ids[2]).unwrap();
+        rga.delete(ids[3]).unwrap();
+        assert_eq!(rga.to_markdown(&marks), "");
+
+        // Marks naming unknown nodes are ignored
+        let other = RGA::new(2);
+        other
+            .insert_str_after(other.sentinel_start_id(), "x")
+            .unwrap();
+        assert_eq!(other.to_markdown(&marks), "x".to_string());
+    }
+}
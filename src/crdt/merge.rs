@@ -0,0 +1,103 @@
+//! State-based merging of whole documents.
+//!
+//! Operation-based sync sends each edit as it is made. State-based sync
+//! instead exchanges whole documents, as after a long time offline or when
+//! loading a backup: [`RGA::merge`] takes in every node of another replica's
+//! document that the local one lacks, and every tombstone for a node it still
+//! shows, in one pass over the other document. [`RGA::merge_snapshot`] does
+//! the same with a document received as a snapshot.
+//!
+//! Merging is idempotent and commutative like every other way of exchanging
+//! nodes: merging twice changes nothing more, and two replicas that merge
+//! each other's state hold the same text.
+
+use crate::crdt::rga::RGA;
+
+impl RGA {
+    /// Merges every node and tombstone of `other` into the document.
+    ///
+    /// Nodes are taken in `other`'s document order, so each one's origin is
+    /// held before it arrives, and readers of a [`RGA::read_txn`] see the
+    /// whole merge at once. The local clock moves past every timestamp in
+    /// `other`, merged or not.
+    ///
+    /// # Returns
+    ///
+    /// The number of nodes inserted or deleted
+    pub fn merge(&self, other: &RGA) -> usize {
+        if core::ptr::eq(self, other) {
+            return 0;
+        }
+        // Copied out first: applying while `other` is walked could deadlock
+        // against a merge the other way round
+        let nodes = other.all_nodes();
+        let mut merged = 0;
+        self.nodes.batch(|| {
+            for node in nodes.into_iter().filter(|node| !node.is_sentinel()) {
+                // Before the node is accepted or rejected, so local IDs never
+                // collide with one `other` handed out
+                self.update_clock(node.id.timestamp());
+                if self.apply_remote_op(node).changed() {
+                    merged += 1;
+                }
+            }
+        });
+        merged
+    }
+
+    /// Merges a document received as a snapshot, see [`RGA::merge`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of nodes inserted or deleted
+    /// * `Err(&str)` - Error message if the snapshot is malformed; nothing
+    ///   is merged
+    pub fn merge_snapshot(&self, snapshot: &str) -> Result<usize, &'static str> {
+        let other = RGA::from_snapshot(self.replica_id(), snapshot)?;
+        Ok(self.merge(&other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::provenance::ProvenanceFilter;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_merges_whole_documents() {
+        let first = RGA::new(1);
+        let second = RGA::new(2);
+        let hello = first
+            .insert_str_after(first.sentinel_start_id(), "hello")
+            .unwrap();
+        assert_eq!(second.merge(&first), 5);
+        second.insert_str_after(hello[4], " world").unwrap();
+        first.delete(hello[0]).unwrap();
+        first.insert_after(hello[0], 'H').unwrap();
+
+        // Each side takes what it lacks, whichever merges first
+        assert_eq!(first.merge(&second), 6);
+        assert_eq!(second.merge_snapshot(&first.to_snapshot()), Ok(2));
+        assert_eq!(first.to_string(), "Hello world");
+        assert_eq!(second.to_snapshot(), first.to_snapshot());
+        assert_eq!(first.merge(&second), 0);
+        assert_eq!(first.merge(&first), 0);
+        assert!(second.clock.current_counter() >= first.clock.current_counter());
+        assert!(second.merge_snapshot("not a snapshot").is_err());
+        assert!(second.validate().is_ok());
+    }
+
+    #[test]
+    fn test_clock_moves_past_rejected_nodes() {
+        let first = RGA::new(1);
+        let ids = first
+            .insert_str_after(first.sentinel_start_id(), "abc")
+            .unwrap();
+        let second = RGA::new(2);
+        second.restrict_writers(ProvenanceFilter::mute([1]));
+        assert_eq!(second.merge(&first), 0);
+        assert_eq!(second.sync_metrics().rejected, 3);
+        assert_eq!(second.current_clock(), ids[2].counter());
+    }
+}
@@ -0,0 +1,269 @@
+//! Replicated message logs on top of the sequence CRDT.
+//!
+//! Nothing in an [`RGA`] is specific to text: it orders elements the way
+//! concurrent replicas inserted them. A [`MessageLog`] keeps one node per
+//! message, holding [`MESSAGE_CHAR`] in place of a character, and the
+//! messages themselves beside the document, keyed by the node's ID. Chat
+//! histories, comment threads and activity feeds get the CRDT's ordering:
+//! messages posted concurrently land in the same order everywhere, a message
+//! deleted by one replica disappears from all, and no replica ever renumbers
+//! the others' messages.
+//!
+//! Messages are posted with [`RGA::append`], so a growing log never walks its
+//! history. An edit replaces a message's content and is stamped with the
+//! replica's Lamport clock; of concurrent edits, the one with the greatest
+//! stamp wins on every replica. A deletion tombstones the node and drops the
+//! content, and edits arriving for a deleted message are discarded.
+//!
+//! Every operation is returned as a [`MessageOp`] to send to the other
+//! replicas, which pass it to [`MessageLog::apply`]. Like document
+//! operations, a message must reach a replica before its edits, its deletion
+//! and the messages posted after it by the same replica.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// The character a message's node holds, U+FFFC OBJECT REPLACEMENT CHARACTER
+pub const MESSAGE_CHAR: char = '\u{FFFC}';
+
+/// An operation on a message log, to replicate
+#[derive(Debug, Clone)]
+pub enum MessageOp<M> {
+    /// A message was posted at the end of the log
+    Post { node: Node, message: M },
+    /// The message of node `id` was replaced, at Lamport time `stamp`
+    Edit {
+        id: UniqueId,
+        stamp: UniqueId,
+        message: M,
+    },
+    /// A message was deleted; `node` is its tombstone
+    Delete { node: Node },
+}
+
+/// A message in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message<'a, M> {
+    /// The ID of the message's node
+    pub id: UniqueId,
+    /// The replica that posted the message
+    pub author: ReplicaId,
+    /// The current content
+    pub message: &'a M,
+    /// Whether the message was edited since it was posted
+    pub edited: bool,
+}
+
+/// A message's content and the stamp of the operation that set it
+#[derive(Debug, Clone)]
+struct Entry<M> {
+    message: M,
+    stamp: UniqueId,
+}
+
+/// An ordered log of messages of type `M`, replicated through an [`RGA`].
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::MessageLog;
+///
+/// let mut alice = MessageLog::new(1);
+/// let mut bob = MessageLog::new(2);
+/// let hello = alice.post("hello").unwrap();
+/// bob.apply(hello.clone());
+/// let reply = bob.post("hi!").unwrap();
+/// alice.apply(reply);
+///
+/// let texts: Vec<_> = alice.messages().iter().map(|m| *m.message).collect();
+/// assert_eq!(texts, ["hello", "hi!"]);
+/// ```
+pub struct MessageLog<M> {
+    rga: RGA,
+    entries: BTreeMap<UniqueId, Entry<M>>,
+}
+
+impl<M: Clone> MessageLog<M> {
+    /// Creates an empty log for replica `replica_id`.
+    pub fn new(replica_id: ReplicaId) -> Self {
+        MessageLog {
+            rga: RGA::new(replica_id),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// The document ordering the messages
+    pub fn rga(&self) -> &RGA {
+        &self.rga
+    }
+
+    /// Posts a message at the end of the log.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(MessageOp)` - The post, to send to the other replicas
+    /// * `Err(&str)` - Error message if the log is frozen
+    pub fn post(&mut self, message: M) -> Result<MessageOp<M>, &'static str> {
+        let id = self.rga.append(MESSAGE_CHAR)?;
+        let node = self.rga.get_node(id).ok_or("Posted message not found")?;
+        self.set(id, id, message.clone());
+        Ok(MessageOp::Post { node, message })
+    }
+
+    /// Replaces the content of message `id`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(MessageOp)` - The edit, to send to the other replicas
+    /// * `Err(&str)` - Error message if there is no such message or the log
+    ///   is frozen
+    pub fn edit(&mut self, id: UniqueId, message: M) -> Result<MessageOp<M>, &'static str> {
+        self.rga.ensure_unfrozen()?;
+        if !self.entries.contains_key(&id) {
+            return Err("Message not found");
+        }
+        let stamp = self.rga.new_local_id();
+        self.set(id, stamp, message.clone());
+        Ok(MessageOp::Edit { id, stamp, message })
+    }
+
+    /// Deletes message `id`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(MessageOp)` - The deletion, to send to the other replicas
+    /// * `Err(&str)` - Error message if there is no such message or the log
+    ///   is frozen
+    pub fn delete(&mut self, id: UniqueId) -> Result<MessageOp<M>, &'static str> {
+        if !self.entries.contains_key(&id) {
+            return Err("Message not found");
+        }
+        self.rga.delete(id)?;
+        self.entries.remove(&id);
+        let node = self.rga.get_node(id).ok_or("Deleted message not found")?;
+        Ok(MessageOp::Delete { node })
+    }
+
+    /// Applies an operation received from another replica.
+    pub fn apply(&mut self, op: MessageOp<M>) {
+        match op {
+            MessageOp::Post { node, message } => {
+                let id = node.id;
+                self.rga.apply_remote_op(node);
+                self.set(id, id, message);
+            }
+            MessageOp::Edit { id, stamp, message } => {
+                self.rga.update_clock(stamp.timestamp());
+                self.set(id, stamp, message);
+            }
+            MessageOp::Delete { node } => {
+                let id = node.id;
+                self.rga.apply_remote_op(node);
+                self.entries.remove(&id);
+            }
+        }
+    }
+
+    /// Sets the content of message `id` unless a later stamp already did, or
+    /// the message was deleted
+    fn set(&mut self, id: UniqueId, stamp: UniqueId, message: M) {
+        if self.rga.get_node(id).is_none_or(|node| node.is_deleted) {
+            return;
+        }
+        match self.entries.get_mut(&id) {
+            Some(entry) if entry.stamp >= stamp => {}
+            Some(entry) => *entry = Entry { message, stamp },
+            None => {
+                self.entries.insert(id, Entry { message, stamp });
+            }
+        }
+    }
+
+    /// The content of message `id`, if it exists
+    pub fn get(&self, id: UniqueId) -> Option<&M> {
+        self.entries.get(&id).map(|entry| &entry.message)
+    }
+
+    /// The messages, in log order
+    pub fn messages(&self) -> Vec<Message<'_, M>> {
+        self.rga
+            .visible_nodes()
+            .into_iter()
+            .filter_map(|node| {
+                let entry = self.entries.get(&node.id)?;
+                Some(Message {
+                    id: node.id,
+                    author: node.id.replica_id(),
+                    message: &entry.message,
+                    edited: entry.stamp != node.id,
+                })
+            })
+            .collect()
+    }
+
+    /// Number of messages in the log
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the log holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(log: &MessageLog<&'static str>) -> Vec<&'static str> {
+        log.messages().iter().map(|m| *m.message).collect()
+    }
+
+    #[test]
+    fn test_message_logs_converge() {
+        let mut alice = MessageLog::new(1);
+        let mut bob = MessageLog::new(2);
+        let first = alice.post("first").unwrap();
+        bob.apply(first.clone());
+        let MessageOp::Post { node, .. } = &first else {
+            unreachable!()
+        };
+        let first_id = node.id;
+
+        // Concurrent posts, then concurrent edits of the same message
+        let a = alice.post("from alice").unwrap();
+        let b = bob.post("from bob").unwrap();
+        let edit_a = alice.edit(first_id, "first (alice)").unwrap();
+        let edit_b = bob.edit(first_id, "first (bob)").unwrap();
+        alice.apply(b);
+        alice.apply(edit_b);
+        bob.apply(a);
+        bob.apply(edit_a);
+        assert_eq!(texts(&alice), texts(&bob));
+        assert_eq!(texts(&alice).len(), 3);
+        assert_eq!(alice.messages()[0].message, bob.messages()[0].message);
+        assert!(alice.messages()[0].edited);
+        assert_eq!(alice.messages()[0].author, 1);
+
+        // An edit that arrives after the deletion is dropped
+        let late = alice.edit(first_id, "too late").unwrap();
+        let deletion = bob.delete(first_id).unwrap();
+        alice.apply(deletion);
+        bob.apply(late);
+        assert_eq!(texts(&alice), texts(&bob));
+        assert_eq!(alice.len(), 2);
+        assert_eq!(bob.get(first_id), None);
+        assert_eq!(bob.edit(first_id, "again").err(), Some("Message not found"));
+
+        // Posting appends after tombstones too
+        let last = alice.post("last").unwrap();
+        bob.apply(last);
+        assert_eq!(texts(&bob).last(), Some(&"last"));
+        assert_eq!(bob.rga().visible_node_count(), 3);
+    }
+}
@@ -0,0 +1,216 @@
+//! Counters of how remote operations were handled.
+//!
+//! Every RGA counts the remote operations it receives by outcome, so sync
+//! efficiency can be monitored: a high share of duplicates means peers resend
+//! more than they need to, and a growing number of buffered or rejected
+//! operations points at a delivery bug. The counters only ever grow; compare
+//! two [`SyncMetrics`] readings to measure an interval.
+
+use core::fmt;
+
+use crate::crdt::node::Element;
+use crate::crdt::rga::Rga;
+use crate::crdt::types::{Counter, ReplicaId, UniqueId};
+
+/// What became of one remote operation, as counted in [`SyncMetrics`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    /// A node the document lacked was inserted
+    Applied,
+    /// The document already held the node, deleted if the copy was
+    Duplicate,
+    /// A node the document held was deleted by the copy
+    TombstoneMerge,
+    /// Held back by a [`crate::CausalBuffer`] until the given node arrives
+    Buffered(UniqueId),
+    /// Refused for the given reason; the document is unchanged
+    Rejected(RejectReason),
+}
+
+/// Why a remote operation was refused, see [`ApplyOutcome::Rejected`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// An insertion after the given node, which the document lacks. Deliver
+    /// through a [`crate::CausalBuffer`] to wait for it instead
+    MissingOrigin(UniqueId),
+    /// A deletion of the given node, which the document lacks
+    MissingNode(UniqueId),
+    /// The given node it depends on may have been garbage collected, see
+    /// [`crate::ResyncRequired`]
+    Collected(UniqueId),
+    /// The operation would delete a sentinel
+    Sentinel,
+    /// The given replica may not edit the document, see
+    /// [`Rga::restrict_writers`]
+    Forbidden(ReplicaId),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::MissingOrigin(origin) => {
+                write!(f, "origin of insertion {origin:?} not found")
+            }
+            RejectReason::MissingNode(id) => write!(f, "node to delete {id:?} not found"),
+            RejectReason::Collected(id) => {
+                write!(f, "{id:?} may have been garbage collected")
+            }
+            RejectReason::Sentinel => f.write_str("sentinel nodes cannot be changed"),
+            RejectReason::Forbidden(replica) => {
+                write!(f, "replica {replica} may not edit the document")
+            }
+        }
+    }
+}
+
+impl ApplyOutcome {
+    /// Returns true if the operation changed the document
+    pub fn changed(&self) -> bool {
+        matches!(self, ApplyOutcome::Applied | ApplyOutcome::TombstoneMerge)
+    }
+}
+
+/// The live counters kept by an RGA
+pub(crate) struct OpCounters {
+    applied: Counter,
+    deduplicated: Counter,
+    buffered: Counter,
+    rejected: Counter,
+}
+
+impl OpCounters {
+    pub(crate) fn new() -> Self {
+        OpCounters {
+            applied: Counter::new(0),
+            deduplicated: Counter::new(0),
+            buffered: Counter::new(0),
+            rejected: Counter::new(0),
+        }
+    }
+}
+
+/// A reading of an RGA's operation counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncMetrics {
+    /// Remote operations that changed the document
+    pub applied: u64,
+    /// Remote operations the document already held
+    pub deduplicated: u64,
+    /// Operations held back by a [`crate::CausalBuffer`] until the node they
+    /// depend on arrived
+    pub buffered: u64,
+    /// Operations that could not be applied, such as an insertion after a
+    /// node that was garbage collected
+    pub rejected: u64,
+}
+
+impl SyncMetrics {
+    /// Number of operations received, whatever became of them.
+    ///
+    /// A buffered operation is counted again once it is released.
+    pub fn received(&self) -> u64 {
+        self.applied + self.deduplicated + self.rejected
+    }
+
+    /// Counts accumulated since the `earlier` reading
+    pub fn since(&self, earlier: &SyncMetrics) -> SyncMetrics {
+        SyncMetrics {
+            applied: self.applied.saturating_sub(earlier.applied),
+            deduplicated: self.deduplicated.saturating_sub(earlier.deduplicated),
+            buffered: self.buffered.saturating_sub(earlier.buffered),
+            rejected: self.rejected.saturating_sub(earlier.rejected),
+        }
+    }
+}
+
+impl core::ops::Add for SyncMetrics {
+    type Output = SyncMetrics;
+
+    fn add(self, other: SyncMetrics) -> SyncMetrics {
+        SyncMetrics {
+            applied: self.applied + other.applied,
+            deduplicated: self.deduplicated + other.deduplicated,
+            buffered: self.buffered + other.buffered,
+            rejected: self.rejected + other.rejected,
+        }
+    }
+}
+
+impl<T: Element> Rga<T> {
+    /// Reads the counters of remote operations handled by this replica.
+    ///
+    /// Local edits are not counted. Forks, clones and documents loaded from
+    /// a snapshot start from zero.
+    ///
+    /// # Returns
+    ///
+    /// The number of remote operations applied, deduplicated, buffered and
+    /// rejected so far
+    pub fn sync_metrics(&self) -> SyncMetrics {
+        SyncMetrics {
+            applied: self.counters.applied.load(),
+            deduplicated: self.counters.deduplicated.load(),
+            buffered: self.counters.buffered.load(),
+            rejected: self.counters.rejected.load(),
+        }
+    }
+
+    pub(crate) fn record_applied(&self) {
+        self.counters.applied.fetch_add(1);
+    }
+
+    pub(crate) fn record_deduplicated(&self) {
+        self.counters.deduplicated.fetch_add(1);
+    }
+
+    pub(crate) fn record_buffered(&self) {
+        self.counters.buffered.fetch_add(1);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.counters.rejected.fetch_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::CausalBuffer;
+    use crate::crdt::rga::RGA;
+    use crate::crdt::snapshot::LogEntry;
+    use crate::crdt::types::UniqueId;
+
+    #[test]
+    fn test_remote_operations_are_counted_by_outcome() {
+        let source = RGA::new(1);
+        let a_id = source
+            .insert_after(source.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = source.insert_after(a_id, 'b').unwrap();
+
+        let target = RGA::new(2);
+        let mut buffer = CausalBuffer::new();
+        buffer.deliver(&target, source.get_node(b_id).unwrap());
+        buffer.deliver(&target, source.get_node(a_id).unwrap());
+        target.apply_remote_op(source.get_node(a_id).unwrap());
+        let missing = UniqueId::new(9, 3);
+        assert!(
+            target
+                .apply_log_entry(&LogEntry::Delete { id: missing })
+                .is_err()
+        );
+
+        let metrics = target.sync_metrics();
+        assert_eq!(
+            metrics,
+            SyncMetrics {
+                applied: 2,
+                deduplicated: 1,
+                buffered: 1,
+                rejected: 1,
+            }
+        );
+        assert_eq!(metrics.received(), 4);
+        assert_eq!(source.sync_metrics(), SyncMetrics::default());
+    }
+}
@@ -3,11 +3,95 @@
 //! This module contains the RGA (Replicated Growable Array) CRDT implementation
 //! and all its supporting types and structures.
 
+pub mod analytics;
+pub mod anchor;
+pub mod anomaly;
+#[cfg(feature = "std")]
+pub mod audit;
+pub mod awareness;
+pub mod builder;
+pub mod carets;
+pub mod causal;
+pub mod checksum;
+pub mod fork;
+pub mod fragment;
+pub mod freeze;
+#[cfg(feature = "std")]
+pub mod gc;
+pub mod lines;
+pub mod lsp;
+pub mod markup;
+pub mod merge;
+pub mod messages;
+pub mod metrics;
 pub mod node;
+pub mod normalize;
+pub mod op;
+#[cfg(feature = "std")]
+pub mod oplog;
+pub(crate) mod order;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod provenance;
+pub mod range;
+pub(crate) mod replicas;
 pub mod rga;
+#[cfg(feature = "std")]
+pub mod scrub;
+pub mod sequence;
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod spill;
+pub mod store;
+pub mod structure;
+pub mod template;
+pub mod txn;
 pub mod types;
+pub mod validate;
+pub mod vec_sequence;
+pub mod words;
 
 // Re-export the main public API
-pub use node::{Node, SENTINEL_END_CHAR, SENTINEL_START_CHAR};
-pub use rga::RGA;
-pub use types::{LamportClock, LamportTimestamp, ReplicaId, UniqueId};
+pub use analytics::{Contribution, InterleavingConflict, MergeRegion};
+pub use anchor::{Anchor, Bias};
+pub use anomaly::{ClockAnomaly, ClockMonitor, DEFAULT_MAX_CLOCK_SKEW};
+#[cfg(feature = "std")]
+pub use audit::{AuditChain, AuditRecord, AuditReport, Digest};
+pub use awareness::{Awareness, AwarenessUpdate};
+pub use builder::RgaBuilder;
+pub use causal::{CausalBuffer, ResyncRequired};
+pub use checksum::{ChecksumMismatch, ChecksumMonitor, Checksums, RangeChecksum, RangeMismatch};
+pub use fork::ForkDivergence;
+pub use fragment::Fragment;
+pub use freeze::{FROZEN, Freeze};
+#[cfg(feature = "std")]
+pub use gc::{AnchorHolder, Remap};
+pub use lines::LineIndex;
+pub use lsp::{AnchoredRange, LspPosition, LspRange};
+pub use markup::{Mark, MarkKind};
+pub use messages::{MESSAGE_CHAR, Message, MessageLog, MessageOp};
+pub use metrics::{ApplyOutcome, RejectReason, SyncMetrics};
+pub use node::{Element, Node, SENTINEL_END_CHAR, SENTINEL_START_CHAR};
+pub use normalize::Normalization;
+pub use op::Operation;
+#[cfg(feature = "std")]
+pub use oplog::{HistoryPolicy, SegmentConfig, SegmentedLog};
+#[cfg(feature = "profiling")]
+pub use profile::{HotPath, PathStats, Profile};
+pub use provenance::ProvenanceFilter;
+pub use range::Range;
+pub use rga::{RGA, Rga};
+#[cfg(feature = "std")]
+pub use scrub::pseudonymize_log;
+pub use sequence::SequenceCrdt;
+pub use snapshot::{LogEntry, coalesce_deletes, encode_op_log, parse_op_log};
+#[cfg(feature = "std")]
+pub use spill::SpillStats;
+pub use structure::StructureFormat;
+pub use template::Template;
+pub use txn::{Commit, ReadTxn, Transaction};
+pub use types::{
+    Clock, HybridClock, LamportClock, LamportTimestamp, ReplicaId, Reservation, UniqueId,
+};
+pub use vec_sequence::VecSequence;
+pub use words::Word;
@@ -1,9 +1,14 @@
 //! Node definition and related constants for the RGA CRDT.
 //!
-//! This module contains the Node struct which represents individual characters
-//! in the RGA, along with sentinel constants used to mark document boundaries.
+//! This module contains the Node struct which represents individual elements
+//! in the RGA, along with sentinel constants used to mark document boundaries
+//! and the [`Element`] trait for the types an RGA can hold.
 
-use crate::crdt::types::UniqueId;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::types::{LamportTimestamp, ReplicaId, UniqueId};
 
 /// Special sentinel characters that mark the beginning and end of the document.
 /// These are fixed points of reference for all replicas.
@@ -13,12 +18,124 @@ use crate::crdt::types::UniqueId;
 pub const SENTINEL_START_CHAR: char = '\u{2388}'; // Symbol for "begin"
 pub const SENTINEL_END_CHAR: char = '\u{2389}'; // Symbol for "end"
 
-/// Represents a single character within the RGA.
+/// The ID of the start sentinel, smaller than every other ID
+pub(crate) const SENTINEL_START_ID: UniqueId = UniqueId(LamportTimestamp {
+    counter: 0,
+    replica_id: 0,
+    sequence: 0,
+});
+
+/// The ID of the end sentinel, greater than every other ID
+pub(crate) const SENTINEL_END_ID: UniqueId = UniqueId(LamportTimestamp {
+    counter: u64::MAX,
+    replica_id: u64::MAX,
+    sequence: 0,
+});
+
+/// A type of item an [`Rga`](crate::Rga) can hold.
+///
+/// Text documents hold `char`s; bytes, tokens or application structs work
+/// the same way. The sentinels marking the ends of the document need some
+/// content, which is never shown: sentinels are told apart by their IDs.
+///
+/// ```
+/// use crdt_rga::{Element, Rga};
+///
+/// #[derive(Debug, Clone, PartialEq)]
+/// struct Task {
+///     title: String,
+/// }
+///
+/// impl Element for Task {
+///     fn sentinel_start() -> Self {
+///         Task { title: String::new() }
+///     }
+///
+///     fn sentinel_end() -> Self {
+///         Task { title: String::new() }
+///     }
+/// }
+///
+/// let list: Rga<Task> = Rga::new(1);
+/// let first = list.append(Task { title: "write".into() }).unwrap();
+/// list.insert_after(first, Task { title: "review".into() }).unwrap();
+/// assert_eq!(list.to_vec()[1].title, "review");
+/// ```
+pub trait Element: Clone + fmt::Debug + Send + Sync + 'static {
+    /// The content of the start sentinel
+    fn sentinel_start() -> Self;
+
+    /// The content of the end sentinel
+    fn sentinel_end() -> Self;
+
+    /// Returns true if this element ends a line. The document counts its
+    /// visible line breaks to find lines by row; only `'\n'` is one
+    fn is_line_break(&self) -> bool {
+        false
+    }
+}
+
+impl Element for char {
+    fn sentinel_start() -> Self {
+        SENTINEL_START_CHAR
+    }
+
+    fn sentinel_end() -> Self {
+        SENTINEL_END_CHAR
+    }
+
+    fn is_line_break(&self) -> bool {
+        *self == '\n'
+    }
+}
+
+impl Element for String {
+    fn sentinel_start() -> Self {
+        String::new()
+    }
+
+    fn sentinel_end() -> Self {
+        String::new()
+    }
+}
+
+impl<T: Clone + fmt::Debug + Send + Sync + 'static> Element for Vec<T> {
+    fn sentinel_start() -> Self {
+        Vec::new()
+    }
+
+    fn sentinel_end() -> Self {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_element_for_integers {
+    ($($integer:ty),*) => {
+        $(
+            impl Element for $integer {
+                fn sentinel_start() -> Self {
+                    0
+                }
+
+                fn sentinel_end() -> Self {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+impl_element_for_integers!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// Represents a single element, by default a character, within the RGA.
 ///
 /// Each node contains:
-/// - A unique identifier that determines its position in the total order
-/// - The character content
+/// - A unique identifier that orders it among nodes inserted at the same spot
+/// - The element content
 /// - A deletion flag that acts as a tombstone for logical deletion
+/// - The origin: the node it was inserted after, if known
+/// - The right origin: the node that followed the origin at insertion time, if known
+/// - The author, if the character was copied from text another replica wrote
 ///
 /// # Tombstone Deletion
 ///
@@ -26,58 +143,93 @@ pub const SENTINEL_END_CHAR: char = '\u{2389}'; // Symbol for "end"
 /// `is_deleted` to true. This ensures that the structure remains consistent across
 /// replicas and allows for proper handling of concurrent operations.
 #[derive(Debug, Clone)]
-pub struct Node {
-    /// Unique identifier that determines this node's position in the sequence
+pub struct Node<T = char> {
+    /// Unique identifier; among nodes inserted concurrently after the same
+    /// origin, greater IDs come first
     pub id: UniqueId,
-    /// The character content of this node
-    pub character: char,
+    /// The content of this node: its character in a text document
+    pub character: T,
     /// Whether this node has been logically deleted (tombstone)
     pub is_deleted: bool,
+    /// The node this one was inserted after (`None` for sentinels)
+    pub origin: Option<UniqueId>,
+    /// The node that followed `origin` when this one was inserted.
+    ///
+    /// Together with `origin` it places the insertion in the insertion tree.
+    /// `None` for sentinels and for nodes received without it.
+    pub right_origin: Option<UniqueId>,
+    /// The replica that wrote the character, when it differs from the one
+    /// that inserted it: pasted text keeps its original author.
+    pub author: Option<ReplicaId>,
 }
 
-impl Node {
+impl<T> Node<T> {
     /// Creates a new node with the given ID and character.
     /// The node is initially not deleted.
-    pub fn new(id: UniqueId, character: char) -> Self {
+    pub fn new(id: UniqueId, character: T) -> Self {
         Node {
             id,
             character,
             is_deleted: false,
+            origin: None,
+            right_origin: None,
+            author: None,
         }
     }
 
-    /// Creates a new deleted node (tombstone) with the given ID and character.
-    pub fn new_deleted(id: UniqueId, character: char) -> Self {
+    /// Creates a new node inserted after the given origin node.
+    pub fn with_origin(id: UniqueId, character: T, origin: UniqueId) -> Self {
         Node {
-            id,
-            character,
-            is_deleted: true,
+            origin: Some(origin),
+            ..Node::new(id, character)
         }
     }
 
-    /// Creates the sentinel start node.
-    /// This node always has the smallest possible UniqueId to ensure it appears first.
-    pub fn sentinel_start() -> Self {
+    /// Creates a new node inserted between `origin` and `right_origin`, the
+    /// node that followed it at the time.
+    pub fn with_origins(
+        id: UniqueId,
+        character: T,
+        origin: UniqueId,
+        right_origin: Option<UniqueId>,
+    ) -> Self {
         Node {
-            id: UniqueId::new(0, 0),
-            character: SENTINEL_START_CHAR,
-            is_deleted: false,
+            right_origin,
+            ..Node::with_origin(id, character, origin)
         }
     }
 
-    /// Creates the sentinel end node.
-    /// This node always has the largest possible UniqueId to ensure it appears last.
-    pub fn sentinel_end() -> Self {
+    /// Creates a new deleted node (tombstone) with the given ID and character.
+    pub fn new_deleted(id: UniqueId, character: T) -> Self {
         Node {
-            id: UniqueId::new(u64::MAX, u64::MAX),
-            character: SENTINEL_END_CHAR,
-            is_deleted: false,
+            id,
+            character,
+            is_deleted: true,
+            origin: None,
+            right_origin: None,
+            author: None,
         }
     }
 
+    /// The node this one was inserted after, if known
+    pub fn origin(&self) -> Option<UniqueId> {
+        self.origin
+    }
+
+    /// The node that followed the origin when this one was inserted, if known
+    pub fn right_origin(&self) -> Option<UniqueId> {
+        self.right_origin
+    }
+
+    /// The replica that wrote the character: its author if it was copied,
+    /// otherwise the replica that inserted it
+    pub fn author(&self) -> ReplicaId {
+        self.author.unwrap_or(self.id.replica_id())
+    }
+
     /// Returns true if this node is a sentinel (start or end).
     pub fn is_sentinel(&self) -> bool {
-        self.character == SENTINEL_START_CHAR || self.character == SENTINEL_END_CHAR
+        self.id == SENTINEL_START_ID || self.id == SENTINEL_END_ID
     }
 
     /// Returns true if this node is visible (not deleted and not a sentinel).
@@ -103,22 +255,55 @@ impl Node {
     }
 }
 
-impl PartialEq for Node {
+impl<T: Element> Node<T> {
+    /// Creates the sentinel start node.
+    /// This node always has the smallest possible UniqueId to ensure it appears first.
+    pub fn sentinel_start() -> Self {
+        Node {
+            id: SENTINEL_START_ID,
+            character: T::sentinel_start(),
+            is_deleted: false,
+            origin: None,
+            right_origin: None,
+            author: None,
+        }
+    }
+
+    /// Creates the sentinel end node.
+    /// This node always has the largest possible UniqueId and always stays last.
+    pub fn sentinel_end() -> Self {
+        Node {
+            id: SENTINEL_END_ID,
+            character: T::sentinel_end(),
+            is_deleted: false,
+            origin: None,
+            right_origin: None,
+            author: None,
+        }
+    }
+
+    /// Returns true if this node is a visible line break
+    pub(crate) fn is_line_break(&self) -> bool {
+        self.is_visible() && self.character.is_line_break()
+    }
+}
+
+impl<T> PartialEq for Node<T> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
     }
 }
 
-impl Eq for Node {}
+impl<T> Eq for Node<T> {}
 
-impl PartialOrd for Node {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl<T> PartialOrd for Node<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for Node {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+impl<T> Ord for Node<T> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.id.cmp(&other.id)
     }
 }
@@ -136,6 +321,17 @@ mod tests {
         assert_eq!(node.id, id);
         assert_eq!(node.character, 'A');
         assert!(!node.is_deleted);
+        assert_eq!(node.origin, None);
+
+        let origin = UniqueId::new(0, 0);
+        let child = Node::with_origin(UniqueId::new(2, 1), 'B', origin);
+        assert_eq!(child.origin(), Some(origin));
+        assert_eq!(child.right_origin(), None);
+
+        let right = UniqueId::new(1, 1);
+        let between = Node::with_origins(UniqueId::new(3, 1), 'C', origin, Some(right));
+        assert_eq!(between.origin(), Some(origin));
+        assert_eq!(between.right_origin(), Some(right));
     }
 
     #[test]
@@ -149,8 +345,8 @@ mod tests {
 
     #[test]
     fn test_sentinel_nodes() {
-        let start = Node::sentinel_start();
-        let end = Node::sentinel_end();
+        let start: Node = Node::sentinel_start();
+        let end: Node = Node::sentinel_end();
 
         assert!(start.is_sentinel());
         assert!(end.is_sentinel());
@@ -167,7 +363,7 @@ mod tests {
     fn test_node_visibility() {
         let id = UniqueId::new(1, 1);
         let mut node = Node::new(id, 'A');
-        let start = Node::sentinel_start();
+        let start: Node = Node::sentinel_start();
 
         assert!(node.is_visible());
         assert!(!start.is_visible()); // Sentinel not visible
@@ -0,0 +1,197 @@
+//! Compact operations for replication.
+//!
+//! [`RGA::apply_remote_op`] takes whole [`Node`] snapshots, so a deletion is
+//! the deleted node sent again with its tombstone set, repeating the
+//! character and origins every receiver already holds. An [`Operation`]
+//! carries only what the receiver lacks: an insertion with its place in the
+//! document, or the bare ID of a deleted node.
+//!
+//! [`RGA::insert_op`] and [`RGA::delete_op`] edit the document like
+//! [`RGA::insert_after`] and [`RGA::delete`] and return the operation to send;
+//! [`RGA::apply`] applies one received. An operation whose dependency has not
+//! arrived yet, the origin of an insertion or the node a deletion removes, is
+//! refused with [`ApplyOutcome::Rejected`] rather than guessed at, so the
+//! network layer can hold it back and try again.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::crdt::metrics::{ApplyOutcome, RejectReason};
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// A single edit, as exchanged between replicas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Character `ch` was inserted as node `id` after `origin`, with
+    /// `right_origin` following it at the time
+    Insert {
+        id: UniqueId,
+        origin: UniqueId,
+        right_origin: Option<UniqueId>,
+        ch: char,
+    },
+    /// Node `id` was deleted
+    Delete { id: UniqueId },
+}
+
+impl Operation {
+    /// The node the operation inserts or deletes
+    pub fn id(&self) -> UniqueId {
+        match *self {
+            Operation::Insert { id, .. } | Operation::Delete { id } => id,
+        }
+    }
+
+    /// The operations that recreate `node`: its insertion, then its deletion
+    /// if it is a tombstone. Sentinels need none.
+    pub fn from_node(node: &Node) -> Vec<Operation> {
+        let Some(origin) = node.origin.filter(|_| !node.is_sentinel()) else {
+            return Vec::new();
+        };
+        let insert = Operation::Insert {
+            id: node.id,
+            origin,
+            right_origin: node.right_origin,
+            ch: node.character,
+        };
+        if node.is_deleted {
+            vec![insert, Operation::Delete { id: node.id }]
+        } else {
+            vec![insert]
+        }
+    }
+}
+
+impl RGA {
+    /// Inserts a character after `after_id`, as [`RGA::insert_after`] does.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Operation)` - The insertion, to send to the other replicas
+    /// * `Err(&str)` - Error message if the operation fails
+    pub fn insert_op(
+        &self,
+        after_id: UniqueId,
+        character: char,
+    ) -> Result<Operation, &'static str> {
+        let id = self.insert_after(after_id, character)?;
+        let node = self.get_node(id).ok_or("Inserted node not found")?;
+        Ok(Operation::Insert {
+            id,
+            origin: after_id,
+            right_origin: node.right_origin,
+            ch: node.character,
+        })
+    }
+
+    /// Deletes node `id`, as [`RGA::delete`] does.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Operation)` - The deletion, to send to the other replicas
+    /// * `Err(&str)` - Error message if the operation fails
+    pub fn delete_op(&self, id: UniqueId) -> Result<Operation, &'static str> {
+        self.delete(id)?;
+        Ok(Operation::Delete { id })
+    }
+
+    /// Applies an operation received from another replica.
+    ///
+    /// Operations already applied change nothing, so they may be delivered
+    /// more than once.
+    ///
+    /// # Returns
+    ///
+    /// What [`RGA::apply_remote_op`] made of the operation, or
+    /// [`ApplyOutcome::Rejected`] with the reason nothing was applied: the
+    /// insertion's origin or the deleted node is not held, possibly because
+    /// it was garbage collected, the inserting replica may not write, or the
+    /// deletion targets a sentinel
+    pub fn apply(&self, op: Operation) -> ApplyOutcome {
+        let node = match op {
+            Operation::Insert {
+                id,
+                origin,
+                right_origin,
+                ch,
+            } => {
+                // A missing origin is refused by `apply_remote_op`, but
+                // re-sending the insertion must not revive a deleted node
+                if self.holds(id) {
+                    self.record_deduplicated();
+                    return ApplyOutcome::Duplicate;
+                }
+                Node::with_origins(id, ch, origin, right_origin)
+            }
+            Operation::Delete { id } => {
+                let Some(mut node) = self.get_node(id) else {
+                    self.record_rejected();
+                    return ApplyOutcome::Rejected(if self.may_be_collected(id) {
+                        RejectReason::Collected(id)
+                    } else {
+                        RejectReason::MissingNode(id)
+                    });
+                };
+                node.is_deleted = true;
+                node
+            }
+        };
+        self.apply_remote_op(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_operations_replicate_edits() {
+        let first = RGA::new(1);
+        let second = RGA::new(2);
+        let a = first.insert_op(first.sentinel_start_id(), 'a').unwrap();
+        let b = first.insert_op(a.id(), 'b').unwrap();
+        let deletion = first.delete_op(a.id()).unwrap();
+        assert_eq!(deletion, Operation::Delete { id: a.id() });
+
+        // Dependencies that have not arrived are refused
+        assert_eq!(
+            second.apply(b),
+            ApplyOutcome::Rejected(RejectReason::MissingOrigin(a.id()))
+        );
+        assert_eq!(
+            second.apply(deletion),
+            ApplyOutcome::Rejected(RejectReason::MissingNode(a.id()))
+        );
+        let outcomes = [a, b, deletion, a, deletion].map(|op| second.apply(op));
+        assert_eq!(
+            outcomes,
+            [
+                ApplyOutcome::Applied,
+                ApplyOutcome::Applied,
+                ApplyOutcome::TombstoneMerge,
+                ApplyOutcome::Duplicate,
+                ApplyOutcome::Duplicate,
+            ]
+        );
+        assert_eq!(second.to_string(), "b");
+        assert_eq!(second.total_node_count(), first.total_node_count());
+        assert!(first.verify_checksums(&second.checksums()).is_ok());
+
+        // Nodes convert to the operations that recreate them
+        let third = RGA::new(3);
+        for node in first.all_nodes() {
+            for op in Operation::from_node(&node) {
+                assert!(!matches!(third.apply(op), ApplyOutcome::Rejected(_)));
+            }
+        }
+        assert_eq!(third.to_snapshot(), first.to_snapshot());
+        let sentinel = second.sentinel_start_id();
+        assert_eq!(
+            second.apply(Operation::Delete { id: sentinel }),
+            ApplyOutcome::Rejected(RejectReason::Sentinel)
+        );
+    }
+}
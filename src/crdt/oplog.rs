@@ -0,0 +1,769 @@
+//! A segmented operation log with snapshot checkpoints.
+//!
+//! Operations are appended to a directory of segment files in the operation
+//! log format of [`crate::crdt::snapshot`]. A segment is sealed once it holds
+//! [`SegmentConfig::segment_entries`] operations and a new one is started.
+//! Every so often the caller writes a checkpoint, a snapshot of the document
+//! after a given number of operations, which seals the active segment so that
+//! checkpoints always fall on segment boundaries.
+//!
+//! Compaction keeps the newest [`SegmentConfig::retained_checkpoints`]
+//! checkpoints and removes older ones along with every segment that lies
+//! entirely before the oldest retained checkpoint. Disk usage is then bounded
+//! by the retained checkpoints plus the segments written since the oldest of
+//! them, and the document can be restored as it was at any retained
+//! checkpoint, or at the end of the log.
+//!
+//! A [`HistoryPolicy`] bounds the operations [`SegmentedLog::restore`] has to
+//! replay: once more than its maximum depth were logged after the newest
+//! checkpoint, [`SegmentedLog::checkpoint_if_due`] writes a new one, and a
+//! truncating policy then drops every older checkpoint and segment.
+//!
+//! Files are named after their position, the number of operations logged
+//! before them: `segment-<first>.log` and `checkpoint-<position>.snapshot`.
+//! Both can be fed to `crdt-rga-diff`. With auditing enabled, `audit.log`
+//! chains a digest of every appended batch, see [`crate::crdt::audit`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crdt::audit::{AuditChain, AuditReport, Digest, chain_digest};
+use crate::crdt::rga::RGA;
+use crate::crdt::scrub::pseudonymize_log;
+use crate::crdt::snapshot::{LogEntry, OP_LOG_HEADER, parse_op_log, write_log_entry};
+use crate::crdt::types::ReplicaId;
+
+/// When to rotate segments, checkpoint and compact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentConfig {
+    /// Operations per segment
+    pub segment_entries: usize,
+    /// Segments' worth of operations after which
+    /// [`SegmentedLog::needs_checkpoint`] asks for a new checkpoint
+    pub checkpoint_segments: usize,
+    /// Checkpoints kept by compaction; at least one is always kept
+    pub retained_checkpoints: usize,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        SegmentConfig {
+            segment_entries: 4096,
+            checkpoint_segments: 4,
+            retained_checkpoints: 2,
+        }
+    }
+}
+
+/// Bounds the history the log replays on restore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryPolicy {
+    /// Operations logged after the newest checkpoint beyond which a new one
+    /// is due
+    pub max_depth: u64,
+    /// Whether a new checkpoint drops every older checkpoint and segment,
+    /// instead of keeping [`SegmentConfig::retained_checkpoints`]
+    pub truncate: bool,
+}
+
+impl HistoryPolicy {
+    /// A policy checkpointing past `max_depth` operations and keeping only
+    /// the newest checkpoint.
+    pub fn max_depth(max_depth: u64) -> Self {
+        HistoryPolicy {
+            max_depth,
+            truncate: true,
+        }
+    }
+
+    /// Returns the policy keeping older checkpoints as compaction would.
+    pub fn keep_checkpoints(mut self) -> Self {
+        self.truncate = false;
+        self
+    }
+}
+
+fn segment_name(first: u64) -> String {
+    format!("segment-{first:020}.log")
+}
+
+/// Name of the audit file in a log directory
+pub const AUDIT_FILE: &str = "audit.log";
+
+fn checkpoint_name(position: u64) -> String {
+    format!("checkpoint-{position:020}.snapshot")
+}
+
+/// Replaces the file at `path` with `contents`, never leaving it partial
+fn replace_file(path: &Path, contents: &str) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut file = File::create(&partial)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&partial, path)
+}
+
+fn invalid(path: &Path, error: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {error}", path.display()),
+    )
+}
+
+/// A log directory
+#[derive(Debug)]
+pub struct SegmentedLog {
+    dir: PathBuf,
+    config: SegmentConfig,
+    /// First positions of the segments, oldest first; the last is active
+    segments: Vec<u64>,
+    /// Checkpoint positions, oldest first
+    checkpoints: Vec<u64>,
+    /// Operations logged so far, including compacted ones
+    position: u64,
+    /// Appends to the active segment, opened on first use
+    active: Option<File>,
+    /// Digests of the appended batches, once auditing is enabled
+    audit: Option<AuditChain>,
+    /// Times the logged operations were rewritten since the log was opened
+    rewrites: u64,
+    /// Bound on the operations replayed on restore, if any
+    history: Option<HistoryPolicy>,
+}
+
+impl SegmentedLog {
+    /// Opens the log in `dir`, creating the directory if needed.
+    pub fn open(dir: impl Into<PathBuf>, config: SegmentConfig) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        let mut checkpoints = Vec::new();
+        for file in fs::read_dir(&dir)? {
+            let name = file?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let position = |prefix: &str, suffix: &str| {
+                name.strip_prefix(prefix)?
+                    .strip_suffix(suffix)?
+                    .parse::<u64>()
+                    .ok()
+            };
+            if let Some(first) = position("segment-", ".log") {
+                segments.push(first);
+            } else if let Some(position) = position("checkpoint-", ".snapshot") {
+                checkpoints.push(position);
+            }
+        }
+        segments.sort_unstable();
+        checkpoints.sort_unstable();
+
+        let mut log = SegmentedLog {
+            dir,
+            config,
+            segments,
+            checkpoints,
+            position: 0,
+            active: None,
+            audit: None,
+            rewrites: 0,
+            history: None,
+        };
+        log.position = match log.segments.last() {
+            Some(&first) => first + log.read_segment(first)?.len() as u64,
+            None => log.checkpoints.last().copied().unwrap_or_default(),
+        };
+        Ok(log)
+    }
+
+    /// Operations logged so far, including those compacted away
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Operations [`SegmentedLog::restore`] replays after the newest
+    /// checkpoint
+    pub fn depth(&self) -> u64 {
+        self.position - self.checkpoints.last().copied().unwrap_or_default()
+    }
+
+    /// Sets the policy bounding the history replayed on restore, or removes
+    /// it with `None`.
+    pub fn set_history_policy(&mut self, policy: Option<HistoryPolicy>) {
+        self.history = policy;
+    }
+
+    /// The policy bounding the history replayed on restore, if any
+    pub fn history_policy(&self) -> Option<HistoryPolicy> {
+        self.history
+    }
+
+    /// Positions of the retained checkpoints, oldest first
+    pub fn checkpoints(&self) -> &[u64] {
+        &self.checkpoints
+    }
+
+    /// Number of segment files, the active one included
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Total size of the log's files in bytes
+    pub fn disk_usage(&self) -> io::Result<u64> {
+        let mut total = 0;
+        for &first in &self.segments {
+            total += fs::metadata(self.dir.join(segment_name(first)))?.len();
+        }
+        for &position in &self.checkpoints {
+            total += fs::metadata(self.dir.join(checkpoint_name(position)))?.len();
+        }
+        Ok(total)
+    }
+
+    /// Appends operations, sealing segments as they fill up.
+    ///
+    /// With auditing enabled, the operations are then audited as one batch.
+    pub fn append(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        let first = self.position;
+        let mut remaining = entries;
+        while !remaining.is_empty() {
+            let first = match self.segments.last() {
+                Some(&first)
+                    if self.position - first < self.config.segment_entries.max(1) as u64 =>
+                {
+                    first
+                }
+                _ => self.start_segment()?,
+            };
+            let room = self.config.segment_entries.max(1) - (self.position - first) as usize;
+            let (batch, rest) = remaining.split_at(room.min(remaining.len()));
+
+            let mut lines = String::new();
+            for entry in batch {
+                write_log_entry(&mut lines, entry);
+            }
+            let file = match &mut self.active {
+                Some(file) => file,
+                None => self.active.insert(
+                    OpenOptions::new()
+                        .append(true)
+                        .open(self.dir.join(segment_name(first)))?,
+                ),
+            };
+            file.write_all(lines.as_bytes())?;
+            file.sync_data()?;
+            self.position += batch.len() as u64;
+            remaining = rest;
+        }
+        if let Some(audit) = &mut self.audit
+            && !entries.is_empty()
+        {
+            audit.append(first, entries)?;
+        }
+        Ok(())
+    }
+
+    /// Audits every batch appended from now on in [`AUDIT_FILE`].
+    ///
+    /// A new audit file starts with an empty batch at the current position,
+    /// marking where auditing began. An existing one is continued; operations
+    /// logged since its last batch, as when the process stopped between
+    /// writing a segment and its audit record, are audited first as one
+    /// batch.
+    pub fn enable_audit(&mut self) -> io::Result<()> {
+        let path = self.dir.join(AUDIT_FILE);
+        let mut audit = AuditChain::open(&path)?;
+        match audit.end() {
+            Some(end) if end > self.position => {
+                return Err(invalid(
+                    &path,
+                    "audit covers operations missing from the log",
+                ));
+            }
+            Some(end) if end < self.position => {
+                let missing = self.entries_from(end)?;
+                audit.append(end, &missing)?;
+            }
+            Some(_) => {}
+            None => {
+                audit.append(self.position, &[])?;
+            }
+        }
+        self.audit = Some(audit);
+        Ok(())
+    }
+
+    /// Digest of the newest audited batch, if auditing is enabled
+    pub fn audit_head(&self) -> Option<Digest> {
+        self.audit.as_ref().map(AuditChain::head)
+    }
+
+    /// Checks the audit file against the log.
+    ///
+    /// The file is read again from disk. Its batches must be contiguous and
+    /// end at the log's position, and every batch whose operations are still
+    /// in the segments must match its digest, chained to the batch before.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] on the first mismatch, and
+    /// with [`io::ErrorKind::NotFound`] if auditing is not enabled.
+    pub fn verify_audit(&self) -> io::Result<AuditReport> {
+        if self.audit.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "auditing is not enabled",
+            ));
+        }
+        let path = self.dir.join(AUDIT_FILE);
+        let chain = AuditChain::open(&path)?;
+        if chain.end() != Some(self.position) {
+            return Err(invalid(&path, "audit does not end where the log does"));
+        }
+
+        let base = self.segments.first().copied().unwrap_or(self.position);
+        let entries = self.entries_from(base)?;
+        if entries.len() as u64 != self.position - base {
+            return Err(invalid(
+                &self.dir,
+                "segments no longer hold the logged operations",
+            ));
+        }
+        let mut previous = Digest::default();
+        let mut verified = 0;
+        for record in chain.records() {
+            // Batches before the oldest segment were compacted away
+            if record.first >= base {
+                let start = (record.first - base) as usize;
+                let batch = &entries[start..start + record.count as usize];
+                if chain_digest(&previous, record.first, batch) != record.digest {
+                    return Err(invalid(
+                        &path,
+                        &format!("batch at {} does not match its digest", record.first),
+                    ));
+                }
+                verified += 1;
+            }
+            previous = record.digest;
+        }
+        Ok(AuditReport {
+            batches: chain.records().len(),
+            verified,
+            head: chain.head(),
+        })
+    }
+
+    /// Whether [`SegmentConfig::checkpoint_segments`] segments' worth of
+    /// operations were logged since the last checkpoint, or more than the
+    /// history policy's maximum depth
+    pub fn needs_checkpoint(&self) -> bool {
+        let threshold = self.config.checkpoint_segments.max(1) * self.config.segment_entries.max(1);
+        self.depth() >= threshold as u64
+            || self
+                .history
+                .is_some_and(|policy| self.depth() > policy.max_depth)
+    }
+
+    /// Writes a checkpoint of `rga` if one is due, see
+    /// [`SegmentedLog::needs_checkpoint`]. `rga` must be the document after
+    /// every logged operation.
+    ///
+    /// Returns the new checkpoint's position, if one was written.
+    pub fn checkpoint_if_due(&mut self, rga: &RGA) -> io::Result<Option<u64>> {
+        if !self.needs_checkpoint() {
+            return Ok(None);
+        }
+        self.checkpoint(rga).map(Some)
+    }
+
+    /// Writes a checkpoint of `rga`, which must be the document after every
+    /// logged operation, then compacts the log.
+    ///
+    /// Returns the checkpoint's position.
+    pub fn checkpoint(&mut self, rga: &RGA) -> io::Result<u64> {
+        let position = self.position;
+        // Write to a temporary name first so a crash never leaves a partial
+        // checkpoint behind
+        replace_file(
+            &self.dir.join(checkpoint_name(position)),
+            &rga.to_snapshot(),
+        )?;
+        if self.checkpoints.last() != Some(&position) {
+            self.checkpoints.push(position);
+        }
+
+        // Later operations go to a segment starting at the checkpoint
+        if self.segments.last().is_some_and(|&first| first < position) {
+            self.start_segment()?;
+        }
+        self.compact()?;
+        Ok(position)
+    }
+
+    /// Removes checkpoints beyond the retained number and the segments they
+    /// make unnecessary. A truncating history policy retains only the newest
+    /// checkpoint.
+    ///
+    /// Returns the number of files removed.
+    pub fn compact(&mut self) -> io::Result<usize> {
+        let keep = match self.history {
+            Some(policy) if policy.truncate => 1,
+            _ => self.config.retained_checkpoints.max(1),
+        };
+        let mut removed = 0;
+        while self.checkpoints.len() > keep {
+            let position = self.checkpoints.remove(0);
+            fs::remove_file(self.dir.join(checkpoint_name(position)))?;
+            removed += 1;
+        }
+
+        let Some(&oldest) = self.checkpoints.first() else {
+            return Ok(removed);
+        };
+        // A segment is covered when the next one starts at or before the
+        // oldest checkpoint; the active segment is never removed
+        while self.segments.len() > 1 && self.segments[1] <= oldest {
+            let first = self.segments.remove(0);
+            fs::remove_file(self.dir.join(segment_name(first)))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Replaces `replica` with `pseudonym` in every segment and checkpoint, as
+    /// [`RGA::pseudonymize`] does in a document, for erasure requests.
+    ///
+    /// Each file is written under a temporary name and renamed over the
+    /// original. With auditing enabled, the digests of the batches still in
+    /// the segments are recomputed, so the audit keeps verifying under a new
+    /// head; older digests only hash the compacted operations.
+    ///
+    /// Returns the number of files rewritten.
+    pub fn pseudonymize(&mut self, replica: ReplicaId, pseudonym: ReplicaId) -> io::Result<usize> {
+        let mut rewritten = 0;
+        for &position in &self.checkpoints {
+            let path = self.dir.join(checkpoint_name(position));
+            // Snapshots do not record the replica they were written by
+            let rga = self.restore_at(0, position)?;
+            if rga
+                .pseudonymize(replica, pseudonym)
+                .map_err(|error| invalid(&path, error))?
+                .is_empty()
+            {
+                continue;
+            }
+            replace_file(&path, &rga.to_snapshot())?;
+            rewritten += 1;
+        }
+
+        let base = self.segments.first().copied().unwrap_or(self.position);
+        let mut entries = Vec::new();
+        for &first in &self.segments {
+            let segment = self.read_segment(first)?;
+            let renamed = pseudonymize_log(&segment, replica, pseudonym);
+            if renamed != segment {
+                // One line per entry, as appended, so positions stay put
+                let mut text = format!("{OP_LOG_HEADER}\n");
+                for entry in &renamed {
+                    write_log_entry(&mut text, entry);
+                }
+                replace_file(&self.dir.join(segment_name(first)), &text)?;
+                rewritten += 1;
+            }
+            entries.extend(renamed);
+        }
+        // The active segment was replaced, reopen it on the next append
+        self.active = None;
+
+        if let Some(audit) = &mut self.audit {
+            audit.rechain(base, &entries)?;
+        }
+        self.rewrites += 1;
+        Ok(rewritten)
+    }
+
+    /// Times [`SegmentedLog::pseudonymize`] rewrote the log since it was
+    /// opened; copies of the logged document must be restored again after
+    /// each
+    pub fn rewrites(&self) -> u64 {
+        self.rewrites
+    }
+
+    /// Rebuilds the document after every logged operation.
+    pub fn restore(&self, replica_id: ReplicaId) -> io::Result<RGA> {
+        let rga = match self.checkpoints.last() {
+            Some(&position) => self.restore_at(replica_id, position)?,
+            None => {
+                if self.segments.first().is_some_and(|&first| first > 0) {
+                    return Err(invalid(&self.dir, "log has no checkpoint to start from"));
+                }
+                RGA::new(replica_id)
+            }
+        };
+        let start = self.checkpoints.last().copied().unwrap_or_default();
+
+        for (index, &first) in self.segments.iter().enumerate() {
+            let end = self.segments.get(index + 1).copied().unwrap_or(u64::MAX);
+            if end <= start {
+                continue;
+            }
+            let path = self.dir.join(segment_name(first));
+            let skip = start.saturating_sub(first) as usize;
+            for entry in self.read_segment(first)?.iter().skip(skip) {
+                rga.apply_log_entry(entry)
+                    .map_err(|error| invalid(&path, error))?;
+            }
+        }
+        Ok(rga)
+    }
+
+    /// Rebuilds the document as it was at a retained checkpoint.
+    pub fn restore_at(&self, replica_id: ReplicaId, position: u64) -> io::Result<RGA> {
+        if !self.checkpoints.contains(&position) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no checkpoint at position {position}"),
+            ));
+        }
+        let path = self.dir.join(checkpoint_name(position));
+        RGA::from_snapshot(replica_id, &fs::read_to_string(&path)?)
+            .map_err(|error| invalid(&path, error))
+    }
+
+    /// Seals the active segment and starts one at the current position
+    fn start_segment(&mut self) -> io::Result<u64> {
+        let first = self.position;
+        let mut file = File::create(self.dir.join(segment_name(first)))?;
+        writeln!(file, "{OP_LOG_HEADER}")?;
+        file.sync_data()?;
+        if self.segments.last() != Some(&first) {
+            self.segments.push(first);
+        }
+        self.active = Some(file);
+        Ok(first)
+    }
+
+    /// The logged operations from `position` on, read from the segments
+    fn entries_from(&self, position: u64) -> io::Result<Vec<LogEntry>> {
+        if self.segments.first().is_none_or(|&first| first > position) && position < self.position {
+            return Err(invalid(&self.dir, "operations were compacted away"));
+        }
+        let mut entries = Vec::new();
+        for &first in &self.segments {
+            let segment = self.read_segment(first)?;
+            let skip = position.saturating_sub(first) as usize;
+            entries.extend(segment.into_iter().skip(skip));
+        }
+        Ok(entries)
+    }
+
+    fn read_segment(&self, first: u64) -> io::Result<Vec<LogEntry>> {
+        let path = self.dir.join(segment_name(first));
+        parse_op_log(&fs::read_to_string(&path)?).map_err(|error| invalid(&path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    /// A fresh directory under the system temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Types `text` into `rga`, logging the operations
+    fn type_text(rga: &RGA, log: &mut SegmentedLog, text: &str) {
+        let before = rga.fork(0);
+        let after = rga
+            .visible_nodes()
+            .last()
+            .map_or_else(|| rga.sentinel_start_id(), |node| node.id);
+        rga.insert_str_after(after, text).unwrap();
+        log.append(&rga.log_entries_since(&before)).unwrap();
+    }
+
+    #[test]
+    fn test_segments_rotate_and_restore() {
+        let dir = temp_dir("oplog-rotate");
+        let config = SegmentConfig {
+            segment_entries: 4,
+            ..SegmentConfig::default()
+        };
+        let mut log = SegmentedLog::open(&dir, config).unwrap();
+        let rga = RGA::new(1);
+        type_text(&rga, &mut log, "hello world");
+        let id = rga.visible_nodes()[5].id;
+        rga.delete(id).unwrap();
+        log.append(&[LogEntry::Delete { id }]).unwrap();
+
+        assert_eq!(log.position(), 12);
+        assert_eq!(log.segment_count(), 3);
+        assert_eq!(log.restore(2).unwrap().to_string(), "helloworld");
+
+        // Reopening continues the active segment
+        let mut log = SegmentedLog::open(&dir, config).unwrap();
+        assert_eq!(log.position(), 12);
+        type_text(&rga, &mut log, "!");
+        assert_eq!(log.segment_count(), 4);
+        assert_eq!(log.restore(2).unwrap().to_snapshot(), rga.to_snapshot());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_keeps_checkpoints_restorable() {
+        let dir = temp_dir("oplog-compact");
+        let config = SegmentConfig {
+            segment_entries: 2,
+            checkpoint_segments: 2,
+            retained_checkpoints: 2,
+        };
+        let mut log = SegmentedLog::open(&dir, config).unwrap();
+        let rga = RGA::new(1);
+        let mut checkpoints = Vec::new();
+        for word in ["one ", "two ", "three ", "four "] {
+            type_text(&rga, &mut log, word);
+            assert!(log.needs_checkpoint());
+            checkpoints.push((log.checkpoint(&rga).unwrap(), rga.to_string()));
+            assert!(!log.needs_checkpoint());
+        }
+
+        // Only the last two checkpoints survive, with the segments after them
+        let retained: Vec<u64> = checkpoints[2..].iter().map(|(at, _)| *at).collect();
+        assert_eq!(log.checkpoints(), retained);
+        assert_eq!(log.segment_count(), 4);
+        for (position, text) in &checkpoints[2..] {
+            assert_eq!(&log.restore_at(2, *position).unwrap().to_string(), text);
+        }
+        assert!(log.restore_at(2, checkpoints[0].0).is_err());
+
+        type_text(&rga, &mut log, "five");
+        let reopened = SegmentedLog::open(&dir, config).unwrap();
+        assert_eq!(reopened.position(), log.position());
+        assert_eq!(reopened.restore(2).unwrap().to_string(), rga.to_string());
+        assert!(reopened.disk_usage().unwrap() > 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_policy_bounds_replay() {
+        let dir = temp_dir("oplog-history");
+        let config = SegmentConfig {
+            segment_entries: 4,
+            ..SegmentConfig::default()
+        };
+        let mut log = SegmentedLog::open(&dir, config).unwrap();
+        log.set_history_policy(Some(HistoryPolicy::max_depth(5)));
+        let rga = RGA::new(1);
+        type_text(&rga, &mut log, "hello");
+        assert_eq!(log.checkpoint_if_due(&rga).unwrap(), None);
+
+        let mut checkpoints = Vec::new();
+        for word in [" big", " wide", " world"] {
+            type_text(&rga, &mut log, word);
+            checkpoints.extend(log.checkpoint_if_due(&rga).unwrap());
+            assert!(log.depth() <= 5);
+        }
+        // Each checkpoint truncated the history before it
+        assert_eq!(checkpoints, [9, 20]);
+        assert_eq!(log.checkpoints(), [20]);
+        assert_eq!(log.segment_count(), 1);
+        assert!(log.restore_at(2, 9).is_err());
+
+        // Without truncation older checkpoints are compacted as configured
+        log.set_history_policy(Some(HistoryPolicy::max_depth(0).keep_checkpoints()));
+        type_text(&rga, &mut log, "!");
+        assert_eq!(log.checkpoint_if_due(&rga).unwrap(), Some(21));
+        assert_eq!(log.checkpoints(), [20, 21]);
+        assert_eq!(log.restore(2).unwrap().to_string(), "hello big wide world!");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_audit_detects_tampering() {
+        let dir = temp_dir("oplog-audit");
+        let config = SegmentConfig {
+            segment_entries: 4,
+            checkpoint_segments: 2,
+            retained_checkpoints: 1,
+        };
+        let mut log = SegmentedLog::open(&dir, config).unwrap();
+        log.enable_audit().unwrap();
+        let rga = RGA::new(1);
+        for word in ["one ", "two ", "three "] {
+            type_text(&rga, &mut log, word);
+        }
+        let report = log.verify_audit().unwrap();
+        // The first batch marks where auditing started
+        assert_eq!((report.batches, report.verified), (4, 4));
+        assert_eq!(Some(report.head), log.audit_head());
+
+        // Compacted batches are still covered by the digests after them
+        log.checkpoint(&rga).unwrap();
+        type_text(&rga, &mut log, "four");
+        let report = log.verify_audit().unwrap();
+        assert_eq!((report.batches, report.verified), (5, 1));
+
+        // Reopening continues the chain
+        let mut reopened = SegmentedLog::open(&dir, config).unwrap();
+        reopened.enable_audit().unwrap();
+        type_text(&rga, &mut reopened, "!");
+        assert_eq!(reopened.verify_audit().unwrap().batches, 6);
+
+        // Rewriting a logged character breaks the chain
+        let segment = dir.join(segment_name(reopened.segments[0]));
+        let text = fs::read_to_string(&segment).unwrap();
+        let (kept, last) = text.trim_end().rsplit_once('\n').unwrap();
+        let mut fields: Vec<&str> = last.split(' ').collect();
+        fields[3] = "3f";
+        fs::write(&segment, format!("{kept}\n{}\n", fields.join(" "))).unwrap();
+        let error = reopened.verify_audit().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pseudonymize_rewrites_segments_and_checkpoints() {
+        let dir = temp_dir("oplog-pseudonymize");
+        let mut log = SegmentedLog::open(&dir, SegmentConfig::default()).unwrap();
+        log.enable_audit().unwrap();
+        let writer = RGA::new(7);
+        type_text(&writer, &mut log, "secret ");
+        log.checkpoint(&writer).unwrap();
+        type_text(&writer, &mut log, "notes");
+        let head = log.audit_head().unwrap();
+
+        assert_eq!(log.pseudonymize(7, 1000).unwrap(), 2);
+        assert_eq!(log.rewrites(), 1);
+        for file in fs::read_dir(&dir).unwrap() {
+            let path = file.unwrap().path();
+            if path.file_name().unwrap() != AUDIT_FILE {
+                let text = fs::read_to_string(&path).unwrap();
+                assert!(!text.contains(".7.") && !text.contains("r 7\n"));
+            }
+        }
+
+        // The audit still verifies, under a new head
+        let report = log.verify_audit().unwrap();
+        assert_ne!(report.head, head);
+        assert_eq!(Some(report.head), log.audit_head());
+        let expected = writer.fork(1);
+        expected.pseudonymize(7, 1000).unwrap();
+        assert_eq!(
+            log.restore(2).unwrap().to_snapshot(),
+            expected.to_snapshot()
+        );
+
+        // Appending continues in the rewritten segment
+        type_text(&expected, &mut log, "!");
+        assert_eq!(log.restore(2).unwrap().to_string(), "secret notes!");
+        assert!(log.verify_audit().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+//! Order-statistics index over the document order.
+//!
+//! The node store keeps the document order as a linked list, which finds the
+//! character at a visible index only by walking it. [`OrderIndex`] mirrors
+//! that list in a treap: a binary tree ordered by document position and
+//! balanced by random priorities, where every subtree counts its entries, its
+//! visible entries and its visible line breaks. Finding the entry at a visible
+//! index, or the line a visible index is on, descends the tree by those
+//! counts, and linking, unlinking or hiding an entry updates the counts on its
+//! way to the root, all in expected O(log n).
+//!
+//! Entries live in an arena and point at their parents, so an entry found by
+//! ID through a map can be located in the tree without a search.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::crdt::types::UniqueId;
+
+/// Marks a missing child or parent
+const NIL: usize = usize::MAX;
+
+/// An entry of the tree, for one ID of the document order
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    id: UniqueId,
+    priority: u64,
+    left: usize,
+    right: usize,
+    parent: usize,
+    is_visible: bool,
+    /// Visible and ending a line
+    is_line_break: bool,
+    /// Entries in the subtree rooted here
+    size: usize,
+    /// Visible entries in the subtree rooted here
+    visible: usize,
+    /// Visible line breaks in the subtree rooted here
+    line_breaks: usize,
+}
+
+/// Positions of the IDs of a document order, see the [module docs](self)
+#[derive(Debug)]
+pub(crate) struct OrderIndex {
+    entries: Vec<Entry>,
+    /// Arena slots of removed entries, reused first
+    free: Vec<usize>,
+    /// Arena slot of every indexed ID
+    slots: BTreeMap<UniqueId, usize>,
+    root: usize,
+    /// State of the xorshift generator handing out priorities
+    seed: u64,
+}
+
+impl OrderIndex {
+    /// Creates an index holding only `first`, hidden
+    pub(crate) fn new(first: UniqueId) -> Self {
+        let mut index = OrderIndex {
+            entries: Vec::new(),
+            free: Vec::new(),
+            slots: BTreeMap::new(),
+            root: NIL,
+            seed: 0x2545_f491_4f6c_dd1d,
+        };
+        index.root = index.allocate(first);
+        index
+    }
+
+    /// Number of visible entries
+    pub(crate) fn visible_len(&self) -> usize {
+        self.visible(self.root)
+    }
+
+    /// The ID at visible index `index`, if there is one
+    pub(crate) fn visible_at(&self, mut index: usize) -> Option<UniqueId> {
+        let mut at = self.root;
+        while at != NIL {
+            let entry = &self.entries[at];
+            let before = self.visible(entry.left);
+            if index < before {
+                at = entry.left;
+            } else if index == before && entry.is_visible {
+                return Some(entry.id);
+            } else {
+                index -= before + usize::from(entry.is_visible);
+                at = entry.right;
+            }
+        }
+        None
+    }
+
+    /// Number of lines of the visible entries, one more than their line
+    /// breaks
+    pub(crate) fn line_count(&self) -> usize {
+        self.line_breaks(self.root) + 1
+    }
+
+    /// Visible index range of line `row`, without its line break
+    pub(crate) fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        let start = match row {
+            0 => 0,
+            _ => self.line_break_at(row - 1)? + 1,
+        };
+        let end = self
+            .line_break_at(row)
+            .unwrap_or_else(|| self.visible_len());
+        Some(start..end)
+    }
+
+    /// Row and column of visible index `index` (0 ..= visible length)
+    pub(crate) fn to_position(&self, index: usize) -> Option<(usize, usize)> {
+        if index > self.visible_len() {
+            return None;
+        }
+        let row = self.line_breaks_before(index);
+        let start = self.line_range(row)?.start;
+        Some((row, index - start))
+    }
+
+    /// Visible index of the line break ending line `row`
+    fn line_break_at(&self, mut row: usize) -> Option<usize> {
+        let mut at = self.root;
+        let mut index = 0;
+        while at != NIL {
+            let entry = &self.entries[at];
+            let before = self.line_breaks(entry.left);
+            if row < before {
+                at = entry.left;
+            } else if row == before && entry.is_line_break {
+                return Some(index + self.visible(entry.left));
+            } else {
+                row -= before + usize::from(entry.is_line_break);
+                index += self.visible(entry.left) + usize::from(entry.is_visible);
+                at = entry.right;
+            }
+        }
+        None
+    }
+
+    /// Number of line breaks among the first `count` visible entries
+    fn line_breaks_before(&self, mut count: usize) -> usize {
+        let mut at = self.root;
+        let mut line_breaks = 0;
+        while at != NIL && count > 0 {
+            let entry = &self.entries[at];
+            let before = self.visible(entry.left);
+            if count <= before {
+                at = entry.left;
+            } else {
+                count -= before + usize::from(entry.is_visible);
+                line_breaks += self.line_breaks(entry.left) + usize::from(entry.is_line_break);
+                at = entry.right;
+            }
+        }
+        line_breaks
+    }
+
+    /// Indexes `id`, hidden, right after `previous`.
+    ///
+    /// Nothing changes if `id` is indexed already or `previous` is not.
+    pub(crate) fn insert_after(&mut self, previous: UniqueId, id: UniqueId) {
+        let Some(&slot) = self.slots.get(&previous) else {
+            return;
+        };
+        if self.slots.contains_key(&id) {
+            return;
+        }
+        let position = self.position(slot) + 1;
+        let entry = self.allocate(id);
+        let (before, after) = self.split(self.root, position);
+        let before = self.merge(before, entry);
+        let root = self.merge(before, after);
+        self.set_root(root);
+    }
+
+    /// Drops `id` from the index
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn remove(&mut self, id: &UniqueId) {
+        let Some(slot) = self.slots.remove(id) else {
+            return;
+        };
+        let position = self.position(slot);
+        let (before, rest) = self.split(self.root, position);
+        let (removed, after) = self.split(rest, 1);
+        debug_assert_eq!(removed, slot);
+        let root = self.merge(before, after);
+        self.set_root(root);
+        self.free.push(slot);
+    }
+
+    /// Gives the entry of `id` the ID `renamed`, in place
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn rename(&mut self, id: &UniqueId, renamed: UniqueId) {
+        if let Some(slot) = self.slots.remove(id) {
+            self.entries[slot].id = renamed;
+            self.slots.insert(renamed, slot);
+        }
+    }
+
+    /// Shows or hides `id`, if it is indexed, counting it as a line break
+    /// if it is a visible one
+    pub(crate) fn set_visible(&mut self, id: &UniqueId, is_visible: bool, is_line_break: bool) {
+        let Some(&slot) = self.slots.get(id) else {
+            return;
+        };
+        let is_line_break = is_visible && is_line_break;
+        let entry = &mut self.entries[slot];
+        if (entry.is_visible, entry.is_line_break) == (is_visible, is_line_break) {
+            return;
+        }
+        entry.is_visible = is_visible;
+        entry.is_line_break = is_line_break;
+        let mut at = slot;
+        while at != NIL {
+            self.recount(at);
+            at = self.entries[at].parent;
+        }
+    }
+
+    /// Stores a new, hidden, unlinked entry for `id`
+    fn allocate(&mut self, id: UniqueId) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let entry = Entry {
+            id,
+            priority: self.seed,
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            is_visible: false,
+            is_line_break: false,
+            size: 1,
+            visible: 0,
+            line_breaks: 0,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.entries[slot] = entry;
+                slot
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.slots.insert(id, slot);
+        slot
+    }
+
+    fn size(&self, at: usize) -> usize {
+        if at == NIL { 0 } else { self.entries[at].size }
+    }
+
+    fn visible(&self, at: usize) -> usize {
+        if at == NIL {
+            0
+        } else {
+            self.entries[at].visible
+        }
+    }
+
+    fn line_breaks(&self, at: usize) -> usize {
+        if at == NIL {
+            0
+        } else {
+            self.entries[at].line_breaks
+        }
+    }
+
+    /// Number of entries before `slot` in document order
+    fn position(&self, slot: usize) -> usize {
+        let mut position = self.size(self.entries[slot].left);
+        let (mut at, mut parent) = (slot, self.entries[slot].parent);
+        while parent != NIL {
+            if self.entries[parent].right == at {
+                position += self.size(self.entries[parent].left) + 1;
+            }
+            (at, parent) = (parent, self.entries[parent].parent);
+        }
+        position
+    }
+
+    /// Recomputes the counts of `at` from its children, and adopts them
+    fn recount(&mut self, at: usize) {
+        let Entry {
+            left,
+            right,
+            is_visible,
+            is_line_break,
+            ..
+        } = self.entries[at];
+        for child in [left, right] {
+            if child != NIL {
+                self.entries[child].parent = at;
+            }
+        }
+        self.entries[at].size = 1 + self.size(left) + self.size(right);
+        self.entries[at].visible =
+            usize::from(is_visible) + self.visible(left) + self.visible(right);
+        self.entries[at].line_breaks =
+            usize::from(is_line_break) + self.line_breaks(left) + self.line_breaks(right);
+    }
+
+    fn set_root(&mut self, root: usize) {
+        self.root = root;
+        if root != NIL {
+            self.entries[root].parent = NIL;
+        }
+    }
+
+    /// Splits the tree at `at` into its first `count` entries and the rest
+    fn split(&mut self, at: usize, count: usize) -> (usize, usize) {
+        if at == NIL {
+            return (NIL, NIL);
+        }
+        let Entry { left, right, .. } = self.entries[at];
+        if self.size(left) >= count {
+            let (before, after) = self.split(left, count);
+            self.entries[at].left = after;
+            self.recount(at);
+            (before, at)
+        } else {
+            let (before, after) = self.split(right, count - self.size(left) - 1);
+            self.entries[at].right = before;
+            self.recount(at);
+            (at, after)
+        }
+    }
+
+    /// Joins two trees, every entry of `first` coming first
+    fn merge(&mut self, first: usize, second: usize) -> usize {
+        if first == NIL {
+            return second;
+        }
+        if second == NIL {
+            return first;
+        }
+        if self.entries[first].priority > self.entries[second].priority {
+            let right = self.merge(self.entries[first].right, second);
+            self.entries[first].right = right;
+            self.recount(first);
+            first
+        } else {
+            let left = self.merge(first, self.entries[second].left);
+            self.entries[second].left = left;
+            self.recount(second);
+            second
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finds_visible_entries_by_index() {
+        let start = UniqueId::new(0, 0);
+        let mut index = OrderIndex::new(start);
+        let ids: Vec<UniqueId> = (1..=100).map(|counter| UniqueId::new(counter, 1)).collect();
+        // Each one inserted at the front, so the order is reversed
+        for &id in &ids {
+            index.insert_after(start, id);
+            index.set_visible(&id, true, false);
+        }
+        assert_eq!(index.visible_len(), 100);
+        assert_eq!(index.visible_at(0), Some(ids[99]));
+        assert_eq!(index.visible_at(99), Some(ids[0]));
+        assert_eq!(index.visible_at(100), None);
+
+        // Hidden entries keep their place but are skipped
+        for id in ids.iter().step_by(2) {
+            index.set_visible(id, false, false);
+        }
+        assert_eq!(index.visible_len(), 50);
+        assert_eq!(index.visible_at(0), Some(ids[99]));
+        assert_eq!(index.visible_at(49), Some(ids[1]));
+
+        index.remove(&ids[99]);
+        index.rename(&ids[97], UniqueId::new(500, 2));
+        assert_eq!(index.visible_at(0), Some(UniqueId::new(500, 2)));
+        index.insert_after(UniqueId::new(500, 2), ids[99]);
+        index.set_visible(&ids[99], true, false);
+        assert_eq!(index.visible_at(1), Some(ids[99]));
+        assert_eq!(index.visible_len(), 50);
+        assert_eq!(index.free, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_finds_lines_by_row() {
+        let start = UniqueId::new(0, 0);
+        let mut index = OrderIndex::new(start);
+        // "ab\n\ncd", plus a hidden line break inside "cd"
+        let mut previous = start;
+        for (counter, (is_visible, is_line_break)) in [
+            (true, false),
+            (true, false),
+            (true, true),
+            (true, true),
+            (true, false),
+            (false, true),
+            (true, false),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = UniqueId::new(counter as u64 + 1, 1);
+            index.insert_after(previous, id);
+            index.set_visible(&id, is_visible, is_line_break);
+            previous = id;
+        }
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.line_range(0), Some(0..2));
+        assert_eq!(index.line_range(1), Some(3..3));
+        assert_eq!(index.line_range(2), Some(4..6));
+        assert_eq!(index.line_range(3), None);
+        assert_eq!(index.to_position(0), Some((0, 0)));
+        assert_eq!(index.to_position(2), Some((0, 2)));
+        assert_eq!(index.to_position(3), Some((1, 0)));
+        assert_eq!(index.to_position(4), Some((2, 0)));
+        assert_eq!(index.to_position(6), Some((2, 2)));
+        assert_eq!(index.to_position(7), None);
+
+        index.set_visible(&UniqueId::new(3, 1), false, true);
+        assert_eq!(index.line_count(), 2);
+        assert_eq!(index.line_range(0), Some(0..2));
+        assert_eq!(index.to_position(3), Some((1, 0)));
+    }
+}
@@ -0,0 +1,195 @@
+//! Coarse timers on the RGA's hot paths, for locating bottlenecks.
+//!
+//! With the `profiling` feature every RGA counts the calls of a few hot paths
+//! and the wall-clock time spent in them: whole-document traversals of the
+//! node store, rendering the text, integrating remote operations and local
+//! insertions. [`RGA::profile`] reads them, so an application can tell which
+//! of these dominates its own workload before reaching for a profiler.
+//!
+//! The instrumented functions are also kept out of line, so they appear as
+//! frames of their own in flamegraphs instead of being inlined into callers.
+//! Without the feature none of this is compiled in.
+//!
+//! Times are inclusive: an insertion that traverses the document counts
+//! towards both paths. Like [`crate::SyncMetrics`] the counters only grow;
+//! compare two [`Profile`] readings to measure an interval.
+
+use core::time::Duration;
+use std::time::Instant;
+
+use crate::crdt::rga::RGA;
+use crate::crdt::types::Counter;
+
+/// An instrumented code path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HotPath {
+    /// Visiting every node of the store, as `all_nodes`, `visible_nodes` and
+    /// most queries do
+    Traversal,
+    /// Rendering the visible text with `to_string` or `Display`
+    Render,
+    /// Integrating a node with `apply_remote_op`
+    ApplyRemote,
+    /// Local `insert_after` and `insert_str_after` calls
+    Insert,
+}
+
+impl HotPath {
+    /// Every instrumented path
+    pub const ALL: [HotPath; 4] = [
+        HotPath::Traversal,
+        HotPath::Render,
+        HotPath::ApplyRemote,
+        HotPath::Insert,
+    ];
+
+    /// The path's name in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            HotPath::Traversal => "traversal",
+            HotPath::Render => "render",
+            HotPath::ApplyRemote => "apply_remote",
+            HotPath::Insert => "insert",
+        }
+    }
+}
+
+/// Calls of one hot path and the time spent in them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathStats {
+    /// Number of completed calls
+    pub calls: u64,
+    /// Wall-clock time spent in them
+    pub total: Duration,
+}
+
+impl PathStats {
+    /// Average time per call, zero before the first
+    pub fn mean(&self) -> Duration {
+        match self.calls {
+            0 => Duration::ZERO,
+            calls => self.total / calls as u32,
+        }
+    }
+}
+
+/// A reading of an RGA's hot-path timers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Profile {
+    paths: [PathStats; 4],
+}
+
+impl Profile {
+    /// The figures of one path
+    pub fn get(&self, path: HotPath) -> PathStats {
+        self.paths[path as usize]
+    }
+
+    /// Every path with its figures
+    pub fn iter(&self) -> impl Iterator<Item = (HotPath, PathStats)> + '_ {
+        HotPath::ALL.into_iter().map(|path| (path, self.get(path)))
+    }
+
+    /// Figures accumulated since the `earlier` reading
+    pub fn since(&self, earlier: &Profile) -> Profile {
+        Profile {
+            paths: core::array::from_fn(|index| PathStats {
+                calls: self.paths[index]
+                    .calls
+                    .saturating_sub(earlier.paths[index].calls),
+                total: self.paths[index]
+                    .total
+                    .saturating_sub(earlier.paths[index].total),
+            }),
+        }
+    }
+}
+
+/// The live timers kept by an RGA
+pub(crate) struct Profiler {
+    calls: [Counter; 4],
+    nanos: [Counter; 4],
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Profiler {
+            calls: core::array::from_fn(|_| Counter::new(0)),
+            nanos: core::array::from_fn(|_| Counter::new(0)),
+        }
+    }
+
+    /// Times `path` until the returned guard is dropped
+    pub(crate) fn time(&self, path: HotPath) -> Timer<'_> {
+        Timer {
+            profiler: self,
+            path,
+            start: Instant::now(),
+        }
+    }
+}
+
+/// Adds the time since it was created to its path when dropped
+pub(crate) struct Timer<'a> {
+    profiler: &'a Profiler,
+    path: HotPath,
+    start: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        let index = self.path as usize;
+        let nanos = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.profiler.nanos[index].fetch_add(nanos);
+        self.profiler.calls[index].fetch_add(1);
+    }
+}
+
+impl RGA {
+    /// Reads the hot-path timers of this replica.
+    ///
+    /// Forks, clones and documents loaded from a snapshot start from zero.
+    ///
+    /// # Returns
+    ///
+    /// The calls of each [`HotPath`] so far and the time spent in them
+    pub fn profile(&self) -> Profile {
+        let profiler = &self.profiler;
+        Profile {
+            paths: core::array::from_fn(|index| PathStats {
+                calls: profiler.calls[index].load(),
+                total: Duration::from_nanos(profiler.nanos[index].load()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_paths_are_timed() {
+        let source = RGA::new(1);
+        let ids = source
+            .insert_str_after(source.sentinel_start_id(), "hi")
+            .unwrap();
+        source.insert_after(ids[1], '!').unwrap();
+        let before = source.profile();
+        assert_eq!(before.get(HotPath::Insert).calls, 2);
+
+        let target = RGA::new(2);
+        for node in source.all_nodes().into_iter().filter(|n| !n.is_sentinel()) {
+            target.apply_remote_op(node);
+        }
+        assert_eq!(target.to_string(), "hi!");
+        let profile = target.profile();
+        assert_eq!(profile.get(HotPath::ApplyRemote).calls, 3);
+        assert_eq!(profile.get(HotPath::Render).calls, 1);
+        assert_eq!(profile.get(HotPath::Insert), PathStats::default());
+
+        let traversals = source.profile().since(&before).get(HotPath::Traversal);
+        assert_eq!(traversals.calls, 1, "all_nodes traverses the store once");
+        assert!(traversals.mean() <= traversals.total);
+    }
+}
@@ -0,0 +1,362 @@
+//! Filtering of the document and its history by author and by time.
+//!
+//! Every node records the replica that inserted it. A [`ProvenanceFilter`]
+//! selects replicas, either by listing those to show ("only Alice's edits") or
+//! those to mute (a bot), and [`RGA::history`] and [`RGA::text_by`] apply it.
+//!
+//! A character's author is the replica that wrote it: the one that inserted
+//! it, or for text pasted with [`RGA::insert_fragment`], the one that wrote the
+//! copied text. Tombstones do not record who deleted a character, so a
+//! deletion is attributed to the author of the deleted text, as in
+//! `crdt-rga-diff`.
+//!
+//! With a [`HybridClock`](crate::crdt::types::HybridClock) every operation's counter also carries the wall
+//! time it was made at, which [`RGA::created_at`] reads back and
+//! [`RGA::edits_between`] selects the history by.
+//!
+//! A filter also restricts who may write to a document: after
+//! [`Rga::restrict_writers`], remote insertions stamped by any other replica
+//! are rejected.
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crdt::metrics::RejectReason;
+use crate::crdt::node::{Element, Node};
+use crate::crdt::rga::{RGA, Rga};
+use crate::crdt::snapshot::{LogEntry, coalesce_deletes, range_ids};
+use crate::crdt::types::ReplicaId;
+use crate::crdt::types::UniqueId;
+
+/// Which replicas' operations to keep
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ProvenanceFilter {
+    /// Every replica
+    #[default]
+    All,
+    /// Only these replicas
+    Only(BTreeSet<ReplicaId>),
+    /// Every replica but these
+    Mute(BTreeSet<ReplicaId>),
+}
+
+impl ProvenanceFilter {
+    /// Keeps only the operations of `replicas`
+    pub fn only(replicas: impl IntoIterator<Item = ReplicaId>) -> Self {
+        ProvenanceFilter::Only(replicas.into_iter().collect())
+    }
+
+    /// Drops the operations of `replicas`
+    pub fn mute(replicas: impl IntoIterator<Item = ReplicaId>) -> Self {
+        ProvenanceFilter::Mute(replicas.into_iter().collect())
+    }
+
+    /// Whether operations of `replica` are kept
+    pub fn matches(&self, replica: ReplicaId) -> bool {
+        match self {
+            ProvenanceFilter::All => true,
+            ProvenanceFilter::Only(replicas) => replicas.contains(&replica),
+            ProvenanceFilter::Mute(replicas) => !replicas.contains(&replica),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", not(feature = "single-threaded")))]
+mod register {
+    use super::ProvenanceFilter;
+    use parking_lot::RwLock;
+
+    /// The replicas allowed to write to a document
+    #[derive(Default)]
+    pub(crate) struct WritersRegister(RwLock<ProvenanceFilter>);
+
+    impl WritersRegister {
+        pub(crate) fn get(&self) -> ProvenanceFilter {
+            self.0.read().clone()
+        }
+
+        pub(crate) fn set(&self, writers: ProvenanceFilter) {
+            *self.0.write() = writers;
+        }
+
+        pub(crate) fn matches(&self, replica: super::ReplicaId) -> bool {
+            self.0.read().matches(replica)
+        }
+    }
+}
+
+#[cfg(any(not(feature = "std"), feature = "single-threaded"))]
+mod register {
+    use super::ProvenanceFilter;
+    use core::cell::RefCell;
+
+    /// The replicas allowed to write to a document
+    #[derive(Default)]
+    pub(crate) struct WritersRegister(RefCell<ProvenanceFilter>);
+
+    impl WritersRegister {
+        pub(crate) fn get(&self) -> ProvenanceFilter {
+            self.0.borrow().clone()
+        }
+
+        pub(crate) fn set(&self, writers: ProvenanceFilter) {
+            *self.0.borrow_mut() = writers;
+        }
+
+        pub(crate) fn matches(&self, replica: super::ReplicaId) -> bool {
+            self.0.borrow().matches(replica)
+        }
+    }
+}
+
+pub(crate) use register::WritersRegister;
+
+impl<T: Element> Rga<T> {
+    /// Accepts new nodes only from the replicas `writers` matches; the rest
+    /// are rejected with [`RejectReason::Forbidden`], by
+    /// [`Rga::apply_remote_op`] and by a [`crate::CausalBuffer`] alike, even
+    /// if they arrive already deleted.
+    ///
+    /// Deletions of held nodes carry no record of who made them, so they are
+    /// not checked, and neither are local edits.
+    pub fn restrict_writers(&self, writers: ProvenanceFilter) {
+        self.writers.set(writers);
+    }
+
+    /// The replicas whose remote insertions are accepted
+    pub fn writers(&self) -> ProvenanceFilter {
+        self.writers.get()
+    }
+
+    /// Why `node` is refused, if it is new and was made by a replica that
+    /// may not write
+    pub(crate) fn forbidden(&self, node: &Node<T>) -> Option<RejectReason> {
+        let author = node.id.replica_id();
+        (!self.writers.matches(author) && !self.holds(node.id))
+            .then_some(RejectReason::Forbidden(author))
+    }
+}
+
+impl RGA {
+    /// The operations that built the document, restricted to some authors.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The replicas whose operations to keep
+    ///
+    /// # Returns
+    ///
+    /// Insertions and deletions in document order. Unless the filter keeps
+    /// every replica, insertions may refer to origins that are left out.
+    pub fn history(&self, filter: &ProvenanceFilter) -> Vec<LogEntry> {
+        self.history_where(|_, author| filter.matches(author))
+    }
+
+    /// The wall time node `id` was inserted at, in milliseconds since the
+    /// Unix epoch, as estimated by a [`HybridClock`](crate::crdt::types::HybridClock).
+    ///
+    /// # Returns
+    ///
+    /// The time, or `None` for sentinels and when the document's clock is
+    /// not a hybrid one
+    pub fn created_at(&self, id: UniqueId) -> Option<u64> {
+        if id == self.sentinel_start_id() || id == self.sentinel_end_id() {
+            return None;
+        }
+        self.clock.wall_time(id.counter())
+    }
+
+    /// Returns true if the document's clock dates its operations, see
+    /// [`RGA::created_at`].
+    pub fn dates_edits(&self) -> bool {
+        self.clock.wall_time(0).is_some()
+    }
+
+    /// The operations on characters inserted from `from` up to `to`, in
+    /// milliseconds since the Unix epoch, for views like "changes from
+    /// today".
+    ///
+    /// Tombstones do not record when a character was deleted, so a deletion
+    /// is listed with the insertion it removes.
+    ///
+    /// # Returns
+    ///
+    /// Insertions and deletions in document order; nothing unless the
+    /// document's clock is a [`HybridClock`](crate::crdt::types::HybridClock)
+    pub fn edits_between(&self, from: u64, to: u64) -> Vec<LogEntry> {
+        self.history_between(&ProvenanceFilter::All, from, to)
+    }
+
+    /// [`RGA::history`] restricted to [`RGA::edits_between`] `from` and `to`
+    pub fn history_between(&self, filter: &ProvenanceFilter, from: u64, to: u64) -> Vec<LogEntry> {
+        self.history_where(|id, author| {
+            filter.matches(author)
+                && self
+                    .created_at(id)
+                    .is_some_and(|time| (from..to).contains(&time))
+        })
+    }
+
+    /// The operations on the nodes `keep` accepts by ID and author
+    fn history_where(&self, keep: impl Fn(UniqueId, ReplicaId) -> bool) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        for entry in self.log_entries_since(&RGA::new(self.replica_id())) {
+            match entry {
+                LogEntry::Insert { id, author, .. } => {
+                    if keep(id, author.unwrap_or(id.replica_id())) {
+                        entries.push(entry);
+                    }
+                }
+                LogEntry::Delete { id } => entries.extend(self.attributed_delete(id, &keep)),
+                LogEntry::DeleteRange { first, last } => {
+                    for id in range_ids(first, last).into_iter().flatten() {
+                        entries.extend(self.attributed_delete(id, &keep));
+                    }
+                }
+            }
+        }
+        coalesce_deletes(&entries)
+    }
+
+    /// The delete of `id`, if `keep` accepts it with its author
+    fn attributed_delete(
+        &self,
+        id: UniqueId,
+        keep: impl Fn(UniqueId, ReplicaId) -> bool,
+    ) -> Option<LogEntry> {
+        let author = self
+            .get_node(id)
+            .map_or(id.replica_id(), |node| node.author());
+        keep(id, author).then_some(LogEntry::Delete { id })
+    }
+
+    /// The visible text written by some authors.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The replicas whose characters to keep
+    ///
+    /// # Returns
+    ///
+    /// The kept characters, in document order
+    pub fn text_by(&self, filter: &ProvenanceFilter) -> String {
+        self.visible_nodes()
+            .iter()
+            .filter(|node| filter.matches(node.author()))
+            .map(|node| node.character)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::metrics::ApplyOutcome;
+    use crate::crdt::op::Operation;
+
+    #[test]
+    fn test_filters_by_author() {
+        let alice = RGA::new(1);
+        let bot = RGA::new(2);
+        let ids = alice
+            .insert_str_after(alice.sentinel_start_id(), "hi")
+            .unwrap();
+        for node in alice.all_nodes() {
+            if !node.is_sentinel() {
+                bot.apply_remote_op(node);
+            }
+        }
+        let bot_ids = bot.insert_str_after(ids[1], "!!").unwrap();
+        bot.delete(ids[0]).unwrap();
+        bot.delete(bot_ids[1]).unwrap();
+
+        assert_eq!(bot.text_by(&ProvenanceFilter::All), "i!");
+        assert_eq!(bot.text_by(&ProvenanceFilter::only([1])), "i");
+        assert_eq!(bot.text_by(&ProvenanceFilter::mute([1])), "!");
+
+        let history = bot.history(&ProvenanceFilter::only([1]));
+        assert_eq!(history.len(), 3);
+        assert!(matches!(history[1], LogEntry::Delete { id } if id == ids[0]));
+        assert_eq!(bot.history(&ProvenanceFilter::mute([1])).len(), 3);
+        assert_eq!(bot.history(&ProvenanceFilter::All).len(), 6);
+        assert!(!bot.dates_edits());
+        assert!(bot.edits_between(0, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_insertions_by_restricted_writers_are_rejected() {
+        let alice = RGA::new(1);
+        let bot = RGA::new(2);
+        let a = alice.insert_after(alice.sentinel_start_id(), 'a').unwrap();
+        let b = bot.insert_after(bot.sentinel_start_id(), 'b').unwrap();
+        let rga = RGA::new(3);
+        rga.restrict_writers(ProvenanceFilter::mute([2]));
+        assert_eq!(rga.writers(), ProvenanceFilter::mute([2]));
+
+        assert_eq!(
+            rga.apply_remote_op(bot.get_node(b).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::Forbidden(2))
+        );
+        assert_eq!(
+            rga.apply(Operation::Insert {
+                id: b,
+                origin: rga.sentinel_start_id(),
+                right_origin: None,
+                ch: 'b',
+            }),
+            ApplyOutcome::Rejected(RejectReason::Forbidden(2))
+        );
+        assert_eq!(
+            rga.apply_remote_op(alice.get_node(a).unwrap()),
+            ApplyOutcome::Applied
+        );
+        // Deletions are not restricted, and local edits never are
+        bot.merge(&alice);
+        bot.delete(a).unwrap();
+        assert_eq!(
+            rga.apply_remote_op(bot.get_node(a).unwrap()),
+            ApplyOutcome::TombstoneMerge
+        );
+        // New nodes are refused even if they arrive deleted
+        bot.delete(b).unwrap();
+        assert_eq!(
+            rga.apply_remote_op(bot.get_node(b).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::Forbidden(2))
+        );
+        assert!(rga.get_node(b).is_none());
+        rga.insert_after(rga.sentinel_start_id(), 'c').unwrap();
+        assert_eq!(rga.to_string(), "c");
+        assert_eq!(rga.sync_metrics().rejected, 3);
+    }
+
+    #[test]
+    fn test_selects_edits_by_wall_time() {
+        use crate::crdt::types::HybridClock;
+        use core::sync::atomic::{AtomicU64, Ordering};
+        static NOW: AtomicU64 = AtomicU64::new(1_000_000);
+        let rga = RGA::with_clock(HybridClock::with_time_source(1, || {
+            NOW.load(Ordering::Relaxed)
+        }));
+        let yesterday = rga
+            .insert_str_after(rga.sentinel_start_id(), "old ")
+            .unwrap();
+        NOW.store(2_000_000, Ordering::Relaxed);
+        let today = rga.insert_str_after(yesterday[3], "new").unwrap();
+        rga.delete(yesterday[0]).unwrap();
+        rga.delete(today[0]).unwrap();
+
+        assert!(rga.dates_edits());
+        assert_eq!(rga.created_at(yesterday[1]), Some(1_000_000));
+        assert_eq!(rga.created_at(today[2]), Some(2_000_000));
+        assert_eq!(rga.created_at(rga.sentinel_start_id()), None);
+        let edits = rga.edits_between(2_000_000, u64::MAX);
+        assert_eq!(edits.len(), 4);
+        assert!(matches!(edits[1], LogEntry::Delete { id } if id == today[0]));
+        assert_eq!(rga.edits_between(0, 2_000_000).len(), 5);
+        assert!(
+            rga.history_between(&ProvenanceFilter::mute([1]), 0, u64::MAX)
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Ranges of characters addressed by node IDs.
+//!
+//! A [`Range`] spans the characters from one node to another, inclusive, the
+//! way a selection, a mark or a deleted word does. Because it names node IDs
+//! rather than indices, it stays on the same text while other replicas edit
+//! around it, and characters deleted inside it are skipped. [`RGA::range_at`]
+//! takes the range of some visible indices, and the document resolves, reads
+//! and deletes ranges; marks apply formatting over one.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops;
+
+use crate::crdt::markup::{Mark, MarkKind};
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// The characters from `start` to `end`, inclusive, in document order.
+///
+/// A range whose end comes before its start covers nothing.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::RGA;
+///
+/// let rga = RGA::new(1);
+/// let ids = rga
+///     .insert_str_after(rga.sentinel_start_id(), "hello world")
+///     .unwrap();
+///
+/// let word = rga.range_at(6..11).unwrap();
+/// rga.delete(ids[0]).unwrap();
+/// assert_eq!(rga.range_text(&word).unwrap(), "world");
+/// assert_eq!(rga.resolve_range(&word).unwrap(), 5..10);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    /// The first character in the range
+    pub start: UniqueId,
+    /// The last character in the range
+    pub end: UniqueId,
+}
+
+impl Range {
+    /// Creates a range from `start` to `end`, inclusive.
+    pub fn new(start: UniqueId, end: UniqueId) -> Self {
+        Range { start, end }
+    }
+
+    /// Creates a range covering the single character `id`.
+    pub fn single(id: UniqueId) -> Self {
+        Range::new(id, id)
+    }
+
+    /// Formatting of kind `kind` over this range.
+    pub fn mark(self, kind: MarkKind) -> Mark {
+        Mark { kind, range: self }
+    }
+}
+
+impl RGA {
+    /// Creates the range covering the visible characters at `indices`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Range)` - The range from the first to the last character
+    /// * `Err(&str)` - Error message if `indices` is empty or past the end
+    pub fn range_at(&self, indices: ops::Range<usize>) -> Result<Range, &'static str> {
+        let visible = self.visible_nodes();
+        match visible.get(indices) {
+            Some([first, .., last]) => Ok(Range::new(first.id, last.id)),
+            Some([only]) => Ok(Range::single(only.id)),
+            Some([]) => Err("Range is empty"),
+            None => Err("Range outside the document"),
+        }
+    }
+
+    /// Returns the nodes of `range`, tombstones included, in document order
+    pub(crate) fn range_nodes(&self, range: &Range) -> Result<Vec<Node>, &'static str> {
+        if !self.holds(range.start) || !self.holds(range.end) {
+            return Err("Range node not found");
+        }
+        let mut nodes = Vec::new();
+        let mut inside = false;
+        let mut passed = false;
+        self.for_each_node(|node| {
+            if passed {
+                return;
+            }
+            inside |= node.id == range.start;
+            if inside && !node.is_sentinel() {
+                nodes.push(node.clone());
+            }
+            passed = node.id == range.end;
+        });
+        Ok(nodes)
+    }
+
+    /// Resolves a range to the visible indices it currently covers.
+    ///
+    /// A range whose characters were all deleted resolves to an empty range
+    /// at the spot they used to occupy.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Range<usize>)` - The visible indices of the range's characters
+    /// * `Err(&str)` - Error message if either end of the range is unknown
+    pub fn resolve_range(&self, range: &Range) -> Result<ops::Range<usize>, &'static str> {
+        let covered = self
+            .range_nodes(range)?
+            .iter()
+            .filter(|node| node.is_visible())
+            .count();
+        let mut preceding = 0;
+        self.nodes.walk(|id, node| {
+            if id == range.start {
+                return Some(());
+            }
+            preceding += usize::from(node.is_some_and(Node::is_visible));
+            None
+        });
+        Ok(preceding..preceding + covered)
+    }
+
+    /// Returns the visible text of a range.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The range's characters that are not deleted
+    /// * `Err(&str)` - Error message if either end of the range is unknown
+    pub fn range_text(&self, range: &Range) -> Result<String, &'static str> {
+        Ok(self
+            .range_nodes(range)?
+            .iter()
+            .filter(|node| node.is_visible())
+            .map(|node| node.character)
+            .collect())
+    }
+
+    /// Deletes the visible characters of a range, `Range::new(from, to)` for
+    /// the span between two nodes.
+    ///
+    /// The IDs are collected in one walk of the document that stops at the
+    /// end of the range, and the nodes are then deleted by ID. Traversals see
+    /// all of them deleted or none.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document
+    ///   order, to replicate
+    /// * `Err(&str)` - Error message if either end of the range is unknown
+    pub fn delete_range(&self, range: &Range) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        if !self.holds(range.start) || !self.holds(range.end) {
+            return Err("Range node not found");
+        }
+        self.nodes.batch(|| {
+            let mut ids = Vec::new();
+            let mut inside = false;
+            self.nodes.walk(|id, node| {
+                inside |= id == range.start;
+                if inside && node.is_some_and(Node::is_visible) {
+                    ids.push(id);
+                }
+                (id == range.end).then_some(())
+            });
+            for &id in &ids {
+                self.delete(id)?;
+            }
+            Ok(ids)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_ranges_follow_their_text() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "one two three")
+            .unwrap();
+        let two = rga.range_at(4..7).unwrap();
+        assert_eq!(two, Range::new(ids[4], ids[6]));
+        assert!(rga.range_at(3..3).is_err());
+        assert!(rga.range_at(10..14).is_err());
+
+        // Edits before and inside the range
+        rga.delete(ids[0]).unwrap();
+        rga.delete(ids[5]).unwrap();
+        assert_eq!(rga.to_string(), "ne to three");
+        assert_eq!(rga.resolve_range(&two).unwrap(), 3..5);
+        assert_eq!(rga.range_text(&two).unwrap(), "to");
+
+        assert_eq!(rga.delete_range(&two).unwrap(), [ids[4], ids[6]]);
+        assert_eq!(rga.to_string(), "ne  three");
+        assert_eq!(rga.resolve_range(&two).unwrap(), 3..3);
+        assert_eq!(rga.range_text(&two).unwrap(), "");
+
+        // A range ending before it starts deletes nothing
+        let backwards = Range::new(ids[12], ids[8]);
+        assert_eq!(rga.delete_range(&backwards).unwrap(), []);
+        let three = Range::new(ids[8], ids[12]);
+        assert_eq!(rga.delete_range(&three).unwrap(), ids[8..=12]);
+        assert_eq!(rga.to_string(), "ne  ");
+
+        let unknown = Range::single(UniqueId::new(99, 9));
+        assert!(rga.range_text(&unknown).is_err());
+        assert!(rga.delete_range(&unknown).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Compact aliases for replica IDs in encodings.
+//!
+//! Every node ID carries a full replica ID, and in an encoded document most of
+//! them repeat a handful of replicas (the end sentinel's, stored in every
+//! right origin, takes 20 digits alone). Encoders register each replica they
+//! meet in a [`ReplicaRegistry`], which hands out small aliases in order of
+//! first use, write the registry's table once and the aliases everywhere
+//! else. Decoders rebuild the registry from the table and resolve aliases
+//! back.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// Assigns small integer aliases to replica IDs
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ReplicaRegistry {
+    /// Alias of every registered replica
+    aliases: BTreeMap<ReplicaId, u32>,
+    /// Registered replicas, indexed by alias
+    replicas: Vec<ReplicaId>,
+}
+
+impl ReplicaRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the next entry of a table being read, as returned by
+    /// [`ReplicaRegistry::replicas`]
+    pub(crate) fn declare(&mut self, replica: ReplicaId) -> Result<u32, &'static str> {
+        if self.aliases.contains_key(&replica) {
+            return Err("Replica declared twice");
+        }
+        Ok(self.alias(replica))
+    }
+
+    /// The alias of `replica`, registering it if needed
+    pub(crate) fn alias(&mut self, replica: ReplicaId) -> u32 {
+        if let Some(&alias) = self.aliases.get(&replica) {
+            return alias;
+        }
+        let alias = self.replicas.len() as u32;
+        self.aliases.insert(replica, alias);
+        self.replicas.push(replica);
+        alias
+    }
+
+    /// The replica registered under `alias`
+    pub(crate) fn replica(&self, alias: u64) -> Result<ReplicaId, &'static str> {
+        usize::try_from(alias)
+            .ok()
+            .and_then(|alias| self.replicas.get(alias))
+            .copied()
+            .ok_or("Unknown replica alias")
+    }
+
+    /// The table of registered replicas, in alias order
+    pub(crate) fn replicas(&self) -> &[ReplicaId] {
+        &self.replicas
+    }
+
+    /// `id` with its replica replaced by the replica's alias
+    pub(crate) fn alias_id(&mut self, id: UniqueId) -> UniqueId {
+        let alias = self.alias(id.replica_id());
+        UniqueId::new_with_sequence(id.counter(), u64::from(alias), id.sequence())
+    }
+
+    /// Reverses [`ReplicaRegistry::alias_id`]
+    pub(crate) fn resolve_id(&self, id: UniqueId) -> Result<UniqueId, &'static str> {
+        let replica = self.replica(id.replica_id())?;
+        Ok(UniqueId::new_with_sequence(
+            id.counter(),
+            replica,
+            id.sequence(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases_round_trip() {
+        let mut registry = ReplicaRegistry::new();
+        let ids = [
+            UniqueId::new(4, 0xdead_beef_cafe),
+            UniqueId::new(1, ReplicaId::MAX),
+            UniqueId::new_with_sequence(9, 0xdead_beef_cafe, 3),
+        ];
+        let aliased: Vec<UniqueId> = ids.iter().map(|&id| registry.alias_id(id)).collect();
+        assert_eq!(aliased[0], UniqueId::new(4, 0));
+        assert_eq!(aliased[1], UniqueId::new(1, 1));
+        assert_eq!(aliased[2], UniqueId::new_with_sequence(9, 0, 3));
+        assert_eq!(registry.replicas(), [0xdead_beef_cafe, ReplicaId::MAX]);
+
+        let mut decoder = ReplicaRegistry::new();
+        for &replica in registry.replicas() {
+            decoder.declare(replica).unwrap();
+        }
+        for (id, aliased) in ids.iter().zip(&aliased) {
+            assert_eq!(decoder.resolve_id(*aliased), Ok(*id));
+        }
+        assert!(decoder.resolve_id(UniqueId::new(1, 2)).is_err());
+        assert!(decoder.declare(ReplicaId::MAX).is_err());
+    }
+}
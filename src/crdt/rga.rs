@@ -3,17 +3,33 @@
 //! This module contains the main RGA (Replicated Growable Array) struct and its operations.
 //! The RGA provides a conflict-free replicated data type suitable for collaborative text editing.
 
-use crossbeam_skiplist::SkipMap;
-use parking_lot::RwLock;
-use std::sync::Arc;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
 
-use crate::crdt::node::Node;
-use crate::crdt::types::{LamportClock, LamportTimestamp, ReplicaId, UniqueId};
+use crate::crdt::freeze::FreezeRegister;
+use crate::crdt::metrics::{ApplyOutcome, OpCounters, RejectReason};
+use crate::crdt::node::{Element, Node, SENTINEL_END_ID, SENTINEL_START_ID};
+use crate::crdt::normalize::Normalization;
+#[cfg(feature = "profiling")]
+use crate::crdt::profile::{HotPath, Profiler};
+use crate::crdt::provenance::WritersRegister;
+use crate::crdt::store::NodeStore;
+use crate::crdt::types::{Clock, LamportClock, LamportTimestamp, ReplicaId, UniqueId};
 
-/// The Replicated Growable Array (RGA) CRDT.
+/// The Replicated Growable Array (RGA) CRDT over elements of type `T`.
+///
+/// A text document is an [`RGA`], which holds `char`s and adds the text
+/// operations: strings, lines, words, markup and the rest. Any other
+/// [`Element`] (bytes, tokens, application structs) makes a list CRDT with
+/// the same insertion, deletion and replication of single nodes.
 ///
 /// The RGA uses a concurrent SkipMap to store nodes, providing O(log n) operations
-/// with lock-free concurrent access for high performance.
+/// with lock-free concurrent access for high performance. An index over the
+/// document order finds the node at a visible index in O(log n) too. Without
+/// the `std` feature a `BTreeMap` backend is used instead (see
+/// [`crate::crdt::store`]).
 ///
 /// # Design
 ///
@@ -21,18 +37,68 @@ use crate::crdt::types::{LamportClock, LamportTimestamp, ReplicaId, UniqueId};
 /// - SkipMap for concurrent lock-free operations
 /// - Tombstone-based deletion for consistency
 /// - Sentinel nodes for stable reference points
-/// - Thread-safe Lamport clock for timestamp generation
-pub struct RGA {
+/// - Pluggable clock for timestamp generation (a thread-safe Lamport clock by default)
+///
+/// # Concurrency
+///
+/// With the default concurrent store the RGA is `Send + Sync`: share it
+/// behind an `Arc` and edit and read it from any thread through `&self`.
+/// Without `std` it is `Send` only, and with the `single-threaded` feature
+/// neither.
+///
+/// Every edit is atomic: readers see a character inserted or not, deleted or
+/// not, and a run from [`RGA::insert_str_after`] all at once. A deletion is
+/// never undone by a copy of the node applied concurrently. A committed
+/// transaction is only seen whole by a [`RGA::read_txn`], and two queries
+/// made one after the other can straddle an edit: [`RGA::visible_node_count`]
+/// and `to_string` may disagree while another thread writes. Take a read
+/// transaction for queries that must agree.
+pub struct Rga<T = char> {
     /// The unique identifier for this replica
     replica_id: ReplicaId,
-    /// Thread-safe Lamport clock for generating new timestamps
-    clock: LamportClock,
-    /// The core data store: a concurrent SkipMap mapping `UniqueId` to `Node`
-    /// SkipMap provides lock-free concurrent operations with ordered traversal
-    skipmap: Arc<SkipMap<UniqueId, Arc<RwLock<Node>>>>,
+    /// Clock for generating new timestamps
+    pub(crate) clock: Box<dyn Clock>,
+    /// The core data store: the nodes by `UniqueId` and their document order
+    /// (lock-free SkipMaps when the `std` feature is enabled)
+    pub(crate) nodes: NodeStore<T>,
+    /// Normalization applied to text inserted with `insert_str_after`
+    pub(crate) normalization: Normalization,
+    /// Outcomes of remote operations, see [`RGA::sync_metrics`]
+    pub(crate) counters: OpCounters,
+    /// Hot-path timers, see [`RGA::profile`]
+    #[cfg(feature = "profiling")]
+    pub(crate) profiler: Profiler,
+    /// Whether local edits are refused, see [`RGA::freeze`]
+    pub(crate) freeze: FreezeRegister,
+    /// Replicas whose remote insertions are accepted, see
+    /// [`Rga::restrict_writers`]
+    pub(crate) writers: WritersRegister,
+    /// Tombstones moved to disk, see [`RGA::enable_tombstone_spill`]
+    #[cfg(feature = "std")]
+    pub(crate) spill: Option<parking_lot::Mutex<crate::crdt::spill::TombstoneSpill<T>>>,
+    /// Remapped on garbage collection, see [`RGA::register_anchor_holder`]
+    #[cfg(feature = "std")]
+    pub(crate) anchor_holders: crate::crdt::gc::AnchorHolders,
+    /// Deletions recorded for [`RGA::take_deletions`]
+    #[cfg(feature = "std")]
+    pub(crate) deletion_log: crate::crdt::gc::DeletionLog,
+    /// The newest node garbage collected so far, see [`RGA::collect_garbage`]
+    #[cfg(feature = "std")]
+    pub(crate) collected: crate::crdt::gc::Collected,
 }
 
-impl RGA {
+/// A collaborative text document: an [`Rga`] of characters.
+pub type RGA = Rga<char>;
+
+// The server and applications share documents between threads; a field that
+// is not thread-safe must fail to build here rather than in their code
+#[cfg(all(feature = "std", not(feature = "single-threaded")))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RGA>();
+};
+
+impl<T: Element> Rga<T> {
     /// Creates a new RGA instance, initialized with sentinel nodes.
     ///
     /// # Arguments
@@ -43,19 +109,47 @@ impl RGA {
     ///
     /// A new RGA instance with sentinel start and end nodes
     pub fn new(replica_id: ReplicaId) -> Self {
-        let skipmap = Arc::new(SkipMap::new());
+        Self::with_clock(LamportClock::new(replica_id))
+    }
 
-        // Insert sentinel nodes
-        let start_node = Node::sentinel_start();
-        let end_node = Node::sentinel_end();
+    /// Creates a new RGA instance that stamps local operations with `clock`.
+    ///
+    /// The replica ID is taken from the clock. This is how tests inject
+    /// deterministic or skewed clocks, and how applications plug in hybrid
+    /// logical or externally coordinated clocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - The clock to generate timestamps with
+    ///
+    /// # Returns
+    ///
+    /// A new RGA instance with sentinel start and end nodes
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        let nodes = NodeStore::new();
 
-        skipmap.insert(start_node.id, Arc::new(RwLock::new(start_node)));
-        skipmap.insert(end_node.id, Arc::new(RwLock::new(end_node)));
+        // Insert sentinel nodes
+        nodes.insert(Node::sentinel_start());
+        nodes.insert(Node::sentinel_end());
 
-        RGA {
-            replica_id,
-            clock: LamportClock::new(replica_id),
-            skipmap,
+        Rga {
+            replica_id: clock.replica_id(),
+            clock: Box::new(clock),
+            nodes,
+            normalization: Normalization::default(),
+            counters: OpCounters::new(),
+            #[cfg(feature = "profiling")]
+            profiler: Profiler::new(),
+            freeze: FreezeRegister::default(),
+            writers: WritersRegister::default(),
+            #[cfg(feature = "std")]
+            spill: None,
+            #[cfg(feature = "std")]
+            anchor_holders: Default::default(),
+            #[cfg(feature = "std")]
+            deletion_log: Default::default(),
+            #[cfg(feature = "std")]
+            collected: Default::default(),
         }
     }
 
@@ -72,22 +166,23 @@ impl RGA {
     /// Generates a new unique identifier for a local operation.
     ///
     /// Uses the thread-safe Lamport clock to generate timestamps.
-    fn new_local_id(&self) -> UniqueId {
+    pub(crate) fn new_local_id(&self) -> UniqueId {
         UniqueId::from(self.clock.tick())
     }
 
     /// Updates the local Lamport clock based on a received timestamp.
     ///
     /// This ensures causal consistency when receiving remote operations.
-    fn update_clock(&self, received_timestamp: LamportTimestamp) {
+    pub(crate) fn update_clock(&self, received_timestamp: LamportTimestamp) {
         self.clock.update(received_timestamp);
     }
 
     /// Inserts a character after the node identified by `after_id`.
     ///
-    /// This method generates a new `UniqueId` for the inserted character.
-    /// The B-tree's natural ordering handles placement according to the
-    /// total order defined by UniqueId.
+    /// This method generates a new `UniqueId` for the inserted character and
+    /// records `after_id` as its origin. The new node lands right after
+    /// `after_id`; text other replicas insert concurrently at the same spot
+    /// is ordered by ID, newest first, identically on every replica.
     ///
     /// # Arguments
     ///
@@ -98,26 +193,158 @@ impl RGA {
     ///
     /// * `Ok(UniqueId)` - The ID of the newly inserted node
     /// * `Err(&str)` - Error message if the operation fails
-    pub fn insert_after(
-        &self,
-        after_id: UniqueId,
-        character: char,
-    ) -> Result<UniqueId, &'static str> {
-        let new_node_id = self.new_local_id();
-        let new_node = Node::new(new_node_id, character);
-
+    #[cfg_attr(feature = "profiling", inline(never))]
+    pub fn insert_after(&self, after_id: UniqueId, character: T) -> Result<UniqueId, &'static str> {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Insert);
+        self.ensure_unfrozen()?;
         // Check if `after_id` exists. If not, we can't insert after it.
-        if !self.skipmap.contains_key(&after_id) {
+        if !self.holds(after_id) {
             return Err("Reference node for insertion not found");
         }
 
-        // The SkipMap automatically handles placing `new_node` according to its `id`.
-        // The `UniqueId` (Lamport timestamp + replica ID + sequence) ensures a globally consistent sort order.
-        self.skipmap
-            .insert(new_node.id, Arc::new(RwLock::new(new_node)));
+        let new_node_id = self.new_local_id();
+        self.insert_with_id(new_node_id, after_id, self.right_of(after_id), character);
+        self.debug_validate(new_node_id);
         Ok(new_node_id)
     }
 
+    /// Inserts a character at a visible index.
+    ///
+    /// The character lands before the one currently at `index`, or at the
+    /// end when `index` is the length, as if typed after the character
+    /// preceding it.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Visible index to insert at (0 ..= length)
+    /// * `character` - The character to insert
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UniqueId)` - The ID of the newly inserted node
+    /// * `Err(&str)` - Error message if `index` is past the end
+    pub fn insert_at(&self, index: usize, character: T) -> Result<UniqueId, &'static str> {
+        let after_id = self.insertion_point(index)?;
+        self.insert_after(after_id, character)
+    }
+
+    /// The node to insert after so that new text lands at visible `index`
+    pub(crate) fn insertion_point(&self, index: usize) -> Result<UniqueId, &'static str> {
+        match index {
+            0 => Ok(self.sentinel_start_id()),
+            _ => Ok(self
+                .visible_ids(index - 1..index)
+                .ok_or("Index out of range")?[0]),
+        }
+    }
+
+    /// The IDs of the visible characters at `indices`, found in O(log n)
+    /// each.
+    ///
+    /// Returns `None` if `indices` reaches past the end.
+    pub(crate) fn visible_ids(&self, indices: core::ops::Range<usize>) -> Option<Vec<UniqueId>> {
+        self.nodes.visible_ids(indices)
+    }
+
+    /// Appends a character at the end of the document.
+    ///
+    /// The last node is found without walking the document, so logs that
+    /// only ever grow at the end, like a chat history, append in constant
+    /// time however long they get.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UniqueId)` - The ID of the newly inserted node
+    /// * `Err(&str)` - Error message if the document is frozen
+    pub fn append(&self, character: T) -> Result<UniqueId, &'static str> {
+        self.insert_after(self.nodes.last(), character)
+    }
+
+    /// Inserts `elements` after the node identified by `after_id` as one
+    /// run, the way [`RGA::insert_str_after`] inserts text.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_id` - The UniqueId of the node to insert after
+    /// * `elements` - The elements to insert
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, in order
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    pub fn insert_slice_after(
+        &self,
+        after_id: UniqueId,
+        elements: &[T],
+    ) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        if !self.holds(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        Ok(self.insert_elements(after_id, elements.to_vec()))
+    }
+
+    /// Appends `elements` at the end of the document as one run, see
+    /// [`Rga::append`] and [`Rga::insert_slice_after`].
+    pub fn append_slice(&self, elements: &[T]) -> Result<Vec<UniqueId>, &'static str> {
+        self.insert_slice_after(self.nodes.last(), elements)
+    }
+
+    /// Inserts `elements` after `after_id` as one chained run stamped with a
+    /// single Lamport counter, in one write to the store.
+    ///
+    /// Callers are responsible for validating the reference node first.
+    pub(crate) fn insert_elements(&self, after_id: UniqueId, elements: Vec<T>) -> Vec<UniqueId> {
+        let count = elements.len();
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let ids: Vec<UniqueId> = self
+            .clock
+            .tick_batch(count)
+            .into_iter()
+            .map(UniqueId::from)
+            .collect();
+        // Traversals see the whole run or none of it
+        self.nodes.batch(|| {
+            // The whole run was typed between `after_id` and its neighbor
+            let right_origin = self.right_of(after_id);
+            let origins = core::iter::once(after_id).chain(ids.iter().copied());
+            self.nodes.extend(ids.iter().zip(origins).zip(elements).map(
+                |((&id, origin), character)| {
+                    Node::with_origins(id, character, origin, right_origin)
+                },
+            ));
+        });
+        for &id in &ids {
+            self.debug_validate(id);
+        }
+        ids
+    }
+
+    /// Inserts a locally generated node with a pre-allocated ID.
+    ///
+    /// Callers are responsible for validating the reference node first.
+    pub(crate) fn insert_with_id(
+        &self,
+        id: UniqueId,
+        after_id: UniqueId,
+        right_origin: Option<UniqueId>,
+        character: T,
+    ) {
+        // The store integrates the node after its origin
+        self.nodes
+            .insert(Node::with_origins(id, character, after_id, right_origin));
+    }
+
+    /// The node following `after_id` in the document, tombstones included:
+    /// the right origin of a node inserted after it now
+    pub(crate) fn right_of(&self, after_id: UniqueId) -> Option<UniqueId> {
+        self.nodes.successor(&after_id)
+    }
+
     /// Logically deletes a character identified by its `UniqueId`.
     ///
     /// This sets the `is_deleted` flag to true (tombstone approach).
@@ -131,12 +358,65 @@ impl RGA {
     /// * `Ok(())` - If the deletion was successful
     /// * `Err(&str)` - Error message if the operation fails
     pub fn delete(&self, id_to_delete: UniqueId) -> Result<(), &'static str> {
-        if let Some(entry) = self.skipmap.get(&id_to_delete) {
-            let mut node = entry.value().write();
-            node.delete()
-        } else {
-            Err("Node to delete not found")
+        self.ensure_unfrozen()?;
+        let result = match self.nodes.update(&id_to_delete, Node::delete) {
+            Some(result) => result,
+            // Spilled nodes are already deleted
+            None if self.spilled_node(id_to_delete).is_some() => Ok(()),
+            None => Err("Node to delete not found"),
+        };
+        if result.is_ok() {
+            self.note_deletion(id_to_delete);
+        }
+        self.debug_validate(id_to_delete);
+        result
+    }
+
+    /// Logically deletes the character at a visible index.
+    ///
+    /// The character is found in O(log n).
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Visible index of the character (0 .. length)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UniqueId)` - The ID of the deleted node
+    /// * `Err(&str)` - Error message if `index` is out of range
+    pub fn delete_at(&self, index: usize) -> Result<UniqueId, &'static str> {
+        Ok(self.delete_range_at(index, 1)?[0])
+    }
+
+    /// Logically deletes `len` visible characters starting at `start`.
+    ///
+    /// Every character is found before any is deleted, and traversals see all
+    /// of them deleted or none. Each one is found in O(log n), without
+    /// walking the document.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Visible index of the first character
+    /// * `len` - Number of characters to delete
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document order
+    /// * `Err(&str)` - Error message if the characters reach past the end;
+    ///   nothing is deleted
+    pub fn delete_range_at(&self, start: usize, len: usize) -> Result<Vec<UniqueId>, &'static str> {
+        self.ensure_unfrozen()?;
+        let end = start.checked_add(len).ok_or("Index out of range")?;
+        if len == 0 && start > self.visible_node_count() {
+            return Err("Index out of range");
         }
+        self.nodes.batch(|| {
+            let ids = self.visible_ids(start..end).ok_or("Index out of range")?;
+            for &id in &ids {
+                self.delete(id)?;
+            }
+            Ok(ids)
+        })
     }
 
     /// Applies a remote operation by integrating a received `Node` into the local RGA.
@@ -144,80 +424,285 @@ impl RGA {
     /// This implicitly handles concurrent inserts/deletes due to CRDT properties.
     /// The method updates the local Lamport clock and integrates the remote node.
     ///
+    /// A node already held only takes the remote tombstone: deletion wins, so
+    /// an older copy sent before the deletion never revives the node, and a
+    /// copy that deletes nothing new, an exact duplicate included, changes
+    /// nothing. Either way the remote copy's other fields are ignored.
+    ///
+    /// A new node whose origin the document lacks is refused rather than
+    /// placed at a guess; deliver through a [`crate::CausalBuffer`] to wait
+    /// for the origin instead. If the origin is no newer than a node this
+    /// replica garbage collected, it may never arrive, and the refusal says
+    /// so. New nodes by replicas [`Rga::restrict_writers`] excludes are
+    /// refused as well.
+    ///
     /// # Arguments
     ///
     /// * `remote_node` - The node received from a remote replica
-    pub fn apply_remote_op(&self, remote_node: Node) {
+    ///
+    /// # Returns
+    ///
+    /// * [`ApplyOutcome::Applied`] - If the node was new
+    /// * [`ApplyOutcome::TombstoneMerge`] - If the copy deleted a held node
+    /// * [`ApplyOutcome::Duplicate`] - If nothing changed
+    /// * [`ApplyOutcome::Rejected`] - If the copy deletes a sentinel, its
+    ///   replica may not write or its origin is missing; nothing changed
+    #[cfg_attr(feature = "profiling", inline(never))]
+    pub fn apply_remote_op(&self, remote_node: Node<T>) -> ApplyOutcome {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::ApplyRemote);
+        // A node we already hold only changes if the copy deletes it; spilled
+        // tombstones are deleted already
+        let id = remote_node.id;
+        let local = self.get_node(id);
+        let rejected = match &local {
+            Some(_) if remote_node.is_sentinel() && remote_node.is_deleted => {
+                Some(RejectReason::Sentinel)
+            }
+            None => self.forbidden(&remote_node).or_else(|| {
+                let origin = remote_node.origin.filter(|&origin| !self.holds(origin))?;
+                Some(if self.may_be_collected(origin) {
+                    RejectReason::Collected(origin)
+                } else {
+                    RejectReason::MissingOrigin(origin)
+                })
+            }),
+            Some(_) => None,
+        };
+        if let Some(reason) = rejected {
+            self.record_rejected();
+            return ApplyOutcome::Rejected(reason);
+        }
+
         // Update local Lamport clock
         self.update_clock(remote_node.id.timestamp());
+        let (remote_node, outcome) = match local {
+            Some(local) if local.is_deleted || !remote_node.is_deleted => {
+                self.record_deduplicated();
+                return ApplyOutcome::Duplicate;
+            }
+            Some(local) => (
+                Node {
+                    is_deleted: true,
+                    ..local
+                },
+                ApplyOutcome::TombstoneMerge,
+            ),
+            None => (remote_node, ApplyOutcome::Applied),
+        };
 
-        // Insert or update the remote node. SkipMap handles sorting by UniqueId.
-        // If a node with the same ID already exists, it gets replaced
-        // (which is important for updates like `is_deleted`).
-        self.skipmap
-            .insert(remote_node.id, Arc::new(RwLock::new(remote_node)));
+        // A new node is integrated after its origin; a held one is updated in
+        // place and stays deleted even if a stale copy races this one
+        let is_deleted = remote_node.is_deleted;
+        self.nodes.insert(remote_node);
+        self.record_applied();
+        if is_deleted {
+            self.note_deletion(id);
+        }
+        self.debug_validate(id);
+        outcome
     }
 
-    /// Returns the current visible content of the RGA as a String.
+    /// Returns all nodes (including deleted and sentinel) in document order.
+    pub fn all_nodes(&self) -> Vec<Node<T>> {
+        let mut nodes = Vec::with_capacity(self.total_node_count());
+        self.for_each_node(|node| nodes.push(node.clone()));
+        nodes
+    }
+
+    /// Visits every node in document order, tombstones spilled to disk
+    /// included.
     ///
-    /// Filters out deleted nodes and sentinel characters to show only
-    /// the actual document content.
-    pub fn to_string(&self) -> String {
-        self.skipmap
-            .iter()
-            .filter_map(|entry| {
-                let node = entry.value().read();
-                if node.is_visible() {
-                    Some(node.character)
-                } else {
-                    None
-                }
-            })
-            .collect()
+    /// Reads every spilled tombstone; the visible text never needs this.
+    #[cfg_attr(feature = "profiling", inline(never))]
+    pub(crate) fn for_each_node(&self, mut f: impl FnMut(&Node<T>)) {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Traversal);
+        let spilled = self.spilled_nodes();
+        self.nodes.walk(|id, node| {
+            match node {
+                Some(node) => f(node),
+                None => match spilled.binary_search_by_key(&id, |node| node.id) {
+                    Ok(index) => f(&spilled[index]),
+                    // Spilled while the walk was under way
+                    Err(_) => {
+                        if let Some(node) = self.spilled_node(id) {
+                            f(&node);
+                        }
+                    }
+                },
+            }
+            None::<()>
+        });
     }
 
-    /// Returns all nodes (including deleted and sentinel) for debugging.
-    pub fn all_nodes(&self) -> Vec<Node> {
-        self.skipmap
-            .iter()
-            .map(|entry| entry.value().read().clone())
-            .collect()
+    /// Visits every node in ID order, tombstones spilled to disk included.
+    ///
+    /// Cheaper than [`RGA::for_each_node`] where the order does not matter,
+    /// and what ID-ordered encodings are built from.
+    pub(crate) fn for_each_node_by_id(&self, mut f: impl FnMut(&Node<T>)) {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Traversal);
+        let spilled = self.spilled_nodes();
+        let mut spilled = spilled.iter().peekable();
+        self.nodes.for_each_by_id(|node| {
+            while let Some(tombstone) = spilled.next_if(|tombstone| tombstone.id < node.id) {
+                f(tombstone);
+            }
+            f(node);
+        });
+        spilled.for_each(f);
+    }
+
+    /// Places `node` right after `previous`, as it stood in the document it
+    /// was copied from, and moves the clock past it.
+    ///
+    /// Copying a document node by node in document order this way rebuilds
+    /// its order exactly.
+    pub(crate) fn place_after(&self, previous: UniqueId, node: Node<T>) {
+        self.update_clock(node.id.timestamp());
+        self.nodes.insert_at(previous, node);
+    }
+
+    /// Returns true if the document holds node `id`, in memory or spilled
+    pub(crate) fn holds(&self, id: UniqueId) -> bool {
+        self.nodes.contains(&id) || self.spilled_node(id).is_some()
     }
 
     /// Returns only visible nodes (excluding deleted and sentinel nodes).
-    pub fn visible_nodes(&self) -> Vec<Node> {
-        self.skipmap
-            .iter()
-            .filter_map(|entry| {
-                let node = entry.value().read();
-                if node.is_visible() {
-                    Some(node.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
+    #[cfg_attr(feature = "profiling", inline(never))]
+    pub fn visible_nodes(&self) -> Vec<Node<T>> {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Traversal);
+        let mut nodes = Vec::new();
+        self.nodes.for_each(|node| {
+            if node.is_visible() {
+                nodes.push(node.clone());
+            }
+        });
+        nodes
+    }
+
+    /// Returns the visible elements in document order: the characters of a
+    /// text document, which `to_string` renders instead.
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut elements = Vec::new();
+        self.nodes.for_each(|node| {
+            if node.is_visible() {
+                elements.push(node.character.clone());
+            }
+        });
+        elements
     }
 
     /// Gets the number of total nodes (including deleted and sentinel).
     pub fn total_node_count(&self) -> usize {
-        self.skipmap.len()
+        self.stored_count()
     }
 
     /// Gets the number of visible nodes (excluding deleted and sentinel).
     pub fn visible_node_count(&self) -> usize {
-        self.skipmap
-            .iter()
-            .filter(|entry| entry.value().read().is_visible())
-            .count()
+        self.nodes.visible_len()
+    }
+
+    /// The newest Lamport counter of each replica whose insertions the
+    /// document holds, tombstones included.
+    pub fn version_vector(&self) -> BTreeMap<ReplicaId, u64> {
+        let mut vector = BTreeMap::new();
+        self.for_each_node_by_id(|node| {
+            if !node.is_sentinel() {
+                let counter = vector.entry(node.id.replica_id()).or_insert(0);
+                *counter = (*counter).max(node.id.counter());
+            }
+        });
+        vector
+    }
+
+    /// Returns a copy of the node with the given ID, if it exists.
+    pub fn get_node(&self, id: UniqueId) -> Option<Node<T>> {
+        self.nodes.get(&id).or_else(|| self.spilled_node(id))
+    }
+
+    /// Gets the sentinel start node ID.
+    pub fn sentinel_start_id(&self) -> UniqueId {
+        SENTINEL_START_ID
+    }
+
+    /// Gets the sentinel end node ID.
+    pub fn sentinel_end_id(&self) -> UniqueId {
+        SENTINEL_END_ID
+    }
+}
+
+impl RGA {
+    /// Inserts a string after the node identified by `after_id`.
+    ///
+    /// The text is normalized according to [`RGA::normalization`] and then
+    /// inserted as a chained run, each character after the previous one. The
+    /// run is stamped as one batch sharing a Lamport counter, so text another
+    /// replica inserts concurrently at the same spot cannot interleave with
+    /// it: each run stays contiguous after a merge.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_id` - The UniqueId of the node to insert after
+    /// * `text` - The text to insert
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, in text order
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    #[cfg_attr(feature = "profiling", inline(never))]
+    pub fn insert_str_after(
+        &self,
+        after_id: UniqueId,
+        text: &str,
+    ) -> Result<Vec<UniqueId>, &'static str> {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Insert);
+        self.ensure_unfrozen()?;
+        if !self.holds(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        Ok(self.insert_run(after_id, &self.normalization.apply(text)))
+    }
+
+    /// Inserts a string at a visible index, as one run like
+    /// [`RGA::insert_str_after`].
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Visible index to insert at (0 ..= length)
+    /// * `text` - The text to insert
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the inserted nodes, in text order
+    /// * `Err(&str)` - Error message if `index` is past the end
+    pub fn insert_str_at(&self, index: usize, text: &str) -> Result<Vec<UniqueId>, &'static str> {
+        let after_id = self.insertion_point(index)?;
+        self.insert_str_after(after_id, text)
+    }
+
+    /// Appends a string at the end of the document as one run, see
+    /// [`RGA::append`] and [`RGA::insert_str_after`].
+    pub fn append_str(&self, text: &str) -> Result<Vec<UniqueId>, &'static str> {
+        self.insert_str_after(self.nodes.last(), text)
+    }
+
+    /// Inserts already normalized text after `after_id` as one chained run,
+    /// see [`Rga::insert_elements`].
+    ///
+    /// Callers are responsible for validating the reference node first.
+    pub(crate) fn insert_run(&self, after_id: UniqueId, text: &str) -> Vec<UniqueId> {
+        self.insert_elements(after_id, text.chars().collect())
     }
 
     /// For debugging: prints all nodes including sentinels and deleted.
+    #[cfg(feature = "std")]
     pub fn dump_nodes(&self) {
         println!("--- RGA Node Dump (Replica ID: {}) ---", self.replica_id);
-        for entry in self.skipmap.iter() {
-            let id = entry.key();
-            let node = entry.value().read();
+        self.nodes.for_each(|node| {
             let status = if node.is_sentinel() {
                 "SENTINEL"
             } else if node.is_deleted {
@@ -225,17 +710,19 @@ impl RGA {
             } else {
                 "ACTIVE"
             };
-            println!("{:?} -> Char: '{}', Status: {}", id, node.character, status);
-        }
-        println!("Content: '{}'", self.to_string());
+            println!(
+                "{:?} -> Char: '{}', Status: {}, Origin: {:?}, Right origin: {:?}",
+                node.id, node.character, status, node.origin, node.right_origin
+            );
+        });
+        println!("Content: '{}'", self);
         println!("------------------------------------");
     }
 
     /// Finds a node by its character (useful for examples/testing).
     /// Returns the first non-deleted node with the given character.
     pub fn find_node_by_char(&self, character: char) -> Option<UniqueId> {
-        self.skipmap.iter().find_map(|entry| {
-            let node = entry.value().read();
+        self.nodes.find_map(|node| {
             if node.character == character && !node.is_deleted {
                 Some(node.id)
             } else {
@@ -243,32 +730,96 @@ impl RGA {
             }
         })
     }
+}
 
-    /// Gets the sentinel start node ID.
-    pub fn sentinel_start_id(&self) -> UniqueId {
-        Node::sentinel_start().id
+impl fmt::Display for RGA {
+    /// Writes the current visible content of the RGA.
+    ///
+    /// Filters out deleted nodes and sentinel characters to show only
+    /// the actual document content.
+    #[cfg_attr(feature = "profiling", inline(never))]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "profiling")]
+        let _timer = self.profiler.time(HotPath::Render);
+        self.nodes
+            .find_map(|node| {
+                if node.is_visible() {
+                    f.write_char(node.character).err()
+                } else {
+                    None
+                }
+            })
+            .map_or(Ok(()), Err)
+    }
+}
+
+/// Without `std` nothing is ever spilled or garbage collected
+#[cfg(not(feature = "std"))]
+impl<T: Element> Rga<T> {
+    pub(crate) fn note_deletion(&self, _id: UniqueId) {}
+
+    pub(crate) fn may_be_collected(&self, _id: UniqueId) -> bool {
+        false
     }
 
-    /// Gets the sentinel end node ID.
-    pub fn sentinel_end_id(&self) -> UniqueId {
-        Node::sentinel_end().id
+    pub(crate) fn spilled_node(&self, _id: UniqueId) -> Option<Node<T>> {
+        None
+    }
+
+    pub(crate) fn spilled_nodes(&self) -> Vec<Node<T>> {
+        Vec::new()
+    }
+
+    pub(crate) fn stored_count(&self) -> usize {
+        self.nodes.len()
     }
 }
 
-impl Clone for RGA {
+impl<T: Element> Clone for Rga<T> {
     fn clone(&self) -> Self {
-        let skipmap_clone = Arc::new(SkipMap::new());
+        let nodes = NodeStore::new();
+        let freeze = FreezeRegister::default();
+        freeze.set(self.freeze.get());
+        let writers = WritersRegister::default();
+        writers.set(self.writers.get());
 
-        // Copy all entries from the original skipmap
-        for entry in self.skipmap.iter() {
-            let node = entry.value().read().clone();
-            skipmap_clone.insert(*entry.key(), Arc::new(RwLock::new(node)));
-        }
+        // Copy all entries from the original store in document order,
+        // spilled tombstones included: the clone keeps every node in memory
+        let clock = LamportClock::new(self.replica_id);
+        let mut previous = SENTINEL_START_ID;
+        self.for_each_node(|node| {
+            if !node.is_sentinel() {
+                clock.update(node.id.timestamp());
+            }
+            nodes.insert_at(previous, node.clone());
+            previous = node.id;
+        });
+        // The original's clock may be ahead of its nodes, and the clone must
+        // never hand out an ID it already handed out
+        clock.update(LamportTimestamp {
+            counter: self.current_clock(),
+            replica_id: self.replica_id,
+            sequence: 0,
+        });
 
-        RGA {
+        Rga {
             replica_id: self.replica_id,
-            clock: LamportClock::new(self.replica_id),
-            skipmap: skipmap_clone,
+            clock: Box::new(clock),
+            nodes,
+            normalization: self.normalization,
+            counters: OpCounters::new(),
+            #[cfg(feature = "profiling")]
+            profiler: Profiler::new(),
+            freeze,
+            writers,
+            #[cfg(feature = "std")]
+            spill: None,
+            #[cfg(feature = "std")]
+            anchor_holders: Default::default(),
+            #[cfg(feature = "std")]
+            deletion_log: Default::default(),
+            #[cfg(feature = "std")]
+            collected: self.collected.clone(),
         }
     }
 }
@@ -276,6 +827,8 @@ impl Clone for RGA {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crdt::node::SENTINEL_START_CHAR;
+    use crate::testing::assert_converged;
 
     #[test]
     fn test_rga_creation() {
@@ -302,6 +855,82 @@ mod tests {
         assert_eq!(rga.visible_node_count(), 2);
     }
 
+    #[test]
+    fn test_insert_at_index() {
+        let rga = RGA::new(1);
+        rga.insert_at(0, 'b').unwrap();
+        rga.insert_at(0, 'a').unwrap();
+        let d = rga.insert_at(2, 'd').unwrap();
+        rga.insert_at(2, 'c').unwrap();
+        assert_eq!(rga.to_string(), "abcd");
+
+        // Deleted characters are not counted
+        rga.delete(d).unwrap();
+        rga.insert_at(3, 'e').unwrap();
+        assert_eq!(rga.to_string(), "abce");
+        assert_eq!(rga.insert_at(5, 'x'), Err("Index out of range"));
+        assert_eq!(rga.to_string(), "abce");
+
+        // A run shares one Lamport counter
+        let clock = rga.current_clock();
+        let ids = rga.insert_str_at(2, "xyz").unwrap();
+        assert_eq!(rga.to_string(), "abxyzce");
+        assert!(ids.iter().all(|id| id.counter() == clock + 1));
+        assert_eq!(rga.insert_str_at(8, "!"), Err("Index out of range"));
+    }
+
+    #[test]
+    fn test_delete_at_index() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "abcdef")
+            .unwrap();
+        assert_eq!(rga.delete_at(1), Ok(ids[1]));
+        assert_eq!(rga.delete_range_at(1, 2), Ok(vec![ids[2], ids[3]]));
+        assert_eq!(rga.to_string(), "aef");
+
+        // Out of range deletes nothing
+        assert_eq!(rga.delete_at(3), Err("Index out of range"));
+        assert_eq!(rga.delete_range_at(2, 2), Err("Index out of range"));
+        assert_eq!(rga.delete_range_at(3, 0), Ok(vec![]));
+        assert_eq!(rga.delete_range_at(4, 0), Err("Index out of range"));
+        assert_eq!(rga.to_string(), "aef");
+    }
+
+    #[test]
+    fn test_index_follows_concurrent_edits() {
+        let replicas = [RGA::new(1), RGA::new(2), RGA::new(3)];
+        for step in 0..300usize {
+            let rga = &replicas[step % 3];
+            let len = rga.visible_node_count();
+            match step % 5 {
+                0 | 1 => {
+                    rga.insert_at(step * 7 % (len + 1), 'x').unwrap();
+                }
+                2 if len > 0 => {
+                    rga.delete_at(step * 13 % len).unwrap();
+                }
+                3 if len > 2 => {
+                    rga.delete_range_at(step % (len - 2), 2).unwrap();
+                }
+                _ => {
+                    rga.merge(&replicas[(step + 1) % 3]);
+                }
+            }
+            let visible: Vec<UniqueId> = rga.visible_nodes().iter().map(|node| node.id).collect();
+            assert_eq!(rga.visible_node_count(), visible.len());
+            assert_eq!(rga.visible_ids(0..visible.len()), Some(visible));
+        }
+        for rga in &replicas {
+            rga.merge(&replicas[0]);
+            rga.merge(&replicas[1]);
+            rga.merge(&replicas[2]);
+        }
+        assert_converged(&replicas);
+        let clone = replicas[0].clone();
+        assert_eq!(clone.delete_at(1), replicas[0].delete_at(1));
+    }
+
     #[test]
     fn test_deletion() {
         let rga = RGA::new(1);
@@ -326,13 +955,88 @@ mod tests {
         let a_id = rga1.insert_after(start_id, 'A').unwrap();
 
         // Apply RGA1's operation to RGA2
-        let node_a = rga1.all_nodes().into_iter().find(|n| n.id == a_id).unwrap();
+        let node_a = rga1.get_node(a_id).unwrap();
         rga2.apply_remote_op(node_a);
 
-        assert_eq!(rga1.to_string(), rga2.to_string());
+        assert_converged([&rga1, &rga2]);
         assert_eq!(rga2.to_string(), "A");
     }
 
+    #[test]
+    fn test_remote_copies_never_revive_tombstones() {
+        let first = RGA::new(1);
+        let second = RGA::new(2);
+        let id = first.insert_after(first.sentinel_start_id(), 'A').unwrap();
+        let inserted = first.get_node(id).unwrap();
+        assert_eq!(
+            second.apply_remote_op(inserted.clone()),
+            ApplyOutcome::Applied
+        );
+        first.delete(id).unwrap();
+        let deleted = first.get_node(id).unwrap();
+
+        // The deletion wins over copies older or newer than it
+        assert_eq!(
+            second.apply_remote_op(deleted.clone()),
+            ApplyOutcome::TombstoneMerge
+        );
+        assert_eq!(
+            second.apply_remote_op(inserted.clone()),
+            ApplyOutcome::Duplicate
+        );
+        second.apply_remote_op(Node {
+            character: 'B',
+            ..inserted
+        });
+        assert_eq!(second.apply_remote_op(deleted), ApplyOutcome::Duplicate);
+        assert_eq!(second.to_string(), "");
+        assert_eq!(second.get_node(id).unwrap().character, 'A');
+        let metrics = second.sync_metrics();
+        assert_eq!((metrics.applied, metrics.deduplicated), (2, 3));
+        assert_converged([&first, &second]);
+    }
+
+    #[test]
+    fn test_remote_copies_that_cannot_apply_are_rejected() {
+        let first = RGA::new(1);
+        let second = RGA::new(2);
+        let a_id = first.insert_after(first.sentinel_start_id(), 'a').unwrap();
+        let b_id = first.insert_after(a_id, 'b').unwrap();
+
+        // `b` is refused until `a` arrives, rather than placed by guesswork
+        assert_eq!(
+            second.apply_remote_op(first.get_node(b_id).unwrap()),
+            ApplyOutcome::Rejected(RejectReason::MissingOrigin(a_id))
+        );
+        let mut start = Node::sentinel_start();
+        start.is_deleted = true;
+        assert_eq!(
+            second.apply_remote_op(start),
+            ApplyOutcome::Rejected(RejectReason::Sentinel)
+        );
+        assert_eq!(second.total_node_count(), 2);
+        assert_eq!(second.sync_metrics().rejected, 2);
+        assert!(second.validate().is_ok());
+    }
+
+    #[test]
+    fn test_clones_never_reuse_ids() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "abc")
+            .unwrap();
+        // Received operations move the clock past the document's own nodes
+        rga.update_clock(UniqueId::new(10, 2).timestamp());
+
+        let clone = rga.clone();
+        let x = clone.insert_after(clone.sentinel_start_id(), 'X').unwrap();
+        assert!(!ids.contains(&x));
+        assert!(x.counter() > 10);
+        assert_eq!(clone.to_string(), "Xabc");
+        assert_eq!(rga.to_string(), "abc");
+        assert!(clone.validate().is_ok());
+    }
+
     #[test]
     fn test_concurrent_operations() {
         let rga1 = RGA::new(1);
@@ -351,8 +1055,209 @@ mod tests {
         rga1.apply_remote_op(node_b);
 
         // Both should converge to the same state
-        assert_eq!(rga1.to_string(), rga2.to_string());
-        // Due to UniqueId ordering, 'A' (from replica 1) should come before 'B' (from replica 2)
-        assert_eq!(rga1.to_string(), "AB");
+        assert_converged([&rga1, &rga2]);
+        // Concurrent insertions at the same spot are ordered newest first:
+        // 'B' (from replica 2) has the greater ID
+        assert_eq!(rga1.to_string(), "BA");
+        assert_eq!(
+            rga1.version_vector(),
+            BTreeMap::from([(1, a_id.counter()), (2, b_id.counter())])
+        );
+        assert_eq!(rga1.version_vector(), rga2.version_vector());
+    }
+
+    #[test]
+    fn test_insertions_follow_their_origin() {
+        let rga1 = RGA::new(1);
+        let ids = rga1
+            .insert_str_after(rga1.sentinel_start_id(), "abc")
+            .unwrap();
+        let x = rga1.insert_after(ids[0], 'x').unwrap();
+        assert_eq!(rga1.to_string(), "axbc");
+        assert_eq!(rga1.right_of(x), Some(ids[1]));
+
+        // A replica far ahead inserts after 'b' while 'y' is inserted after
+        // 'a' here; both stay next to the node they were inserted after
+        let rga2 = RGA::new(2);
+        rga2.clock.update(UniqueId::new(50, 2).timestamp());
+        for node in rga1.all_nodes().into_iter().filter(|n| !n.is_sentinel()) {
+            rga2.apply_remote_op(node);
+        }
+        let z = rga2.insert_after(ids[1], 'z').unwrap();
+        let y = rga1.insert_after(ids[0], 'y').unwrap();
+        assert!(z > y);
+        rga1.apply_remote_op(rga2.get_node(z).unwrap());
+        rga2.apply_remote_op(rga1.get_node(y).unwrap());
+        assert_converged([&rga1, &rga2]);
+        assert_eq!(rga1.to_string(), "ayxbzc");
+
+        // Whatever order the nodes arrive in
+        let mut nodes = rga1.all_nodes();
+        nodes.reverse();
+        let rga3 = RGA::new(3);
+        let mut pending: Vec<Node> = nodes.into_iter().filter(|n| !n.is_sentinel()).collect();
+        while !pending.is_empty() {
+            pending.retain(|node| {
+                let ready = node
+                    .origin
+                    .is_none_or(|origin| rga3.get_node(origin).is_some());
+                if ready {
+                    rga3.apply_remote_op(node.clone());
+                }
+                !ready
+            });
+        }
+        assert_converged([&rga1, &rga3]);
+    }
+
+    /// A clock running a fixed number of ticks ahead of a Lamport clock.
+    struct SkewedClock {
+        inner: LamportClock,
+        skew: u64,
+    }
+
+    impl Clock for SkewedClock {
+        fn tick(&self) -> LamportTimestamp {
+            let mut timestamp = self.inner.tick();
+            timestamp.counter += self.skew;
+            timestamp
+        }
+
+        fn update(&self, received_timestamp: LamportTimestamp) {
+            self.inner.update(received_timestamp);
+        }
+
+        fn current_counter(&self) -> u64 {
+            self.inner.current_counter() + self.skew
+        }
+
+        fn replica_id(&self) -> ReplicaId {
+            self.inner.replica_id()
+        }
+    }
+
+    #[test]
+    fn test_lists_of_other_elements() {
+        let first: Rga<u8> = Rga::new(1);
+        let second: Rga<u8> = Rga::new(2);
+        let ids = first.append_slice(&[1, 2, 3]).unwrap();
+        first.delete(ids[1]).unwrap();
+        second.append(9).unwrap();
+
+        for node in first.all_nodes().into_iter().filter(|n| !n.is_sentinel()) {
+            second.apply_remote_op(node);
+        }
+        for node in second.all_nodes().into_iter().filter(|n| !n.is_sentinel()) {
+            first.apply_remote_op(node);
+        }
+        assert_eq!(first.to_vec(), second.to_vec());
+        assert_eq!(first.to_vec(), [9, 1, 3]);
+
+        // Sentinels are told apart by ID, so no content is reserved for them
+        let text = RGA::new(1);
+        text.append(SENTINEL_START_CHAR).unwrap();
+        assert_eq!(text.visible_node_count(), 1);
+    }
+
+    #[test]
+    fn test_with_custom_clock() {
+        let skewed = RGA::with_clock(SkewedClock {
+            inner: LamportClock::new(7),
+            skew: 100,
+        });
+        assert_eq!(skewed.replica_id(), 7);
+
+        let start_id = skewed.sentinel_start_id();
+        let a_id = skewed.insert_after(start_id, 'A').unwrap();
+        assert_eq!(a_id.counter(), 101);
+
+        let carets = skewed.insert_at_carets(&[0, 1], 'x').unwrap();
+        assert!(carets.iter().all(|id| id.counter() > a_id.counter()));
+
+        // A replica receiving the skewed edit moves its own clock past it
+        let rga = RGA::new(1);
+        rga.apply_remote_op(skewed.get_node(a_id).unwrap());
+        assert_eq!(rga.current_clock(), 101);
+        assert!(rga.insert_after(start_id, 'B').unwrap() > a_id);
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_readers_never_see_torn_writes() {
+        extern crate std;
+        use alloc::string::String;
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        let rga = Arc::new(RGA::new(1));
+        let source = RGA::new(2);
+        source
+            .insert_str_after(source.sentinel_start_id(), &"x".repeat(300))
+            .unwrap();
+        let copies = source.all_nodes();
+        for node in copies.iter().filter(|node| !node.is_sentinel()) {
+            rga.apply_remote_op(node.clone());
+        }
+        let done = Arc::new(AtomicBool::new(false));
+
+        // Typing runs at the start, re-sending stale copies of the remote
+        // nodes and deleting those nodes, all at once
+        let typist = {
+            let rga = Arc::clone(&rga);
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    rga.insert_str_after(rga.sentinel_start_id(), "abcd")
+                        .unwrap();
+                }
+            })
+        };
+        let resender = {
+            let (rga, copies) = (Arc::clone(&rga), copies.clone());
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    for node in copies.iter().filter(|node| !node.is_sentinel()) {
+                        rga.apply_remote_op(node.clone());
+                    }
+                }
+            })
+        };
+        let eraser = {
+            let (rga, copies) = (Arc::clone(&rga), copies.clone());
+            thread::spawn(move || {
+                for node in copies.iter().filter(|node| !node.is_sentinel()) {
+                    rga.delete(node.id).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let (rga, done) = (Arc::clone(&rga), Arc::clone(&done));
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        // A single pass sees whole runs and no sentinel
+                        let text = rga.to_string();
+                        let typed: String = text.chars().filter(|&c| c != 'x').collect();
+                        assert_eq!(typed, "abcd".repeat(typed.len() / 4));
+                        // A read transaction agrees with itself
+                        let txn = rga.read_txn();
+                        assert_eq!(txn.to_string().chars().count(), txn.len());
+                        assert!(txn.len() < txn.total_node_count());
+                    }
+                })
+            })
+            .collect();
+
+        for writer in [typist, resender, eraser] {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        // No stale copy revived a deleted node
+        assert_eq!(rga.to_string(), "abcd".repeat(200));
+        assert_eq!(rga.total_node_count(), 1102);
+        assert!(rga.validate().is_ok());
     }
 }
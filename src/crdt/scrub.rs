@@ -0,0 +1,295 @@
+//! Pseudonymizing a replica's identity for erasure requests.
+//!
+//! Every node ID names the replica that inserted it, origins name the
+//! replicas of their nodes, and pasted text records the replica that wrote
+//! it. When a user exercises a right to erasure, [`RGA::pseudonymize`]
+//! replaces one replica ID with a pseudonym everywhere it appears, in place,
+//! leaving the text, the tombstones and the structure of the document as they
+//! were.
+//!
+//! Concurrent insertions are ordered by ID, and IDs with equal counters by
+//! replica, so a pseudonym is only accepted if no other replica of the
+//! document sorts between it and the replica it replaces: the document then
+//! keeps its order and later operations integrate exactly as they would have.
+//! [`RGA::pseudonym`] picks one in the middle of that gap, which tells nothing
+//! about where in the gap the original replica was.
+//!
+//! Pseudonymizing is not an operation other replicas receive. Each copy of
+//! the document, and each stored snapshot or operation log (see
+//! [`pseudonymize_log`]), is rewritten with the same pseudonym, and
+//! operations still referring to the old IDs are no longer accepted. The
+//! pseudonymized replica itself cannot edit on afterwards.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use crate::crdt::gc::Remap;
+use crate::crdt::node::Node;
+use crate::crdt::rga::RGA;
+use crate::crdt::snapshot::LogEntry;
+use crate::crdt::types::{LamportTimestamp, ReplicaId, UniqueId};
+
+/// `id`, belonging to `pseudonym` if it belonged to `replica`
+fn rename(id: UniqueId, replica: ReplicaId, pseudonym: ReplicaId) -> UniqueId {
+    if id.replica_id() == replica {
+        UniqueId::new_with_sequence(id.counter(), pseudonym, id.sequence())
+    } else {
+        id
+    }
+}
+
+/// `author`, replaced by `pseudonym` if it is `replica`
+fn rename_author(author: ReplicaId, replica: ReplicaId, pseudonym: ReplicaId) -> ReplicaId {
+    if author == replica { pseudonym } else { author }
+}
+
+/// Whether `node` names `replica` anywhere
+fn names(node: &Node, replica: ReplicaId) -> bool {
+    node.id.replica_id() == replica
+        || node.origin.is_some_and(|id| id.replica_id() == replica)
+        || node
+            .right_origin
+            .is_some_and(|id| id.replica_id() == replica)
+        || node.author == Some(replica)
+}
+
+/// Rewrites logged operations, replacing `replica` with `pseudonym` as
+/// [`RGA::pseudonymize`] does in a document
+pub fn pseudonymize_log(
+    entries: &[LogEntry],
+    replica: ReplicaId,
+    pseudonym: ReplicaId,
+) -> Vec<LogEntry> {
+    let rename = |id| rename(id, replica, pseudonym);
+    entries
+        .iter()
+        .map(|entry| match *entry {
+            LogEntry::Insert {
+                id,
+                origin,
+                character,
+                right_origin,
+                author,
+            } => LogEntry::Insert {
+                id: rename(id),
+                origin: rename(origin),
+                character,
+                right_origin: right_origin.map(rename),
+                author: author.map(|author| rename_author(author, replica, pseudonym)),
+            },
+            LogEntry::Delete { id } => LogEntry::Delete { id: rename(id) },
+            LogEntry::DeleteRange { first, last } => LogEntry::DeleteRange {
+                first: rename(first),
+                last: rename(last),
+            },
+        })
+        .collect()
+}
+
+impl RGA {
+    /// Every replica the document names: in node IDs, origins and authors,
+    /// its own and the frozen state's
+    pub fn named_replicas(&self) -> BTreeSet<ReplicaId> {
+        let mut replicas = BTreeSet::from([self.replica_id()]);
+        let mut add = |node: &Node| {
+            replicas.insert(node.id.replica_id());
+            replicas.extend(node.origin.map(|id| id.replica_id()));
+            replicas.extend(node.right_origin.map(|id| id.replica_id()));
+            replicas.extend(node.author);
+        };
+        self.for_each_node(&mut add);
+        self.spilled_nodes().iter().for_each(add);
+        replicas.extend(self.freeze.get().map(|freeze| freeze.timestamp.replica_id));
+        replicas
+    }
+
+    /// A pseudonym [`RGA::pseudonymize`] accepts for `replica`: the middle of
+    /// the gap between the replicas the document names just below and just
+    /// above it.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(ReplicaId)` - An unused replica ID sorting where `replica` does
+    /// * `None` - If the document does not name `replica`, `replica` is its
+    ///   own or a sentinel's, or no other ID sorts in its place
+    pub fn pseudonym(&self, replica: ReplicaId) -> Option<ReplicaId> {
+        let replicas = self.named_replicas();
+        if !replicas.contains(&replica) || replica == self.replica_id() {
+            return None;
+        }
+        let below = *replicas.range(..replica).next_back()?;
+        let above = *replicas.range(replica.checked_add(1)?..).next()?;
+        let middle = below + (above - below) / 2;
+        let pseudonym = if middle != replica {
+            middle
+        } else if replica + 1 < above {
+            replica + 1
+        } else {
+            replica - 1
+        };
+        (below < pseudonym && pseudonym < above).then_some(pseudonym)
+    }
+
+    /// Replaces `replica` with `pseudonym` in every node ID, origin and author
+    /// of the document.
+    ///
+    /// The text, the tombstones and the order of the document do not change.
+    /// Every registered [`crate::AnchorHolder`] is remapped to the new IDs
+    /// before read transactions see them, as after a garbage collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `replica` - The replica to erase, other than this document's own
+    /// * `pseudonym` - The replica ID that replaces it, unused by the document
+    ///   and with no other replica sorting between the two; see
+    ///   [`RGA::pseudonym`]
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Remap)` - Each renamed node with its new ID
+    /// * `Err(&str)` - If the pseudonym is not acceptable, or tombstones
+    ///   spilled to disk name `replica`
+    pub fn pseudonymize(
+        &self,
+        replica: ReplicaId,
+        pseudonym: ReplicaId,
+    ) -> Result<Remap, &'static str> {
+        if replica == self.replica_id() {
+            return Err("A replica cannot pseudonymize itself");
+        }
+        if replica == self.sentinel_start_id().replica_id()
+            || replica == self.sentinel_end_id().replica_id()
+        {
+            return Err("Sentinel replicas cannot be pseudonymized");
+        }
+        let replicas = self.named_replicas();
+        if !replicas.contains(&replica) {
+            return Ok(Remap::default());
+        }
+        if replicas.contains(&pseudonym) {
+            return Err("Pseudonym is already in use");
+        }
+        let (low, high) = (replica.min(pseudonym), replica.max(pseudonym));
+        if replicas.range(low..=high).any(|&named| named != replica) {
+            return Err("Pseudonym would reorder the document");
+        }
+        if self.spilled_nodes().iter().any(|node| names(node, replica)) {
+            return Err("Spilled tombstones cannot be pseudonymized");
+        }
+
+        let rename = |id| rename(id, replica, pseudonym);
+        let mut named = Vec::new();
+        self.for_each_node(|node| {
+            if names(node, replica) {
+                named.push(node.clone());
+            }
+        });
+        let remap = Remap::renamed(
+            named
+                .iter()
+                .filter(|node| node.id.replica_id() == replica)
+                .map(|node| (node.id, rename(node.id))),
+        );
+
+        self.nodes.batch(|| {
+            for node in named {
+                self.nodes.rename(
+                    &node.id,
+                    Node {
+                        id: rename(node.id),
+                        origin: node.origin.map(rename),
+                        right_origin: node.right_origin.map(rename),
+                        author: node
+                            .author
+                            .map(|author| rename_author(author, replica, pseudonym)),
+                        ..node
+                    },
+                );
+            }
+            if let Some(mut freeze) = self.freeze.get()
+                && freeze.timestamp.replica_id == replica
+            {
+                freeze.timestamp = LamportTimestamp {
+                    replica_id: pseudonym,
+                    ..freeze.timestamp
+                };
+                self.freeze.set(Some(freeze));
+            }
+            self.deletion_log.rename(&remap);
+            self.remap_anchor_holders(&remap);
+        });
+        Ok(remap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::anchor::Anchor;
+    use crate::crdt::snapshot::encode_op_log;
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use parking_lot::Mutex;
+
+    #[test]
+    fn test_pseudonymized_replica_disappears() {
+        let server = RGA::new(1);
+        let alice = server.fork(200);
+        let bob = server.fork(300);
+        let a = alice
+            .insert_str_after(alice.sentinel_start_id(), "alice ")
+            .unwrap();
+        let b = bob
+            .insert_str_after(bob.sentinel_start_id(), "bob")
+            .unwrap();
+        alice.delete(a[5]).unwrap();
+        for node in alice.missing_from(&server) {
+            server.apply_remote_op(node);
+        }
+        for node in bob.missing_from(&server) {
+            server.apply_remote_op(node);
+        }
+        // Bob pastes Alice's words, keeping their author
+        let fragment = server.copy_range(&crate::Range::new(a[0], a[4])).unwrap();
+        server.insert_fragment(b[2], &fragment).unwrap();
+        let text = server.to_string();
+        let before = server.to_snapshot();
+        let anchors = Arc::new(Mutex::new(vec![Anchor::after(a[2])]));
+        server.register_anchor_holder(anchors.clone());
+
+        assert_eq!(server.pseudonym(5), None);
+        assert_eq!(server.pseudonym(1), None);
+        let pseudonym = server.pseudonym(200).unwrap();
+        assert_eq!(pseudonym, 150);
+        assert_eq!(
+            server.pseudonymize(200, 300).unwrap_err(),
+            "Pseudonym is already in use"
+        );
+        assert_eq!(
+            server.pseudonymize(200, 301).unwrap_err(),
+            "Pseudonym would reorder the document"
+        );
+
+        let log = server.log_entries_since(&RGA::new(0));
+        let remap = server.pseudonymize(200, pseudonym).unwrap();
+        assert_eq!(remap.len(), 6);
+        assert_eq!(server.to_string(), text);
+        assert!(server.validate().is_ok());
+        assert!(!server.named_replicas().contains(&200));
+        assert_ne!(server.to_snapshot(), before);
+        assert_eq!(
+            server.resolve_anchor(&anchors.lock()[0]),
+            Ok(11),
+            "anchors follow the renamed nodes"
+        );
+
+        // A rewritten log rebuilds the same document
+        let rewritten = pseudonymize_log(&log, 200, pseudonym);
+        assert!(!encode_op_log(&rewritten).contains("200"));
+        let restored = RGA::new(0);
+        for entry in &rewritten {
+            restored.apply_log_entry(entry).unwrap();
+        }
+        assert_eq!(restored.to_snapshot(), server.to_snapshot());
+    }
+}
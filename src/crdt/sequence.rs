@@ -0,0 +1,127 @@
+//! The operations every sequence CRDT backend offers.
+//!
+//! [`SequenceCrdt`] captures what replicating a sequence takes: local
+//! insertions and deletions that return the [`Operation`] to send, applying
+//! operations received, reading the visible elements and the version vector.
+//! Code written against the trait runs on any backend, so applications can
+//! pick the one that matches their workload and benchmarks can compare them.
+//!
+//! Two backends implement it. [`RGA`] is the full document: thread-safe, with
+//! anchors, marks, transactions, snapshots and the rest of the crate built
+//! around it. [`VecSequence`](crate::crdt::vec_sequence::VecSequence) keeps
+//! its elements in one contiguous vector for single-threaded use, where no
+//! locks are needed and scans stay in cache. Both place concurrent
+//! insertions the same way, so they converge with each other.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+
+use crate::crdt::metrics::ApplyOutcome;
+use crate::crdt::node::SENTINEL_START_ID;
+use crate::crdt::op::Operation;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// A replicated sequence of characters
+pub trait SequenceCrdt {
+    /// The replica local edits are stamped with
+    fn replica_id(&self) -> ReplicaId;
+
+    /// Inserts a character after the element `after_id`, or at the start
+    /// after [`SequenceCrdt::start_id`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Operation)` - The insertion, to send to the other replicas
+    /// * `Err(&str)` - Error message if `after_id` is unknown
+    fn insert(&mut self, after_id: UniqueId, character: char) -> Result<Operation, &'static str>;
+
+    /// Deletes element `id`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Operation)` - The deletion, to send to the other replicas
+    /// * `Err(&str)` - Error message if `id` is unknown or a sentinel
+    fn remove(&mut self, id: UniqueId) -> Result<Operation, &'static str>;
+
+    /// Applies an operation received from another replica, reporting what
+    /// became of it, see [`RGA::apply`].
+    fn apply(&mut self, op: Operation) -> ApplyOutcome;
+
+    /// Visits the visible elements in order
+    fn for_each_visible(&self, f: &mut dyn FnMut(UniqueId, char));
+
+    /// The newest Lamport counter of each replica whose insertions the
+    /// sequence holds
+    fn version(&self) -> BTreeMap<ReplicaId, u64>;
+
+    /// The ID to insert after to insert at the start
+    fn start_id(&self) -> UniqueId {
+        SENTINEL_START_ID
+    }
+
+    /// Number of visible elements
+    fn len(&self) -> usize {
+        let mut len = 0;
+        self.for_each_visible(&mut |_, _| len += 1);
+        len
+    }
+
+    /// Returns true if no element is visible.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The visible text
+    fn text(&self) -> String {
+        let mut text = String::new();
+        self.for_each_visible(&mut |_, character| text.push(character));
+        text
+    }
+
+    /// The ID of the visible element at `index`
+    fn id_at(&self, index: usize) -> Option<UniqueId> {
+        let (mut at, mut found) = (0, None);
+        self.for_each_visible(&mut |id, _| {
+            if at == index {
+                found = Some(id);
+            }
+            at += 1;
+        });
+        found
+    }
+}
+
+impl SequenceCrdt for RGA {
+    fn replica_id(&self) -> ReplicaId {
+        RGA::replica_id(self)
+    }
+
+    fn insert(&mut self, after_id: UniqueId, character: char) -> Result<Operation, &'static str> {
+        self.insert_op(after_id, character)
+    }
+
+    fn remove(&mut self, id: UniqueId) -> Result<Operation, &'static str> {
+        self.delete_op(id)
+    }
+
+    fn apply(&mut self, op: Operation) -> ApplyOutcome {
+        RGA::apply(self, op)
+    }
+
+    fn for_each_visible(&self, f: &mut dyn FnMut(UniqueId, char)) {
+        self.nodes.for_each(|node| {
+            if node.is_visible() {
+                f(node.id, node.character);
+            }
+        });
+    }
+
+    fn version(&self) -> BTreeMap<ReplicaId, u64> {
+        self.version_vector()
+    }
+
+    fn len(&self) -> usize {
+        self.visible_node_count()
+    }
+}
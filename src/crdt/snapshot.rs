@@ -0,0 +1,719 @@
+//! Plain-text snapshots and operation logs.
+//!
+//! A snapshot records every node of a document, tombstones included, so a
+//! replica can be rebuilt from it exactly. An operation log records inserts
+//! and deletes in the order they were applied. Both are line-based text that
+//! can be attached to a bug report, diffed with `crdt-rga-diff` and edited by
+//! hand:
+//!
+//! ```text
+//! crdt-rga snapshot 2
+//! r 7
+//! r 0
+//! r 18446744073709551615
+//! 1.0.0 0.1.0 v 48 18446744073709551615.2.0
+//! 2.0.0 1.0.0 d 69 18446744073709551615.2.0
+//! ```
+//!
+//! A snapshot starts with its replica table: each `r <replica>` line gives the
+//! next replica an alias, counting from 0. Every other line is a node: its ID
+//! (`counter.alias.sequence`), its origin (`-` if unknown), `v` for visible or
+//! `d` for deleted, the character's code point in hex and, if known, its right
+//! origin. Nodes are listed in document order and rebuilt in the order listed,
+//! so a loaded document shows exactly the text it was saved with. Version 1 snapshots, which have no table and write replica IDs in
+//! full, are still read. Log lines are `i <id> <origin> <code point>` for
+//! inserts, followed by the right origin if known, and `d <id>` for deletes,
+//! with full replica IDs. Sentinels are never written. Characters pasted with
+//! their original author (see [`crate::crdt::fragment`]) end with
+//! `@<replica>` in both formats, an alias in snapshots.
+//!
+//! Backspacing through a word deletes one node after another, all inserted by
+//! the same replica with consecutive IDs. [`coalesce_deletes`] merges such
+//! back-to-back deletes into one ranged record, `d <first> <last>`, which is
+//! expanded again when it is applied. The IDs of a range have consecutive
+//! sequence numbers and either share their counter (text inserted as one
+//! batch) or have consecutive counters too (text typed a character at a
+//! time), so the first and last ID determine every one in between.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::crdt::metrics::OpCounters;
+use crate::crdt::node::Node;
+use crate::crdt::replicas::ReplicaRegistry;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{ReplicaId, UniqueId};
+
+/// First line of every snapshot
+pub const SNAPSHOT_HEADER: &str = "crdt-rga snapshot 2";
+/// First line of snapshots written before replica aliases
+pub const SNAPSHOT_HEADER_V1: &str = "crdt-rga snapshot 1";
+/// First line of every operation log
+pub const OP_LOG_HEADER: &str = "crdt-rga oplog 1";
+
+/// An operation recorded in a log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEntry {
+    /// A character inserted after `origin`
+    Insert {
+        id: UniqueId,
+        origin: UniqueId,
+        character: char,
+        /// The node that followed `origin` at the time, if known
+        right_origin: Option<UniqueId>,
+        /// The replica that wrote the character, if it was copied from
+        /// another replica's text
+        author: Option<ReplicaId>,
+    },
+    /// A node deleted
+    Delete { id: UniqueId },
+    /// The nodes from `first` to `last` deleted, written for back-to-back
+    /// deletes by [`coalesce_deletes`]
+    DeleteRange { first: UniqueId, last: UniqueId },
+}
+
+/// The IDs of a ranged delete from `first` to `last`, in order
+pub(crate) fn range_ids(
+    first: UniqueId,
+    last: UniqueId,
+) -> Result<impl Iterator<Item = UniqueId>, &'static str> {
+    let steps = last
+        .sequence()
+        .checked_sub(first.sequence())
+        .filter(|_| first.replica_id() == last.replica_id())
+        .ok_or("Malformed delete range")?;
+    let counter_step = if first.counter() == last.counter() {
+        0
+    } else if last.counter().checked_sub(first.counter()) == Some(u64::from(steps)) {
+        1
+    } else {
+        return Err("Malformed delete range");
+    };
+    Ok((0..=steps).map(move |step| {
+        UniqueId::new_with_sequence(
+            first.counter() + u64::from(step) * counter_step,
+            first.replica_id(),
+            first.sequence() + step,
+        )
+    }))
+}
+
+/// Back-to-back deletes being merged into one range
+struct DeleteRun {
+    first: UniqueId,
+    last: UniqueId,
+}
+
+impl DeleteRun {
+    /// Whether `next` directly follows `previous` in a range whose counters
+    /// are shared (`Some(0)`), consecutive (`Some(1)`) or not yet known
+    fn follows(previous: UniqueId, next: UniqueId, counter_step: Option<u64>) -> bool {
+        previous.replica_id() == next.replica_id()
+            && previous.sequence().checked_add(1) == Some(next.sequence())
+            && next
+                .counter()
+                .checked_sub(previous.counter())
+                .is_some_and(|step| step <= 1 && counter_step.is_none_or(|known| known == step))
+    }
+
+    /// Adds `id` to either end of the run, if it extends it
+    fn extend(&mut self, id: UniqueId) -> bool {
+        let counter_step = (self.first != self.last)
+            .then(|| u64::from(self.first.counter() != self.last.counter()));
+        if Self::follows(self.last, id, counter_step) {
+            self.last = id;
+        } else if Self::follows(id, self.first, counter_step) {
+            self.first = id;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn entry(&self) -> LogEntry {
+        if self.first == self.last {
+            LogEntry::Delete { id: self.first }
+        } else {
+            LogEntry::DeleteRange {
+                first: self.first,
+                last: self.last,
+            }
+        }
+    }
+}
+
+/// Merges back-to-back deletes of consecutive IDs into ranged records.
+///
+/// Other entries are kept as they are, in order. Applying the result has the
+/// same effect as applying `entries`.
+pub fn coalesce_deletes(entries: &[LogEntry]) -> Vec<LogEntry> {
+    let mut coalesced = Vec::with_capacity(entries.len());
+    let mut run: Option<DeleteRun> = None;
+    for entry in entries {
+        let ids: Vec<UniqueId> = match *entry {
+            LogEntry::Delete { id } => alloc::vec![id],
+            LogEntry::DeleteRange { first, last } => match range_ids(first, last) {
+                Ok(ids) => ids.collect(),
+                Err(_) => Vec::new(),
+            },
+            LogEntry::Insert { .. } => Vec::new(),
+        };
+        if ids.is_empty() {
+            coalesced.extend(run.take().map(|run| run.entry()));
+            coalesced.push(*entry);
+            continue;
+        }
+        for id in ids {
+            if !run.as_mut().is_some_and(|run| run.extend(id)) {
+                coalesced.extend(
+                    run.replace(DeleteRun {
+                        first: id,
+                        last: id,
+                    })
+                    .map(|run| run.entry()),
+                );
+            }
+        }
+    }
+    coalesced.extend(run.map(|run| run.entry()));
+    coalesced
+}
+
+pub(crate) fn write_id(out: &mut String, id: UniqueId) {
+    let _ = write!(
+        out,
+        "{}.{}.{}",
+        id.counter(),
+        id.replica_id(),
+        id.sequence()
+    );
+}
+
+fn parse_id(field: Option<&str>) -> Result<UniqueId, &'static str> {
+    let mut parts = field.ok_or("Missing node ID")?.split('.');
+    let mut next = || parts.next().ok_or("Malformed node ID");
+    let counter = next()?.parse().map_err(|_| "Malformed node ID")?;
+    let replica_id = next()?.parse().map_err(|_| "Malformed node ID")?;
+    let sequence = next()?.parse().map_err(|_| "Malformed node ID")?;
+    Ok(UniqueId::new_with_sequence(counter, replica_id, sequence))
+}
+
+fn parse_right_origin(field: Option<&str>) -> Result<Option<UniqueId>, &'static str> {
+    field.map(|field| parse_id(Some(field))).transpose()
+}
+
+/// Parses the optional right origin and `@author` fields ending a node
+fn parse_trailer<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Result<(Option<UniqueId>, Option<ReplicaId>), &'static str> {
+    let mut right_origin = None;
+    let mut author = None;
+    let mut field = fields.next();
+    if let Some(id) = field.filter(|field| !field.starts_with('@')) {
+        right_origin = Some(parse_id(Some(id))?);
+        field = fields.next();
+    }
+    if let Some(replica) = field {
+        let replica = replica.strip_prefix('@').ok_or("Malformed author")?;
+        author = Some(replica.parse().map_err(|_| "Malformed author")?);
+    }
+    Ok((right_origin, author))
+}
+
+fn parse_char(field: Option<&str>) -> Result<char, &'static str> {
+    u32::from_str_radix(field.ok_or("Missing character")?, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or("Malformed character")
+}
+
+/// The content lines of `text`, after checking its header against `headers`
+fn body<'a>(
+    text: &'a str,
+    headers: &[&str],
+) -> Result<(usize, impl Iterator<Item = &'a str>), &'static str> {
+    let mut lines = text.lines();
+    let first = lines.next().map(str::trim);
+    let version = headers
+        .iter()
+        .position(|&header| Some(header) == first)
+        .ok_or("Unrecognized header")?;
+    Ok((
+        version,
+        lines.map(str::trim).filter(|line| !line.is_empty()),
+    ))
+}
+
+/// Appends one operation log line for `entry`
+pub(crate) fn write_log_entry(out: &mut String, entry: &LogEntry) {
+    match *entry {
+        LogEntry::Insert {
+            id,
+            origin,
+            character,
+            right_origin,
+            author,
+        } => {
+            out.push_str("i ");
+            write_id(out, id);
+            out.push(' ');
+            write_id(out, origin);
+            let _ = write!(out, " {:x}", u32::from(character));
+            if let Some(right_origin) = right_origin {
+                out.push(' ');
+                write_id(out, right_origin);
+            }
+            if let Some(author) = author {
+                let _ = write!(out, " @{author}");
+            }
+            out.push('\n');
+        }
+        LogEntry::Delete { id } => {
+            out.push_str("d ");
+            write_id(out, id);
+            out.push('\n');
+        }
+        LogEntry::DeleteRange { first, last } => {
+            out.push_str("d ");
+            write_id(out, first);
+            out.push(' ');
+            write_id(out, last);
+            out.push('\n');
+        }
+    }
+}
+
+/// Encodes operations as an operation log, coalescing back-to-back deletes
+pub fn encode_op_log(entries: &[LogEntry]) -> String {
+    let mut out = String::from(OP_LOG_HEADER);
+    out.push('\n');
+    for entry in &coalesce_deletes(entries) {
+        write_log_entry(&mut out, entry);
+    }
+    out
+}
+
+/// Parses an operation log written by [`encode_op_log`]
+pub fn parse_op_log(text: &str) -> Result<Vec<LogEntry>, &'static str> {
+    body(text, &[OP_LOG_HEADER])?
+        .1
+        .map(|line| {
+            let mut fields = line.split(' ');
+            match fields.next() {
+                Some("i") => {
+                    let id = parse_id(fields.next())?;
+                    let origin = parse_id(fields.next())?;
+                    let character = parse_char(fields.next())?;
+                    let (right_origin, author) = parse_trailer(fields)?;
+                    Ok(LogEntry::Insert {
+                        id,
+                        origin,
+                        character,
+                        right_origin,
+                        author,
+                    })
+                }
+                Some("d") => {
+                    let first = parse_id(fields.next())?;
+                    match parse_right_origin(fields.next())? {
+                        Some(last) => {
+                            range_ids(first, last).map(|_| LogEntry::DeleteRange { first, last })
+                        }
+                        None => Ok(LogEntry::Delete { id: first }),
+                    }
+                }
+                _ => Err("Unknown log entry"),
+            }
+        })
+        .collect()
+}
+
+impl RGA {
+    /// Encodes every node of the document, tombstones included.
+    pub fn to_snapshot(&self) -> String {
+        let mut replicas = ReplicaRegistry::new();
+        let mut nodes = String::new();
+        self.for_each_node(|node| {
+            if node.is_sentinel() {
+                return;
+            }
+            write_id(&mut nodes, replicas.alias_id(node.id));
+            match node.origin {
+                Some(origin) => {
+                    nodes.push(' ');
+                    write_id(&mut nodes, replicas.alias_id(origin));
+                }
+                None => nodes.push_str(" -"),
+            }
+            let state = if node.is_deleted { 'd' } else { 'v' };
+            let _ = write!(nodes, " {} {:x}", state, u32::from(node.character));
+            if let Some(right_origin) = node.right_origin {
+                nodes.push(' ');
+                write_id(&mut nodes, replicas.alias_id(right_origin));
+            }
+            if let Some(author) = node.author {
+                let _ = write!(nodes, " @{}", replicas.alias(author));
+            }
+            nodes.push('\n');
+        });
+
+        let mut out = String::from(SNAPSHOT_HEADER);
+        out.push('\n');
+        for replica in replicas.replicas() {
+            let _ = writeln!(out, "r {replica}");
+        }
+        out.push_str(&nodes);
+        out
+    }
+
+    /// Rebuilds a document from a snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `replica_id` - The replica the rebuilt document edits as
+    /// * `snapshot` - Text written by [`RGA::to_snapshot`], in the current or
+    ///   the version 1 format
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(RGA)` - The document, with its clock past every snapshot node
+    /// * `Err(&str)` - Error message if the snapshot is malformed
+    pub fn from_snapshot(replica_id: ReplicaId, snapshot: &str) -> Result<RGA, &'static str> {
+        let mut rga = RGA::new(replica_id);
+        let mut nodes = Vec::new();
+        let (version, lines) = body(snapshot, &[SNAPSHOT_HEADER, SNAPSHOT_HEADER_V1])?;
+        // Version 1 snapshots write replica IDs in full
+        let mut replicas = (version == 0).then(ReplicaRegistry::new);
+        let resolve = |replicas: &Option<ReplicaRegistry>, id: UniqueId| match replicas {
+            Some(replicas) => replicas.resolve_id(id),
+            None => Ok(id),
+        };
+        for line in lines {
+            let mut fields = line.split(' ');
+            if let (Some(replicas), Some(replica)) = (replicas.as_mut(), line.strip_prefix("r ")) {
+                let replica = replica.parse().map_err(|_| "Malformed replica")?;
+                replicas.declare(replica)?;
+                continue;
+            }
+            let id = resolve(&replicas, parse_id(fields.next())?)?;
+            let origin = match fields.next() {
+                Some("-") => None,
+                field => Some(resolve(&replicas, parse_id(field)?)?),
+            };
+            let is_deleted = match fields.next() {
+                Some("v") => false,
+                Some("d") => true,
+                _ => return Err("Malformed node state"),
+            };
+            let character = parse_char(fields.next())?;
+            let (right_origin, author) = parse_trailer(fields)?;
+            let right_origin = right_origin.map(|id| resolve(&replicas, id)).transpose()?;
+            let author = match (&replicas, author) {
+                (Some(replicas), Some(alias)) => Some(replicas.replica(alias)?),
+                (None, author) | (_, author @ None) => author,
+            };
+            nodes.push(Node {
+                id,
+                character,
+                is_deleted,
+                origin,
+                right_origin,
+                author,
+            });
+        }
+
+        // Nodes are listed in document order, in which origins precede the
+        // nodes inserted after them, and are placed as listed
+        let mut previous = rga.sentinel_start_id();
+        for node in nodes {
+            if node
+                .origin
+                .is_some_and(|origin| !rga.nodes.contains(&origin))
+            {
+                return Err("Snapshot node references an unknown origin");
+            }
+            let id = node.id;
+            rga.place_after(previous, node);
+            previous = id;
+        }
+        // Loading the document is not sync traffic
+        rga.counters = OpCounters::new();
+        Ok(rga)
+    }
+
+    /// The operations that turn `base`, an earlier copy of this document,
+    /// into this document.
+    ///
+    /// Nodes that were inserted without a recorded origin are logged as
+    /// inserted after the node preceding them.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The copy the operations are relative to
+    ///
+    /// # Returns
+    ///
+    /// * The operations in an order [`RGA::apply_log_entry`] accepts, with
+    ///   back-to-back deletes coalesced
+    pub fn log_entries_since(&self, base: &RGA) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        let mut previous = self.sentinel_start_id();
+        self.for_each_node(|node| {
+            if node.is_sentinel() {
+                return;
+            }
+            let known = base.get_node(node.id);
+            if known.is_none() {
+                entries.push(LogEntry::Insert {
+                    id: node.id,
+                    origin: node.origin.unwrap_or(previous),
+                    character: node.character,
+                    right_origin: node.right_origin,
+                    author: node.author,
+                });
+            }
+            if node.is_deleted && known.is_none_or(|known| !known.is_deleted) {
+                entries.push(LogEntry::Delete { id: node.id });
+            }
+            previous = node.id;
+        });
+        coalesce_deletes(&entries)
+    }
+
+    /// Applies an operation read from a log.
+    ///
+    /// Inserting a node that already exists changes nothing, so a log may
+    /// overlap the state it is applied to.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The operation to apply
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the operation was applied or already present
+    /// * `Err(&str)` - Error message if it references a node this document lacks
+    pub fn apply_log_entry(&self, entry: &LogEntry) -> Result<(), &'static str> {
+        match *entry {
+            LogEntry::Insert {
+                id,
+                origin,
+                character,
+                right_origin,
+                author,
+            } => {
+                if self.holds(id) {
+                    self.record_deduplicated();
+                    return Ok(());
+                }
+                if !self.holds(origin) {
+                    self.record_rejected();
+                    return Err("Reference node for insertion not found");
+                }
+                self.apply_remote_op(Node {
+                    author,
+                    ..Node::with_origins(id, character, origin, right_origin)
+                });
+                Ok(())
+            }
+            LogEntry::Delete { id } => match self.get_node(id) {
+                Some(node) if node.is_deleted => {
+                    self.record_deduplicated();
+                    Ok(())
+                }
+                Some(_) => {
+                    let result = self.delete(id);
+                    match result {
+                        Ok(()) => self.record_applied(),
+                        Err(_) => self.record_rejected(),
+                    }
+                    result
+                }
+                None => {
+                    self.record_rejected();
+                    Err("Node to delete not found")
+                }
+            },
+            LogEntry::DeleteRange { first, last } => {
+                for id in range_ids(first, last)? {
+                    self.apply_log_entry(&LogEntry::Delete { id })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "a b\n\u{1F600}")
+            .unwrap();
+        rga.delete(ids[1]).unwrap();
+
+        let snapshot = rga.to_snapshot();
+        assert!(snapshot.starts_with(
+            "crdt-rga snapshot 2\nr 1\nr 0\nr 18446744073709551615\n1.0.0 0.1.0 v 61 18446744073709551615.2.0\n"
+        ));
+        let restored = RGA::from_snapshot(2, &snapshot).unwrap();
+        assert_eq!(restored.to_string(), "ab\n\u{1F600}");
+        assert_eq!(restored.total_node_count(), rga.total_node_count());
+        assert_eq!(restored.to_snapshot(), snapshot);
+
+        // The restored replica's own edits come after everything it loaded
+        let id = restored
+            .insert_after(restored.sentinel_start_id(), 'x')
+            .unwrap();
+        assert!(id > ids[4]);
+    }
+
+    #[test]
+    fn test_malformed_snapshots_are_rejected() {
+        assert!(RGA::from_snapshot(1, "").is_err());
+        assert!(RGA::from_snapshot(1, "crdt-rga oplog 1\n").is_err());
+        for line in [
+            "1.0 - v 61",
+            "1.0.0 - x 61",
+            "1.0.0 - v zz",
+            "1.0.0 - v 110000",
+            "2.0.0 1.0.0 v 61",
+            "1.1.0 - v 61",
+            "r x",
+            "r 5",
+        ] {
+            let snapshot = SNAPSHOT_HEADER.to_string() + "\nr 5\n" + line;
+            assert!(RGA::from_snapshot(1, &snapshot).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_version_1_snapshots_are_read() {
+        let end = "18446744073709551615.18446744073709551615.0";
+        let snapshot = format!("{SNAPSHOT_HEADER_V1}\n1.7.0 0.0.0 v 61 {end}\n2.7.0 1.7.0 d 62\n");
+        let rga = RGA::from_snapshot(1, &snapshot).unwrap();
+        assert_eq!(rga.to_string(), "a");
+        let b = rga.get_node(UniqueId::new(2, 7)).unwrap();
+        assert!(b.is_deleted);
+        assert_eq!(b.origin, Some(UniqueId::new(1, 7)));
+        assert_eq!(
+            rga.get_node(UniqueId::new(1, 7)).unwrap().right_origin,
+            Some(rga.sentinel_end_id())
+        );
+        assert!(RGA::from_snapshot(1, &(SNAPSHOT_HEADER_V1.to_string() + "\nr 5")).is_err());
+    }
+
+    #[test]
+    fn test_op_log_round_trip() {
+        let rga = RGA::new(1);
+        let a = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        let b = rga.insert_after(a, 'b').unwrap();
+        let entries = [
+            LogEntry::Insert {
+                id: a,
+                origin: rga.sentinel_start_id(),
+                character: 'a',
+                right_origin: Some(rga.sentinel_end_id()),
+                author: None,
+            },
+            LogEntry::Insert {
+                id: b,
+                origin: a,
+                character: 'b',
+                right_origin: None,
+                author: Some(7),
+            },
+            LogEntry::Delete { id: a },
+        ];
+
+        let log = encode_op_log(&entries);
+        assert_eq!(parse_op_log(&log).unwrap(), entries);
+        assert!(parse_op_log("crdt-rga oplog 1\nx 1.1.0").is_err());
+
+        let replica = RGA::new(2);
+        for entry in &entries {
+            replica.apply_log_entry(entry).unwrap();
+        }
+        // Replaying an insert does not resurrect the deleted node
+        replica.apply_log_entry(&entries[0]).unwrap();
+        assert_eq!(replica.to_string(), "b");
+        let copy = RGA::new(3);
+        for entry in replica.log_entries_since(&copy) {
+            copy.apply_log_entry(&entry).unwrap();
+        }
+        assert_eq!(copy.to_snapshot(), replica.to_snapshot());
+        assert!(replica.log_entries_since(&replica).is_empty());
+        assert!(
+            replica
+                .apply_log_entry(&LogEntry::Delete {
+                    id: UniqueId::new(9, 9)
+                })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_back_to_back_deletes_are_coalesced() {
+        let rga = RGA::new(1);
+        let base = rga.fork(0);
+        let mut typed = Vec::new();
+        let mut after = rga.sentinel_start_id();
+        for character in "word".chars() {
+            after = rga.insert_after(after, character).unwrap();
+            typed.push(after);
+        }
+        let pasted = rga.insert_str_after(after, " pasted").unwrap();
+        let synced = rga.fork(2);
+
+        // Backspace through the typed word, then select and delete the paste
+        let backspaces: Vec<LogEntry> = typed
+            .iter()
+            .rev()
+            .map(|&id| LogEntry::Delete { id })
+            .collect();
+        let selection: Vec<LogEntry> = pasted.iter().map(|&id| LogEntry::Delete { id }).collect();
+        assert_eq!(
+            coalesce_deletes(&backspaces),
+            [LogEntry::DeleteRange {
+                first: typed[0],
+                last: typed[3],
+            }]
+        );
+        assert_eq!(
+            coalesce_deletes(&[backspaces.clone(), selection.clone()].concat()),
+            [
+                // The paste's first ID was the clock's next tick after the word
+                LogEntry::DeleteRange {
+                    first: typed[0],
+                    last: pasted[0],
+                },
+                LogEntry::DeleteRange {
+                    first: pasted[1],
+                    last: pasted[6],
+                },
+            ]
+        );
+
+        for entry in backspaces.iter().chain(&selection) {
+            rga.apply_log_entry(entry).unwrap();
+        }
+        let log = encode_op_log(&rga.log_entries_since(&synced));
+        assert_eq!(log.lines().count(), 3);
+        for entry in parse_op_log(&log).unwrap() {
+            synced.apply_log_entry(&entry).unwrap();
+        }
+        assert_eq!(synced.to_string(), "");
+        assert_eq!(rga.log_entries_since(&base).len(), 22);
+
+        // A range must run over consecutive IDs of one replica
+        for range in ["d 1.1.0 3.1.1", "d 1.1.1 1.1.0", "d 1.1.0 1.2.1"] {
+            assert!(parse_op_log(&(OP_LOG_HEADER.to_string() + "\n" + range)).is_err());
+        }
+    }
+}
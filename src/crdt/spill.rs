@@ -0,0 +1,569 @@
+//! Spilling old tombstones to disk.
+//!
+//! Deleted characters stay in the document as tombstones so that concurrent
+//! operations referring to them can still be placed. In a long-lived document
+//! they eventually outnumber the visible text many times over. With
+//! [`RGA::enable_tombstone_spill`], once more tombstones than a threshold are
+//! held in memory, the oldest are moved to a directory of run files until half
+//! the threshold remain, so memory stays proportional to the visible content
+//! plus the threshold.
+//!
+//! Spilled tombstones are still part of the document: [`RGA::get_node`] finds
+//! them, remote insertions after them and repeated deletions of them are
+//! integrated as before, and snapshots, forks and sync exports include them.
+//! Only lookups pay for the disk access, and the visible text never needs one.
+//! Each keeps its place in the document order in memory, a few IDs' worth, so
+//! insertions after it land where they belong.
+//!
+//! Each spill writes one run: a file of fixed-size records sorted by ID, so a
+//! lookup binary searches it without an index in memory. Once more than
+//! [`MAX_RUNS`] runs exist they are merged into one. The directory is scratch
+//! space: its run files are removed when spilling is enabled, and the document
+//! itself is persisted through snapshots and operation logs as usual.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use alloc::vec::Vec;
+use parking_lot::Mutex;
+
+use crate::crdt::node::{Element, Node};
+use crate::crdt::rga::{RGA, Rga};
+use crate::crdt::types::UniqueId;
+
+/// Runs kept before they are merged into one
+pub const MAX_RUNS: usize = 8;
+
+/// Size of an encoded ID: counter, replica and sequence
+const ID_LEN: usize = 8 + 8 + 4;
+/// Size of a record: ID, character, flags, origin, right origin and author
+const RECORD_LEN: usize = ID_LEN + 4 + 1 + 2 * ID_LEN + 8;
+const HAS_ORIGIN: u8 = 1;
+const HAS_RIGHT_ORIGIN: u8 = 2;
+const HAS_AUTHOR: u8 = 4;
+
+fn encode_id(out: &mut Vec<u8>, id: Option<UniqueId>) {
+    let id = id.unwrap_or_else(|| UniqueId::new(0, 0));
+    out.extend_from_slice(&id.counter().to_le_bytes());
+    out.extend_from_slice(&id.replica_id().to_le_bytes());
+    out.extend_from_slice(&id.sequence().to_le_bytes());
+}
+
+fn decode_id(bytes: &[u8]) -> UniqueId {
+    let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+    let sequence = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+    UniqueId::new_with_sequence(u64_at(0), u64_at(8), sequence)
+}
+
+/// Writes and reads the records of one element type; only characters have
+/// a record format, so only text documents can enable spilling
+struct Codec<T> {
+    encode: fn(&mut Vec<u8>, &Node<T>),
+    decode: fn(&[u8; RECORD_LEN]) -> io::Result<Node<T>>,
+}
+
+impl<T> Clone for Codec<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Codec<T> {}
+
+/// The record format of characters
+const CHAR_CODEC: Codec<char> = Codec {
+    encode: encode_record,
+    decode: decode_record,
+};
+
+fn encode_record(out: &mut Vec<u8>, node: &Node) {
+    encode_id(out, Some(node.id));
+    out.extend_from_slice(&u32::from(node.character).to_le_bytes());
+    let flags = if node.origin.is_some() { HAS_ORIGIN } else { 0 }
+        | if node.right_origin.is_some() {
+            HAS_RIGHT_ORIGIN
+        } else {
+            0
+        }
+        | if node.author.is_some() { HAS_AUTHOR } else { 0 };
+    out.push(flags);
+    encode_id(out, node.origin);
+    encode_id(out, node.right_origin);
+    out.extend_from_slice(&node.author.unwrap_or_default().to_le_bytes());
+}
+
+fn decode_record(record: &[u8; RECORD_LEN]) -> io::Result<Node> {
+    let character = u32::from_le_bytes(record[ID_LEN..ID_LEN + 4].try_into().unwrap());
+    let character = char::from_u32(character)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt tombstone record"))?;
+    let flags = record[ID_LEN + 4];
+    let origin_at = ID_LEN + 5;
+    let right_origin_at = origin_at + ID_LEN;
+    let author_at = right_origin_at + ID_LEN;
+    Ok(Node {
+        id: decode_id(&record[..ID_LEN]),
+        character,
+        is_deleted: true,
+        origin: (flags & HAS_ORIGIN != 0).then(|| decode_id(&record[origin_at..right_origin_at])),
+        right_origin: (flags & HAS_RIGHT_ORIGIN != 0)
+            .then(|| decode_id(&record[right_origin_at..author_at])),
+        author: (flags & HAS_AUTHOR != 0)
+            .then(|| u64::from_le_bytes(record[author_at..].try_into().unwrap())),
+    })
+}
+
+/// A file of tombstone records sorted by ID
+struct Run<T> {
+    codec: Codec<T>,
+    path: PathBuf,
+    file: File,
+    len: u64,
+    first: UniqueId,
+    last: UniqueId,
+}
+
+impl<T> Run<T> {
+    /// Writes `nodes`, sorted by ID, as a run at `path`
+    fn write(
+        codec: Codec<T>,
+        path: PathBuf,
+        nodes: impl IntoIterator<Item = io::Result<Node<T>>>,
+    ) -> io::Result<Run<T>> {
+        let mut out = BufWriter::new(File::create(&path)?);
+        let mut record = Vec::with_capacity(RECORD_LEN);
+        let mut len = 0;
+        let mut bounds = None;
+        for node in nodes {
+            let node = node?;
+            record.clear();
+            (codec.encode)(&mut record, &node);
+            out.write_all(&record)?;
+            len += 1;
+            let first = bounds.map_or(node.id, |(first, _)| first);
+            bounds = Some((first, node.id));
+        }
+        out.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        let (first, last) = bounds.ok_or_else(|| io::Error::other("empty tombstone run"))?;
+        Ok(Run {
+            codec,
+            file: File::open(&path)?,
+            path,
+            len,
+            first,
+            last,
+        })
+    }
+
+    fn read_at(&mut self, index: u64) -> io::Result<Node<T>> {
+        let mut record = [0; RECORD_LEN];
+        self.file.seek(SeekFrom::Start(index * RECORD_LEN as u64))?;
+        self.file.read_exact(&mut record)?;
+        (self.codec.decode)(&record)
+    }
+
+    fn get(&mut self, id: UniqueId) -> io::Result<Option<Node<T>>> {
+        if id < self.first || id > self.last {
+            return Ok(None);
+        }
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let node = self.read_at(middle)?;
+            match node.id.cmp(&id) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return Ok(Some(node)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the run from the start, in ID order
+    fn records(&self) -> io::Result<impl Iterator<Item = io::Result<Node<T>>> + use<T>> {
+        let decode = self.codec.decode;
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        Ok((0..self.len).map(move |_| {
+            let mut record = [0; RECORD_LEN];
+            reader.read_exact(&mut record)?;
+            decode(&record)
+        }))
+    }
+}
+
+/// Merges ID-ordered streams of nodes into one
+fn merge<T>(
+    mut streams: Vec<core::iter::Peekable<impl Iterator<Item = io::Result<Node<T>>>>>,
+) -> impl Iterator<Item = io::Result<Node<T>>> {
+    core::iter::from_fn(move || {
+        let mut next: Option<(usize, UniqueId)> = None;
+        for (index, stream) in streams.iter_mut().enumerate() {
+            match stream.peek() {
+                None => {}
+                Some(Err(_)) => return stream.next(),
+                Some(Ok(node)) if next.is_none_or(|(_, smallest)| node.id < smallest) => {
+                    next = Some((index, node.id));
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+        streams[next?.0].next()
+    })
+}
+
+/// Tombstones held on disk, with the counters reported by
+/// [`RGA::tombstone_spill_stats`]
+pub(crate) struct TombstoneSpill<T> {
+    codec: Codec<T>,
+    dir: PathBuf,
+    /// Tombstones kept in memory before spilling
+    threshold: usize,
+    /// Oldest first
+    runs: Vec<Run<T>>,
+    next_run: u64,
+    /// Deletions since tombstones were last counted
+    deletions: usize,
+    read_errors: u64,
+    write_errors: u64,
+}
+
+impl TombstoneSpill<char> {
+    fn create(dir: &Path, threshold: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "run") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(TombstoneSpill {
+            codec: CHAR_CODEC,
+            dir: dir.to_path_buf(),
+            threshold,
+            runs: Vec::new(),
+            next_run: 0,
+            deletions: 0,
+            read_errors: 0,
+            write_errors: 0,
+        })
+    }
+}
+
+impl<T: Clone> TombstoneSpill<T> {
+    fn len(&self) -> u64 {
+        self.runs.iter().map(|run| run.len).sum()
+    }
+
+    fn next_path(&mut self) -> PathBuf {
+        self.next_run += 1;
+        self.dir
+            .join(format!("tombstones-{:08}.run", self.next_run))
+    }
+
+    /// Writes `nodes`, sorted by ID, as a new run
+    fn spill(&mut self, nodes: &[Node<T>]) -> io::Result<()> {
+        let path = self.next_path();
+        let run = Run::write(self.codec, path, nodes.iter().cloned().map(Ok))?;
+        self.runs.push(run);
+        if self.runs.len() > MAX_RUNS {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Merges every run into one
+    fn compact(&mut self) -> io::Result<()> {
+        let streams = self
+            .runs
+            .iter()
+            .map(|run| Ok(run.records()?.peekable()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let path = self.next_path();
+        let merged = Run::write(self.codec, path, merge(streams))?;
+        for run in core::mem::replace(&mut self.runs, Vec::from([merged])) {
+            fs::remove_file(run.path)?;
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, id: UniqueId) -> Option<Node<T>> {
+        for run in self.runs.iter_mut().rev() {
+            match run.get(id) {
+                Ok(Some(node)) => return Some(node),
+                Ok(None) => {}
+                Err(_) => self.read_errors += 1,
+            }
+        }
+        None
+    }
+
+    fn nodes(&mut self) -> Vec<Node<T>> {
+        let streams = self
+            .runs
+            .iter()
+            .filter_map(|run| run.records().ok())
+            .map(Iterator::peekable)
+            .collect();
+        let mut nodes = Vec::new();
+        for node in merge(streams) {
+            match node {
+                Ok(node) => nodes.push(node),
+                Err(_) => self.read_errors += 1,
+            }
+        }
+        nodes
+    }
+}
+
+/// Counters describing a document's spilled tombstones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpillStats {
+    /// Tombstones held on disk
+    pub spilled: u64,
+    /// Run files holding them
+    pub runs: usize,
+    /// Failed reads; a tombstone that could not be read is treated as absent
+    pub read_errors: u64,
+    /// Failed spills; the tombstones stay in memory
+    pub write_errors: u64,
+}
+
+impl RGA {
+    /// Spills tombstones to `dir` once more than `threshold` are held in
+    /// memory.
+    ///
+    /// The check runs after every `threshold / 2` deletions, local or remote,
+    /// and moves the oldest tombstones to disk until half the threshold
+    /// remain. Existing run files in `dir` are removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - A directory used only for this document's tombstones
+    /// * `threshold` - Tombstones kept in memory before spilling
+    ///
+    /// # Returns
+    ///
+    /// * `Err` - If the directory cannot be created or cleared
+    pub fn enable_tombstone_spill(
+        &mut self,
+        dir: impl AsRef<Path>,
+        threshold: usize,
+    ) -> io::Result<()> {
+        self.spill = Some(Mutex::new(TombstoneSpill::create(dir.as_ref(), threshold)?));
+        Ok(())
+    }
+}
+
+impl<T: Element> Rga<T> {
+    /// Spills the oldest tombstones now if more than the threshold are held in
+    /// memory.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of tombstones moved to disk; `0` if spilling
+    ///   is not enabled or the threshold is not exceeded
+    /// * `Err` - If the run could not be written; the tombstones stay in memory
+    pub fn spill_tombstones(&self) -> io::Result<usize> {
+        let Some(spill) = &self.spill else {
+            return Ok(0);
+        };
+        // One write, so a frozen reader sees every tombstone in memory or on
+        // disk, never both; the store is entered before the spill, like
+        // everywhere else
+        self.nodes.batch(|| {
+            let mut spill = spill.lock();
+            self.spill_locked(&mut spill)
+        })
+    }
+
+    fn spill_locked(&self, spill: &mut TombstoneSpill<T>) -> io::Result<usize> {
+        spill.deletions = 0;
+        let mut tombstones = Vec::new();
+        self.nodes.for_each_by_id(|node| {
+            if node.is_deleted {
+                tombstones.push(node.clone());
+            }
+        });
+        if tombstones.len() <= spill.threshold {
+            return Ok(0);
+        }
+        tombstones.truncate(tombstones.len() - spill.threshold / 2);
+        if let Err(e) = spill.spill(&tombstones) {
+            spill.write_errors += 1;
+            return Err(e);
+        }
+        // Written before removal, so lookups find every tombstone throughout
+        for node in &tombstones {
+            self.nodes.evict(&node.id);
+        }
+        Ok(tombstones.len())
+    }
+
+    /// Statistics of the spilled tombstones, if spilling is enabled
+    pub fn tombstone_spill_stats(&self) -> Option<SpillStats> {
+        let spill = self.spill.as_ref()?.lock();
+        Some(SpillStats {
+            spilled: spill.len(),
+            runs: spill.runs.len(),
+            read_errors: spill.read_errors,
+            write_errors: spill.write_errors,
+        })
+    }
+
+    /// Records a deletion, counts it and spills once enough have accumulated
+    pub(crate) fn note_deletion(&self, id: UniqueId) {
+        self.log_deletion(id);
+        let Some(spill) = &self.spill else {
+            return;
+        };
+        let due = {
+            let mut spill = spill.lock();
+            spill.deletions += 1;
+            spill.deletions >= (spill.threshold / 2).max(1)
+        };
+        if due {
+            // Failures are counted in the stats and retried after the next
+            // interval
+            let _ = self.spill_tombstones();
+        }
+    }
+
+    pub(crate) fn spilled_node(&self, id: UniqueId) -> Option<Node<T>> {
+        self.spill.as_ref()?.lock().get(id)
+    }
+
+    pub(crate) fn spilled_nodes(&self) -> Vec<Node<T>> {
+        self.spill
+            .as_ref()
+            .map(|spill| spill.lock().nodes())
+            .unwrap_or_default()
+    }
+
+    /// Nodes in memory and on disk, counted under the spill's lock, which
+    /// eviction holds, so a tombstone being moved is counted once
+    pub(crate) fn stored_count(&self) -> usize {
+        match &self.spill {
+            Some(spill) => {
+                let spill = spill.lock();
+                self.nodes.len() + spill.len() as usize
+            }
+            None => self.nodes.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_converged;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("crdt-rga-spill-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_old_tombstones_move_to_disk() {
+        let dir = scratch("move");
+        let mut rga = RGA::new(1);
+        rga.enable_tombstone_spill(&dir, 4).unwrap();
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "abcdefghij")
+            .unwrap();
+        for &id in &ids[..8] {
+            rga.delete(id).unwrap();
+        }
+
+        // Checked every 2 deletions; the 6 tombstones counted after the sixth
+        // exceed the threshold and all but 2 are spilled
+        let stats = rga.tombstone_spill_stats().unwrap();
+        assert_eq!(stats.spilled, 4);
+        assert_eq!(stats.runs, 1);
+        assert_eq!(rga.nodes.len(), 2 + 2 + 4);
+        assert_eq!(rga.total_node_count(), 12);
+        assert_eq!(rga.to_string(), "ij");
+
+        // Spilled tombstones are still found and still anchor insertions
+        let a = rga.get_node(ids[0]).unwrap();
+        assert!(a.is_deleted);
+        assert_eq!(a.character, 'a');
+        assert_eq!(a.origin, Some(rga.sentinel_start_id()));
+        rga.delete(ids[0]).unwrap();
+        let x = rga.insert_after(ids[1], 'x').unwrap();
+        assert_eq!(rga.get_node(x).unwrap().origin, Some(ids[1]));
+        rga.validate().unwrap();
+
+        // Full-state exports include them
+        let restored = RGA::from_snapshot(2, &rga.to_snapshot()).unwrap();
+        assert_eq!(restored.total_node_count(), rga.total_node_count());
+        assert_eq!(rga.all_nodes().len(), rga.total_node_count());
+        assert_converged([&rga, &rga.fork(3)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remote_operations_consult_spilled_tombstones() {
+        let dir = scratch("remote");
+        let source = RGA::new(1);
+        let ids = source
+            .insert_str_after(source.sentinel_start_id(), "abcdef")
+            .unwrap();
+        let mut target = source.fork(2);
+        target.enable_tombstone_spill(&dir, 2).unwrap();
+        for &id in &ids[..4] {
+            source.delete(id).unwrap();
+            target.apply_remote_op(source.get_node(id).unwrap());
+        }
+        assert!(target.tombstone_spill_stats().unwrap().spilled > 0);
+
+        // Stale copies and redeliveries do not resurrect spilled tombstones
+        let before = target.sync_metrics();
+        let stale = Node::with_origins(ids[0], 'a', source.sentinel_start_id(), None);
+        target.apply_remote_op(stale);
+        target.apply_remote_op(source.get_node(ids[0]).unwrap());
+        assert_eq!(target.sync_metrics().since(&before).deduplicated, 2);
+
+        // An insertion after a spilled tombstone is integrated
+        let y = source.insert_after(ids[0], 'y').unwrap();
+        for node in source.missing_from(&target) {
+            target.apply_remote_op(node);
+        }
+        assert_eq!(target.get_node(y).unwrap().origin, Some(ids[0]));
+        assert!(source.missing_from(&target).is_empty());
+        assert!(target.missing_from(&source).is_empty());
+        assert_eq!(target.to_string(), "yef");
+        // Spilled tombstones are skipped by index too
+        assert_eq!(target.delete_at(1), Ok(ids[4]));
+        assert_eq!(target.to_string(), "yf");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_runs_are_merged() {
+        let dir = scratch("merge");
+        let mut rga = RGA::new(1);
+        rga.enable_tombstone_spill(&dir, 1).unwrap();
+        let text: String = ('a'..='z').collect();
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), &text)
+            .unwrap();
+        for &id in ids.iter().rev() {
+            rga.delete(id).unwrap();
+        }
+
+        let stats = rga.tombstone_spill_stats().unwrap();
+        assert!(stats.runs <= MAX_RUNS);
+        assert_eq!(stats.spilled, 26);
+        assert_eq!(stats.read_errors, 0);
+        for (&id, character) in ids.iter().zip(text.chars()) {
+            assert_eq!(rga.get_node(id).unwrap().character, character);
+        }
+        let spilled: Vec<UniqueId> = rga.spilled_nodes().iter().map(|node| node.id).collect();
+        assert_eq!(spilled, ids);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,741 @@
+//! Node storage backends for the RGA CRDT.
+//!
+//! The RGA keeps its nodes in an ordered map keyed by `UniqueId`. With the
+//! `std` feature (the default) this is a concurrent `SkipMap` whose entries are
+//! guarded by per-node locks, giving lock-free traversal across threads. Without
+//! `std` the nodes live in an `alloc::collections::BTreeMap` behind a `RefCell`,
+//! which compiles on `no_std + alloc` targets such as embedded devices. The
+//! `single-threaded` feature selects the same backend even with `std`, for
+//! targets like browser WASM where the concurrent structures are pure overhead.
+//!
+//! Both backends expose the same closure-based API, so the RGA logic does not
+//! need to know which one is in use.
+//!
+//! Next to the nodes each backend keeps the document order as a doubly linked
+//! list of IDs, starting at the start sentinel. A new node is integrated the
+//! RGA way: it goes right after its origin, past every node with a greater ID.
+//! Those are the origin's children inserted concurrently with a greater ID and
+//! everything inserted after them, as a node's ID is always greater than its
+//! origin's; the scan stops at the first older node or at the end sentinel.
+//! The resulting order depends only on the nodes and their origins, not on the
+//! order they arrived in, so every replica holding the same nodes shows the
+//! same text. A node without a known origin is integrated after the start
+//! sentinel. Tombstones spilled to disk leave the map but keep their place in
+//! the list, so insertions after them can still be integrated.
+//!
+//! The list is mirrored in an [`OrderIndex`] that counts the visible nodes
+//! and line breaks, so the node at a visible index, or the line it is on, is
+//! found in O(log n) instead of by walking the list. Linking a node, and any change to whether a node is visible, go
+//! through the store, which keeps the index up to date.
+//!
+//! Writes that change the document order are serialized. Traversals in
+//! document order follow the links without locking, like ID-order ones.
+//!
+//! A traversal of the concurrent backend can observe some writes made while it
+//! runs and miss others, but never half of one: a node is stored before it is
+//! linked, and a run inserted with [`NodeStore::extend`] is linked by a single
+//! pointer update. [`NodeStore::frozen`] runs a closure with no write in
+//! progress, and [`NodeStore::batch`] makes several writes appear to it at
+//! once. The single-threaded backend cannot interleave, so both simply run the
+//! closure.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::crdt::node::{Element, Node, SENTINEL_END_ID, SENTINEL_START_ID};
+use crate::crdt::order::OrderIndex;
+use crate::crdt::types::UniqueId;
+
+/// Whether `node` is visible, and a visible line break, as the index records
+fn indexed<T: Element>(node: &Node<T>) -> (bool, bool) {
+    (node.is_visible(), node.is_line_break())
+}
+
+/// A node's neighbours in document order
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    prev: Option<UniqueId>,
+    next: Option<UniqueId>,
+}
+
+#[cfg(all(feature = "std", not(feature = "single-threaded")))]
+mod backend {
+    use super::{Element, Link, Node, OrderIndex, SENTINEL_START_ID, UniqueId};
+    use crossbeam_skiplist::SkipMap;
+    use parking_lot::{Mutex, RwLock};
+    use std::sync::Arc;
+
+    /// Concurrent node store backed by lock-free `SkipMap`s.
+    pub(crate) struct NodeStore<T = char> {
+        map: SkipMap<UniqueId, Arc<RwLock<Node<T>>>>,
+        /// The document order, see the [module docs](super)
+        links: SkipMap<UniqueId, Link>,
+        /// Shared by writers, held exclusively while the store is frozen
+        writers: RwLock<()>,
+        /// Held while the document order changes
+        linking: Mutex<()>,
+        /// IDs of the nodes linked since they were last taken, if tracked
+        inserted: Mutex<Option<Vec<UniqueId>>>,
+        /// The document order by visible index. Locked after a node's lock,
+        /// never before
+        order: Mutex<OrderIndex>,
+    }
+
+    impl<T: Element> NodeStore<T> {
+        pub(crate) fn new() -> Self {
+            let links = SkipMap::new();
+            links.insert(SENTINEL_START_ID, Link::default());
+            NodeStore {
+                map: SkipMap::new(),
+                links,
+                writers: RwLock::new(()),
+                linking: Mutex::new(()),
+                inserted: Mutex::new(None),
+                order: Mutex::new(OrderIndex::new(SENTINEL_START_ID)),
+            }
+        }
+
+        /// Runs `f` on the IDs of the nodes linked since they were last taken
+        pub(super) fn insertions<R>(&self, f: impl FnOnce(&mut Option<Vec<UniqueId>>) -> R) -> R {
+            f(&mut self.inserted.lock())
+        }
+
+        /// Runs `f` on the index of the document order
+        pub(super) fn order<R>(&self, f: impl FnOnce(&mut OrderIndex) -> R) -> R {
+            f(&mut self.order.lock())
+        }
+
+        /// Stores `node` without touching the document order
+        pub(super) fn put(&self, node: Node<T>) {
+            self.map.insert(node.id, Arc::new(RwLock::new(node)));
+        }
+
+        /// Drops node `id` without touching the document order
+        pub(super) fn take(&self, id: &UniqueId) -> Option<Node<T>> {
+            self.map
+                .remove(id)
+                .map(|entry| entry.value().read().clone())
+        }
+
+        pub(super) fn link(&self, id: &UniqueId) -> Option<Link> {
+            self.links.get(id).map(|entry| *entry.value())
+        }
+
+        pub(super) fn set_link(&self, id: UniqueId, link: Link) {
+            self.links.insert(id, link);
+        }
+
+        pub(super) fn drop_link(&self, id: &UniqueId) {
+            self.links.remove(id);
+        }
+
+        /// Runs `f` as the only change to the document order in progress
+        pub(super) fn linking<R>(&self, f: impl FnOnce() -> R) -> R {
+            let _linking = self.linking.lock();
+            f()
+        }
+
+        /// Runs `f` on node `id`, `None` if it is not in memory
+        pub(super) fn inspect<R>(&self, id: &UniqueId, f: impl FnOnce(Option<&Node<T>>) -> R) -> R {
+            match self.map.get(id) {
+                Some(entry) => f(Some(&entry.value().read())),
+                None => f(None),
+            }
+        }
+
+        pub(crate) fn contains(&self, id: &UniqueId) -> bool {
+            self.map.contains_key(id)
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.map.len()
+        }
+
+        pub(crate) fn get(&self, id: &UniqueId) -> Option<Node<T>> {
+            self.map.get(id).map(|entry| entry.value().read().clone())
+        }
+
+        pub(crate) fn update<R>(
+            &self,
+            id: &UniqueId,
+            f: impl FnOnce(&mut Node<T>) -> R,
+        ) -> Option<R> {
+            let _writing = self.writers.read_recursive();
+            let entry = self.map.get(id)?;
+            let mut node = entry.value().write();
+            let indexed = super::indexed(&node);
+            let result = f(&mut node);
+            if super::indexed(&node) != indexed {
+                self.order(|order| order.set_visible(id, node.is_visible(), node.is_line_break()));
+            }
+            Some(result)
+        }
+
+        /// Visits the nodes in ID order until `f` returns a value
+        pub(crate) fn find_map_by_id<R>(
+            &self,
+            mut f: impl FnMut(&Node<T>) -> Option<R>,
+        ) -> Option<R> {
+            self.map.iter().find_map(|entry| f(&entry.value().read()))
+        }
+
+        /// Runs `f` as one write, never observed half done by [`Self::frozen`]
+        pub(crate) fn batch<R>(&self, f: impl FnOnce() -> R) -> R {
+            let _writing = self.writers.read_recursive();
+            f()
+        }
+
+        /// Runs `f` while no write is in progress.
+        ///
+        /// `f` must not write to the store, or it deadlocks.
+        pub(crate) fn frozen<R>(&self, f: impl FnOnce() -> R) -> R {
+            let _frozen = self.writers.write();
+            f()
+        }
+    }
+}
+
+#[cfg(any(not(feature = "std"), feature = "single-threaded"))]
+mod backend {
+    use super::{Element, Link, Node, OrderIndex, SENTINEL_START_ID, UniqueId};
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    /// Single-threaded node store backed by `BTreeMap`s.
+    pub(crate) struct NodeStore<T = char> {
+        map: RefCell<BTreeMap<UniqueId, Node<T>>>,
+        /// The document order, see the [module docs](super)
+        links: RefCell<BTreeMap<UniqueId, Link>>,
+        /// IDs of the nodes linked since they were last taken, if tracked
+        inserted: RefCell<Option<Vec<UniqueId>>>,
+        /// The document order by visible index
+        order: RefCell<OrderIndex>,
+    }
+
+    impl<T: Element> NodeStore<T> {
+        pub(crate) fn new() -> Self {
+            NodeStore {
+                map: RefCell::new(BTreeMap::new()),
+                links: RefCell::new(BTreeMap::from([(SENTINEL_START_ID, Link::default())])),
+                inserted: RefCell::new(None),
+                order: RefCell::new(OrderIndex::new(SENTINEL_START_ID)),
+            }
+        }
+
+        pub(super) fn insertions<R>(&self, f: impl FnOnce(&mut Option<Vec<UniqueId>>) -> R) -> R {
+            f(&mut self.inserted.borrow_mut())
+        }
+
+        pub(super) fn order<R>(&self, f: impl FnOnce(&mut OrderIndex) -> R) -> R {
+            f(&mut self.order.borrow_mut())
+        }
+
+        pub(super) fn put(&self, node: Node<T>) {
+            self.map.borrow_mut().insert(node.id, node);
+        }
+
+        /// Only tombstone spilling and collection drop nodes, which need `std`
+        #[cfg_attr(not(feature = "std"), allow(dead_code))]
+        pub(super) fn take(&self, id: &UniqueId) -> Option<Node<T>> {
+            self.map.borrow_mut().remove(id)
+        }
+
+        pub(super) fn link(&self, id: &UniqueId) -> Option<Link> {
+            self.links.borrow().get(id).copied()
+        }
+
+        pub(super) fn set_link(&self, id: UniqueId, link: Link) {
+            self.links.borrow_mut().insert(id, link);
+        }
+
+        #[cfg_attr(not(feature = "std"), allow(dead_code))]
+        pub(super) fn drop_link(&self, id: &UniqueId) {
+            self.links.borrow_mut().remove(id);
+        }
+
+        pub(super) fn linking<R>(&self, f: impl FnOnce() -> R) -> R {
+            f()
+        }
+
+        pub(super) fn inspect<R>(&self, id: &UniqueId, f: impl FnOnce(Option<&Node<T>>) -> R) -> R {
+            f(self.map.borrow().get(id))
+        }
+
+        pub(crate) fn contains(&self, id: &UniqueId) -> bool {
+            self.map.borrow().contains_key(id)
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.map.borrow().len()
+        }
+
+        pub(crate) fn get(&self, id: &UniqueId) -> Option<Node<T>> {
+            self.map.borrow().get(id).cloned()
+        }
+
+        pub(crate) fn update<R>(
+            &self,
+            id: &UniqueId,
+            f: impl FnOnce(&mut Node<T>) -> R,
+        ) -> Option<R> {
+            let mut map = self.map.borrow_mut();
+            let node = map.get_mut(id)?;
+            let indexed = super::indexed(node);
+            let result = f(node);
+            if super::indexed(node) != indexed {
+                self.order(|order| order.set_visible(id, node.is_visible(), node.is_line_break()));
+            }
+            Some(result)
+        }
+
+        pub(crate) fn find_map_by_id<R>(&self, f: impl FnMut(&Node<T>) -> Option<R>) -> Option<R> {
+            self.map.borrow().values().find_map(f)
+        }
+
+        pub(crate) fn batch<R>(&self, f: impl FnOnce() -> R) -> R {
+            f()
+        }
+
+        pub(crate) fn frozen<R>(&self, f: impl FnOnce() -> R) -> R {
+            f()
+        }
+    }
+}
+
+pub(crate) use backend::NodeStore;
+
+impl<T: Element> NodeStore<T> {
+    /// Inserts `node`, or replaces the node with its ID.
+    ///
+    /// A new node is integrated into the document order after its origin; a
+    /// replaced one keeps its place.
+    pub(crate) fn insert(&self, node: Node<T>) {
+        self.batch(|| self.linking(|| self.integrate(node)));
+    }
+
+    /// Inserts many nodes as one write.
+    ///
+    /// A run of new nodes, each inserted after the previous one, is linked in
+    /// one step, so traversals see either none of it or all of it.
+    pub(crate) fn extend(&self, nodes: impl IntoIterator<Item = Node<T>>) {
+        let nodes: Vec<Node<T>> = nodes.into_iter().collect();
+        self.batch(|| {
+            self.linking(|| {
+                if self.is_new_chain(&nodes) {
+                    self.splice_chain(nodes);
+                } else {
+                    for node in nodes {
+                        self.integrate(node);
+                    }
+                }
+            })
+        });
+    }
+
+    /// Inserts `node` right after `previous` without integrating it, for
+    /// copying a document in document order
+    pub(crate) fn insert_at(&self, previous: UniqueId, node: Node<T>) {
+        self.batch(|| {
+            self.linking(|| {
+                let id = node.id;
+                if self.link(&id).is_none() {
+                    self.splice(previous, id);
+                }
+                self.put(node);
+                self.reindex(&id);
+            })
+        });
+    }
+
+    /// Removes node `id` from memory and from the document order
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn remove(&self, id: &UniqueId) -> Option<Node<T>> {
+        self.batch(|| {
+            self.linking(|| {
+                self.unsplice(id);
+                self.take(id)
+            })
+        })
+    }
+
+    /// Removes node `id` from memory but keeps its place in the document
+    /// order, for tombstones spilled to disk
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn evict(&self, id: &UniqueId) -> Option<Node<T>> {
+        self.batch(|| {
+            let node = self.take(id);
+            self.reindex(id);
+            node
+        })
+    }
+
+    /// Replaces node `id` with `node`, which may have another ID, in place
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn rename(&self, id: &UniqueId, node: Node<T>) {
+        self.insertions(|inserted| {
+            for inserted in inserted.iter_mut().flatten() {
+                if inserted == id {
+                    *inserted = node.id;
+                }
+            }
+        });
+        self.batch(|| {
+            self.linking(|| {
+                if node.id != *id
+                    && let Some(link) = self.link(id)
+                {
+                    self.set_link(node.id, link);
+                    self.relink(link, Some(node.id));
+                    self.drop_link(id);
+                    self.take(id);
+                    self.order(|order| order.rename(id, node.id));
+                }
+                let renamed = node.id;
+                self.put(node);
+                self.reindex(&renamed);
+            })
+        });
+    }
+
+    /// Starts recording the IDs of new nodes for [`NodeStore::take_insertions`]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn track_insertions(&self) {
+        self.insertions(|inserted| {
+            inserted.get_or_insert_with(Vec::new);
+        });
+    }
+
+    /// The IDs of the nodes linked since the last call, if tracked
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn take_insertions(&self) -> Vec<UniqueId> {
+        self.insertions(|inserted| inserted.as_mut().map(core::mem::take))
+            .unwrap_or_default()
+    }
+
+    fn log_insertion(&self, id: UniqueId) {
+        self.insertions(|inserted| {
+            if let Some(inserted) = inserted {
+                inserted.push(id);
+            }
+        });
+    }
+
+    /// Number of visible nodes
+    pub(crate) fn visible_len(&self) -> usize {
+        self.order(|order| order.visible_len())
+    }
+
+    /// The IDs of the visible nodes at `indices`, in O(log n) each, or `None`
+    /// if they reach past the end
+    pub(crate) fn visible_ids(&self, indices: Range<usize>) -> Option<Vec<UniqueId>> {
+        self.order(|order| indices.map(|index| order.visible_at(index)).collect())
+    }
+
+    /// Number of visible lines
+    pub(crate) fn line_count(&self) -> usize {
+        self.order(|order| order.line_count())
+    }
+
+    /// Visible index range of line `row`, without its line break
+    pub(crate) fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        self.order(|order| order.line_range(row))
+    }
+
+    /// Row and column of visible index `index`
+    pub(crate) fn to_position(&self, index: usize) -> Option<(usize, usize)> {
+        self.order(|order| order.to_position(index))
+    }
+
+    /// Records in the index whether node `id` is visible.
+    ///
+    /// Read under the node's lock, so a concurrent [`NodeStore::update`]
+    /// cannot record an older state after it.
+    fn reindex(&self, id: &UniqueId) {
+        self.inspect(id, |node| {
+            let (is_visible, is_line_break) = node.map_or((false, false), indexed);
+            self.order(|order| order.set_visible(id, is_visible, is_line_break));
+        });
+    }
+
+    /// The ID following `id` in document order, spilled tombstones included
+    pub(crate) fn successor(&self, id: &UniqueId) -> Option<UniqueId> {
+        self.link(id)?.next
+    }
+
+    /// The ID right before the end sentinel, spilled tombstones included
+    pub(crate) fn last(&self) -> UniqueId {
+        self.link(&SENTINEL_END_ID)
+            .and_then(|link| link.prev)
+            .unwrap_or(SENTINEL_START_ID)
+    }
+
+    /// Visits the IDs in document order, with their node or `None` for
+    /// spilled tombstones, until `f` returns a value.
+    ///
+    /// The closure must not write to the store.
+    pub(crate) fn walk<R>(
+        &self,
+        mut f: impl FnMut(UniqueId, Option<&Node<T>>) -> Option<R>,
+    ) -> Option<R> {
+        let mut at = Some(SENTINEL_START_ID);
+        while let Some(id) = at {
+            at = self.link(&id)?.next;
+            if let Some(found) = self.inspect(&id, |node| f(id, node)) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Visits the nodes in document order until `f` returns a value.
+    ///
+    /// The closure must not write to the store.
+    pub(crate) fn find_map<R>(&self, mut f: impl FnMut(&Node<T>) -> Option<R>) -> Option<R> {
+        self.walk(|_, node| node.and_then(&mut f))
+    }
+
+    /// Visits every node in document order.
+    ///
+    /// The closure must not call back into the store.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&Node<T>)) {
+        self.find_map(|node| {
+            f(node);
+            None::<()>
+        });
+    }
+
+    /// Visits every node in ID order, which is cheaper where the order does
+    /// not matter.
+    ///
+    /// The closure must not call back into the store.
+    pub(crate) fn for_each_by_id(&self, mut f: impl FnMut(&Node<T>)) {
+        self.find_map_by_id(|node| {
+            f(node);
+            None::<()>
+        });
+    }
+
+    /// Links `node` into the document order, if new, and stores it.
+    ///
+    /// A node already held is updated under its own lock rather than
+    /// replaced, and stays deleted once it is: a copy sent before its
+    /// deletion must not revive it, even when the two race.
+    fn integrate(&self, node: Node<T>) {
+        let id = node.id;
+        if self.link(&id).is_none() {
+            let previous = self.integration_point(&node);
+            // Stored before it is linked, so traversals never reach it missing
+            self.put(node);
+            self.splice(previous, id);
+            return;
+        }
+        let mut node = Some(node);
+        self.update(&id, |local| {
+            if let Some(node) = node.take() {
+                let is_deleted = local.is_deleted || node.is_deleted;
+                *local = Node { is_deleted, ..node };
+            }
+        });
+        if let Some(node) = node {
+            self.put(node);
+            self.reindex(&id);
+        }
+    }
+
+    /// The ID a new node is linked after: its origin, then past every node
+    /// with a greater ID
+    fn integration_point(&self, node: &Node<T>) -> UniqueId {
+        let end = SENTINEL_END_ID;
+        let mut previous = match node.origin.filter(|origin| self.link(origin).is_some()) {
+            // Nothing follows the end sentinel
+            Some(origin) if origin == end => {
+                self.link(&end).and_then(|link| link.prev).unwrap_or(origin)
+            }
+            Some(origin) => origin,
+            None => SENTINEL_START_ID,
+        };
+        while let Some(next) = self.successor(&previous)
+            && next > node.id
+            && next != end
+        {
+            previous = next;
+        }
+        previous
+    }
+
+    /// Returns true if `nodes` are not held yet and each one's origin is the
+    /// node before it
+    fn is_new_chain(&self, nodes: &[Node<T>]) -> bool {
+        nodes
+            .windows(2)
+            .all(|pair| pair[1].origin == Some(pair[0].id) && pair[0].id < pair[1].id)
+            && nodes.iter().all(|node| self.link(&node.id).is_none())
+    }
+
+    /// Stores and links a chain of new nodes, see [`Self::is_new_chain`].
+    ///
+    /// The chain lands where integrating its nodes one by one would put it:
+    /// the first node's successor is older than it, so older than the rest.
+    fn splice_chain(&self, nodes: Vec<Node<T>>) {
+        let (Some(first), Some(last)) = (nodes.first(), nodes.last()) else {
+            return;
+        };
+        let (first, last) = (first.id, last.id);
+        let previous = self.integration_point(&nodes[0]);
+        let next = self.successor(&previous);
+        let ids: Vec<UniqueId> = nodes.iter().map(|node| node.id).collect();
+        for (index, node) in nodes.into_iter().enumerate() {
+            self.log_insertion(node.id);
+            let prev = index.checked_sub(1).map_or(previous, |before| ids[before]);
+            self.order(|order| order.insert_after(prev, node.id));
+            self.set_link(
+                node.id,
+                Link {
+                    prev: Some(prev),
+                    next: ids.get(index + 1).copied().or(next),
+                },
+            );
+            self.put(node);
+        }
+        if let Some(next) = next {
+            self.set_link(
+                next,
+                Link {
+                    prev: Some(last),
+                    ..self.link(&next).unwrap_or_default()
+                },
+            );
+        }
+        // Forward traversals see the whole chain once its predecessor links it
+        self.set_link(
+            previous,
+            Link {
+                next: Some(first),
+                ..self.link(&previous).unwrap_or_default()
+            },
+        );
+        for id in &ids {
+            self.reindex(id);
+        }
+    }
+
+    /// Links `id` right after `previous`
+    fn splice(&self, previous: UniqueId, id: UniqueId) {
+        self.log_insertion(id);
+        self.order(|order| order.insert_after(previous, id));
+        let next = self.successor(&previous);
+        self.set_link(
+            id,
+            Link {
+                prev: Some(previous),
+                next,
+            },
+        );
+        // Forward traversals see the new node once its predecessor links it
+        self.relink(
+            Link {
+                prev: Some(previous),
+                next,
+            },
+            Some(id),
+        );
+        self.reindex(&id);
+    }
+
+    /// Unlinks `id` from its neighbours
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    fn unsplice(&self, id: &UniqueId) {
+        if let Some(link) = self.link(id) {
+            self.relink(link, None);
+            self.drop_link(id);
+            self.order(|order| order.remove(id));
+        }
+    }
+
+    /// Points the neighbours in `link` at `id`, or at each other if `None`
+    fn relink(&self, link: Link, id: Option<UniqueId>) {
+        if let Some(next) = link.next {
+            let prev = id.or(link.prev);
+            self.set_link(
+                next,
+                Link {
+                    prev,
+                    ..self.link(&next).unwrap_or_default()
+                },
+            );
+        }
+        if let Some(previous) = link.prev {
+            let next = id.or(link.next);
+            self.set_link(
+                previous,
+                Link {
+                    next,
+                    ..self.link(&previous).unwrap_or_default()
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_integrates_after_origins() {
+        let store = NodeStore::new();
+        let start = Node::sentinel_start();
+        let end = Node::sentinel_end();
+        let a = UniqueId::new(1, 1);
+        store.insert(start.clone());
+        store.insert(end.clone());
+        store.insert(Node::with_origin(a, 'a', start.id));
+        store.insert(Node::with_origin(UniqueId::new(2, 1), 'c', a));
+        // Concurrent with 'c', so after it and everything inserted after it
+        store.insert(Node::with_origin(UniqueId::new(2, 0), 'b', a));
+        store.insert(Node::with_origin(
+            UniqueId::new(3, 1),
+            'd',
+            UniqueId::new(2, 1),
+        ));
+        // Newer than everything after 'a', so right after it
+        store.insert(Node::with_origin(UniqueId::new(4, 1), 'x', a));
+
+        let mut seen = String::new();
+        store.for_each(|node| seen.push(node.character));
+        assert_eq!(seen, format!("{}axcdb{}", start.character, end.character));
+        assert_eq!(store.len(), 7);
+
+        // ID order is still available
+        let mut by_id = String::new();
+        store.for_each_by_id(|node| by_id.push(node.character));
+        assert_eq!(by_id, format!("{}abcdx{}", start.character, end.character));
+
+        // Evicted nodes keep their place, removed ones do not
+        store.evict(&UniqueId::new(4, 1));
+        store.remove(&UniqueId::new(2, 0));
+        let mut placed = Vec::new();
+        store.walk(|id, node| {
+            placed.push((id, node.is_some()));
+            None::<()>
+        });
+        assert_eq!(placed[2], (UniqueId::new(4, 1), false));
+        assert_eq!(placed.len(), 6);
+        assert_eq!(store.successor(&UniqueId::new(3, 1)), Some(end.id));
+        assert_eq!(store.last(), UniqueId::new(3, 1));
+    }
+
+    #[test]
+    fn test_store_update_and_get() {
+        let store = NodeStore::new();
+        let id = UniqueId::new(1, 1);
+        store.insert(Node::new(id, 'a'));
+
+        assert_eq!(store.update(&id, |node| node.delete()), Some(Ok(())));
+        assert!(store.get(&id).unwrap().is_deleted);
+        assert!(store.contains(&id));
+        assert_eq!(store.update(&UniqueId::new(9, 9), |_| ()), None);
+        assert_eq!(store.find_map(|node| Some(node.id)), Some(id));
+    }
+}
@@ -0,0 +1,228 @@
+//! Exports of the node graph for visualization.
+//!
+//! [`RGA::export_structure`] writes every node, tombstones and sentinels
+//! included, with the origin and right origin it was inserted between. As
+//! Graphviz DOT, nodes are coloured by replica, tombstones are dashed and
+//! origin edges form the insertion tree:
+//!
+//! ```text
+//! dot -Tsvg structure.dot > structure.svg
+//! ```
+//!
+//! As JSON, the same graph can be fed to other tools. IDs are written as
+//! `counter.replica.sequence`, like snapshots.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::crdt::node::{Node, SENTINEL_START_ID};
+use crate::crdt::rga::RGA;
+use crate::crdt::snapshot::write_id;
+use crate::crdt::types::UniqueId;
+
+/// Fill colours assigned to replicas in DOT output, by replica ID
+const REPLICA_COLORS: [&str; 8] = [
+    "#8dd3c7", "#ffffb3", "#bebada", "#fb8072", "#80b1d3", "#fdb462", "#b3de69", "#fccde5",
+];
+
+/// Output format of [`RGA::export_structure`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureFormat {
+    /// A Graphviz `digraph`
+    Dot,
+    /// A JSON object listing every node
+    Json,
+}
+
+impl StructureFormat {
+    /// Parses `dot` or `json`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dot" => Some(StructureFormat::Dot),
+            "json" => Some(StructureFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+fn id_string(id: UniqueId) -> String {
+    let mut out = String::new();
+    write_id(&mut out, id);
+    out
+}
+
+/// A readable label for `character` inside a quoted DOT string
+fn dot_label(out: &mut String, node: &Node) {
+    if node.is_sentinel() {
+        out.push_str(if node.id == SENTINEL_START_ID {
+            "start"
+        } else {
+            "end"
+        });
+        return;
+    }
+    match node.character {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        ' ' => out.push('␠'),
+        '\n' => out.push('↵'),
+        '\t' => out.push('⇥'),
+        c if c.is_control() => {
+            let _ = write!(out, "U+{:04X}", u32::from(c));
+        }
+        c => out.push(c),
+    }
+}
+
+/// Appends `character` as a JSON string
+fn json_char(out: &mut String, character: char) {
+    out.push('"');
+    match character {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        '\r' => out.push_str("\\r"),
+        c if c.is_control() => {
+            let _ = write!(out, "\\u{:04x}", u32::from(c));
+        }
+        c => out.push(c),
+    }
+    out.push('"');
+}
+
+fn json_id(out: &mut String, id: Option<UniqueId>) {
+    match id {
+        Some(id) => {
+            out.push('"');
+            write_id(out, id);
+            out.push('"');
+        }
+        None => out.push_str("null"),
+    }
+}
+
+impl RGA {
+    /// Writes the node graph for visualization tools.
+    ///
+    /// Every node is included, tombstones and sentinels too, with its replica,
+    /// its deletion state, its origin and its right origin.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - Graphviz DOT or JSON
+    ///
+    /// # Returns
+    ///
+    /// The graph as text
+    pub fn export_structure(&self, format: StructureFormat) -> String {
+        match format {
+            StructureFormat::Dot => self.structure_dot(),
+            StructureFormat::Json => self.structure_json(),
+        }
+    }
+
+    fn structure_dot(&self) -> String {
+        let mut out = String::from("digraph rga {\n");
+        out.push_str("  rankdir=LR;\n");
+        out.push_str("  node [shape=box, style=filled, fontname=\"monospace\"];\n");
+        let mut edges = String::new();
+        self.for_each_node(|node| {
+            let id = id_string(node.id);
+            let _ = write!(out, "  \"{id}\" [label=\"");
+            dot_label(&mut out, node);
+            if node.is_sentinel() {
+                out.push_str("\", fillcolor=\"white\"];\n");
+            } else {
+                let color =
+                    REPLICA_COLORS[(node.id.replica_id() % REPLICA_COLORS.len() as u64) as usize];
+                let _ = write!(out, "\\n{id}\", fillcolor=\"{color}\"");
+                if node.is_deleted {
+                    out.push_str(", style=\"filled,dashed\", fontcolor=\"gray40\"");
+                }
+                out.push_str("];\n");
+            }
+            if let Some(origin) = node.origin {
+                let _ = writeln!(edges, "  \"{}\" -> \"{id}\";", id_string(origin));
+            }
+            if let Some(right_origin) = node.right_origin {
+                let _ = writeln!(
+                    edges,
+                    "  \"{id}\" -> \"{}\" [style=dotted, arrowhead=open, constraint=false];",
+                    id_string(right_origin)
+                );
+            }
+        });
+        out.push_str(&edges);
+        out.push_str("}\n");
+        out
+    }
+
+    fn structure_json(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "{{\"replica_id\":{},\"nodes\":[", self.replica_id());
+        let mut first = true;
+        self.for_each_node(|node| {
+            if !first {
+                out.push(',');
+            }
+            first = false;
+            out.push_str("{\"id\":");
+            json_id(&mut out, Some(node.id));
+            let _ = write!(out, ",\"replica\":{},\"character\":", node.id.replica_id());
+            json_char(&mut out, node.character);
+            let _ = write!(
+                out,
+                ",\"deleted\":{},\"sentinel\":{},\"origin\":",
+                node.is_deleted,
+                node.is_sentinel()
+            );
+            json_id(&mut out, node.origin);
+            out.push_str(",\"right_origin\":");
+            json_id(&mut out, node.right_origin);
+            out.push('}');
+        });
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_shows_origins_and_tombstones() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "a\"")
+            .unwrap();
+        rga.delete(ids[1]).unwrap();
+
+        let dot = rga.export_structure(StructureFormat::Dot);
+        assert!(dot.starts_with("digraph rga {\n"));
+        assert!(dot.contains("\"1.1.0\" [label=\"a\\n1.1.0\", fillcolor=\"#ffffb3\"];"));
+        assert!(
+            dot.contains("[label=\"\\\"\\n1.1.1\", fillcolor=\"#ffffb3\", style=\"filled,dashed\"")
+        );
+        assert!(dot.contains("\"0.0.0\" -> \"1.1.0\";"));
+        assert!(dot.contains("\"1.1.0\" -> \"1.1.1\";"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_json_lists_every_node() {
+        let rga = RGA::new(7);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), '\n').unwrap();
+        rga.delete(a_id).unwrap();
+
+        let json = rga.export_structure(StructureFormat::Json);
+        assert!(json.starts_with("{\"replica_id\":7,\"nodes\":[{\"id\":\"0.0.0\""));
+        assert!(json.contains(
+            "{\"id\":\"1.7.0\",\"replica\":7,\"character\":\"\\n\",\"deleted\":true,\"sentinel\":false,\"origin\":\"0.0.0\",\"right_origin\":\"18446744073709551615.18446744073709551615.0\"}"
+        ));
+        assert_eq!(json.matches("\"id\"").count(), 3);
+        assert_eq!(StructureFormat::parse("json"), Some(StructureFormat::Json));
+        assert_eq!(StructureFormat::parse("svg"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Filling document regions from text templates.
+//!
+//! A [`Template`] is text with `{{name}}` placeholders, as used for form
+//! letters and boilerplate. [`RGA::instantiate_template`] renders it with a
+//! data map and writes the result over a region of the document in one
+//! [`RGA::transaction`], so readers and peers see the region either as it was
+//! or fully instantiated.
+//!
+//! Only the part of the region that differs from the rendered text is
+//! replaced: characters shared at its start and end keep their IDs, and with
+//! them their anchors and formatting. Instantiating a template over its own
+//! earlier rendering with changed data therefore touches little more than the
+//! changed values.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::crdt::rga::RGA;
+use crate::crdt::txn::Commit;
+
+/// A piece of a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(String),
+}
+
+/// Text with `{{name}}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parses a template.
+    ///
+    /// Placeholder names are trimmed, so `{{ name }}` and `{{name}}` are the
+    /// same placeholder.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Template)` - The parsed template
+    /// * `Err(&str)` - Error message if a placeholder is unclosed or unnamed
+    pub fn parse(source: &str) -> Result<Template, &'static str> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(open) = rest.find("{{") {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let after = &rest[open + 2..];
+            let close = after.find("}}").ok_or("Unclosed template placeholder")?;
+            let name = after[..close].trim();
+            if name.is_empty() {
+                return Err("Unnamed template placeholder");
+            }
+            parts.push(Part::Placeholder(name.to_string()));
+            rest = &after[close + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// The names of the template's placeholders, in order of appearance
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(name) => Some(name.as_str()),
+            Part::Text(_) => None,
+        })
+    }
+
+    /// Renders the template.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The value of every placeholder
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The template with its placeholders replaced
+    /// * `Err(&str)` - Error message if a placeholder has no value
+    pub fn render(&self, data: &BTreeMap<String, String>) -> Result<String, &'static str> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(name) => {
+                    rendered.push_str(data.get(name).ok_or("Missing template value")?)
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+impl RGA {
+    /// Instantiates a template over a region of the visible document.
+    ///
+    /// The region is replaced by the rendered template in one transaction.
+    /// Characters the region shares with the rendering at its start and end
+    /// are kept rather than rewritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - Visible indices of the region; empty to insert the
+    ///   rendering at `range.start`
+    /// * `template` - The template to instantiate
+    /// * `data` - The value of every placeholder
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Commit)` - The insertions and deletions, to broadcast as one batch
+    /// * `Err(&str)` - Error message if a placeholder has no value or the
+    ///   region is outside the document; the document is unchanged
+    pub fn instantiate_template(
+        &self,
+        range: Range<usize>,
+        template: &Template,
+        data: &BTreeMap<String, String>,
+    ) -> Result<Commit, &'static str> {
+        let rendered = template.render(data)?;
+        let rendered: Vec<char> = self.normalization.apply(&rendered).chars().collect();
+        let visible = self.read_txn();
+        let region = visible
+            .visible_nodes()
+            .get(range.clone())
+            .ok_or("Template region outside the document")?;
+
+        let prefix = region
+            .iter()
+            .zip(&rendered)
+            .take_while(|(node, character)| node.character == **character)
+            .count();
+        let suffix = region[prefix..]
+            .iter()
+            .rev()
+            .zip(rendered[prefix..].iter().rev())
+            .take_while(|(node, character)| node.character == **character)
+            .count();
+
+        let replaced = &region[prefix..region.len() - suffix];
+        let inserted: String = rendered[prefix..rendered.len() - suffix].iter().collect();
+        let after = visible.insertion_point(range.start + prefix);
+        self.transaction(|txn| {
+            for node in replaced {
+                txn.delete(node.id)?;
+            }
+            txn.insert_str_after(after, &inserted)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_and_renders_templates() {
+        let template = Template::parse("Dear {{ name }}, re: {{topic}}.").unwrap();
+        assert_eq!(
+            template.placeholders().collect::<Vec<_>>(),
+            ["name", "topic"]
+        );
+        assert_eq!(
+            template
+                .render(&data(&[("name", "Ada"), ("topic", "CRDTs")]))
+                .unwrap(),
+            "Dear Ada, re: CRDTs."
+        );
+        assert!(template.render(&data(&[("name", "Ada")])).is_err());
+        assert!(Template::parse("Dear {{name").is_err());
+        assert!(Template::parse("Dear {{ }}").is_err());
+    }
+
+    #[test]
+    fn test_instantiates_over_a_region() {
+        let rga = RGA::new(1);
+        let template = Template::parse("Hi {{name}}!").unwrap();
+
+        let commit = rga
+            .instantiate_template(0..0, &template, &data(&[("name", "Ada")]))
+            .unwrap();
+        assert_eq!(commit.inserted().count(), 7);
+        assert_eq!(rga.to_string(), "Hi Ada!");
+
+        // Only the changed value is rewritten
+        let kept = [rga.read_txn().id_at(0), rga.read_txn().id_at(6)];
+        let commit = rga
+            .instantiate_template(0..7, &template, &data(&[("name", "Bob")]))
+            .unwrap();
+        assert_eq!(commit.deleted.len(), 3);
+        assert_eq!(commit.inserted().count(), 3);
+        assert_eq!(rga.visible_node_count(), 7);
+        assert!(
+            kept.iter()
+                .flatten()
+                .all(|id| rga.read_txn().index_of(*id).is_some())
+        );
+
+        assert!(
+            rga.instantiate_template(1..20, &template, &data(&[("name", "Eve")]))
+                .is_err()
+        );
+        assert!(
+            rga.instantiate_template(0..7, &template, &data(&[]))
+                .is_err()
+        );
+        assert!(rga.validate().is_ok());
+    }
+}
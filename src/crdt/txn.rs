@@ -0,0 +1,466 @@
+//! Transactions over an RGA.
+//!
+//! Each query on an [`RGA`] walks the live document, so two queries made one
+//! after the other can observe different states when another thread edits in
+//! between: `visible_nodes().len()` may disagree with `to_string()`. A
+//! [`ReadTxn`] captures the document once, with no write in progress, and
+//! answers every query from that capture.
+//!
+//! Likewise, an edit made of several inserts and deletes, such as replacing a
+//! selection, is seen half done between its steps. [`RGA::transaction`] stages
+//! the steps and applies them together as one [`Commit`]: readers see all of
+//! them or none, every insertion shares one Lamport counter like a clock
+//! batch, and the commit is the unit to broadcast and to undo.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+use core::ops::Range;
+
+use crate::crdt::node::{Node, SENTINEL_START_ID};
+use crate::crdt::rga::RGA;
+use crate::crdt::types::{LamportTimestamp, UniqueId};
+
+/// A consistent view of a document, taken by [`RGA::read_txn`].
+///
+/// The view owns its copy of the visible text, so it does not hold up writers
+/// and later edits do not change it.
+#[derive(Debug, Clone)]
+pub struct ReadTxn {
+    /// Visible nodes in document order
+    visible: Vec<Node>,
+    /// Every node, including tombstones and sentinels
+    total_nodes: usize,
+}
+
+impl ReadTxn {
+    /// Number of visible characters
+    pub fn len(&self) -> usize {
+        self.visible.len()
+    }
+
+    /// Returns true if no character is visible
+    pub fn is_empty(&self) -> bool {
+        self.visible.is_empty()
+    }
+
+    /// Number of nodes, including tombstones and sentinels
+    pub fn total_node_count(&self) -> usize {
+        self.total_nodes
+    }
+
+    /// The visible nodes in document order
+    pub fn visible_nodes(&self) -> &[Node] {
+        &self.visible
+    }
+
+    /// The ID of the visible character at `index`
+    pub fn id_at(&self, index: usize) -> Option<UniqueId> {
+        self.visible.get(index).map(|node| node.id)
+    }
+
+    /// The visible index of the character `id`, or `None` if it is deleted
+    /// or unknown
+    pub fn index_of(&self, id: UniqueId) -> Option<usize> {
+        self.visible.iter().position(|node| node.id == id)
+    }
+
+    /// The ID to insert after so that new text lands at visible `index`.
+    ///
+    /// Indexes past the end insert at the end.
+    pub fn insertion_point(&self, index: usize) -> UniqueId {
+        match index.min(self.visible.len()) {
+            0 => SENTINEL_START_ID,
+            index => self.visible[index - 1].id,
+        }
+    }
+
+    /// The visible text in `range`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The characters in `range`
+    /// * `Err(&str)` - Error message if `range` is outside the document
+    pub fn slice(&self, range: Range<usize>) -> Result<String, &'static str> {
+        self.visible
+            .get(range)
+            .map(|nodes| nodes.iter().map(|node| node.character).collect())
+            .ok_or("Range outside the document")
+    }
+}
+
+impl fmt::Display for ReadTxn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.visible
+            .iter()
+            .try_for_each(|node| f.write_char(node.character))
+    }
+}
+
+impl RGA {
+    /// Captures the document for several queries that must agree.
+    ///
+    /// Waits for writes in progress to finish, including every run of
+    /// [`RGA::insert_str_after`], then copies the visible nodes. Writers are
+    /// only held up while the copy is taken.
+    ///
+    /// # Returns
+    ///
+    /// A [`ReadTxn`] answering queries from the captured state
+    pub fn read_txn(&self) -> ReadTxn {
+        self.nodes.frozen(|| ReadTxn {
+            visible: self.visible_nodes(),
+            total_nodes: self.total_node_count(),
+        })
+    }
+}
+
+/// Local edits staged by [`RGA::transaction`].
+///
+/// Nothing reaches the document until the transaction commits. Staged
+/// insertions can be referenced by later steps of the same transaction.
+pub struct Transaction<'a> {
+    rga: &'a RGA,
+    /// The tick whose counter stamps every insertion
+    stamp: Option<LamportTimestamp>,
+    /// Staged insertions in ID order, which is staging order
+    inserts: Vec<Node>,
+    /// Nodes of the document to delete, in staging order
+    deletes: Vec<UniqueId>,
+}
+
+impl Transaction<'_> {
+    /// Number of staged insertions and deletions
+    pub fn len(&self) -> usize {
+        self.inserts.len() + self.deletes.len()
+    }
+
+    /// Returns true if nothing is staged
+    pub fn is_empty(&self) -> bool {
+        self.inserts.is_empty() && self.deletes.is_empty()
+    }
+
+    /// Stages inserting `character` after `after_id`.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_id` - A node of the document or of this transaction
+    /// * `character` - The character to insert
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UniqueId)` - The ID the character will have
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    pub fn insert_after(
+        &mut self,
+        after_id: UniqueId,
+        character: char,
+    ) -> Result<UniqueId, &'static str> {
+        if !self.contains(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        // A run staged in this transaction shares its first node's neighbor
+        let right_origin = match self.inserts.binary_search_by_key(&after_id, |node| node.id) {
+            Ok(index) => self.inserts[index].right_origin,
+            Err(_) => self.rga.right_of(after_id),
+        };
+        let id = self.next_id();
+        self.inserts
+            .push(Node::with_origins(id, character, after_id, right_origin));
+        Ok(id)
+    }
+
+    /// Stages inserting `text` after `after_id` as a chained run, normalized
+    /// like [`RGA::insert_str_after`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs the characters will have, in text order
+    /// * `Err(&str)` - Error message if the reference node does not exist
+    pub fn insert_str_after(
+        &mut self,
+        after_id: UniqueId,
+        text: &str,
+    ) -> Result<Vec<UniqueId>, &'static str> {
+        if !self.contains(after_id) {
+            return Err("Reference node for insertion not found");
+        }
+        let text = self.rga.normalization.apply(text);
+        let mut after_id = after_id;
+        let mut ids = Vec::new();
+        for character in text.chars() {
+            after_id = self.insert_after(after_id, character)?;
+            ids.push(after_id);
+        }
+        Ok(ids)
+    }
+
+    /// Stages deleting the node `id`, of the document or of this transaction.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the deletion was staged
+    /// * `Err(&str)` - Error message if the node does not exist or is a sentinel
+    pub fn delete(&mut self, id: UniqueId) -> Result<(), &'static str> {
+        if let Ok(index) = self.inserts.binary_search_by_key(&id, |node| node.id) {
+            return self.inserts[index].delete();
+        }
+        let node = self.rga.get_node(id).ok_or("Node to delete not found")?;
+        if node.is_sentinel() {
+            return Err("Cannot delete sentinel nodes");
+        }
+        if !node.is_deleted && !self.deletes.contains(&id) {
+            self.deletes.push(id);
+        }
+        Ok(())
+    }
+
+    fn contains(&self, id: UniqueId) -> bool {
+        self.rga.holds(id)
+            || self
+                .inserts
+                .binary_search_by_key(&id, |node| node.id)
+                .is_ok()
+    }
+
+    /// The ID of the next staged insertion.
+    ///
+    /// The first insertion ticks the clock; later ones reuse its counter with
+    /// consecutive sequence numbers, as [`crate::Clock::tick_batch`] would.
+    fn next_id(&mut self) -> UniqueId {
+        let rga = self.rga;
+        let stamp = *self.stamp.get_or_insert_with(|| rga.clock.tick());
+        UniqueId::from(LamportTimestamp {
+            sequence: stamp.sequence.wrapping_add(self.inserts.len() as u32),
+            ..stamp
+        })
+    }
+
+    fn commit(self) -> Commit {
+        let rga = self.rga;
+        rga.nodes.batch(|| {
+            for node in &self.inserts {
+                rga.nodes.insert(node.clone());
+            }
+            for id in &self.deletes {
+                rga.nodes.update(id, Node::delete);
+            }
+        });
+        for &id in &self.deletes {
+            rga.note_deletion(id);
+        }
+
+        let mut operations = self.inserts;
+        for &id in &self.deletes {
+            if let Some(node) = rga.get_node(id) {
+                operations.push(node);
+            }
+        }
+        for node in &operations {
+            rga.debug_validate(node.id);
+        }
+        Commit {
+            deleted: self.deletes,
+            operations,
+        }
+    }
+}
+
+/// The edits of a committed [`Transaction`]
+#[derive(Debug, Clone, Default)]
+pub struct Commit {
+    /// Nodes to send to other replicas with [`RGA::apply_remote_op`]: the
+    /// insertions, deleted if the transaction deleted them, followed by the
+    /// deleted nodes of the document
+    pub operations: Vec<Node>,
+    /// Nodes of the document the transaction deleted
+    pub deleted: Vec<UniqueId>,
+}
+
+impl Commit {
+    /// Returns true if the transaction changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// IDs of the inserted nodes, in staging order
+    pub fn inserted(&self) -> impl Iterator<Item = UniqueId> + '_ {
+        let inserts = self.operations.len() - self.deleted.len();
+        self.operations[..inserts].iter().map(|node| node.id)
+    }
+}
+
+impl RGA {
+    /// Applies several local edits atomically.
+    ///
+    /// `f` stages inserts and deletes on a [`Transaction`]. If it succeeds
+    /// they are applied together: [`RGA::read_txn`] sees all of them or none,
+    /// and every insertion is stamped with one Lamport counter, so concurrent
+    /// transactions never interleave their text. If `f` fails, nothing is
+    /// applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Stages the edits
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Commit)` - The applied edits, to broadcast as one batch
+    /// * `Err(&str)` - The error returned by `f`, or [`crate::crdt::freeze::FROZEN`]
+    ///   if the document is frozen; the document is unchanged
+    pub fn transaction(
+        &self,
+        f: impl FnOnce(&mut Transaction<'_>) -> Result<(), &'static str>,
+    ) -> Result<Commit, &'static str> {
+        self.ensure_unfrozen()?;
+        let mut txn = Transaction {
+            rga: self,
+            stamp: None,
+            inserts: Vec::new(),
+            deletes: Vec::new(),
+        };
+        f(&mut txn)?;
+        Ok(txn.commit())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_read_txn_queries_agree() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "hello")
+            .unwrap();
+        rga.delete(ids[1]).unwrap();
+
+        let txn = rga.read_txn();
+        rga.insert_str_after(ids[4], " world").unwrap();
+
+        assert_eq!(txn.to_string(), "hllo");
+        assert_eq!(txn.len(), 4);
+        assert_eq!(txn.total_node_count(), 7);
+        assert_eq!(txn.index_of(ids[2]), Some(1));
+        assert_eq!(txn.index_of(ids[1]), None);
+        assert_eq!(txn.id_at(3), Some(ids[4]));
+        assert_eq!(txn.slice(1..3).unwrap(), "ll");
+        assert!(txn.slice(2..9).is_err());
+        assert_eq!(txn.insertion_point(0), rga.sentinel_start_id());
+        assert_eq!(txn.insertion_point(99), ids[4]);
+    }
+
+    #[test]
+    fn test_transaction_commits_as_one_batch() {
+        let rga = RGA::new(1);
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "cat")
+            .unwrap();
+
+        let commit = rga
+            .transaction(|txn| {
+                txn.delete(ids[2])?;
+                let r = txn.insert_after(ids[1], 'r')?;
+                let typo = txn.insert_str_after(r, "tt")?;
+                txn.delete(typo[1])
+            })
+            .unwrap();
+
+        assert_eq!(rga.to_string(), "cart");
+        assert_eq!(commit.deleted, vec![ids[2]]);
+        let inserted: Vec<UniqueId> = commit.inserted().collect();
+        assert_eq!(inserted.len(), 3);
+        assert!(
+            inserted
+                .iter()
+                .all(|id| id.counter() == inserted[0].counter())
+        );
+        assert!(commit.operations[2].is_deleted);
+
+        let replica = RGA::new(2);
+        for node in rga
+            .all_nodes()
+            .into_iter()
+            .filter(|node| ids.contains(&node.id))
+        {
+            replica.apply_remote_op(Node {
+                is_deleted: false,
+                ..node
+            });
+        }
+        for node in commit.operations {
+            replica.apply_remote_op(node);
+        }
+        assert_eq!(replica.to_string(), "cart");
+    }
+
+    #[test]
+    fn test_failed_transaction_changes_nothing() {
+        let rga = RGA::new(1);
+        let ids = rga.insert_str_after(rga.sentinel_start_id(), "ab").unwrap();
+
+        let result = rga.transaction(|txn| {
+            txn.delete(ids[0])?;
+            txn.insert_after(ids[1], 'c')?;
+            txn.delete(rga.sentinel_end_id())
+        });
+
+        assert_eq!(result.unwrap_err(), "Cannot delete sentinel nodes");
+        assert_eq!(rga.to_string(), "ab");
+        assert_eq!(rga.total_node_count(), 4);
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_read_txn_never_sees_half_a_run() {
+        extern crate std;
+        use std::sync::Arc;
+
+        let rga = Arc::new(RGA::new(1));
+        let writer = {
+            let rga = Arc::clone(&rga);
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    rga.insert_str_after(rga.sentinel_start_id(), "abcd")
+                        .unwrap();
+                }
+            })
+        };
+        for _ in 0..200 {
+            let txn = rga.read_txn();
+            assert_eq!(txn.len() % 4, 0);
+            assert_eq!(txn.to_string().chars().count(), txn.len());
+        }
+        writer.join().unwrap();
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_read_txn_never_sees_half_a_range_deleted() {
+        extern crate std;
+        use std::sync::Arc;
+
+        let rga = Arc::new(RGA::new(1));
+        rga.insert_str_after(rga.sentinel_start_id(), &"abcd".repeat(200))
+            .unwrap();
+        let writer = {
+            let rga = Arc::clone(&rga);
+            std::thread::spawn(move || {
+                for round in 0..200 {
+                    if round % 2 == 0 {
+                        rga.delete_range_at(0, 4).unwrap();
+                    } else {
+                        rga.delete_range(&rga.range_at(0..4).unwrap()).unwrap();
+                    }
+                }
+            })
+        };
+        for _ in 0..200 {
+            assert_eq!(rga.read_txn().len() % 4, 0);
+        }
+        writer.join().unwrap();
+        assert_eq!(rga.visible_node_count(), 0);
+    }
+}
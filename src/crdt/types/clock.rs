@@ -1,34 +1,113 @@
 //! Thread-safe Lamport clock implementation for generating timestamps.
 //!
-//! This module contains the LamportClock struct which provides thread-safe
-//! generation of Lamport timestamps for maintaining causal ordering in the CRDT.
+//! This module contains the `Clock` trait the RGA uses to stamp operations, and
+//! the LamportClock struct which provides thread-safe generation of Lamport
+//! timestamps for maintaining causal ordering in the CRDT.
 
-use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use alloc::vec::Vec;
 
+use crate::crdt::types::counter::Counter;
 use crate::crdt::types::replica::ReplicaId;
 use crate::crdt::types::timestamp::LamportTimestamp;
 
+/// Thread-safety required of every [`Clock`].
+///
+/// Clocks must be `Send + Sync` so the RGA can be shared across threads. With
+/// the `single-threaded` feature the RGA is `!Sync` anyway and no bound applies.
+#[cfg(not(feature = "single-threaded"))]
+pub trait ClockBounds: Send + Sync {}
+#[cfg(not(feature = "single-threaded"))]
+impl<T: Send + Sync + ?Sized> ClockBounds for T {}
+
+/// Thread-safety required of every [`Clock`].
+#[cfg(feature = "single-threaded")]
+pub trait ClockBounds {}
+#[cfg(feature = "single-threaded")]
+impl<T: ?Sized> ClockBounds for T {}
+
+/// A source of timestamps for local operations.
+///
+/// [`LamportClock`] is the default implementation. Tests and simulations can
+/// plug in deterministic or skewed clocks, and applications can supply hybrid
+/// logical clocks or externally coordinated ones, via [`crate::RGA::with_clock`].
+///
+/// Implementations must never hand out the same timestamp twice and must keep
+/// every new timestamp's counter above any counter passed to [`Clock::update`].
+/// A counter handed out by [`Clock::tick`] must not be reused by later ticks:
+/// [`crate::RGA::transaction`] stamps a whole transaction with the counter of
+/// a single tick.
+pub trait Clock: ClockBounds {
+    /// Generates the next timestamp for this replica
+    fn tick(&self) -> LamportTimestamp;
+
+    /// Generates `count` timestamps for edits made as one logical event.
+    ///
+    /// The default implementation calls [`Clock::tick`] `count` times.
+    fn tick_batch(&self, count: usize) -> Vec<LamportTimestamp> {
+        (0..count).map(|_| self.tick()).collect()
+    }
+
+    /// Reserves a block of `count` consecutive counters for a planned bulk
+    /// insert.
+    ///
+    /// The default implementation ticks once and advances the clock past the
+    /// rest of the block with [`Clock::update`]. That is only atomic if the
+    /// clock is not ticked concurrently, so clocks shared between threads
+    /// should override it.
+    fn reserve(&self, count: usize) -> Reservation {
+        if count == 0 {
+            return Reservation::empty(self.replica_id());
+        }
+        let first = self.tick();
+        self.update(LamportTimestamp {
+            counter: first.counter + count as u64 - 1,
+            ..first
+        });
+        Reservation::new(first, count)
+    }
+
+    /// Advances the clock past a timestamp received from another replica
+    fn update(&self, received_timestamp: LamportTimestamp);
+
+    /// Gets the current counter value
+    fn current_counter(&self) -> u64;
+
+    /// Gets the replica ID stamped on generated timestamps
+    fn replica_id(&self) -> ReplicaId;
+
+    /// The wall time, in milliseconds since the Unix epoch, that a counter
+    /// handed out by this kind of clock was generated at, or `None` if its
+    /// counters carry no physical time.
+    ///
+    /// The default implementation returns `None`; [`HybridClock`] overrides
+    /// it.
+    fn wall_time(&self, counter: u64) -> Option<u64> {
+        let _ = counter;
+        None
+    }
+}
+
 /// A thread-safe clock for generating Lamport timestamps
 pub struct LamportClock {
-    counter: AtomicU64,
+    counter: Counter,
     replica_id: ReplicaId,
-    sequence: AtomicU64,
+    sequence: Counter,
 }
 
 impl LamportClock {
     /// Creates a new Lamport clock
     pub fn new(replica_id: ReplicaId) -> Self {
         LamportClock {
-            counter: AtomicU64::new(0),
+            counter: Counter::new(0),
             replica_id,
-            sequence: AtomicU64::new(0),
+            sequence: Counter::new(0),
         }
     }
 
     /// Generates the next timestamp for this replica
     pub fn tick(&self) -> LamportTimestamp {
-        let counter = self.counter.fetch_add(1, AtomicOrdering::SeqCst) + 1;
-        let sequence = self.sequence.fetch_add(1, AtomicOrdering::SeqCst);
+        let counter = self.counter.fetch_add(1) + 1;
+        let sequence = self.sequence.fetch_add(1);
 
         LamportTimestamp {
             counter,
@@ -37,29 +116,62 @@ impl LamportClock {
         }
     }
 
+    /// Generates `count` timestamps that share a single counter value.
+    ///
+    /// The timestamps are distinguished by consecutive sequence numbers, so a
+    /// batch of edits made together (e.g. at several carets) is stamped as one
+    /// logical event while every timestamp stays unique.
+    pub fn tick_batch(&self, count: usize) -> Vec<LamportTimestamp> {
+        let counter = self.counter.fetch_add(1) + 1;
+        let start = self.sequence.fetch_add(count as u64) as u32;
+        // The batch is ordered by sequence alone, so one that would wrap past
+        // `u32::MAX` is numbered from zero instead; its counter is its own
+        let first_sequence = match start.checked_add((count as u32).saturating_sub(1)) {
+            Some(_) => start,
+            None => 0,
+        };
+
+        (0..count as u32)
+            .map(|offset| LamportTimestamp {
+                counter,
+                replica_id: self.replica_id,
+                sequence: first_sequence + offset,
+            })
+            .collect()
+    }
+
+    /// Atomically reserves `count` consecutive counters.
+    ///
+    /// A bulk insert (a paste or an import) planned ahead takes its whole
+    /// block with one atomic addition instead of one per character, and no
+    /// concurrent tick can land inside the block, so the inserted text sorts
+    /// contiguously among this replica's operations.
+    pub fn reserve(&self, count: usize) -> Reservation {
+        if count == 0 {
+            return Reservation::empty(self.replica_id);
+        }
+        let counter = self.counter.fetch_add(count as u64) + 1;
+        let sequence = self.sequence.fetch_add(count as u64);
+
+        Reservation::new(
+            LamportTimestamp {
+                counter,
+                replica_id: self.replica_id,
+                sequence: sequence as u32,
+            },
+            count,
+        )
+    }
+
     /// Updates the clock based on a received timestamp (for causal consistency)
     pub fn update(&self, received_timestamp: LamportTimestamp) {
-        let current = self.counter.load(AtomicOrdering::SeqCst);
-        let new_counter = current.max(received_timestamp.counter);
-
-        // Use compare_and_swap in a loop to ensure we don't go backwards
-        let mut current_val = current;
-        while current_val < new_counter {
-            match self.counter.compare_exchange_weak(
-                current_val,
-                new_counter,
-                AtomicOrdering::SeqCst,
-                AtomicOrdering::SeqCst,
-            ) {
-                Ok(_) => break,
-                Err(actual) => current_val = actual,
-            }
-        }
+        // Raising to the maximum never moves the clock backwards
+        self.counter.fetch_max(received_timestamp.counter);
     }
 
     /// Gets the current counter value (for debugging)
     pub fn current_counter(&self) -> u64 {
-        self.counter.load(AtomicOrdering::SeqCst)
+        self.counter.load()
     }
 
     /// Gets the replica ID
@@ -68,6 +180,172 @@ impl LamportClock {
     }
 }
 
+/// Bits of a [`HybridClock`] counter below the physical time
+const LOGICAL_BITS: u32 = 16;
+
+/// A hybrid logical clock: a Lamport clock whose counters start from the
+/// wall time.
+///
+/// The upper bits of every counter are milliseconds since the Unix epoch and
+/// the lower 16 bits a logical count, so counters still only grow, receiving
+/// a timestamp from a replica whose clock runs ahead still moves this one
+/// past it, and each counter tells roughly when it was generated. That time
+/// is an estimate: it is the newest of the local wall time and every
+/// timestamp seen, so a replica with a fast clock drags the others' dates
+/// forward.
+///
+/// Every replica of a document has to use a hybrid clock for the dates to
+/// mean anything; a plain [`LamportClock`]'s counters read as the first
+/// moments of 1970.
+pub struct HybridClock {
+    lamport: LamportClock,
+    /// Milliseconds since the Unix epoch
+    now: fn() -> u64,
+}
+
+impl HybridClock {
+    /// Creates a hybrid clock reading the system time
+    #[cfg(feature = "std")]
+    pub fn new(replica_id: ReplicaId) -> Self {
+        Self::with_time_source(replica_id, || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64)
+        })
+    }
+
+    /// Creates a hybrid clock reading the time from `now`, in milliseconds
+    /// since the Unix epoch, for targets without `std` and for tests
+    pub fn with_time_source(replica_id: ReplicaId, now: fn() -> u64) -> Self {
+        HybridClock {
+            lamport: LamportClock::new(replica_id),
+            now,
+        }
+    }
+
+    /// Moves the counter up to the current wall time
+    fn catch_up(&self) {
+        self.lamport.counter.fetch_max((self.now)() << LOGICAL_BITS);
+    }
+}
+
+impl Clock for HybridClock {
+    fn tick(&self) -> LamportTimestamp {
+        self.catch_up();
+        self.lamport.tick()
+    }
+
+    fn tick_batch(&self, count: usize) -> Vec<LamportTimestamp> {
+        self.catch_up();
+        self.lamport.tick_batch(count)
+    }
+
+    fn reserve(&self, count: usize) -> Reservation {
+        self.catch_up();
+        self.lamport.reserve(count)
+    }
+
+    fn update(&self, received_timestamp: LamportTimestamp) {
+        self.lamport.update(received_timestamp)
+    }
+
+    fn current_counter(&self) -> u64 {
+        self.lamport.current_counter()
+    }
+
+    fn replica_id(&self) -> ReplicaId {
+        self.lamport.replica_id()
+    }
+
+    fn wall_time(&self, counter: u64) -> Option<u64> {
+        Some(counter >> LOGICAL_BITS)
+    }
+}
+
+/// A block of consecutive counters reserved by [`Clock::reserve`].
+///
+/// Iterating yields one timestamp per counter, in order; the timestamps are
+/// this replica's and nobody else's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    /// The next timestamp to hand out
+    next: LamportTimestamp,
+    /// Timestamps left in the block
+    remaining: usize,
+}
+
+impl Reservation {
+    fn new(first: LamportTimestamp, count: usize) -> Self {
+        Reservation {
+            next: first,
+            remaining: count,
+        }
+    }
+
+    fn empty(replica_id: ReplicaId) -> Self {
+        Reservation {
+            next: LamportTimestamp {
+                counter: 0,
+                replica_id,
+                sequence: 0,
+            },
+            remaining: 0,
+        }
+    }
+
+    /// The counters left in the block
+    pub fn counters(&self) -> core::ops::Range<u64> {
+        self.next.counter..self.next.counter + self.remaining as u64
+    }
+}
+
+impl Iterator for Reservation {
+    type Item = LamportTimestamp;
+
+    fn next(&mut self) -> Option<LamportTimestamp> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let timestamp = self.next;
+        self.remaining -= 1;
+        self.next.counter += 1;
+        self.next.sequence = self.next.sequence.wrapping_add(1);
+        Some(timestamp)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Reservation {}
+
+impl Clock for LamportClock {
+    fn tick(&self) -> LamportTimestamp {
+        LamportClock::tick(self)
+    }
+
+    fn tick_batch(&self, count: usize) -> Vec<LamportTimestamp> {
+        LamportClock::tick_batch(self, count)
+    }
+
+    fn reserve(&self, count: usize) -> Reservation {
+        LamportClock::reserve(self, count)
+    }
+
+    fn update(&self, received_timestamp: LamportTimestamp) {
+        LamportClock::update(self, received_timestamp)
+    }
+
+    fn current_counter(&self) -> u64 {
+        LamportClock::current_counter(self)
+    }
+
+    fn replica_id(&self) -> ReplicaId {
+        LamportClock::replica_id(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,6 +394,177 @@ mod tests {
         assert!(ts1 < ts2);
     }
 
+    #[test]
+    fn test_tick_batch_shares_counter() {
+        let clock = LamportClock::new(3);
+        let before = clock.tick();
+
+        let batch = clock.tick_batch(3);
+        assert_eq!(batch.len(), 3);
+        assert!(batch.iter().all(|ts| ts.counter == before.counter + 1));
+        assert!(batch.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(before < batch[0]);
+
+        let after = clock.tick();
+        assert!(batch[2] < after);
+    }
+
+    #[test]
+    fn test_reserve_takes_a_contiguous_block() {
+        let clock = LamportClock::new(4);
+        let before = clock.tick();
+
+        let block = clock.reserve(3);
+        assert_eq!(block.counters(), before.counter + 1..before.counter + 4);
+        let timestamps: Vec<LamportTimestamp> = block.collect();
+        assert_eq!(timestamps.len(), 3);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(timestamps.iter().all(|ts| ts.replica_id == 4));
+
+        // Later ticks land after the block, and empty blocks take nothing
+        assert_eq!(clock.reserve(0).count(), 0);
+        assert_eq!(clock.tick().counter, before.counter + 4);
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_concurrent_reservations_do_not_overlap() {
+        let clock = std::sync::Arc::new(LamportClock::new(1));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let clock = clock.clone();
+                std::thread::spawn(move || {
+                    (0..50)
+                        .map(|_| clock.reserve(10).counters())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut blocks: Vec<_> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        blocks.sort_by_key(|block| block.start);
+        assert!(blocks.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert_eq!(clock.current_counter(), 2000);
+    }
+
+    #[test]
+    fn test_tick_batch_stays_ordered_across_sequence_wrap() {
+        let clock = LamportClock::new(2);
+        clock.sequence.fetch_add(u64::from(u32::MAX) - 1);
+
+        let mut stamps = vec![clock.tick()];
+        stamps.extend(clock.tick_batch(4));
+        stamps.push(clock.tick());
+        stamps.extend(clock.reserve(3));
+        stamps.push(clock.tick());
+
+        // The running sequence wrapped to zero, yet every stamp is newer than
+        // the one before it
+        assert_eq!(stamps.last().unwrap().sequence, 7);
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    /// Ticks, batches, reservations and updates `clock` from `threads` threads
+    /// at once, returning each thread's timestamps in the order it got them.
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    fn hammer<C: Clock + 'static>(
+        clock: std::sync::Arc<C>,
+        threads: u64,
+        rounds: usize,
+    ) -> Vec<Vec<LamportTimestamp>> {
+        let handles: Vec<_> = (0..threads)
+            .map(|seed| {
+                let clock = clock.clone();
+                std::thread::spawn(move || {
+                    let mut rng = crate::testing::SplitMix64::new(seed);
+                    let mut stamps = Vec::new();
+                    for _ in 0..rounds {
+                        match rng.next_u64() % 4 {
+                            0 => stamps.push(clock.tick()),
+                            1 => stamps.extend(clock.tick_batch(1 + rng.next_u64() as usize % 5)),
+                            2 => stamps.extend(clock.reserve(1 + rng.next_u64() as usize % 5)),
+                            _ => {
+                                let received = clock.current_counter() + rng.next_u64() % 8;
+                                clock.update(LamportTimestamp {
+                                    counter: received,
+                                    replica_id: 99,
+                                    sequence: 0,
+                                });
+                                let next = clock.tick();
+                                assert!(next.counter > received);
+                                stamps.push(next);
+                            }
+                        }
+                    }
+                    stamps
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    fn assert_monotonic_and_unique(per_thread: &[Vec<LamportTimestamp>]) {
+        for stamps in per_thread {
+            assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        let mut all: Vec<_> = per_thread.iter().flatten().copied().collect();
+        let total = all.len();
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), total, "a timestamp was handed out twice");
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_concurrent_lamport_stamps_are_monotonic_and_unique() {
+        for headroom in [u64::from(u32::MAX) + 1, 100] {
+            let clock = LamportClock::new(1);
+            clock.sequence.fetch_add(u64::from(u32::MAX) + 1 - headroom);
+            let per_thread = hammer(std::sync::Arc::new(clock), 8, 2_000);
+            assert_monotonic_and_unique(&per_thread);
+        }
+    }
+
+    #[cfg(all(feature = "std", not(feature = "single-threaded")))]
+    #[test]
+    fn test_concurrent_hybrid_stamps_survive_time_going_backwards() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // A wall clock that steps back a millisecond on every read
+        static NOW: AtomicU64 = AtomicU64::new(1_700_000_000_000);
+        let clock = HybridClock::with_time_source(1, || NOW.fetch_sub(1, Ordering::Relaxed));
+        let per_thread = hammer(std::sync::Arc::new(clock), 8, 2_000);
+        assert_monotonic_and_unique(&per_thread);
+    }
+
+    #[test]
+    fn test_hybrid_clock_counters_carry_wall_time() {
+        let clock = HybridClock::with_time_source(2, || 1_700_000_000_000);
+        let first = clock.tick();
+        let second = clock.tick();
+        assert!(first < second);
+        assert_eq!(clock.wall_time(first.counter), Some(1_700_000_000_000));
+        assert_eq!(clock.wall_time(second.counter), Some(1_700_000_000_000));
+
+        // A replica running ahead drags the clock forward
+        clock.update(LamportTimestamp {
+            counter: 1_700_000_005_000 << LOGICAL_BITS,
+            replica_id: 3,
+            sequence: 0,
+        });
+        assert_eq!(
+            clock.wall_time(clock.tick().counter),
+            Some(1_700_000_005_000)
+        );
+        assert_eq!(LamportClock::new(1).wall_time(5), None);
+    }
+
     #[test]
     fn test_clock_replica_id() {
         let clock = LamportClock::new(42);
@@ -0,0 +1,85 @@
+//! Monotonic counters backing the Lamport clock.
+//!
+//! By default counters are `AtomicU64`s so the clock can be shared between
+//! threads. With the `single-threaded` feature (intended for single-threaded
+//! WASM) they are plain `Cell`s, avoiding the cost of `SeqCst` atomics where no
+//! other thread can ever observe them.
+
+#[cfg(not(feature = "single-threaded"))]
+mod imp {
+    use core::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+    /// A thread-safe monotonic counter.
+    pub(crate) struct Counter(AtomicU64);
+
+    impl Counter {
+        pub(crate) fn new(value: u64) -> Self {
+            Counter(AtomicU64::new(value))
+        }
+
+        /// Adds `delta` and returns the previous value.
+        pub(crate) fn fetch_add(&self, delta: u64) -> u64 {
+            self.0.fetch_add(delta, AtomicOrdering::SeqCst)
+        }
+
+        /// Raises the counter to at least `value` and returns the previous value.
+        pub(crate) fn fetch_max(&self, value: u64) -> u64 {
+            self.0.fetch_max(value, AtomicOrdering::SeqCst)
+        }
+
+        pub(crate) fn load(&self) -> u64 {
+            self.0.load(AtomicOrdering::SeqCst)
+        }
+    }
+}
+
+#[cfg(feature = "single-threaded")]
+mod imp {
+    use core::cell::Cell;
+
+    /// A monotonic counter for single-threaded use.
+    pub(crate) struct Counter(Cell<u64>);
+
+    impl Counter {
+        pub(crate) fn new(value: u64) -> Self {
+            Counter(Cell::new(value))
+        }
+
+        /// Adds `delta` and returns the previous value.
+        pub(crate) fn fetch_add(&self, delta: u64) -> u64 {
+            let previous = self.0.get();
+            self.0.set(previous.wrapping_add(delta));
+            previous
+        }
+
+        /// Raises the counter to at least `value` and returns the previous value.
+        pub(crate) fn fetch_max(&self, value: u64) -> u64 {
+            let previous = self.0.get();
+            self.0.set(previous.max(value));
+            previous
+        }
+
+        pub(crate) fn load(&self) -> u64 {
+            self.0.get()
+        }
+    }
+}
+
+pub(crate) use imp::Counter;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_operations() {
+        let counter = Counter::new(5);
+        assert_eq!(counter.fetch_add(2), 5);
+        assert_eq!(counter.load(), 7);
+
+        assert_eq!(counter.fetch_max(3), 7);
+        assert_eq!(counter.load(), 7);
+        assert_eq!(counter.fetch_max(10), 7);
+        assert_eq!(counter.load(), 10);
+    }
+}
@@ -4,12 +4,14 @@
 //! organized into focused submodules for better maintainability.
 
 pub mod clock;
+mod counter;
 pub mod replica;
 pub mod timestamp;
 pub mod unique_id;
 
 // Re-export all public types for backward compatibility
-pub use clock::LamportClock;
+pub use clock::{Clock, ClockBounds, HybridClock, LamportClock, Reservation};
+pub(crate) use counter::Counter;
 pub use replica::ReplicaId;
 pub use timestamp::LamportTimestamp;
 pub use unique_id::UniqueId;
@@ -3,7 +3,7 @@
 //! This module contains the LamportTimestamp struct which provides a total ordering
 //! of events across replicas in the CRDT system.
 
-use std::cmp::Ordering;
+use core::cmp::Ordering;
 
 use crate::crdt::types::replica::ReplicaId;
 
@@ -15,8 +15,11 @@ use crate::crdt::types::replica::ReplicaId;
 ///
 /// # Ordering
 ///
-/// Lamport timestamps are ordered first by counter, then by replica_id. This ensures
-/// a deterministic global ordering of all operations across all replicas.
+/// Lamport timestamps are ordered first by counter, then by replica_id, then by
+/// sequence. This ensures a deterministic global ordering of all operations across
+/// all replicas, in which timestamps from one [`tick_batch`] are adjacent.
+///
+/// [`tick_batch`]: crate::crdt::types::LamportClock::tick_batch
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LamportTimestamp {
     /// The logical clock value when this timestamp was created
@@ -38,12 +41,11 @@ impl Ord for LamportTimestamp {
         // First compare by counter (logical time)
         match self.counter.cmp(&other.counter) {
             Ordering::Equal => {
-                // If counters are equal, compare by sequence number
-                match self.sequence.cmp(&other.sequence) {
-                    Ordering::Equal => {
-                        // If sequence is also equal, compare by replica_id for deterministic ordering
-                        self.replica_id.cmp(&other.replica_id)
-                    }
+                // If counters are equal, compare by replica_id so that a batch
+                // stamped with one counter stays together, then by sequence
+                // within the batch
+                match self.replica_id.cmp(&other.replica_id) {
+                    Ordering::Equal => self.sequence.cmp(&other.sequence),
                     other => other,
                 }
             }
@@ -110,6 +112,6 @@ mod tests {
 
         assert!(ts1 < ts2); // Same counter/replica, different sequence
         assert!(ts1 < ts3); // Same counter, different replica
-        assert!(ts3 < ts2); // Lower replica wins even with higher sequence
+        assert!(ts2 < ts3); // Lower replica wins even with higher sequence
     }
 }
@@ -124,6 +124,6 @@ mod tests {
 
         assert!(id1 < id2); // Same counter/replica, different sequence
         assert!(id1 < id3); // Same counter, different replica
-        assert!(id3 < id2); // Lower replica wins even with higher sequence
+        assert!(id2 < id3); // Lower replica wins even with higher sequence
     }
 }
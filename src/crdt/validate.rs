@@ -0,0 +1,197 @@
+//! Structural invariant checks for the RGA CRDT.
+//!
+//! A corrupted document usually surfaces far from the bug that caused it, as a
+//! divergence between replicas. [`Rga::validate`] checks the invariants every
+//! replica must uphold so corruption is caught where it happens. Debug builds
+//! check the mutated node after every mutation, and the `validate` feature
+//! makes them run the full check instead.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use crate::crdt::node::{Element, SENTINEL_END_ID};
+use crate::crdt::rga::Rga;
+use crate::crdt::types::UniqueId;
+
+impl<T: Element> Rga<T> {
+    /// Checks the structural invariants of this replica.
+    ///
+    /// The checks are:
+    /// - both sentinels are present and not deleted
+    /// - every recorded origin refers to a node in the document
+    /// - every node is newer than its origin
+    /// - every node comes after its origin in document order
+    /// - no node carries a counter ahead of the local clock
+    ///
+    /// This walks the whole document, so it is O(n log n).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If every invariant holds
+    /// * `Err(&str)` - A description of the first violated invariant
+    pub fn validate(&self) -> Result<(), &'static str> {
+        self.validate_sentinels()?;
+
+        let mut seen = BTreeSet::new();
+        let mut max_counter = 0;
+        let mut origins = Vec::new();
+        let mut misordered = false;
+        self.for_each_node(|node| {
+            if !node.is_sentinel() {
+                max_counter = max_counter.max(node.id.counter());
+            }
+            if node
+                .origin
+                .is_some_and(|origin| !Self::follows(node.id, origin))
+            {
+                misordered = true;
+            }
+            origins.extend(node.origin.map(|origin| (origin, seen.contains(&origin))));
+            seen.insert(node.id);
+        });
+        // Read the clock after the scan: it never moves backwards, and local
+        // operations tick it before their node becomes visible
+        let clock = self.clock.current_counter();
+
+        if origins.iter().any(|&(origin, _)| !self.holds(origin)) {
+            return Err("Node origin is not in the document");
+        }
+        if misordered {
+            return Err("Node is older than its origin");
+        }
+        if origins.iter().any(|&(_, preceded)| !preceded) {
+            return Err("Node precedes its origin");
+        }
+        if max_counter > clock {
+            return Err("Node counter is ahead of the clock");
+        }
+        Ok(())
+    }
+
+    /// Checks the invariants that a mutation of node `id` can break.
+    ///
+    /// This is the O(log n) subset of [`Rga::validate`] restricted to one node.
+    fn validate_node(&self, id: UniqueId) -> Result<(), &'static str> {
+        self.validate_sentinels()?;
+
+        let Some(node) = self.nodes.get(&id) else {
+            return Ok(());
+        };
+        if node.origin.is_some_and(|origin| !self.holds(origin)) {
+            return Err("Node origin is not in the document");
+        }
+        if node
+            .origin
+            .is_some_and(|origin| !Self::follows(node.id, origin))
+        {
+            return Err("Node is older than its origin");
+        }
+        if !node.is_sentinel() && node.id.counter() > self.clock.current_counter() {
+            return Err("Node counter is ahead of the clock");
+        }
+        Ok(())
+    }
+
+    /// Returns true if node `id` may have been inserted after `origin`: it is
+    /// newer, unless the origin is the end sentinel
+    fn follows(id: UniqueId, origin: UniqueId) -> bool {
+        id > origin || origin == SENTINEL_END_ID
+    }
+
+    fn validate_sentinels(&self) -> Result<(), &'static str> {
+        let start = self.nodes.get(&self.sentinel_start_id());
+        let end = self.nodes.get(&self.sentinel_end_id());
+        match (start, end) {
+            (Some(start), Some(end)) if !start.is_deleted && !end.is_deleted => Ok(()),
+            (Some(_), Some(_)) => Err("Sentinel node is deleted"),
+            _ => Err("Sentinel node is missing"),
+        }
+    }
+
+    /// Panics if the mutation of node `id` violated an invariant.
+    ///
+    /// Only checks in debug builds. By default just the mutated node is
+    /// checked; the `validate` feature runs the full [`Rga::validate`] instead.
+    #[inline]
+    pub(crate) fn debug_validate(&self, id: UniqueId) {
+        if cfg!(debug_assertions) {
+            let result = if cfg!(feature = "validate") {
+                self.validate()
+            } else {
+                self.validate_node(id)
+            };
+            if let Err(violation) = result {
+                panic!("RGA invariant violated: {violation}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::node::Node;
+    use crate::crdt::rga::RGA;
+
+    #[test]
+    fn test_valid_document() {
+        let rga = RGA::new(1);
+        assert_eq!(rga.validate(), Ok(()));
+
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        rga.insert_after(a_id, 'b').unwrap();
+        rga.delete(a_id).unwrap();
+        assert_eq!(rga.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_deleted_sentinel_is_reported() {
+        let rga = RGA::new(1);
+        let mut start = Node::sentinel_start();
+        start.is_deleted = true;
+        rga.nodes.insert(start);
+
+        assert_eq!(rga.validate(), Err("Sentinel node is deleted"));
+    }
+
+    #[test]
+    fn test_dangling_origin_is_reported() {
+        let rga = RGA::new(1);
+        let missing = UniqueId::new(5, 2);
+        rga.clock.update(missing.timestamp());
+        rga.nodes
+            .insert(Node::with_origin(UniqueId::new(6, 2), 'x', missing));
+
+        assert_eq!(rga.validate(), Err("Node origin is not in the document"));
+    }
+
+    #[test]
+    fn test_node_older_than_its_origin_is_reported() {
+        let rga = RGA::new(1);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        let b_id = rga.insert_after(a_id, 'b').unwrap();
+        // `a` claims to have been inserted after the newer `b`
+        rga.nodes.update(&a_id, |node| node.origin = Some(b_id));
+
+        assert_eq!(rga.validate(), Err("Node is older than its origin"));
+    }
+
+    #[test]
+    fn test_counter_ahead_of_clock_is_reported() {
+        let rga = RGA::new(1);
+        rga.nodes.insert(Node::new(UniqueId::new(9, 2), 'x'));
+
+        assert_eq!(rga.validate(), Err("Node counter is ahead of the clock"));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "RGA invariant violated")]
+    fn test_mutations_are_checked_in_debug_builds() {
+        let rga = RGA::new(1);
+        let mut start = Node::sentinel_start();
+        start.is_deleted = true;
+        rga.nodes.insert(start);
+        rga.insert_after(rga.sentinel_start_id(), 'x').unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! A sequence CRDT backend in one contiguous vector.
+//!
+//! [`VecSequence`] holds its elements, tombstones included, in a `Vec` in
+//! document order, next to a sorted vector of their IDs. Whether an ID is
+//! known is a binary search of the sorted IDs, and an element is located by
+//! scanning the document vector, which stays in cache. There are no locks and
+//! no per-element allocations, so for a single thread editing documents of
+//! moderate size it is lighter than an [`RGA`](crate::RGA), whose store is
+//! built for concurrent readers and writers.
+//!
+//! Concurrent insertions are placed exactly as the RGA places them, so the two
+//! backends exchange [`Operation`]s and converge with each other. Only the
+//! [`SequenceCrdt`] operations are offered: anchors, marks, snapshots and the
+//! other document features need an RGA.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::crdt::metrics::{ApplyOutcome, RejectReason};
+use crate::crdt::node::{SENTINEL_END_ID, SENTINEL_START_ID};
+use crate::crdt::op::Operation;
+use crate::crdt::sequence::SequenceCrdt;
+use crate::crdt::types::{LamportClock, ReplicaId, UniqueId};
+
+/// An element of the sequence
+#[derive(Debug, Clone, Copy)]
+struct Element {
+    id: UniqueId,
+    character: char,
+    is_deleted: bool,
+}
+
+/// A sequence CRDT backed by a vector, for single-threaded use.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::{ApplyOutcome, RGA, SequenceCrdt, VecSequence};
+///
+/// let mut local = VecSequence::new(1);
+/// let mut remote = RGA::new(2);
+/// let start = local.start_id();
+/// let op = local.insert(start, 'a').unwrap();
+/// assert_eq!(remote.apply(op), ApplyOutcome::Applied);
+/// assert_eq!(SequenceCrdt::text(&remote), local.text());
+/// ```
+pub struct VecSequence {
+    clock: LamportClock,
+    /// Every element in document order, sentinels excluded
+    elements: Vec<Element>,
+    /// The IDs of `elements`, sorted
+    ids: Vec<UniqueId>,
+    version: BTreeMap<ReplicaId, u64>,
+    visible: usize,
+}
+
+impl VecSequence {
+    /// Creates an empty sequence for replica `replica_id`.
+    pub fn new(replica_id: ReplicaId) -> Self {
+        VecSequence {
+            clock: LamportClock::new(replica_id),
+            elements: Vec::new(),
+            ids: Vec::new(),
+            version: BTreeMap::new(),
+            visible: 0,
+        }
+    }
+
+    /// Returns true if `id` is an element or a sentinel
+    fn holds(&self, id: UniqueId) -> bool {
+        id == SENTINEL_START_ID || id == SENTINEL_END_ID || self.ids.binary_search(&id).is_ok()
+    }
+
+    /// Position of element `id` in `elements`
+    fn position(&self, id: UniqueId) -> Option<usize> {
+        if self.ids.binary_search(&id).is_err() {
+            return None;
+        }
+        self.elements.iter().position(|element| element.id == id)
+    }
+
+    /// The position right after `id`: the start for the start sentinel, the
+    /// end for the end sentinel
+    fn after(&self, id: UniqueId) -> Option<usize> {
+        if id == SENTINEL_START_ID {
+            Some(0)
+        } else if id == SENTINEL_END_ID {
+            Some(self.elements.len())
+        } else {
+            self.position(id).map(|position| position + 1)
+        }
+    }
+
+    /// Places a new element after its origin, past every element with a
+    /// greater ID, as the RGA's store does
+    fn integrate(&mut self, origin: UniqueId, element: Element) {
+        let mut at = self.after(origin).unwrap_or(0);
+        while self
+            .elements
+            .get(at)
+            .is_some_and(|next| next.id > element.id)
+        {
+            at += 1;
+        }
+        self.elements.insert(at, element);
+        let sorted = self.ids.binary_search(&element.id).unwrap_or_else(|at| at);
+        self.ids.insert(sorted, element.id);
+        let counter = self.version.entry(element.id.replica_id()).or_insert(0);
+        *counter = (*counter).max(element.id.counter());
+        self.visible += 1;
+    }
+
+    /// Marks element `id` deleted
+    fn tombstone(&mut self, id: UniqueId) -> Result<(), &'static str> {
+        if id == SENTINEL_START_ID || id == SENTINEL_END_ID {
+            return Err("Cannot delete sentinel nodes");
+        }
+        let position = self.position(id).ok_or("Node to delete not found")?;
+        let element = &mut self.elements[position];
+        if !element.is_deleted {
+            element.is_deleted = true;
+            self.visible -= 1;
+        }
+        Ok(())
+    }
+
+    /// Number of elements, tombstones included
+    pub fn total_len(&self) -> usize {
+        self.elements.len()
+    }
+}
+
+impl SequenceCrdt for VecSequence {
+    fn replica_id(&self) -> ReplicaId {
+        self.clock.replica_id()
+    }
+
+    fn insert(&mut self, after_id: UniqueId, character: char) -> Result<Operation, &'static str> {
+        let after = self
+            .after(after_id)
+            .ok_or("Reference node for insertion not found")?;
+        // What followed the origin, as the RGA records it
+        let right_origin = match self.elements.get(after) {
+            _ if after_id == SENTINEL_END_ID => None,
+            Some(next) => Some(next.id),
+            None => Some(SENTINEL_END_ID),
+        };
+        let id = UniqueId::from(self.clock.tick());
+        self.integrate(
+            after_id,
+            Element {
+                id,
+                character,
+                is_deleted: false,
+            },
+        );
+        Ok(Operation::Insert {
+            id,
+            origin: after_id,
+            right_origin,
+            ch: character,
+        })
+    }
+
+    fn remove(&mut self, id: UniqueId) -> Result<Operation, &'static str> {
+        self.tombstone(id)?;
+        Ok(Operation::Delete { id })
+    }
+
+    fn apply(&mut self, op: Operation) -> ApplyOutcome {
+        match op {
+            Operation::Insert { id, origin, ch, .. } => {
+                if !self.holds(origin) {
+                    return ApplyOutcome::Rejected(RejectReason::MissingOrigin(origin));
+                }
+                self.clock.update(id.timestamp());
+                if self.holds(id) {
+                    return ApplyOutcome::Duplicate;
+                }
+                self.integrate(
+                    origin,
+                    Element {
+                        id,
+                        character: ch,
+                        is_deleted: false,
+                    },
+                );
+                ApplyOutcome::Applied
+            }
+            Operation::Delete { id } if id == SENTINEL_START_ID || id == SENTINEL_END_ID => {
+                ApplyOutcome::Rejected(RejectReason::Sentinel)
+            }
+            Operation::Delete { id } => match self.position(id) {
+                None => ApplyOutcome::Rejected(RejectReason::MissingNode(id)),
+                Some(position) if self.elements[position].is_deleted => ApplyOutcome::Duplicate,
+                Some(_) => {
+                    self.tombstone(id).expect("element is held");
+                    ApplyOutcome::TombstoneMerge
+                }
+            },
+        }
+    }
+
+    fn for_each_visible(&self, f: &mut dyn FnMut(UniqueId, char)) {
+        for element in self.elements.iter().filter(|element| !element.is_deleted) {
+            f(element.id, element.character);
+        }
+    }
+
+    fn version(&self) -> BTreeMap<ReplicaId, u64> {
+        self.version.clone()
+    }
+
+    fn len(&self) -> usize {
+        self.visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::rga::RGA;
+
+    /// Types `text` at the start of `sequence`, each character after the
+    /// previous one
+    fn type_text(sequence: &mut impl SequenceCrdt, text: &str) -> Vec<Operation> {
+        let mut after = sequence.start_id();
+        text.chars()
+            .map(|character| {
+                let op = sequence.insert(after, character).unwrap();
+                after = op.id();
+                op
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_vector_backend_converges_with_rga() {
+        let mut vector = VecSequence::new(1);
+        let mut rga = RGA::new(2);
+        let mut other = VecSequence::new(3);
+        let mut ops = type_text(&mut vector, "hello");
+        ops.extend(type_text(&mut rga, "world"));
+        ops.extend(type_text(&mut other, "!!"));
+        let middle = vector.id_at(2).unwrap();
+        ops.push(vector.insert(middle, 'L').unwrap());
+        ops.push(vector.remove(middle).unwrap());
+        assert_eq!(
+            vector.remove(vector.start_id()).unwrap_err(),
+            "Cannot delete sentinel nodes"
+        );
+
+        // Every replica receives every operation, its own ones included
+        for sequence in [&mut vector as &mut dyn SequenceCrdt, &mut rga, &mut other] {
+            for &op in &ops {
+                assert!(!matches!(sequence.apply(op), ApplyOutcome::Rejected(_)));
+            }
+        }
+        assert_eq!(vector.text(), SequenceCrdt::text(&rga));
+        assert_eq!(other.text(), vector.text());
+        assert_eq!(vector.len(), 12);
+        assert_eq!(vector.total_len(), 13);
+        assert_eq!(vector.version(), SequenceCrdt::version(&rga));
+        assert!(rga.validate().is_ok());
+
+        // Operations that arrive too early are refused
+        let mut late = VecSequence::new(4);
+        assert_eq!(
+            late.apply(ops[1]),
+            ApplyOutcome::Rejected(RejectReason::MissingOrigin(ops[0].id()))
+        );
+        assert_eq!(
+            late.apply(ops[13]),
+            ApplyOutcome::Rejected(RejectReason::MissingNode(middle))
+        );
+    }
+}
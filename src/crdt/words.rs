@@ -0,0 +1,216 @@
+//! Word-wise navigation and editing.
+//!
+//! Editor keybindings such as Ctrl+Left, Ctrl+Right and Ctrl+Backspace work on
+//! words rather than characters. Word boundaries follow the Unicode word
+//! segmentation rules (UAX #29), so they behave sensibly for scripts without
+//! spaces and for punctuation. All positions are visible character indices.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::crdt::range::Range;
+use crate::crdt::rga::RGA;
+use crate::crdt::types::UniqueId;
+
+/// A word of the visible document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    /// Visible index of the word's first character
+    pub start: usize,
+    /// The word's text
+    pub text: String,
+    /// IDs of the word's characters, in document order
+    pub ids: Vec<UniqueId>,
+}
+
+impl Word {
+    /// Visible index just past the word's last character
+    pub fn end(&self) -> usize {
+        self.start + self.ids.len()
+    }
+
+    /// The range of the word's characters, following them through later edits
+    pub fn range(&self) -> Range {
+        Range::new(self.ids[0], self.ids[self.ids.len() - 1])
+    }
+}
+
+/// Character ranges of the words in `text`, in order
+fn word_ranges(text: &str) -> Vec<ops::Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut chars_before = 0;
+    for segment in text.split_word_bounds() {
+        let len = segment.chars().count();
+        // Segments of whitespace or punctuation separate words
+        if segment.chars().any(char::is_alphanumeric) {
+            ranges.push(chars_before..chars_before + len);
+        }
+        chars_before += len;
+    }
+    ranges
+}
+
+impl RGA {
+    /// Returns the words of the visible document, in order.
+    ///
+    /// Runs of whitespace and punctuation are not words.
+    pub fn words(&self) -> Vec<Word> {
+        let visible = self.visible_nodes();
+        let text: String = visible.iter().map(|node| node.character).collect();
+        word_ranges(&text)
+            .into_iter()
+            .map(|range| Word {
+                start: range.start,
+                text: visible[range.clone()]
+                    .iter()
+                    .map(|node| node.character)
+                    .collect(),
+                ids: visible[range].iter().map(|node| node.id).collect(),
+            })
+            .collect()
+    }
+
+    /// Where Ctrl+Left moves a caret: the start of the word before `caret`.
+    ///
+    /// Skips any whitespace and punctuation directly before the caret. Returns
+    /// 0 if no word precedes it.
+    ///
+    /// # Arguments
+    ///
+    /// * `caret` - Visible index of the caret (0 ..= length)
+    pub fn previous_word_start(&self, caret: usize) -> usize {
+        self.words()
+            .iter()
+            .rev()
+            .map(|word| word.start)
+            .find(|&start| start < caret)
+            .unwrap_or(0)
+    }
+
+    /// Where Ctrl+Right moves a caret: the end of the word after `caret`.
+    ///
+    /// Skips any whitespace and punctuation directly after the caret. Returns
+    /// the document length if no word follows it.
+    ///
+    /// # Arguments
+    ///
+    /// * `caret` - Visible index of the caret (0 ..= length)
+    pub fn next_word_end(&self, caret: usize) -> usize {
+        self.words()
+            .iter()
+            .map(Word::end)
+            .find(|&end| end > caret)
+            .unwrap_or_else(|| self.visible_node_count())
+    }
+
+    /// Deletes the word containing the character at `index`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Visible index of any character of the word
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document order
+    /// * `Err(&str)` - Error message if no word covers `index`
+    pub fn delete_word_at(&self, index: usize) -> Result<Vec<UniqueId>, &'static str> {
+        let word = self
+            .words()
+            .into_iter()
+            .find(|word| (word.start..word.end()).contains(&index))
+            .ok_or("No word at index")?;
+        self.delete_range(&word.range())
+    }
+
+    /// Deletes from the start of the word before `caret` up to the caret, as
+    /// Ctrl+Backspace does.
+    ///
+    /// # Arguments
+    ///
+    /// * `caret` - Visible index of the caret (0 ..= length)
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the deleted nodes, in document order
+    /// * `Err(&str)` - Error message if the caret is out of range
+    pub fn delete_word_before(&self, caret: usize) -> Result<Vec<UniqueId>, &'static str> {
+        if caret > self.visible_node_count() {
+            return Err("Caret position out of range");
+        }
+        let start = self.previous_word_start(caret);
+        if start == caret {
+            return Ok(Vec::new());
+        }
+        self.delete_range(&self.range_at(start..caret)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn document(text: &str) -> RGA {
+        let rga = RGA::new(1);
+        rga.insert_str_after(rga.sentinel_start_id(), text).unwrap();
+        rga
+    }
+
+    #[test]
+    fn test_words() {
+        let rga = document("Hello, wörld! 42 times");
+        let words: Vec<(usize, String)> = rga
+            .words()
+            .into_iter()
+            .map(|word| (word.start, word.text))
+            .collect();
+        assert_eq!(
+            words,
+            vec![
+                (0, "Hello".to_string()),
+                (7, "wörld".to_string()),
+                (14, "42".to_string()),
+                (17, "times".to_string()),
+            ]
+        );
+        assert!(RGA::new(1).words().is_empty());
+    }
+
+    #[test]
+    fn test_word_jumps() {
+        let rga = document("one  two, three");
+        assert_eq!(rga.previous_word_start(15), 10);
+        assert_eq!(rga.previous_word_start(10), 5);
+        assert_eq!(rga.previous_word_start(7), 5);
+        assert_eq!(rga.previous_word_start(5), 0);
+        assert_eq!(rga.previous_word_start(0), 0);
+
+        assert_eq!(rga.next_word_end(0), 3);
+        assert_eq!(rga.next_word_end(3), 8);
+        assert_eq!(rga.next_word_end(8), 15);
+        assert_eq!(rga.next_word_end(15), 15);
+    }
+
+    #[test]
+    fn test_delete_word_at() {
+        let rga = document("one two three");
+        let deleted = rga.delete_word_at(5).unwrap();
+        assert_eq!(deleted.len(), 3);
+        assert_eq!(rga.to_string(), "one  three");
+        assert!(rga.delete_word_at(3).is_err());
+        assert!(rga.delete_word_at(100).is_err());
+    }
+
+    #[test]
+    fn test_delete_word_before() {
+        let rga = document("one two, ");
+        rga.delete_word_before(9).unwrap();
+        assert_eq!(rga.to_string(), "one ");
+        rga.delete_word_before(2).unwrap();
+        assert_eq!(rga.to_string(), "e ");
+        assert!(rga.delete_word_before(3).is_err());
+    }
+}
@@ -11,6 +11,29 @@
 //! - **Efficient**: Uses SkipMap for O(log n) operations
 //! - **Tombstone-based deletion**: Supports safe deletion with eventual consistency
 //!
+//! ## `no_std` Support
+//!
+//! The CRDT core only needs `alloc`. Disable the default `std` feature to build
+//! it for `no_std` targets; nodes are then stored in a `BTreeMap` instead of the
+//! concurrent SkipMap.
+//!
+//! ## Cargo Features
+//!
+//! - `std` (default): concurrent SkipMap storage and std-only utilities
+//! - `server` (default): the Axum collaboration server in [`server`]
+//! - `mqtt`: replicate documents through an MQTT broker, see [`mqtt`]
+//! - `graphql`: GraphQL queries and subscriptions at `/graphql`
+//! - `swagger-ui`: serve Swagger UI for the server's OpenAPI document
+//! - `traces`: load recorded editing traces for benchmarks
+//! - `single-threaded`: plain-cell storage for single-threaded WASM
+//! - `validate`: run the full [`RGA::validate`] after every mutation in debug builds
+//! - `profiling`: time the RGA's hot paths, read with `RGA::profile`
+//! - `testing`: the `testing` module's in-memory simulated network, for tests,
+//!   examples and benchmarks
+//!
+//! The [`client`] module echoes local edits optimistically and tracks them
+//! until the server acknowledges them.
+//!
 //! ## Example
 //!
 //! ```rust
@@ -21,8 +44,42 @@
 //! println!("Content: {}", rga.to_string());
 //! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "server", feature = "single-threaded"))]
+compile_error!(
+    "the `server` feature shares documents between threads and cannot be combined with `single-threaded`"
+);
+
+pub mod client;
 pub mod crdt;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 // Re-export the main public API from the CRDT module
-pub use crdt::{LamportClock, LamportTimestamp, ReplicaId, UniqueId};
-pub use crdt::{Node, RGA, SENTINEL_END_CHAR, SENTINEL_START_CHAR};
+pub use crdt::{
+    Anchor, AnchoredRange, ApplyOutcome, Awareness, AwarenessUpdate, Bias, CausalBuffer,
+    ChecksumMismatch, ChecksumMonitor, Checksums, ClockAnomaly, ClockMonitor, Commit,
+    DEFAULT_MAX_CLOCK_SKEW, ForkDivergence, Fragment, Freeze, LineIndex, LspPosition, LspRange,
+    MESSAGE_CHAR, Mark, MarkKind, Message, MessageLog, MessageOp, Operation, ProvenanceFilter,
+    Range, RangeChecksum, RangeMismatch, ReadTxn, RejectReason, ResyncRequired, RgaBuilder,
+    SequenceCrdt, StructureFormat, SyncMetrics, Template, Transaction, VecSequence,
+};
+#[cfg(feature = "std")]
+pub use crdt::{
+    AnchorHolder, AuditChain, AuditRecord, AuditReport, Digest, HistoryPolicy, Remap,
+    SegmentConfig, SegmentedLog, SpillStats, pseudonymize_log,
+};
+pub use crdt::{
+    Clock, HybridClock, LamportClock, LamportTimestamp, ReplicaId, Reservation, UniqueId,
+};
+pub use crdt::{Element, Node, Normalization, RGA, Rga, SENTINEL_END_CHAR, SENTINEL_START_CHAR};
+#[cfg(feature = "profiling")]
+pub use crdt::{HotPath, PathStats, Profile};
+pub use crdt::{LogEntry, coalesce_deletes, encode_op_log, parse_op_log};
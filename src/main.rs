@@ -1,31 +1,235 @@
 //! Main entry point for the RGA CRDT web server.
 //!
 //! This binary provides an HTTP API for interacting with the RGA CRDT
-//! using the Axum web framework.
+//! using the Axum web framework. It requires the `server` feature.
 
 use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
-use tokio;
-use tokio::sync::RwLock;
-use tracing::{Level, info};
-use tracing_subscriber;
+use std::time::Duration;
 
-mod crdt;
-mod server;
+use parking_lot::Mutex;
+use tracing::{Level, error, info};
 
-use crdt::RGA;
-use server::{create_router, websocket::AppState};
+use crdt_rga::server::archive::spawn_archiver;
+use crdt_rga::server::awareness::spawn_awareness_expiry;
+use crdt_rga::server::documents::{AppState, DocumentRegistry};
+use crdt_rga::server::persistence;
+use crdt_rga::server::replication::Peer;
+use crdt_rga::server::retention::spawn_retention;
+use crdt_rga::server::tenants::TenantQuota;
+use crdt_rga::server::websocket::BroadcastPolicy;
+use crdt_rga::server::{create_router, serve};
+use crdt_rga::{HistoryPolicy, RGA, SegmentConfig};
+
+/// Tombstones kept in memory when `TOMBSTONE_SPILL_THRESHOLD` is not set
+const DEFAULT_SPILL_THRESHOLD: usize = 100_000;
+
+/// Seconds a document stays in memory unused when `ARCHIVE_IDLE_SECS` is not
+/// set
+const DEFAULT_ARCHIVE_IDLE_SECS: u64 = 600;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Initialize tracing
     tracing_subscriber::fmt().with_max_level(Level::INFO).init();
 
     info!("Starting RGA CRDT Axum server...");
 
-    // Create shared RGA state (replica ID = 1 for now)
-    let rga = RGA::new(1);
-    let state: AppState = Arc::new(RwLock::new(rga));
+    // Create shared RGA state (replica ID = 1 for now), restoring it from an
+    // operation log when one is configured
+    let oplog_dir = std::env::var("OPLOG_DIR").ok();
+    let (mut rga, oplog) = match &oplog_dir {
+        Some(dir) => match persistence::restore(dir, 1, SegmentConfig::default()) {
+            Ok((rga, log)) => (rga, Some(log)),
+            Err(e) => {
+                error!("Failed to restore the operation log in {}: {}", dir, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => (RGA::new(1), None),
+    };
+    // Keep old tombstones on disk when a spill directory is configured
+    if let Ok(dir) = std::env::var("TOMBSTONE_SPILL_DIR") {
+        let threshold = std::env::var("TOMBSTONE_SPILL_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_SPILL_THRESHOLD);
+        if let Err(e) = rga.enable_tombstone_spill(&dir, threshold) {
+            error!(
+                "Failed to prepare the tombstone spill directory {}: {}",
+                dir, e
+            );
+            return ExitCode::FAILURE;
+        }
+        info!("Spilling tombstones beyond {} to {}", threshold, dir);
+    }
+    let state: AppState = Arc::new(DocumentRegistry::new(rga));
+    let admin_token = std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    if admin_token.is_some() {
+        info!("Admin endpoints enabled");
+    }
+    state.set_admin_token(admin_token);
+    // Serve every tenant listed as `id=token` from its own namespace
+    if let Ok(tenants) = std::env::var("TENANTS") {
+        let limit = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+        };
+        let quota = TenantQuota {
+            max_documents: limit("TENANT_MAX_DOCUMENTS"),
+            max_document_chars: limit("TENANT_MAX_DOCUMENT_CHARS"),
+            max_edits_per_minute: limit("TENANT_MAX_EDITS_PER_MINUTE").map(|max| max as u64),
+        };
+        for entry in tenants.split(',').filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((id, token)) if !id.contains(':') && !token.is_empty() => {
+                    state.add_tenant(id.trim(), token.trim(), quota);
+                    info!("Serving tenant {}", id.trim());
+                }
+                _ => {
+                    error!("Invalid tenant '{}', expected id=token", entry);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    // Lease replica IDs to clients, keeping the leases across restarts when
+    // a lease file is configured
+    if let Some(secs) = std::env::var("REPLICA_LEASE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        state.leases().set_ttl(Duration::from_secs(secs));
+    }
+    if let Ok(file) = std::env::var("REPLICA_LEASE_FILE") {
+        if let Err(e) = state.leases().open(&file) {
+            error!("Failed to load the replica leases from {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+        info!("Keeping replica leases in {}", file);
+    }
+    // Peers documents can be re-replicated to, listed as `name=url`
+    if let Ok(peers) = std::env::var("REPLICATION_PEERS") {
+        let token = std::env::var("REPLICATION_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        for entry in peers.split(',').filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((name, url)) if url.trim().starts_with("http://") => {
+                    state.add_peer(
+                        name.trim(),
+                        Peer {
+                            url: url.trim().to_string(),
+                            token: token.clone(),
+                        },
+                    );
+                    info!("Replicating to peer {} at {}", name.trim(), url.trim());
+                }
+                _ => {
+                    error!(
+                        "Invalid replication peer '{}', expected name=http://host",
+                        entry
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    if let Ok(dir) = std::env::var("SESSION_CAPTURE_DIR") {
+        info!("Capturing sessions to {}", dir);
+        state.set_capture_dir(Some(dir.into()));
+    }
+    if let Some(mut log) = oplog {
+        // Bound the operations replayed on restart
+        if let Some(max_depth) = std::env::var("OPLOG_MAX_DEPTH")
+            .ok()
+            .and_then(|value| value.parse().ok())
+        {
+            log.set_history_policy(Some(HistoryPolicy::max_depth(max_depth)));
+            info!(
+                "Keeping at most {} operations to replay on restart",
+                max_depth
+            );
+        }
+        // Chain every persisted batch into a tamper-evident audit file
+        if std::env::var("OPLOG_AUDIT").is_ok() {
+            if let Err(e) = log.enable_audit() {
+                error!("Failed to open the audit file: {}", e);
+                return ExitCode::FAILURE;
+            }
+            info!("Auditing the operation log");
+        }
+        // Queue operations in memory while the log cannot be written, up to
+        // a bound past which edits are refused
+        let max_pending = std::env::var("OPLOG_MAX_PENDING")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(persistence::DEFAULT_MAX_PENDING);
+        state.main().storage.set_max_pending(Some(max_pending));
+        if std::env::var("OPLOG_DEGRADED_REJECT_DOCUMENTS").is_ok() {
+            info!("Refusing new documents while the operation log is unwritable");
+            state.set_reject_documents_when_degraded(true);
+        }
+        info!("Logging operations to {}", oplog_dir.unwrap_or_default());
+        let log = Arc::new(Mutex::new(log));
+        state.set_oplog(log.clone());
+        persistence::spawn_persistence(state.main(), log, Duration::from_secs(1));
+    }
+    // Purge tombstones deleted longer ago than the retention window
+    if let Some(secs) = std::env::var("TOMBSTONE_RETENTION_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        info!("Purging tombstones deleted more than {} s ago", secs);
+        spawn_retention(state.clone(), Duration::from_secs(secs));
+    }
+
+    // Move documents nobody uses out of memory
+    if let Ok(dir) = std::env::var("ARCHIVE_DIR") {
+        let secs = std::env::var("ARCHIVE_IDLE_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_ARCHIVE_IDLE_SECS);
+        info!("Archiving documents idle for {} s to {}", secs, dir);
+        state.set_archive_dir(Some(dir.into()));
+        spawn_archiver(state.clone(), Duration::from_secs(secs));
+    }
+
+    // Resynchronize sessions that fall further behind than configured
+    if let Some(messages) = std::env::var("MAX_PEER_LAG")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        state.main().set_max_peer_lag(messages);
+    }
+
+    // Expire awareness states of replicas that stopped heartbeating
+    if let Some(ttl_ms) = std::env::var("AWARENESS_TTL_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        state
+            .main()
+            .set_awareness_ttl(Duration::from_millis(ttl_ms));
+    }
+    spawn_awareness_expiry(state.clone());
+
+    // Coalesce broadcasts to many viewers when an interval is configured
+    if let Some(interval_ms) = std::env::var("BROADCAST_INTERVAL_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+    {
+        info!("Coalescing broadcasts every {} ms", interval_ms);
+        state
+            .main()
+            .set_broadcast_policy(BroadcastPolicy::Coalesce(Duration::from_millis(
+                interval_ms,
+            )));
+    }
 
     // Build our application with routes from the server module
     let app = create_router().with_state(state);
@@ -36,7 +240,10 @@ async fn main() {
     info!("Server listening on http://{}", addr);
     info!("Available endpoints:");
     info!("  GET  /health  - Health check");
+    info!("  GET  /healthz - Liveness probe");
+    info!("  GET  /readyz  - Readiness probe");
     info!("  GET  /ws      - WebSocket for collaborative editing");
+    info!("  POST /docs/:id/fork - Fork a document");
     info!("");
     info!("Try these commands:");
     info!("  curl http://localhost:3000/health");
@@ -44,6 +251,11 @@ async fn main() {
     info!("  # Open frontend/index.html to test collaborative editing");
 
     // Run the server
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    match serve(addr, app).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Server stopped: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
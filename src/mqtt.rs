@@ -0,0 +1,852 @@
+//! MQTT sync adapter for constrained devices.
+//!
+//! IoT and edge devices usually already talk to an MQTT broker, so this adapter
+//! replicates a document through one instead of the WebSocket server:
+//!
+//! - every local operation is published to `crdt/<doc>/<replica>`
+//! - each replica keeps a retained snapshot of its document at
+//!   `crdt/<doc>/snapshot/<replica>`, so a device joining late receives the
+//!   current state as soon as it subscribes
+//!
+//! Payloads are JSON. Snapshots list the replicas once in a `replicas` table
+//! and refer to them by index, so long replica IDs are not repeated for every
+//! character. Operations carry the whole node, so applying them is
+//! idempotent and redelivery under QoS 1 is harmless. Topics of different
+//! replicas are not ordered relative to each other, so incoming operations go
+//! through a [`CausalBuffer`]. Each replica's own topic is ordered, so its
+//! operations are also checked by a [`ClockMonitor`] and anomalies such as a
+//! counter far ahead of the local clock are logged as warnings.
+//!
+//! An operation inserted after a node that was garbage collected cannot be
+//! placed. Its sender's retained snapshot is then requested again and used to
+//! resynchronize the document; see [`MqttSyncError::ResyncRequired`].
+//!
+//! Lost or corrupted operations leave replicas diverged without either
+//! noticing. Each replica therefore publishes its [`Checksums`] to
+//! `crdt/<doc>/checksum/<replica>` when it connects and then periodically. A
+//! replica whose own checksums differ at a version both have reached, or
+//! that sees the sender stuck behind it, logs the [`MqttSyncError::Diverged`]
+//! diagnostics and publishes the nodes of the differing ranges to
+//! `crdt/<doc>/repair/<replica>`, see [`crate::crdt::checksum`]. Each side
+//! repairs what the other lacks.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rumqttc::{AsyncClient, ClientError, Event, EventLoop, Packet, Publish, QoS};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::crdt::replicas::ReplicaRegistry;
+use crate::crdt::{
+    CausalBuffer, ChecksumMismatch, ChecksumMonitor, Checksums, ClockMonitor, Node, RGA,
+    RangeChecksum, ReplicaId, UniqueId,
+};
+
+/// Delay before polling again after the connection to the broker fails
+const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+/// Upper bound on the delay between reconnection attempts
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How often a replica publishes its checksums unless configured otherwise
+pub const DEFAULT_CHECKSUM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An error raised by the MQTT adapter
+#[derive(Debug)]
+pub enum MqttSyncError {
+    /// A payload could not be encoded or decoded
+    Codec(serde_json::Error),
+    /// The request could not be handed to the MQTT client
+    Client(ClientError),
+    /// The operation references a node this replica does not have
+    Rejected(&'static str),
+    /// `replica` sent an insertion after `missing`, a node that may have been
+    /// garbage collected. [`MqttSync::run`] recovers from its snapshot.
+    ResyncRequired {
+        replica: ReplicaId,
+        missing: UniqueId,
+    },
+    /// `replica`'s checksums differ from ours at a version both reached.
+    /// [`MqttSync::run`] publishes the differing ranges.
+    Diverged {
+        replica: ReplicaId,
+        mismatch: ChecksumMismatch,
+    },
+}
+
+impl fmt::Display for MqttSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MqttSyncError::Codec(e) => write!(f, "invalid payload: {e}"),
+            MqttSyncError::Client(e) => write!(f, "mqtt client error: {e}"),
+            MqttSyncError::Rejected(reason) => write!(f, "operation rejected: {reason}"),
+            MqttSyncError::ResyncRequired { replica, missing } => write!(
+                f,
+                "resync required: replica {replica} inserted after {missing:?}, which may have been garbage collected"
+            ),
+            MqttSyncError::Diverged { replica, mismatch } => {
+                write!(f, "diverged from replica {replica}: {mismatch}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MqttSyncError {}
+
+impl From<serde_json::Error> for MqttSyncError {
+    fn from(e: serde_json::Error) -> Self {
+        MqttSyncError::Codec(e)
+    }
+}
+
+impl From<ClientError> for MqttSyncError {
+    fn from(e: ClientError) -> Self {
+        MqttSyncError::Client(e)
+    }
+}
+
+/// Wire form of a node ID
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct WireId {
+    counter: u64,
+    replica: ReplicaId,
+    sequence: u32,
+}
+
+impl From<UniqueId> for WireId {
+    fn from(id: UniqueId) -> Self {
+        WireId {
+            counter: id.counter(),
+            replica: id.replica_id(),
+            sequence: id.sequence(),
+        }
+    }
+}
+
+impl From<WireId> for UniqueId {
+    fn from(id: WireId) -> Self {
+        UniqueId::new_with_sequence(id.counter, id.replica, id.sequence)
+    }
+}
+
+/// Wire form of a node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct WireNode {
+    id: WireId,
+    #[serde(rename = "ch")]
+    character: char,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<WireId>,
+    #[serde(default, rename = "right", skip_serializing_if = "Option::is_none")]
+    right_origin: Option<WireId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<ReplicaId>,
+}
+
+impl From<&Node> for WireNode {
+    fn from(node: &Node) -> Self {
+        WireNode {
+            id: node.id.into(),
+            character: node.character,
+            deleted: node.is_deleted,
+            origin: node.origin.map(WireId::from),
+            right_origin: node.right_origin.map(WireId::from),
+            author: node.author,
+        }
+    }
+}
+
+impl From<WireNode> for Node {
+    fn from(node: WireNode) -> Self {
+        Node {
+            id: node.id.into(),
+            character: node.character,
+            is_deleted: node.deleted,
+            origin: node.origin.map(UniqueId::from),
+            right_origin: node.right_origin.map(UniqueId::from),
+            author: node.author,
+        }
+    }
+}
+
+/// Encode a single operation
+pub fn encode_op(node: &Node) -> Result<Vec<u8>, MqttSyncError> {
+    Ok(serde_json::to_vec(&WireNode::from(node))?)
+}
+
+/// Decode a single operation
+pub fn decode_op(payload: &[u8]) -> Result<Node, MqttSyncError> {
+    Ok(serde_json::from_slice::<WireNode>(payload)?.into())
+}
+
+/// Wire form of a snapshot. The `replica` of every ID is an index into
+/// `replicas`.
+#[derive(Serialize, Deserialize)]
+struct WireSnapshot {
+    replicas: Vec<ReplicaId>,
+    nodes: Vec<WireNode>,
+}
+
+/// Snapshots published before replica aliases were plain node lists
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotPayload {
+    Aliased(WireSnapshot),
+    Plain(Vec<WireNode>),
+}
+
+/// Encode every node of a document except the sentinels
+pub fn encode_snapshot(rga: &RGA) -> Result<Vec<u8>, MqttSyncError> {
+    encode_nodes(
+        rga.all_nodes()
+            .into_iter()
+            .filter(|node| !node.is_sentinel()),
+    )
+}
+
+/// Encode some nodes in the snapshot format, as repairs are sent
+pub fn encode_nodes(nodes: impl IntoIterator<Item = Node>) -> Result<Vec<u8>, MqttSyncError> {
+    let mut replicas = ReplicaRegistry::new();
+    let nodes: Vec<WireNode> = nodes
+        .into_iter()
+        .map(|mut node| {
+            node.id = replicas.alias_id(node.id);
+            node.origin = node.origin.map(|id| replicas.alias_id(id));
+            node.right_origin = node.right_origin.map(|id| replicas.alias_id(id));
+            node.author = node.author.map(|author| u64::from(replicas.alias(author)));
+            WireNode::from(&node)
+        })
+        .collect();
+    Ok(serde_json::to_vec(&WireSnapshot {
+        replicas: replicas.replicas().to_vec(),
+        nodes,
+    })?)
+}
+
+/// Decode a snapshot into its nodes, in document order
+pub fn decode_snapshot(payload: &[u8]) -> Result<Vec<Node>, MqttSyncError> {
+    let snapshot = match serde_json::from_slice(payload)? {
+        SnapshotPayload::Aliased(snapshot) => snapshot,
+        SnapshotPayload::Plain(nodes) => {
+            return Ok(nodes.into_iter().map(Node::from).collect());
+        }
+    };
+    let mut replicas = ReplicaRegistry::new();
+    for replica in snapshot.replicas {
+        replicas.declare(replica).map_err(MqttSyncError::Rejected)?;
+    }
+    snapshot
+        .nodes
+        .into_iter()
+        .map(|node| {
+            let mut node = Node::from(node);
+            node.id = replicas.resolve_id(node.id)?;
+            node.origin = node.origin.map(|id| replicas.resolve_id(id)).transpose()?;
+            node.right_origin = node
+                .right_origin
+                .map(|id| replicas.resolve_id(id))
+                .transpose()?;
+            node.author = node
+                .author
+                .map(|alias| replicas.replica(alias))
+                .transpose()?;
+            Ok(node)
+        })
+        .collect::<Result<_, &'static str>>()
+        .map_err(MqttSyncError::Rejected)
+}
+
+/// Wire form of a range's checksum
+#[derive(Serialize, Deserialize)]
+struct WireRange {
+    counter: u64,
+    nodes: u64,
+    hash: u64,
+}
+
+/// Encode a document's checksums
+pub fn encode_checksums(checksums: &Checksums) -> Result<Vec<u8>, MqttSyncError> {
+    let ranges: BTreeMap<ReplicaId, WireRange> = checksums
+        .version
+        .iter()
+        .map(|(&replica, &counter)| {
+            let range = checksums.ranges.get(&replica).copied().unwrap_or_default();
+            let wire = WireRange {
+                counter,
+                nodes: range.nodes,
+                hash: range.hash,
+            };
+            (replica, wire)
+        })
+        .collect();
+    Ok(serde_json::to_vec(&ranges)?)
+}
+
+/// Decode a document's checksums
+pub fn decode_checksums(payload: &[u8]) -> Result<Checksums, MqttSyncError> {
+    let ranges: BTreeMap<ReplicaId, WireRange> = serde_json::from_slice(payload)?;
+    Ok(Checksums {
+        version: ranges
+            .iter()
+            .map(|(&replica, range)| (replica, range.counter))
+            .collect(),
+        ranges: ranges
+            .into_iter()
+            .map(|(replica, range)| {
+                let checksum = RangeChecksum {
+                    nodes: range.nodes,
+                    hash: range.hash,
+                };
+                (replica, checksum)
+            })
+            .collect(),
+    })
+}
+
+/// What a topic under `crdt/<doc>/` carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    /// Operations published by a replica
+    Ops(ReplicaId),
+    /// A replica's retained snapshot
+    Snapshot(ReplicaId),
+    /// A replica's checksums
+    Checksum(ReplicaId),
+    /// Nodes a replica sent to repair diverged ranges
+    Repair(ReplicaId),
+}
+
+/// Topic layout for one document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentTopics {
+    doc: String,
+}
+
+impl DocumentTopics {
+    /// Topics for the document `doc`
+    pub fn new(doc: impl Into<String>) -> Self {
+        DocumentTopics { doc: doc.into() }
+    }
+
+    /// `crdt/<doc>/<replica>`: operations published by `replica`
+    pub fn ops(&self, replica: ReplicaId) -> String {
+        format!("crdt/{}/{}", self.doc, replica)
+    }
+
+    /// `crdt/<doc>/snapshot/<replica>`: the retained snapshot of `replica`
+    pub fn snapshot(&self, replica: ReplicaId) -> String {
+        format!("crdt/{}/snapshot/{}", self.doc, replica)
+    }
+
+    /// `crdt/<doc>/checksum/<replica>`: the checksums of `replica`
+    pub fn checksum(&self, replica: ReplicaId) -> String {
+        format!("crdt/{}/checksum/{}", self.doc, replica)
+    }
+
+    /// `crdt/<doc>/repair/<replica>`: nodes `replica` sent to repair diverged
+    /// ranges
+    pub fn repair(&self, replica: ReplicaId) -> String {
+        format!("crdt/{}/repair/{}", self.doc, replica)
+    }
+
+    /// Filters matching every replica's operations, snapshots, checksums and
+    /// repairs
+    pub fn subscriptions(&self) -> [String; 4] {
+        [
+            format!("crdt/{}/+", self.doc),
+            format!("crdt/{}/snapshot/+", self.doc),
+            format!("crdt/{}/checksum/+", self.doc),
+            format!("crdt/{}/repair/+", self.doc),
+        ]
+    }
+
+    /// Classify a topic of this document
+    pub fn parse(&self, topic: &str) -> Option<Topic> {
+        let rest = topic
+            .strip_prefix("crdt/")?
+            .strip_prefix(self.doc.as_str())?
+            .strip_prefix('/')?;
+        let (kind, replica): (fn(ReplicaId) -> Topic, &str) = match rest.split_once('/') {
+            Some(("snapshot", replica)) => (Topic::Snapshot, replica),
+            Some(("checksum", replica)) => (Topic::Checksum, replica),
+            Some(("repair", replica)) => (Topic::Repair, replica),
+            Some(_) => return None,
+            None => (Topic::Ops, rest),
+        };
+        replica.parse().ok().map(kind)
+    }
+}
+
+/// Replicates an RGA through an MQTT broker.
+///
+/// Local edits are made on the RGA as usual and then announced with
+/// [`MqttSync::publish_op`]. Incoming messages are applied by
+/// [`MqttSync::run`], which drives the client's event loop.
+pub struct MqttSync {
+    client: AsyncClient,
+    rga: Arc<RGA>,
+    topics: DocumentTopics,
+    /// Operations received before the nodes they depend on
+    pending: Mutex<CausalBuffer>,
+    /// Checks incoming operations for clock anomalies
+    monitor: Mutex<ClockMonitor>,
+    /// How often [`MqttSync::run`] publishes the checksums, if at all
+    checksum_interval: Mutex<Option<Duration>>,
+    /// Compares incoming checksums with the local replica's
+    checksum_monitor: Mutex<ChecksumMonitor>,
+}
+
+impl MqttSync {
+    /// Create an adapter for the document `doc`.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A client created with [`AsyncClient::new`]
+    /// * `doc` - The document name used in topics
+    /// * `rga` - The local replica of the document
+    pub fn new(client: AsyncClient, doc: impl Into<String>, rga: Arc<RGA>) -> Self {
+        MqttSync {
+            client,
+            rga,
+            topics: DocumentTopics::new(doc),
+            pending: Mutex::new(CausalBuffer::new()),
+            monitor: Mutex::new(ClockMonitor::new()),
+            checksum_interval: Mutex::new(Some(DEFAULT_CHECKSUM_INTERVAL)),
+            checksum_monitor: Mutex::new(ChecksumMonitor::new()),
+        }
+    }
+
+    /// The topic layout of the document
+    pub fn topics(&self) -> &DocumentTopics {
+        &self.topics
+    }
+
+    /// Subscribe to every replica's operations and snapshots
+    pub async fn subscribe(&self) -> Result<(), MqttSyncError> {
+        for filter in self.topics.subscriptions() {
+            self.client.subscribe(filter, QoS::AtLeastOnce).await?;
+        }
+        Ok(())
+    }
+
+    /// Publish the current state of node `id` as an operation
+    ///
+    /// Call this after every local insert or delete.
+    pub async fn publish_op(&self, id: UniqueId) -> Result<(), MqttSyncError> {
+        let node = self
+            .rga
+            .get_node(id)
+            .ok_or(MqttSyncError::Rejected("Node to publish not found"))?;
+        let topic = self.topics.ops(self.rga.replica_id());
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, encode_op(&node)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Publish this replica's retained snapshot
+    pub async fn publish_snapshot(&self) -> Result<(), MqttSyncError> {
+        let topic = self.topics.snapshot(self.rga.replica_id());
+        self.client
+            .publish(topic, QoS::AtLeastOnce, true, encode_snapshot(&self.rga)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Publish this replica's checksums for the others to compare
+    pub async fn publish_checksums(&self) -> Result<(), MqttSyncError> {
+        let topic = self.topics.checksum(self.rga.replica_id());
+        let payload = encode_checksums(&self.rga.checksums())?;
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await?;
+        Ok(())
+    }
+
+    /// Publish the nodes of the ranges that differ in `mismatch`
+    pub async fn publish_repair(&self, mismatch: &ChecksumMismatch) -> Result<(), MqttSyncError> {
+        let topic = self.topics.repair(self.rga.replica_id());
+        let payload = encode_nodes(self.rga.mismatched_nodes(mismatch))?;
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await?;
+        Ok(())
+    }
+
+    /// Set how often [`MqttSync::run`] publishes the checksums, or stop with
+    /// `None`. Takes effect the next time `run` is called.
+    pub fn set_checksum_interval(&self, interval: Option<Duration>) {
+        *self.checksum_interval.lock() = interval;
+    }
+
+    /// Number of checksum mismatches found with each replica so far
+    pub fn divergences(&self) -> BTreeMap<ReplicaId, u64> {
+        self.checksum_monitor.lock().reported().clone()
+    }
+
+    /// Ask the broker to send `replica`'s retained snapshot again
+    pub async fn request_snapshot(&self, replica: ReplicaId) -> Result<(), MqttSyncError> {
+        // Subscribing again redelivers the retained message
+        self.client
+            .subscribe(self.topics.snapshot(replica), QoS::AtLeastOnce)
+            .await?;
+        Ok(())
+    }
+
+    /// Set how far ahead of the local clock an incoming counter may be before
+    /// it is logged as an anomaly
+    pub fn set_max_clock_skew(&self, max_skew: u64) {
+        self.monitor.lock().set_max_skew(max_skew);
+    }
+
+    /// Number of clock anomalies logged for each replica so far
+    pub fn clock_anomalies(&self) -> BTreeMap<ReplicaId, u64> {
+        self.monitor.lock().reported().clone()
+    }
+
+    /// Declare that tombstones with IDs below `horizon` may have been garbage
+    /// collected, so operations depending on them need a resync
+    pub fn set_horizon(&self, horizon: UniqueId) {
+        self.pending.lock().set_horizon(horizon);
+    }
+
+    /// Apply an incoming publish to the local replica.
+    ///
+    /// Messages from this replica and from other documents are ignored.
+    /// Snapshots are applied as complete states and resolve pending resyncs.
+    /// Checksums are compared with the local replica's, and repairs are
+    /// applied like operations.
+    pub fn handle_publish(&self, publish: &Publish) -> Result<(), MqttSyncError> {
+        let own = self.rga.replica_id();
+        match self.topics.parse(&publish.topic) {
+            Some(Topic::Ops(replica)) if replica != own => {
+                let node = decode_op(&publish.payload)?;
+                for anomaly in self.monitor.lock().inspect(&self.rga, &node) {
+                    warn!("Clock anomaly on {}: {}", publish.topic, anomaly);
+                }
+                let mut pending = self.pending.lock();
+                let before = pending.resync_required().len();
+                pending.deliver(&self.rga, node);
+                if let Some(resync) = pending.resync_required().get(before) {
+                    return Err(MqttSyncError::ResyncRequired {
+                        replica,
+                        missing: resync.missing,
+                    });
+                }
+            }
+            Some(Topic::Snapshot(replica)) if replica != own => {
+                let nodes = decode_snapshot(&publish.payload)?;
+                self.pending.lock().resync(&self.rga, nodes);
+            }
+            Some(Topic::Checksum(replica)) if replica != own => {
+                let checksums = decode_checksums(&publish.payload)?;
+                self.checksum_monitor
+                    .lock()
+                    .inspect(&self.rga, replica, &checksums)
+                    .map_err(|mismatch| MqttSyncError::Diverged { replica, mismatch })?;
+            }
+            Some(Topic::Repair(replica)) if replica != own => {
+                let mut pending = self.pending.lock();
+                for node in decode_snapshot(&publish.payload)? {
+                    // A tombstone we never saw inserted would wait for itself
+                    if node.is_deleted && self.rga.get_node(node.id).is_none() {
+                        let inserted = Node {
+                            is_deleted: false,
+                            ..node.clone()
+                        };
+                        pending.deliver(&self.rga, inserted);
+                    }
+                    pending.deliver(&self.rga, node);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Drive the event loop, applying incoming messages, until the client is
+    /// dropped.
+    ///
+    /// Subscribes and publishes the checksums on every (re)connection, then
+    /// publishes them at the checksum interval. Connection failures are
+    /// retried with bounded exponential backoff; undecodable messages are
+    /// logged and skipped. An operation that needs a resync requests its
+    /// sender's snapshot, and diverged checksums publish the differing
+    /// ranges.
+    pub async fn run(&self, mut eventloop: EventLoop) {
+        let mut backoff = RECONNECT_INITIAL_BACKOFF;
+        let checksum_interval = *self.checksum_interval.lock();
+        let mut checksum_timer =
+            tokio::time::interval(checksum_interval.unwrap_or(DEFAULT_CHECKSUM_INTERVAL));
+        // The first tick would fire at once, the connection publishes instead
+        checksum_timer.reset();
+        loop {
+            let event = tokio::select! {
+                event = eventloop.poll() => event,
+                _ = checksum_timer.tick(), if checksum_interval.is_some() => {
+                    if let Err(e) = self.publish_checksums().await {
+                        warn!("Failed to publish checksums: {}", e);
+                    }
+                    continue;
+                }
+            };
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    backoff = RECONNECT_INITIAL_BACKOFF;
+                    if let Err(e) = self.subscribe().await {
+                        warn!("Failed to subscribe to document topics: {}", e);
+                        return;
+                    }
+                    if checksum_interval.is_some()
+                        && let Err(e) = self.publish_checksums().await
+                    {
+                        warn!("Failed to publish checksums: {}", e);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    match self.handle_publish(&publish) {
+                        Ok(()) => {}
+                        Err(e @ MqttSyncError::ResyncRequired { replica, .. }) => {
+                            warn!("{}; requesting the snapshot of replica {}", e, replica);
+                            if let Err(e) = self.request_snapshot(replica).await {
+                                warn!("Failed to request snapshot: {}", e);
+                            }
+                        }
+                        Err(MqttSyncError::Diverged { replica, mismatch }) => {
+                            warn!(
+                                "Diverged from replica {}: {}; publishing the differing ranges",
+                                replica, mismatch
+                            );
+                            if let Err(e) = self.publish_repair(&mismatch).await {
+                                warn!("Failed to publish repair: {}", e);
+                            }
+                        }
+                        Err(e) => warn!("Skipping message on {}: {}", publish.topic, e),
+                    }
+                }
+                Ok(event) => debug!("MQTT event: {:?}", event),
+                Err(rumqttc::ConnectionError::RequestsDone) => return,
+                Err(e) => {
+                    warn!("MQTT connection error: {}; retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::MqttOptions;
+
+    fn adapter(replica: ReplicaId) -> (MqttSync, EventLoop) {
+        let options = MqttOptions::new(format!("replica-{replica}"), "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(options, 16);
+        (
+            MqttSync::new(client, "notes", Arc::new(RGA::new(replica))),
+            eventloop,
+        )
+    }
+
+    #[test]
+    fn test_op_round_trip() {
+        let rga = RGA::new(1);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        rga.delete(a_id).unwrap();
+        let node = rga.get_node(a_id).unwrap();
+
+        let decoded = decode_op(&encode_op(&node).unwrap()).unwrap();
+        assert_eq!(decoded.id, node.id);
+        assert_eq!(decoded.character, 'a');
+        assert!(decoded.is_deleted);
+        assert_eq!(decoded.origin, Some(rga.sentinel_start_id()));
+        assert_eq!(decoded.right_origin, Some(rga.sentinel_end_id()));
+        assert!(decode_op(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_topics() {
+        let topics = DocumentTopics::new("notes");
+        assert_eq!(topics.ops(7), "crdt/notes/7");
+        assert_eq!(topics.snapshot(7), "crdt/notes/snapshot/7");
+        assert_eq!(topics.parse("crdt/notes/7"), Some(Topic::Ops(7)));
+        assert_eq!(
+            topics.parse("crdt/notes/snapshot/7"),
+            Some(Topic::Snapshot(7))
+        );
+        assert_eq!(topics.parse(&topics.checksum(7)), Some(Topic::Checksum(7)));
+        assert_eq!(topics.parse(&topics.repair(7)), Some(Topic::Repair(7)));
+        assert_eq!(topics.parse("crdt/notes/other/7"), None);
+        assert_eq!(topics.parse("crdt/notesx/7"), None);
+        assert_eq!(topics.parse("crdt/other/7"), None);
+    }
+
+    #[test]
+    fn test_handle_publish_applies_ops_and_snapshots() {
+        let (remote, _remote_loop) = adapter(2);
+        let remote_rga = &remote.rga;
+        let a_id = remote_rga
+            .insert_after(remote_rga.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = remote_rga.insert_after(a_id, 'b').unwrap();
+
+        let (local, _local_loop) = adapter(1);
+        let topics = local.topics().clone();
+        let snapshot = Publish::new(
+            topics.snapshot(2),
+            QoS::AtLeastOnce,
+            encode_snapshot(remote_rga).unwrap(),
+        );
+        local.handle_publish(&snapshot).unwrap();
+        assert_eq!(local.rga.to_string(), "ab");
+
+        // A local delete survives a stale redelivery of the insert
+        local.rga.delete(b_id).unwrap();
+        let stale = Publish::new(
+            topics.ops(2),
+            QoS::AtLeastOnce,
+            encode_op(&remote_rga.get_node(b_id).unwrap()).unwrap(),
+        );
+        local.handle_publish(&stale).unwrap();
+        assert_eq!(local.rga.to_string(), "a");
+
+        // Our own messages echoed by the broker are ignored
+        let own = Publish::new(topics.ops(1), QoS::AtLeastOnce, b"garbage".to_vec());
+        assert!(local.handle_publish(&own).is_ok());
+    }
+
+    #[test]
+    fn test_insert_after_collected_node_resyncs_from_snapshot() {
+        let (remote, _remote_loop) = adapter(2);
+        let remote_rga = &remote.rga;
+        let a_id = remote_rga
+            .insert_after(remote_rga.sentinel_start_id(), 'a')
+            .unwrap();
+        let b_id = remote_rga.insert_after(a_id, 'b').unwrap();
+
+        // `a` is older than the collection horizon and never arrives
+        let (local, _local_loop) = adapter(1);
+        local.set_horizon(b_id);
+        let topics = local.topics().clone();
+        let op = Publish::new(
+            topics.ops(2),
+            QoS::AtLeastOnce,
+            encode_op(&remote_rga.get_node(b_id).unwrap()).unwrap(),
+        );
+        assert!(matches!(
+            local.handle_publish(&op),
+            Err(MqttSyncError::ResyncRequired { replica: 2, missing }) if missing == a_id
+        ));
+        assert_eq!(local.rga.to_string(), "");
+
+        let snapshot = Publish::new(
+            topics.snapshot(2),
+            QoS::AtLeastOnce,
+            encode_snapshot(remote_rga).unwrap(),
+        );
+        local.handle_publish(&snapshot).unwrap();
+        assert_eq!(local.rga.to_string(), "ab");
+        assert!(!local.pending.lock().needs_resync());
+    }
+
+    #[test]
+    fn test_diverged_checksums_are_repaired() {
+        let (remote, _remote_loop) = adapter(2);
+        let remote_rga = &remote.rga;
+        let ids = remote_rga
+            .insert_str_after(remote_rga.sentinel_start_id(), "abc")
+            .unwrap();
+        let (local, _local_loop) = adapter(1);
+        for node in remote_rga
+            .all_nodes()
+            .into_iter()
+            .filter(|n| !n.is_sentinel())
+        {
+            local.rga.apply_remote_op(node);
+        }
+        let topics = local.topics().clone();
+        let checksums = |rga: &RGA, replica| {
+            let payload = encode_checksums(&rga.checksums()).unwrap();
+            Publish::new(topics.checksum(replica), QoS::AtLeastOnce, payload)
+        };
+
+        // The deletion of `b` and the insertion of `x` after it were lost
+        remote_rga.delete(ids[1]).unwrap();
+        let x_id = remote_rga.insert_after(ids[1], 'x').unwrap();
+        remote_rga.delete(x_id).unwrap();
+        // The local replica is behind, so cannot tell yet
+        local.handle_publish(&checksums(remote_rga, 2)).unwrap();
+        let Err(MqttSyncError::Diverged {
+            replica: 1,
+            mismatch,
+        }) = remote.handle_publish(&checksums(&local.rga, 1))
+        else {
+            panic!("the lost deletion went unnoticed");
+        };
+        assert_eq!(remote.divergences(), BTreeMap::from([(1, 1)]));
+        assert_eq!(mismatch.ranges[0].local.nodes, 3);
+
+        let repair = encode_nodes(remote_rga.mismatched_nodes(&mismatch)).unwrap();
+        local
+            .handle_publish(&Publish::new(topics.repair(2), QoS::AtLeastOnce, repair))
+            .unwrap();
+        assert_eq!(local.rga.to_string(), "ac");
+        remote.handle_publish(&checksums(&local.rga, 1)).unwrap();
+        local.handle_publish(&checksums(remote_rga, 2)).unwrap();
+    }
+
+    #[test]
+    fn test_clock_anomalies_are_counted_per_replica() {
+        let (local, _local_loop) = adapter(1);
+        local.set_max_clock_skew(10);
+        let topics = local.topics().clone();
+        let start = local.rga.sentinel_start_id();
+        let op = |counter| {
+            let node = Node::with_origins(UniqueId::new(counter, 3), 'x', start, None);
+            Publish::new(topics.ops(3), QoS::AtLeastOnce, encode_op(&node).unwrap())
+        };
+
+        local.handle_publish(&op(5)).unwrap();
+        assert!(local.clock_anomalies().is_empty());
+        local.handle_publish(&op(1000)).unwrap();
+        local.handle_publish(&op(6)).unwrap();
+        assert_eq!(local.clock_anomalies(), BTreeMap::from([(3, 2)]));
+        // The operations are applied regardless
+        assert_eq!(local.rga.to_string(), "xxx");
+    }
+
+    #[test]
+    fn test_snapshot_aliases_replicas() {
+        let rga = RGA::new(0xdead_beef_cafe);
+        let a_id = rga.insert_after(rga.sentinel_start_id(), 'a').unwrap();
+        rga.insert_after(a_id, 'b').unwrap();
+        rga.delete(a_id).unwrap();
+
+        let payload = encode_snapshot(&rga).unwrap();
+        let text = String::from_utf8(payload.clone()).unwrap();
+        assert_eq!(text.matches("244837814094590").count(), 1);
+        let wire = |nodes: &[Node]| nodes.iter().map(WireNode::from).collect::<Vec<_>>();
+        let expected: Vec<Node> = rga
+            .all_nodes()
+            .into_iter()
+            .filter(|node| !node.is_sentinel())
+            .collect();
+        assert_eq!(wire(&decode_snapshot(&payload).unwrap()), wire(&expected));
+
+        // Snapshots without a replica table are still accepted
+        let plain = serde_json::to_vec(&wire(&expected[..1])).unwrap();
+        assert_eq!(
+            wire(&decode_snapshot(&plain).unwrap()),
+            wire(&expected[..1])
+        );
+        let unknown =
+            br#"{"replicas":[],"nodes":[{"id":{"counter":1,"replica":0,"sequence":0},"ch":"a"}]}"#;
+        assert!(decode_snapshot(unknown).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Access control for WebSocket sessions.
+//!
+//! Each document has an [`Acl`] mapping access tokens to a [`Permission`].
+//! A session presents its token when connecting (`/ws?token=...`) and keeps the
+//! permission for its lifetime. Every operation is checked before it is
+//! dispatched: viewers may only read and share presence, commenters may also
+//! comment and share decorations, and only editors may change the document. Anything not explicitly
+//! allowed at a level is refused, including operation types the server does
+//! not know.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::server::error::{ServerError, ServerResult};
+
+/// What a session may do with a document, from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Read the document and share presence
+    View,
+    /// Additionally comment on the document and share decorations
+    Comment,
+    /// Additionally edit the document
+    Edit,
+}
+
+impl Permission {
+    /// The least permission that allows an operation type
+    pub(crate) fn required_for(op_type: &str) -> Permission {
+        match op_type {
+            "get_content" | "stats" | "hello" | "presence" | "awareness" | "mute" | "unmute" => {
+                Permission::View
+            }
+            "comment" | "decorate" => Permission::Comment,
+            // Edits, and anything else, need full access
+            _ => Permission::Edit,
+        }
+    }
+
+    /// Parses a permission as displayed: `view`, `comment` or `edit`
+    pub fn parse(name: &str) -> Option<Permission> {
+        match name {
+            "view" => Some(Permission::View),
+            "comment" => Some(Permission::Comment),
+            "edit" => Some(Permission::Edit),
+            _ => None,
+        }
+    }
+
+    /// Check that this permission allows an operation type
+    pub fn authorize(self, op_type: &str) -> ServerResult {
+        let required = Permission::required_for(op_type);
+        if self >= required {
+            Ok(())
+        } else {
+            Err(ServerError::Forbidden {
+                operation: op_type.to_string(),
+                permission: self,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Permission::View => "view",
+            Permission::Comment => "comment",
+            Permission::Edit => "edit",
+        })
+    }
+}
+
+/// Permissions granted to access tokens on one document
+#[derive(Debug, Clone)]
+pub struct Acl {
+    /// Permission of sessions without a token or with an unknown one
+    default: Permission,
+    grants: HashMap<String, Permission>,
+}
+
+impl Default for Acl {
+    /// Everyone may edit, as before access control existed
+    fn default() -> Self {
+        Acl::new(Permission::Edit)
+    }
+}
+
+impl Acl {
+    /// Create an ACL granting `default` to sessions without a known token
+    pub fn new(default: Permission) -> Self {
+        Acl {
+            default,
+            grants: HashMap::new(),
+        }
+    }
+
+    /// Grant `permission` to sessions presenting `token`
+    pub fn grant(&mut self, token: impl Into<String>, permission: Permission) {
+        self.grants.insert(token.into(), permission);
+    }
+
+    /// Withdraw the grant of `token`
+    pub fn revoke(&mut self, token: &str) {
+        self.grants.remove(token);
+    }
+
+    /// The permission of a session presenting `token`
+    pub fn permission_for(&self, token: Option<&str>) -> Permission {
+        token
+            .and_then(|token| self.grants.get(token))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDIT_OPS: [&str; 12] = [
+        "insert",
+        "multi_insert",
+        "multi_delete",
+        "delete_word",
+        "replace",
+        "composition_update",
+        "composition_commit",
+        "composition_cancel",
+        "paste_begin",
+        "paste_chunk",
+        "paste_commit",
+        "paste_abort",
+    ];
+
+    #[test]
+    fn test_comment_only_allows_presence_and_comments() {
+        for op in ["get_content", "hello", "presence", "comment", "decorate"] {
+            assert!(Permission::Comment.authorize(op).is_ok(), "{op}");
+        }
+    }
+
+    #[test]
+    fn test_comment_only_rejects_edits() {
+        for op in EDIT_OPS {
+            let error = Permission::Comment.authorize(op).unwrap_err();
+            assert_eq!(error.code(), "forbidden");
+            assert!(!error.is_fatal());
+        }
+        for op in EDIT_OPS {
+            assert!(Permission::Edit.authorize(op).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_disguised_operations_are_rejected() {
+        // Variants of allowed or edit types must not slip past the check
+        for op in [
+            "Insert",
+            "INSERT",
+            " insert",
+            "insert ",
+            "comment\0insert",
+            "comment,insert",
+            "Comment",
+            "",
+            "delete",
+            "apply_remote_op",
+        ] {
+            assert!(Permission::Comment.authorize(op).is_err(), "{op:?}");
+        }
+    }
+
+    #[test]
+    fn test_viewers_cannot_comment() {
+        assert!(Permission::View.authorize("presence").is_ok());
+        assert!(Permission::View.authorize("comment").is_err());
+        assert!(Permission::View.authorize("insert").is_err());
+    }
+
+    #[test]
+    fn test_tokens_cannot_escalate() {
+        let mut acl = Acl::new(Permission::View);
+        acl.grant("reviewer", Permission::Comment);
+        acl.grant("author", Permission::Edit);
+
+        assert_eq!(acl.permission_for(None), Permission::View);
+        assert_eq!(acl.permission_for(Some("reviewer")), Permission::Comment);
+        assert_eq!(acl.permission_for(Some("author")), Permission::Edit);
+        // Unknown or mangled tokens fall back to the default
+        assert_eq!(acl.permission_for(Some("Author")), Permission::View);
+        assert_eq!(acl.permission_for(Some("reviewer ")), Permission::View);
+        assert_eq!(acl.permission_for(Some("")), Permission::View);
+
+        acl.revoke("author");
+        assert_eq!(acl.permission_for(Some("author")), Permission::View);
+    }
+}
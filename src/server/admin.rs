@@ -0,0 +1,77 @@
+//! Administrative endpoints for inspecting documents.
+//!
+//! `GET /admin/docs/{id}/structure?format=...` serves a document's node graph
+//! as Graphviz DOT or JSON, for debugging merges and teaching how the RGA
+//! orders concurrent edits. The graph includes the text of deleted characters,
+//! so admin endpoints require the token configured with `ADMIN_TOKEN`, sent as
+//! `Authorization: Bearer <token>`, and are disabled when none is configured.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::crdt::StructureFormat;
+use crate::server::documents::{AppState, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+
+/// Query parameters of the structure endpoint
+#[derive(Deserialize)]
+pub struct StructureParams {
+    /// `dot` (default) or `json`
+    pub format: Option<String>,
+}
+
+/// Checks the bearer token of an admin request
+pub(crate) fn authorize(state: &DocumentRegistry, headers: &HeaderMap) -> ServerResult {
+    let expected = state.admin_token();
+    let Some(expected) = expected.as_deref() else {
+        return Err(ServerError::AdminDisabled);
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(token) if token == expected => Ok(()),
+        _ => Err(ServerError::Unauthorized),
+    }
+}
+
+/// Export a document's node graph
+#[utoipa::path(
+    get,
+    path = "/admin/docs/{id}/structure",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "Document to inspect"),
+        ("format" = Option<String>, Query, description = "`dot` (default) or `json`"),
+    ),
+    responses(
+        (status = 200, description = "Every node with its origins and deletion state", body = String),
+        (status = 400, description = "Unsupported format"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn document_structure(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<StructureParams>,
+    headers: HeaderMap,
+) -> ServerResult<Response> {
+    authorize(&state, &headers)?;
+    let document = state.get(&id)?;
+    let name = params.format.as_deref().unwrap_or("dot");
+    let format = StructureFormat::parse(name)
+        .ok_or_else(|| ServerError::UnsupportedFormat(name.to_string()))?;
+    let content_type = match format {
+        StructureFormat::Dot => "text/vnd.graphviz; charset=utf-8",
+        StructureFormat::Json => "application/json",
+    };
+    let body = document.state.rga.export_structure(format);
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
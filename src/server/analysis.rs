@@ -0,0 +1,175 @@
+//! Analyzers: diagnostics computed from a document's change stream.
+//!
+//! An [`Analyzer`], such as a spell checker or a linter, is run by
+//! [`spawn_analyzer`] next to a document. After each edit only the lines the
+//! edit touched are analyzed again; diagnostics elsewhere are kept and moved
+//! with the text. The combined diagnostics are published as the analyzer's
+//! decorations, which sessions display until the text under them changes.
+//!
+//! Analysis runs on a copy of the text and may take a while. If the document
+//! changed in the meantime, its diagnostics are not published, since their
+//! positions would be off; the next pass covers that edit and publishes.
+
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::server::decorations::DecorationSpan;
+use crate::server::websocket::DocumentState;
+
+/// Computes diagnostics over parts of a document
+pub trait Analyzer: Send + Sync + 'static {
+    /// The owner the diagnostics are published as
+    fn name(&self) -> &str;
+
+    /// The diagnostics within `range` of `text`.
+    ///
+    /// `range` is in characters and covers whole lines. The spans returned
+    /// are positioned in `text` and must lie within `range`.
+    fn analyze(
+        &self,
+        text: &str,
+        range: Range<usize>,
+    ) -> impl Future<Output = Vec<DecorationSpan>> + Send;
+}
+
+/// The lines an edit from `old` to `new` touched, as character ranges in
+/// each, or `None` if the texts are equal
+fn affected_lines(old: &str, new: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    if prefix == old.len() && prefix == new.len() {
+        return None;
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let start = new[..prefix]
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |newline| newline + 1);
+    let new_end = new[new.len() - suffix..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map_or(new.len(), |newline| new.len() - suffix + newline);
+    let old_end = old.len() - (new.len() - new_end);
+    Some((start..old_end, start..new_end))
+}
+
+/// Replace the spans on lines an edit touched with `found`, moving the spans
+/// after them by the edit's change in length
+fn splice(
+    spans: Vec<DecorationSpan>,
+    old: Range<usize>,
+    new: Range<usize>,
+    found: Vec<DecorationSpan>,
+) -> Vec<DecorationSpan> {
+    let mut spliced: Vec<DecorationSpan> = spans
+        .into_iter()
+        .filter(|span| span.end <= old.start || span.start >= old.end)
+        .map(|span| {
+            if span.start >= old.end {
+                DecorationSpan {
+                    start: span.start - old.end + new.end,
+                    end: span.end - old.end + new.end,
+                    ..span
+                }
+            } else {
+                span
+            }
+        })
+        .chain(found)
+        .collect();
+    spliced.sort_by_key(|span| span.start);
+    spliced
+}
+
+/// Run `analyzer` over a document now and after every edit, until the
+/// document is dropped
+pub fn spawn_analyzer<A: Analyzer>(state: Arc<DocumentState>, analyzer: A) -> JoinHandle<()> {
+    let mut changes = state.peers.subscribe();
+    tokio::spawn(async move {
+        let mut analyzed = String::new();
+        let mut spans = Vec::new();
+        let mut unpublished = false;
+        loop {
+            // The version is read first, so a text newer than it shows as a
+            // version change below
+            let version = state.version();
+            let text = state.rga.to_string();
+            if let Some((old, new)) = affected_lines(&analyzed, &text) {
+                let found = analyzer.analyze(&text, new.clone()).await;
+                spans = splice(spans, old, new, found);
+                analyzed = text;
+                unpublished = true;
+            }
+            if unpublished && state.version() == version {
+                if let Err(e) = state.decorate(analyzer.name(), spans.clone()) {
+                    warn!(
+                        "Analyzer {} published invalid spans: {}",
+                        analyzer.name(),
+                        e
+                    );
+                }
+                unpublished = false;
+            }
+
+            // Wait for the next edit
+            loop {
+                match changes.recv().await {
+                    Ok(batch) => {
+                        if batch
+                            .iter()
+                            .any(|message| message.response.response_type == "update")
+                        {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => break,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize) -> DecorationSpan {
+        DecorationSpan {
+            start,
+            end,
+            kind: "spelling".to_string(),
+            message: None,
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn test_reanalyzes_only_the_touched_lines() {
+        assert_eq!(affected_lines("same", "same"), None);
+        assert_eq!(
+            affected_lines("one\ntwo\nthree", "one\ntwoo\nthree"),
+            Some((4..7, 4..8))
+        );
+        assert_eq!(affected_lines("a\nb", "a\nb\nc"), Some((2..3, 2..5)));
+
+        // A span on the touched line is replaced, one after it moves
+        let spans = vec![span(0, 3), span(4, 7), span(8, 13)];
+        assert_eq!(
+            splice(spans, 4..7, 4..8, vec![span(4, 8)]),
+            vec![span(0, 3), span(4, 8), span(9, 14)]
+        );
+    }
+}
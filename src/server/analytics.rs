@@ -0,0 +1,258 @@
+//! Document activity over time.
+//!
+//! Every document counts the edits shared with its sessions per minute and
+//! per author: how many edits each made and how many characters they added
+//! and removed. `GET /docs/{id}/activity` returns the counts, merged into
+//! buckets of `?bucket=<minutes>` and optionally limited to those starting at
+//! or after `?since=<unix seconds>`, so a team can see who worked on a
+//! document and when without exporting its history.
+//!
+//! Characters are counted by comparing the text before and after each edit,
+//! so a replacement counts as both. The last day of activity is kept.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::server::documents::AppState;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::tenants::TenantScope;
+use crate::server::websocket::DocumentState;
+
+/// Number of most recent minutes whose activity a document keeps
+const RETAINED_MINUTES: usize = 24 * 60;
+
+/// What one author did within a bucket
+#[derive(Serialize, ToSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthorActivity {
+    /// Session, bot or other origin that made the edits
+    pub author: String,
+    /// Number of edits
+    pub edits: u64,
+    /// Characters added
+    pub added: u64,
+    /// Characters removed
+    pub removed: u64,
+}
+
+/// The activity within one bucket of time
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ActivityBucket {
+    /// Start of the bucket, in seconds since the Unix epoch
+    pub start: u64,
+    /// Activity of each author who edited, by author
+    pub authors: Vec<AuthorActivity>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActivityResponse {
+    pub id: String,
+    /// Length of each bucket in minutes
+    pub bucket_minutes: u64,
+    /// Buckets with any activity, oldest first
+    pub buckets: Vec<ActivityBucket>,
+}
+
+/// Per-minute counts of a document's edits
+pub struct Activity {
+    /// The text after the last edit counted
+    text: Vec<char>,
+    /// Counts by minute since the Unix epoch, then by author
+    minutes: BTreeMap<u64, BTreeMap<String, AuthorActivity>>,
+}
+
+impl Activity {
+    /// Start counting the edits of a document holding `text`
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.chars().collect(),
+            minutes: BTreeMap::new(),
+        }
+    }
+
+    /// Count an edit by `author` that left the document with `content`, made
+    /// `at` seconds since the Unix epoch
+    pub fn record(&mut self, author: &str, content: &str, at: u64) {
+        let new: Vec<char> = content.chars().collect();
+        let prefix = self
+            .text
+            .iter()
+            .zip(&new)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = self.text[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let entry = self
+            .minutes
+            .entry(at / 60)
+            .or_default()
+            .entry(author.to_string())
+            .or_insert_with(|| AuthorActivity {
+                author: author.to_string(),
+                ..AuthorActivity::default()
+            });
+        entry.edits += 1;
+        entry.added += (new.len() - prefix - suffix) as u64;
+        entry.removed += (self.text.len() - prefix - suffix) as u64;
+        self.text = new;
+
+        while self.minutes.len() > RETAINED_MINUTES {
+            self.minutes.pop_first();
+        }
+    }
+
+    /// The counts merged into buckets of `bucket_minutes`, leaving out those
+    /// starting before `since`
+    pub fn buckets(&self, bucket_minutes: u64, since: u64) -> Vec<ActivityBucket> {
+        let mut buckets: BTreeMap<u64, BTreeMap<&str, AuthorActivity>> = BTreeMap::new();
+        for (minute, authors) in &self.minutes {
+            let start = minute / bucket_minutes * bucket_minutes * 60;
+            if start < since {
+                continue;
+            }
+            let bucket = buckets.entry(start).or_default();
+            for (author, activity) in authors {
+                let total = bucket.entry(author).or_insert_with(|| AuthorActivity {
+                    author: author.clone(),
+                    ..AuthorActivity::default()
+                });
+                total.edits += activity.edits;
+                total.added += activity.added;
+                total.removed += activity.removed;
+            }
+        }
+        buckets
+            .into_iter()
+            .map(|(start, authors)| ActivityBucket {
+                start,
+                authors: authors.into_values().collect(),
+            })
+            .collect()
+    }
+}
+
+impl DocumentState {
+    /// Count an edit by `author` that left the document with `content`
+    pub(crate) fn record_activity(&self, author: &str, content: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.activity.lock().record(author, content, now);
+    }
+
+    /// The document's activity in buckets of `bucket_minutes`, leaving out
+    /// those starting before `since` seconds since the Unix epoch
+    pub fn activity(&self, bucket_minutes: u64, since: u64) -> Vec<ActivityBucket> {
+        self.activity.lock().buckets(bucket_minutes, since)
+    }
+}
+
+/// Query parameters of the activity endpoint
+#[derive(Deserialize)]
+pub struct ActivityParams {
+    /// Length of each bucket in minutes, 1 by default
+    pub bucket: Option<u64>,
+    /// Unix time in seconds before which buckets are left out
+    pub since: Option<u64>,
+}
+
+/// Get a document's activity over time
+#[utoipa::path(
+    get,
+    path = "/docs/{id}/activity",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document whose activity to report"),
+        ("bucket" = Option<u64>, Query, description = "Length of each bucket in minutes, 1 by default"),
+        ("since" = Option<u64>, Query, description = "Unix time in seconds before which buckets are left out"),
+    ),
+    responses(
+        (status = 200, description = "Edits and characters changed per bucket and author", body = ActivityResponse),
+        (status = 400, description = "Invalid bucket length"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn document_activity(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+    Query(params): Query<ActivityParams>,
+) -> ServerResult<Json<ActivityResponse>> {
+    scope.check(&id)?;
+    let bucket_minutes = params.bucket.unwrap_or(1);
+    if bucket_minutes == 0 {
+        return Err(ServerError::InvalidFilter(
+            "'bucket' must be at least one minute".to_string(),
+        ));
+    }
+    let document = state.get(&id)?;
+    let buckets = document
+        .state
+        .activity(bucket_minutes, params.since.unwrap_or(0));
+    Ok(Json(ActivityResponse {
+        id: document.id,
+        bucket_minutes,
+        buckets,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn author(author: &str, edits: u64, added: u64, removed: u64) -> AuthorActivity {
+        AuthorActivity {
+            author: author.to_string(),
+            edits,
+            added,
+            removed,
+        }
+    }
+
+    #[test]
+    fn test_counts_edits_per_bucket_and_author() {
+        let mut activity = Activity::new("hello");
+        activity.record("a", "hello world", 60);
+        activity.record("b", "hello there world", 90);
+        // A replacement adds and removes
+        activity.record("a", "hello there earth", 130);
+        activity.record("a", "hello earth", 200);
+
+        assert_eq!(
+            activity.buckets(1, 0),
+            vec![
+                ActivityBucket {
+                    start: 60,
+                    authors: vec![author("a", 1, 6, 0), author("b", 1, 6, 0)],
+                },
+                ActivityBucket {
+                    start: 120,
+                    authors: vec![author("a", 1, 5, 5)],
+                },
+                ActivityBucket {
+                    start: 180,
+                    authors: vec![author("a", 1, 0, 6)],
+                },
+            ]
+        );
+        assert_eq!(
+            activity.buckets(5, 0),
+            vec![ActivityBucket {
+                start: 0,
+                authors: vec![author("a", 3, 11, 11), author("b", 1, 6, 0)],
+            }]
+        );
+        assert_eq!(activity.buckets(1, 150).len(), 1);
+    }
+}
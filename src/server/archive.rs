@@ -0,0 +1,275 @@
+//! Moving idle documents out of memory.
+//!
+//! With an archive directory configured (`ARCHIVE_DIR`), [`spawn_archiver`]
+//! writes every document nobody has used for the idle period
+//! (`ARCHIVE_IDLE_SECS`) to a snapshot file there and drops it from memory,
+//! so memory grows with the documents in use rather than with every document
+//! ever hosted. [`DocumentRegistry::get`] loads an archived document back the
+//! first time it is asked for it, so a client connecting to it, or a request
+//! naming it, finds it as it was left.
+//!
+//! A document is idle once no session is connected to it, no request holds it
+//! and its version has not changed for the whole period. The main document,
+//! which the operation log persists, is never archived, and neither are forks
+//! and forked documents, whose merges compare them with each other.
+//!
+//! The text, tombstones, access control list, formatting marks, frozen state
+//! and session settings survive archiving. The history of past versions,
+//! activity counts, decorations and awareness states do not, and the version
+//! count starts again from zero when the document is loaded.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::crdt::{Freeze, Mark, RGA, ReplicaId};
+use crate::server::acl::Acl;
+use crate::server::documents::{AppState, Document, DocumentRegistry, MAIN_DOCUMENT, Published};
+use crate::server::error::ServerResult;
+use crate::server::websocket::{BroadcastPolicy, DocumentState};
+
+/// What stays in memory of an archived document
+pub(crate) struct Archived {
+    /// Visible characters, counted against the tenant's usage
+    pub(crate) characters: usize,
+    replica_id: ReplicaId,
+    published: Option<Published>,
+    acl: Acl,
+    marks: Vec<Mark>,
+    freeze: Option<Freeze>,
+    broadcast_policy: BroadcastPolicy,
+    max_peer_lag: usize,
+    awareness_ttl: Duration,
+}
+
+/// The archiver's view of the documents it watches
+#[derive(Default)]
+pub(crate) struct IdleTracker {
+    /// Per document, its version when last seen in use and since when it
+    /// has been idle
+    since: BTreeMap<String, (u64, Instant)>,
+}
+
+/// The file an archived document is written to. Characters that are not
+/// safe in file names, such as a tenant's `:`, are percent-encoded.
+fn archive_path(dir: &Path, id: &str) -> PathBuf {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    dir.join(format!("{name}.snapshot"))
+}
+
+impl DocumentRegistry {
+    /// Archive the documents idle for at least `idle`, returning how many
+    /// were archived. Does nothing without an archive directory.
+    pub fn archive_idle(&self, idle: Duration) -> ServerResult<usize> {
+        let Some(dir) = self.archive_dir() else {
+            return Ok(0);
+        };
+        let now = Instant::now();
+        let mut candidates = Vec::new();
+        {
+            let mut tracker = self.idle.lock();
+            let documents = self.documents();
+            tracker
+                .since
+                .retain(|id, _| documents.iter().any(|document| &document.id == id));
+            for document in documents {
+                let version = document.state.version();
+                // Held by anyone but the registry and this loop
+                let in_use = Arc::strong_count(&document.state) > 2;
+                let since = tracker
+                    .since
+                    .entry(document.id.clone())
+                    .or_insert((version, now));
+                if in_use || since.0 != version {
+                    *since = (version, now);
+                } else if now.duration_since(since.1) >= idle && self.archivable(&document) {
+                    candidates.push(document);
+                }
+            }
+        }
+
+        let mut archived = 0;
+        for document in candidates {
+            if self.archive(&dir, document)? {
+                archived += 1;
+            }
+        }
+        Ok(archived)
+    }
+
+    /// Whether a document may leave memory at all
+    fn archivable(&self, document: &Document) -> bool {
+        document.id != MAIN_DOCUMENT && self.ensure_unforked(document).is_ok()
+    }
+
+    /// Write a document to `dir` and drop it from memory, unless it was
+    /// used meanwhile
+    fn archive(&self, dir: &Path, document: Document) -> ServerResult<bool> {
+        let version = document.state.version();
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(
+            archive_path(dir, &document.id),
+            document.state.rga.to_snapshot(),
+        )?;
+
+        let id = document.id.clone();
+        drop(document);
+        {
+            let mut documents = self.documents.write();
+            let Some(document) = documents.get(&id) else {
+                return Ok(false);
+            };
+            // Nobody else may hold the state, or their edits would be lost
+            if Arc::strong_count(&document.state) > 1 || document.state.version() != version {
+                return Ok(false);
+            }
+            let state = &document.state;
+            let entry = Archived {
+                characters: state.rga.visible_node_count(),
+                replica_id: state.rga.replica_id(),
+                published: document.published.clone(),
+                acl: state.acl.read().clone(),
+                marks: state.marks.read().clone(),
+                freeze: state.rga.freeze_state(),
+                broadcast_policy: state.broadcast_policy(),
+                max_peer_lag: state.max_peer_lag(),
+                awareness_ttl: state.awareness_ttl(),
+            };
+            documents.remove(&id);
+            self.archived.write().insert(id.clone(), entry);
+        }
+        self.idle.lock().since.remove(&id);
+        info!("Archived idle document {}", id);
+        Ok(true)
+    }
+
+    /// Load an archived document back into memory.
+    ///
+    /// # Returns
+    ///
+    /// The document, or `None` if `id` is not archived
+    pub(crate) fn rehydrate(&self, id: &str) -> ServerResult<Option<Document>> {
+        if !self.archived.read().contains_key(id) {
+            return Ok(None);
+        }
+        let mut documents = self.documents.write();
+        if let Some(document) = documents.get(id) {
+            // Loaded by another request meanwhile
+            return Ok(Some(document.clone()));
+        }
+        let Some(dir) = self.archive_dir() else {
+            return Ok(None);
+        };
+        let mut archived = self.archived.write();
+        let Some(entry) = archived.get(id) else {
+            return Ok(None);
+        };
+        let snapshot = std::fs::read_to_string(archive_path(&dir, id))?;
+        let rga = RGA::from_snapshot(entry.replica_id, &snapshot)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(freeze) = entry.freeze {
+            rga.apply_remote_freeze(freeze);
+        }
+
+        let state = DocumentState::new(rga);
+        *state.acl.write() = entry.acl.clone();
+        *state.marks.write() = entry.marks.clone();
+        state.set_broadcast_policy(entry.broadcast_policy);
+        state.set_max_peer_lag(entry.max_peer_lag);
+        state.set_awareness_ttl(entry.awareness_ttl);
+        *state.tenant.write() = self.tenant_of(id);
+        self.attach_hooks(id, &state);
+        let document = Document {
+            id: id.to_string(),
+            state: Arc::new(state),
+            upstream: None,
+            published: entry.published.clone(),
+        };
+        archived.remove(id);
+        documents.insert(document.id.clone(), document.clone());
+        info!("Loaded archived document {}", id);
+        Ok(Some(document))
+    }
+
+    /// IDs of the documents archived out of memory, in ID order
+    pub fn archived(&self) -> Vec<String> {
+        self.archived.read().keys().cloned().collect()
+    }
+}
+
+/// Archives documents idle for `idle`, checking four times per period and at
+/// least every minute
+pub fn spawn_archiver(state: AppState, idle: Duration) {
+    let interval = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match state.archive_idle(idle) {
+                Ok(0) => {}
+                Ok(archived) => info!("Archived {} idle documents", archived),
+                Err(e) => error!("Failed to archive idle documents: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::acl::Permission;
+    use crate::server::error::ServerError;
+
+    #[tokio::test]
+    async fn test_idle_documents_are_archived_and_loaded_back() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-archive-{}", std::process::id()));
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        state.set_archive_dir(Some(dir.clone()));
+        let notes = state.create("notes").unwrap();
+        let rga = &notes.state.rga;
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "todo")
+            .unwrap();
+        rga.delete(ids[3]).unwrap();
+        notes.state.record_edit();
+        notes.state.acl.write().grant("reader", Permission::View);
+        drop(notes);
+        let busy = state.create("busy").unwrap();
+
+        // Not idle for long enough, then archived; held documents stay
+        assert_eq!(state.archive_idle(Duration::from_secs(60)).unwrap(), 0);
+        assert_eq!(state.archive_idle(Duration::ZERO).unwrap(), 1);
+        assert_eq!(state.archived(), ["notes"]);
+        assert!(state.documents().iter().all(|d| d.id != "notes"));
+        assert!(dir.join("notes.snapshot").exists());
+
+        // Loaded back on first use, tombstones and access control included
+        let notes = state.get("notes").unwrap();
+        assert_eq!(notes.state.rga.to_string(), "tod");
+        assert_eq!(notes.state.rga.total_node_count(), 6);
+        assert_eq!(notes.permission_for(Some("reader")), Permission::View);
+        assert!(state.archived().is_empty());
+        notes.state.rga.insert_after(ids[2], '!').unwrap();
+
+        drop((notes, busy));
+        assert_eq!(state.archive_idle(Duration::ZERO).unwrap(), 2);
+        assert_eq!(state.get("busy").unwrap().state.rga.to_string(), "");
+        assert_eq!(state.get("notes").unwrap().state.rga.to_string(), "tod!");
+        assert!(matches!(
+            state.get("missing"),
+            Err(ServerError::UnknownDocument(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Verifying the audit chain of the operation log.
+//!
+//! With `OPLOG_DIR` and `OPLOG_AUDIT` set, every batch of operations persisted
+//! for the `main` document is chained into the log's audit file (see
+//! [`crate::crdt::audit`]). `GET /admin/audit` checks the file against the
+//! operations still in the log and reports the newest digest, which an
+//! operator can record elsewhere to detect a rewritten history later.
+
+use std::sync::Arc;
+
+use axum::{extract::State, http::HeaderMap, response::Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+
+/// The outcome of verifying the audit chain
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct AuditResponse {
+    /// Whether the audit file matches the logged operations
+    pub valid: bool,
+    /// Number of audited batches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batches: Option<usize>,
+    /// Batches checked against operations still in the log; older ones were
+    /// compacted and are covered by the digests after them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified: Option<usize>,
+    /// Hexadecimal digest of the newest batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    /// Why verification failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DocumentRegistry {
+    /// Verify the audit chain of the operation log.
+    ///
+    /// Blocks on file IO and on the persistence task.
+    pub fn verify_audit(&self) -> ServerResult<AuditResponse> {
+        let log = self.oplog().ok_or(ServerError::AuditDisabled)?;
+        let log = log.lock();
+        if log.audit_head().is_none() {
+            return Err(ServerError::AuditDisabled);
+        }
+        Ok(match log.verify_audit() {
+            Ok(report) => AuditResponse {
+                valid: true,
+                batches: Some(report.batches),
+                verified: Some(report.verified),
+                head: Some(report.head.to_string()),
+                error: None,
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => AuditResponse {
+                valid: false,
+                batches: None,
+                verified: None,
+                head: None,
+                error: Some(e.to_string()),
+            },
+            Err(e) => return Err(e.into()),
+        })
+    }
+}
+
+/// Verify the operation log's audit chain
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Whether the audit chain matches the log", body = AuditResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "The operation log is not audited"),
+    )
+)]
+pub async fn verify_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ServerResult<Json<AuditResponse>> {
+    authorize(&state, &headers)?;
+    let registry = Arc::clone(&state);
+    let response = tokio::task::spawn_blocking(move || registry.verify_audit())
+        .await
+        .map_err(|e| ServerError::Io(std::io::Error::other(e)))??;
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{RGA, SegmentConfig, SegmentedLog};
+    use parking_lot::Mutex;
+
+    #[test]
+    fn test_audit_is_verified_against_the_log() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let state = DocumentRegistry::new(RGA::new(1));
+        assert!(matches!(
+            state.verify_audit(),
+            Err(ServerError::AuditDisabled)
+        ));
+
+        let mut log = SegmentedLog::open(&dir, SegmentConfig::default()).unwrap();
+        log.enable_audit().unwrap();
+        let rga = RGA::new(1);
+        let empty = rga.fork(0);
+        rga.insert_str_after(rga.sentinel_start_id(), "audited")
+            .unwrap();
+        log.append(&rga.log_entries_since(&empty)).unwrap();
+        let head = log.audit_head().unwrap().to_string();
+        state.set_oplog(Arc::new(Mutex::new(log)));
+
+        assert_eq!(
+            state.verify_audit().unwrap(),
+            AuditResponse {
+                valid: true,
+                batches: Some(2),
+                verified: Some(2),
+                head: Some(head),
+                error: None,
+            }
+        );
+
+        // The audit file emptied
+        std::fs::write(dir.join("audit.log"), "crdt-rga audit 1\n").unwrap();
+        let response = state.verify_audit().unwrap();
+        assert!(!response.valid);
+        assert!(response.error.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! The awareness channel of a document.
+//!
+//! Clients share presence, cursors, typing indicators and similar ephemeral
+//! state as `awareness` messages carrying an [`AwarenessState`]. The server
+//! merges them into the document's [`Awareness`], relays the accepted ones to
+//! the other sessions and gives joining sessions every current state in their
+//! `init`. None of it reaches the RGA, the history or the persisted snapshots.
+//!
+//! A replica belongs to the session that first shared a state for it. When
+//! that session ends, its states are withdrawn on its behalf.
+//!
+//! Clients keep their state alive by sending it again, with the next clock,
+//! at least every half time to live (see [`Awareness::heartbeat`]). States not
+//! refreshed within the time to live expire: [`spawn_awareness_expiry`] drops
+//! them and sends their withdrawal to every session, so the cursor of a
+//! crashed browser whose connection lingers disappears like any other.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::crdt::{Awareness, AwarenessUpdate, ReplicaId};
+use crate::server::documents::AppState;
+use crate::server::error::ServerResult;
+use crate::server::websocket::{DocumentState, PeerMessage, RGAResponse};
+
+/// How long a replica's state lasts without being refreshed
+pub const DEFAULT_AWARENESS_TTL: Duration = Duration::from_secs(30);
+/// How often expired awareness states are looked for
+const AWARENESS_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A replica's awareness state as exchanged with clients
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AwarenessState {
+    /// The client replica whose state this is
+    pub replica: ReplicaId,
+    /// Increases with every change the replica makes to its state
+    pub clock: u64,
+    /// Client-defined state, usually JSON; absent once withdrawn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+}
+
+impl From<AwarenessUpdate> for AwarenessState {
+    fn from(update: AwarenessUpdate) -> Self {
+        AwarenessState {
+            replica: update.replica,
+            clock: update.clock,
+            state: update.state,
+        }
+    }
+}
+
+impl From<AwarenessState> for AwarenessUpdate {
+    fn from(state: AwarenessState) -> Self {
+        AwarenessUpdate {
+            replica: state.replica,
+            clock: state.clock,
+            state: state.state,
+        }
+    }
+}
+
+/// The awareness of a document and the sessions owning its replicas
+#[derive(Debug)]
+pub struct DocumentAwareness {
+    awareness: Awareness,
+    owners: HashMap<ReplicaId, String>,
+}
+
+impl DocumentAwareness {
+    /// Awareness kept by a server acting as `replica`
+    pub fn new(replica: ReplicaId) -> Self {
+        DocumentAwareness {
+            awareness: Awareness::new(replica, DEFAULT_AWARENESS_TTL.as_millis() as u64),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Merge a state shared by session `owner` at `now`.
+    ///
+    /// Returns whether the state was newer than the one held.
+    pub fn share(&mut self, owner: &str, state: AwarenessState, now: u64) -> ServerResult<bool> {
+        if state.replica == self.awareness.replica() {
+            return Err("Awareness replica is reserved by the server".into());
+        }
+        let claimed = self
+            .owners
+            .entry(state.replica)
+            .or_insert_with(|| owner.to_string());
+        if claimed != owner {
+            return Err("Awareness replica belongs to another session".into());
+        }
+        Ok(self.awareness.apply(&state.into(), now))
+    }
+
+    /// Withdraw the states of the replicas owned by session `owner`
+    pub fn withdraw(&mut self, owner: &str, now: u64) -> Vec<AwarenessState> {
+        let replicas: Vec<ReplicaId> = self
+            .owners
+            .iter()
+            .filter(|(_, session)| *session == owner)
+            .map(|(&replica, _)| replica)
+            .collect();
+        replicas
+            .into_iter()
+            .filter_map(|replica| {
+                self.owners.remove(&replica);
+                self.awareness.remove(replica, now)
+            })
+            .map(AwarenessState::from)
+            .collect()
+    }
+
+    /// How long states last without being refreshed
+    pub fn ttl(&self) -> Duration {
+        Duration::from_millis(self.awareness.ttl())
+    }
+
+    /// Change how long states last without being refreshed
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.awareness.set_ttl(ttl.as_millis() as u64);
+    }
+
+    /// Drop the states that expired by `now`, returning their withdrawals
+    pub fn expire(&mut self, now: u64) -> Vec<AwarenessState> {
+        let expired = self.awareness.expire(now);
+        for update in &expired {
+            self.owners.remove(&update.replica);
+        }
+        expired.into_iter().map(AwarenessState::from).collect()
+    }
+
+    /// The states held at `now`
+    pub fn states(&self, now: u64) -> Vec<AwarenessState> {
+        self.awareness
+            .updates(now)
+            .into_iter()
+            .map(AwarenessState::from)
+            .collect()
+    }
+}
+
+impl DocumentState {
+    /// Milliseconds since the document was opened, the awareness clock
+    fn awareness_now(&self) -> u64 {
+        self.opened_at.elapsed().as_millis() as u64
+    }
+
+    /// Merge a state shared by session `owner` and relay it if it was new
+    pub fn share_awareness(self: &Arc<Self>, owner: &str, state: AwarenessState) -> ServerResult {
+        let now = self.awareness_now();
+        if self.awareness.lock().share(owner, state.clone(), now)? {
+            self.publish_awareness(Some(owner), vec![state]);
+        }
+        Ok(())
+    }
+
+    /// Withdraw the states shared by session `owner` and tell its peers
+    pub fn withdraw_awareness(self: &Arc<Self>, owner: &str) {
+        let now = self.awareness_now();
+        let withdrawn = self.awareness.lock().withdraw(owner, now);
+        if !withdrawn.is_empty() {
+            self.publish_awareness(Some(owner), withdrawn);
+        }
+    }
+
+    /// The awareness states of the document's replicas
+    pub fn awareness_states(&self) -> Vec<AwarenessState> {
+        self.awareness.lock().states(self.awareness_now())
+    }
+
+    /// How long the document's awareness states last without a heartbeat
+    pub fn awareness_ttl(&self) -> Duration {
+        self.awareness.lock().ttl()
+    }
+
+    /// Change how long awareness states last without a heartbeat
+    pub fn set_awareness_ttl(&self, ttl: Duration) {
+        self.awareness.lock().set_ttl(ttl);
+    }
+
+    /// Drop the awareness states whose replicas stopped heartbeating and tell
+    /// every session, returning how many expired
+    pub fn expire_awareness(self: &Arc<Self>) -> usize {
+        let now = self.awareness_now();
+        let expired = self.awareness.lock().expire(now);
+        let count = expired.len();
+        if count > 0 {
+            self.publish_awareness(None, expired);
+        }
+        count
+    }
+
+    /// Send awareness states to every session but the one they came from, if
+    /// any
+    fn publish_awareness(self: &Arc<Self>, origin: Option<&str>, states: Vec<AwarenessState>) {
+        self.publish(PeerMessage {
+            origin: origin.unwrap_or("awareness").to_string(),
+            response: RGAResponse {
+                response_type: "awareness".to_string(),
+                content: String::new(),
+                position: None,
+                session_id: origin.map(str::to_string),
+                decorations: None,
+                version: None,
+                selection: None,
+                awareness: Some(states),
+                stats: None,
+            },
+        });
+    }
+}
+
+/// Expires the awareness states of every document's replicas once they stop
+/// heartbeating
+pub fn spawn_awareness_expiry(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(AWARENESS_EXPIRY_INTERVAL);
+        loop {
+            ticker.tick().await;
+            for document in state.documents() {
+                let expired = document.state.expire_awareness();
+                if expired > 0 {
+                    info!(
+                        "Expired {} awareness states of {} without heartbeats",
+                        expired, document.id
+                    );
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::RGA;
+
+    fn state(replica: ReplicaId, clock: u64, state: &str) -> AwarenessState {
+        AwarenessState {
+            replica,
+            clock,
+            state: Some(state.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_awareness_stays_out_of_the_document() {
+        let document = Arc::new(DocumentState::new(RGA::new(1)));
+        let mut peers = document.peers.subscribe();
+
+        document
+            .share_awareness("a", state(7, 1, r#"{"cursor":0}"#))
+            .unwrap();
+        // Stale and foreign updates are not relayed
+        document.share_awareness("a", state(7, 1, "stale")).unwrap();
+        assert!(document.share_awareness("b", state(7, 2, "theft")).is_err());
+        assert!(
+            document
+                .share_awareness("b", state(1, 1, "server"))
+                .is_err()
+        );
+        document
+            .share_awareness("b", state(8, 1, "typing"))
+            .unwrap();
+
+        assert_eq!(
+            document.awareness_states(),
+            [state(7, 1, r#"{"cursor":0}"#), state(8, 1, "typing")]
+        );
+        assert_eq!(document.rga.to_string(), "");
+        assert_eq!(document.rga.to_snapshot(), RGA::new(1).to_snapshot());
+
+        document.withdraw_awareness("a");
+        assert_eq!(document.awareness_states(), [state(8, 1, "typing")]);
+
+        let relayed: Vec<Vec<AwarenessState>> = std::iter::from_fn(|| peers.try_recv().ok())
+            .flat_map(|batch| batch.iter().cloned().collect::<Vec<_>>())
+            .filter_map(|message| message.response.awareness)
+            .collect();
+        assert_eq!(
+            relayed,
+            [
+                vec![state(7, 1, r#"{"cursor":0}"#)],
+                vec![state(8, 1, "typing")],
+                vec![AwarenessState {
+                    replica: 7,
+                    clock: 2,
+                    state: None,
+                }],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_states_expire_without_heartbeats() {
+        let document = Arc::new(DocumentState::new(RGA::new(1)));
+        document.set_awareness_ttl(Duration::from_millis(100));
+        let mut peers = document.peers.subscribe();
+
+        document
+            .share_awareness("a", state(7, 1, "cursor"))
+            .unwrap();
+        document
+            .share_awareness("b", state(8, 1, "cursor"))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        // Only the second replica keeps heartbeating
+        document
+            .share_awareness("b", state(8, 2, "cursor"))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        assert_eq!(document.expire_awareness(), 1);
+        assert_eq!(document.awareness_states(), [state(8, 2, "cursor")]);
+        // A replica that comes back is accepted again, by any session
+        document.share_awareness("c", state(7, 1, "back")).unwrap();
+
+        let expiry = std::iter::from_fn(|| peers.try_recv().ok())
+            .flat_map(|batch| batch.iter().cloned().collect::<Vec<_>>())
+            .find(|message| message.origin == "awareness")
+            .unwrap();
+        assert_eq!(expiry.response.session_id, None);
+        assert_eq!(
+            expiry.response.awareness.unwrap(),
+            [AwarenessState {
+                replica: 7,
+                clock: 1,
+                state: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Bots: in-process replicas editing a hosted document.
+//!
+//! [`DocumentRegistry::attach_bot`] hands out a [`Bot`] that reads a
+//! document's change stream and edits it like a session would, for
+//! autocorrection or templating. Unlike a session, which edits as the server's
+//! replica, a bot gets a replica ID of its own, so its text can be told apart
+//! in the document's history (`GET /docs/{id}/history?only=<replica>`) and
+//! muted in exports.
+//!
+//! Every bot edit is made on a copy of the document taken with the bot's
+//! replica ID, and the operations it produced are then delivered to the
+//! document as remote operations, so edits sessions make meanwhile merge with
+//! it as with any other replica.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::crdt::{RGA, ReplicaId};
+use crate::server::documents::DocumentRegistry;
+use crate::server::error::ServerResult;
+use crate::server::merges::apply_operations;
+use crate::server::websocket::{
+    DocumentState, PeerBatch, PeerMessage, RGAResponse, insert_text, replace_text,
+};
+
+/// A handle editing a document as its own replica
+pub struct Bot {
+    /// Name the bot's messages carry as their origin
+    name: String,
+    replica_id: ReplicaId,
+    state: Arc<DocumentState>,
+    changes: broadcast::Receiver<PeerBatch>,
+    /// Messages of the last batch not yet returned by [`Bot::next_change`]
+    pending: VecDeque<PeerMessage>,
+}
+
+impl DocumentRegistry {
+    /// Attach a bot named `name` to a document.
+    ///
+    /// The bot gets a fresh replica ID and sees the changes made from now on.
+    pub fn attach_bot(&self, id: &str, name: &str) -> ServerResult<Bot> {
+        let document = self.get(id)?;
+        document.ensure_editable()?;
+        let replica_id = self.allocate_replica_id();
+        info!(
+            "Bot {} attached to {} as replica {}",
+            name, document.id, replica_id
+        );
+        Ok(Bot {
+            name: name.to_string(),
+            replica_id,
+            changes: document.state.peers.subscribe(),
+            state: document.state,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl Bot {
+    /// Name the bot's messages carry as their origin
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Replica ID the bot's operations are stamped with
+    pub fn replica_id(&self) -> ReplicaId {
+        self.replica_id
+    }
+
+    /// The document's current text
+    pub fn text(&self) -> String {
+        self.state.rga.to_string()
+    }
+
+    /// Wait for the next message from the document's sessions, skipping the
+    /// bot's own.
+    ///
+    /// A bot that falls too far behind skips the messages it missed; the
+    /// document's current text is always available from [`Bot::text`].
+    /// Returns `None` once the document is gone.
+    pub async fn next_change(&mut self) -> Option<PeerMessage> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                if message.origin != self.name {
+                    return Some(message);
+                }
+                continue;
+            }
+            match self.changes.recv().await {
+                Ok(batch) => self.pending.extend(batch.iter().cloned()),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Bot {} skipped {} messages", self.name, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Insert `text` at visible `position`
+    pub async fn insert(&mut self, position: usize, text: &str) -> ServerResult<u64> {
+        self.edit(Some(position), |replica| {
+            insert_text(replica, position, text)
+        })
+        .await
+    }
+
+    /// Delete `length` visible characters at `position`
+    pub async fn delete(&mut self, position: usize, length: usize) -> ServerResult<u64> {
+        self.replace(position, length, "").await
+    }
+
+    /// Replace `length` visible characters at `position` with `text`
+    pub async fn replace(
+        &mut self,
+        position: usize,
+        length: usize,
+        text: &str,
+    ) -> ServerResult<u64> {
+        self.edit(Some(position), |replica| {
+            replace_text(replica, position, length, text).map(|_| ())
+        })
+        .await
+    }
+
+    /// Apply an edit made by `edit` to a copy of the document and share it.
+    ///
+    /// # Returns
+    ///
+    /// The version the edit produced
+    pub async fn edit(
+        &mut self,
+        position: Option<usize>,
+        edit: impl FnOnce(&RGA) -> ServerResult,
+    ) -> ServerResult<u64> {
+        let guard = self.state.begin_edit().await;
+        // The bot's copy does not inherit the document's frozen state
+        self.state.ensure_unfrozen()?;
+        let rga = &self.state.rga;
+        let replica = rga.fork(self.replica_id);
+        edit(&replica)?;
+        apply_operations(rga, &replica.missing_from(rga));
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(guard);
+
+        self.state.publish(PeerMessage {
+            origin: self.name.clone(),
+            response: RGAResponse {
+                response_type: "update".to_string(),
+                content,
+                position,
+                session_id: None,
+                decorations: None,
+                version: Some(version),
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::ProvenanceFilter;
+    use crate::server::documents::{AppState, MAIN_DOCUMENT};
+
+    #[tokio::test]
+    async fn test_bots_edit_as_their_own_replica() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let main = state.main();
+        let mut bot = state.attach_bot(MAIN_DOCUMENT, "autocorrect").unwrap();
+        assert_ne!(bot.replica_id(), 1);
+
+        {
+            let _edit = main.begin_edit().await;
+            insert_text(&main.rga, 0, "teh cat").unwrap();
+            main.record_edit();
+        }
+        main.publish(PeerMessage {
+            origin: "alice".to_string(),
+            response: RGAResponse {
+                response_type: "update".to_string(),
+                content: main.rga.to_string(),
+                position: Some(0),
+                session_id: None,
+                decorations: None,
+                version: Some(1),
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+        assert_eq!(bot.next_change().await.unwrap().origin, "alice");
+
+        assert_eq!(bot.delete(1, 2).await.unwrap(), 2);
+        assert_eq!(bot.text(), "t cat");
+        assert_eq!(bot.insert(5, "s").await.unwrap(), 3);
+        assert_eq!(bot.text(), "t cats");
+        assert_eq!(
+            main.rga
+                .text_by(&ProvenanceFilter::only([bot.replica_id()])),
+            "s"
+        );
+        assert!(main.rga.validate().is_ok());
+
+        // The bot does not hear its own edits
+        main.publish(PeerMessage {
+            origin: "bob".to_string(),
+            response: RGAResponse {
+                response_type: "presence".to_string(),
+                content: String::new(),
+                position: None,
+                session_id: None,
+                decorations: None,
+                version: None,
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+        assert_eq!(bot.next_change().await.unwrap().origin, "bob");
+    }
+}
@@ -0,0 +1,379 @@
+//! Capture and replay of WebSocket sessions.
+//!
+//! With a capture directory configured, every session writes the frames its
+//! client sends, with the time each arrived, to `<session id>.capture`:
+//!
+//! ```text
+//! crdt-rga capture 1
+//! session session_1792171027090_4
+//! document main
+//! permission edit
+//! replica 0
+//! started 1792171027090123
+//! 0 "{\"type\":\"insert\",\"character\":\"a\",\"position\":0}"
+//! 18250 "{\"type\":\"presence\",\"position\":1}"
+//! ```
+//!
+//! `started` is the session's start in microseconds since the Unix epoch and
+//! every frame is prefixed with its offset from it, so captures of several
+//! sessions merge into one [`timeline`]. Frames are stored as JSON strings,
+//! exactly as received, malformed ones included.
+//!
+//! A [`Replayer`] applies a timeline to a bare RGA the way sessions apply it
+//! to the document: the same edits, refused under the same permissions, from
+//! the server's replica ID. Replaying the captures of every session of a
+//! document from the state it was opened with reproduces its content, so a
+//! divergence report can be investigated locally with the `crdt-rga-replay`
+//! tool. Changes that do not come from sessions, such as merges, imports and
+//! MQTT operations, are not captured.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::crdt::{RGA, ReplicaId};
+use crate::server::acl::Permission;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::paste::PasteTransactions;
+use crate::server::websocket::{RGAOperation, insert_text, replace_text, required};
+
+/// First line of every capture
+pub const CAPTURE_HEADER: &str = "crdt-rga capture 1";
+
+/// The session a capture was taken from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureHeader {
+    /// ID of the captured session
+    pub session_id: String,
+    /// ID of the document it edited
+    pub document: String,
+    /// What the session was allowed to do
+    pub permission: Permission,
+    /// Replica ID of the server's copy of the document
+    pub replica_id: ReplicaId,
+    /// When the session started, in microseconds since the Unix epoch
+    pub started_at: u64,
+}
+
+/// A frame received from a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// Time since the session started
+    pub offset: Duration,
+    /// The frame, as received
+    pub text: String,
+}
+
+/// Writes the frames of one session to its capture file
+pub struct SessionCapture {
+    out: LineWriter<File>,
+    started: Instant,
+}
+
+impl SessionCapture {
+    /// Create `<session id>.capture` in `dir` and write its header.
+    ///
+    /// The session is taken to start now.
+    pub fn create(
+        dir: &Path,
+        session_id: &str,
+        document: &str,
+        permission: Permission,
+        replica_id: ReplicaId,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut out = LineWriter::new(File::create(dir.join(format!("{session_id}.capture")))?);
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        writeln!(out, "{CAPTURE_HEADER}")?;
+        writeln!(out, "session {session_id}")?;
+        writeln!(out, "document {document}")?;
+        writeln!(out, "permission {permission}")?;
+        writeln!(out, "replica {replica_id}")?;
+        writeln!(out, "started {started_at}")?;
+        Ok(SessionCapture {
+            out,
+            started: Instant::now(),
+        })
+    }
+
+    /// Append a frame received now
+    pub fn record(&mut self, frame: &str) -> io::Result<()> {
+        let offset = self.started.elapsed().as_micros();
+        let frame = serde_json::to_string(frame).map_err(io::Error::other)?;
+        writeln!(self.out, "{offset} {frame}")
+    }
+}
+
+/// A parsed capture file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub header: CaptureHeader,
+    /// Frames in the order they were received
+    pub frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    /// Parse a capture written by [`SessionCapture`]
+    pub fn parse(text: &str) -> Result<Capture, &'static str> {
+        let mut lines = text.lines();
+        if lines.next() != Some(CAPTURE_HEADER) {
+            return Err("Not a capture");
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|line| line.strip_prefix(' '))
+                .ok_or("Malformed capture header")
+        };
+        let header = CaptureHeader {
+            session_id: field("session")?.to_string(),
+            document: field("document")?.to_string(),
+            permission: Permission::parse(field("permission")?).ok_or("Unknown permission")?,
+            replica_id: field("replica")?
+                .parse()
+                .map_err(|_| "Malformed replica ID")?,
+            started_at: field("started")?
+                .parse()
+                .map_err(|_| "Malformed start time")?,
+        };
+
+        let frames = lines
+            .map(|line| {
+                let (offset, frame) = line.split_once(' ').ok_or("Malformed frame")?;
+                Ok(CapturedFrame {
+                    offset: Duration::from_micros(
+                        offset.parse().map_err(|_| "Malformed frame offset")?,
+                    ),
+                    text: serde_json::from_str(frame).map_err(|_| "Malformed frame")?,
+                })
+            })
+            .collect::<Result<_, &'static str>>()?;
+        Ok(Capture { header, frames })
+    }
+
+    /// When `frame` arrived, in microseconds since the Unix epoch
+    pub fn arrival(&self, frame: &CapturedFrame) -> u64 {
+        self.header.started_at + frame.offset.as_micros() as u64
+    }
+}
+
+/// The frames of several captures in the order they arrived, each with its
+/// capture. Frames that arrived in the same microsecond keep the order of
+/// `captures`, and frames of one session always keep their own order.
+pub fn timeline(captures: &[Capture]) -> Vec<(&Capture, &CapturedFrame)> {
+    let mut frames: Vec<_> = captures
+        .iter()
+        .flat_map(|capture| capture.frames.iter().map(move |frame| (capture, frame)))
+        .collect();
+    // Stable, so ties and each session's frames keep their order
+    frames.sort_by_key(|(capture, frame)| capture.arrival(frame));
+    frames
+}
+
+/// Applies captured frames to a bare RGA like sessions apply them to the
+/// document.
+pub struct Replayer {
+    rga: RGA,
+    /// Pastes in progress, by session
+    pastes: HashMap<String, PasteTransactions>,
+    /// Frames that edited the document
+    pub edits: usize,
+    /// Frames a session would have refused
+    pub rejected: usize,
+}
+
+impl Replayer {
+    /// Replay onto `rga`, which should hold the document as it was before
+    /// the first captured frame
+    pub fn new(rga: RGA) -> Self {
+        Replayer {
+            rga,
+            pastes: HashMap::new(),
+            edits: 0,
+            rejected: 0,
+        }
+    }
+
+    /// The document as replayed so far
+    pub fn rga(&self) -> &RGA {
+        &self.rga
+    }
+
+    /// Apply a frame of `header`'s session.
+    ///
+    /// Frames that do not change the document, like presence and comments,
+    /// are accepted and ignored.
+    ///
+    /// # Returns
+    ///
+    /// The error the session would have answered the frame with
+    pub fn apply(&mut self, header: &CaptureHeader, frame: &str) -> ServerResult {
+        let result = self.edit(header, frame);
+        if result.is_err() {
+            self.rejected += 1;
+        }
+        result
+    }
+
+    fn edit(&mut self, header: &CaptureHeader, frame: &str) -> ServerResult {
+        let operation =
+            serde_json::from_str::<RGAOperation>(frame).map_err(ServerError::InvalidMessage)?;
+        header.permission.authorize(&operation.op_type)?;
+        let rga = &self.rga;
+        let pastes = self.pastes.entry(header.session_id.clone()).or_default();
+        match operation.op_type.as_str() {
+            "insert" => {
+                let character = required(operation.character, "insert", "character")?;
+                let position = operation.position.unwrap_or(0);
+                insert_text(rga, position, character.encode_utf8(&mut [0; 4]))?;
+            }
+            "multi_insert" => {
+                let character = required(operation.character, "multi_insert", "character")?;
+                let positions = required(operation.positions, "multi_insert", "positions")?;
+                rga.insert_at_carets(&positions, rga.normalization().apply_char(character))?;
+            }
+            "multi_delete" => {
+                let positions = required(operation.positions, "multi_delete", "positions")?;
+                rga.delete_at_carets(&positions)?;
+            }
+            "delete_word" => {
+                let position = required(operation.position, "delete_word", "position")?;
+                rga.delete_word_before(position)?;
+            }
+            "replace" => {
+                let position = required(operation.position, "replace", "position")?;
+                let length = required(operation.length, "replace", "length")?;
+                let text = operation.text.unwrap_or_default();
+                replace_text(rga, position, length, &text)?;
+            }
+            "composition_commit" => {
+                let text = required(operation.text, "composition_commit", "text")?;
+                insert_text(rga, operation.position.unwrap_or(0), &text)?;
+            }
+            "paste_begin" => {
+                let transaction = required(operation.transaction, "paste_begin", "transaction")?;
+                let position = required(operation.position, "paste_begin", "position")?;
+                return pastes.begin(transaction, position);
+            }
+            "paste_chunk" => {
+                let transaction = required(operation.transaction, "paste_chunk", "transaction")?;
+                let sequence = required(operation.sequence, "paste_chunk", "sequence")?;
+                let text = required(operation.text, "paste_chunk", "text")?;
+                return pastes.append(&transaction, sequence, &text);
+            }
+            "paste_commit" => {
+                let transaction = required(operation.transaction, "paste_commit", "transaction")?;
+                let (position, text) = pastes.commit(&transaction)?;
+                insert_text(rga, position, &text)?;
+            }
+            "paste_abort" => {
+                let transaction = required(operation.transaction, "paste_abort", "transaction")?;
+                return pastes.abort(&transaction);
+            }
+            "get_content" | "hello" | "presence" | "comment" | "decorate"
+            | "composition_update" | "composition_cancel" => return Ok(()),
+            _ => return Err(ServerError::UnknownOperation(operation.op_type)),
+        }
+        self.edits += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(session_id: &str, permission: Permission, started_at: u64) -> CaptureHeader {
+        CaptureHeader {
+            session_id: session_id.to_string(),
+            document: "main".to_string(),
+            permission,
+            replica_id: 0,
+            started_at,
+        }
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-capture-{}", std::process::id()));
+        let mut capture =
+            SessionCapture::create(&dir, "session_1", "notes", Permission::Comment, 7).unwrap();
+        capture.record("{\"type\":\"presence\"}").unwrap();
+        capture.record("not json\nat all").unwrap();
+        drop(capture);
+
+        let text = std::fs::read_to_string(dir.join("session_1.capture")).unwrap();
+        let capture = Capture::parse(&text).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(capture.header.session_id, "session_1");
+        assert_eq!(capture.header.document, "notes");
+        assert_eq!(capture.header.permission, Permission::Comment);
+        assert_eq!(capture.header.replica_id, 7);
+        let frames: Vec<_> = capture.frames.iter().map(|f| f.text.as_str()).collect();
+        assert_eq!(frames, ["{\"type\":\"presence\"}", "not json\nat all"]);
+        assert!(capture.frames[0].offset <= capture.frames[1].offset);
+
+        assert_eq!(
+            Capture::parse("crdt-rga snapshot 2\n"),
+            Err("Not a capture")
+        );
+        let truncated = format!("{CAPTURE_HEADER}\nsession s\ndocument main\n");
+        assert!(Capture::parse(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_replay_interleaves_sessions() {
+        let frame = |offset_us, text: &str| CapturedFrame {
+            offset: Duration::from_micros(offset_us),
+            text: text.to_string(),
+        };
+        let captures = [
+            Capture {
+                header: header("a", Permission::Edit, 1_000),
+                frames: vec![
+                    frame(0, r#"{"type":"insert","character":"a","position":0}"#),
+                    frame(
+                        20,
+                        r#"{"type":"paste_begin","transaction":"p","position":2}"#,
+                    ),
+                    frame(
+                        21,
+                        r#"{"type":"paste_chunk","transaction":"p","sequence":0,"text":"cd"}"#,
+                    ),
+                    frame(22, r#"{"type":"paste_commit","transaction":"p"}"#),
+                ],
+            },
+            Capture {
+                header: header("b", Permission::Edit, 1_005),
+                frames: vec![
+                    frame(0, r#"{"type":"insert","character":"b","position":1}"#),
+                    frame(
+                        30,
+                        r#"{"type":"replace","position":3,"length":1,"text":"D"}"#,
+                    ),
+                ],
+            },
+            Capture {
+                header: header("viewer", Permission::View, 1_000),
+                frames: vec![
+                    frame(1, r#"{"type":"presence","position":1}"#),
+                    frame(2, r#"{"type":"insert","character":"x","position":0}"#),
+                ],
+            },
+        ];
+
+        let mut replayer = Replayer::new(RGA::new(0));
+        for (capture, frame) in timeline(&captures) {
+            let _ = replayer.apply(&capture.header, &frame.text);
+        }
+        assert_eq!(replayer.rga().to_string(), "abcD");
+        assert_eq!(replayer.edits, 4);
+        assert_eq!(replayer.rejected, 1);
+    }
+}
@@ -0,0 +1,163 @@
+//! Negotiated compression and adaptive batching for WebSocket sessions.
+//!
+//! Mobile clients on cellular links pay for every byte and every round trip.
+//! A client that sends a `hello` frame listing the codecs it supports gets
+//! messages from its peers delivered in `batch` frames instead of one frame per
+//! message. Batch frames are compressed with the negotiated codec (zstd) and
+//! sent as binary messages.
+//!
+//! How long a batch may collect messages follows the measured round-trip time:
+//! on a slow link a few more milliseconds of delay are imperceptible next to
+//! the RTT, while on a fast link batching stays short so peers' edits still
+//! appear immediately.
+
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::RGAResponse;
+
+/// zstd level used for batch frames; low levels are fast and already shrink
+/// repetitive JSON considerably
+const ZSTD_LEVEL: i32 = 3;
+/// Fraction of the smoothed RTT a batch may wait before it is flushed
+const BATCH_WINDOW_RTT_DIVISOR: u32 = 4;
+/// Batch window used until the first RTT sample arrives
+const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(20);
+/// Bounds on the batch window
+const MIN_BATCH_WINDOW: Duration = Duration::from_millis(5);
+const MAX_BATCH_WINDOW: Duration = Duration::from_millis(250);
+/// A batch is flushed early once it holds this many messages
+const MAX_BATCH_LEN: usize = 64;
+
+/// Compression applied to batch frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Batch frames are sent as JSON text
+    None,
+    /// Batch frames are zstd-compressed JSON sent as binary
+    Zstd,
+}
+
+impl Codec {
+    /// The codec's name on the wire
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// Pick the codec to use from the ones a client offered
+    pub fn negotiate(offered: &[String]) -> Codec {
+        if offered.iter().any(|codec| codec == Codec::Zstd.name()) {
+            Codec::Zstd
+        } else {
+            Codec::None
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchFrame<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    messages: &'a [RGAResponse],
+}
+
+/// Encode messages as one `batch` frame
+pub fn encode_batch(codec: Codec, messages: &[RGAResponse]) -> ServerResult<Message> {
+    let frame = BatchFrame {
+        kind: "batch",
+        messages,
+    };
+    let json = serde_json::to_string(&frame).map_err(ServerError::Serialization)?;
+    Ok(match codec {
+        Codec::None => Message::Text(json),
+        Codec::Zstd => Message::Binary(zstd::encode_all(json.as_bytes(), ZSTD_LEVEL)?),
+    })
+}
+
+/// Smoothed round-trip time, estimated like TCP's SRTT
+#[derive(Debug, Default)]
+pub struct RttEstimator {
+    smoothed: Option<Duration>,
+}
+
+impl RttEstimator {
+    /// Record a new RTT sample
+    pub fn update(&mut self, sample: Duration) {
+        self.smoothed = Some(match self.smoothed {
+            // Exponential moving average with weight 1/8 for the new sample
+            Some(smoothed) => (smoothed * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
+    /// The smoothed RTT, once a sample has been recorded
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed
+    }
+
+    /// How long a batch may collect messages on this link
+    pub fn batch_window(&self) -> Duration {
+        self.smoothed
+            .map_or(DEFAULT_BATCH_WINDOW, |rtt| rtt / BATCH_WINDOW_RTT_DIVISOR)
+            .clamp(MIN_BATCH_WINDOW, MAX_BATCH_WINDOW)
+    }
+}
+
+/// Collects outgoing messages for one session until the batch is due
+#[derive(Debug)]
+pub struct Batcher {
+    pub codec: Codec,
+    pub rtt: RttEstimator,
+    pending: Vec<RGAResponse>,
+    deadline: Option<Instant>,
+}
+
+impl Batcher {
+    /// Create a batcher sending frames with `codec`
+    pub fn new(codec: Codec) -> Self {
+        Batcher {
+            codec,
+            rtt: RttEstimator::default(),
+            pending: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// Queue a message. Returns true if the batch is full and must be flushed.
+    pub fn push(&mut self, response: RGAResponse) -> bool {
+        if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + self.rtt.batch_window());
+        }
+        self.pending.push(response);
+        self.pending.len() >= MAX_BATCH_LEN
+    }
+
+    /// When the pending batch must be flushed, if anything is pending
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Drop the pending batch without sending it
+    pub fn discard(&mut self) {
+        self.deadline = None;
+        self.pending.clear();
+    }
+
+    /// Encode and clear the pending batch, if there is one
+    pub fn flush(&mut self) -> ServerResult<Option<Message>> {
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let frame = encode_batch(self.codec, &self.pending)?;
+        self.pending.clear();
+        Ok(Some(frame))
+    }
+}
@@ -0,0 +1,246 @@
+//! Transient decorations shared between the sessions of a document.
+//!
+//! Decorations are spans such as lint warnings or search highlights that a
+//! session wants its peers to see without making them part of the document.
+//! They are never written to the CRDT and are lost when the server restarts.
+//!
+//! A decoration arrives positioned by visible indices and is anchored to the
+//! IDs of the characters it covers, so it moves with its text while others
+//! edit around it. Once any of those characters is deleted or text is inserted
+//! inside the span, the decoration no longer describes what it was computed
+//! for and is dropped; the session that published it can send a fresh one.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{RGA, Remap, UniqueId};
+use crate::server::error::ServerResult;
+
+/// A decoration as exchanged with clients
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DecorationSpan {
+    /// Visible index of the first decorated character
+    pub start: usize,
+    /// Visible index just past the last decorated character
+    pub end: usize,
+    /// Client-defined category, such as `warning` or `highlight`
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The session that published the decoration, filled in by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// A decoration anchored to the characters it covers
+#[derive(Debug)]
+struct Decoration {
+    owner: String,
+    kind: String,
+    message: Option<String>,
+    /// The covered characters when the decoration was published
+    covered: Vec<UniqueId>,
+}
+
+/// The decorations of one document
+#[derive(Debug, Default)]
+pub struct Decorations {
+    decorations: Vec<Decoration>,
+    /// The spans clients were last told about
+    published: Vec<DecorationSpan>,
+}
+
+impl Decorations {
+    /// Follow the covered characters to their new IDs after the document's
+    /// nodes were renamed
+    pub fn rename(&mut self, remap: &Remap) {
+        for decoration in &mut self.decorations {
+            for id in &mut decoration.covered {
+                *id = remap.after(*id);
+            }
+        }
+    }
+
+    /// Replace the decorations published by `owner`.
+    ///
+    /// Returns the spans of every session's decorations to send to clients.
+    pub fn set(
+        &mut self,
+        rga: &RGA,
+        owner: &str,
+        spans: Vec<DecorationSpan>,
+    ) -> ServerResult<Vec<DecorationSpan>> {
+        let visible = rga.visible_nodes();
+        let mut anchored = Vec::with_capacity(spans.len());
+        for span in spans {
+            if span.start >= span.end || span.end > visible.len() {
+                return Err("Decoration out of range".into());
+            }
+            anchored.push(Decoration {
+                owner: owner.to_string(),
+                kind: span.kind,
+                message: span.message,
+                covered: visible[span.start..span.end]
+                    .iter()
+                    .map(|node| node.id)
+                    .collect(),
+            });
+        }
+
+        self.decorations
+            .retain(|decoration| decoration.owner != owner);
+        self.decorations.extend(anchored);
+        self.published = self.resolve(rga);
+        Ok(self.published.clone())
+    }
+
+    /// Drop the decorations published by `owner`, returning the remaining
+    /// spans if any were dropped.
+    pub fn clear(&mut self, rga: &RGA, owner: &str) -> Option<Vec<DecorationSpan>> {
+        let before = self.decorations.len();
+        self.decorations
+            .retain(|decoration| decoration.owner != owner);
+        if self.decorations.len() == before {
+            return None;
+        }
+        self.published = self.resolve(rga);
+        Some(self.published.clone())
+    }
+
+    /// Re-anchor the decorations after an edit.
+    ///
+    /// Drops decorations whose text changed and returns the new spans if
+    /// anything clients see has changed.
+    pub fn refresh(&mut self, rga: &RGA) -> Option<Vec<DecorationSpan>> {
+        if self.decorations.is_empty() && self.published.is_empty() {
+            return None;
+        }
+        let spans = self.resolve(rga);
+        if spans == self.published {
+            return None;
+        }
+        self.published = spans;
+        Some(self.published.clone())
+    }
+
+    /// The spans clients were last told about
+    pub fn spans(&self) -> &[DecorationSpan] {
+        &self.published
+    }
+
+    /// Drop invalidated decorations and position the rest
+    fn resolve(&mut self, rga: &RGA) -> Vec<DecorationSpan> {
+        let visible: Vec<UniqueId> = rga.visible_nodes().iter().map(|node| node.id).collect();
+
+        // Visible text is in document order, so a span's characters are
+        // contiguous there exactly when nothing was inserted or deleted inside
+        self.decorations.retain(|decoration| {
+            visible
+                .iter()
+                .position(|&id| id == decoration.covered[0])
+                .is_some_and(|start| visible[start..].starts_with(&decoration.covered))
+        });
+
+        self.decorations
+            .iter()
+            .map(|decoration| {
+                let start = visible
+                    .iter()
+                    .position(|&id| id == decoration.covered[0])
+                    .unwrap_or_default();
+                DecorationSpan {
+                    start,
+                    end: start + decoration.covered.len(),
+                    kind: decoration.kind.clone(),
+                    message: decoration.message.clone(),
+                    session_id: Some(decoration.owner.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, kind: &str) -> DecorationSpan {
+        DecorationSpan {
+            start,
+            end,
+            kind: kind.to_string(),
+            message: None,
+            session_id: None,
+        }
+    }
+
+    fn document(text: &str) -> RGA {
+        let rga = RGA::new(1);
+        rga.insert_str_after(rga.sentinel_start_id(), text).unwrap();
+        rga
+    }
+
+    #[test]
+    fn test_decorations_follow_and_expire_with_their_text() {
+        let rga = document("let x = 1;");
+        let mut decorations = Decorations::default();
+        let spans = decorations
+            .set(
+                &rga,
+                "a",
+                vec![span(4, 5, "warning"), span(8, 9, "highlight")],
+            )
+            .unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].session_id.as_deref(), Some("a"));
+
+        // Deleting text before the spans moves them
+        let ids: Vec<UniqueId> = rga.visible_nodes().iter().map(|node| node.id).collect();
+        rga.delete(ids[0]).unwrap();
+        let spans = decorations.refresh(&rga).unwrap();
+        assert_eq!((spans[0].start, spans[0].end), (3, 4));
+        assert_eq!(decorations.refresh(&rga), None);
+
+        // Deleting decorated text invalidates the decoration
+        rga.delete(ids[8]).unwrap();
+        let spans = decorations.refresh(&rga).unwrap();
+        assert_eq!(
+            spans,
+            vec![DecorationSpan {
+                session_id: Some("a".to_string()),
+                ..span(3, 4, "warning")
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sessions_replace_and_clear_their_own_decorations() {
+        let rga = document("abcdef");
+        let mut decorations = Decorations::default();
+        decorations
+            .set(&rga, "a", vec![span(0, 2, "lint")])
+            .unwrap();
+        decorations
+            .set(&rga, "b", vec![span(2, 4, "lint")])
+            .unwrap();
+        let spans = decorations
+            .set(&rga, "a", vec![span(4, 6, "lint")])
+            .unwrap();
+        let starts: Vec<usize> = spans.iter().map(|span| span.start).collect();
+        assert_eq!(starts, vec![2, 4]);
+
+        assert!(
+            decorations
+                .set(&rga, "a", vec![span(5, 7, "lint")])
+                .is_err()
+        );
+        assert!(
+            decorations
+                .set(&rga, "a", vec![span(3, 3, "lint")])
+                .is_err()
+        );
+        assert_eq!(decorations.spans().len(), 2);
+
+        assert_eq!(decorations.clear(&rga, "b").unwrap().len(), 1);
+        assert_eq!(decorations.clear(&rga, "b"), None);
+    }
+}
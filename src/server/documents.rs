@@ -0,0 +1,387 @@
+//! The documents a server hosts.
+//!
+//! Every server starts with one document, [`MAIN_DOCUMENT`], which `/ws`
+//! edits. Further documents are created by forking an existing one; each fork
+//! edits as its own replica and remembers its upstream, so the two can be
+//! compared and reconciled later. Publishing a document at some version
+//! registers a read-only copy of its text at that version. Idle documents may
+//! be archived out of memory and are loaded back when next used, see
+//! [`crate::server::archive`].
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::crdt::{RGA, ReplicaId, SegmentedLog};
+use crate::server::acl::Permission;
+use crate::server::archive::{Archived, IdleTracker};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::hooks::DocumentHook;
+use crate::server::leases::ReplicaLeases;
+use crate::server::merges::MergeRequests;
+use crate::server::replication::Peer;
+use crate::server::tenants::Tenant;
+use crate::server::websocket::DocumentState;
+
+/// ID of the document every server starts with
+pub const MAIN_DOCUMENT: &str = "main";
+
+/// Shared application state: the documents this server hosts
+pub type AppState = Arc<DocumentRegistry>;
+
+/// Where a forked document came from
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    /// The document this one was forked from
+    pub document_id: String,
+    /// The upstream's Lamport counter when the fork was taken
+    pub forked_at_clock: u64,
+}
+
+/// What a published document is a copy of
+#[derive(Debug, Clone)]
+pub struct Published {
+    /// The document that was published
+    pub document_id: String,
+    /// The version of it the copy holds
+    pub version: u64,
+}
+
+/// A hosted document
+#[derive(Clone)]
+pub struct Document {
+    pub id: String,
+    pub state: Arc<DocumentState>,
+    /// Set if the document is a fork
+    pub upstream: Option<Upstream>,
+    /// Set if the document is a published copy, which cannot be edited
+    pub published: Option<Published>,
+}
+
+impl Document {
+    /// The permission of a session presenting `token`. Sessions on a
+    /// published copy may comment at most.
+    pub fn permission_for(&self, token: Option<&str>) -> Permission {
+        let permission = self.state.acl.read().permission_for(token);
+        if self.published.is_some() {
+            permission.min(Permission::Comment)
+        } else {
+            permission
+        }
+    }
+
+    /// Fail unless the document may be edited
+    pub fn ensure_editable(&self) -> ServerResult {
+        match self.published {
+            Some(_) => Err(ServerError::Published(self.id.clone())),
+            None => self.state.ensure_unfrozen(),
+        }
+    }
+}
+
+/// Hosted documents by ID
+pub struct DocumentRegistry {
+    /// Documents held in memory
+    pub(crate) documents: RwLock<BTreeMap<String, Document>>,
+    /// Documents archived out of memory, by ID
+    pub(crate) archived: RwLock<BTreeMap<String, Archived>>,
+    /// Directory idle documents are archived to, if they are
+    archive_dir: RwLock<Option<PathBuf>>,
+    /// How long each document has been idle
+    pub(crate) idle: Mutex<IdleTracker>,
+    /// Replica ID handed to the next fork
+    next_replica_id: AtomicU64,
+    /// Sequence number used to name the next fork
+    next_fork: AtomicU64,
+    /// Proposals to merge forks back into their upstream
+    pub(crate) merge_requests: MergeRequests,
+    /// Bearer token required by the admin endpoints, which are disabled
+    /// without one
+    admin_token: RwLock<Option<String>>,
+    /// Directory sessions are captured to, if they are
+    capture_dir: RwLock<Option<PathBuf>>,
+    /// Hooks registered for every document
+    pub(crate) hooks: RwLock<Vec<Arc<dyn DocumentHook>>>,
+    /// Customers by ID, see [`DocumentRegistry::add_tenant`]
+    pub(crate) tenants: RwLock<BTreeMap<String, Arc<Tenant>>>,
+    /// The operation log the main document is persisted to, if any
+    oplog: RwLock<Option<Arc<Mutex<SegmentedLog>>>>,
+    /// Replica IDs leased to clients
+    leases: ReplicaLeases,
+    /// Whether documents are refused while the main document's log cannot
+    /// be written
+    reject_documents_when_degraded: AtomicBool,
+    /// Servers documents are re-replicated to, by name
+    pub(crate) peers: RwLock<BTreeMap<String, Peer>>,
+}
+
+impl DocumentRegistry {
+    /// Create a registry hosting `rga` as the main document
+    pub fn new(rga: RGA) -> Self {
+        let next_replica_id = rga.replica_id() + 1;
+        let main = Document {
+            id: MAIN_DOCUMENT.to_string(),
+            state: Arc::new(DocumentState::new(rga)),
+            upstream: None,
+            published: None,
+        };
+        main.state.hooks.write().document = main.id.clone();
+        Self {
+            documents: RwLock::new(BTreeMap::from([(main.id.clone(), main)])),
+            archived: RwLock::new(BTreeMap::new()),
+            archive_dir: RwLock::new(None),
+            idle: Mutex::new(IdleTracker::default()),
+            next_replica_id: AtomicU64::new(next_replica_id),
+            next_fork: AtomicU64::new(1),
+            merge_requests: MergeRequests::default(),
+            admin_token: RwLock::new(None),
+            capture_dir: RwLock::new(None),
+            hooks: RwLock::new(Vec::new()),
+            tenants: RwLock::new(BTreeMap::new()),
+            oplog: RwLock::new(None),
+            leases: ReplicaLeases::default(),
+            reject_documents_when_degraded: AtomicBool::new(false),
+            peers: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Require `token` for the admin endpoints, or disable them with `None`
+    pub fn set_admin_token(&self, token: Option<String>) {
+        *self.admin_token.write() = token;
+    }
+
+    /// The token admin endpoints require, if they are enabled
+    pub fn admin_token(&self) -> Option<String> {
+        self.admin_token.read().clone()
+    }
+
+    /// Capture the frames of new sessions to files in `dir`, or stop with
+    /// `None`
+    pub fn set_capture_dir(&self, dir: Option<PathBuf>) {
+        *self.capture_dir.write() = dir;
+    }
+
+    /// Archive idle documents to files in `dir`, or keep every document in
+    /// memory with `None`
+    pub fn set_archive_dir(&self, dir: Option<PathBuf>) {
+        *self.archive_dir.write() = dir;
+    }
+
+    /// The directory idle documents are archived to, if any
+    pub fn archive_dir(&self) -> Option<PathBuf> {
+        self.archive_dir.read().clone()
+    }
+
+    /// Remember the operation log the main document is persisted to
+    pub fn set_oplog(&self, log: Arc<Mutex<SegmentedLog>>) {
+        *self.oplog.write() = Some(log);
+    }
+
+    /// The operation log the main document is persisted to, if any
+    pub fn oplog(&self) -> Option<Arc<Mutex<SegmentedLog>>> {
+        self.oplog.read().clone()
+    }
+
+    /// The directory new sessions are captured to, if any
+    pub fn capture_dir(&self) -> Option<PathBuf> {
+        self.capture_dir.read().clone()
+    }
+
+    /// Refuse to create, fork or publish documents while the main document's
+    /// operation log cannot be written
+    pub fn set_reject_documents_when_degraded(&self, reject: bool) {
+        self.reject_documents_when_degraded
+            .store(reject, Ordering::Relaxed);
+    }
+
+    /// Fail if documents are refused because storage is degraded
+    fn admit_document(&self) -> ServerResult {
+        if self.reject_documents_when_degraded.load(Ordering::Relaxed)
+            && self.main().storage.is_degraded()
+        {
+            return Err(ServerError::StorageUnavailable);
+        }
+        Ok(())
+    }
+
+    /// The replica IDs leased to clients
+    pub fn leases(&self) -> &ReplicaLeases {
+        &self.leases
+    }
+
+    /// The main document
+    pub fn main(&self) -> Arc<DocumentState> {
+        self.documents.read()[MAIN_DOCUMENT].state.clone()
+    }
+
+    /// A replica ID no document or bot edits as yet
+    pub(crate) fn allocate_replica_id(&self) -> ReplicaId {
+        self.next_replica_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Look up a document by ID, loading it back if it was archived
+    pub fn get(&self, id: &str) -> ServerResult<Document> {
+        if let Some(document) = self.documents.read().get(id) {
+            return Ok(document.clone());
+        }
+        self.rehydrate(id)?
+            .ok_or_else(|| ServerError::UnknownDocument(id.to_string()))
+    }
+
+    /// All documents held in memory, in ID order
+    pub fn documents(&self) -> Vec<Document> {
+        self.documents.read().values().cloned().collect()
+    }
+
+    /// Number of documents held in memory
+    pub fn len(&self) -> usize {
+        self.documents.read().len()
+    }
+
+    /// Returns true if no document is hosted
+    pub fn is_empty(&self) -> bool {
+        self.documents.read().is_empty()
+    }
+
+    /// Create an empty document registered as `id`.
+    ///
+    /// It gets a fresh replica ID and the hooks registered for every
+    /// document.
+    pub fn create(&self, id: &str) -> ServerResult<Document> {
+        if self.get(id).is_ok() {
+            return Err(ServerError::DocumentExists(id.to_string()));
+        }
+        self.admit_document()?;
+        let state = DocumentState::new(RGA::new(self.allocate_replica_id()));
+        self.attach_tenant(id, &state)?;
+        self.attach_hooks(id, &state);
+        let document = Document {
+            id: id.to_string(),
+            state: Arc::new(state),
+            upstream: None,
+            published: None,
+        };
+        self.documents
+            .write()
+            .insert(document.id.clone(), document.clone());
+        Ok(document)
+    }
+
+    /// Fork a document.
+    ///
+    /// The fork is taken with no edit in flight, gets a fresh replica ID and is
+    /// registered under a new ID. It inherits the upstream's broadcast policy,
+    /// lag limit, awareness time to live, access control list and formatting
+    /// marks, and gets the hooks registered for every document.
+    pub async fn fork(&self, id: &str) -> ServerResult<Document> {
+        let upstream = self.get(id)?;
+        self.admit_document()?;
+        let replica_id = self.allocate_replica_id();
+        let (rga, forked_at_clock) = {
+            let _exclusive = upstream.state.exclusive().await;
+            let rga = &upstream.state.rga;
+            (rga.fork(replica_id), rga.current_clock())
+        };
+
+        let state = DocumentState::new(rga);
+        state.set_broadcast_policy(upstream.state.broadcast_policy());
+        state.set_max_peer_lag(upstream.state.max_peer_lag());
+        state.set_awareness_ttl(upstream.state.awareness_ttl());
+        *state.acl.write() = upstream.state.acl.read().clone();
+        *state.marks.write() = upstream.state.marks.read().clone();
+        let fork_id = format!(
+            "{}-fork-{}",
+            id,
+            self.next_fork.fetch_add(1, Ordering::Relaxed)
+        );
+        self.attach_tenant(&fork_id, &state)?;
+        self.attach_hooks(&fork_id, &state);
+        let fork = Document {
+            id: fork_id,
+            upstream: Some(Upstream {
+                document_id: upstream.id,
+                forked_at_clock,
+            }),
+            state: Arc::new(state),
+            published: None,
+        };
+        self.documents.write().insert(fork.id.clone(), fork.clone());
+        Ok(fork)
+    }
+
+    /// Publish a document at `version`, or at its current version.
+    ///
+    /// The copy holds the document's visible text at that version, without
+    /// tombstones or formatting, and is registered as `{id}-v{version}`.
+    /// Publishing the same version again returns the existing copy. Past
+    /// versions are rebuilt from the document's history, back to the last
+    /// purge of its tombstones.
+    pub async fn publish(&self, id: &str, version: Option<u64>) -> ServerResult<Document> {
+        let source = self.get(id)?;
+        self.admit_document()?;
+        let (text, version) = match version {
+            Some(version) => {
+                let text = source.state.text_at(version).await.ok_or_else(|| {
+                    ServerError::UnknownVersion {
+                        document: id.to_string(),
+                        version,
+                    }
+                })?;
+                (text, version)
+            }
+            None => {
+                let (rga, version) = source.state.versioned_snapshot().await;
+                (rga.to_string(), version)
+            }
+        };
+
+        let published_id = format!("{id}-v{version}");
+        if let Ok(existing) = self.get(&published_id) {
+            return Ok(existing);
+        }
+        let replica_id = self.allocate_replica_id();
+        let rga = RGA::new(replica_id);
+        rga.insert_str_after(rga.sentinel_start_id(), &text)?;
+
+        let state = DocumentState::new(rga);
+        *state.acl.write() = source.state.acl.read().clone();
+        self.attach_tenant(&published_id, &state)?;
+        self.attach_hooks(&published_id, &state);
+        let published = Document {
+            id: published_id.clone(),
+            state: Arc::new(state),
+            upstream: None,
+            published: Some(Published {
+                document_id: source.id,
+                version,
+            }),
+        };
+        Ok(self
+            .documents
+            .write()
+            .entry(published_id)
+            .or_insert(published)
+            .clone())
+    }
+
+    /// The direct forks of a document, in ID order
+    pub fn forks_of(&self, id: &str) -> ServerResult<Vec<Document>> {
+        let documents = self.documents.read();
+        if !documents.contains_key(id) {
+            return Err(ServerError::UnknownDocument(id.to_string()));
+        }
+        Ok(documents
+            .values()
+            .filter(|document| {
+                document
+                    .upstream
+                    .as_ref()
+                    .is_some_and(|upstream| upstream.document_id == id)
+            })
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,209 @@
+//! Erasing a replica's identity from a document.
+//!
+//! `POST /admin/docs/{id}/pseudonymize?replica=<id>` answers a right to
+//! erasure request: every node ID, origin and author naming the replica is
+//! given a pseudonym (see [`crate::crdt::scrub`]), in the live document and,
+//! for the main document, in every segment and checkpoint of the operation
+//! log. The text does not change. The pseudonym sorts where the replica did,
+//! so the document keeps its order.
+//!
+//! Sessions edit by position and never see node IDs, so connected clients
+//! carry on unaffected. Forks and the documents they were forked from refer
+//! to each other's nodes in merges and are not pseudonymized.
+//!
+//! With `OPLOG_AUDIT` set, the audit chain is recomputed over the rewritten
+//! operations and gets a new head; a head recorded earlier no longer matches.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::crdt::{Remap, ReplicaId};
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, DocumentRegistry, MAIN_DOCUMENT};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::DocumentState;
+
+/// Query parameters of the pseudonymize endpoint
+#[derive(Deserialize)]
+pub struct PseudonymizeParams {
+    /// The replica to erase
+    pub replica: ReplicaId,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct PseudonymizeResponse {
+    /// The erased replica
+    pub replica: ReplicaId,
+    /// The replica ID that replaced it
+    pub pseudonym: ReplicaId,
+    /// Number of nodes the replica had inserted
+    pub renamed: usize,
+    /// Number of operation log files rewritten
+    pub rewritten_files: usize,
+}
+
+impl DocumentState {
+    /// Replace `replica` with `pseudonym` in the document and in the node IDs
+    /// the server keeps beside it
+    fn pseudonymize(&self, replica: ReplicaId, pseudonym: ReplicaId) -> ServerResult<Remap> {
+        let remap = self.rga.pseudonymize(replica, pseudonym)?;
+        for deleted in self.deletions.lock().iter_mut() {
+            for id in &mut deleted.ids {
+                *id = remap.after(*id);
+            }
+        }
+        let mut inserted = self.inserted.lock();
+        *inserted = inserted
+            .drain()
+            .map(|(id, version)| (remap.after(id), version))
+            .collect();
+        drop(inserted);
+        self.decorations.lock().rename(&remap);
+        Ok(remap)
+    }
+}
+
+impl DocumentRegistry {
+    /// Give `replica` a pseudonym in document `id` and, for the main
+    /// document, in its operation log
+    pub async fn pseudonymize(
+        &self,
+        id: &str,
+        replica: ReplicaId,
+    ) -> ServerResult<PseudonymizeResponse> {
+        let document = self.get(id)?;
+        self.ensure_unforked(&document)?;
+        let state = document.state;
+        if !state.rga.named_replicas().contains(&replica) {
+            return Err(ServerError::UnknownReplica {
+                document: id.to_string(),
+                replica,
+            });
+        }
+        let pseudonym = state
+            .rga
+            .pseudonym(replica)
+            .ok_or("No free replica ID sorts in the replica's place")?;
+        let log = if id == MAIN_DOCUMENT {
+            self.oplog()
+        } else {
+            None
+        };
+
+        let _exclusive = state.exclusive().await;
+        let document = Arc::clone(&state);
+        // Rewriting the log blocks on file IO and on the persistence task
+        let (remap, rewritten_files) = tokio::task::spawn_blocking(move || {
+            let Some(log) = log else {
+                return Ok((document.pseudonymize(replica, pseudonym)?, 0));
+            };
+            let mut log = log.lock();
+            let remap = document.pseudonymize(replica, pseudonym)?;
+            match log.pseudonymize(replica, pseudonym) {
+                Ok(files) => Ok((remap, files)),
+                Err(e) => {
+                    // Keep the document consistent with the log it is
+                    // persisted to
+                    document.pseudonymize(pseudonym, replica)?;
+                    Err(ServerError::Io(e))
+                }
+            }
+        })
+        .await
+        .map_err(|e| ServerError::Io(std::io::Error::other(e)))??;
+
+        info!(
+            "Pseudonymized a replica of {} in {} nodes and {} log files",
+            id,
+            remap.len(),
+            rewritten_files
+        );
+        Ok(PseudonymizeResponse {
+            replica,
+            pseudonym,
+            renamed: remap.len(),
+            rewritten_files,
+        })
+    }
+}
+
+/// Replace a replica's identity in a document with a pseudonym
+#[utoipa::path(
+    post,
+    path = "/admin/docs/{id}/pseudonymize",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "Document to rewrite"),
+        ("replica" = u64, Query, description = "The replica to erase"),
+    ),
+    responses(
+        (status = 200, description = "Pseudonymized", body = PseudonymizeResponse),
+        (status = 400, description = "No pseudonym can replace the replica"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document, or the document names no such replica"),
+        (status = 409, description = "The document is a fork or has forks"),
+    )
+)]
+pub async fn pseudonymize_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<PseudonymizeParams>,
+    headers: HeaderMap,
+) -> ServerResult<Json<PseudonymizeResponse>> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.pseudonymize(&id, params.replica).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{RGA, SegmentConfig, SegmentedLog};
+    use parking_lot::Mutex;
+
+    #[tokio::test]
+    async fn test_pseudonymizes_document_and_log() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-erasure-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let state = DocumentRegistry::new(RGA::new(1));
+        let main = state.main();
+        let visitor = RGA::new(40);
+        visitor
+            .insert_str_after(visitor.sentinel_start_id(), "hi")
+            .unwrap();
+        for node in visitor.missing_from(&main.rga) {
+            main.rga.apply_remote_op(node);
+        }
+        let mut log = SegmentedLog::open(&dir, SegmentConfig::default()).unwrap();
+        log.append(&main.rga.log_entries_since(&RGA::new(0)))
+            .unwrap();
+        state.set_oplog(Arc::new(Mutex::new(log)));
+
+        assert!(matches!(
+            state.pseudonymize(MAIN_DOCUMENT, 41).await,
+            Err(ServerError::UnknownReplica { .. })
+        ));
+        assert_eq!(
+            state.pseudonymize(MAIN_DOCUMENT, 40).await.unwrap(),
+            PseudonymizeResponse {
+                replica: 40,
+                pseudonym: 1 << 63,
+                renamed: 2,
+                rewritten_files: 1,
+            }
+        );
+        assert_eq!(main.rga.to_string(), "hi");
+        assert!(!main.rga.named_replicas().contains(&40));
+        let restored = state.oplog().unwrap().lock().restore(2).unwrap();
+        assert_eq!(restored.to_snapshot(), main.rga.to_snapshot());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Error type shared by the server's request paths.
+//!
+//! Handlers return [`ServerError`] instead of panicking. Errors caused by a
+//! client's message are reported back to that client as an `error` frame and
+//! the session carries on; only transport failures end a session.
+
+use std::fmt;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+
+use crate::server::acl::Permission;
+
+/// Result type used throughout the server
+pub type ServerResult<T = ()> = Result<T, ServerError>;
+
+/// An error raised while serving a request or a WebSocket session
+#[derive(Debug)]
+pub enum ServerError {
+    /// The client sent a frame that is not a valid operation
+    InvalidMessage(serde_json::Error),
+    /// The client sent an operation type the server does not know
+    UnknownOperation(String),
+    /// An operation is missing a field it requires
+    MissingField {
+        operation: &'static str,
+        field: &'static str,
+    },
+    /// The CRDT rejected the operation
+    Rejected(&'static str),
+    /// A chunked paste operation does not fit its transaction
+    InvalidPaste(&'static str),
+    /// The session's permission does not allow the operation
+    Forbidden {
+        operation: String,
+        permission: Permission,
+    },
+    /// No document with this ID is hosted
+    UnknownDocument(String),
+    /// An admin endpoint was called without the configured admin token
+    Unauthorized,
+    /// Admin endpoints are disabled because no admin token is configured
+    AdminDisabled,
+    /// The document is not a fork
+    NotAFork(String),
+    /// No merge request with this ID exists
+    UnknownMergeRequest(u64),
+    /// The merge request was already merged or rejected
+    MergeRequestClosed(u64),
+    /// An export was requested in a format the server cannot produce
+    UnsupportedFormat(String),
+    /// A filter by author could not be parsed or applied
+    InvalidFilter(String),
+    /// The document does not retain this version
+    UnknownVersion { document: String, version: u64 },
+    /// The document is a published copy and cannot be edited
+    Published(String),
+    /// The document is frozen and cannot be edited until it is unfrozen
+    Frozen,
+    /// A document with this ID is already hosted
+    DocumentExists(String),
+    /// The server is multi-tenant and the request presented no known tenant
+    /// token
+    UnknownTenant,
+    /// The ID is outside the namespace of the requesting tenant
+    OutsideNamespace(String),
+    /// The endpoint spans every tenant's documents and is not served on a
+    /// multi-tenant server
+    TenantScoped(&'static str),
+    /// The tenant reached one of its quotas
+    QuotaExceeded { tenant: String, quota: &'static str },
+    /// The document is a fork or has forks, whose merges may still refer to
+    /// its tombstones and node IDs
+    Forked(String),
+    /// The server does not audit its operation log
+    AuditDisabled,
+    /// The document names no such replica
+    UnknownReplica { document: String, replica: u64 },
+    /// Too many operations wait for the operation log to become writable
+    StorageUnavailable,
+    /// The replica ID is not leased, or under another lease
+    UnknownLease(u64),
+    /// No replication peer is registered under this name
+    UnknownPeer(String),
+    /// A replication peer could not be reached or refused a document
+    PeerFailed { peer: String, reason: String },
+    /// A response could not be serialized
+    Serialization(serde_json::Error),
+    /// The WebSocket failed while sending or receiving
+    Transport(axum::Error),
+    /// Binding the listener or serving connections failed
+    Io(std::io::Error),
+}
+
+impl ServerError {
+    /// Short machine-readable error code sent to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::InvalidMessage(_) => "invalid_message",
+            ServerError::UnknownOperation(_) => "unknown_operation",
+            ServerError::MissingField { .. } => "missing_field",
+            ServerError::Rejected(_) => "rejected",
+            ServerError::InvalidPaste(_) => "invalid_paste",
+            ServerError::Forbidden { .. } => "forbidden",
+            ServerError::UnknownDocument(_) => "unknown_document",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::AdminDisabled => "admin_disabled",
+            ServerError::NotAFork(_) => "not_a_fork",
+            ServerError::UnknownMergeRequest(_) => "unknown_merge_request",
+            ServerError::MergeRequestClosed(_) => "merge_request_closed",
+            ServerError::UnsupportedFormat(_) => "unsupported_format",
+            ServerError::InvalidFilter(_) => "invalid_filter",
+            ServerError::UnknownVersion { .. } => "unknown_version",
+            ServerError::Published(_) => "published",
+            ServerError::Frozen => "frozen",
+            ServerError::DocumentExists(_) => "document_exists",
+            ServerError::UnknownTenant => "unknown_tenant",
+            ServerError::OutsideNamespace(_) => "outside_namespace",
+            ServerError::TenantScoped(_) => "tenant_scoped",
+            ServerError::QuotaExceeded { .. } => "quota_exceeded",
+            ServerError::Forked(_) => "forked",
+            ServerError::AuditDisabled => "audit_disabled",
+            ServerError::UnknownReplica { .. } => "unknown_replica",
+            ServerError::UnknownLease(_) => "unknown_lease",
+            ServerError::UnknownPeer(_) => "unknown_peer",
+            ServerError::PeerFailed { .. } => "peer_failed",
+            ServerError::StorageUnavailable => "storage_unavailable",
+            ServerError::Serialization(_) => "serialization",
+            ServerError::Transport(_) => "transport",
+            ServerError::Io(_) => "io",
+        }
+    }
+
+    /// Whether the session cannot continue after this error.
+    ///
+    /// Errors caused by a single bad frame are not fatal.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ServerError::Transport(_) | ServerError::Io(_))
+    }
+
+    /// HTTP status used when the error answers a REST request
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServerError::UnknownDocument(_)
+            | ServerError::UnknownMergeRequest(_)
+            | ServerError::UnknownVersion { .. }
+            | ServerError::AuditDisabled
+            | ServerError::UnknownReplica { .. }
+            | ServerError::UnknownLease(_)
+            | ServerError::UnknownPeer(_) => StatusCode::NOT_FOUND,
+            ServerError::NotAFork(_)
+            | ServerError::MergeRequestClosed(_)
+            | ServerError::Published(_)
+            | ServerError::Frozen
+            | ServerError::DocumentExists(_)
+            | ServerError::Forked(_) => StatusCode::CONFLICT,
+            ServerError::Forbidden { .. }
+            | ServerError::AdminDisabled
+            | ServerError::OutsideNamespace(_)
+            | ServerError::TenantScoped(_) => StatusCode::FORBIDDEN,
+            ServerError::Unauthorized | ServerError::UnknownTenant => StatusCode::UNAUTHORIZED,
+            ServerError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::StorageUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::PeerFailed { .. } => StatusCode::BAD_GATEWAY,
+            ServerError::InvalidMessage(_)
+            | ServerError::UnknownOperation(_)
+            | ServerError::MissingField { .. }
+            | ServerError::Rejected(_)
+            | ServerError::InvalidPaste(_)
+            | ServerError::UnsupportedFormat(_)
+            | ServerError::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+            ServerError::Serialization(_) | ServerError::Transport(_) | ServerError::Io(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::InvalidMessage(e) => write!(f, "invalid message: {e}"),
+            ServerError::UnknownOperation(op) => write!(f, "unknown operation type '{op}'"),
+            ServerError::MissingField { operation, field } => {
+                write!(f, "{operation} operation is missing '{field}'")
+            }
+            ServerError::Rejected(reason) => write!(f, "operation rejected: {reason}"),
+            ServerError::InvalidPaste(reason) => write!(f, "invalid paste: {reason}"),
+            ServerError::Forbidden {
+                operation,
+                permission,
+            } => write!(f, "{permission} access does not allow '{operation}'"),
+            ServerError::UnknownDocument(id) => write!(f, "unknown document '{id}'"),
+            ServerError::Unauthorized => write!(f, "missing or invalid admin token"),
+            ServerError::AdminDisabled => {
+                write!(
+                    f,
+                    "admin endpoints are disabled; set ADMIN_TOKEN to enable them"
+                )
+            }
+            ServerError::NotAFork(id) => write!(f, "document '{id}' is not a fork"),
+            ServerError::UnknownMergeRequest(id) => write!(f, "unknown merge request {id}"),
+            ServerError::MergeRequestClosed(id) => {
+                write!(f, "merge request {id} is no longer open")
+            }
+            ServerError::UnsupportedFormat(format) => {
+                write!(f, "unsupported export format '{format}'")
+            }
+            ServerError::InvalidFilter(reason) => write!(f, "invalid filter: {reason}"),
+            ServerError::UnknownVersion { document, version } => {
+                write!(f, "document '{document}' does not retain version {version}")
+            }
+            ServerError::Published(id) => {
+                write!(f, "document '{id}' is published and cannot be edited")
+            }
+            ServerError::Frozen => write!(f, "the document is frozen and cannot be edited"),
+            ServerError::DocumentExists(id) => write!(f, "document '{id}' already exists"),
+            ServerError::UnknownTenant => write!(f, "missing or unknown tenant token"),
+            ServerError::OutsideNamespace(id) => {
+                write!(f, "document '{id}' is outside the tenant's namespace")
+            }
+            ServerError::TenantScoped(endpoint) => {
+                write!(f, "{endpoint} is not available on a multi-tenant server")
+            }
+            ServerError::QuotaExceeded { tenant, quota } => {
+                write!(f, "tenant '{tenant}' exceeded its {quota} quota")
+            }
+            ServerError::Forked(id) => write!(
+                f,
+                "document '{id}' is forked or a fork, so a merge may still refer to its nodes"
+            ),
+            ServerError::AuditDisabled => write!(
+                f,
+                "the operation log is not audited; set OPLOG_DIR and OPLOG_AUDIT to audit it"
+            ),
+            ServerError::UnknownReplica { document, replica } => {
+                write!(f, "document '{document}' names no replica {replica}")
+            }
+            ServerError::StorageUnavailable => write!(
+                f,
+                "storage is unavailable and too many operations wait to be persisted"
+            ),
+            ServerError::UnknownLease(replica) => {
+                write!(f, "replica {replica} is not leased under this lease")
+            }
+            ServerError::UnknownPeer(name) => write!(f, "unknown replication peer '{name}'"),
+            ServerError::PeerFailed { peer, reason } => {
+                write!(f, "replication to peer '{peer}' failed: {reason}")
+            }
+            ServerError::Serialization(e) => write!(f, "failed to serialize response: {e}"),
+            ServerError::Transport(e) => write!(f, "websocket error: {e}"),
+            ServerError::Io(e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::InvalidMessage(e) | ServerError::Serialization(e) => Some(e),
+            ServerError::Transport(e) => Some(e),
+            ServerError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// REST handlers answer errors with `{"error": code, "message": description}`
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<axum::Error> for ServerError {
+    fn from(e: axum::Error) -> Self {
+        ServerError::Transport(e)
+    }
+}
+
+impl From<std::io::Error> for ServerError {
+    fn from(e: std::io::Error) -> Self {
+        ServerError::Io(e)
+    }
+}
+
+impl From<&'static str> for ServerError {
+    fn from(reason: &'static str) -> Self {
+        ServerError::Rejected(reason)
+    }
+}
@@ -0,0 +1,144 @@
+//! REST endpoints for exporting and importing formatted documents.
+//!
+//! `GET /docs/{id}/export?format=...` renders a document's text together with
+//! its formatting marks as plain text, Markdown or HTML, or writes a snapshot
+//! for `crdt-rga-diff`. `POST /docs/{id}/import` appends a Markdown body to
+//! the document, storing its inline formatting as marks, and broadcasts the
+//! new content to connected sessions.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::crdt::ProvenanceFilter;
+use crate::server::documents::AppState;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::history::ProvenanceParams;
+use crate::server::tenants::TenantScope;
+use crate::server::websocket::{PeerMessage, RGAResponse};
+
+/// Query parameters of the export endpoint
+#[derive(Deserialize)]
+pub struct ExportParams {
+    /// `text` (default), `markdown`, `html` or `snapshot`
+    pub format: Option<String>,
+    /// Authors whose text to keep or drop, for the `text` format
+    #[serde(flatten)]
+    pub provenance: ProvenanceParams,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+    /// Number of characters inserted
+    pub characters: usize,
+    /// Number of formatting marks added
+    pub marks: usize,
+}
+
+/// Export a document
+#[utoipa::path(
+    get,
+    path = "/docs/{id}/export",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document to export"),
+        ("format" = Option<String>, Query, description = "`text` (default), `markdown`, `html` or `snapshot`"),
+        ("only" = Option<String>, Query, description = "Comma-separated replicas whose text to keep, for `text`"),
+        ("mute" = Option<String>, Query, description = "Comma-separated replicas whose text to drop, for `text`"),
+    ),
+    responses(
+        (status = 200, description = "The rendered document", body = String),
+        (status = 400, description = "Unsupported format or invalid filter"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn export_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+    Query(params): Query<ExportParams>,
+) -> ServerResult<Response> {
+    scope.check(&id)?;
+    let filter = params.provenance.filter()?;
+    let document = state.get(&id)?;
+    let rga = &document.state.rga;
+    let marks = document.state.marks.read();
+
+    let format = params.format.as_deref().unwrap_or("text");
+    if filter != ProvenanceFilter::All && format != "text" {
+        // Marks and snapshots cannot leave characters out
+        return Err(ServerError::InvalidFilter(format!(
+            "the {format} format cannot be filtered by author"
+        )));
+    }
+    let (content_type, body) = match format {
+        "text" => ("text/plain; charset=utf-8", rga.text_by(&filter)),
+        "markdown" => ("text/markdown; charset=utf-8", rga.to_markdown(&marks)),
+        "html" => ("text/html; charset=utf-8", rga.to_html(&marks)),
+        "snapshot" => ("text/plain; charset=utf-8", rga.to_snapshot()),
+        other => return Err(ServerError::UnsupportedFormat(other.to_string())),
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// Append Markdown to a document
+#[utoipa::path(
+    post,
+    path = "/docs/{id}/import",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document to append to")),
+    request_body(content = String, content_type = "text/markdown"),
+    responses(
+        (status = 200, description = "Imported", body = ImportResponse),
+        (status = 404, description = "No such document"),
+        (status = 409, description = "The document is published"),
+    )
+)]
+pub async fn import_markdown(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+    markdown: String,
+) -> ServerResult<Json<ImportResponse>> {
+    scope.check(&id)?;
+    let document = state.get(&id)?;
+    document.ensure_editable()?;
+    document.state.admit_edit(true)?;
+    let (imported, content, version) = {
+        let _edit = document.state.begin_edit().await;
+        let rga = &document.state.rga;
+        let before = rga.visible_node_count();
+        let after_id = rga
+            .visible_nodes()
+            .last()
+            .map_or_else(|| rga.sentinel_start_id(), |node| node.id);
+
+        let marks = rga.import_markdown(after_id, &markdown)?;
+        let imported = ImportResponse {
+            characters: rga.visible_node_count() - before,
+            marks: marks.len(),
+        };
+        document.state.marks.write().extend(marks);
+        (imported, rga.to_string(), document.state.record_edit())
+    };
+
+    document.state.publish(PeerMessage {
+        origin: "import".to_string(),
+        response: RGAResponse {
+            response_type: "update".to_string(),
+            content,
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        },
+    });
+    Ok(Json(imported))
+}
@@ -0,0 +1,133 @@
+//! REST endpoints for forking documents.
+//!
+//! `POST /docs/{id}/fork` copies a document into a new one that shares its
+//! history up to that point. `GET /docs/{id}/forks` lists a document's forks
+//! and `GET /docs/{id}/divergence` reports how far a fork and its upstream
+//! have drifted apart. Forks are edited over `GET /docs/{id}/ws`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::crdt::ReplicaId;
+use crate::server::documents::{AppState, Document};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::tenants::TenantScope;
+
+#[derive(Serialize, ToSchema)]
+pub struct ForkInfo {
+    /// ID of the fork
+    pub id: String,
+    /// ID of the document it was forked from
+    pub upstream: String,
+    /// Replica ID the fork is edited as
+    pub replica_id: ReplicaId,
+    /// The upstream's Lamport counter when the fork was taken
+    pub forked_at_clock: u64,
+}
+
+impl ForkInfo {
+    fn from_document(document: &Document) -> ServerResult<Self> {
+        let upstream = document
+            .upstream
+            .as_ref()
+            .ok_or_else(|| ServerError::NotAFork(document.id.clone()))?;
+        Ok(ForkInfo {
+            id: document.id.clone(),
+            upstream: upstream.document_id.clone(),
+            replica_id: document.state.rga.replica_id(),
+            forked_at_clock: upstream.forked_at_clock,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DivergenceResponse {
+    pub id: String,
+    pub upstream: String,
+    /// Operations the fork has that upstream lacks
+    pub ahead: usize,
+    /// Operations upstream has that the fork lacks
+    pub behind: usize,
+}
+
+/// Fork a document
+#[utoipa::path(
+    post,
+    path = "/docs/{id}/fork",
+    tag = "documents",
+    params(("id" = String, Path, description = "Document to fork")),
+    responses(
+        (status = 201, description = "Fork created", body = ForkInfo),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn fork_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+) -> ServerResult<(StatusCode, Json<ForkInfo>)> {
+    scope.check(&id)?;
+    let fork = state.fork(&id).await?;
+    Ok((StatusCode::CREATED, Json(ForkInfo::from_document(&fork)?)))
+}
+
+/// List the forks of a document
+#[utoipa::path(
+    get,
+    path = "/docs/{id}/forks",
+    tag = "documents",
+    params(("id" = String, Path, description = "Upstream document")),
+    responses(
+        (status = 200, description = "Direct forks of the document", body = [ForkInfo]),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn list_forks(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+) -> ServerResult<Json<Vec<ForkInfo>>> {
+    scope.check(&id)?;
+    let forks = state
+        .forks_of(&id)?
+        .iter()
+        .map(ForkInfo::from_document)
+        .collect::<ServerResult<_>>()?;
+    Ok(Json(forks))
+}
+
+/// Compare a fork with its upstream
+#[utoipa::path(
+    get,
+    path = "/docs/{id}/divergence",
+    tag = "documents",
+    params(("id" = String, Path, description = "A forked document")),
+    responses(
+        (status = 200, description = "How far the fork and its upstream have drifted", body = DivergenceResponse),
+        (status = 404, description = "No such document"),
+        (status = 409, description = "The document is not a fork"),
+    )
+)]
+pub async fn divergence(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+) -> ServerResult<Json<DivergenceResponse>> {
+    scope.check(&id)?;
+    let fork = state.get(&id)?;
+    let info = ForkInfo::from_document(&fork)?;
+    let upstream = state.get(&info.upstream)?;
+
+    let divergence = fork.state.rga.divergence_from(&upstream.state.rga);
+    Ok(Json(DivergenceResponse {
+        id: info.id,
+        upstream: info.upstream,
+        ahead: divergence.ahead.len(),
+        behind: divergence.behind.len(),
+    }))
+}
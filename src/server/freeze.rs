@@ -0,0 +1,170 @@
+//! Freezing documents.
+//!
+//! `POST /admin/docs/{id}/freeze` makes a document read-only until `POST
+//! /admin/docs/{id}/unfreeze`. The frozen state is kept by the document's RGA
+//! (see [`crate::crdt::freeze`]), so local edits are refused by the library as
+//! well; the server additionally refuses edit operations from sessions, bot
+//! edits, imports and merges into the document with a `frozen` error, and
+//! tells the document's sessions with a `frozen` or `unfrozen` message.
+//! Comments, presence and decorations are still shared.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::Json,
+};
+use serde::Serialize;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::{DocumentState, PeerMessage, RGAResponse};
+
+impl DocumentState {
+    /// Fail if the document is frozen
+    pub fn ensure_unfrozen(&self) -> ServerResult {
+        if self.rga.is_frozen() {
+            Err(ServerError::Frozen)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Freeze or unfreeze the document and tell its sessions
+    pub async fn set_frozen(self: &Arc<Self>, frozen: bool) {
+        {
+            // Edits in flight finish before the document is frozen
+            let _exclusive = self.exclusive().await;
+            if frozen {
+                self.rga.freeze();
+            } else {
+                self.rga.unfreeze();
+            }
+        }
+        self.publish(PeerMessage {
+            origin: "admin".to_string(),
+            response: RGAResponse {
+                response_type: if frozen { "frozen" } else { "unfrozen" }.to_string(),
+                content: String::new(),
+                position: None,
+                session_id: None,
+                decorations: None,
+                version: None,
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+    }
+}
+
+impl DocumentRegistry {
+    /// Freeze or unfreeze a document
+    pub async fn set_frozen(&self, id: &str, frozen: bool) -> ServerResult {
+        let document = self.get(id)?;
+        if document.state.rga.is_frozen() != frozen {
+            document.state.set_frozen(frozen).await;
+            info!(
+                "{} {}",
+                if frozen { "Froze" } else { "Unfroze" },
+                document.id
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FreezeResponse {
+    pub id: String,
+    /// Whether the document now refuses edits
+    pub frozen: bool,
+}
+
+/// Make a document read-only
+#[utoipa::path(
+    post,
+    path = "/admin/docs/{id}/freeze",
+    tag = "admin",
+    params(("id" = String, Path, description = "Document to freeze")),
+    responses(
+        (status = 200, description = "Frozen", body = FreezeResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn freeze_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> ServerResult<Json<FreezeResponse>> {
+    authorize(&state, &headers)?;
+    state.set_frozen(&id, true).await?;
+    Ok(Json(FreezeResponse { id, frozen: true }))
+}
+
+/// Allow edits of a frozen document again
+#[utoipa::path(
+    post,
+    path = "/admin/docs/{id}/unfreeze",
+    tag = "admin",
+    params(("id" = String, Path, description = "Document to unfreeze")),
+    responses(
+        (status = 200, description = "Unfrozen", body = FreezeResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn unfreeze_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> ServerResult<Json<FreezeResponse>> {
+    authorize(&state, &headers)?;
+    state.set_frozen(&id, false).await?;
+    Ok(Json(FreezeResponse { id, frozen: false }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::RGA;
+    use crate::server::documents::MAIN_DOCUMENT;
+    use crate::server::websocket::insert_text;
+
+    #[tokio::test]
+    async fn test_frozen_documents_refuse_edits() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let main = state.main();
+        let mut peers = main.peers.subscribe();
+        let mut bot = state.attach_bot(MAIN_DOCUMENT, "typist").unwrap();
+        bot.insert(0, "draft").await.unwrap();
+
+        state.set_frozen(MAIN_DOCUMENT, true).await.unwrap();
+        assert!(matches!(main.ensure_unfrozen(), Err(ServerError::Frozen)));
+        assert!(matches!(
+            state.get(MAIN_DOCUMENT).unwrap().ensure_editable(),
+            Err(ServerError::Frozen)
+        ));
+        assert!(insert_text(&main.rga, 0, "x").is_err());
+        assert!(matches!(bot.insert(0, "x").await, Err(ServerError::Frozen)));
+        assert!(state.attach_bot(MAIN_DOCUMENT, "late").is_err());
+        assert_eq!(main.rga.to_string(), "draft");
+
+        state.set_frozen(MAIN_DOCUMENT, false).await.unwrap();
+        bot.insert(5, "!").await.unwrap();
+        assert_eq!(main.rga.to_string(), "draft!");
+
+        let types: Vec<String> = std::iter::from_fn(|| peers.try_recv().ok())
+            .flat_map(|batch| batch.iter().cloned().collect::<Vec<_>>())
+            .map(|message| message.response.response_type)
+            .collect();
+        assert_eq!(types, ["update", "frozen", "unfrozen", "update"]);
+    }
+}
@@ -0,0 +1,211 @@
+//! GraphQL endpoint for frontends standardized on GraphQL.
+//!
+//! Queries expose the document content and metadata; the `changes`
+//! subscription streams the same change events WebSocket sessions receive.
+//! `POST /graphql` executes queries, `GET /graphql` serves GraphQL Playground,
+//! and `GET /graphql/ws` accepts subscriptions over the `graphql-transport-ws`
+//! and `graphql-ws` protocols. GraphQL spans every document, so it is refused
+//! on a multi-tenant server.
+
+use std::str::FromStr;
+
+use async_graphql::http::{
+    ALL_WEBSOCKET_PROTOCOLS, GraphQLPlaygroundConfig, WebSocket as GraphQLWebSocket,
+    WebSocketProtocols, WsMessage, playground_source,
+};
+use async_graphql::{Context, Data, EmptyMutation, Object, Schema, SimpleObject, Subscription};
+use axum::{
+    Extension, Router,
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocketUpgrade},
+    },
+    response::{Html, IntoResponse, Json, Response},
+    routing::get,
+};
+use futures_util::{SinkExt, Stream, StreamExt, future, stream};
+use tokio::sync::broadcast;
+
+use crate::crdt::ReplicaId;
+use crate::server::documents::AppState;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::PeerMessage;
+
+/// The server's GraphQL schema
+pub type GraphQLSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Build the GraphQL schema. Resolvers read the [`AppState`] from request data.
+pub fn build_schema() -> GraphQLSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot).finish()
+}
+
+/// Metadata about the document
+#[derive(SimpleObject)]
+pub struct DocumentInfo {
+    /// The replica ID this server edits the document as
+    pub replica_id: ReplicaId,
+    /// Current Lamport counter of the document's clock
+    pub clock: u64,
+    /// Number of visible characters
+    pub length: usize,
+    /// Nodes held by the document, including tombstones and sentinels
+    pub total_nodes: usize,
+    /// Sessions connected over the editing WebSocket
+    pub connected_sessions: usize,
+}
+
+/// A change made to the document by a WebSocket session
+#[derive(SimpleObject, Clone)]
+pub struct ChangeEvent {
+    /// The message type, e.g. `update` or `composition`
+    pub kind: String,
+    /// The content carried by the change (the whole document for `update`)
+    pub content: String,
+    pub position: Option<usize>,
+    /// The session the change originated from
+    pub session_id: String,
+}
+
+impl From<PeerMessage> for ChangeEvent {
+    fn from(message: PeerMessage) -> Self {
+        ChangeEvent {
+            kind: message.response.response_type,
+            content: message.response.content,
+            position: message.response.position,
+            session_id: message.origin,
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The visible text of the document
+    async fn content(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(ctx.data::<AppState>()?.main().rga.to_string())
+    }
+
+    /// Metadata about the document
+    async fn document(&self, ctx: &Context<'_>) -> async_graphql::Result<DocumentInfo> {
+        let state = ctx.data::<AppState>()?.main();
+        let txn = state.rga.read_txn();
+        Ok(DocumentInfo {
+            replica_id: state.rga.replica_id(),
+            clock: state.rga.current_clock(),
+            length: txn.len(),
+            total_nodes: txn.total_node_count(),
+            connected_sessions: state.peers.receiver_count(),
+        })
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Every change made to the document from now on, optionally only those
+    /// of some sessions or all but those of some sessions
+    async fn changes(
+        &self,
+        ctx: &Context<'_>,
+        only: Option<Vec<String>>,
+        mute: Option<Vec<String>>,
+    ) -> async_graphql::Result<impl Stream<Item = ChangeEvent>> {
+        let receiver = ctx.data::<AppState>()?.main().peers.subscribe();
+        let batches = stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(batch) => return Some((batch, receiver)),
+                    // A slow subscriber misses events rather than ending the stream
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        let shown = move |session: &String| {
+            only.as_ref().is_none_or(|only| only.contains(session))
+                && mute.as_ref().is_none_or(|mute| !mute.contains(session))
+        };
+        Ok(batches
+            .flat_map(|batch| {
+                stream::iter((0..batch.len()).map(move |i| ChangeEvent::from(batch[i].clone())))
+            })
+            .filter(move |event| std::future::ready(shown(&event.session_id))))
+    }
+}
+
+/// Execute a GraphQL query
+pub async fn graphql_handler(
+    State(state): State<AppState>,
+    Extension(schema): Extension<GraphQLSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> ServerResult<Json<async_graphql::Response>> {
+    if state.is_multi_tenant() {
+        return Err(ServerError::TenantScoped("graphql"));
+    }
+    Ok(Json(schema.execute(request.data(state)).await))
+}
+
+/// Serve GraphQL Playground
+pub async fn graphql_playground() -> Html<String> {
+    Html(playground_source(
+        GraphQLPlaygroundConfig::new("/graphql").subscription_endpoint("/graphql/ws"),
+    ))
+}
+
+/// Accept a GraphQL subscription connection
+pub async fn graphql_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Extension(schema): Extension<GraphQLSchema>,
+) -> Response {
+    if state.is_multi_tenant() {
+        return ServerError::TenantScoped("graphql").into_response();
+    }
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| async move {
+            let protocol = socket
+                .protocol()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| WebSocketProtocols::from_str(value).ok())
+                .unwrap_or(WebSocketProtocols::SubscriptionsTransportWS);
+
+            let (mut sink, source) = socket.split();
+            let input = source
+                .take_while(|message| future::ready(message.is_ok()))
+                .filter_map(|message| {
+                    future::ready(match message {
+                        Ok(Message::Text(text)) => Some(text.into_bytes()),
+                        Ok(Message::Binary(bytes)) => Some(bytes),
+                        _ => None,
+                    })
+                });
+
+            let mut data = Data::default();
+            data.insert(state);
+            let mut output = GraphQLWebSocket::new(schema, input, protocol).connection_data(data);
+
+            while let Some(message) = output.next().await {
+                let message = match message {
+                    WsMessage::Text(text) => Message::Text(text),
+                    WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                        code,
+                        reason: reason.into(),
+                    })),
+                };
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+        })
+        .into_response()
+}
+
+/// Routes for the GraphQL endpoint
+pub fn graphql_routes() -> Router<AppState> {
+    Router::new()
+        .route("/graphql", get(graphql_playground).post(graphql_handler))
+        .route("/graphql/ws", get(graphql_ws_handler))
+        .layer(Extension(build_schema()))
+}
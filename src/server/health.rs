@@ -0,0 +1,221 @@
+//! Liveness and readiness probes.
+//!
+//! `/healthz` answers as long as the process can serve requests, and is meant
+//! for liveness probes. `/readyz` additionally reports whether the server should
+//! receive traffic: it answers `503 Service Unavailable` while storage is
+//! unreachable or the host is under memory pressure, so load balancers and
+//! Kubernetes readiness probes can route around it.
+//!
+//! While the operation log cannot be written the server is degraded (see
+//! [`crate::server::persistence`]): `/healthz` still answers `200`, as
+//! restarting would lose the operations queued in memory, but reports
+//! `degraded`, and `/readyz` reports the storage disconnected.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::crdt::{ReplicaId, SyncMetrics};
+use crate::server::documents::AppState;
+
+/// Fraction of total memory below which available memory counts as pressure
+const MEMORY_PRESSURE_AVAILABLE_RATIO: f64 = 0.1;
+
+#[derive(Serialize, ToSchema)]
+pub struct LivenessResponse {
+    /// `ok`, or `degraded` while the operation log cannot be written
+    pub status: &'static str,
+    pub replica_id: ReplicaId,
+    pub uptime_seconds: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    pub status: &'static str,
+    pub replica_id: ReplicaId,
+    pub storage: StorageStatus,
+    pub open_documents: usize,
+    pub connected_sessions: usize,
+    pub memory: MemoryStatus,
+    /// Remote operations handled by all documents
+    pub operations: OperationCounts,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StorageStatus {
+    /// Where documents are kept
+    pub backend: &'static str,
+    pub connected: bool,
+    /// Operations queued in memory until the log can be written
+    pub pending_operations: usize,
+    /// Why the log cannot be written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl StorageStatus {
+    /// The storage of the main document
+    fn of(state: &AppState) -> Self {
+        let storage = &state.main().storage;
+        StorageStatus {
+            backend: if state.oplog().is_some() {
+                "oplog"
+            } else {
+                "memory"
+            },
+            connected: !storage.is_degraded(),
+            pending_operations: storage.pending(),
+            error: storage.last_error(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MemoryStatus {
+    /// Resident set size of the process, where the platform reports it
+    pub resident_bytes: Option<u64>,
+    /// Memory the host can still hand out, where the platform reports it
+    pub available_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    /// Nodes held by all documents, including tombstones and sentinels
+    pub document_nodes: usize,
+    pub tombstones: usize,
+    pub under_pressure: bool,
+}
+
+/// Remote operations by outcome, see [`crate::crdt::SyncMetrics`]
+#[derive(Serialize, ToSchema)]
+pub struct OperationCounts {
+    pub applied: u64,
+    pub deduplicated: u64,
+    /// Held back until the node they depend on arrived
+    pub buffered: u64,
+    pub rejected: u64,
+}
+
+impl From<SyncMetrics> for OperationCounts {
+    fn from(metrics: SyncMetrics) -> Self {
+        OperationCounts {
+            applied: metrics.applied,
+            deduplicated: metrics.deduplicated,
+            buffered: metrics.buffered,
+            rejected: metrics.rejected,
+        }
+    }
+}
+
+/// Liveness probe
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is serving requests", body = LivenessResponse))
+)]
+pub async fn healthz(State(state): State<AppState>) -> Json<LivenessResponse> {
+    Json(LivenessResponse {
+        status: if state.main().storage.is_degraded() {
+            "degraded"
+        } else {
+            "ok"
+        },
+        replica_id: state.main().rga.replica_id(),
+        uptime_seconds: state.main().opened_at.elapsed().as_secs(),
+    })
+}
+
+/// Readiness probe
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready to receive traffic", body = ReadinessResponse),
+        (status = 503, description = "Storage unreachable or under memory pressure", body = ReadinessResponse),
+    )
+)]
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let storage = StorageStatus::of(&state);
+
+    let (total_bytes, available_bytes) = host_memory();
+    let under_pressure = match (total_bytes, available_bytes) {
+        (Some(total), Some(available)) => {
+            (available as f64) < total as f64 * MEMORY_PRESSURE_AVAILABLE_RATIO
+        }
+        _ => false,
+    };
+    let documents = state.documents();
+    let document_nodes = documents
+        .iter()
+        .map(|document| document.state.rga.total_node_count())
+        .sum();
+    let visible_nodes: usize = documents
+        .iter()
+        .map(|document| document.state.rga.visible_node_count())
+        .sum();
+    let memory = MemoryStatus {
+        resident_bytes: resident_bytes(),
+        available_bytes,
+        total_bytes,
+        document_nodes,
+        // Each document has two sentinels, never deleted and never visible.
+        // Saturate, since edits may land between the counts
+        tombstones: document_nodes.saturating_sub(visible_nodes + 2 * documents.len()),
+        under_pressure,
+    };
+
+    let ready = storage.connected && !memory.under_pressure;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let response = ReadinessResponse {
+        status: if ready { "ready" } else { "not_ready" },
+        replica_id: state.main().rga.replica_id(),
+        storage,
+        open_documents: documents.len(),
+        connected_sessions: documents
+            .iter()
+            .map(|document| document.state.peers.receiver_count())
+            .sum(),
+        memory,
+        operations: documents
+            .iter()
+            .map(|document| document.state.rga.sync_metrics())
+            .fold(SyncMetrics::default(), |total, metrics| total + metrics)
+            .into(),
+    };
+    (status, Json(response))
+}
+
+/// Resident set size of this process, read from `/proc/self/statm` on Linux
+///
+/// Assumes 4 KiB pages, which holds on the x86-64 and most arm64 hosts we run on.
+fn resident_bytes() -> Option<u64> {
+    const PAGE_SIZE: u64 = 4096;
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * PAGE_SIZE)
+}
+
+/// Total and available host memory, read from `/proc/meminfo` on Linux
+fn host_memory() -> (Option<u64>, Option<u64>) {
+    let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") else {
+        return (None, None);
+    };
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|rest| {
+                rest.trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .map(|kib| kib * 1024)
+    };
+    (field("MemTotal:"), field("MemAvailable:"))
+}
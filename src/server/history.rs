@@ -0,0 +1,175 @@
+//! Document history, filtered by author and by time.
+//!
+//! `GET /docs/{id}/history` returns the operations that built a document as
+//! an operation log, the format `crdt-rga-diff --log` reads. `?only=1,2` keeps
+//! the operations of the listed replicas, `?mute=3` drops them. The export
+//! endpoint accepts the same parameters for its `text` format.
+//!
+//! Documents whose clock is a `HybridClock` also take `?since=` and
+//! `?until=`, in milliseconds since the Unix epoch, to keep the operations on
+//! characters inserted in that window, see `RGA::edits_between`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::crdt::{LogEntry, ProvenanceFilter, RGA, ReplicaId, encode_op_log};
+use crate::server::documents::AppState;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::tenants::TenantScope;
+
+/// Query parameters selecting operations by author
+#[derive(Deserialize, Default)]
+pub struct ProvenanceParams {
+    /// Comma-separated replicas whose operations to keep
+    pub only: Option<String>,
+    /// Comma-separated replicas whose operations to drop
+    pub mute: Option<String>,
+}
+
+impl ProvenanceParams {
+    /// The filter described by the parameters
+    pub fn filter(&self) -> ServerResult<ProvenanceFilter> {
+        match (&self.only, &self.mute) {
+            (None, None) => Ok(ProvenanceFilter::All),
+            (Some(only), None) => Ok(ProvenanceFilter::only(parse_replicas(only)?)),
+            (None, Some(mute)) => Ok(ProvenanceFilter::mute(parse_replicas(mute)?)),
+            (Some(_), Some(_)) => Err(ServerError::InvalidFilter(
+                "'only' and 'mute' cannot be combined".to_string(),
+            )),
+        }
+    }
+}
+
+/// Query parameters selecting operations by wall time
+#[derive(Deserialize, Default)]
+pub struct TimeParams {
+    /// Keep characters inserted at or after this time
+    pub since: Option<u64>,
+    /// Keep characters inserted before this time
+    pub until: Option<u64>,
+}
+
+impl TimeParams {
+    /// The operations of `rga` kept by `filter` and by the parameters
+    pub fn history(&self, rga: &RGA, filter: &ProvenanceFilter) -> ServerResult<Vec<LogEntry>> {
+        if self.since.is_none() && self.until.is_none() {
+            return Ok(rga.history(filter));
+        }
+        if !rga.dates_edits() {
+            return Err(ServerError::InvalidFilter(
+                "the document's clock does not date its edits".to_string(),
+            ));
+        }
+        let (since, until) = (self.since.unwrap_or(0), self.until.unwrap_or(u64::MAX));
+        Ok(rga.history_between(filter, since, until))
+    }
+}
+
+fn parse_replicas(list: &str) -> ServerResult<Vec<ReplicaId>> {
+    list.split(',')
+        .map(|replica| {
+            replica
+                .trim()
+                .parse()
+                .map_err(|_| ServerError::InvalidFilter(format!("invalid replica '{replica}'")))
+        })
+        .collect()
+}
+
+/// Get a document's operations
+#[utoipa::path(
+    get,
+    path = "/docs/{id}/history",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document whose history to list"),
+        ("only" = Option<String>, Query, description = "Comma-separated replicas whose operations to keep"),
+        ("mute" = Option<String>, Query, description = "Comma-separated replicas whose operations to drop"),
+        ("since" = Option<u64>, Query, description = "Keep characters inserted at or after this time, in milliseconds since the Unix epoch"),
+        ("until" = Option<u64>, Query, description = "Keep characters inserted before this time, in milliseconds since the Unix epoch"),
+    ),
+    responses(
+        (status = 200, description = "The operations, as an operation log", body = String),
+        (status = 400, description = "Invalid filter, or a time window on a document that does not date its edits"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn document_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+    Query(params): Query<ProvenanceParams>,
+    Query(time): Query<TimeParams>,
+) -> ServerResult<Response> {
+    scope.check(&id)?;
+    let filter = params.filter()?;
+    let document = state.get(&id)?;
+    let history = time.history(&document.state.rga, &filter)?;
+    let log = encode_op_log(&history);
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], log).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(only: Option<&str>, mute: Option<&str>) -> ProvenanceParams {
+        ProvenanceParams {
+            only: only.map(str::to_string),
+            mute: mute.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parses_filters() {
+        assert_eq!(params(None, None).filter().unwrap(), ProvenanceFilter::All);
+        assert_eq!(
+            params(Some("1, 2"), None).filter().unwrap(),
+            ProvenanceFilter::only([1, 2])
+        );
+        assert_eq!(
+            params(None, Some("7")).filter().unwrap(),
+            ProvenanceFilter::mute([7])
+        );
+        assert!(params(Some("bot"), None).filter().is_err());
+        assert!(params(Some("1"), Some("2")).filter().is_err());
+    }
+
+    #[test]
+    fn test_filters_by_time() {
+        use crate::crdt::HybridClock;
+
+        let rga = RGA::with_clock(HybridClock::with_time_source(1, || 5_000));
+        rga.insert_str_after(rga.sentinel_start_id(), "hi").unwrap();
+        let time = |since, until| TimeParams { since, until };
+        let all = ProvenanceFilter::All;
+        assert_eq!(
+            time(Some(5_000), None).history(&rga, &all).unwrap().len(),
+            2
+        );
+        assert!(
+            time(Some(6_000), None)
+                .history(&rga, &all)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            time(None, Some(5_000))
+                .history(&rga, &all)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            time(None, None).history(&RGA::new(2), &all).unwrap().len(),
+            0
+        );
+        assert!(matches!(
+            time(Some(0), None).history(&RGA::new(2), &all),
+            Err(ServerError::InvalidFilter(_))
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+//! Hooks extending what the server does with its documents.
+//!
+//! A [`DocumentHook`] is told when an edit is applied to a document, when a
+//! session joins it and when a snapshot of it is taken, which is enough to
+//! build profanity filters, bots or analytics without touching the session
+//! code. Hooks are registered with the [`DocumentRegistry`], either for one
+//! document or for every document, including those created later.
+//!
+//! Hooks are called synchronously on the task that caused the event and must
+//! not block. Edits are reported once they are applied and about to be shared
+//! with the document's sessions; a hook that wants to answer with edits of its
+//! own spawns a task to apply them.
+
+use std::sync::Arc;
+
+use crate::crdt::RGA;
+use crate::server::acl::Permission;
+use crate::server::documents::DocumentRegistry;
+use crate::server::error::ServerResult;
+use crate::server::websocket::DocumentState;
+
+/// An edit applied to a document
+pub struct OpApplied<'a> {
+    /// The edited document
+    pub document: &'a str,
+    pub state: &'a Arc<DocumentState>,
+    /// The session that made the edit, or what did (`import`, a merge request)
+    pub session_id: &'a str,
+    /// The version the edit produced
+    pub version: u64,
+    /// Visible index of the edit, if it had a single one
+    pub position: Option<usize>,
+    /// The document's text after the edit
+    pub content: &'a str,
+}
+
+/// A session that joined a document
+pub struct ClientJoin<'a> {
+    /// The joined document
+    pub document: &'a str,
+    pub state: &'a Arc<DocumentState>,
+    pub session_id: &'a str,
+    /// What the session may do
+    pub permission: Permission,
+}
+
+/// A snapshot taken of a document, with no edit in flight
+pub struct SnapshotTaken<'a> {
+    /// The document the snapshot is of
+    pub document: &'a str,
+    pub snapshot: &'a RGA,
+    /// The version the snapshot holds
+    pub version: u64,
+}
+
+/// Callbacks for events on a document. Every method does nothing by default.
+pub trait DocumentHook: Send + Sync {
+    /// Called after an edit was applied
+    fn on_op_applied(&self, _event: &OpApplied<'_>) {}
+
+    /// Called once a session has received the document and starts editing
+    fn on_client_join(&self, _event: &ClientJoin<'_>) {}
+
+    /// Called after a snapshot of the document was taken
+    fn on_snapshot(&self, _event: &SnapshotTaken<'_>) {}
+}
+
+/// The hooks of one document
+#[derive(Default)]
+pub(crate) struct DocumentHooks {
+    /// ID the document is registered under
+    pub(crate) document: String,
+    pub(crate) hooks: Vec<Arc<dyn DocumentHook>>,
+}
+
+impl DocumentState {
+    /// The document's ID and hooks, cloned so that hooks may register others
+    pub(crate) fn hooks(&self) -> Option<(String, Vec<Arc<dyn DocumentHook>>)> {
+        let hooks = self.hooks.read();
+        (!hooks.hooks.is_empty()).then(|| (hooks.document.clone(), hooks.hooks.clone()))
+    }
+}
+
+impl DocumentRegistry {
+    /// Register `hook` for every document, current and future
+    pub fn add_hook(&self, hook: Arc<dyn DocumentHook>) {
+        self.hooks.write().push(hook.clone());
+        for document in self.documents() {
+            document.state.hooks.write().hooks.push(hook.clone());
+        }
+    }
+
+    /// Register `hook` for one document
+    pub fn add_document_hook(&self, id: &str, hook: Arc<dyn DocumentHook>) -> ServerResult {
+        self.get(id)?.state.hooks.write().hooks.push(hook);
+        Ok(())
+    }
+
+    /// Give a document about to be registered under `id` the global hooks
+    pub(crate) fn attach_hooks(&self, id: &str, state: &DocumentState) {
+        *state.hooks.write() = DocumentHooks {
+            document: id.to_string(),
+            hooks: self.hooks.read().clone(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::server::documents::{AppState, MAIN_DOCUMENT};
+    use crate::server::websocket::{PeerMessage, RGAResponse, insert_text};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl DocumentHook for Recorder {
+        fn on_op_applied(&self, event: &OpApplied<'_>) {
+            self.0.lock().push(format!(
+                "{} {} v{} {}",
+                event.document, event.session_id, event.version, event.content
+            ));
+        }
+
+        fn on_snapshot(&self, event: &SnapshotTaken<'_>) {
+            self.0
+                .lock()
+                .push(format!("{} snapshot v{}", event.document, event.version));
+        }
+    }
+
+    async fn edit(state: &Arc<DocumentState>, session: &str, text: &str) {
+        let edit = state.begin_edit().await;
+        let position = state.rga.visible_node_count();
+        insert_text(&state.rga, position, text).unwrap();
+        let version = state.record_edit();
+        drop(edit);
+        state.publish(PeerMessage {
+            origin: session.to_string(),
+            response: RGAResponse {
+                response_type: "update".to_string(),
+                content: state.rga.to_string(),
+                position: Some(position),
+                session_id: None,
+                decorations: None,
+                version: Some(version),
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+    }
+
+    #[tokio::test]
+    async fn test_hooks_see_edits_and_snapshots() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let global = Arc::new(Recorder::default());
+        let main_only = Arc::new(Recorder::default());
+        state.add_hook(global.clone());
+        state
+            .add_document_hook(MAIN_DOCUMENT, main_only.clone())
+            .unwrap();
+        assert!(state.add_document_hook("missing", global.clone()).is_err());
+
+        let main = state.main();
+        edit(&main, "a", "hi").await;
+        let fork = state.fork(MAIN_DOCUMENT).await.unwrap();
+        edit(&fork.state, "b", "!").await;
+        main.snapshot().await;
+
+        assert_eq!(
+            *global.0.lock(),
+            ["main a v1 hi", "main-fork-1 b v1 hi!", "main snapshot v1"]
+        );
+        assert_eq!(*main_only.0.lock(), ["main a v1 hi", "main snapshot v1"]);
+    }
+}
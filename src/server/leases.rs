@@ -0,0 +1,360 @@
+//! Replica ID leases for clients that edit as replicas of their own.
+//!
+//! Sessions edit as the server's replica, but clients running an RGA of their
+//! own (offline editors, the [`crate::client`] module) need a replica ID that
+//! no other client uses. `POST /replicas` grants one for a time to live,
+//! `POST /replicas/{id}/renew?lease=<lease>` is the heartbeat that extends
+//! it, and `DELETE /replicas/{id}?lease=<lease>` hands it back early.
+//!
+//! A lease not renewed in time expires, and its ID is handed to the next
+//! client that asks, so many short-lived browser tabs do not use up the ID
+//! space. Every grant carries a new lease number, and renewing or releasing
+//! needs it: a client that slept through its lease's expiry is refused
+//! rather than sharing its ID with the new holder, and must take a new lease
+//! (and sync the document before editing with it).
+//!
+//! Leased IDs start at [`FIRST_LEASED_REPLICA`], far above the IDs the server
+//! gives documents and bots. With `REPLICA_LEASE_FILE` set, the leases are
+//! saved to that file on every change and loaded back at startup, so a
+//! restart neither forgets a live lease nor hands out an ID twice.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::crdt::ReplicaId;
+use crate::server::documents::AppState;
+use crate::server::error::{ServerError, ServerResult};
+
+/// How long a lease lasts without being renewed
+pub const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(300);
+/// The first replica ID handed out by lease
+pub const FIRST_LEASED_REPLICA: ReplicaId = 1 << 32;
+
+/// A granted lease
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+struct Lease {
+    /// Distinguishes this grant of the ID from earlier ones
+    lease: u64,
+    /// Milliseconds since the Unix epoch
+    expires_at: u64,
+}
+
+/// Every lease, as saved to the lease file
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct LeaseTable {
+    /// The ID granted when none is free
+    next_replica: ReplicaId,
+    /// The lease number of the next grant
+    next_lease: u64,
+    leases: BTreeMap<ReplicaId, Lease>,
+    /// IDs whose lease expired or was released
+    free: BTreeSet<ReplicaId>,
+}
+
+impl Default for LeaseTable {
+    fn default() -> Self {
+        LeaseTable {
+            next_replica: FIRST_LEASED_REPLICA,
+            next_lease: 1,
+            leases: BTreeMap::new(),
+            free: BTreeSet::new(),
+        }
+    }
+}
+
+impl LeaseTable {
+    /// Frees the IDs of the leases expired at `now`
+    fn reclaim(&mut self, now: u64) {
+        let expired: Vec<ReplicaId> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.expires_at <= now)
+            .map(|(&replica, _)| replica)
+            .collect();
+        for replica in expired {
+            self.leases.remove(&replica);
+            self.free.insert(replica);
+        }
+    }
+}
+
+struct LeaseState {
+    table: LeaseTable,
+    ttl: Duration,
+    /// File the table is saved to, if any
+    file: Option<PathBuf>,
+}
+
+/// The replica ID leases of a server
+pub struct ReplicaLeases {
+    state: Mutex<LeaseState>,
+}
+
+impl Default for ReplicaLeases {
+    fn default() -> Self {
+        ReplicaLeases {
+            state: Mutex::new(LeaseState {
+                table: LeaseTable::default(),
+                ttl: DEFAULT_LEASE_TTL,
+                file: None,
+            }),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl ReplicaLeases {
+    /// Save the leases to `file` from now on, loading those it holds
+    pub fn open(&self, file: impl Into<PathBuf>) -> std::io::Result<()> {
+        let file = file.into();
+        let table = match std::fs::read(&file) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => LeaseTable::default(),
+            Err(e) => return Err(e),
+        };
+        let mut state = self.state.lock();
+        state.table = table;
+        state.file = Some(file);
+        Ok(())
+    }
+
+    /// Grant leases for `ttl`
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.state.lock().ttl = ttl;
+    }
+
+    /// Number of live leases
+    pub fn len(&self) -> usize {
+        let now = now_millis();
+        let state = self.state.lock();
+        state
+            .table
+            .leases
+            .values()
+            .filter(|lease| lease.expires_at > now)
+            .count()
+    }
+
+    /// Returns true if no lease is live
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lease a replica ID no live lease holds
+    pub fn grant(&self) -> ServerResult<LeaseInfo> {
+        let now = now_millis();
+        let mut state = self.state.lock();
+        let ttl = state.ttl;
+        let table = &mut state.table;
+        table.reclaim(now);
+        let replica = match table.free.pop_first() {
+            Some(replica) => replica,
+            None => {
+                table.next_replica += 1;
+                table.next_replica - 1
+            }
+        };
+        let lease = Lease {
+            lease: table.next_lease,
+            expires_at: now + ttl.as_millis() as u64,
+        };
+        table.next_lease += 1;
+        table.leases.insert(replica, lease);
+        save(&state)?;
+        Ok(LeaseInfo::new(replica, lease.lease, ttl))
+    }
+
+    /// Extend lease `lease` of `replica` by the time to live
+    pub fn renew(&self, replica: ReplicaId, lease: u64) -> ServerResult<LeaseInfo> {
+        let now = now_millis();
+        let mut state = self.state.lock();
+        let ttl = state.ttl;
+        state.table.reclaim(now);
+        match state.table.leases.get_mut(&replica) {
+            Some(held) if held.lease == lease => held.expires_at = now + ttl.as_millis() as u64,
+            _ => return Err(ServerError::UnknownLease(replica)),
+        }
+        save(&state)?;
+        Ok(LeaseInfo::new(replica, lease, ttl))
+    }
+
+    /// End lease `lease` of `replica` before it expires
+    pub fn release(&self, replica: ReplicaId, lease: u64) -> ServerResult {
+        let mut state = self.state.lock();
+        let table = &mut state.table;
+        if table
+            .leases
+            .get(&replica)
+            .is_none_or(|held| held.lease != lease)
+        {
+            return Err(ServerError::UnknownLease(replica));
+        }
+        table.leases.remove(&replica);
+        table.free.insert(replica);
+        save(&state)
+    }
+}
+
+/// Writes the table to the lease file, if there is one, replacing it whole
+fn save(state: &LeaseState) -> ServerResult {
+    let Some(file) = &state.file else {
+        return Ok(());
+    };
+    let partial = file.with_extension("tmp");
+    let bytes = serde_json::to_vec(&state.table).map_err(ServerError::Serialization)?;
+    std::fs::write(&partial, bytes)?;
+    std::fs::rename(&partial, file)?;
+    Ok(())
+}
+
+/// A lease as returned to its client
+#[derive(Serialize, Deserialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct LeaseInfo {
+    /// The replica ID to edit as
+    pub replica_id: ReplicaId,
+    /// The lease number, needed to renew or release the lease
+    pub lease: u64,
+    /// Seconds until the lease expires unless renewed
+    pub ttl_secs: u64,
+}
+
+impl LeaseInfo {
+    fn new(replica_id: ReplicaId, lease: u64, ttl: Duration) -> Self {
+        LeaseInfo {
+            replica_id,
+            lease,
+            ttl_secs: ttl.as_secs(),
+        }
+    }
+}
+
+/// Query parameters naming a lease
+#[derive(Deserialize)]
+pub struct LeaseParams {
+    /// The lease number returned when the lease was granted
+    pub lease: u64,
+}
+
+/// Lease a replica ID
+#[utoipa::path(
+    post,
+    path = "/replicas",
+    tag = "replicas",
+    responses(
+        (status = 201, description = "Leased", body = LeaseInfo),
+    )
+)]
+pub async fn lease_replica(
+    State(state): State<AppState>,
+) -> ServerResult<(StatusCode, Json<LeaseInfo>)> {
+    Ok((StatusCode::CREATED, Json(state.leases().grant()?)))
+}
+
+/// Renew a replica ID lease
+#[utoipa::path(
+    post,
+    path = "/replicas/{id}/renew",
+    tag = "replicas",
+    params(
+        ("id" = u64, Path, description = "Leased replica ID"),
+        ("lease" = u64, Query, description = "Lease number returned by the grant"),
+    ),
+    responses(
+        (status = 200, description = "Renewed", body = LeaseInfo),
+        (status = 404, description = "The lease expired or was never granted"),
+    )
+)]
+pub async fn renew_replica_lease(
+    State(state): State<AppState>,
+    Path(id): Path<ReplicaId>,
+    Query(params): Query<LeaseParams>,
+) -> ServerResult<Json<LeaseInfo>> {
+    Ok(Json(state.leases().renew(id, params.lease)?))
+}
+
+/// Release a replica ID lease
+#[utoipa::path(
+    delete,
+    path = "/replicas/{id}",
+    tag = "replicas",
+    params(
+        ("id" = u64, Path, description = "Leased replica ID"),
+        ("lease" = u64, Query, description = "Lease number returned by the grant"),
+    ),
+    responses(
+        (status = 204, description = "Released"),
+        (status = 404, description = "The lease expired or was never granted"),
+    )
+)]
+pub async fn release_replica_lease(
+    State(state): State<AppState>,
+    Path(id): Path<ReplicaId>,
+    Query(params): Query<LeaseParams>,
+) -> ServerResult<StatusCode> {
+    state.leases().release(id, params.lease)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leases_expire_and_survive_restarts() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-leases-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("leases.json");
+        let _ = std::fs::remove_file(&file);
+
+        let leases = ReplicaLeases::default();
+        leases.open(&file).unwrap();
+        let first = leases.grant().unwrap();
+        let second = leases.grant().unwrap();
+        assert_eq!(first.replica_id, FIRST_LEASED_REPLICA);
+        assert_eq!(second.replica_id, FIRST_LEASED_REPLICA + 1);
+        assert_eq!(leases.renew(first.replica_id, first.lease).unwrap(), first);
+        assert!(matches!(
+            leases.renew(first.replica_id, second.lease),
+            Err(ServerError::UnknownLease(_))
+        ));
+        assert_eq!(leases.len(), 2);
+
+        // A restart keeps the live leases and hands out fresh IDs
+        let restarted = ReplicaLeases::default();
+        restarted.open(&file).unwrap();
+        assert_eq!(restarted.len(), 2);
+        assert_eq!(
+            restarted.grant().unwrap().replica_id,
+            FIRST_LEASED_REPLICA + 2
+        );
+
+        // Released and expired IDs are reclaimed, under new lease numbers
+        restarted.release(first.replica_id, first.lease).unwrap();
+        let reused = restarted.grant().unwrap();
+        assert_eq!(reused.replica_id, first.replica_id);
+        assert_ne!(reused.lease, first.lease);
+        restarted.set_ttl(Duration::ZERO);
+        restarted.renew(second.replica_id, second.lease).unwrap();
+        assert_eq!(restarted.grant().unwrap().replica_id, second.replica_id);
+        assert!(restarted.renew(second.replica_id, second.lease).is_err());
+        assert!(restarted.release(second.replica_id, second.lease).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! Merge requests: proposing a fork's changes back to its upstream.
+//!
+//! Opening a merge request records the operations the fork has that its
+//! upstream lacks. While the request is open, its preview shows what the
+//! upstream would look like with them applied. Approving it applies them to
+//! the upstream in one step, with no edit in flight; concurrent edits on either
+//! side are reconciled by the CRDT like any other remote operations, so a merge
+//! never conflicts.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use parking_lot::RwLock;
+use serde::Serialize;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::crdt::{CausalBuffer, Node, RGA};
+use crate::server::documents::{AppState, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::tenants::TenantScope;
+use crate::server::websocket::{PeerMessage, RGAResponse};
+
+/// Where a merge request stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStatus {
+    Open,
+    Merged,
+    Rejected,
+}
+
+/// A proposal to apply a fork's operations to its upstream
+#[derive(Debug, Clone)]
+pub struct MergeRequest {
+    pub id: u64,
+    pub fork: String,
+    pub upstream: String,
+    /// The fork's operations missing upstream when the request was opened
+    pub operations: Vec<Node>,
+    pub status: MergeStatus,
+}
+
+/// Merge requests by ID
+#[derive(Default)]
+pub struct MergeRequests {
+    requests: RwLock<BTreeMap<u64, MergeRequest>>,
+    next_id: AtomicU64,
+}
+
+impl DocumentRegistry {
+    /// Open a merge request proposing a fork's changes to its upstream
+    pub fn open_merge_request(&self, fork_id: &str) -> ServerResult<MergeRequest> {
+        let fork = self.get(fork_id)?;
+        let upstream_id = fork
+            .upstream
+            .as_ref()
+            .ok_or_else(|| ServerError::NotAFork(fork.id.clone()))?
+            .document_id
+            .clone();
+        let upstream = self.get(&upstream_id)?;
+        upstream.ensure_editable()?;
+
+        let request = MergeRequest {
+            id: self.merge_requests.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            operations: fork.state.rga.missing_from(&upstream.state.rga),
+            fork: fork.id,
+            upstream: upstream_id,
+            status: MergeStatus::Open,
+        };
+        self.merge_requests
+            .requests
+            .write()
+            .insert(request.id, request.clone());
+        Ok(request)
+    }
+
+    /// Look up a merge request
+    pub fn merge_request(&self, id: u64) -> ServerResult<MergeRequest> {
+        self.merge_requests
+            .requests
+            .read()
+            .get(&id)
+            .cloned()
+            .ok_or(ServerError::UnknownMergeRequest(id))
+    }
+
+    /// All merge requests, oldest first
+    pub fn merge_requests(&self) -> Vec<MergeRequest> {
+        self.merge_requests
+            .requests
+            .read()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Apply an open merge request to its upstream.
+    ///
+    /// The operations are applied while the upstream is held exclusively, so
+    /// sessions observe the document either before or after the whole merge.
+    pub async fn approve_merge_request(&self, id: u64) -> ServerResult<MergeRequest> {
+        self.get(&self.merge_request(id)?.upstream)?
+            .ensure_editable()?;
+        let request = self.close_merge_request(id, MergeStatus::Merged)?;
+        let upstream = self.get(&request.upstream)?;
+
+        let (content, version) = {
+            let _exclusive = upstream.state.exclusive().await;
+            apply_operations(&upstream.state.rga, &request.operations);
+            (upstream.state.rga.to_string(), upstream.state.record_edit())
+        };
+
+        upstream.state.publish(PeerMessage {
+            origin: format!("merge_request_{id}"),
+            response: RGAResponse {
+                response_type: "update".to_string(),
+                content,
+                position: None,
+                session_id: None,
+                decorations: None,
+                version: Some(version),
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+        info!(
+            "Merged {} operations from {} into {}",
+            request.operations.len(),
+            request.fork,
+            request.upstream
+        );
+        Ok(request)
+    }
+
+    /// Close an open merge request without applying it
+    pub fn reject_merge_request(&self, id: u64) -> ServerResult<MergeRequest> {
+        self.close_merge_request(id, MergeStatus::Rejected)
+    }
+
+    /// Move an open merge request to `status`, exactly once
+    fn close_merge_request(&self, id: u64, status: MergeStatus) -> ServerResult<MergeRequest> {
+        let mut requests = self.merge_requests.requests.write();
+        let request = requests
+            .get_mut(&id)
+            .ok_or(ServerError::UnknownMergeRequest(id))?;
+        if request.status != MergeStatus::Open {
+            return Err(ServerError::MergeRequestClosed(id));
+        }
+        request.status = status;
+        Ok(request.clone())
+    }
+}
+
+/// Apply a fork's operations, in document order, without resurrecting nodes the
+/// target has deleted since
+pub(crate) fn apply_operations(rga: &RGA, operations: &[Node]) {
+    let mut buffer = CausalBuffer::new();
+    for node in operations {
+        buffer.deliver(rga, node.clone());
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MergeRequestInfo {
+    pub id: u64,
+    pub fork: String,
+    pub upstream: String,
+    pub status: MergeStatus,
+    /// Number of operations the merge applies
+    pub operations: usize,
+    /// What the merge would change, while the request is open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<MergePreview>,
+}
+
+/// The effect of a merge on the upstream's current content
+#[derive(Serialize, ToSchema)]
+pub struct MergePreview {
+    pub before: String,
+    pub after: String,
+    /// The after content as runs of unchanged, inserted and deleted text
+    pub diff: Vec<DiffSegment>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiffSegment {
+    /// `equal`, `insert` or `delete`
+    pub kind: &'static str,
+    pub text: String,
+}
+
+impl MergeRequestInfo {
+    fn new(registry: &DocumentRegistry, request: MergeRequest) -> ServerResult<Self> {
+        let preview = match request.status {
+            MergeStatus::Open => {
+                let upstream = registry.get(&request.upstream)?;
+                Some(preview(&upstream.state.rga, &request.operations))
+            }
+            MergeStatus::Merged | MergeStatus::Rejected => None,
+        };
+        Ok(MergeRequestInfo {
+            id: request.id,
+            fork: request.fork,
+            upstream: request.upstream,
+            status: request.status,
+            operations: request.operations.len(),
+            preview,
+        })
+    }
+}
+
+/// Apply `operations` to a copy of `upstream` and diff it node by node
+fn preview(upstream: &RGA, operations: &[Node]) -> MergePreview {
+    let merged = upstream.clone();
+    apply_operations(&merged, operations);
+
+    let mut diff: Vec<DiffSegment> = Vec::new();
+    for node in merged.all_nodes() {
+        if node.is_sentinel() {
+            continue;
+        }
+        let was_visible = upstream.get_node(node.id).is_some_and(|n| n.is_visible());
+        let kind = match (was_visible, node.is_visible()) {
+            (true, true) => "equal",
+            (false, true) => "insert",
+            (true, false) => "delete",
+            (false, false) => continue,
+        };
+        match diff.last_mut() {
+            Some(segment) if segment.kind == kind => segment.text.push(node.character),
+            _ => diff.push(DiffSegment {
+                kind,
+                text: node.character.to_string(),
+            }),
+        }
+    }
+
+    MergePreview {
+        before: upstream.to_string(),
+        after: merged.to_string(),
+        diff,
+    }
+}
+
+/// Propose a fork's changes to its upstream
+#[utoipa::path(
+    post,
+    path = "/docs/{id}/merge-requests",
+    tag = "documents",
+    params(("id" = String, Path, description = "The fork whose changes are proposed")),
+    responses(
+        (status = 201, description = "Merge request opened", body = MergeRequestInfo),
+        (status = 404, description = "No such document"),
+        (status = 409, description = "The document is not a fork, or its upstream is published"),
+    )
+)]
+pub async fn open_merge_request(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+) -> ServerResult<(StatusCode, Json<MergeRequestInfo>)> {
+    scope.check(&id)?;
+    let request = state.open_merge_request(&id)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(MergeRequestInfo::new(&state, request)?),
+    ))
+}
+
+/// List merge requests
+#[utoipa::path(
+    get,
+    path = "/merge-requests",
+    tag = "documents",
+    responses((status = 200, description = "All merge requests, oldest first", body = [MergeRequestInfo]))
+)]
+pub async fn list_merge_requests(
+    State(state): State<AppState>,
+    scope: TenantScope,
+) -> ServerResult<Json<Vec<MergeRequestInfo>>> {
+    let requests = state
+        .merge_requests()
+        .into_iter()
+        .filter(|request| scope.check(&request.fork).is_ok())
+        .map(|request| MergeRequestInfo::new(&state, request))
+        .collect::<ServerResult<_>>()?;
+    Ok(Json(requests))
+}
+
+/// Show a merge request and, while it is open, its preview
+#[utoipa::path(
+    get,
+    path = "/merge-requests/{id}",
+    tag = "documents",
+    params(("id" = u64, Path, description = "Merge request ID")),
+    responses(
+        (status = 200, description = "The merge request", body = MergeRequestInfo),
+        (status = 404, description = "No such merge request"),
+    )
+)]
+pub async fn get_merge_request(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    scope: TenantScope,
+) -> ServerResult<Json<MergeRequestInfo>> {
+    let request = scope.merge_request(&state, id)?;
+    Ok(Json(MergeRequestInfo::new(&state, request)?))
+}
+
+/// Approve a merge request, applying it to the upstream
+#[utoipa::path(
+    post,
+    path = "/merge-requests/{id}/approve",
+    tag = "documents",
+    params(("id" = u64, Path, description = "Merge request ID")),
+    responses(
+        (status = 200, description = "Merged", body = MergeRequestInfo),
+        (status = 404, description = "No such merge request"),
+        (status = 409, description = "The merge request is no longer open"),
+    )
+)]
+pub async fn approve_merge_request(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    scope: TenantScope,
+) -> ServerResult<Json<MergeRequestInfo>> {
+    scope.merge_request(&state, id)?;
+    let request = state.approve_merge_request(id).await?;
+    Ok(Json(MergeRequestInfo::new(&state, request)?))
+}
+
+/// Reject a merge request
+#[utoipa::path(
+    post,
+    path = "/merge-requests/{id}/reject",
+    tag = "documents",
+    params(("id" = u64, Path, description = "Merge request ID")),
+    responses(
+        (status = 200, description = "Rejected", body = MergeRequestInfo),
+        (status = 404, description = "No such merge request"),
+        (status = 409, description = "The merge request is no longer open"),
+    )
+)]
+pub async fn reject_merge_request(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    scope: TenantScope,
+) -> ServerResult<Json<MergeRequestInfo>> {
+    scope.merge_request(&state, id)?;
+    let request = state.reject_merge_request(id)?;
+    Ok(Json(MergeRequestInfo::new(&state, request)?))
+}
@@ -0,0 +1,332 @@
+//! Middleware chains between a document and its sessions.
+//!
+//! Every operation a session sends passes through the document's inbound
+//! chain before it is applied, and every frame sent to a session through its
+//! outbound chain. An [`InboundMiddleware`] can validate an operation, refusing
+//! it with an error reported to the client, or rewrite it, for instance to
+//! sanitize its text. An [`OutboundMiddleware`] can rewrite a frame or withhold
+//! it. Policies such as length limits, profanity filters or metrics are set per
+//! document with [`DocumentRegistry::set_middleware`], without touching the
+//! session code.
+//!
+//! Middleware runs synchronously on the session's task, in the order it was
+//! added to the chain, and must not block. Forks and published copies start
+//! without middleware.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::server::acl::Permission;
+use crate::server::documents::DocumentRegistry;
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::{DocumentState, RGAOperation, RGAResponse};
+
+/// An operation received from a session
+pub struct Inbound<'a> {
+    pub state: &'a Arc<DocumentState>,
+    /// The session that sent the operation
+    pub session_id: &'a str,
+    /// What the session may do
+    pub permission: Permission,
+}
+
+/// A frame about to be sent to a session
+pub struct Outbound<'a> {
+    pub state: &'a Arc<DocumentState>,
+    /// The session the frame is sent to
+    pub session_id: &'a str,
+}
+
+/// A step of a document's inbound chain
+pub trait InboundMiddleware: Send + Sync {
+    /// Inspects or rewrites `operation` before it is applied.
+    ///
+    /// An error refuses the operation: it is reported to the client and the
+    /// rest of the chain is skipped.
+    fn process(&self, operation: &mut RGAOperation, context: &Inbound<'_>) -> ServerResult;
+}
+
+/// A step of a document's outbound chain
+pub trait OutboundMiddleware: Send + Sync {
+    /// Inspects or rewrites `response` before it is sent.
+    ///
+    /// Returning false withholds the frame from the session and skips the rest
+    /// of the chain. Withholding an `update` leaves the client's copy stale
+    /// until the next one.
+    fn process(&self, response: &mut RGAResponse, context: &Outbound<'_>) -> bool;
+}
+
+/// The inbound and outbound chains of a document
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    inbound: Vec<Arc<dyn InboundMiddleware>>,
+    outbound: Vec<Arc<dyn OutboundMiddleware>>,
+}
+
+impl MiddlewareChain {
+    /// Creates empty chains, which let everything through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `middleware` to the inbound chain
+    pub fn inbound(mut self, middleware: Arc<dyn InboundMiddleware>) -> Self {
+        self.inbound.push(middleware);
+        self
+    }
+
+    /// Appends `middleware` to the outbound chain
+    pub fn outbound(mut self, middleware: Arc<dyn OutboundMiddleware>) -> Self {
+        self.outbound.push(middleware);
+        self
+    }
+
+    /// Returns true if neither chain has a step
+    pub fn is_empty(&self) -> bool {
+        self.inbound.is_empty() && self.outbound.is_empty()
+    }
+}
+
+/// Refuses operations whose text is longer than a number of characters
+pub struct MaxTextLength(pub usize);
+
+impl InboundMiddleware for MaxTextLength {
+    fn process(&self, operation: &mut RGAOperation, _context: &Inbound<'_>) -> ServerResult {
+        match &operation.text {
+            Some(text) if text.chars().count() > self.0 => Err(ServerError::Rejected(
+                "text is longer than the document allows",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Removes control characters other than newlines and tabs from the text of
+/// operations, and refuses single characters that are one
+pub struct StripControlCharacters;
+
+impl StripControlCharacters {
+    fn is_stripped(character: char) -> bool {
+        character.is_control() && character != '\n' && character != '\t'
+    }
+}
+
+impl InboundMiddleware for StripControlCharacters {
+    fn process(&self, operation: &mut RGAOperation, _context: &Inbound<'_>) -> ServerResult {
+        if operation.character.is_some_and(Self::is_stripped) {
+            return Err(ServerError::Rejected("control characters are not allowed"));
+        }
+        if let Some(text) = &mut operation.text {
+            text.retain(|character| !Self::is_stripped(character));
+        }
+        Ok(())
+    }
+}
+
+/// Counts the operations and frames passing through the chains it is added to
+#[derive(Debug, Default)]
+pub struct FrameCounter {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl FrameCounter {
+    /// Operations received so far, refused ones included
+    pub fn inbound(&self) -> u64 {
+        self.inbound.load(Ordering::Relaxed)
+    }
+
+    /// Frames sent so far
+    pub fn outbound(&self) -> u64 {
+        self.outbound.load(Ordering::Relaxed)
+    }
+}
+
+impl InboundMiddleware for FrameCounter {
+    fn process(&self, _operation: &mut RGAOperation, _context: &Inbound<'_>) -> ServerResult {
+        self.inbound.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl OutboundMiddleware for FrameCounter {
+    fn process(&self, _response: &mut RGAResponse, _context: &Outbound<'_>) -> bool {
+        self.outbound.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+impl DocumentState {
+    /// The document's middleware chains
+    pub fn middleware(&self) -> MiddlewareChain {
+        self.middleware.read().clone()
+    }
+
+    /// Replaces the document's middleware chains
+    pub fn set_middleware(&self, chain: MiddlewareChain) {
+        *self.middleware.write() = chain;
+    }
+
+    /// Passes an operation from session `session_id` through the inbound chain
+    pub(crate) fn filter_inbound(
+        self: &Arc<Self>,
+        session_id: &str,
+        permission: Permission,
+        operation: &mut RGAOperation,
+    ) -> ServerResult {
+        let context = Inbound {
+            state: self,
+            session_id,
+            permission,
+        };
+        let chain = self.middleware.read();
+        chain
+            .inbound
+            .iter()
+            .try_for_each(|middleware| middleware.process(operation, &context))
+    }
+
+    /// Passes a frame for session `session_id` through the outbound chain,
+    /// returning it unless it is withheld
+    pub(crate) fn filter_outbound(
+        self: &Arc<Self>,
+        session_id: &str,
+        mut response: RGAResponse,
+    ) -> Option<RGAResponse> {
+        let context = Outbound {
+            state: self,
+            session_id,
+        };
+        let chain = self.middleware.read();
+        chain
+            .outbound
+            .iter()
+            .all(|middleware| middleware.process(&mut response, &context))
+            .then_some(response)
+    }
+
+    /// Returns true if frames sent to the document's sessions may be
+    /// rewritten or withheld
+    pub(crate) fn filters_outbound(&self) -> bool {
+        !self.middleware.read().outbound.is_empty()
+    }
+}
+
+impl DocumentRegistry {
+    /// Replace the middleware chains of one document
+    pub fn set_middleware(&self, id: &str, chain: MiddlewareChain) -> ServerResult {
+        self.get(id)?.state.set_middleware(chain);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::RGA;
+    use crate::server::documents::{AppState, MAIN_DOCUMENT};
+
+    /// Masks a word in the text of operations and in the content of frames
+    struct ProfanityFilter(&'static str);
+
+    impl ProfanityFilter {
+        fn mask(&self, text: &mut String) {
+            *text = text.replace(self.0, &"*".repeat(self.0.len()));
+        }
+    }
+
+    impl InboundMiddleware for ProfanityFilter {
+        fn process(&self, operation: &mut RGAOperation, _context: &Inbound<'_>) -> ServerResult {
+            if let Some(text) = &mut operation.text {
+                self.mask(text);
+            }
+            Ok(())
+        }
+    }
+
+    impl OutboundMiddleware for ProfanityFilter {
+        fn process(&self, response: &mut RGAResponse, _context: &Outbound<'_>) -> bool {
+            self.mask(&mut response.content);
+            true
+        }
+    }
+
+    /// Withholds comments from every session
+    struct NoComments;
+
+    impl OutboundMiddleware for NoComments {
+        fn process(&self, response: &mut RGAResponse, _context: &Outbound<'_>) -> bool {
+            response.response_type != "comment"
+        }
+    }
+
+    fn operation(json: &str) -> RGAOperation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn response(response_type: &str, content: &str) -> RGAResponse {
+        RGAResponse {
+            response_type: response_type.to_string(),
+            content: content.to_string(),
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chains_validate_rewrite_and_withhold() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let counter = Arc::new(FrameCounter::default());
+        let filter = Arc::new(ProfanityFilter("darn"));
+        let chain = MiddlewareChain::new()
+            .inbound(counter.clone())
+            .inbound(Arc::new(StripControlCharacters))
+            .inbound(Arc::new(MaxTextLength(16)))
+            .inbound(filter.clone())
+            .outbound(filter)
+            .outbound(Arc::new(NoComments))
+            .outbound(counter.clone());
+        state.set_middleware(MAIN_DOCUMENT, chain).unwrap();
+        assert!(
+            state
+                .set_middleware("missing", MiddlewareChain::new())
+                .is_err()
+        );
+        let main = state.main();
+        assert!(main.filters_outbound());
+
+        let mut paste = operation(r#"{"type": "paste_chunk", "text": "oh\u0007 darn\n"}"#);
+        main.filter_inbound("a", Permission::Edit, &mut paste)
+            .unwrap();
+        assert_eq!(paste.text.as_deref(), Some("oh ****\n"));
+        let mut long =
+            operation(r#"{"type": "paste_chunk", "text": "far too long for the limit"}"#);
+        let error = main
+            .filter_inbound("a", Permission::Edit, &mut long)
+            .unwrap_err();
+        assert_eq!(error.code(), "rejected");
+        let mut bell = operation(r#"{"type": "insert", "character": "\u0007"}"#);
+        assert!(
+            main.filter_inbound("a", Permission::Edit, &mut bell)
+                .is_err()
+        );
+
+        let update = main.filter_outbound("b", response("update", "darn it"));
+        assert_eq!(update.unwrap().content, "**** it");
+        assert!(
+            main.filter_outbound("b", response("comment", "hi"))
+                .is_none()
+        );
+        assert_eq!((counter.inbound(), counter.outbound()), (3, 1));
+
+        // Other documents keep letting everything through
+        let fork = state.fork(MAIN_DOCUMENT).await.unwrap();
+        assert!(fork.state.middleware().is_empty());
+        assert!(!fork.state.filters_outbound());
+    }
+}
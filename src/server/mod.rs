@@ -3,8 +3,45 @@
 //! This module contains the Axum web server implementation that provides
 //! HTTP endpoints for interacting with the RGA CRDT.
 
+pub mod acl;
+pub mod admin;
+pub mod analysis;
+pub mod analytics;
+pub mod archive;
+pub mod audit;
+pub mod awareness;
+pub mod bots;
+pub mod capture;
+pub mod compression;
+pub mod decorations;
+pub mod documents;
+pub mod erasure;
+pub mod error;
+pub mod export;
+pub mod forks;
+pub mod freeze;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod leases;
+pub mod merges;
+pub mod middleware;
+pub mod openapi;
+pub mod paste;
+pub mod persistence;
+pub mod publish;
+pub mod replication;
+pub mod retention;
 pub mod routes;
+pub mod selection;
+pub mod serve;
+pub mod stats;
+pub mod tenants;
 pub mod websocket;
 
 // Re-export main server functionality
+pub use error::{ServerError, ServerResult};
 pub use routes::*;
+pub use serve::{bind_with_retry, serve};
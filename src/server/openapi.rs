@@ -0,0 +1,121 @@
+//! OpenAPI description of the HTTP API.
+//!
+//! The document is generated from the handler annotations with `utoipa` and
+//! served at `/openapi.json`, so client SDKs can be generated for the HTTP
+//! surface. With the `swagger-ui` feature, Swagger UI is served at
+//! `/swagger-ui` as well. The WebSocket protocol is documented separately in
+//! the server README.
+
+use axum::{Router, response::Json, routing::get};
+use utoipa::OpenApi;
+
+use crate::server::analytics::{ActivityBucket, ActivityResponse, AuthorActivity};
+use crate::server::audit::AuditResponse;
+use crate::server::documents::AppState;
+use crate::server::erasure::PseudonymizeResponse;
+use crate::server::export::ImportResponse;
+use crate::server::forks::{DivergenceResponse, ForkInfo};
+use crate::server::freeze::FreezeResponse;
+use crate::server::health::{
+    LivenessResponse, MemoryStatus, OperationCounts, ReadinessResponse, StorageStatus,
+};
+use crate::server::leases::LeaseInfo;
+use crate::server::merges::{DiffSegment, MergePreview, MergeRequestInfo, MergeStatus};
+use crate::server::publish::PublishedInfo;
+use crate::server::replication::{ReplicaMerged, ReplicationBundle, ReplicationReport};
+use crate::server::retention::PurgeResponse;
+use crate::server::routes::HealthResponse;
+use crate::server::tenants::TenantMetrics;
+
+/// The OpenAPI document for the REST endpoints
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "RGA CRDT server"),
+    paths(
+        crate::server::routes::health,
+        crate::server::health::healthz,
+        crate::server::health::readyz,
+        crate::server::forks::fork_document,
+        crate::server::forks::list_forks,
+        crate::server::forks::divergence,
+        crate::server::export::export_document,
+        crate::server::export::import_markdown,
+        crate::server::history::document_history,
+        crate::server::analytics::document_activity,
+        crate::server::publish::publish_document,
+        crate::server::merges::open_merge_request,
+        crate::server::merges::list_merge_requests,
+        crate::server::merges::get_merge_request,
+        crate::server::merges::approve_merge_request,
+        crate::server::merges::reject_merge_request,
+        crate::server::admin::document_structure,
+        crate::server::retention::purge_document,
+        crate::server::erasure::pseudonymize_document,
+        crate::server::freeze::freeze_document,
+        crate::server::freeze::unfreeze_document,
+        crate::server::replication::replicate_document,
+        crate::server::replication::export_replica,
+        crate::server::replication::receive_replica,
+        crate::server::leases::lease_replica,
+        crate::server::leases::renew_replica_lease,
+        crate::server::leases::release_replica_lease,
+        crate::server::tenants::create_document,
+        crate::server::tenants::tenant_metrics,
+        crate::server::tenants::list_tenants,
+        crate::server::audit::verify_audit,
+    ),
+    components(schemas(
+        HealthResponse,
+        LivenessResponse,
+        ReadinessResponse,
+        StorageStatus,
+        MemoryStatus,
+        OperationCounts,
+        ForkInfo,
+        DivergenceResponse,
+        ImportResponse,
+        PublishedInfo,
+        LeaseInfo,
+        PurgeResponse,
+        PseudonymizeResponse,
+        FreezeResponse,
+        ReplicationBundle,
+        ReplicaMerged,
+        ReplicationReport,
+        TenantMetrics,
+        AuditResponse,
+        MergeRequestInfo,
+        MergeStatus,
+        MergePreview,
+        DiffSegment,
+        ActivityResponse,
+        ActivityBucket,
+        AuthorActivity,
+    )),
+    tags(
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "documents", description = "Forking, merging, publishing, exporting and importing documents"),
+        (name = "replicas", description = "Leasing replica IDs to clients that edit as replicas of their own"),
+        (name = "tenants", description = "Usage of the requesting tenant on a multi-tenant server"),
+        (name = "admin", description = "Inspecting, purging, freezing and re-replicating documents, with the admin token"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Serve the OpenAPI document
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Routes serving the OpenAPI document and, if enabled, Swagger UI
+pub fn openapi_routes() -> Router<AppState> {
+    let router = Router::new().route("/openapi.json", get(openapi_json));
+
+    #[cfg(feature = "swagger-ui")]
+    let router = router.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/swagger-ui")
+            .config(utoipa_swagger_ui::Config::from("/openapi.json")),
+    );
+
+    router
+}
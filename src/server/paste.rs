@@ -0,0 +1,142 @@
+//! Chunked paste transactions.
+//!
+//! Pasting a very large text as one frame means multi-megabyte messages, and
+//! as per-character inserts means thousands of them. Instead, a client opens a
+//! paste transaction, streams the text in numbered chunks and commits it; the
+//! server then inserts the whole text in one step. Chunks are buffered per
+//! session and nothing reaches the document before the commit.
+
+use std::collections::HashMap;
+
+use crate::server::error::{ServerError, ServerResult};
+
+/// Upper bound on the text a session may buffer across open transactions
+pub const MAX_PASTE_BYTES: usize = 16 * 1024 * 1024;
+/// Upper bound on the transactions a session may have open at once
+pub const MAX_OPEN_PASTES: usize = 4;
+
+/// A paste being streamed in
+#[derive(Debug)]
+struct PendingPaste {
+    /// Visible index the text will be inserted at
+    position: usize,
+    text: String,
+    /// Sequence number the next chunk must carry
+    next_sequence: u64,
+}
+
+/// The paste transactions a session has open
+#[derive(Debug, Default)]
+pub struct PasteTransactions {
+    open: HashMap<String, PendingPaste>,
+}
+
+impl PasteTransactions {
+    /// Open a transaction inserting at `position` when committed
+    pub fn begin(&mut self, transaction: String, position: usize) -> ServerResult {
+        if self.open.contains_key(&transaction) {
+            return Err(ServerError::InvalidPaste("transaction is already open"));
+        }
+        if self.open.len() >= MAX_OPEN_PASTES {
+            return Err(ServerError::InvalidPaste(
+                "too many open paste transactions",
+            ));
+        }
+        self.open.insert(
+            transaction,
+            PendingPaste {
+                position,
+                text: String::new(),
+                next_sequence: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Append chunk number `sequence` to a transaction.
+    ///
+    /// Chunks must arrive in order starting at 0.
+    pub fn append(&mut self, transaction: &str, sequence: u64, chunk: &str) -> ServerResult {
+        let buffered = self.buffered_bytes();
+        let paste = self
+            .open
+            .get_mut(transaction)
+            .ok_or(ServerError::InvalidPaste("unknown paste transaction"))?;
+        if sequence != paste.next_sequence {
+            return Err(ServerError::InvalidPaste("paste chunk out of sequence"));
+        }
+        if buffered + chunk.len() > MAX_PASTE_BYTES {
+            return Err(ServerError::InvalidPaste("paste exceeds the size limit"));
+        }
+        paste.text.push_str(chunk);
+        paste.next_sequence += 1;
+        Ok(())
+    }
+
+    /// Close a transaction, returning its position and complete text
+    pub fn commit(&mut self, transaction: &str) -> ServerResult<(usize, String)> {
+        let paste = self
+            .open
+            .remove(transaction)
+            .ok_or(ServerError::InvalidPaste("unknown paste transaction"))?;
+        Ok((paste.position, paste.text))
+    }
+
+    /// Discard a transaction
+    pub fn abort(&mut self, transaction: &str) -> ServerResult {
+        self.open
+            .remove(transaction)
+            .map(|_| ())
+            .ok_or(ServerError::InvalidPaste("unknown paste transaction"))
+    }
+
+    /// Bytes buffered across all open transactions
+    fn buffered_bytes(&self) -> usize {
+        self.open.values().map(|paste| paste.text.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_are_joined_in_order() {
+        let mut pastes = PasteTransactions::default();
+        pastes.begin("t1".to_string(), 3).unwrap();
+        pastes.append("t1", 0, "hello ").unwrap();
+        pastes.append("t1", 1, "world").unwrap();
+
+        assert_eq!(pastes.commit("t1").unwrap(), (3, "hello world".to_string()));
+        assert!(pastes.commit("t1").is_err());
+    }
+
+    #[test]
+    fn test_out_of_sequence_and_unknown_chunks_are_rejected() {
+        let mut pastes = PasteTransactions::default();
+        pastes.begin("t1".to_string(), 0).unwrap();
+        assert!(pastes.append("t1", 1, "skipped").is_err());
+        pastes.append("t1", 0, "a").unwrap();
+        assert!(pastes.append("t1", 0, "replayed").is_err());
+        assert!(pastes.append("t2", 0, "b").is_err());
+        assert!(pastes.begin("t1".to_string(), 0).is_err());
+
+        pastes.abort("t1").unwrap();
+        assert!(pastes.append("t1", 1, "c").is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut pastes = PasteTransactions::default();
+        for i in 0..MAX_OPEN_PASTES {
+            pastes.begin(i.to_string(), 0).unwrap();
+        }
+        assert!(pastes.begin("one more".to_string(), 0).is_err());
+
+        let chunk = "x".repeat(MAX_PASTE_BYTES / 2);
+        pastes.append("0", 0, &chunk).unwrap();
+        pastes.append("1", 0, &chunk).unwrap();
+        let error = pastes.append("2", 0, "x").unwrap_err();
+        assert_eq!(error.code(), "invalid_paste");
+    }
+}
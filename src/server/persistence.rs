@@ -0,0 +1,278 @@
+//! Persists a document to a segmented operation log on disk.
+//!
+//! The server restores the main document from `OPLOG_DIR` at startup and then
+//! runs [`spawn_persistence`], which appends new operations to the log at a
+//! fixed interval and writes a checkpoint whenever enough segments have
+//! accumulated. Checkpoints compact the log, so a long-lived document uses a
+//! bounded amount of disk. With `OPLOG_MAX_DEPTH` set, a checkpoint is also
+//! written once more operations than that were logged after the last one,
+//! and the history before it is dropped, which bounds the operations
+//! replayed on restart. See [`crate::crdt::oplog`] for the file layout.
+//!
+//! The task compares the live document with a copy holding exactly what has
+//! been logged, so edits need no hooks of their own, at the cost of keeping
+//! that copy in memory.
+//!
+//! With `OPLOG_AUDIT` set, every persisted batch is also chained into the
+//! log's audit file (see [`crate::crdt::audit`]), which
+//! `GET /admin/audit` verifies.
+//!
+//! When the log cannot be written, the document keeps serving: operations not
+//! logged yet stay queued in memory and are retried at every tick, and the
+//! document's [`StorageHealth`] reports the failure to `/healthz` and
+//! `/readyz`. Once more than a bound of operations are queued
+//! (`OPLOG_MAX_PENDING`), edits are refused with `storage_unavailable` rather
+//! than accepted without durability, until the log can be written again.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tracing::{error, info, warn};
+
+use crate::crdt::{RGA, ReplicaId, SegmentConfig, SegmentedLog};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::DocumentState;
+
+/// Operations queued while the log cannot be written before edits are
+/// refused, when `OPLOG_MAX_PENDING` is not set
+pub const DEFAULT_MAX_PENDING: usize = 100_000;
+
+/// Whether a document's operations are reaching its log
+#[derive(Default)]
+pub struct StorageHealth {
+    state: Mutex<StorageState>,
+}
+
+#[derive(Default)]
+struct StorageState {
+    /// When writing the log started failing, if it is failing
+    failing_since: Option<Instant>,
+    last_error: Option<String>,
+    /// Operations not logged as of the last attempt
+    pending: usize,
+    /// Pending operations beyond which edits are refused
+    max_pending: Option<usize>,
+}
+
+impl StorageHealth {
+    /// Refuse edits once more than `max` operations wait to be logged, or
+    /// never with `None`
+    pub fn set_max_pending(&self, max: Option<usize>) {
+        self.state.lock().max_pending = max;
+    }
+
+    /// Returns true if the last attempt to write the log failed
+    pub fn is_degraded(&self) -> bool {
+        self.state.lock().failing_since.is_some()
+    }
+
+    /// The error the last attempt failed with, if it failed
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().last_error.clone()
+    }
+
+    /// Operations waiting to be logged as of the last attempt
+    pub fn pending(&self) -> usize {
+        self.state.lock().pending
+    }
+
+    /// Fail if so many operations wait to be logged that no more may be
+    /// accepted
+    pub fn admit_write(&self) -> ServerResult {
+        let state = self.state.lock();
+        match state.max_pending {
+            Some(max) if state.failing_since.is_some() && state.pending >= max => {
+                Err(ServerError::StorageUnavailable)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record an attempt to write the log, leaving `pending` operations
+    /// unlogged
+    fn record(&self, result: &std::io::Result<()>, pending: usize) {
+        let mut state = self.state.lock();
+        state.pending = pending;
+        match result {
+            Ok(()) => {
+                if let Some(since) = state.failing_since.take() {
+                    info!(
+                        "Operation log writable again after {} s",
+                        since.elapsed().as_secs()
+                    );
+                }
+                state.last_error = None;
+            }
+            Err(e) => {
+                if state.failing_since.is_none() {
+                    warn!("Operation log unwritable, queueing operations in memory");
+                    state.failing_since = Some(Instant::now());
+                }
+                state.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Opens the log in `dir` and rebuilds the document it holds.
+pub fn restore(
+    dir: impl Into<PathBuf>,
+    replica_id: ReplicaId,
+    config: SegmentConfig,
+) -> std::io::Result<(RGA, SegmentedLog)> {
+    let log = SegmentedLog::open(dir, config)?;
+    let rga = log.restore(replica_id)?;
+    info!(
+        "Restored {} characters from {} logged operations",
+        rga.visible_node_count(),
+        log.position()
+    );
+    Ok((rga, log))
+}
+
+/// The document as logged so far
+struct Logged {
+    rga: RGA,
+    /// [`SegmentedLog::rewrites`] when `rga` was last in step with the log
+    rewrites: u64,
+    /// Operations of the document found missing from the log by the last
+    /// attempt to append them, and not appended
+    pending: usize,
+}
+
+/// Appends what `rga` gained over `logged` and checkpoints when due
+fn persist(rga: &RGA, logged: &mut Logged, log: &mut SegmentedLog) -> std::io::Result<()> {
+    if logged.rewrites != log.rewrites() {
+        // The log was pseudonymized, start again from what it now holds
+        logged.rga = log.restore(0)?;
+        logged.rewrites = log.rewrites();
+    }
+    let entries = rga.log_entries_since(&logged.rga);
+    if !entries.is_empty() {
+        logged.pending = entries.len();
+        log.append(&entries)?;
+        logged.pending = 0;
+        for entry in &entries {
+            // The entries were just read from a superset of `logged`
+            let _ = logged.rga.apply_log_entry(entry);
+        }
+    }
+    // Also checked without new operations, so a log restored with more
+    // history than its policy allows is checkpointed right away
+    if let Some(position) = log.checkpoint_if_due(&logged.rga)? {
+        info!("Checkpointed the document at operation {}", position);
+    }
+    Ok(())
+}
+
+/// Logs the document's operations every `interval`.
+///
+/// `log` must hold the document's current state, as it does right after
+/// [`restore`]. It stays shared so that the audit can be verified while the
+/// task runs.
+pub fn spawn_persistence(
+    document: Arc<DocumentState>,
+    log: Arc<Mutex<SegmentedLog>>,
+    interval: Duration,
+) {
+    let logged = Arc::new(Mutex::new(Logged {
+        rga: document.rga.fork(0),
+        rewrites: log.lock().rewrites(),
+        pending: 0,
+    }));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let rga = Arc::clone(&document.rga);
+            let log = Arc::clone(&log);
+            let logged = Arc::clone(&logged);
+            // File IO blocks, so it runs off the async workers
+            let task = tokio::task::spawn_blocking(move || {
+                let mut logged = logged.lock();
+                let result = persist(&rga, &mut logged, &mut log.lock());
+                (result, logged.pending)
+            });
+            let Ok((result, pending)) = task.await else {
+                error!("Persistence task panicked, no longer logging operations");
+                // Nothing will be logged anymore, so no edit is admitted
+                document.storage.record(
+                    &Err(std::io::Error::other("persistence task panicked")),
+                    usize::MAX,
+                );
+                return;
+            };
+            if let Err(e) = &result {
+                error!("Failed to persist operations: {}", e);
+            }
+            document.storage.record(&result, pending);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::documents::{AppState, DocumentRegistry};
+    use crate::server::health::{healthz, readyz};
+    use axum::{extract::State, http::StatusCode};
+
+    #[tokio::test]
+    async fn test_degrades_while_the_log_cannot_be_written() {
+        let dir = std::env::temp_dir().join(format!("crdt-rga-degraded-{}", std::process::id()));
+        let (rga, mut log) = restore(&dir, 1, SegmentConfig::default()).unwrap();
+        let state: AppState = Arc::new(DocumentRegistry::new(rga));
+        state.set_reject_documents_when_degraded(true);
+        let main = state.main();
+        main.storage.set_max_pending(Some(3));
+        let mut logged = Logged {
+            rga: main.rga.fork(0),
+            rewrites: log.rewrites(),
+            pending: 0,
+        };
+
+        // Operations stay queued while the log's directory is gone
+        std::fs::remove_dir_all(&dir).unwrap();
+        main.rga
+            .insert_str_after(main.rga.sentinel_start_id(), "hi")
+            .unwrap();
+        let result = persist(&main.rga, &mut logged, &mut log);
+        assert!(result.is_err());
+        main.storage.record(&result, logged.pending);
+        assert_eq!(healthz(State(state.clone())).await.status, "degraded");
+        let (status, ready) = readyz(State(state.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready.storage.pending_operations, 2);
+        assert!(ready.storage.error.is_some());
+        assert!(matches!(
+            state.create("notes"),
+            Err(ServerError::StorageUnavailable)
+        ));
+        main.admit_edit(true).unwrap();
+
+        // Past the bound, edits are refused instead of risking more
+        main.rga.append('!').unwrap();
+        let result = persist(&main.rga, &mut logged, &mut log);
+        main.storage.record(&result, logged.pending);
+        assert!(matches!(
+            main.admit_edit(false),
+            Err(ServerError::StorageUnavailable)
+        ));
+
+        // Once the log is writable again, the queue drains into it
+        std::fs::create_dir_all(&dir).unwrap();
+        let result = persist(&main.rga, &mut logged, &mut log);
+        assert!(result.is_ok());
+        main.storage.record(&result, logged.pending);
+        assert_eq!(healthz(State(state.clone())).await.status, "ok");
+        main.admit_edit(true).unwrap();
+        state.create("notes").unwrap();
+        drop(log);
+        let (restored, _) = restore(&dir, 1, SegmentConfig::default()).unwrap();
+        assert_eq!(restored.to_string(), "hi!");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! Publishing documents.
+//!
+//! `POST /docs/{id}/publish?at=<version>` registers a read-only copy of a
+//! document's text at a past version, or at its current version without `at`. The copy is a document of its own: it can be viewed, exported
+//! and forked like any other, but not edited.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::crdt::ReplicaId;
+use crate::server::documents::AppState;
+use crate::server::error::ServerResult;
+use crate::server::tenants::TenantScope;
+
+/// Query parameters of a publish request
+#[derive(Deserialize)]
+pub struct PublishParams {
+    /// Version to publish, the current one if absent
+    pub at: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PublishedInfo {
+    /// ID of the published copy
+    pub id: String,
+    /// ID of the document that was published
+    pub source: String,
+    /// The version of it the copy holds
+    pub version: u64,
+    /// Replica ID the copy was written as
+    pub replica_id: ReplicaId,
+}
+
+/// Publish a document
+#[utoipa::path(
+    post,
+    path = "/docs/{id}/publish",
+    tag = "documents",
+    params(
+        ("id" = String, Path, description = "Document to publish"),
+        ("at" = Option<u64>, Query, description = "Version to publish, the current one if absent"),
+    ),
+    responses(
+        (status = 201, description = "Published", body = PublishedInfo),
+        (status = 404, description = "No such document, or the version is unknown or purged"),
+    )
+)]
+pub async fn publish_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+    Query(params): Query<PublishParams>,
+) -> ServerResult<(StatusCode, Json<PublishedInfo>)> {
+    scope.check(&id)?;
+    let document = state.publish(&id, params.at).await?;
+    let version = document
+        .published
+        .as_ref()
+        .map_or(0, |published| published.version);
+    Ok((
+        StatusCode::CREATED,
+        Json(PublishedInfo {
+            replica_id: document.state.rga.replica_id(),
+            version,
+            source: id,
+            id: document.id,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::crdt::RGA;
+    use crate::server::acl::Permission;
+    use crate::server::documents::{DocumentRegistry, MAIN_DOCUMENT};
+    use crate::server::error::ServerError;
+    use crate::server::websocket::insert_text;
+
+    #[tokio::test]
+    async fn test_publishes_a_past_version_read_only() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let main = state.main();
+        for (position, text) in [(0, "draft"), (5, " two")] {
+            let _edit = main.begin_edit().await;
+            insert_text(&main.rga, position, text).unwrap();
+            main.record_edit();
+        }
+
+        let published = state.publish(MAIN_DOCUMENT, Some(1)).await.unwrap();
+        assert_eq!(published.id, "main-v1");
+        assert_eq!(published.state.rga.to_string(), "draft");
+        assert_eq!(published.permission_for(None), Permission::Comment);
+        assert!(matches!(
+            published.ensure_editable(),
+            Err(ServerError::Published(_))
+        ));
+
+        let again = state.publish(MAIN_DOCUMENT, Some(1)).await.unwrap();
+        assert!(Arc::ptr_eq(&again.state, &published.state));
+        let current = state.publish(MAIN_DOCUMENT, None).await.unwrap();
+        assert_eq!(current.state.rga.to_string(), "draft two");
+        assert!(matches!(
+            state.publish(MAIN_DOCUMENT, Some(9)).await,
+            Err(ServerError::UnknownVersion { version: 9, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rebuilds_versions_until_their_tombstones_are_purged() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let main = state.main();
+        for round in 0..100 {
+            let _edit = main.begin_edit().await;
+            if round % 2 == 0 {
+                insert_text(&main.rga, 0, "ab").unwrap();
+            } else {
+                main.rga.delete_at(0).unwrap();
+            }
+            main.record_edit();
+        }
+
+        let oldest = state.publish(MAIN_DOCUMENT, Some(1)).await.unwrap();
+        assert_eq!(oldest.state.rga.to_string(), "ab");
+        let second = state.publish(MAIN_DOCUMENT, Some(2)).await.unwrap();
+        assert_eq!(second.state.rga.to_string(), "b");
+        assert_eq!(main.text_at(100).await.unwrap(), "b".repeat(50));
+        assert_eq!(main.text_at(0).await.unwrap(), "");
+
+        assert_eq!(state.purge_before(MAIN_DOCUMENT, 11).await.unwrap(), 5);
+        assert!(matches!(
+            state.publish(MAIN_DOCUMENT, Some(3)).await,
+            Err(ServerError::UnknownVersion { version: 3, .. })
+        ));
+        assert_eq!(main.text_at(10).await.unwrap(), "b".repeat(5));
+        assert_eq!(main.text_at(100).await.unwrap(), "b".repeat(50));
+    }
+}
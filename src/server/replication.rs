@@ -0,0 +1,440 @@
+//! Forced re-replication of whole documents to named peers.
+//!
+//! Sessions keep replicas in step one edit at a time. Bootstrapping a new
+//! region, or recovering a replica whose state was lost or corrupted, instead
+//! needs the whole document: `POST /admin/docs/{id}/replicate?peer=<name>`
+//! sends it to a peer configured with `REPLICATION_PEERS`, in two steps:
+//!
+//! 1. the document's snapshot, taken with no edit in flight, which carries
+//!    every node and tombstone;
+//! 2. an operation log of the edits made while the snapshot was in transit,
+//!    so the peer is caught up to the moment the push finished.
+//!
+//! The peer receives both at `PUT /admin/docs/{id}/replica`, creating the
+//! document if it does not host it and merging into it otherwise. Merging is
+//! idempotent, so a push can be repeated safely, and a peer's own edits
+//! survive it. `GET /admin/docs/{id}/replica` returns the first step, for
+//! copying a document by hand.
+//!
+//! Peers are reached over plain HTTP, authenticated with the admin token set
+//! by `REPLICATION_TOKEN`, which the peer must accept as its `ADMIN_TOKEN`.
+//! Put a TLS-terminating proxy in front of peers in other networks.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::crdt::{RGA, encode_op_log, parse_op_log};
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, Document, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::{PeerMessage, RGAResponse};
+
+/// How long a peer has to answer each step of a push
+const PEER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A server documents are re-replicated to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    /// Base URL of the peer, `http://host[:port][/prefix]`
+    pub url: String,
+    /// Admin token the peer accepts, if it requires one
+    pub token: Option<String>,
+}
+
+/// One step of a re-replication
+#[derive(Serialize, Deserialize, ToSchema, Default, Debug)]
+pub struct ReplicationBundle {
+    /// The whole document, as written by `RGA::to_snapshot`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Operations to apply after the snapshot, as an operation log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops: Option<String>,
+}
+
+/// What receiving a bundle changed
+#[derive(Serialize, Deserialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct ReplicaMerged {
+    /// Whether the document was created to receive the bundle
+    pub created: bool,
+    /// Nodes inserted or deleted by the snapshot
+    pub merged: usize,
+    /// Operations applied from the operation log, including those already
+    /// held
+    pub ops: usize,
+}
+
+/// The outcome of a push to a peer
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ReplicationReport {
+    pub document: String,
+    pub peer: String,
+    /// Size of the snapshot sent, in bytes
+    pub snapshot_bytes: usize,
+    /// Operations made during the transfer and sent after the snapshot
+    pub catch_up_ops: usize,
+}
+
+impl DocumentRegistry {
+    /// Re-replicate documents to `peer` under `name`, replacing any peer
+    /// registered under it
+    pub fn add_peer(&self, name: &str, peer: Peer) {
+        self.peers.write().insert(name.to_string(), peer);
+    }
+
+    /// The peer registered under `name`
+    pub fn peer(&self, name: &str) -> ServerResult<Peer> {
+        self.peers
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ServerError::UnknownPeer(name.to_string()))
+    }
+
+    /// Merge a bundle into document `id`, creating it if it is not hosted.
+    ///
+    /// The bundle is applied while the document is held exclusively, and its
+    /// sessions are sent the resulting text.
+    pub async fn receive_replica(
+        &self,
+        id: &str,
+        bundle: &ReplicationBundle,
+    ) -> ServerResult<ReplicaMerged> {
+        // Parsed up front, so a malformed bundle changes nothing
+        let ops = bundle.ops.as_deref().map(parse_op_log).transpose()?;
+        let (document, created) = match self.get(id) {
+            Ok(document) => (document, false),
+            Err(ServerError::UnknownDocument(_)) => (self.create(id)?, true),
+            Err(e) => return Err(e),
+        };
+        document.ensure_editable()?;
+        document.state.storage.admit_write()?;
+
+        let (received, content, version) = {
+            let _exclusive = document.state.exclusive().await;
+            let rga = &document.state.rga;
+            let merged = match &bundle.snapshot {
+                Some(snapshot) => rga.merge_snapshot(snapshot)?,
+                None => 0,
+            };
+            let ops = ops.unwrap_or_default();
+            for entry in &ops {
+                rga.apply_log_entry(entry)?;
+            }
+            let received = ReplicaMerged {
+                created,
+                merged,
+                ops: ops.len(),
+            };
+            (received, rga.to_string(), document.state.record_edit())
+        };
+
+        document.state.publish(PeerMessage {
+            origin: "replication".to_string(),
+            response: RGAResponse {
+                response_type: "update".to_string(),
+                content,
+                position: None,
+                session_id: None,
+                decorations: None,
+                version: Some(version),
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+        Ok(received)
+    }
+
+    /// Send the whole of document `id` to the peer registered as `peer`,
+    /// see the [module documentation](self)
+    pub async fn replicate_to(&self, id: &str, peer: &str) -> ServerResult<ReplicationReport> {
+        let document = self.get(id)?;
+        let target = self.peer(peer)?;
+
+        let (snapshot, base) = snapshot_of(&document).await?;
+        let snapshot_bytes = snapshot.len();
+        send(
+            peer,
+            &target,
+            id,
+            &ReplicationBundle {
+                snapshot: Some(snapshot),
+                ops: None,
+            },
+        )
+        .await?;
+
+        let catch_up = {
+            let _exclusive = document.state.exclusive().await;
+            document.state.rga.log_entries_since(&base)
+        };
+        if !catch_up.is_empty() {
+            send(
+                peer,
+                &target,
+                id,
+                &ReplicationBundle {
+                    snapshot: None,
+                    ops: Some(encode_op_log(&catch_up)),
+                },
+            )
+            .await?;
+        }
+        info!(
+            "Re-replicated {} to {} ({} bytes, {} operations caught up)",
+            id,
+            peer,
+            snapshot_bytes,
+            catch_up.len()
+        );
+        Ok(ReplicationReport {
+            document: id.to_string(),
+            peer: peer.to_string(),
+            snapshot_bytes,
+            catch_up_ops: catch_up.len(),
+        })
+    }
+}
+
+/// The document's snapshot with no edit in flight, and the copy of the
+/// document it holds
+async fn snapshot_of(document: &Document) -> ServerResult<(String, RGA)> {
+    let _exclusive = document.state.exclusive().await;
+    let rga = &document.state.rga;
+    let snapshot = rga.to_snapshot();
+    let base = RGA::from_snapshot(rga.replica_id(), &snapshot)?;
+    Ok((snapshot, base))
+}
+
+/// Sends one bundle to `PUT {url}/admin/docs/{id}/replica` over HTTP/1.1
+async fn send(name: &str, peer: &Peer, id: &str, bundle: &ReplicationBundle) -> ServerResult {
+    let failed = |reason: String| ServerError::PeerFailed {
+        peer: name.to_string(),
+        reason,
+    };
+    let rest = peer
+        .url
+        .strip_prefix("http://")
+        .ok_or_else(|| failed("only http:// peers are supported".to_string()))?;
+    let (authority, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    let prefix = prefix.trim_end_matches('/');
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+
+    let body = serde_json::to_vec(bundle).map_err(ServerError::Serialization)?;
+    let mut request = format!(
+        "PUT {}/admin/docs/{}/replica HTTP/1.1\r\nHost: {authority}\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{prefix}")
+        },
+        encode_path_segment(id),
+        body.len()
+    );
+    if let Some(token) = &peer.token {
+        request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    request.push_str("\r\n");
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(PEER_TIMEOUT, exchange)
+        .await
+        .map_err(|_| failed("timed out".to_string()))?
+        .map_err(|e| failed(e.to_string()))?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| failed("malformed response".to_string()))?;
+    if !(200..300).contains(&status) {
+        return Err(failed(format!("answered {status}: {}", body.trim())));
+    }
+    Ok(())
+}
+
+/// Percent-encodes everything but unreserved characters and `:`, which
+/// tenant namespaces use
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~:".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Query parameters of the replicate endpoint
+#[derive(Deserialize)]
+pub struct ReplicateParams {
+    /// Name of the peer to send the document to
+    pub peer: String,
+}
+
+/// Re-replicate a document to a peer
+#[utoipa::path(
+    post,
+    path = "/admin/docs/{id}/replicate",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "Document to send"),
+        ("peer" = String, Query, description = "Name of a peer from `REPLICATION_PEERS`"),
+    ),
+    responses(
+        (status = 200, description = "The peer holds the whole document", body = ReplicationReport),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document or peer"),
+        (status = 502, description = "The peer could not be reached or refused the document"),
+    )
+)]
+pub async fn replicate_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<ReplicateParams>,
+    headers: HeaderMap,
+) -> ServerResult<Json<ReplicationReport>> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.replicate_to(&id, &params.peer).await?))
+}
+
+/// Export a document for re-replication
+#[utoipa::path(
+    get,
+    path = "/admin/docs/{id}/replica",
+    tag = "admin",
+    params(("id" = String, Path, description = "Document to export")),
+    responses(
+        (status = 200, description = "The document's snapshot", body = ReplicationBundle),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document"),
+    )
+)]
+pub async fn export_replica(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> ServerResult<Json<ReplicationBundle>> {
+    authorize(&state, &headers)?;
+    let (snapshot, _) = snapshot_of(&state.get(&id)?).await?;
+    Ok(Json(ReplicationBundle {
+        snapshot: Some(snapshot),
+        ops: None,
+    }))
+}
+
+/// Merge a re-replicated document
+#[utoipa::path(
+    put,
+    path = "/admin/docs/{id}/replica",
+    tag = "admin",
+    params(("id" = String, Path, description = "Document to merge into, created if missing")),
+    request_body = ReplicationBundle,
+    responses(
+        (status = 200, description = "Merged", body = ReplicaMerged),
+        (status = 400, description = "Malformed snapshot or operation log"),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 409, description = "The document is published or frozen"),
+    )
+)]
+pub async fn receive_replica(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(bundle): Json<ReplicationBundle>,
+) -> ServerResult<Json<ReplicaMerged>> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.receive_replica(&id, &bundle).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::server::routes::create_router;
+
+    #[tokio::test]
+    async fn test_replicates_documents_to_peers() {
+        let source = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let document = source.create("notes").unwrap();
+        let rga = &document.state.rga;
+        let hello = rga
+            .insert_str_after(rga.sentinel_start_id(), "hello")
+            .unwrap();
+        rga.delete(hello[0]).unwrap();
+
+        let target = Arc::new(DocumentRegistry::new(RGA::new(100)));
+        target.set_admin_token(Some("secret".to_string()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = create_router().with_state(target.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        source.add_peer(
+            "eu",
+            Peer {
+                url: format!("http://{address}/"),
+                token: Some("wrong".to_string()),
+            },
+        );
+        assert!(matches!(
+            source.replicate_to("notes", "eu").await,
+            Err(ServerError::PeerFailed { .. })
+        ));
+        assert!(matches!(
+            source.replicate_to("notes", "us").await,
+            Err(ServerError::UnknownPeer(_))
+        ));
+
+        source.add_peer(
+            "eu",
+            Peer {
+                url: format!("http://{address}"),
+                token: Some("secret".to_string()),
+            },
+        );
+        let report = source.replicate_to("notes", "eu").await.unwrap();
+        assert_eq!(report.catch_up_ops, 0);
+        let replica = target.get("notes").unwrap();
+        assert_eq!(replica.state.rga.to_string(), "ello");
+        assert_eq!(replica.state.rga.to_snapshot(), rga.to_snapshot());
+
+        // Pushing again is harmless and keeps the peer's own edits
+        let own = &replica.state.rga;
+        own.insert_after(hello[4], '!').unwrap();
+        source.replicate_to("notes", "eu").await.unwrap();
+        assert_eq!(own.to_string(), "ello!");
+        assert!(own.validate().is_ok());
+    }
+}
@@ -0,0 +1,216 @@
+//! Purging old tombstones.
+//!
+//! Every document stamps the nodes an edit deletes with the edit's version and
+//! time. With a retention window configured (`TOMBSTONE_RETENTION_SECS`),
+//! [`spawn_retention`] purges the tombstones deleted longer ago than the
+//! window; `POST /admin/docs/{id}/purge?before=<version>` purges those deleted
+//! before a version on demand. Purging never changes the visible text, and
+//! marks into purged nodes are remapped.
+//!
+//! A tombstone may only be purged once no replica still needs it. Sessions
+//! edit as the server's replica, so that holds for every deletion the server
+//! has applied, except in documents that are forks or have forks: a merge
+//! between them refers to their tombstones, so they keep all of them.
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::crdt::{RGA, UniqueId};
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, Document, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::websocket::DocumentState;
+
+/// The nodes one edit deleted
+pub(crate) struct Deletions {
+    pub(crate) at: Instant,
+    pub(crate) version: u64,
+    pub(crate) ids: Vec<UniqueId>,
+}
+
+impl Deletions {
+    /// The tombstones a document already holds when it is opened, stamped as
+    /// deleted before its first version
+    pub(crate) fn existing(rga: &RGA, at: Instant) -> Option<Deletions> {
+        let ids: Vec<UniqueId> = rga
+            .all_nodes()
+            .into_iter()
+            .filter(|node| node.is_deleted)
+            .map(|node| node.id)
+            .collect();
+        (!ids.is_empty()).then_some(Deletions {
+            at,
+            version: 0,
+            ids,
+        })
+    }
+}
+
+impl DocumentState {
+    /// Purge the tombstones of the oldest deletions while `expired` holds.
+    ///
+    /// The versions before the last purged deletion can no longer be rebuilt.
+    async fn purge(&self, expired: impl Fn(&Deletions) -> bool) -> usize {
+        let _exclusive = self.exclusive().await;
+        let mut ids = Vec::new();
+        let mut floor = None;
+        {
+            let mut deletions = self.deletions.lock();
+            while let Some(deleted) = deletions.pop_front_if(|deleted| expired(deleted)) {
+                ids.extend(deleted.ids);
+                floor = Some(deleted.version);
+            }
+        }
+        let Some(floor) = floor else {
+            return 0;
+        };
+        self.history_floor.fetch_max(floor, Ordering::AcqRel);
+        let mut inserted = self.inserted.lock();
+        for id in &ids {
+            inserted.remove(id);
+        }
+        drop(inserted);
+        self.rga.purge_tombstones(ids).len()
+    }
+}
+
+impl DocumentRegistry {
+    /// Fail if merges between the document and its forks may still refer to
+    /// its nodes
+    pub(crate) fn ensure_unforked(&self, document: &Document) -> ServerResult {
+        if document.upstream.is_some() || !self.forks_of(&document.id)?.is_empty() {
+            return Err(ServerError::Forked(document.id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Purge a document's tombstones deleted before `version`
+    pub async fn purge_before(&self, id: &str, version: u64) -> ServerResult<usize> {
+        let document = self.get(id)?;
+        self.ensure_unforked(&document)?;
+        Ok(document
+            .state
+            .purge(|deleted| deleted.version < version)
+            .await)
+    }
+
+    /// Purge the tombstones deleted longer than `window` ago, in every
+    /// document that is neither a fork nor forked
+    pub async fn purge_expired(&self, window: Duration) -> usize {
+        let Some(cutoff) = Instant::now().checked_sub(window) else {
+            return 0;
+        };
+        let mut purged = 0;
+        for document in self.documents() {
+            if self.ensure_unforked(&document).is_ok() {
+                purged += document.state.purge(|deleted| deleted.at <= cutoff).await;
+            }
+        }
+        purged
+    }
+}
+
+/// Purges tombstones deleted longer than `window` ago, checking four times per
+/// window and at least every minute
+pub fn spawn_retention(state: AppState, window: Duration) {
+    let interval = (window / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let purged = state.purge_expired(window).await;
+            if purged > 0 {
+                info!("Purged {} tombstones past the retention window", purged);
+            }
+        }
+    });
+}
+
+/// Query parameters of the purge endpoint
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    /// Tombstones deleted before this version are purged
+    pub before: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PurgeResponse {
+    /// Number of tombstones purged
+    pub purged: usize,
+}
+
+/// Purge a document's tombstones deleted before a version
+#[utoipa::path(
+    post,
+    path = "/admin/docs/{id}/purge",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "Document to purge"),
+        ("before" = u64, Query, description = "Tombstones deleted before this version are purged"),
+    ),
+    responses(
+        (status = 200, description = "Purged", body = PurgeResponse),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+        (status = 404, description = "No such document"),
+        (status = 409, description = "The document is a fork or has forks"),
+    )
+)]
+pub async fn purge_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<PurgeParams>,
+    headers: HeaderMap,
+) -> ServerResult<Json<PurgeResponse>> {
+    authorize(&state, &headers)?;
+    let purged = state.purge_before(&id, params.before).await?;
+    Ok(Json(PurgeResponse { purged }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::server::documents::MAIN_DOCUMENT;
+
+    #[tokio::test]
+    async fn test_purges_by_version_and_age() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        let main = state.main();
+        let ids = main
+            .rga
+            .insert_str_after(main.rga.sentinel_start_id(), "abcd")
+            .unwrap();
+        main.record_edit();
+        for id in &ids[1..3] {
+            main.rga.delete(*id).unwrap();
+            main.record_edit();
+        }
+
+        assert_eq!(state.purge_before(MAIN_DOCUMENT, 3).await.unwrap(), 1);
+        assert_eq!(main.rga.total_node_count(), 5);
+        assert_eq!(state.purge_expired(Duration::from_secs(60)).await, 0);
+        assert_eq!(state.purge_expired(Duration::ZERO).await, 1);
+        assert_eq!(main.rga.to_string(), "ad");
+        assert_eq!(main.rga.total_node_count(), 4);
+
+        main.rga.delete(ids[0]).unwrap();
+        main.record_edit();
+        state.fork(MAIN_DOCUMENT).await.unwrap();
+        assert!(matches!(
+            state.purge_before(MAIN_DOCUMENT, 10).await,
+            Err(ServerError::Forked(_))
+        ));
+        assert_eq!(state.purge_expired(Duration::ZERO).await, 0);
+    }
+}
@@ -5,21 +5,49 @@
 
 use axum::{
     Router,
-    extract::{State, ws::WebSocketUpgrade},
-    response::{Json, Response},
-    routing::get,
+    extract::{Path, Query, State, ws::WebSocketUpgrade},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::server::websocket::{AppState, handle_websocket_connection};
+use crate::server::admin::document_structure;
+use crate::server::analytics::document_activity;
+use crate::server::audit::verify_audit;
+use crate::server::documents::{AppState, MAIN_DOCUMENT};
+use crate::server::erasure::pseudonymize_document;
+use crate::server::export::{export_document, import_markdown};
+use crate::server::forks::{divergence, fork_document, list_forks};
+use crate::server::freeze::{freeze_document, unfreeze_document};
+use crate::server::health::{healthz, readyz};
+use crate::server::history::document_history;
+use crate::server::leases::{lease_replica, release_replica_lease, renew_replica_lease};
+use crate::server::merges::{
+    approve_merge_request, get_merge_request, list_merge_requests, open_merge_request,
+    reject_merge_request,
+};
+use crate::server::openapi::openapi_routes;
+use crate::server::publish::publish_document;
+use crate::server::replication::{export_replica, receive_replica, replicate_document};
+use crate::server::retention::purge_document;
+use crate::server::tenants::TenantScope;
+use crate::server::tenants::{create_document, list_tenants, tenant_metrics};
+use crate::server::websocket::handle_websocket_connection;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct HealthResponse {
     pub status: String,
     pub message: String,
 }
 
 /// Basic health check endpoint
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses((status = 200, description = "Server is running", body = HealthResponse))
+)]
 pub async fn health() -> Json<HealthResponse> {
     Json(HealthResponse {
         status: "ok".to_string(),
@@ -27,14 +55,105 @@ pub async fn health() -> Json<HealthResponse> {
     })
 }
 
-/// WebSocket connection handler for collaborative editing
-pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
-    ws.on_upgrade(move |socket| handle_websocket_connection(socket, state))
+/// Query parameters of the editing WebSocket
+#[derive(Deserialize)]
+pub struct ConnectParams {
+    /// Access token looked up in the document's ACL
+    pub token: Option<String>,
+}
+
+/// WebSocket connection handler for collaborative editing of the main document
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(params): Query<ConnectParams>,
+    scope: TenantScope,
+) -> Response {
+    if let Err(e) = scope.check(MAIN_DOCUMENT) {
+        return e.into_response();
+    }
+    let document = state.main();
+    let permission = document.acl.read().permission_for(params.token.as_deref());
+    let capture_dir = state.capture_dir();
+    ws.on_upgrade(move |socket| {
+        handle_websocket_connection(
+            socket,
+            MAIN_DOCUMENT.to_string(),
+            document,
+            permission,
+            capture_dir,
+        )
+    })
+}
+
+/// WebSocket connection handler for collaborative editing of any document
+pub async fn document_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<ConnectParams>,
+    scope: TenantScope,
+) -> Response {
+    match scope.document(&state, &id) {
+        Ok(document) => {
+            let permission = document.permission_for(params.token.as_deref());
+            let capture_dir = state.capture_dir();
+            ws.on_upgrade(move |socket| {
+                handle_websocket_connection(
+                    socket,
+                    document.id,
+                    document.state,
+                    permission,
+                    capture_dir,
+                )
+            })
+        }
+        Err(e) => e.into_response(),
+    }
 }
 
 /// Creates and configures the main application router
 pub fn create_router() -> Router<AppState> {
-    Router::new()
+    let router = Router::new()
         .route("/health", get(health))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .route("/ws", get(ws_handler))
+        .route("/docs/:id", post(create_document))
+        .route("/docs/:id/ws", get(document_ws_handler))
+        .route("/docs/:id/fork", post(fork_document))
+        .route("/docs/:id/forks", get(list_forks))
+        .route("/docs/:id/divergence", get(divergence))
+        .route("/docs/:id/export", get(export_document))
+        .route("/docs/:id/import", post(import_markdown))
+        .route("/docs/:id/history", get(document_history))
+        .route("/docs/:id/activity", get(document_activity))
+        .route("/docs/:id/publish", post(publish_document))
+        .route("/docs/:id/merge-requests", post(open_merge_request))
+        .route("/merge-requests", get(list_merge_requests))
+        .route("/merge-requests/:id", get(get_merge_request))
+        .route("/merge-requests/:id/approve", post(approve_merge_request))
+        .route("/merge-requests/:id/reject", post(reject_merge_request))
+        .route("/replicas", post(lease_replica))
+        .route("/replicas/:id", delete(release_replica_lease))
+        .route("/replicas/:id/renew", post(renew_replica_lease))
+        .route("/tenant", get(tenant_metrics))
+        .route("/admin/docs/:id/structure", get(document_structure))
+        .route("/admin/docs/:id/purge", post(purge_document))
+        .route("/admin/docs/:id/pseudonymize", post(pseudonymize_document))
+        .route("/admin/docs/:id/freeze", post(freeze_document))
+        .route("/admin/docs/:id/unfreeze", post(unfreeze_document))
+        .route(
+            "/admin/docs/:id/replica",
+            get(export_replica).put(receive_replica),
+        )
+        .route("/admin/docs/:id/replicate", post(replicate_document))
+        .route("/admin/tenants", get(list_tenants))
+        .route("/admin/audit", get(verify_audit))
+        .merge(openapi_routes());
+
+    #[cfg(feature = "graphql")]
+    let router = router.merge(crate::server::graphql::graphql_routes());
+
+    router
 }
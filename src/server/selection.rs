@@ -0,0 +1,152 @@
+//! Selection-aware conflict hints.
+//!
+//! A session shares its selection by sending `presence` with a `length`. The
+//! server anchors the selection to the IDs of the selected characters, and
+//! whenever a peer's edit deletes some of them or inserts text between them
+//! it sends the session a `selection` message with the selection's new bounds
+//! and what changed, so the frontend can adjust its highlight and show a merge
+//! indicator. Text inserted directly before or after the selection stays
+//! outside it. The session's own edits move the selection silently.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::crdt::{Anchor, RGA, UniqueId};
+use crate::server::error::ServerResult;
+
+/// How peers' edits changed a session's selection
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct SelectionChange {
+    /// Visible index of the first selected character
+    pub start: usize,
+    /// Visible index just past the last selected character
+    pub end: usize,
+    /// Number of selected characters peers deleted
+    pub deleted: usize,
+    /// Number of characters peers inserted inside the selection
+    pub inserted: usize,
+}
+
+/// A selection anchored to the characters it covers
+#[derive(Debug)]
+pub struct Selection {
+    /// The selected characters, in document order
+    covered: Vec<UniqueId>,
+}
+
+impl Selection {
+    /// Select `length` visible characters at `position`, or nothing if
+    /// `length` is 0
+    pub fn new(rga: &RGA, position: usize, length: usize) -> ServerResult<Option<Selection>> {
+        let visible = rga.visible_nodes();
+        let covered = position
+            .checked_add(length)
+            .and_then(|end| visible.get(position..end))
+            .ok_or("Selection out of range")?;
+        Ok((length > 0).then(|| Selection {
+            covered: covered.iter().map(|node| node.id).collect(),
+        }))
+    }
+
+    /// Re-anchor the selection after edits.
+    ///
+    /// Returns how the edits changed the selected text, if they did. A
+    /// selection whose characters were all deleted collapses and is then
+    /// empty.
+    pub fn refresh(&mut self, rga: &RGA) -> Option<SelectionChange> {
+        if self.covered.is_empty() {
+            return None;
+        }
+        let visible: Vec<UniqueId> = rga.visible_nodes().iter().map(|node| node.id).collect();
+        let covered: HashSet<UniqueId> = self.covered.iter().copied().collect();
+
+        // Visible text is in document order, so whatever lies between the
+        // first and last surviving characters is still selected
+        let first = visible.iter().position(|id| covered.contains(id));
+        let last = visible.iter().rposition(|id| covered.contains(id));
+        let (start, selected) = match (first, last) {
+            (Some(first), Some(last)) => (first, &visible[first..=last]),
+            _ => {
+                let start = rga
+                    .resolve_anchor(&Anchor::before(self.covered[0]))
+                    .unwrap_or_default();
+                (start, &[][..])
+            }
+        };
+
+        let surviving = selected.iter().filter(|id| covered.contains(id)).count();
+        let change = SelectionChange {
+            start,
+            end: start + selected.len(),
+            deleted: self.covered.len() - surviving,
+            inserted: selected.len() - surviving,
+        };
+        if change.deleted == 0 && change.inserted == 0 {
+            return None;
+        }
+        self.covered = selected.to_vec();
+        Some(change)
+    }
+
+    /// Returns true once every selected character was deleted
+    pub fn is_empty(&self) -> bool {
+        self.covered.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::Node;
+
+    #[test]
+    fn test_reports_edits_inside_the_selection() {
+        let rga = RGA::new(1);
+        let mut ids = vec![rga.sentinel_start_id()];
+        for character in "abcdef".chars() {
+            ids.push(rga.insert_after(ids[ids.len() - 1], character).unwrap());
+        }
+        ids.remove(0);
+        let mut selection = Selection::new(&rga, 1, 4).unwrap().unwrap();
+        assert!(Selection::new(&rga, 1, 0).unwrap().is_none());
+        assert!(Selection::new(&rga, 4, 3).is_err());
+        assert!(Selection::new(&rga, 1, usize::MAX).is_err());
+
+        // Edits outside the selection only move it
+        rga.delete(ids[0]).unwrap();
+        assert_eq!(selection.refresh(&rga), None);
+
+        rga.delete(ids[2]).unwrap();
+        rga.delete(ids[4]).unwrap();
+        assert_eq!(
+            selection.refresh(&rga),
+            Some(SelectionChange {
+                start: 0,
+                end: 2,
+                deleted: 2,
+                inserted: 0,
+            })
+        );
+
+        // A concurrent insert from another replica lands inside
+        rga.apply_remote_op(Node::with_origin(UniqueId::new(7, 2), 'x', ids[1]));
+        assert_eq!(
+            selection.refresh(&rga),
+            Some(SelectionChange {
+                start: 0,
+                end: 3,
+                deleted: 0,
+                inserted: 1,
+            })
+        );
+
+        for id in [ids[1], ids[3]] {
+            rga.delete(id).unwrap();
+        }
+        let change = selection.refresh(&rga).unwrap();
+        assert_eq!((change.deleted, change.inserted), (2, 0));
+        assert_eq!(rga.to_string(), "xf");
+        assert!(!selection.is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+//! Binding and running the server.
+//!
+//! Binding is retried with bounded exponential backoff, so a restart that races
+//! the previous process releasing the port does not fail outright.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use tokio::net::TcpListener;
+use tracing::warn;
+
+use crate::server::error::ServerResult;
+
+/// Number of bind attempts before giving up
+const BIND_ATTEMPTS: u32 = 5;
+/// Delay before the first retry; doubled after every failed attempt
+const BIND_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Upper bound on the delay between attempts
+const BIND_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Bind a TCP listener, retrying with bounded exponential backoff
+pub async fn bind_with_retry(addr: SocketAddr) -> ServerResult<TcpListener> {
+    let mut backoff = BIND_INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) if attempt < BIND_ATTEMPTS => {
+                warn!(
+                    "Failed to bind {} (attempt {}/{}): {}; retrying in {:?}",
+                    addr, attempt, BIND_ATTEMPTS, e, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(BIND_MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Bind `addr` and serve `app` until the server fails
+pub async fn serve(addr: SocketAddr, app: Router) -> ServerResult {
+    let listener = bind_with_retry(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
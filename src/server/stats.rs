@@ -0,0 +1,103 @@
+//! Document statistics for info panels.
+//!
+//! A session sends `{"type": "stats"}` and receives the document's
+//! [`DocumentStats`] in a `stats` message, at the version it describes, so a
+//! frontend can show the length, history size and participants of a document
+//! without a separate REST request.
+//!
+//! With the `profiling` feature the stats also report the time the document
+//! spent in each of its hot paths (see [`crate::crdt::profile`]), so a slow
+//! document can be diagnosed from the browser console.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::crdt::ReplicaId;
+use crate::server::websocket::DocumentState;
+
+/// Figures describing a document
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DocumentStats {
+    /// Number of visible characters
+    pub length: usize,
+    /// Number of deleted characters the document still keeps
+    pub tombstones: usize,
+    /// Number of sessions and bots connected to the document
+    pub participants: usize,
+    /// The newest Lamport counter of each replica whose insertions the
+    /// document holds, see [`crate::RGA::version_vector`]
+    pub version_vector: BTreeMap<ReplicaId, u64>,
+    /// Calls of each hot path of the document and the time spent in them
+    #[cfg(feature = "profiling")]
+    pub profile: BTreeMap<&'static str, PathTiming>,
+}
+
+/// Calls of a hot path and the time spent in them
+#[cfg(feature = "profiling")]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathTiming {
+    /// Number of completed calls
+    pub calls: u64,
+    /// Total time spent in them, in microseconds
+    pub total_micros: u64,
+}
+
+impl DocumentState {
+    /// The document's statistics
+    pub fn stats(&self) -> DocumentStats {
+        let length = self.rga.visible_node_count();
+        DocumentStats {
+            length,
+            // Both sentinels are counted as nodes
+            tombstones: self.rga.total_node_count() - length - 2,
+            participants: self.peers.receiver_count(),
+            version_vector: self.rga.version_vector(),
+            #[cfg(feature = "profiling")]
+            profile: self
+                .rga
+                .profile()
+                .iter()
+                .map(|(path, stats)| {
+                    let timing = PathTiming {
+                        calls: stats.calls,
+                        total_micros: stats.total.as_micros() as u64,
+                    };
+                    (path.name(), timing)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::RGA;
+
+    #[test]
+    fn test_stats_describe_the_document() {
+        let document = DocumentState::new(RGA::new(3));
+        let _session = document.peers.subscribe();
+        let rga = &document.rga;
+        let ids = rga
+            .insert_str_after(rga.sentinel_start_id(), "hello")
+            .unwrap();
+        rga.delete(ids[0]).unwrap();
+
+        let stats = document.stats();
+        #[cfg(feature = "profiling")]
+        assert_eq!(stats.profile["insert"].calls, 1);
+        assert_eq!(
+            stats,
+            DocumentStats {
+                length: 4,
+                tombstones: 1,
+                participants: 1,
+                version_vector: BTreeMap::from([(3, ids[4].counter())]),
+                #[cfg(feature = "profiling")]
+                profile: stats.profile.clone(),
+            }
+        );
+    }
+}
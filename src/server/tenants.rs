@@ -0,0 +1,452 @@
+//! Serving several customers from one server.
+//!
+//! Once a [`Tenant`] is registered with [`DocumentRegistry::add_tenant`], the
+//! server is multi-tenant: requests to the document and merge request
+//! endpoints must present a tenant's token, as `Authorization: Bearer
+//! <token>` or, for WebSockets, `?tenant_token=<token>`, or are refused with
+//! `401`. A tenant's documents are those whose ID starts with its namespace,
+//! `{tenant}:`; it creates them with `POST /docs/{tenant}:{name}`, and forks
+//! and published copies of them stay in the namespace. Documents outside it,
+//! the main document included, are reported as unknown.
+//!
+//! Each tenant has a [`TenantQuota`] limiting how many documents it may have,
+//! how long each may grow and how many edits per minute it may make across
+//! all of them. Edits past a quota are refused with `429` and counted in the
+//! tenant's metrics, served at `GET /tenant` to the tenant and at `GET
+//! /admin/tenants` to administrators. GraphQL spans every document and is
+//! refused in multi-tenant mode.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::Json,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::crdt::RGA;
+use crate::server::admin::authorize;
+use crate::server::documents::{AppState, Document, DocumentRegistry};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::merges::MergeRequest;
+use crate::server::websocket::DocumentState;
+
+/// Length of the window edits are rate limited over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits on what a tenant may do; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantQuota {
+    /// Documents the tenant may have, forks and published copies included
+    pub max_documents: Option<usize>,
+    /// Characters a document may grow to; edits that would only shrink it are
+    /// still accepted
+    pub max_document_chars: Option<usize>,
+    /// Edits per minute across all of the tenant's documents
+    pub max_edits_per_minute: Option<u64>,
+}
+
+/// A customer of the server, owning the documents in its namespace
+pub struct Tenant {
+    id: String,
+    token: String,
+    quota: TenantQuota,
+    /// Start of the current rate window and the edits made in it
+    window: Mutex<(Instant, u64)>,
+    edits: AtomicU64,
+    throttled: AtomicU64,
+    oversized: AtomicU64,
+}
+
+impl Tenant {
+    /// The tenant's ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The tenant's limits
+    pub fn quota(&self) -> TenantQuota {
+        self.quota
+    }
+
+    /// Returns true if document `id` is in the tenant's namespace
+    pub fn owns(&self, id: &str) -> bool {
+        id.strip_prefix(self.id.as_str())
+            .is_some_and(|rest| rest.starts_with(':'))
+    }
+
+    /// Count an edit of a document holding `rga`, unless it exceeds a quota
+    fn admit_edit(&self, rga: &RGA, grows: bool) -> ServerResult {
+        let mut window = self.window.lock();
+        if window.0.elapsed() >= RATE_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        if self
+            .quota
+            .max_edits_per_minute
+            .is_some_and(|max| window.1 >= max)
+        {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            return Err(ServerError::QuotaExceeded {
+                tenant: self.id.clone(),
+                quota: "edits per minute",
+            });
+        }
+        if grows
+            && let Some(max) = self.quota.max_document_chars
+            && rga.visible_node_count() >= max
+        {
+            self.oversized.fetch_add(1, Ordering::Relaxed);
+            return Err(ServerError::QuotaExceeded {
+                tenant: self.id.clone(),
+                quota: "document size",
+            });
+        }
+        window.1 += 1;
+        self.edits.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// What a tenant holds and did
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct TenantMetrics {
+    pub id: String,
+    /// Documents in the tenant's namespace
+    pub documents: usize,
+    /// Visible characters across those documents
+    pub characters: usize,
+    /// Edits accepted
+    pub edits: u64,
+    /// Edits refused for exceeding the rate quota
+    pub throttled: u64,
+    /// Edits refused because their document reached the size quota
+    pub oversized: u64,
+}
+
+impl DocumentRegistry {
+    /// Register a tenant presenting `token`, making the server multi-tenant.
+    ///
+    /// The ID must not contain `:`, which ends the tenant's namespace.
+    pub fn add_tenant(&self, id: &str, token: &str, quota: TenantQuota) -> Arc<Tenant> {
+        assert!(!id.contains(':'), "tenant IDs cannot contain ':'");
+        let tenant = Arc::new(Tenant {
+            id: id.to_string(),
+            token: token.to_string(),
+            quota,
+            window: Mutex::new((Instant::now(), 0)),
+            edits: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            oversized: AtomicU64::new(0),
+        });
+        self.tenants.write().insert(id.to_string(), tenant.clone());
+        tenant
+    }
+
+    /// Returns true once a tenant is registered
+    pub fn is_multi_tenant(&self) -> bool {
+        !self.tenants.read().is_empty()
+    }
+
+    /// The tenant presenting `token`
+    pub fn tenant_for_token(&self, token: &str) -> Option<Arc<Tenant>> {
+        self.tenants
+            .read()
+            .values()
+            .find(|tenant| tenant.token == token)
+            .cloned()
+    }
+
+    /// The tenant whose namespace holds document `id`
+    pub fn tenant_of(&self, id: &str) -> Option<Arc<Tenant>> {
+        self.tenants
+            .read()
+            .values()
+            .find(|tenant| tenant.owns(id))
+            .cloned()
+    }
+
+    /// Give a document about to be registered under `id` the tenant owning
+    /// it, failing if the tenant may not have another document
+    pub(crate) fn attach_tenant(&self, id: &str, state: &DocumentState) -> ServerResult {
+        let Some(tenant) = self.tenant_of(id) else {
+            return Ok(());
+        };
+        if let Some(max) = tenant.quota.max_documents
+            && self.documents_of(&tenant).len() + self.archived_of(&tenant).len() >= max
+        {
+            return Err(ServerError::QuotaExceeded {
+                tenant: tenant.id.clone(),
+                quota: "documents",
+            });
+        }
+        *state.tenant.write() = Some(tenant);
+        Ok(())
+    }
+
+    /// The documents in a tenant's namespace, in ID order
+    pub fn documents_of(&self, tenant: &Tenant) -> Vec<Document> {
+        self.documents()
+            .into_iter()
+            .filter(|document| tenant.owns(&document.id))
+            .collect()
+    }
+
+    /// The characters of each document in a tenant's namespace archived out
+    /// of memory
+    fn archived_of(&self, tenant: &Tenant) -> Vec<usize> {
+        self.archived
+            .read()
+            .iter()
+            .filter(|(id, _)| tenant.owns(id))
+            .map(|(_, archived)| archived.characters)
+            .collect()
+    }
+
+    /// What a tenant holds and did
+    pub fn tenant_metrics(&self, tenant: &Tenant) -> TenantMetrics {
+        let documents = self.documents_of(tenant);
+        let archived = self.archived_of(tenant);
+        TenantMetrics {
+            id: tenant.id.clone(),
+            characters: documents
+                .iter()
+                .map(|document| document.state.rga.visible_node_count())
+                .chain(archived.iter().copied())
+                .sum(),
+            documents: documents.len() + archived.len(),
+            edits: tenant.edits.load(Ordering::Relaxed),
+            throttled: tenant.throttled.load(Ordering::Relaxed),
+            oversized: tenant.oversized.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl DocumentState {
+    /// Count an edit against the quota of the document's tenant, if it has
+    /// one. `grows` is false for edits that can only delete.
+    ///
+    /// Edits are also refused while too many operations wait for the
+    /// document's log to become writable, see
+    /// [`crate::server::persistence::StorageHealth`].
+    pub fn admit_edit(&self, grows: bool) -> ServerResult {
+        self.storage.admit_write()?;
+        let tenant = self.tenant.read().clone();
+        match tenant {
+            Some(tenant) => tenant.admit_edit(&self.rga, grows),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The tenant a request is made for, or `None` on a single-tenant server
+pub struct TenantScope(pub Option<Arc<Tenant>>);
+
+/// Query parameters carrying a tenant's token
+#[derive(Deserialize)]
+struct TenantTokenParams {
+    tenant_token: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for TenantScope {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> ServerResult<Self> {
+        if !state.is_multi_tenant() {
+            return Ok(TenantScope(None));
+        }
+        let token = match bearer_token(&parts.headers) {
+            Some(token) => Some(token.to_string()),
+            None => Query::<TenantTokenParams>::try_from_uri(&parts.uri)
+                .ok()
+                .and_then(|params| params.0.tenant_token),
+        };
+        token
+            .and_then(|token| state.tenant_for_token(&token))
+            .map(|tenant| TenantScope(Some(tenant)))
+            .ok_or(ServerError::UnknownTenant)
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+impl TenantScope {
+    /// Fail as if document `id` did not exist unless the tenant owns it
+    pub fn check(&self, id: &str) -> ServerResult {
+        match &self.0 {
+            Some(tenant) if !tenant.owns(id) => Err(ServerError::UnknownDocument(id.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Look up document `id` if the tenant owns it
+    pub fn document(&self, state: &DocumentRegistry, id: &str) -> ServerResult<Document> {
+        self.check(id)?;
+        state.get(id)
+    }
+
+    /// Look up merge request `id` if the tenant owns its fork
+    pub fn merge_request(&self, state: &DocumentRegistry, id: u64) -> ServerResult<MergeRequest> {
+        let request = state.merge_request(id)?;
+        self.check(&request.fork)
+            .map_err(|_| ServerError::UnknownMergeRequest(id))?;
+        Ok(request)
+    }
+}
+
+/// Create an empty document
+#[utoipa::path(
+    post,
+    path = "/docs/{id}",
+    tag = "documents",
+    params(("id" = String, Path, description = "ID of the new document, `{tenant}:{name}` for tenants")),
+    responses(
+        (status = 201, description = "Created"),
+        (status = 401, description = "Missing or unknown tenant token"),
+        (status = 403, description = "The ID is outside the tenant's namespace"),
+        (status = 409, description = "A document with this ID exists"),
+        (status = 429, description = "The tenant has as many documents as its quota allows"),
+    )
+)]
+pub async fn create_document(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    scope: TenantScope,
+) -> ServerResult<StatusCode> {
+    if let Some(tenant) = &scope.0
+        && !tenant.owns(&id)
+    {
+        return Err(ServerError::OutsideNamespace(id));
+    }
+    state.create(&id)?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Show the requesting tenant's usage
+#[utoipa::path(
+    get,
+    path = "/tenant",
+    tag = "tenants",
+    responses(
+        (status = 200, description = "The tenant's documents and edits", body = TenantMetrics),
+        (status = 401, description = "Missing or unknown tenant token"),
+        (status = 404, description = "The server is not multi-tenant"),
+    )
+)]
+pub async fn tenant_metrics(
+    State(state): State<AppState>,
+    scope: TenantScope,
+) -> ServerResult<Json<TenantMetrics>> {
+    let tenant = scope.0.ok_or(ServerError::UnknownTenant)?;
+    Ok(Json(state.tenant_metrics(&tenant)))
+}
+
+/// List every tenant's usage
+#[utoipa::path(
+    get,
+    path = "/admin/tenants",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Each tenant's documents and edits", body = [TenantMetrics]),
+        (status = 401, description = "Missing or wrong admin token"),
+        (status = 403, description = "No admin token is configured"),
+    )
+)]
+pub async fn list_tenants(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ServerResult<Json<Vec<TenantMetrics>>> {
+    authorize(&state, &headers)?;
+    let tenants: Vec<Arc<Tenant>> = state.tenants.read().values().cloned().collect();
+    Ok(Json(
+        tenants
+            .iter()
+            .map(|tenant| state.tenant_metrics(tenant))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tenants_are_scoped_and_limited() {
+        let state: AppState = Arc::new(DocumentRegistry::new(RGA::new(1)));
+        assert!(!state.is_multi_tenant());
+        let acme = state.add_tenant(
+            "acme",
+            "secret",
+            TenantQuota {
+                max_documents: Some(2),
+                max_document_chars: Some(4),
+                max_edits_per_minute: Some(3),
+            },
+        );
+        assert!(state.tenant_for_token("secret").is_some());
+        assert!(state.tenant_for_token("guess").is_none());
+
+        let scope = TenantScope(Some(acme.clone()));
+        assert!(scope.check("acme:notes").is_ok());
+        assert!(scope.check("main").is_err());
+        assert!(scope.check("acmeco:notes").is_err());
+
+        let notes = state.create("acme:notes").unwrap();
+        assert!(matches!(
+            state.create("acme:notes"),
+            Err(ServerError::DocumentExists(_))
+        ));
+        let fork = state.fork("acme:notes").await.unwrap();
+        assert!(acme.owns(&fork.id));
+        assert!(matches!(
+            state.create("acme:more"),
+            Err(ServerError::QuotaExceeded {
+                quota: "documents",
+                ..
+            })
+        ));
+
+        // Documents may not grow past four characters
+        let rga = &notes.state.rga;
+        notes.state.admit_edit(true).unwrap();
+        rga.insert_str_after(rga.sentinel_start_id(), "full")
+            .unwrap();
+        assert!(notes.state.admit_edit(true).is_err());
+        notes.state.admit_edit(false).unwrap();
+        // The rate is shared by the tenant's documents
+        fork.state.admit_edit(true).unwrap();
+        assert!(matches!(
+            fork.state.admit_edit(true),
+            Err(ServerError::QuotaExceeded {
+                quota: "edits per minute",
+                ..
+            })
+        ));
+
+        assert_eq!(
+            state.tenant_metrics(&acme),
+            TenantMetrics {
+                id: "acme".to_string(),
+                documents: 2,
+                characters: 4,
+                edits: 3,
+                throttled: 1,
+                oversized: 1,
+            }
+        );
+        // Documents outside any namespace are not limited
+        state.main().admit_edit(true).unwrap();
+    }
+}
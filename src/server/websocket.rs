@@ -6,14 +6,419 @@
 use axum::extract::ws::{Message, WebSocket};
 
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{error, info, warn};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, broadcast};
+use tracing::{debug, error, info, warn};
 
-use crate::crdt::RGA;
+use crate::crdt::{Commit, Mark, RGA, UniqueId};
+use crate::server::acl::{Acl, Permission};
+use crate::server::analytics::Activity;
+use crate::server::awareness::{AwarenessState, DocumentAwareness};
+use crate::server::capture::SessionCapture;
+use crate::server::compression::{Batcher, Codec, encode_batch};
+use crate::server::decorations::{DecorationSpan, Decorations};
+use crate::server::error::{ServerError, ServerResult};
+use crate::server::hooks::{ClientJoin, DocumentHooks, OpApplied, SnapshotTaken};
+use crate::server::middleware::MiddlewareChain;
+use crate::server::paste::PasteTransactions;
+use crate::server::persistence::StorageHealth;
+use crate::server::retention::Deletions;
+use crate::server::selection::{Selection, SelectionChange};
+use crate::server::stats::DocumentStats;
+use crate::server::tenants::Tenant;
 
-/// Shared application state containing the RGA CRDT instance
-pub type AppState = Arc<RwLock<RGA>>;
+/// Capacity of the per-document channel used to fan messages out to sessions
+const PEER_CHANNEL_CAPACITY: usize = 256;
+/// How often sessions that negotiated batching measure their round-trip time
+const RTT_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Messages a session may fall behind its peers before it is resynchronized
+pub const DEFAULT_MAX_PEER_LAG: usize = 128;
+
+/// How a document fans messages out to its sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BroadcastPolicy {
+    /// Every message is delivered to peers as soon as it is produced
+    #[default]
+    Immediate,
+    /// Messages produced within the interval are delivered together, in one
+    /// frame per session. Edits still apply to the document one by one.
+    Coalesce(Duration),
+}
+
+/// Shared state of the collaboratively edited document
+///
+/// The RGA is internally concurrent, so sessions share it directly: reads are
+/// lock-free and edits go straight to the SkipMap. Only tasks that need the
+/// document to themselves (snapshotting, garbage collection) coordinate with
+/// editors through [`DocumentState::exclusive`].
+pub struct DocumentState {
+    /// The RGA CRDT instance holding the document content
+    pub rga: Arc<RGA>,
+    /// Channel delivering batches of messages to every connected session
+    pub peers: broadcast::Sender<PeerBatch>,
+    /// Shared by editors, held exclusively by maintenance tasks
+    coordination: RwLock<()>,
+    /// When the document was opened on this server
+    pub opened_at: Instant,
+    broadcast_policy: parking_lot::Mutex<BroadcastPolicy>,
+    /// Messages waiting for the next coalesced broadcast
+    outbox: parking_lot::Mutex<Vec<PeerMessage>>,
+    /// Who may view, comment on or edit the document
+    pub acl: parking_lot::RwLock<Acl>,
+    /// Inline formatting of the document's text, remapped when the document
+    /// is garbage collected
+    pub marks: Arc<parking_lot::RwLock<Vec<Mark>>>,
+    /// Transient spans shared between sessions, outside the document history
+    pub(crate) decorations: parking_lot::Mutex<Decorations>,
+    /// Presence, cursors and other ephemeral state of the clients' replicas,
+    /// outside the document history
+    pub(crate) awareness: parking_lot::Mutex<DocumentAwareness>,
+    /// IDs of the sessions connected to the document
+    sessions: parking_lot::Mutex<HashSet<String>>,
+    /// Number of edits applied to the document, see [`DocumentState::record_edit`]
+    version: AtomicU64,
+    /// The version that inserted each node added since the document was opened
+    pub(crate) inserted: parking_lot::Mutex<HashMap<UniqueId, u64>>,
+    /// The oldest version whose text can still be rebuilt, raised when
+    /// tombstones are purged
+    pub(crate) history_floor: AtomicU64,
+    /// Nodes deleted by each edit and not purged yet, oldest first
+    pub(crate) deletions: parking_lot::Mutex<VecDeque<Deletions>>,
+    /// Number of messages fanned out to sessions so far
+    sequence: AtomicU64,
+    /// Messages a session may fall behind before it is resynchronized
+    max_peer_lag: AtomicUsize,
+    /// Extensions told about the document's events
+    pub(crate) hooks: parking_lot::RwLock<DocumentHooks>,
+    /// Steps operations and frames of the document's sessions pass through
+    pub(crate) middleware: parking_lot::RwLock<MiddlewareChain>,
+    /// Edits per minute and author, see [`DocumentState::activity`]
+    pub(crate) activity: parking_lot::Mutex<Activity>,
+    /// The customer owning the document on a multi-tenant server
+    pub(crate) tenant: parking_lot::RwLock<Option<Arc<Tenant>>>,
+    /// Whether the document's operations reach its log, if it has one
+    pub storage: StorageHealth,
+}
+
+impl DocumentState {
+    /// Create document state around the given RGA
+    pub fn new(rga: RGA) -> Self {
+        let (peers, _) = broadcast::channel(PEER_CHANNEL_CAPACITY);
+        let text = rga.to_string();
+        let marks = Arc::new(parking_lot::RwLock::new(Vec::new()));
+        rga.register_anchor_holder(marks.clone());
+        rga.track_deletions();
+        rga.track_insertions();
+        let opened_at = Instant::now();
+        let deletions = Deletions::existing(&rga, opened_at);
+        let activity = Activity::new(&text);
+        let awareness = DocumentAwareness::new(rga.replica_id());
+        Self {
+            rga: Arc::new(rga),
+            peers,
+            coordination: RwLock::new(()),
+            opened_at,
+            broadcast_policy: parking_lot::Mutex::new(BroadcastPolicy::default()),
+            outbox: parking_lot::Mutex::new(Vec::new()),
+            acl: parking_lot::RwLock::new(Acl::default()),
+            marks,
+            decorations: parking_lot::Mutex::new(Decorations::default()),
+            awareness: parking_lot::Mutex::new(awareness),
+            sessions: parking_lot::Mutex::new(HashSet::new()),
+            version: AtomicU64::new(0),
+            inserted: parking_lot::Mutex::new(HashMap::new()),
+            history_floor: AtomicU64::new(0),
+            deletions: parking_lot::Mutex::new(deletions.into_iter().collect()),
+            sequence: AtomicU64::new(0),
+            max_peer_lag: AtomicUsize::new(DEFAULT_MAX_PEER_LAG),
+            hooks: parking_lot::RwLock::new(DocumentHooks::default()),
+            middleware: parking_lot::RwLock::new(MiddlewareChain::default()),
+            activity: parking_lot::Mutex::new(activity),
+            tenant: parking_lot::RwLock::new(None),
+            storage: StorageHealth::default(),
+        }
+    }
+
+    /// How messages are fanned out to sessions
+    pub fn broadcast_policy(&self) -> BroadcastPolicy {
+        *self.broadcast_policy.lock()
+    }
+
+    /// Change how messages are fanned out to sessions
+    pub fn set_broadcast_policy(&self, policy: BroadcastPolicy) {
+        *self.broadcast_policy.lock() = policy;
+    }
+
+    /// How many messages a session may fall behind its peers
+    pub fn max_peer_lag(&self) -> usize {
+        self.max_peer_lag.load(Ordering::Relaxed)
+    }
+
+    /// Change how many messages a session may fall behind its peers before it
+    /// is resynchronized from a snapshot
+    pub fn set_max_peer_lag(&self, messages: usize) {
+        self.max_peer_lag.store(messages, Ordering::Relaxed);
+    }
+
+    /// Number of messages fanned out to sessions so far
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Acquire)
+    }
+
+    /// Whether a session receiving `batch` has fallen too far behind
+    pub fn lags_behind(&self, batch: &PeerBatch) -> bool {
+        self.sequence().saturating_sub(batch.sequence) > self.max_peer_lag() as u64
+    }
+
+    /// Number `messages` and deliver them to every session
+    fn send_batch(&self, messages: Arc<[PeerMessage]>) {
+        let count = messages.len() as u64;
+        let sequence = self.sequence.fetch_add(count, Ordering::AcqRel) + count;
+        // Sending only fails when no session is subscribed, which is fine
+        let _ = self.peers.send(PeerBatch { sequence, messages });
+    }
+
+    /// Deliver a message to every session, according to the broadcast policy.
+    ///
+    /// An `update` is counted in the document's activity, reported to its
+    /// hooks and followed by the document's decorations if the edit moved or
+    /// invalidated any of them.
+    pub fn publish(self: &Arc<Self>, message: PeerMessage) {
+        let edited = message.response.response_type == "update";
+        if edited {
+            self.record_activity(&message.origin, &message.response.content);
+        }
+        if edited && let Some((document, hooks)) = self.hooks() {
+            let event = OpApplied {
+                document: &document,
+                state: self,
+                session_id: &message.origin,
+                version: message.response.version.unwrap_or_default(),
+                position: message.response.position,
+                content: &message.response.content,
+            };
+            for hook in hooks {
+                hook.on_op_applied(&event);
+            }
+        }
+        match self.broadcast_policy() {
+            BroadcastPolicy::Immediate => {
+                // Messages still coalescing from before a policy change go first
+                self.flush_outbox();
+                self.send_batch(Arc::from([message]));
+            }
+            BroadcastPolicy::Coalesce(interval) => {
+                let mut outbox = self.outbox.lock();
+                outbox.push(message);
+                // The first message of a batch schedules its delivery
+                if outbox.len() == 1 {
+                    let state = Arc::clone(self);
+                    tokio::spawn(async move {
+                        tokio::time::sleep(interval).await;
+                        state.flush_outbox();
+                    });
+                }
+            }
+        }
+
+        if edited {
+            let refreshed = self.decorations.lock().refresh(&self.rga);
+            if let Some(spans) = refreshed {
+                self.publish_decorations(spans);
+            }
+        }
+    }
+
+    /// Replace the decorations of session `owner` and share them
+    pub fn decorate(self: &Arc<Self>, owner: &str, spans: Vec<DecorationSpan>) -> ServerResult {
+        let spans = self.decorations.lock().set(&self.rga, owner, spans)?;
+        self.publish_decorations(spans);
+        Ok(())
+    }
+
+    /// Withdraw the decorations of session `owner`
+    pub fn clear_decorations(self: &Arc<Self>, owner: &str) {
+        let cleared = self.decorations.lock().clear(&self.rga, owner);
+        if let Some(spans) = cleared {
+            self.publish_decorations(spans);
+        }
+    }
+
+    /// The decorations sessions currently display
+    pub fn decorations(&self) -> Vec<DecorationSpan> {
+        self.decorations.lock().spans().to_vec()
+    }
+
+    /// Send the current decorations to every session, including their author
+    fn publish_decorations(self: &Arc<Self>, spans: Vec<DecorationSpan>) {
+        self.publish(PeerMessage {
+            origin: "decorations".to_string(),
+            response: RGAResponse {
+                response_type: "decorations".to_string(),
+                content: String::new(),
+                position: None,
+                session_id: None,
+                decorations: Some(spans),
+                version: None,
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        });
+    }
+
+    /// Deliver the messages waiting in the outbox as one batch
+    fn flush_outbox(&self) {
+        let batch = std::mem::take(&mut *self.outbox.lock());
+        if !batch.is_empty() {
+            self.send_batch(batch.into());
+        }
+    }
+
+    /// Register an in-flight edit.
+    ///
+    /// Any number of edits may run at once; they only wait while an exclusive
+    /// task holds the document.
+    pub async fn begin_edit(&self) -> RwLockReadGuard<'_, ()> {
+        self.coordination.read().await
+    }
+
+    /// Wait for in-flight edits to finish and block new ones until the guard
+    /// is dropped. Reads are not affected.
+    pub async fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.coordination.write().await
+    }
+
+    /// Take a point-in-time copy of the document with no edit in flight
+    pub async fn snapshot(&self) -> RGA {
+        self.versioned_snapshot().await.0
+    }
+
+    /// Take a point-in-time copy of the document along with its version
+    pub async fn versioned_snapshot(&self) -> (RGA, u64) {
+        let (snapshot, version) = {
+            let _exclusive = self.exclusive().await;
+            ((*self.rga).clone(), self.version())
+        };
+        if let Some((document, hooks)) = self.hooks() {
+            let event = SnapshotTaken {
+                document: &document,
+                snapshot: &snapshot,
+                version,
+            };
+            for hook in hooks {
+                hook.on_snapshot(&event);
+            }
+        }
+        (snapshot, version)
+    }
+
+    /// Number of edits applied to the document so far
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Count an edit applied to the document and return its version.
+    ///
+    /// Must be called before the edit's guard is released, so that snapshots
+    /// contain exactly the edits up to their version. The `update` sharing the
+    /// edit carries the version, which lets joining sessions tell the updates
+    /// their snapshot already contains from those it does not. The nodes the
+    /// edit inserted and deleted are stamped with the version, for
+    /// [`DocumentState::text_at`] and the retention policy.
+    pub fn record_edit(&self) -> u64 {
+        let version = self.version.fetch_add(1, Ordering::AcqRel) + 1;
+        let inserted = self.rga.take_insertions();
+        if !inserted.is_empty() {
+            self.inserted
+                .lock()
+                .extend(inserted.into_iter().map(|id| (id, version)));
+        }
+        let deleted = self.rga.take_deletions();
+        if !deleted.is_empty() {
+            self.deletions.lock().push_back(Deletions {
+                at: Instant::now(),
+                version,
+                ids: deleted,
+            });
+        }
+        version
+    }
+
+    /// Adds `sessions` to the peers a session mutes and forgets the muted
+    /// peers that have left. Sessions not connected to the document are
+    /// ignored, so `muted` never outgrows the document's sessions.
+    pub(crate) fn mute(&self, muted: &mut HashSet<String>, sessions: Vec<String>) {
+        let connected = self.sessions.lock();
+        muted.retain(|session| connected.contains(session));
+        muted.extend(
+            sessions
+                .into_iter()
+                .filter(|session| connected.contains(session)),
+        );
+    }
+
+    /// The text of the document at `version`.
+    ///
+    /// The text is rebuilt from the versions that inserted and deleted each
+    /// node, so it is `None` only for future versions and for those older than
+    /// the last purge of their tombstones.
+    pub async fn text_at(&self, version: u64) -> Option<String> {
+        let _exclusive = self.exclusive().await;
+        if version > self.version() || version < self.history_floor.load(Ordering::Acquire) {
+            return None;
+        }
+        let deleted_at: HashMap<UniqueId, u64> = self
+            .deletions
+            .lock()
+            .iter()
+            .flat_map(|deleted| deleted.ids.iter().map(|&id| (id, deleted.version)))
+            .collect();
+        let inserted = self.inserted.lock();
+        let mut text = String::new();
+        self.rga.for_each_node(|node| {
+            let visible_from = inserted.get(&node.id).copied().unwrap_or(0);
+            let visible = match deleted_at.get(&node.id) {
+                Some(&deleted) => version < deleted,
+                None => !node.is_deleted,
+            };
+            if visible_from <= version && visible && !node.is_sentinel() {
+                text.push(node.character);
+            }
+        });
+        Some(text)
+    }
+}
+
+/// Messages delivered to sessions together, in the order they were produced
+#[derive(Clone, Debug)]
+pub struct PeerBatch {
+    /// Number of messages the document had fanned out, this batch included
+    pub sequence: u64,
+    pub messages: Arc<[PeerMessage]>,
+}
+
+impl Deref for PeerBatch {
+    type Target = [PeerMessage];
+
+    fn deref(&self) -> &[PeerMessage] {
+        &self.messages
+    }
+}
+
+/// A message fanned out from one session to all other sessions
+#[derive(Clone, Debug)]
+pub struct PeerMessage {
+    /// The session that produced the message
+    pub origin: String,
+    /// The response to forward to the other sessions
+    pub response: RGAResponse,
+}
 
 /// WebSocket message protocol for RGA operations
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,120 +429,445 @@ pub struct RGAOperation {
     pub position: Option<usize>,
     pub after_id: Option<String>,
     pub delete_id: Option<String>,
+    /// Text payload for multi-character operations such as IME composition
+    pub text: Option<String>,
+    /// Caret positions for multi-caret edits
+    pub positions: Option<Vec<usize>>,
+    /// Number of characters a `replace` deletes at `position`
+    pub length: Option<usize>,
+    /// Codecs the client can decode, offered in a `hello` frame
+    pub compression: Option<Vec<String>>,
+    /// Client-chosen ID of a chunked paste transaction
+    pub transaction: Option<String>,
+    /// Position of a chunk within its paste transaction, starting at 0
+    pub sequence: Option<u64>,
+    /// The complete set of the session's decorations
+    pub decorations: Option<Vec<DecorationSpan>>,
+    /// Sessions to mute or unmute
+    pub sessions: Option<Vec<String>>,
+    /// The state a client shares on the awareness channel
+    pub awareness: Option<AwarenessState>,
+}
+
+/// Frame reporting an operation the server did not apply
+#[derive(Serialize)]
+struct ErrorFrame<'a> {
+    #[serde(rename = "type")]
+    frame_type: &'static str,
+    /// Machine-readable error code, see [`ServerError::code`]
+    code: &'static str,
+    content: String,
+    /// The operation that was refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation: Option<&'a str>,
 }
 
 /// Response messages sent to clients
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Clone, Debug)]
 pub struct RGAResponse {
     #[serde(rename = "type")]
     pub response_type: String,
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub position: Option<usize>,
+    /// The session a forwarded message originated from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The document's decorations, in `decorations` messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decorations: Option<Vec<DecorationSpan>>,
+    /// The document version an `init`, `update` or `caught_up` reflects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// How peers' edits changed the session's selection, in `selection`
+    /// messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<SelectionChange>,
+    /// Replicas' ephemeral states, in `awareness` messages and in `init`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub awareness: Option<Vec<AwarenessState>>,
+    /// The document's statistics, in `stats` messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DocumentStats>,
 }
 
 /// WebSocket session manager
 pub struct WebSocketSession {
     socket: WebSocket,
-    state: AppState,
+    state: Arc<DocumentState>,
     session_id: String,
+    /// What the session may do, fixed when it connects
+    permission: Permission,
+    /// Whether the client currently has an uncommitted IME composition
+    composing: bool,
+    /// Chunked pastes the client is streaming
+    pastes: PasteTransactions,
+    /// Batches peer messages once the client has negotiated it in `hello`
+    batcher: Option<Batcher>,
+    /// Reference point for the timestamps carried by RTT probe pings
+    started_at: Instant,
+    /// Where the frames received from the client are recorded, if anywhere
+    capture: Option<SessionCapture>,
+    /// Connected peers whose presence, compositions and comments are not
+    /// forwarded
+    muted: HashSet<String>,
+    /// The text the client has selected, if any
+    selection: Option<Selection>,
 }
 
 impl WebSocketSession {
     /// Create a new WebSocket session
-    pub fn new(socket: WebSocket, state: AppState, session_id: String) -> Self {
+    pub fn new(
+        socket: WebSocket,
+        state: Arc<DocumentState>,
+        session_id: String,
+        permission: Permission,
+    ) -> Self {
         Self {
             socket,
             state,
             session_id,
+            permission,
+            composing: false,
+            pastes: PasteTransactions::default(),
+            batcher: None,
+            started_at: Instant::now(),
+            capture: None,
+            muted: HashSet::new(),
+            selection: None,
         }
     }
 
+    /// Record every frame received from the client to `capture`
+    pub fn set_capture(&mut self, capture: SessionCapture) {
+        self.capture = Some(capture);
+    }
+
     /// Handle the WebSocket connection lifecycle
     pub async fn handle(mut self) {
         info!("WebSocket session {} established", self.session_id);
 
-        // Send initial document state
-        if let Err(e) = self.send_initial_state().await {
+        // Subscribe before the snapshot is taken, so that messages published
+        // while it is transferred wait in the channel instead of being missed
+        let mut peer_rx = self.state.peers.subscribe();
+        if let Err(e) = self.send_initial_state(&mut peer_rx).await {
             error!("Failed to send initial state to {}: {}", self.session_id, e);
             return;
         }
+        self.state.sessions.lock().insert(self.session_id.clone());
+        if let Some((document, hooks)) = self.state.hooks() {
+            let event = ClientJoin {
+                document: &document,
+                state: &self.state,
+                session_id: &self.session_id,
+                permission: self.permission,
+            };
+            for hook in hooks {
+                hook.on_client_join(&event);
+            }
+        }
 
-        // Process incoming messages
-        while let Some(msg) = self.socket.recv().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    if let Err(e) = self.handle_text_message(&text).await {
-                        error!("Error handling message from {}: {}", self.session_id, e);
-                        break;
+        let mut rtt_probe = tokio::time::interval(RTT_PROBE_INTERVAL);
+
+        // Process incoming messages and messages forwarded from peers
+        loop {
+            let batch_deadline = self.batch_deadline();
+            tokio::select! {
+                msg = self.socket.recv() => {
+                    let Some(msg) = msg else { break };
+                    match msg {
+                        Ok(Message::Text(text)) => {
+                            if let Err(e) = self.handle_text_message(&text).await
+                                && let Err(e) = self.report_error(e).await
+                            {
+                                error!("Error handling message from {}: {}", self.session_id, e);
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(_)) => {
+                            info!("WebSocket session {} closed by client", self.session_id);
+                            break;
+                        }
+                        Ok(Message::Ping(data)) => {
+                            if let Err(e) = self.socket.send(Message::Pong(data)).await {
+                                error!("Failed to send pong to {}: {}", self.session_id, e);
+                                break;
+                            }
+                        }
+                        Ok(Message::Pong(data)) => self.record_rtt(&data),
+                        Ok(_) => {
+                            // Ignore other message types (binary)
+                        }
+                        Err(e) => {
+                            warn!("WebSocket error for {}: {}", self.session_id, e);
+                            break;
+                        }
                     }
                 }
-                Ok(Message::Close(_)) => {
-                    info!("WebSocket session {} closed by client", self.session_id);
-                    break;
+                peer = peer_rx.recv() => {
+                    match peer {
+                        Ok(batch) if self.state.lags_behind(&batch) => {
+                            let lag = self.state.sequence() - batch.sequence;
+                            if let Err(e) = self.resync(&mut peer_rx, lag).await {
+                                error!("Failed to resynchronize {}: {}", self.session_id, e);
+                                break;
+                            }
+                        }
+                        Ok(batch) => {
+                            // Our own messages are echoed back by the channel
+                            let mut responses: Vec<RGAResponse> = batch
+                                .iter()
+                                .filter(|message| message.origin != self.session_id)
+                                .filter(|message| !self.is_muted(message))
+                                .map(|message| message.response.clone())
+                                .collect();
+                            let editor = batch
+                                .iter()
+                                .rev()
+                                .find(|message| {
+                                    message.origin != self.session_id
+                                        && message.response.response_type == "update"
+                                })
+                                .map(|message| message.origin.clone());
+                            if let Some(editor) = editor {
+                                responses.extend(self.selection_hint(Some(editor)));
+                            }
+                            if let Err(e) = self.forward(responses).await {
+                                error!("Failed to forward peer message to {}: {}", self.session_id, e);
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            if let Err(e) = self.resync(&mut peer_rx, skipped).await {
+                                error!("Failed to resynchronize {}: {}", self.session_id, e);
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
                 }
-                Ok(Message::Ping(data)) => {
-                    if let Err(e) = self.socket.send(Message::Pong(data)).await {
-                        error!("Failed to send pong to {}: {}", self.session_id, e);
+                _ = sleep_until_deadline(batch_deadline), if batch_deadline.is_some() => {
+                    if let Err(e) = self.flush_batch().await {
+                        error!("Failed to send batch to {}: {}", self.session_id, e);
                         break;
                     }
                 }
-                Ok(_) => {
-                    // Ignore other message types (binary, pong)
-                }
-                Err(e) => {
-                    warn!("WebSocket error for {}: {}", self.session_id, e);
-                    break;
+                _ = rtt_probe.tick(), if self.batcher.is_some() => {
+                    if let Err(e) = self.probe_rtt().await {
+                        error!("Failed to probe RTT of {}: {}", self.session_id, e);
+                        break;
+                    }
                 }
             }
         }
 
+        // Peers must not keep displaying a composition that will never be committed
+        if self.composing {
+            self.broadcast_composition_end(None);
+        }
+        self.state.clear_decorations(&self.session_id);
+        self.state.withdraw_awareness(&self.session_id);
+        self.state.sessions.lock().remove(&self.session_id);
+
         info!("WebSocket session {} ended", self.session_id);
     }
 
-    /// Send initial document state to newly connected client
-    async fn send_initial_state(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let rga = self.state.read().await;
-        let content = rga.to_string();
-        drop(rga);
+    /// Send initial document state to newly connected client.
+    ///
+    /// The client receives an `init` holding the document at some version,
+    /// then the peer messages published while it was being transferred, minus
+    /// the updates it already contains, and finally a `caught_up` marker with
+    /// the version it has reached. Messages after the marker arrive live.
+    async fn send_initial_state(
+        &mut self,
+        peer_rx: &mut broadcast::Receiver<PeerBatch>,
+    ) -> Result<(), ServerError> {
+        let (snapshot, version) = self.state.versioned_snapshot().await;
+        let decorations = self.state.decorations();
+        let awareness = self.state.awareness_states();
 
         let response = RGAResponse {
             response_type: "init".to_string(),
-            content,
+            content: snapshot.to_string(),
             position: None,
+            session_id: None,
+            decorations: (!decorations.is_empty()).then_some(decorations),
+            version: Some(version),
+            selection: None,
+            awareness: (!awareness.is_empty()).then_some(awareness),
+            stats: None,
         };
+        self.send_response(&response).await?;
 
-        self.send_response(&response).await
+        let (missed, version) = catch_up(peer_rx, version, &self.session_id);
+        for response in &missed {
+            self.send_response(response).await?;
+        }
+        let response = RGAResponse {
+            response_type: "caught_up".to_string(),
+            content: String::new(),
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+        self.send_response(&response).await?;
+        info!(
+            "Session {} caught up at version {} after {} replayed messages",
+            self.session_id,
+            version,
+            missed.len()
+        );
+        Ok(())
+    }
+
+    /// Bring a session that fell too far behind its peers up to date.
+    ///
+    /// The messages queued for it are dropped instead of being delivered late,
+    /// and the client receives a fresh `init` and `caught_up`, as when it
+    /// joined.
+    async fn resync(
+        &mut self,
+        peer_rx: &mut broadcast::Receiver<PeerBatch>,
+        lag: u64,
+    ) -> ServerResult {
+        warn!(
+            "Session {} fell {} messages behind, resynchronizing",
+            self.session_id, lag
+        );
+        *peer_rx = peer_rx.resubscribe();
+        if let Some(batcher) = &mut self.batcher {
+            batcher.discard();
+        }
+        self.send_initial_state(peer_rx).await
     }
 
     /// Handle incoming text messages
-    async fn handle_text_message(&mut self, text: &str) -> Result<(), Box<dyn std::error::Error>> {
-        info!("Session {} received: {}", self.session_id, text);
-
-        match serde_json::from_str::<RGAOperation>(text) {
-            Ok(operation) => self.process_rga_operation(operation).await,
-            Err(e) => {
-                warn!("Failed to parse operation from {}: {}", self.session_id, e);
-                Ok(()) // Don't break connection for parse errors
-            }
+    async fn handle_text_message(&mut self, text: &str) -> ServerResult {
+        if let Some(capture) = &mut self.capture
+            && let Err(e) = capture.record(text)
+        {
+            warn!("Stopped capturing session {}: {}", self.session_id, e);
+            self.capture = None;
+        }
+
+        let mut operation =
+            serde_json::from_str::<RGAOperation>(text).map_err(ServerError::InvalidMessage)?;
+        // Frames carry the users' text, which stays out of the logs
+        debug!(
+            "Session {} received {} ({} bytes)",
+            self.session_id,
+            operation.op_type,
+            text.len()
+        );
+        self.state
+            .filter_inbound(&self.session_id, self.permission, &mut operation)?;
+
+        // Peers' edits not yet forwarded are reported before this operation
+        // can change the selection, and its own changes are not reported
+        if let Some(hint) = self.selection_hint(None) {
+            self.forward(vec![hint]).await?;
         }
+        let result = self.process_rga_operation(operation).await;
+        self.refresh_selection();
+        result
+    }
+
+    /// Report a failed operation to the client.
+    ///
+    /// Errors caused by the client's frame are sent back as an `error` message
+    /// and the session continues. Fatal errors are returned to end the session.
+    async fn report_error(&mut self, error: ServerError) -> ServerResult {
+        if error.is_fatal() {
+            return Err(error);
+        }
+
+        warn!("Rejected operation from {}: {}", self.session_id, error);
+        let frame = ErrorFrame {
+            frame_type: "error",
+            code: error.code(),
+            content: error.to_string(),
+            operation: match &error {
+                ServerError::UnknownOperation(operation)
+                | ServerError::Forbidden { operation, .. } => Some(operation),
+                ServerError::MissingField { operation, .. } => Some(operation),
+                _ => None,
+            },
+        };
+        let json = serde_json::to_string(&frame).map_err(ServerError::Serialization)?;
+        self.socket.send(Message::Text(json)).await?;
+        Ok(())
     }
 
     /// Process RGA operations
-    async fn process_rga_operation(
-        &mut self,
-        operation: RGAOperation,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    async fn process_rga_operation(&mut self, operation: RGAOperation) -> Result<(), ServerError> {
+        self.permission.authorize(&operation.op_type)?;
+        if Permission::required_for(&operation.op_type) == Permission::Edit {
+            self.state.ensure_unfrozen()?;
+            let grows = !matches!(
+                operation.op_type.as_str(),
+                "multi_delete" | "delete_word" | "composition_cancel" | "paste_abort"
+            );
+            self.state.admit_edit(grows)?;
+        }
+
         match operation.op_type.as_str() {
             "insert" => self.handle_insert_operation(operation).await,
             "get_content" => self.handle_get_content_operation().await,
-            _ => {
-                warn!(
-                    "Unknown operation type '{}' from session {}",
-                    operation.op_type, self.session_id
-                );
+            "stats" => self.handle_stats_operation().await,
+            "hello" => self.handle_hello(operation).await,
+            "presence" => self.handle_presence(operation),
+            "awareness" => {
+                let state = required(operation.awareness, "awareness", "awareness")?;
+                self.state.share_awareness(&self.session_id, state)
+            }
+            "comment" => self.handle_comment(operation).await,
+            "mute" => {
+                let sessions = required(operation.sessions, "mute", "sessions")?;
+                self.state.mute(&mut self.muted, sessions);
+                Ok(())
+            }
+            "unmute" => {
+                let sessions = required(operation.sessions, "unmute", "sessions")?;
+                for session in &sessions {
+                    self.muted.remove(session);
+                }
                 Ok(())
             }
+            "decorate" => {
+                let spans = required(operation.decorations, "decorate", "decorations")?;
+                self.state.decorate(&self.session_id, spans)
+            }
+            "multi_insert" => self.handle_multi_insert_operation(operation).await,
+            "multi_delete" => self.handle_multi_delete_operation(operation).await,
+            "delete_word" => self.handle_delete_word_operation(operation).await,
+            "replace" => self.handle_replace_operation(operation).await,
+            "composition_update" => self.handle_composition_update(operation),
+            "composition_commit" => self.handle_composition_commit(operation).await,
+            "composition_cancel" => {
+                self.handle_composition_cancel();
+                Ok(())
+            }
+            "paste_begin" => {
+                let transaction = required(operation.transaction, "paste_begin", "transaction")?;
+                let position = required(operation.position, "paste_begin", "position")?;
+                self.pastes.begin(transaction, position)
+            }
+            "paste_chunk" => {
+                let transaction = required(operation.transaction, "paste_chunk", "transaction")?;
+                let sequence = required(operation.sequence, "paste_chunk", "sequence")?;
+                let text = required(operation.text, "paste_chunk", "text")?;
+                self.pastes.append(&transaction, sequence, &text)
+            }
+            "paste_commit" => self.handle_paste_commit(operation).await,
+            "paste_abort" => {
+                let transaction = required(operation.transaction, "paste_abort", "transaction")?;
+                self.pastes.abort(&transaction)
+            }
+            _ => Err(ServerError::UnknownOperation(operation.op_type)),
         }
     }
 
@@ -145,60 +875,237 @@ impl WebSocketSession {
     async fn handle_insert_operation(
         &mut self,
         operation: RGAOperation,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let Some(character) = operation.character else {
-            warn!(
-                "Insert operation missing character from session {}",
-                self.session_id
-            );
-            return Ok(());
-        };
+    ) -> Result<(), ServerError> {
+        let character = required(operation.character, "insert", "character")?;
 
         let position = operation.position.unwrap_or(0);
 
-        let rga = self.state.write().await;
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        // Normalization may turn one character into several
+        insert_text(rga, position, character.encode_utf8(&mut [0; 4]))?;
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        let response = RGAResponse {
+            response_type: "update".to_string(),
+            content,
+            position: Some(position),
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
 
-        // Calculate insertion point based on position
-        let after_id = self.calculate_insertion_point(&rga, position);
+        self.send_response(&response).await?;
+        self.broadcast(response);
+        info!(
+            "Session {} inserted a character at position {}",
+            self.session_id, position
+        );
+        Ok(())
+    }
 
-        match rga.insert_after(after_id, character) {
-            Ok(_new_id) => {
-                let content = rga.to_string();
-                drop(rga);
+    /// Negotiate compression and batching of peer messages.
+    ///
+    /// The server picks a codec from those the client offered and replies with
+    /// `{"type": "hello", "content": <codec>}`. From then on, peer messages
+    /// arrive in `batch` frames, compressed if a codec was agreed.
+    async fn handle_hello(&mut self, operation: RGAOperation) -> ServerResult {
+        let codec = Codec::negotiate(&operation.compression.unwrap_or_default());
+        // Whatever was queued under the previous codec goes out first
+        self.flush_batch().await?;
+        self.batcher = Some(Batcher::new(codec));
 
-                let response = RGAResponse {
-                    response_type: "update".to_string(),
-                    content,
-                    position: Some(position),
-                };
+        let response = RGAResponse {
+            response_type: "hello".to_string(),
+            content: codec.name().to_string(),
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+        self.send_response(&response).await?;
+        info!(
+            "Session {} negotiated batching with codec {}",
+            self.session_id,
+            codec.name()
+        );
+        Ok(())
+    }
 
-                self.send_response(&response).await?;
-                info!(
-                    "Session {} inserted '{}' at position {}",
-                    self.session_id, character, position
-                );
+    /// Share this session's caret position with its peers.
+    ///
+    /// The `length` characters after the caret, if any, become the session's
+    /// selection, and peers' edits inside it are reported in `selection`
+    /// messages.
+    fn handle_presence(&mut self, operation: RGAOperation) -> ServerResult {
+        self.selection = Selection::new(
+            &self.state.rga,
+            operation.position.unwrap_or(0),
+            operation.length.unwrap_or(0),
+        )?;
+        self.broadcast(RGAResponse {
+            response_type: "presence".to_string(),
+            content: String::new(),
+            position: operation.position,
+            session_id: Some(self.session_id.clone()),
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        });
+        Ok(())
+    }
+
+    /// Re-anchor the selection after edits, dropping it once all of it was
+    /// deleted
+    fn refresh_selection(&mut self) -> Option<SelectionChange> {
+        let selection = self.selection.as_mut()?;
+        let change = selection.refresh(&self.state.rga);
+        if selection.is_empty() {
+            self.selection = None;
+        }
+        change
+    }
+
+    /// A `selection` message if edits changed the selection, naming the peer
+    /// whose update revealed it when known
+    fn selection_hint(&mut self, editor: Option<String>) -> Option<RGAResponse> {
+        let change = self.refresh_selection()?;
+        Some(RGAResponse {
+            response_type: "selection".to_string(),
+            content: String::new(),
+            position: Some(change.start),
+            session_id: editor,
+            decorations: None,
+            version: None,
+            selection: Some(change),
+            awareness: None,
+            stats: None,
+        })
+    }
+
+    /// Whether a peer message is withheld because its author is muted.
+    ///
+    /// Updates carry the whole document, so they are always delivered.
+    fn is_muted(&self, message: &PeerMessage) -> bool {
+        message.response.response_type != "update" && self.muted.contains(&message.origin)
+    }
+
+    /// Relay a comment on the document to this session and its peers.
+    ///
+    /// Comments do not change the document, so commenters may send them.
+    async fn handle_comment(&mut self, operation: RGAOperation) -> ServerResult {
+        let text = required(operation.text, "comment", "text")?;
+
+        let response = RGAResponse {
+            response_type: "comment".to_string(),
+            content: text,
+            position: operation.position,
+            session_id: Some(self.session_id.clone()),
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+        self.send_response(&response).await?;
+        self.broadcast(response);
+        Ok(())
+    }
+
+    /// Deliver messages from peers, batching them if negotiated.
+    ///
+    /// Without a negotiated batcher, several messages coalesced by the
+    /// document still arrive in one `batch` frame.
+    async fn forward(&mut self, mut responses: Vec<RGAResponse>) -> ServerResult {
+        if self.state.filters_outbound() {
+            responses = responses
+                .into_iter()
+                .filter_map(|response| self.state.filter_outbound(&self.session_id, response))
+                .collect();
+        }
+        if self.batcher.is_some() {
+            for response in responses {
+                let full = self
+                    .batcher
+                    .as_mut()
+                    .is_some_and(|batcher| batcher.push(response));
+                if full {
+                    self.flush_batch().await?;
+                }
             }
-            Err(e) => {
-                error!(
-                    "Failed to insert character for session {}: {}",
-                    self.session_id, e
-                );
+            return Ok(());
+        }
+        match responses.as_slice() {
+            [] => Ok(()),
+            [response] => self.send_frame(response).await,
+            _ => {
+                let frame = encode_batch(Codec::None, &responses)?;
+                self.socket.send(frame).await?;
+                Ok(())
             }
         }
+    }
+
+    /// When the pending batch is due, if one is pending
+    fn batch_deadline(&self) -> Option<tokio::time::Instant> {
+        self.batcher.as_ref().and_then(Batcher::deadline)
+    }
+
+    /// Send the pending batch, if any
+    async fn flush_batch(&mut self) -> ServerResult {
+        let Some(batcher) = &mut self.batcher else {
+            return Ok(());
+        };
+        if let Some(frame) = batcher.flush()? {
+            self.socket.send(frame).await?;
+        }
+        Ok(())
+    }
 
+    /// Send a ping carrying the time since the session started
+    async fn probe_rtt(&mut self) -> ServerResult {
+        let sent_at = self.started_at.elapsed().as_micros() as u64;
+        self.socket
+            .send(Message::Ping(sent_at.to_be_bytes().to_vec()))
+            .await?;
         Ok(())
     }
 
+    /// Record the RTT of a probe answered by `pong`
+    fn record_rtt(&mut self, pong: &[u8]) {
+        let (Some(batcher), Ok(bytes)) = (&mut self.batcher, <[u8; 8]>::try_from(pong)) else {
+            return;
+        };
+        let sent_at = Duration::from_micros(u64::from_be_bytes(bytes));
+        if let Some(rtt) = self.started_at.elapsed().checked_sub(sent_at) {
+            batcher.rtt.update(rtt);
+        }
+    }
+
     /// Handle get content operations
-    async fn handle_get_content_operation(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let rga = self.state.read().await;
-        let content = rga.to_string();
-        drop(rga);
+    async fn handle_get_content_operation(&mut self) -> Result<(), ServerError> {
+        let content = self.state.rga.to_string();
 
         let response = RGAResponse {
             response_type: "content".to_string(),
             content,
             position: None,
+            session_id: None,
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
         };
 
         self.send_response(&response).await?;
@@ -206,52 +1113,519 @@ impl WebSocketSession {
         Ok(())
     }
 
-    /// Calculate the node ID to insert after based on position
-    fn calculate_insertion_point(&self, rga: &RGA, position: usize) -> crate::crdt::UniqueId {
-        let visible_nodes = rga.visible_nodes();
+    /// Send the document's statistics, with the version they describe
+    async fn handle_stats_operation(&mut self) -> ServerResult {
+        let version = self.state.version();
+        let response = RGAResponse {
+            response_type: "stats".to_string(),
+            content: String::new(),
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: Some(self.state.stats()),
+        };
+        self.send_response(&response).await
+    }
+
+    /// Handle inserting the same character at several carets
+    async fn handle_multi_insert_operation(
+        &mut self,
+        operation: RGAOperation,
+    ) -> Result<(), ServerError> {
+        let character = required(operation.character, "multi_insert", "character")?;
+        let positions = required(operation.positions, "multi_insert", "positions")?;
 
-        if position == 0 {
-            // Insert at beginning
-            rga.sentinel_start_id()
-        } else if position >= visible_nodes.len() {
-            // Insert at end - find the last visible node
-            if let Some(last_node) = visible_nodes.last() {
-                last_node.id
-            } else {
-                rga.sentinel_start_id()
-            }
-        } else {
-            // Insert after the node at position-1
-            visible_nodes[position - 1].id
-        }
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        rga.insert_at_carets(&positions, rga.normalization().apply_char(character))?;
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        self.send_update(content, version).await?;
+        info!(
+            "Session {} inserted a character at {} carets",
+            self.session_id,
+            positions.len()
+        );
+        Ok(())
     }
 
-    /// Send a response message to the client
-    async fn send_response(
+    /// Handle deleting the character at several carets
+    async fn handle_multi_delete_operation(
         &mut self,
-        response: &RGAResponse,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let json = serde_json::to_string(response)?;
+        operation: RGAOperation,
+    ) -> Result<(), ServerError> {
+        let positions = required(operation.positions, "multi_delete", "positions")?;
+
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        let deleted = rga.delete_at_carets(&positions)?;
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        self.send_update(content, version).await?;
+        info!(
+            "Session {} deleted {} characters at carets",
+            self.session_id,
+            deleted.len()
+        );
+        Ok(())
+    }
+
+    /// Handle deleting the word before a caret (Ctrl+Backspace)
+    async fn handle_delete_word_operation(&mut self, operation: RGAOperation) -> ServerResult {
+        let position = required(operation.position, "delete_word", "position")?;
+
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        let deleted = rga.delete_word_before(position)?;
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        self.send_update(content, version).await?;
+        info!(
+            "Session {} deleted a word of {} characters before position {}",
+            self.session_id,
+            deleted.len(),
+            position
+        );
+        Ok(())
+    }
+
+    /// Handle replacing a range with text, as typing over a selection does.
+    ///
+    /// The deletion and the insertion commit as one transaction, so no peer
+    /// ever sees the range deleted but not yet replaced.
+    async fn handle_replace_operation(&mut self, operation: RGAOperation) -> ServerResult {
+        let position = required(operation.position, "replace", "position")?;
+        let length = required(operation.length, "replace", "length")?;
+        let text = operation.text.unwrap_or_default();
+
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        let commit = replace_text(rga, position, length, &text)?;
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        self.send_update(content, version).await?;
+        info!(
+            "Session {} replaced {} characters at position {} in {} operations",
+            self.session_id,
+            length,
+            position,
+            commit.operations.len()
+        );
+        Ok(())
+    }
+
+    /// Send the content after edit `version` to this session and all of its peers
+    async fn send_update(&mut self, content: String, version: u64) -> Result<(), ServerError> {
+        let response = RGAResponse {
+            response_type: "update".to_string(),
+            content,
+            position: None,
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+
+        self.send_response(&response).await?;
+        self.broadcast(response);
+        Ok(())
+    }
+
+    /// Handle in-progress IME composition text.
+    ///
+    /// The pending text is not committed to the CRDT; it is only forwarded to
+    /// peers so they can display it at the composing client's position.
+    fn handle_composition_update(&mut self, operation: RGAOperation) -> Result<(), ServerError> {
+        let text = required(operation.text, "composition_update", "text")?;
+
+        self.composing = true;
+        self.broadcast(RGAResponse {
+            response_type: "composition".to_string(),
+            content: text,
+            position: operation.position,
+            session_id: Some(self.session_id.clone()),
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        });
+        Ok(())
+    }
+
+    /// Handle the final commit of an IME composition.
+    ///
+    /// The composed string is inserted as one chained run, each character
+    /// after the previous one, within a single edit.
+    async fn handle_composition_commit(
+        &mut self,
+        operation: RGAOperation,
+    ) -> Result<(), ServerError> {
+        let text = required(operation.text, "composition_commit", "text")?;
+
+        let position = operation.position.unwrap_or(0);
+
+        let edit = self.state.begin_edit().await;
+        let rga = &self.state.rga;
+        let result = insert_text(rga, position, &text);
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(edit);
+
+        self.broadcast_composition_end(Some(position));
+
+        let response = RGAResponse {
+            response_type: "update".to_string(),
+            content,
+            position: Some(position),
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+
+        self.send_response(&response).await?;
+        self.broadcast(response);
+        info!(
+            "Session {} committed a composition of {} characters at position {}",
+            self.session_id,
+            text.chars().count(),
+            position
+        );
+        // Whatever was inserted before a failure has already been shared
+        result
+    }
+
+    /// Insert a completed chunked paste.
+    ///
+    /// The text is inserted as one chained run while no other edit is in
+    /// flight, so peers see the paste arrive in a single update.
+    async fn handle_paste_commit(&mut self, operation: RGAOperation) -> ServerResult {
+        let transaction = required(operation.transaction, "paste_commit", "transaction")?;
+        let (position, text) = self.pastes.commit(&transaction)?;
+
+        let exclusive = self.state.exclusive().await;
+        let rga = &self.state.rga;
+        let result = insert_text(rga, position, &text);
+        let content = rga.to_string();
+        let version = self.state.record_edit();
+        drop(exclusive);
+
+        let response = RGAResponse {
+            response_type: "update".to_string(),
+            content,
+            position: Some(position),
+            session_id: None,
+            decorations: None,
+            version: Some(version),
+            selection: None,
+            awareness: None,
+            stats: None,
+        };
+        self.send_response(&response).await?;
+        self.broadcast(response);
+        info!(
+            "Session {} pasted {} bytes at position {} (transaction {})",
+            self.session_id,
+            text.len(),
+            position,
+            transaction
+        );
+        result
+    }
+
+    /// Handle an abandoned IME composition
+    fn handle_composition_cancel(&mut self) {
+        self.broadcast_composition_end(None);
+    }
+
+    /// Tell peers to stop displaying this session's pending composition
+    fn broadcast_composition_end(&mut self, position: Option<usize>) {
+        self.composing = false;
+        self.broadcast(RGAResponse {
+            response_type: "composition_end".to_string(),
+            content: String::new(),
+            position,
+            session_id: Some(self.session_id.clone()),
+            decorations: None,
+            version: None,
+            selection: None,
+            awareness: None,
+            stats: None,
+        });
+    }
+
+    /// Forward a response to every other session connected to the document
+    fn broadcast(&self, response: RGAResponse) {
+        self.state.publish(PeerMessage {
+            origin: self.session_id.clone(),
+            response,
+        });
+    }
+
+    /// Send a response message to the client, through the document's
+    /// outbound middleware
+    async fn send_response(&mut self, response: &RGAResponse) -> ServerResult {
+        if !self.state.filters_outbound() {
+            return self.send_frame(response).await;
+        }
+        match self
+            .state
+            .filter_outbound(&self.session_id, response.clone())
+        {
+            Some(response) => self.send_frame(&response).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send a response message to the client as it is
+    async fn send_frame(&mut self, response: &RGAResponse) -> ServerResult {
+        let json = serde_json::to_string(response).map_err(ServerError::Serialization)?;
         self.socket.send(Message::Text(json)).await?;
         Ok(())
     }
 }
 
+/// Drain the messages waiting in `peer_rx` that a session holding the
+/// document at `version` still needs: everything but its own messages and the
+/// updates its version already contains.
+///
+/// Returns the messages and the version the session reaches by applying them.
+fn catch_up(
+    peer_rx: &mut broadcast::Receiver<PeerBatch>,
+    mut version: u64,
+    session_id: &str,
+) -> (Vec<RGAResponse>, u64) {
+    let snapshot_version = version;
+    let mut missed = Vec::new();
+    loop {
+        match peer_rx.try_recv() {
+            Ok(batch) => {
+                for message in batch.iter() {
+                    if message.origin == session_id {
+                        continue;
+                    }
+                    if let Some(update) = message.response.version {
+                        if update <= snapshot_version {
+                            continue;
+                        }
+                        version = version.max(update);
+                    }
+                    missed.push(message.response.clone());
+                }
+            }
+            Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                warn!(
+                    "Session {} lagged behind by {} peer batches while joining",
+                    session_id, skipped
+                );
+            }
+            Err(_) => break,
+        }
+    }
+    (missed, version)
+}
+
+/// Sleep until `deadline`; pending forever without one
+async fn sleep_until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Insert `text` at visible `position` as one chained run
+pub(crate) fn insert_text(rga: &RGA, position: usize, text: &str) -> ServerResult {
+    rga.insert_str_after(rga.read_txn().insertion_point(position), text)?;
+    Ok(())
+}
+
+/// Replace `length` visible characters at `position` with `text` in one
+/// transaction
+pub(crate) fn replace_text(
+    rga: &RGA,
+    position: usize,
+    length: usize,
+    text: &str,
+) -> ServerResult<Commit> {
+    let visible = rga.read_txn();
+    let deleted = position
+        .checked_add(length)
+        .and_then(|end| visible.visible_nodes().get(position..end))
+        .ok_or(ServerError::Rejected("Replaced range out of range"))?;
+    let commit = rga.transaction(|txn| {
+        for node in deleted {
+            txn.delete(node.id)?;
+        }
+        txn.insert_str_after(visible.insertion_point(position), text)?;
+        Ok(())
+    })?;
+    Ok(commit)
+}
+
+/// Unwrap a field an operation requires
+pub(crate) fn required<T>(
+    value: Option<T>,
+    operation: &'static str,
+    field: &'static str,
+) -> ServerResult<T> {
+    value.ok_or(ServerError::MissingField { operation, field })
+}
+
 /// Generate a unique session ID
+///
+/// The timestamp keeps IDs unique across restarts and the counter keeps them
+/// unique within a millisecond. A clock set before 1970 only loses the former.
 pub fn generate_session_id() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
 
+    static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .unwrap()
+        .unwrap_or_default()
         .as_millis();
+    let sequence = NEXT_SESSION.fetch_add(1, Ordering::Relaxed);
 
-    format!("session_{}", timestamp)
+    format!("session_{}_{}", timestamp, sequence)
 }
 
-/// Create and handle a new WebSocket session
-pub async fn handle_websocket_connection(socket: WebSocket, state: AppState) {
+/// Create and handle a new WebSocket session on document `document_id` with
+/// `permission`, capturing its frames to `capture_dir` if given
+pub async fn handle_websocket_connection(
+    socket: WebSocket,
+    document_id: String,
+    state: Arc<DocumentState>,
+    permission: Permission,
+    capture_dir: Option<PathBuf>,
+) {
     let session_id = generate_session_id();
-    let session = WebSocketSession::new(socket, state, session_id);
+    info!(
+        "Session {} connected to {} with {} access",
+        session_id, document_id, permission
+    );
+    let replica_id = state.rga.replica_id();
+    let mut session = WebSocketSession::new(socket, state, session_id, permission);
+    if let Some(dir) = capture_dir {
+        match SessionCapture::create(
+            &dir,
+            &session.session_id,
+            &document_id,
+            permission,
+            replica_id,
+        ) {
+            Ok(capture) => session.set_capture(capture),
+            Err(e) => warn!("Cannot capture session {}: {}", session.session_id, e),
+        }
+    }
     session.handle().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(origin: &str, response_type: &str, version: Option<u64>) -> PeerMessage {
+        PeerMessage {
+            origin: origin.to_string(),
+            response: RGAResponse {
+                response_type: response_type.to_string(),
+                content: String::new(),
+                position: None,
+                session_id: None,
+                decorations: None,
+                version,
+                selection: None,
+                awareness: None,
+                stats: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_replays_what_the_snapshot_misses() {
+        let state = Arc::new(DocumentState::new(RGA::new(1)));
+        let mut peer_rx = state.peers.subscribe();
+
+        // An edit applied before the snapshot but published after it
+        let applied = state.record_edit();
+        let (_, version) = state.versioned_snapshot().await;
+        assert_eq!(version, applied);
+        state.publish(message("a", "update", Some(applied)));
+        state.publish(message("b", "presence", None));
+        let later = state.record_edit();
+        state.publish(message("a", "update", Some(later)));
+        state.publish(message("joining", "presence", None));
+
+        let (missed, caught_up) = catch_up(&mut peer_rx, version, "joining");
+        let types: Vec<_> = missed
+            .iter()
+            .map(|response| (response.response_type.as_str(), response.version))
+            .collect();
+        assert_eq!(types, [("presence", None), ("update", Some(later))]);
+        assert_eq!(caught_up, later);
+        assert!(catch_up(&mut peer_rx, caught_up, "joining").0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_too_far_behind_are_resynchronized() {
+        let state = Arc::new(DocumentState::new(RGA::new(1)));
+        state.set_max_peer_lag(2);
+        let mut peer_rx = state.peers.subscribe();
+        for _ in 0..4 {
+            state.publish(message("a", "presence", None));
+        }
+
+        let first = peer_rx.recv().await.unwrap();
+        assert_eq!(first.sequence, 1);
+        assert!(state.lags_behind(&first));
+        let second = peer_rx.recv().await.unwrap();
+        assert!(!state.lags_behind(&second));
+    }
+
+    #[test]
+    fn test_replaced_ranges_past_the_end_are_rejected() {
+        let rga = RGA::new(1);
+        insert_text(&rga, 0, "abc").unwrap();
+        for (position, length) in [(2, 2), (4, 0), (1, usize::MAX), (usize::MAX, 1)] {
+            let error = replace_text(&rga, position, length, "x").unwrap_err();
+            assert_eq!(error.code(), "rejected");
+        }
+        replace_text(&rga, 1, 2, "x").unwrap();
+        assert_eq!(rga.to_string(), "ax");
+    }
+
+    #[test]
+    fn test_only_connected_sessions_stay_muted() {
+        let state = DocumentState::new(RGA::new(1));
+        state
+            .sessions
+            .lock()
+            .extend(["a".to_string(), "b".to_string()]);
+        let mut muted = HashSet::new();
+        let sessions =
+            |ids: &[&str]| -> Vec<String> { ids.iter().map(|id| id.to_string()).collect() };
+
+        state.mute(&mut muted, sessions(&["a", "b", "gone", "never"]));
+        assert_eq!(muted, HashSet::from_iter(sessions(&["a", "b"])));
+        // Peers that left are forgotten the next time the session mutes
+        state.sessions.lock().remove("a");
+        state.mute(&mut muted, sessions(&["b"]));
+        assert_eq!(muted, HashSet::from_iter(sessions(&["b"])));
+    }
+}
@@ -0,0 +1,237 @@
+//! A cluster of replicas wired together in memory, stepped by hand.
+//!
+//! [`Cluster`] is the harness for tests, examples and benchmarks that just
+//! need several replicas to exchange their edits. Edits are made directly on
+//! [`Cluster::replica`]; whenever the cluster is stepped, it picks up what
+//! each replica changed since and queues it on a FIFO link to every other
+//! replica. [`Cluster::step`] delivers a single message, so tests can
+//! interleave deliveries with further edits, and
+//! [`Cluster::run_until_quiescent`] delivers everything.
+//!
+//! A cluster owns its replicas or, for tests that keep their own, borrows
+//! them.
+//!
+//! Unlike [`SimNetwork`](crate::testing::SimNetwork) there is no notion of
+//! time: links take turns in a fixed order, so runs are deterministic without
+//! a seed. Messages relayed through different links can still arrive before
+//! their dependencies, and are held back until these arrive.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+
+use crate::crdt::{CausalBuffer, Node, RGA, UniqueId};
+use crate::testing::convergence::{Divergence, assert_converged, check_converged};
+
+/// A full mesh of replicas exchanging their edits over in-memory links.
+///
+/// # Example
+///
+/// ```rust
+/// use crdt_rga::testing::Cluster;
+///
+/// let mut cluster = Cluster::new(3);
+/// for (index, word) in ["one ", "two ", "three "].into_iter().enumerate() {
+///     let replica = cluster.replica(index);
+///     replica.insert_str_after(replica.sentinel_start_id(), word).unwrap();
+/// }
+/// cluster.run_until_quiescent();
+/// cluster.assert_converged();
+/// assert_eq!(cluster.replica(2).to_string(), "three two one ");
+/// ```
+pub struct Cluster<R = RGA> {
+    replicas: Vec<R>,
+    /// Per replica, the nodes it has sent or received and whether they were
+    /// deleted
+    sent: Vec<BTreeMap<UniqueId, bool>>,
+    /// FIFO link `from -> to` at index `from * len + to`
+    links: Vec<VecDeque<Node>>,
+    /// Per-replica buffers for messages whose dependencies have not arrived
+    held_back: Vec<CausalBuffer>,
+    /// Link the next step looks at first
+    next_link: usize,
+}
+
+impl Cluster {
+    /// Creates a cluster of `replica_count` fresh replicas; replica `i` has
+    /// replica ID `i + 1`.
+    pub fn new(replica_count: usize) -> Self {
+        Self::from_replicas((1..=replica_count as u64).map(RGA::new).collect())
+    }
+}
+
+impl<R: Borrow<RGA>> Cluster<R> {
+    /// Wires existing replicas, such as forks of one document, into a
+    /// cluster, owned (`Vec<RGA>`) or borrowed (`Vec<&RGA>`).
+    ///
+    /// Nodes a replica already holds are sent on the first step; those the
+    /// others have too are ignored on arrival.
+    pub fn from_replicas(replicas: Vec<R>) -> Self {
+        let count = replicas.len();
+        Cluster {
+            replicas,
+            sent: (0..count).map(|_| BTreeMap::new()).collect(),
+            links: (0..count * count).map(|_| VecDeque::new()).collect(),
+            held_back: (0..count).map(|_| CausalBuffer::new()).collect(),
+            next_link: 0,
+        }
+    }
+
+    /// Returns the replica at `index`.
+    pub fn replica(&self, index: usize) -> &RGA {
+        self.replicas[index].borrow()
+    }
+
+    /// Returns all replicas.
+    pub fn replicas(&self) -> &[R] {
+        &self.replicas
+    }
+
+    /// Unwraps the replicas, losing the messages still in flight.
+    pub fn into_replicas(self) -> Vec<R> {
+        self.replicas
+    }
+
+    /// Number of replicas in the cluster.
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Returns true if the cluster has no replicas.
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Number of messages still travelling or held back.
+    ///
+    /// Edits made since the last step are not counted until a step sends them.
+    pub fn pending_messages(&self) -> usize {
+        self.links.iter().map(VecDeque::len).sum::<usize>()
+            + self.held_back.iter().map(CausalBuffer::len).sum::<usize>()
+    }
+
+    /// Sends new edits, then delivers the oldest message of the next link
+    /// that has one.
+    ///
+    /// # Returns
+    ///
+    /// * `true` - If a message was delivered
+    /// * `false` - If every link was empty
+    pub fn step(&mut self) -> bool {
+        self.send_edits();
+        let count = self.links.len();
+        for offset in 0..count {
+            let link = (self.next_link + offset) % count;
+            if let Some(node) = self.links[link].pop_front() {
+                let to = link % self.replicas.len();
+                // Received nodes are not the replica's own edits to send on
+                let deleted = self.sent[to].entry(node.id).or_insert(node.is_deleted);
+                *deleted |= node.is_deleted;
+                self.held_back[to].deliver(self.replicas[to].borrow(), node);
+                self.next_link = (link + 1) % count;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Delivers messages until none are left in flight.
+    ///
+    /// # Returns
+    ///
+    /// The number of messages delivered
+    pub fn run_until_quiescent(&mut self) -> usize {
+        let mut delivered = 0;
+        while self.step() {
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Returns true if every replica holds the same visible nodes.
+    pub fn is_converged(&self) -> bool {
+        self.check_converged().is_ok()
+    }
+
+    /// Explains how the replicas differ, if they do; see [`check_converged`].
+    pub fn check_converged(&self) -> Result<(), Divergence> {
+        check_converged(self.replicas.iter().map(Borrow::borrow))
+    }
+
+    /// Panics with a [`Divergence`] report unless every replica holds the same
+    /// visible nodes; see [`assert_converged`].
+    #[track_caller]
+    pub fn assert_converged(&self) {
+        assert_converged(self.replicas.iter().map(Borrow::borrow));
+    }
+
+    /// Queues what each replica inserted or deleted since it last sent, on
+    /// every link from it
+    fn send_edits(&mut self) {
+        let count = self.replicas.len();
+        for from in 0..count {
+            let sent = &mut self.sent[from];
+            let mut messages = Vec::new();
+            self.replicas[from].borrow().for_each_node(|node| {
+                if node.is_sentinel() {
+                    return;
+                }
+                match sent.insert(node.id, node.is_deleted) {
+                    // A node deleted before it was sent needs its insertion
+                    // first, or it would wait for itself
+                    None if node.is_deleted => {
+                        messages.push(Node {
+                            is_deleted: false,
+                            ..node.clone()
+                        });
+                        messages.push(node.clone());
+                    }
+                    None => messages.push(node.clone()),
+                    Some(false) if node.is_deleted => messages.push(node.clone()),
+                    Some(_) => {}
+                }
+            });
+            for to in (0..count).filter(|&to| to != from) {
+                self.links[from * count + to].extend(messages.iter().cloned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_cluster_exchanges_edits_step_by_step() {
+        let mut cluster = Cluster::new(3);
+        assert!(!cluster.step());
+
+        let first = cluster.replica(0);
+        let ids = first
+            .insert_str_after(first.sentinel_start_id(), "abc")
+            .unwrap();
+        first.delete(ids[1]).unwrap();
+        // Typed and deleted before anything was sent
+        let second = cluster.replica(1);
+        let x = second
+            .insert_after(second.sentinel_start_id(), 'x')
+            .unwrap();
+        second.delete(x).unwrap();
+
+        // Each edit goes to both other replicas: 3 insertions and a deletion
+        // from the first, an insertion and its deletion from the second
+        assert!(cluster.step());
+        assert_eq!(cluster.pending_messages(), 6 * 2 - 1);
+        assert!(!cluster.is_converged());
+
+        // Received edits are not sent on
+        assert_eq!(cluster.run_until_quiescent(), 6 * 2 - 1);
+        cluster.replica(2).delete(ids[2]).unwrap();
+        assert_eq!(cluster.run_until_quiescent(), 2);
+        cluster.assert_converged();
+        assert_eq!(cluster.replica(1).to_string(), "a");
+        assert_eq!(cluster.pending_messages(), 0);
+    }
+}
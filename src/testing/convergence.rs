@@ -0,0 +1,210 @@
+//! Convergence checks with readable divergence reports.
+//!
+//! Comparing rendered strings only says *that* replicas differ. These helpers
+//! compare the replicas node by node and, when they diverge, explain where the
+//! documents first differ, which nodes are responsible and which operations
+//! each replica is still missing.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::crdt::{RGA, ReplicaId, UniqueId};
+
+/// What a single replica lacks compared to the union of all replicas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaDivergence {
+    /// The replica this report is about
+    pub replica_id: ReplicaId,
+    /// The replica's visible content
+    pub content: String,
+    /// Nodes other replicas have that this replica never received
+    pub missing_inserts: Vec<UniqueId>,
+    /// Nodes other replicas deleted that are still visible here
+    pub missing_deletes: Vec<UniqueId>,
+}
+
+/// An explanation of why a set of replicas has not converged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The first visible index at which the replicas disagree
+    pub position: usize,
+    /// The node each replica has at `position`, in replica order
+    /// (`None` where the replica's document ends before it)
+    pub nodes_at_position: Vec<Option<(UniqueId, char)>>,
+    /// Per-replica reports, in replica order
+    pub replicas: Vec<ReplicaDivergence>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "replicas diverge at visible index {}", self.position)?;
+        for (replica, node) in self.replicas.iter().zip(&self.nodes_at_position) {
+            write!(
+                f,
+                "  replica {}: {:?} ",
+                replica.replica_id, replica.content
+            )?;
+            match node {
+                Some((id, ch)) => write!(f, "has {ch:?} ({id:?}) there")?,
+                None => write!(f, "ends there")?,
+            }
+            if !replica.missing_inserts.is_empty() {
+                write!(f, "; missing inserts {:?}", replica.missing_inserts)?;
+            }
+            if !replica.missing_deletes.is_empty() {
+                write!(f, "; missing deletes {:?}", replica.missing_deletes)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every replica holds the same visible nodes in the same order.
+///
+/// # Arguments
+///
+/// * `replicas` - The replicas to compare
+///
+/// # Returns
+///
+/// * `Ok(())` - If all replicas agree (vacuously true for fewer than two)
+/// * `Err(Divergence)` - An explanation of the first disagreement
+pub fn check_converged<'a>(replicas: impl IntoIterator<Item = &'a RGA>) -> Result<(), Divergence> {
+    let replicas: Vec<&RGA> = replicas.into_iter().collect();
+    let visible: Vec<Vec<(UniqueId, char)>> = replicas
+        .iter()
+        .map(|rga| {
+            rga.visible_nodes()
+                .into_iter()
+                .map(|node| (node.id, node.character))
+                .collect()
+        })
+        .collect();
+
+    let Some(first) = visible.first() else {
+        return Ok(());
+    };
+    if visible.iter().all(|nodes| nodes == first) {
+        return Ok(());
+    }
+
+    let longest = visible.iter().map(Vec::len).max().unwrap_or(0);
+    let position = (0..longest)
+        .find(|&index| {
+            let expected = first.get(index);
+            visible.iter().any(|nodes| nodes.get(index) != expected)
+        })
+        .unwrap_or(longest);
+    let nodes_at_position = visible
+        .iter()
+        .map(|nodes| nodes.get(position).copied())
+        .collect();
+
+    // The union of what any replica has seen: node id -> deleted anywhere
+    let mut union: BTreeMap<UniqueId, bool> = BTreeMap::new();
+    for rga in &replicas {
+        for node in rga.all_nodes() {
+            if !node.is_sentinel() {
+                *union.entry(node.id).or_default() |= node.is_deleted;
+            }
+        }
+    }
+
+    let reports = replicas
+        .iter()
+        .zip(&visible)
+        .map(|(rga, nodes)| {
+            let known: BTreeSet<UniqueId> = rga.all_nodes().into_iter().map(|n| n.id).collect();
+            let shown: BTreeSet<UniqueId> = nodes.iter().map(|&(id, _)| id).collect();
+            ReplicaDivergence {
+                replica_id: rga.replica_id(),
+                content: rga.to_string(),
+                missing_inserts: union
+                    .keys()
+                    .filter(|id| !known.contains(id))
+                    .copied()
+                    .collect(),
+                missing_deletes: union
+                    .iter()
+                    .filter(|&(id, &deleted)| deleted && shown.contains(id))
+                    .map(|(&id, _)| id)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Err(Divergence {
+        position,
+        nodes_at_position,
+        replicas: reports,
+    })
+}
+
+/// Asserts that every replica holds the same visible nodes in the same order.
+///
+/// # Panics
+///
+/// Panics with a [`Divergence`] explanation if the replicas differ.
+#[track_caller]
+pub fn assert_converged<'a>(replicas: impl IntoIterator<Item = &'a RGA>) {
+    if let Err(divergence) = check_converged(replicas) {
+        panic!("{divergence}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_replicas_converge() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let id = rga1.insert_after(rga1.sentinel_start_id(), 'a').unwrap();
+        rga2.apply_remote_op(rga1.get_node(id).unwrap());
+
+        assert_converged([&rga1, &rga2]);
+        assert_converged(core::iter::empty());
+    }
+
+    #[test]
+    fn test_divergence_reports_missing_ops() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        let start_id = rga1.sentinel_start_id();
+        let a_id = rga1.insert_after(start_id, 'a').unwrap();
+        let b_id = rga1.insert_after(a_id, 'b').unwrap();
+        rga2.apply_remote_op(rga1.get_node(a_id).unwrap());
+        rga2.apply_remote_op(rga1.get_node(b_id).unwrap());
+        rga1.delete(a_id).unwrap();
+        let c_id = rga2.insert_after(b_id, 'c').unwrap();
+
+        let divergence = check_converged([&rga1, &rga2]).unwrap_err();
+        assert_eq!(divergence.position, 0);
+        assert_eq!(
+            divergence.nodes_at_position,
+            vec![Some((b_id, 'b')), Some((a_id, 'a'))]
+        );
+        assert_eq!(divergence.replicas[0].missing_inserts, vec![c_id]);
+        assert!(divergence.replicas[0].missing_deletes.is_empty());
+        assert!(divergence.replicas[1].missing_inserts.is_empty());
+        assert_eq!(divergence.replicas[1].missing_deletes, vec![a_id]);
+
+        let explanation = divergence.to_string();
+        assert!(explanation.contains("visible index 0"));
+        assert!(explanation.contains("missing deletes"));
+    }
+
+    #[test]
+    #[should_panic(expected = "replicas diverge")]
+    fn test_assert_converged_panics_on_divergence() {
+        let rga1 = RGA::new(1);
+        let rga2 = RGA::new(2);
+        rga1.insert_after(rga1.sentinel_start_id(), 'x').unwrap();
+
+        assert_converged([&rga1, &rga2]);
+    }
+}
@@ -0,0 +1,333 @@
+//! A replica behind an unreliable link, for testing retry and resync logic.
+//!
+//! [`FlakyRga`] wraps an [`RGA`] and stands between it and the remote
+//! operations an application forwards to it. Following a seeded
+//! [`FaultPolicy`], each operation is dropped, held back for a while or
+//! delivered twice, so an application's tests can check that it notices what
+//! went missing (through [`RGA::sync_metrics`], [`check_converged`] or its own
+//! acknowledgements) and recovers, for example by resending what
+//! [`RGA::missing_from`] reports.
+//!
+//! Operations reach the inner RGA as they would over a real network without
+//! ordering guarantees: through a [`CausalBuffer`], which holds back those
+//! whose dependencies were delayed or lost until they arrive, while
+//! duplicates are deduplicated by the RGA. Time only advances with
+//! [`FlakyRga::advance`], and equal seeds give identical runs.
+//!
+//! [`check_converged`]: crate::testing::check_converged
+
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use core::time::Duration;
+
+use crate::crdt::{CausalBuffer, Node, RGA};
+use crate::testing::transport::SplitMix64;
+
+/// How often each kind of fault happens to a forwarded operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultPolicy {
+    /// Probability that an operation is lost
+    pub drop_probability: f64,
+    /// Probability that an operation that was not lost is delivered twice
+    pub duplicate_probability: f64,
+    /// Probability that a delivery is held back
+    pub delay_probability: f64,
+    /// Held-back deliveries wait a duration drawn uniformly from
+    /// `0..=max_delay`
+    pub max_delay: Duration,
+}
+
+impl FaultPolicy {
+    /// A policy that forwards every operation once, at once.
+    pub fn reliable() -> Self {
+        FaultPolicy {
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    /// Returns the policy losing operations with the given probability.
+    pub fn with_drops(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Returns the policy delivering operations twice with the given
+    /// probability.
+    pub fn with_duplicates(mut self, probability: f64) -> Self {
+        self.duplicate_probability = probability;
+        self
+    }
+
+    /// Returns the policy holding deliveries back for up to `max_delay` with
+    /// the given probability.
+    pub fn with_delays(mut self, probability: f64, max_delay: Duration) -> Self {
+        self.delay_probability = probability;
+        self.max_delay = max_delay;
+        self
+    }
+}
+
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        Self::reliable()
+    }
+}
+
+/// What happened to the operations forwarded so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Operations handed to the wrapper
+    pub received: u64,
+    /// Operations lost
+    pub dropped: u64,
+    /// Operations delivered a second time
+    pub duplicated: u64,
+    /// Deliveries held back
+    pub delayed: u64,
+    /// Deliveries that reached the inner RGA, duplicates included
+    pub delivered: u64,
+}
+
+/// A delivery held back, ordered by due time then hold order.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Delayed {
+    deliver_at: Duration,
+    seq: u64,
+    node: Node,
+}
+
+/// An [`RGA`] whose remote operations pass through a faulty link.
+///
+/// # Example
+///
+/// ```rust
+/// use core::time::Duration;
+/// use crdt_rga::RGA;
+/// use crdt_rga::testing::{FaultPolicy, FlakyRga};
+///
+/// let source = RGA::new(1);
+/// source.insert_str_after(source.sentinel_start_id(), "hello").unwrap();
+///
+/// let policy = FaultPolicy::reliable()
+///     .with_drops(0.3)
+///     .with_delays(0.5, Duration::from_millis(100));
+/// let mut flaky = FlakyRga::new(RGA::new(2), policy, 7);
+/// for node in source.missing_from(flaky.inner()) {
+///     flaky.apply_remote_op(node);
+/// }
+/// flaky.flush();
+///
+/// // Whatever was lost is found and sent again
+/// while !source.missing_from(flaky.inner()).is_empty() {
+///     for node in source.missing_from(flaky.inner()) {
+///         flaky.apply_remote_op(node);
+///     }
+///     flaky.flush();
+/// }
+/// assert_eq!(flaky.inner().to_string(), "hello");
+/// ```
+pub struct FlakyRga {
+    inner: RGA,
+    policy: FaultPolicy,
+    rng: SplitMix64,
+    delayed: BinaryHeap<Reverse<Delayed>>,
+    /// Delivered operations whose dependencies have not arrived
+    held_back: CausalBuffer,
+    now: Duration,
+    next_seq: u64,
+    stats: FaultStats,
+}
+
+impl FlakyRga {
+    /// Wraps `inner` behind a link following `policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The replica operations are forwarded to
+    /// * `policy` - How often operations are dropped, duplicated and delayed
+    /// * `seed` - Seed for the faults; equal seeds give identical runs
+    pub fn new(inner: RGA, policy: FaultPolicy, seed: u64) -> Self {
+        FlakyRga {
+            inner,
+            policy,
+            rng: SplitMix64::new(seed),
+            delayed: BinaryHeap::new(),
+            held_back: CausalBuffer::new(),
+            now: Duration::ZERO,
+            next_seq: 0,
+            stats: FaultStats::default(),
+        }
+    }
+
+    /// Returns the wrapped replica.
+    pub fn inner(&self) -> &RGA {
+        &self.inner
+    }
+
+    /// Unwraps the replica, losing the deliveries still held back.
+    pub fn into_inner(self) -> RGA {
+        self.inner
+    }
+
+    /// Changes the policy applied to operations forwarded from now on, for
+    /// example to heal the link.
+    pub fn set_policy(&mut self, policy: FaultPolicy) {
+        self.policy = policy;
+    }
+
+    /// Current simulated time.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Number of deliveries the link holds back.
+    pub fn pending(&self) -> usize {
+        self.delayed.len()
+    }
+
+    /// Number of delivered operations waiting for a dependency that was
+    /// delayed or lost.
+    pub fn held_back(&self) -> usize {
+        self.held_back.len()
+    }
+
+    /// What happened to the operations forwarded so far.
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    /// Forwards a remote operation to the inner replica, unless the policy
+    /// loses, delays or duplicates it.
+    pub fn apply_remote_op(&mut self, node: Node) {
+        self.stats.received += 1;
+        if self.rng.next_f64() < self.policy.drop_probability {
+            self.stats.dropped += 1;
+            return;
+        }
+        if self.rng.next_f64() < self.policy.duplicate_probability {
+            self.stats.duplicated += 1;
+            self.send(node.clone());
+        }
+        self.send(node);
+    }
+
+    /// Delivers every held-back operation due by `now + duration` and
+    /// advances the clock.
+    pub fn advance(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        while self
+            .delayed
+            .peek()
+            .is_some_and(|Reverse(delayed)| delayed.deliver_at <= until)
+        {
+            self.deliver_next();
+        }
+        self.now = until;
+    }
+
+    /// Delivers every held-back operation.
+    ///
+    /// # Returns
+    ///
+    /// The simulated time at which the last one was delivered
+    pub fn flush(&mut self) -> Duration {
+        while !self.delayed.is_empty() {
+            self.deliver_next();
+        }
+        self.now
+    }
+
+    /// Delivers `node` now or holds it back
+    fn send(&mut self, node: Node) {
+        if self.rng.next_f64() < self.policy.delay_probability {
+            self.stats.delayed += 1;
+            let deliver_at = self.now + self.rng.duration_up_to(self.policy.max_delay);
+            self.delayed.push(Reverse(Delayed {
+                deliver_at,
+                seq: self.next_seq,
+                node,
+            }));
+            self.next_seq += 1;
+        } else {
+            self.deliver(node);
+        }
+    }
+
+    fn deliver_next(&mut self) {
+        let Some(Reverse(delayed)) = self.delayed.pop() else {
+            return;
+        };
+        self.now = self.now.max(delayed.deliver_at);
+        self.deliver(delayed.node);
+    }
+
+    fn deliver(&mut self, node: Node) {
+        self.stats.delivered += 1;
+        self.held_back.deliver(&self.inner, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Sends everything `source` has that `flaky` lacks, then lets it arrive
+    fn resend(source: &RGA, flaky: &mut FlakyRga) -> usize {
+        let missing = source.missing_from(flaky.inner());
+        let count = missing.len();
+        for node in missing {
+            flaky.apply_remote_op(node);
+        }
+        flaky.flush();
+        count
+    }
+
+    #[test]
+    fn test_faults_follow_the_seeded_policy() {
+        let source = RGA::new(1);
+        source
+            .insert_str_after(source.sentinel_start_id(), "the quick brown fox")
+            .unwrap();
+
+        let policy = FaultPolicy::reliable()
+            .with_drops(0.25)
+            .with_duplicates(0.25)
+            .with_delays(0.5, Duration::from_millis(200));
+        let run = |seed| {
+            let mut flaky = FlakyRga::new(RGA::new(2), policy, seed);
+            let sent = resend(&source, &mut flaky);
+            let rounds = 1
+                + (0..50)
+                    .take_while(|_| resend(&source, &mut flaky) > 0)
+                    .count();
+            assert_eq!(flaky.inner().to_string(), source.to_string());
+            (sent, rounds, flaky.stats())
+        };
+
+        let (sent, rounds, stats) = run(11);
+        assert_eq!(sent, 19);
+        assert!(rounds > 1);
+        assert!(stats.dropped > 0 && stats.duplicated > 0 && stats.delayed > 0);
+        assert_eq!(
+            stats.delivered,
+            stats.received - stats.dropped + stats.duplicated
+        );
+        assert_eq!(run(11), (sent, rounds, stats));
+
+        // Held-back operations wait for the clock
+        let policy = FaultPolicy::reliable().with_delays(1.0, Duration::from_millis(50));
+        let mut flaky = FlakyRga::new(RGA::new(2), policy, 3);
+        let nodes: Vec<Node> = source.missing_from(flaky.inner());
+        for node in nodes {
+            flaky.apply_remote_op(node);
+        }
+        assert_eq!(flaky.inner().to_string(), "");
+        flaky.advance(Duration::from_millis(50));
+        assert_eq!(flaky.pending(), 0);
+        assert_eq!(flaky.inner().to_string(), source.to_string());
+    }
+}
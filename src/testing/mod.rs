@@ -0,0 +1,18 @@
+//! Utilities for testing and simulating replicated RGA deployments.
+//!
+//! Nothing in here is needed to use the CRDT; these helpers exist so tests,
+//! examples and benchmarks can exercise several replicas without a real network.
+
+pub mod cluster;
+pub mod convergence;
+pub mod flaky;
+pub mod simulation;
+#[cfg(feature = "traces")]
+pub mod traces;
+pub mod transport;
+
+pub use cluster::Cluster;
+pub use convergence::{Divergence, ReplicaDivergence, assert_converged, check_converged};
+pub use flaky::{FaultPolicy, FaultStats, FlakyRga};
+pub use simulation::{OpMix, Simulation, SimulationConfig, SimulationReport};
+pub use transport::{Latency, LinkConfig, SimNetwork, SplitMix64};
@@ -0,0 +1,201 @@
+//! Seeded editing scenarios over a simulated network.
+//!
+//! A [`Simulation`] lets several replicas edit one document at once over a
+//! [`SimNetwork`]: every round, each replica inserts or deletes a character at
+//! a position of its own choosing, then simulated time moves on. Everything
+//! the replicas do follows from a [`SimulationConfig`], the seed included, so
+//! the examples, benchmarks and tests that share a configuration replay the
+//! same edits and their numbers can be compared from run to run.
+
+use alloc::string::{String, ToString};
+use core::time::Duration;
+
+use crate::testing::transport::{LinkConfig, SimNetwork, SplitMix64};
+
+/// Relative weights of the edits replicas make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpMix {
+    /// Weight of inserting a character
+    pub insert: u32,
+    /// Weight of deleting a character; a replica with nothing to delete
+    /// inserts instead
+    pub delete: u32,
+}
+
+impl OpMix {
+    /// Insertions only, as in plain typing
+    pub fn inserts_only() -> Self {
+        OpMix {
+            insert: 1,
+            delete: 0,
+        }
+    }
+}
+
+impl Default for OpMix {
+    /// Three insertions for every deletion, like typing with corrections
+    fn default() -> Self {
+        OpMix {
+            insert: 3,
+            delete: 1,
+        }
+    }
+}
+
+/// Everything that determines a simulation run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationConfig {
+    /// Number of replicas editing
+    pub replicas: usize,
+    /// Edits each replica makes
+    pub ops_per_replica: usize,
+    /// How the edits split between insertions and deletions
+    pub mix: OpMix,
+    /// Conditions of every link
+    pub link: LinkConfig,
+    /// Simulated time between two rounds of edits
+    pub step: Duration,
+    /// Seed of the edits and of the network's delays
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            replicas: 3,
+            ops_per_replica: 100,
+            mix: OpMix::default(),
+            link: LinkConfig::fixed(Duration::from_millis(20)),
+            step: Duration::from_millis(5),
+            seed: 0,
+        }
+    }
+}
+
+/// What a finished run did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    /// Characters inserted, across replicas
+    pub inserts: usize,
+    /// Deletions made, across replicas; replicas deleting the same
+    /// character concurrently each count one
+    pub deletes: usize,
+    /// Simulated time until every edit reached every replica
+    pub convergence_time: Duration,
+    /// The text of the first replica at the end
+    pub content: String,
+}
+
+/// Concurrent editing driven by a [`SimulationConfig`].
+///
+/// ```rust
+/// use crdt_rga::testing::{Simulation, SimulationConfig};
+///
+/// let config = SimulationConfig { seed: 7, ..SimulationConfig::default() };
+/// let report = Simulation::new(config).run();
+/// assert_eq!(report, Simulation::new(config).run());
+/// ```
+pub struct Simulation {
+    config: SimulationConfig,
+    network: SimNetwork,
+    rng: SplitMix64,
+}
+
+impl Simulation {
+    /// Creates fresh replicas over links configured as `config` says
+    pub fn new(config: SimulationConfig) -> Self {
+        let mut network = SimNetwork::new(config.replicas, config.seed);
+        network.set_default_link(config.link);
+        Simulation {
+            config,
+            network,
+            // Distinct from the network's stream of delays
+            rng: SplitMix64::new(!config.seed),
+        }
+    }
+
+    /// The network the replicas edit over
+    pub fn network(&self) -> &SimNetwork {
+        &self.network
+    }
+
+    /// Makes every edit, waits until the replicas converge and reports.
+    ///
+    /// # Panics
+    ///
+    /// If the replicas do not converge
+    pub fn run(&mut self) -> SimulationReport {
+        let (mut inserts, mut deletes) = (0, 0);
+        for _ in 0..self.config.ops_per_replica {
+            for from in 0..self.config.replicas {
+                if self.edit(from) {
+                    inserts += 1;
+                } else {
+                    deletes += 1;
+                }
+            }
+            self.network.advance(self.config.step);
+        }
+        let convergence_time = self.network.run_until_quiescent();
+        assert!(self.network.is_converged(), "replicas did not converge");
+        SimulationReport {
+            inserts,
+            deletes,
+            convergence_time,
+            content: self.network.replica(0).to_string(),
+        }
+    }
+
+    /// Makes one edit at replica `from`, returning true for an insertion
+    fn edit(&mut self, from: usize) -> bool {
+        let visible = self.network.replica(from).visible_nodes();
+        let OpMix { insert, delete } = self.config.mix;
+        let roll = self.rng.next_u64() % u64::from((insert + delete).max(1));
+        if roll >= u64::from(insert) && !visible.is_empty() {
+            let index = (self.rng.next_u64() % visible.len() as u64) as usize;
+            // The node is visible at `from`, so deleting it succeeds
+            let _ = self.network.delete(from, visible[index].id);
+            return false;
+        }
+        let index = (self.rng.next_u64() % (visible.len() as u64 + 1)) as usize;
+        let after_id = match index {
+            0 => self.network.replica(from).sentinel_start_id(),
+            _ => visible[index - 1].id,
+        };
+        let character = char::from(b'a' + (self.rng.next_u64() % 26) as u8);
+        // `after_id` is held by `from`, so inserting after it succeeds
+        let _ = self.network.insert_after(from, after_id, character);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_are_reproducible_by_seed() {
+        let config = SimulationConfig {
+            replicas: 4,
+            ops_per_replica: 50,
+            link: LinkConfig::fixed(Duration::from_millis(30))
+                .with_jitter(Duration::from_millis(20)),
+            ..SimulationConfig::default()
+        };
+        let report = Simulation::new(config).run();
+        assert_eq!(report.inserts + report.deletes, 200);
+        assert!(report.deletes > 0);
+        // Concurrent deletions of one character count twice
+        assert!(report.content.chars().count() >= report.inserts - report.deletes);
+        assert_eq!(report, Simulation::new(config).run());
+
+        let other = Simulation::new(SimulationConfig { seed: 1, ..config }).run();
+        assert_ne!(other.content, report.content);
+
+        let typing = SimulationConfig {
+            mix: OpMix::inserts_only(),
+            ..config
+        };
+        assert_eq!(Simulation::new(typing).run().deletes, 0);
+    }
+}
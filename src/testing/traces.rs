@@ -0,0 +1,229 @@
+//! Recorded editing traces for benchmarks.
+//!
+//! Reads traces in the format of the public editing-traces collection
+//! (<https://github.com/josephg/editing-traces>), such as the
+//! `automerge-paper` trace of a paper being written keystroke by keystroke:
+//!
+//! ```json
+//! {
+//!   "startContent": "",
+//!   "endContent": "Hi",
+//!   "txns": [{ "time": "...", "patches": [[0, 0, "H"], [1, 0, "i"]] }]
+//! }
+//! ```
+//!
+//! Each patch deletes `deleted` characters at `position` and inserts text
+//! there. Positions count Unicode scalar values. Files ending in `.gz` are
+//! decompressed while loading.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use crate::crdt::{RGA, UniqueId};
+
+/// Environment variable naming a directory of traces to benchmark
+pub const TRACE_DIR_VAR: &str = "CRDT_RGA_TRACES";
+
+/// An error raised while loading a trace
+#[derive(Debug)]
+pub enum TraceError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not a trace
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "cannot read trace: {e}"),
+            TraceError::Json(e) => write!(f, "invalid trace: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for TraceError {
+    fn from(e: serde_json::Error) -> Self {
+        TraceError::Json(e)
+    }
+}
+
+/// One edit of a trace
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "(usize, usize, String)")]
+pub struct TracePatch {
+    /// Visible index of the edit
+    pub position: usize,
+    /// Characters deleted at `position`
+    pub deleted: usize,
+    /// Text inserted at `position` after the deletion
+    pub inserted: String,
+}
+
+impl From<(usize, usize, String)> for TracePatch {
+    fn from((position, deleted, inserted): (usize, usize, String)) -> Self {
+        TracePatch {
+            position,
+            deleted,
+            inserted,
+        }
+    }
+}
+
+/// Patches applied together, such as one keystroke or one paste
+#[derive(Deserialize, Debug, Clone)]
+pub struct TraceTransaction {
+    pub patches: Vec<TracePatch>,
+}
+
+/// A recorded editing session
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EditingTrace {
+    /// The document before the first transaction
+    #[serde(default)]
+    pub start_content: String,
+    /// The document after the last transaction
+    pub end_content: String,
+    pub txns: Vec<TraceTransaction>,
+}
+
+impl EditingTrace {
+    /// Parses a trace from JSON text
+    pub fn from_json(json: &str) -> Result<Self, TraceError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Loads a `.json` or `.json.gz` trace file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TraceError> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(File::open(path)?);
+        let mut json = String::new();
+        if path.extension().is_some_and(|extension| extension == "gz") {
+            GzDecoder::new(file).read_to_string(&mut json)?;
+        } else {
+            file.read_to_string(&mut json)?;
+        }
+        Self::from_json(&json)
+    }
+
+    /// Every patch of the trace in order
+    pub fn patches(&self) -> impl Iterator<Item = &TracePatch> {
+        self.txns.iter().flat_map(|txn| &txn.patches)
+    }
+
+    /// Number of characters inserted and deleted by the whole trace
+    pub fn operation_count(&self) -> usize {
+        self.patches()
+            .map(|patch| patch.deleted + patch.inserted.chars().count())
+            .sum()
+    }
+
+    /// Replays the trace on an empty replica.
+    ///
+    /// Every character is inserted after the character the trace placed it
+    /// after, and deletions remove the characters the trace deleted. The IDs
+    /// are tracked alongside the document, so no positional lookup in the RGA
+    /// is needed.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<UniqueId>)` - The IDs of the final text in trace order
+    /// * `Err(&str)` - Error message if a patch falls outside the document
+    pub fn replay(&self, rga: &RGA) -> Result<Vec<UniqueId>, &'static str> {
+        let mut ids = if self.start_content.is_empty() {
+            Vec::new()
+        } else {
+            rga.insert_str_after(rga.sentinel_start_id(), &self.start_content)?
+        };
+
+        for patch in self.patches() {
+            let end = patch.position + patch.deleted;
+            if end > ids.len() {
+                return Err("Trace patch out of range");
+            }
+            for id in ids.drain(patch.position..end) {
+                rga.delete(id)?;
+            }
+            if !patch.inserted.is_empty() {
+                let after = match patch.position {
+                    0 => rga.sentinel_start_id(),
+                    position => ids[position - 1],
+                };
+                let inserted = rga.insert_str_after(after, &patch.inserted)?;
+                ids.splice(patch.position..patch.position, inserted);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+/// Trace files to benchmark: those in the directory named by
+/// [`TRACE_DIR_VAR`] if it is set, else the fixtures in `benches/traces`
+pub fn trace_files() -> io::Result<Vec<PathBuf>> {
+    let dir = std::env::var_os(TRACE_DIR_VAR).map_or_else(
+        || Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/traces"),
+        PathBuf::from,
+    );
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    files.retain(|path| {
+        let name = path.to_string_lossy();
+        name.ends_with(".json") || name.ends_with(".json.gz")
+    });
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_reproduces_end_content() {
+        let trace = EditingTrace::from_json(
+            r#"{"startContent": "ab", "endContent": "aXb!",
+                "txns": [{"time": "t", "patches": [[1, 0, "XZ"], [3, 0, "b"], [2, 1, ""]]},
+                         {"time": "t", "patches": [[3, 1, "!"]]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(trace.operation_count(), 6);
+
+        let rga = RGA::new(1);
+        let ids = trace.replay(&rga).unwrap();
+        let text: String = ids
+            .iter()
+            .map(|&id| rga.get_node(id).unwrap().character)
+            .collect();
+        assert_eq!(text, "aXb!");
+        assert_eq!(rga.visible_node_count(), 4);
+    }
+
+    #[test]
+    fn test_fixtures_load() {
+        for path in trace_files().unwrap() {
+            let trace = EditingTrace::load(&path).unwrap();
+            let rga = RGA::new(1);
+            let ids = trace.replay(&rga).unwrap();
+            let text: String = ids
+                .iter()
+                .map(|&id| rga.get_node(id).unwrap().character)
+                .collect();
+            assert_eq!(text, trace.end_content, "{}", path.display());
+        }
+    }
+}
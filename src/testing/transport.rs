@@ -0,0 +1,426 @@
+//! In-memory transport with simulated network conditions.
+//!
+//! [`SimNetwork`] owns a set of replicas and relays every local edit to all
+//! other replicas through per-link queues. Each link samples a delivery delay
+//! from its [`Latency`] distribution plus jitter, and time only advances when
+//! the simulation is stepped, so runs are deterministic for a given seed and
+//! convergence time can be measured in simulated rather than wall-clock time.
+//!
+//! Links are FIFO, like the TCP/WebSocket connections they stand in for: jitter
+//! delays a message but never lets it overtake an earlier one on the same link.
+//! Messages whose dependencies a replica has not received yet (the origin of an
+//! insertion, or the node a deletion removes) are held back until they arrive,
+//! so delivery is causal.
+
+use alloc::collections::{BTreeMap, BinaryHeap};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::time::Duration;
+
+use crate::crdt::{CausalBuffer, Node, RGA, UniqueId};
+use crate::testing::convergence::check_converged;
+
+/// Distribution of the one-way delay of a link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    /// Every message takes exactly this long
+    Fixed(Duration),
+    /// Delays are drawn uniformly from `min..=max`
+    Uniform { min: Duration, max: Duration },
+    /// Usually `base`, but with the given probability `tail` instead,
+    /// modelling retransmissions and congested WAN paths
+    LongTail {
+        base: Duration,
+        tail: Duration,
+        probability: f64,
+    },
+}
+
+/// Network conditions of a single directed link between two replicas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConfig {
+    /// Base delay distribution
+    pub latency: Latency,
+    /// Extra delay drawn uniformly from `0..=jitter` for every message
+    pub jitter: Duration,
+}
+
+impl LinkConfig {
+    /// A link that delivers instantly.
+    pub fn instant() -> Self {
+        Self::fixed(Duration::ZERO)
+    }
+
+    /// A link with a fixed delay and no jitter.
+    pub fn fixed(delay: Duration) -> Self {
+        LinkConfig {
+            latency: Latency::Fixed(delay),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Returns the link with the given jitter.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self::instant()
+    }
+}
+
+/// A message waiting on a link, ordered by delivery time then send order.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    deliver_at: Duration,
+    seq: u64,
+    to: usize,
+    node: Node,
+}
+
+/// Deterministic SplitMix64 generator, so runs are reproducible without `rand`.
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// A generator whose sequence is fixed by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Next 64 uniform bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform duration in `0..=max`, at nanosecond resolution.
+    pub fn duration_up_to(&mut self, max: Duration) -> Duration {
+        let nanos = max.as_nanos() as u64;
+        match nanos {
+            0 => Duration::ZERO,
+            _ => Duration::from_nanos(self.next_u64() % (nanos + 1)),
+        }
+    }
+}
+
+/// A simulated full-mesh network of RGA replicas.
+///
+/// Replica `i` has replica ID `i + 1`. Edits made through the network are
+/// applied locally at once and queued for every other replica.
+pub struct SimNetwork {
+    replicas: Vec<RGA>,
+    default_link: LinkConfig,
+    links: BTreeMap<(usize, usize), LinkConfig>,
+    /// Latest scheduled delivery per link, to keep links FIFO
+    link_tail: BTreeMap<(usize, usize), Duration>,
+    queue: BinaryHeap<Reverse<InFlight>>,
+    /// Per-replica buffers for messages whose dependencies have not arrived
+    held_back: Vec<CausalBuffer>,
+    now: Duration,
+    next_seq: u64,
+    rng: SplitMix64,
+}
+
+impl SimNetwork {
+    /// Creates a network of `replica_count` fresh replicas with instant links.
+    ///
+    /// # Arguments
+    ///
+    /// * `replica_count` - Number of replicas to create
+    /// * `seed` - Seed for latency sampling; equal seeds give identical runs
+    pub fn new(replica_count: usize, seed: u64) -> Self {
+        SimNetwork {
+            replicas: (1..=replica_count as u64).map(RGA::new).collect(),
+            held_back: (0..replica_count).map(|_| CausalBuffer::new()).collect(),
+            default_link: LinkConfig::instant(),
+            links: BTreeMap::new(),
+            link_tail: BTreeMap::new(),
+            queue: BinaryHeap::new(),
+            now: Duration::ZERO,
+            next_seq: 0,
+            rng: SplitMix64::new(seed),
+        }
+    }
+
+    /// Sets the conditions of every link without an explicit configuration.
+    pub fn set_default_link(&mut self, config: LinkConfig) {
+        self.default_link = config;
+    }
+
+    /// Sets the conditions of the directed link `from -> to`.
+    pub fn set_link(&mut self, from: usize, to: usize, config: LinkConfig) {
+        self.links.insert((from, to), config);
+    }
+
+    /// Returns the replica at `index`.
+    pub fn replica(&self, index: usize) -> &RGA {
+        &self.replicas[index]
+    }
+
+    /// Returns all replicas.
+    pub fn replicas(&self) -> &[RGA] {
+        &self.replicas
+    }
+
+    /// Current simulated time.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Number of messages still travelling or held back.
+    pub fn pending_messages(&self) -> usize {
+        self.queue.len() + self.held_back.iter().map(CausalBuffer::len).sum::<usize>()
+    }
+
+    /// Inserts a character at replica `from` and sends it to every other replica.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UniqueId)` - The ID of the inserted node
+    /// * `Err(&str)` - Error message if the local insertion fails
+    pub fn insert_after(
+        &mut self,
+        from: usize,
+        after_id: UniqueId,
+        character: char,
+    ) -> Result<UniqueId, &'static str> {
+        let id = self.replicas[from].insert_after(after_id, character)?;
+        self.broadcast(from, id);
+        Ok(id)
+    }
+
+    /// Deletes a node at replica `from` and sends the tombstone to every other replica.
+    pub fn delete(&mut self, from: usize, id: UniqueId) -> Result<(), &'static str> {
+        self.replicas[from].delete(id)?;
+        self.broadcast(from, id);
+        Ok(())
+    }
+
+    /// Sends the current state of node `id` at replica `from` to every other replica.
+    ///
+    /// Use this for edits made directly on [`SimNetwork::replica`].
+    pub fn broadcast(&mut self, from: usize, id: UniqueId) {
+        let Some(node) = self.replicas[from].get_node(id) else {
+            return;
+        };
+        for to in (0..self.replicas.len()).filter(|&to| to != from) {
+            let config = self
+                .links
+                .get(&(from, to))
+                .copied()
+                .unwrap_or(self.default_link);
+            let sampled = self.now + self.sample_delay(config);
+            let tail = self.link_tail.entry((from, to)).or_default();
+            let deliver_at = sampled.max(*tail);
+            *tail = deliver_at;
+
+            self.queue.push(Reverse(InFlight {
+                deliver_at,
+                seq: self.next_seq,
+                to,
+                node: node.clone(),
+            }));
+            self.next_seq += 1;
+        }
+    }
+
+    /// Delivers every message due by `now + duration` and advances the clock.
+    pub fn advance(&mut self, duration: Duration) {
+        let until = self.now + duration;
+        while self
+            .queue
+            .peek()
+            .is_some_and(|Reverse(message)| message.deliver_at <= until)
+        {
+            self.deliver_next();
+        }
+        self.now = until;
+    }
+
+    /// Delivers messages until none are left in flight.
+    ///
+    /// # Returns
+    ///
+    /// The simulated time at which the last message was delivered
+    pub fn run_until_quiescent(&mut self) -> Duration {
+        while !self.queue.is_empty() {
+            self.deliver_next();
+        }
+        self.now
+    }
+
+    /// Returns true if every replica holds the same visible nodes.
+    ///
+    /// Use [`check_converged`] on [`SimNetwork::replicas`] for an explanation
+    /// when they differ.
+    pub fn is_converged(&self) -> bool {
+        check_converged(&self.replicas).is_ok()
+    }
+
+    fn deliver_next(&mut self) {
+        let Some(Reverse(message)) = self.queue.pop() else {
+            return;
+        };
+        self.now = self.now.max(message.deliver_at);
+
+        self.held_back[message.to].deliver(&self.replicas[message.to], message.node);
+    }
+
+    fn sample_delay(&mut self, config: LinkConfig) -> Duration {
+        let base = match config.latency {
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } => min + self.rng.duration_up_to(max.saturating_sub(min)),
+            Latency::LongTail {
+                base,
+                tail,
+                probability,
+            } => {
+                if self.rng.next_f64() < probability {
+                    tail
+                } else {
+                    base
+                }
+            }
+        };
+        base + self.rng.duration_up_to(config.jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(network: &mut SimNetwork, from: usize, text: &str) {
+        let mut last_id = network.replica(from).sentinel_start_id();
+        for ch in text.chars() {
+            last_id = network.insert_after(from, last_id, ch).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_instant_links_deliver_immediately() {
+        let mut network = SimNetwork::new(3, 1);
+        type_text(&mut network, 0, "hi");
+
+        assert_eq!(network.pending_messages(), 4);
+        network.advance(Duration::ZERO);
+        assert_eq!(network.pending_messages(), 0);
+        assert!(network.is_converged());
+    }
+
+    #[test]
+    fn test_latency_delays_delivery() {
+        let mut network = SimNetwork::new(2, 1);
+        network.set_default_link(LinkConfig::fixed(Duration::from_millis(80)));
+        type_text(&mut network, 0, "a");
+
+        network.advance(Duration::from_millis(79));
+        assert_eq!(network.replica(1).to_string(), "");
+        network.advance(Duration::from_millis(1));
+        assert_eq!(network.replica(1).to_string(), "a");
+    }
+
+    #[test]
+    fn test_jitter_keeps_links_fifo_and_converges() {
+        let mut network = SimNetwork::new(3, 42);
+        network.set_default_link(
+            LinkConfig {
+                latency: Latency::Uniform {
+                    min: Duration::from_millis(20),
+                    max: Duration::from_millis(120),
+                },
+                jitter: Duration::ZERO,
+            }
+            .with_jitter(Duration::from_millis(30)),
+        );
+        network.set_link(
+            0,
+            2,
+            LinkConfig {
+                latency: Latency::LongTail {
+                    base: Duration::from_millis(50),
+                    tail: Duration::from_millis(900),
+                    probability: 0.2,
+                },
+                jitter: Duration::from_millis(10),
+            },
+        );
+
+        type_text(&mut network, 0, "hello");
+        type_text(&mut network, 1, "world");
+        let first = network.replica(0).visible_nodes()[0].id;
+        network.delete(0, first).unwrap();
+
+        let elapsed = network.run_until_quiescent();
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(network.is_converged());
+        assert_eq!(network.pending_messages(), 0);
+        assert_eq!(network.replica(2).visible_node_count(), 9);
+    }
+
+    #[test]
+    fn test_delete_overtaking_insert_is_held_back() {
+        let mut network = SimNetwork::new(3, 7);
+        network.set_link(0, 2, LinkConfig::fixed(Duration::from_millis(500)));
+        type_text(&mut network, 0, "x");
+        network.advance(Duration::from_millis(1));
+
+        // Replica 1 deletes the node before replica 2 has received it
+        let id = network.replica(1).visible_nodes()[0].id;
+        network.delete(1, id).unwrap();
+        network.advance(Duration::from_millis(10));
+        assert_eq!(network.pending_messages(), 2);
+
+        network.run_until_quiescent();
+        assert!(network.is_converged());
+        assert_eq!(network.replica(2).to_string(), "");
+    }
+
+    #[test]
+    fn test_insert_overtaking_its_origin_is_held_back() {
+        let mut network = SimNetwork::new(3, 3);
+        network.set_link(0, 2, LinkConfig::fixed(Duration::from_millis(500)));
+        type_text(&mut network, 0, "a");
+        network.advance(Duration::from_millis(1));
+
+        // Replica 1 types after a node replica 2 has not received yet
+        let a_id = network.replica(1).visible_nodes()[0].id;
+        network.insert_after(1, a_id, 'b').unwrap();
+        network.advance(Duration::from_millis(10));
+        assert_eq!(network.replica(2).to_string(), "");
+        assert_eq!(network.pending_messages(), 2);
+
+        network.run_until_quiescent();
+        assert!(network.is_converged());
+        assert_eq!(network.replica(2).to_string(), "ab");
+    }
+
+    #[test]
+    fn test_same_seed_is_deterministic() {
+        let run = |seed| {
+            let mut network = SimNetwork::new(2, seed);
+            network.set_default_link(LinkConfig {
+                latency: Latency::Uniform {
+                    min: Duration::from_millis(1),
+                    max: Duration::from_millis(200),
+                },
+                jitter: Duration::from_millis(25),
+            });
+            type_text(&mut network, 0, "deterministic");
+            network.run_until_quiescent()
+        };
+
+        assert_eq!(run(9), run(9));
+    }
+}
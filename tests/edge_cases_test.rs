@@ -3,7 +3,11 @@
 //! These tests verify the robustness of the RGA CRDT under various edge conditions
 //! including boundary values, error conditions, and stress scenarios.
 
+use crdt_rga::testing::{
+    Cluster, LinkConfig, OpMix, Simulation, SimulationConfig, assert_converged,
+};
 use crdt_rga::{RGA, SENTINEL_END_CHAR, SENTINEL_START_CHAR, UniqueId};
+use std::time::Duration;
 
 #[test]
 fn test_sentinel_deletion_protection() {
@@ -131,7 +135,7 @@ fn test_extreme_replica_ids() {
     rga_zero.apply_remote_op(node_from_max);
     rga_max.apply_remote_op(node_from_zero);
 
-    assert_eq!(rga_zero.to_string(), rga_max.to_string());
+    assert_converged([&rga_zero, &rga_max]);
     assert_eq!(rga_zero.visible_node_count(), 2);
 }
 
@@ -178,15 +182,7 @@ fn test_concurrent_deletion_same_node() {
     rga2.delete(char_id).unwrap();
 
     // Sync the deletion operations
-    let rga1_nodes = rga1.all_nodes();
-    let rga2_nodes = rga2.all_nodes();
-
-    for node in rga1_nodes {
-        rga2.apply_remote_op(node);
-    }
-    for node in rga2_nodes {
-        rga1.apply_remote_op(node);
-    }
+    Cluster::from_replicas(vec![&rga1, &rga2]).run_until_quiescent();
 
     // Both should converge to empty document
     assert_eq!(rga1.to_string(), "");
@@ -374,22 +370,8 @@ fn test_replica_convergence_with_mixed_operations() {
     let _id2 = rga3.insert_after(id1, 'N').unwrap();
     rga3.delete(id1).unwrap(); // Delete 'M', keep 'N'
 
-    // Collect all operations from all replicas
-    let mut all_operations = Vec::new();
-    for rga in [&rga1, &rga2, &rga3] {
-        for node in rga.all_nodes() {
-            if !node.is_sentinel() {
-                all_operations.push(node);
-            }
-        }
-    }
-
-    // Apply all operations to all replicas
-    for rga in [&rga1, &rga2, &rga3] {
-        for op in &all_operations {
-            rga.apply_remote_op(op.clone());
-        }
-    }
+    // Exchange all operations between all replicas
+    Cluster::from_replicas(vec![&rga1, &rga2, &rga3]).run_until_quiescent();
 
     // All replicas should converge to the same state
     let result1 = rga1.to_string();
@@ -407,3 +389,27 @@ fn test_replica_convergence_with_mixed_operations() {
 
     assert_eq!(rga1.visible_node_count(), 7); // 6 + 1 (N, M deleted)
 }
+
+#[test]
+fn test_seeded_simulations_converge() {
+    for replicas in [2, 5] {
+        for seed in 0..4 {
+            let config = SimulationConfig {
+                replicas,
+                ops_per_replica: 40,
+                mix: OpMix {
+                    insert: 1,
+                    delete: 1,
+                },
+                link: LinkConfig::fixed(Duration::from_millis(50))
+                    .with_jitter(Duration::from_millis(50)),
+                seed,
+                ..SimulationConfig::default()
+            };
+            let report = Simulation::new(config).run();
+            assert_eq!(report.inserts + report.deletes, replicas * 40);
+            // Concurrent deletions of one character count twice
+            assert!(report.content.chars().count() >= report.inserts - report.deletes);
+        }
+    }
+}
@@ -4,6 +4,7 @@
 //! including basic operations, concurrent editing, and convergence properties.
 
 use crdt_rga::RGA;
+use crdt_rga::testing::Cluster;
 
 #[test]
 fn test_basic_rga_operations() {
@@ -42,26 +43,11 @@ fn test_concurrent_replicas_convergence() {
     assert_eq!(rga2.to_string(), "Y");
 
     // Cross-replicate operations
-    let rga1_ops: Vec<_> = rga1
-        .all_nodes()
-        .into_iter()
-        .filter(|n| !n.is_sentinel())
-        .collect();
-    let rga2_ops: Vec<_> = rga2
-        .all_nodes()
-        .into_iter()
-        .filter(|n| !n.is_sentinel())
-        .collect();
-
-    for op in &rga2_ops {
-        rga1.apply_remote_op(op.clone());
-    }
-    for op in &rga1_ops {
-        rga2.apply_remote_op(op.clone());
-    }
+    let mut cluster = Cluster::from_replicas(vec![&rga1, &rga2]);
+    cluster.run_until_quiescent();
 
     // After synchronization, both converged
-    assert_eq!(rga1.to_string(), rga2.to_string());
+    cluster.assert_converged();
     assert!(!rga1.to_string().is_empty());
 }
 
@@ -78,20 +64,12 @@ fn test_deterministic_ordering() {
         rga2.insert_after(start_id, 'Y').unwrap();
 
         // Sync both ways
-        for node in rga1.all_nodes() {
-            if !node.is_sentinel() {
-                rga2.apply_remote_op(node);
-            }
-        }
-        for node in rga2.all_nodes() {
-            if !node.is_sentinel() {
-                rga1.apply_remote_op(node);
-            }
-        }
+        let mut cluster = Cluster::from_replicas(vec![&rga1, &rga2]);
+        cluster.run_until_quiescent();
 
         // Should always converge to the same result
+        cluster.assert_converged();
         let result = rga1.to_string();
-        assert_eq!(result, rga2.to_string());
         assert_eq!(result.len(), 2);
         assert!(result.contains('X') && result.contains('Y'));
     }
@@ -125,29 +103,40 @@ fn test_three_way_merge() {
     rga2.insert_after(start_id, '2').unwrap();
     rga3.insert_after(start_id, '3').unwrap();
 
-    // Collect all operations
-    let mut all_ops = Vec::new();
-    for rga in [&rga1, &rga2, &rga3] {
-        for node in rga.all_nodes() {
-            if !node.is_sentinel() {
-                all_ops.push(node);
-            }
-        }
-    }
-
-    // Apply all operations to all replicas
-    for rga in [&rga1, &rga2, &rga3] {
-        for op in &all_ops {
-            rga.apply_remote_op(op.clone());
-        }
-    }
+    // Exchange all operations
+    let mut cluster = Cluster::from_replicas(vec![&rga1, &rga2, &rga3]);
+    cluster.run_until_quiescent();
 
     // All should converge to same state
-    let result1 = rga1.to_string();
-    let result2 = rga2.to_string();
-    let result3 = rga3.to_string();
+    cluster.assert_converged();
+    assert_eq!(rga1.to_string().len(), 3);
+}
 
-    assert_eq!(result1, result2);
-    assert_eq!(result2, result3);
-    assert_eq!(result1.len(), 3);
+#[test]
+fn test_concurrent_sentences_do_not_interleave() {
+    let rga1 = RGA::new(1);
+    let start_id = rga1.sentinel_start_id();
+    let shared = rga1.insert_str_after(start_id, "Notes: ").unwrap();
+    let rga2 = rga1.fork(2);
+    let rga3 = rga1.fork(3);
+    let end_id = *shared.last().unwrap();
+
+    // Three people type a sentence at the same spot while offline; the third
+    // made another edit first, so its clock is ahead
+    rga1.insert_str_after(end_id, "Buy milk. ").unwrap();
+    rga2.insert_str_after(end_id, "Call Sam. ").unwrap();
+    let scratch = rga3.insert_after(end_id, '?').unwrap();
+    rga3.delete(scratch).unwrap();
+    rga3.insert_str_after(end_id, "Water plants. ").unwrap();
+
+    let mut cluster = Cluster::from_replicas(vec![&rga1, &rga2, &rga3]);
+    cluster.run_until_quiescent();
+
+    // Each sentence stays readable instead of merging character by character,
+    // the newest insertion right after the shared text
+    cluster.assert_converged();
+    assert_eq!(
+        rga1.to_string(),
+        "Notes: Water plants. Call Sam. Buy milk. "
+    );
 }
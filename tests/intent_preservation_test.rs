@@ -0,0 +1,163 @@
+//! Intention-preservation tests for classic collaborative-editing scenarios.
+//!
+//! Convergence alone is easy to satisfy: every replica could agree on
+//! garbage. These scenarios also assert the merged text each user would
+//! expect, and so document what the crate guarantees:
+//!
+//! - a character deleted by anyone stays deleted, and only that character goes
+//! - text inserted concurrently survives, even next to or inside deleted text
+//! - a run inserted with `insert_str_after` stays contiguous
+//! - inserted text lands right after the character it was inserted after
+//! - concurrent runs at the same spot are ordered newest first, and by
+//!   replica ID, highest first, when typed at the same Lamport time
+
+use crdt_rga::testing::Cluster;
+use crdt_rga::{RGA, UniqueId};
+
+/// A replica holding `text`, typed by replica 1
+fn document(text: &str) -> RGA {
+    let rga = RGA::new(1);
+    rga.insert_str_after(rga.sentinel_start_id(), text).unwrap();
+    rga
+}
+
+/// Exchanges every operation between the replicas
+fn sync(replicas: &[&RGA]) {
+    let mut cluster = Cluster::from_replicas(replicas.to_vec());
+    cluster.run_until_quiescent();
+    cluster.assert_converged();
+}
+
+/// The ID of the visible character at `index`
+fn id_at(rga: &RGA, index: usize) -> UniqueId {
+    rga.visible_nodes()[index].id
+}
+
+#[test]
+fn test_simultaneous_insertions_into_empty_document() {
+    let alice = RGA::new(1);
+    let bob = RGA::new(2);
+    alice
+        .insert_str_after(alice.sentinel_start_id(), "Hello")
+        .unwrap();
+    bob.insert_str_after(bob.sentinel_start_id(), "Bonjour")
+        .unwrap();
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "BonjourHello");
+}
+
+#[test]
+fn test_simultaneous_appends_stay_contiguous() {
+    let alice = document("Agenda:");
+    let bob = alice.fork(2);
+    let carol = alice.fork(3);
+    let end = id_at(&alice, 6);
+
+    carol.insert_str_after(end, " budget").unwrap();
+    bob.insert_str_after(end, " hiring").unwrap();
+    alice.insert_str_after(end, " roadmap").unwrap();
+
+    sync(&[&alice, &bob, &carol]);
+    assert_eq!(alice.to_string(), "Agenda: budget hiring roadmap");
+}
+
+#[test]
+fn test_delete_inside_concurrent_insert() {
+    let alice = document("abc");
+    let bob = alice.fork(2);
+
+    // Alice appends while Bob deletes `b`
+    alice.insert_str_after(id_at(&alice, 2), "XYZ").unwrap();
+    bob.delete(id_at(&bob, 1)).unwrap();
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "acXYZ");
+
+    // Bob deletes inside Alice's insertion while she keeps typing after it
+    let z = id_at(&alice, 4);
+    bob.delete(id_at(&bob, 3)).unwrap();
+    alice.insert_after(z, '!').unwrap();
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "acXZ!");
+}
+
+#[test]
+fn test_insert_inside_concurrently_deleted_word() {
+    // Bob fixes a typo in a word Alice deletes at the same time: the fix
+    // survives on its own rather than resurrecting the word
+    let alice = document("helo wrld");
+    let bob = alice.fork(2);
+    let word: Vec<UniqueId> = (5..9).map(|index| id_at(&alice, index)).collect();
+
+    for &id in &word {
+        alice.delete(id).unwrap();
+    }
+    bob.insert_after(word[0], 'o').unwrap();
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "helo o");
+}
+
+#[test]
+fn test_concurrent_deletes_of_overlapping_ranges() {
+    let alice = document("the quick brown fox");
+    let bob = alice.fork(2);
+
+    // Alice deletes "quick ", Bob deletes "ick brown "
+    let ids: Vec<UniqueId> = alice.visible_nodes().iter().map(|node| node.id).collect();
+    for &id in &ids[4..10] {
+        alice.delete(id).unwrap();
+    }
+    for &id in &ids[6..16] {
+        bob.delete(id).unwrap();
+    }
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "the fox");
+}
+
+#[test]
+fn test_delete_and_retype_same_character() {
+    // Both replace the last character; both replacements are kept and the
+    // original is deleted once
+    let alice = document("cat");
+    let bob = alice.fork(2);
+    let t = id_at(&alice, 2);
+
+    alice.delete(t).unwrap();
+    alice.insert_after(id_at(&alice, 1), 'r').unwrap();
+    bob.delete(t).unwrap();
+    bob.insert_after(id_at(&bob, 1), 'b').unwrap();
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "cabr");
+    assert_eq!(alice.total_node_count(), 2 + 5);
+}
+
+#[test]
+fn test_simultaneous_prefix_insertions() {
+    let alice = document("world");
+    let bob = alice.fork(2);
+
+    alice
+        .insert_str_after(alice.sentinel_start_id(), "Hello ")
+        .unwrap();
+    bob.insert_str_after(bob.sentinel_start_id(), "Big ")
+        .unwrap();
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "Big Hello world");
+}
+
+#[test]
+fn test_split_word_edits() {
+    // Alice fixes "helo" while Bob fixes "wrld" in the same sentence
+    let alice = document("helo wrld");
+    let bob = alice.fork(2);
+
+    alice.insert_after(id_at(&alice, 2), 'l').unwrap();
+    bob.insert_after(id_at(&bob, 5), 'o').unwrap();
+
+    sync(&[&alice, &bob]);
+    assert_eq!(alice.to_string(), "hello world");
+}